@@ -15,6 +15,9 @@
 #![allow(clippy::upper_case_acronyms)]
 
 use std::collections::HashMap;
+use std::future::Future;
+#[cfg(feature = "local-ns-channel")]
+use std::os::unix::fs::PermissionsExt;
 use std::path::Path;
 use std::process;
 use std::process::Command;
@@ -25,14 +28,13 @@ use async_std::fs;
 use async_std::path::Path as AsyncPath;
 use async_std::prelude::*;
 use async_std::sync::{Arc, RwLock};
-use async_std::task;
 
 use futures::stream::TryStreamExt;
 
 use zenoh::*;
 
 use fog05_sdk::fresult::{FError, FResult};
-use fog05_sdk::types::IPAddress;
+use fog05_sdk::types::{IPAddress, IPVersion};
 use fog05_sdk::zconnector::ZConnector;
 
 use znrpc_macros::znserver;
@@ -40,17 +42,33 @@ use zrpc::ZNServe;
 
 use signal_hook_async_std::Signals;
 
+use tracing::Instrument;
+
 use uuid::Uuid;
 
 use structopt::StructOpt;
 
 use git_version::git_version;
 
-use fog05_networking_linux::types::NamespaceManager;
+#[cfg(feature = "local-ns-channel")]
+use async_std::os::unix::net::{UnixListener, UnixStream};
+use fog05_networking_linux::logging;
+use fog05_networking_linux::networking::{flush_bridge_fdb, set_bridge_port};
+use fog05_networking_linux::ns_channel::NSManagerRequest;
+#[cfg(feature = "local-ns-channel")]
+use fog05_networking_linux::ns_channel::{read_frame, write_frame};
+use fog05_networking_linux::runtime;
+use fog05_networking_linux::sysctl::{self, MartianCounters, ReversePathFilter};
+use fog05_networking_linux::types::{
+    BridgePortConfig, DefaultRoute, IPTunnelKind, LinkStatsDump, NamespaceManager,
+    SharedNamespaceManager, SpanContext,
+};
 
 use netlink_packet_route::rtnl::address::nlas::Nla;
 use rtnetlink::new_connection;
 use rtnetlink::packet::rtnl::link::nlas::Nla as LinkNla;
+use rtnetlink::packet::rtnl::route::nlas::Nla as RouteNla;
+use rtnetlink::packet::RouteMessage;
 
 use ipnetwork::IpNetwork;
 
@@ -67,12 +85,18 @@ const GIT_VERSION: &str = git_version!(prefix = "v", cargo_prefix = "v");
 #[derive(StructOpt, Debug)]
 struct NSManagerArgs {
     /// Config file
-    #[structopt(short, long)]
-    netns: String,
-    #[structopt(short, long)]
-    locator: String,
+    #[structopt(short, long, required_unless = "shared")]
+    netns: Option<String>,
+    #[structopt(short, long, required_unless = "socket")]
+    locator: Option<String>,
     #[structopt(short, long)]
     id: Uuid,
+    /// Manage multiple namespaces from this process
+    #[structopt(long)]
+    shared: bool,
+    /// Serve over this unix socket instead of zenoh
+    #[structopt(long)]
+    socket: Option<String>,
 }
 
 pub struct NSManagerState {
@@ -81,17 +105,32 @@ pub struct NSManagerState {
 
 #[derive(Clone)]
 pub struct NSManager {
-    pub z: Arc<zenoh::net::Session>,
+    pub z: Option<Arc<zenoh::net::Session>>,
     pub pid: u32,
     pub uuid: Uuid,
     pub state: Arc<RwLock<NSManagerState>>,
+    /// Namespace entered by the thread serving the request when the
+    /// manager is created by a shared process
+    pub shared_netns: Option<String>,
+    /// Unix socket served in place of zenoh
+    pub socket_path: Option<String>,
+}
+
+/// Serves the requests of many namespaces from a single process, each
+/// request carries the namespace UUID and is served by a thread that
+/// enters the namespace for the time of the request.
+#[derive(Clone)]
+pub struct SharedNSManager {
+    pub z: Arc<zenoh::net::Session>,
+    pub pid: u32,
+    pub uuid: Uuid,
+    /// Name of each managed namespace
+    pub namespaces: Arc<RwLock<HashMap<Uuid, String>>>,
 }
 
 fn main() {
     // Init logging
-    env_logger::init_from_env(
-        env_logger::Env::default().filter_or(env_logger::DEFAULT_FILTER_ENV, "trace"),
-    );
+    logging::init("trace");
     let args = NSManagerArgs::from_args();
 
     log::debug!(
@@ -100,6 +139,26 @@ fn main() {
     );
     log::trace!("Args: {:?}", args);
 
+    #[cfg(not(feature = "local-ns-channel"))]
+    if args.socket.is_some() {
+        log::error!("Unix socket channel is not enabled in this build");
+        process::exit(-1);
+    }
+
+    if args.shared {
+        runtime::block_on(async { __shared_main(args).await });
+        log::info!("Bye!");
+        return;
+    }
+
+    let netns = match args.netns.clone() {
+        Some(netns) => netns,
+        None => {
+            log::error!("Missing namespace");
+            process::exit(-1);
+        }
+    };
+
     log::trace!("Changing namespace");
     // https://github.com/shemminger/iproute2/blob/f33a871b8094ae0f6e6293804e1cc6edbba0e108/lib/namespace.c#L49
     let mut unshare_flags = CloneFlags::empty();
@@ -112,7 +171,7 @@ fn main() {
 
     let mut netns_path = String::new();
     netns_path.push_str(NETNS_PATH);
-    netns_path.push_str(&args.netns);
+    netns_path.push_str(&netns);
 
     open_flags.insert(OFlag::O_RDONLY);
     open_flags.insert(OFlag::O_CLOEXEC);
@@ -163,7 +222,7 @@ fn main() {
             let sys_fs = Path::new(&SYS_FS);
             mount_flags = nix::mount::MsFlags::empty();
             if let Err(e) = nix::mount::mount(
-                Some(Path::new(&args.netns)),
+                Some(Path::new(&netns)),
                 Path::new("/sys"),
                 Some(sys_fs),
                 mount_flags,
@@ -174,12 +233,19 @@ fn main() {
             }
 
             async fn __main(args: NSManagerArgs) {
-                log::info!("Running on namespace {}", args.netns);
+                log::info!("Running on namespace {:?}", args.netns);
                 let my_pid = process::id();
 
-                let properties = format!("mode=client;peer={}", args.locator.clone());
-                let zproperties = Properties::from(properties);
-                let zenoh = Arc::new(zenoh::net::open(zproperties.into()).await.unwrap());
+                let zenoh = match (&args.socket, &args.locator) {
+                    (None, Some(locator)) => {
+                        let properties = format!("mode=client;peer={}", locator);
+                        let zproperties = Properties::from(properties);
+                        Some(Arc::new(
+                            zenoh::net::open(zproperties.into()).await.unwrap(),
+                        ))
+                    }
+                    _ => None,
+                };
 
                 let mut manager = match NSManager::new(zenoh, my_pid, args.id).await {
                     Ok(m) => m,
@@ -188,6 +254,7 @@ fn main() {
                         process::exit(-1);
                     }
                 };
+                manager.socket_path = args.socket.clone();
                 let (s, handle) = manager.start().await;
 
                 let signals = Signals::new(&[
@@ -237,17 +304,193 @@ fn main() {
                     process::exit(-1);
                 }
             }
-            async_std::task::block_on(async { __main(args).await });
+            runtime::block_on(async { __main(args).await });
             log::info!("Bye!");
         }
     }
 }
 
+async fn __shared_main(args: NSManagerArgs) {
+    log::info!("Running as shared namespace manager");
+    let my_pid = process::id();
+
+    let locator = match args.locator.clone() {
+        Some(locator) => locator,
+        None => {
+            log::error!("Missing locator");
+            process::exit(-1);
+        }
+    };
+    let properties = format!("mode=client;peer={}", locator);
+    let zproperties = Properties::from(properties);
+    let zenoh = Arc::new(zenoh::net::open(zproperties.into()).await.unwrap());
+
+    let mut manager = SharedNSManager::new(zenoh, my_pid, args.id);
+    let (s, handle) = manager.start().await;
+
+    let signals = Signals::new(&[
+        signal_hook::consts::SIGTERM,
+        signal_hook::consts::SIGINT,
+        signal_hook::consts::SIGQUIT,
+    ])
+    .unwrap();
+    let sig_handle = signals.handle();
+
+    let mut signals = signals.fuse();
+    if let Some(signal) = signals.next().await {
+        match signal {
+            signal_hook::consts::SIGTERM
+            | signal_hook::consts::SIGINT
+            | signal_hook::consts::SIGQUIT => {
+                log::trace!("Received stop signal closing...");
+                manager.stop(s).await.unwrap();
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    handle.await.unwrap();
+    sig_handle.close();
+}
+
+impl SharedNSManager {
+    pub fn new(z: Arc<zenoh::net::Session>, pid: u32, uuid: Uuid) -> Self {
+        Self {
+            z,
+            pid,
+            uuid,
+            namespaces: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    async fn run(&self, stop: runtime::Receiver<()>) -> FResult<()> {
+        log::info!("Shared Network Namespace Manager main loop starting...");
+        let shared_server = self
+            .clone()
+            .get_shared_namespace_manager_server(self.z.clone(), Some(self.uuid));
+
+        let (stopper, _h) = shared_server.connect().await?;
+        shared_server.initialize().await?;
+        shared_server.register().await?;
+
+        let (sender, handle) = shared_server.start().await?;
+
+        stop.recv().await;
+
+        shared_server.stop(sender).await?;
+        shared_server.unregister().await?;
+        shared_server.disconnect(stopper).await?;
+
+        log::info!("Shared Network Namespace Manager main loop exiting");
+        Ok(())
+    }
+
+    pub async fn start(&mut self) -> (runtime::Sender<()>, runtime::JoinHandle<FResult<()>>) {
+        let (s, r) = runtime::bounded::<()>(1);
+        let plugin = self.clone();
+        let h = runtime::spawn_blocking(move || runtime::block_on(async { plugin.run(r).await }));
+        (s, h)
+    }
+
+    pub async fn stop(&self, stop: runtime::Sender<()>) -> FResult<()> {
+        log::info!("Stopping...");
+        stop.send(()).await;
+        log::info!("Stopped");
+        Ok(())
+    }
+}
+
+#[znserver]
+impl SharedNamespaceManager for SharedNSManager {
+    async fn add_namespace(&self, ns_name: String, ns_uuid: Uuid) -> FResult<()> {
+        log::trace!("add_namespace {} {}", ns_name, ns_uuid);
+        let mut guard = self.namespaces.write().await;
+        if guard.contains_key(&ns_uuid) {
+            return Err(FError::AlreadyPresent);
+        }
+        if !AsyncPath::new(&format!("{}{}", NETNS_PATH, ns_name))
+            .exists()
+            .await
+        {
+            return Err(FError::NotFound);
+        }
+        guard.insert(ns_uuid, ns_name);
+        Ok(())
+    }
+
+    async fn remove_namespace(&self, ns_uuid: Uuid) -> FResult<()> {
+        log::trace!("remove_namespace {}", ns_uuid);
+        let mut guard = self.namespaces.write().await;
+        guard.remove(&ns_uuid).ok_or(FError::NotFound)?;
+        Ok(())
+    }
+
+    async fn list_namespaces(&self) -> FResult<Vec<Uuid>> {
+        let guard = self.namespaces.read().await;
+        Ok(guard.keys().copied().collect())
+    }
+
+    async fn namespace_request(
+        &self,
+        ns_uuid: Uuid,
+        ctx: SpanContext,
+        req: NSManagerRequest,
+    ) -> FResult<Vec<u8>> {
+        let netns = self
+            .namespaces
+            .read()
+            .await
+            .get(&ns_uuid)
+            .cloned()
+            .ok_or(FError::NotFound)?;
+        let pid = self.pid;
+        in_namespace(netns.clone(), move || {
+            runtime::block_on(async {
+                // the netlink socket is opened inside the namespace
+                let mut manager = NSManager::new(None, pid, ns_uuid).await?;
+                manager.shared_netns = Some(netns);
+                manager.handle_request(ctx, req).await
+            })
+        })
+        .await
+    }
+}
+
+/// Runs `f` on a blocking thread moved into `netns`, processes spawned by
+/// `f` inherit the namespace. The thread goes back to its namespace
+/// before being returned to the pool.
+async fn in_namespace<F, T>(netns: String, f: F) -> FResult<T>
+where
+    F: FnOnce() -> FResult<T> + Send + 'static,
+    T: Send + 'static,
+{
+    use std::os::unix::io::AsRawFd;
+    runtime::spawn_blocking(move || {
+        let current_ns = std::fs::File::open("/proc/thread-self/ns/net")?;
+        let target_ns = std::fs::File::open(format!("{}{}", NETNS_PATH, netns))?;
+        nix::sched::setns(target_ns.as_raw_fd(), CloneFlags::CLONE_NEWNET)
+            .map_err(|e| FError::NetworkingError(format!("{}", e)))?;
+        let res = f();
+        if let Err(e) = nix::sched::setns(current_ns.as_raw_fd(), CloneFlags::CLONE_NEWNET) {
+            // the thread would be reused by other tasks in the namespace
+            log::error!(
+                target: logging::NS_MANAGER,
+                "Unable to leave the namespace {}: {}",
+                netns,
+                e
+            );
+            std::process::abort();
+        }
+        res
+    })
+    .await
+}
+
 impl NSManager {
-    pub async fn new(z: Arc<zenoh::net::Session>, pid: u32, uuid: Uuid) -> FResult<Self> {
+    pub async fn new(z: Option<Arc<zenoh::net::Session>>, pid: u32, uuid: Uuid) -> FResult<Self> {
         // This will disappear once netlink merges async-std support
         let (connection, handle, _) = new_connection().unwrap();
-        async_std::task::spawn(connection);
+        runtime::spawn(connection);
 
         let state = NSManagerState { nl_handler: handle };
 
@@ -256,14 +499,22 @@ impl NSManager {
             pid,
             uuid,
             state: Arc::new(RwLock::new(state)),
+            shared_netns: None,
+            socket_path: None,
         })
     }
 
-    async fn run(&self, stop: async_std::channel::Receiver<()>) -> FResult<()> {
+    async fn run(&self, stop: runtime::Receiver<()>) -> FResult<()> {
+        #[cfg(feature = "local-ns-channel")]
+        if let Some(ref path) = self.socket_path {
+            return self.run_unix(path.clone(), stop).await;
+        }
+
         log::info!("Network Namespace Manager main loop starting...");
+        let z = self.z.clone().ok_or(FError::NotConnected)?;
         let ns_manager_server = self
             .clone()
-            .get_namespace_manager_server(self.z.clone(), Some(self.uuid));
+            .get_namespace_manager_server(z, Some(self.uuid));
 
         let (stopper, _h) = ns_manager_server.connect().await?;
         ns_manager_server.initialize().await?;
@@ -283,29 +534,241 @@ impl NSManager {
         Ok(())
     }
 
-    pub async fn start(
-        &mut self,
-    ) -> (
-        async_std::channel::Sender<()>,
-        async_std::task::JoinHandle<FResult<()>>,
-    ) {
-        let (s, r) = async_std::channel::bounded::<()>(1);
+    /// Serves the NamespaceManager requests received on the unix socket
+    /// until the stop signal.
+    #[cfg(feature = "local-ns-channel")]
+    async fn run_unix(&self, path: String, stop: runtime::Receiver<()>) -> FResult<()> {
+        log::info!(
+            "Network Namespace Manager main loop starting on {}...",
+            path
+        );
+        if AsyncPath::new(&path).exists().await {
+            fs::remove_file(&path).await?;
+        }
+        let listener = UnixListener::bind(&path).await?;
+        // only the plugin, running as the same user, can connect
+        fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).await?;
+
+        log::trace!("Interfaces in namespace {:?}", self.dump_links().await);
+
+        let manager = self.clone();
+        let acceptor = async move {
+            let mut incoming = listener.incoming();
+            while let Some(stream) = incoming.next().await {
+                match stream {
+                    Ok(stream) => {
+                        let m = manager.clone();
+                        runtime::spawn(async move {
+                            if let Err(e) = m.serve_unix_connection(stream).await {
+                                log::error!("Error serving unix socket connection: {}", e);
+                            }
+                        });
+                    }
+                    Err(e) => log::error!("Error accepting unix socket connection: {}", e),
+                }
+            }
+        };
+
+        // the acceptor is dropped on stop
+        acceptor
+            .race(async {
+                let _ = stop.recv().await;
+            })
+            .await;
+        fs::remove_file(&path).await?;
+
+        log::info!("Network Namespace Manager main loop exiting");
+        Ok(())
+    }
+
+    #[cfg(feature = "local-ns-channel")]
+    async fn serve_unix_connection(&self, mut stream: UnixStream) -> FResult<()> {
+        // the client closing the connection ends the loop
+        while let Ok(data) = read_frame(&mut stream).await {
+            let (ctx, req) = bincode::deserialize::<(SpanContext, NSManagerRequest)>(&data)
+                .map_err(|e| FError::NetworkingError(format!("{}", e)))?;
+            let resp = self.handle_request(ctx, req).await?;
+            write_frame(&mut stream, &resp).await?;
+        }
+        Ok(())
+    }
+
+    async fn handle_request(&self, ctx: SpanContext, req: NSManagerRequest) -> FResult<Vec<u8>> {
+        fn encode<T: serde::Serialize>(res: FResult<T>) -> FResult<Vec<u8>> {
+            bincode::serialize(&res).map_err(|e| FError::NetworkingError(format!("{}", e)))
+        }
+        match req {
+            NSManagerRequest::SetVirtualInterfaceUp { iface } => {
+                encode(self.set_virtual_interface_up(ctx, iface).await)
+            }
+            NSManagerRequest::SetVirtualInterfaceDown { iface } => {
+                encode(self.set_virtual_interface_down(ctx, iface).await)
+            }
+            NSManagerRequest::SetDefaultRoute { iface } => {
+                encode(self.set_default_route(ctx, iface).await)
+            }
+            NSManagerRequest::AddVirtualInterfaceDefaultRoute { iface, route } => encode(
+                self.add_virtual_interface_default_route(ctx, iface, route)
+                    .await,
+            ),
+            NSManagerRequest::DelVirtualInterfaceDefaultRoute { iface, route } => encode(
+                self.del_virtual_interface_default_route(ctx, iface, route)
+                    .await,
+            ),
+            NSManagerRequest::ProbeGateway { iface, gateway } => {
+                encode(self.probe_gateway(ctx, iface, gateway).await)
+            }
+            NSManagerRequest::DumpLinkStats {} => encode(self.dump_link_stats(ctx).await),
+            NSManagerRequest::SetReversePathFilter { iface, filter } => {
+                encode(self.set_reverse_path_filter(ctx, iface, filter).await)
+            }
+            NSManagerRequest::GetMartianCounters {} => encode(self.get_martian_counters(ctx).await),
+            NSManagerRequest::SetVirtualInterfaceBridgePort { iface, config } => encode(
+                self.set_virtual_interface_bridge_port(ctx, iface, config)
+                    .await,
+            ),
+            NSManagerRequest::FlushVirtualInterfaceFdb { iface } => {
+                encode(self.flush_virtual_interface_fdb(ctx, iface).await)
+            }
+            NSManagerRequest::AddVirtualInterfaceRoute {
+                iface,
+                destination,
+                gateway,
+            } => encode(
+                self.add_virtual_interface_route(ctx, iface, destination, gateway)
+                    .await,
+            ),
+            NSManagerRequest::DelVirtualInterfaceRoute {
+                iface,
+                destination,
+                gateway,
+            } => encode(
+                self.del_virtual_interface_route(ctx, iface, destination, gateway)
+                    .await,
+            ),
+            NSManagerRequest::CheckVirtualInterfaceExists { iface } => {
+                encode(self.check_virtual_interface_exists(ctx, iface).await)
+            }
+            NSManagerRequest::MoveVirtualInterfaceIntoDefaultNs { iface } => encode(
+                self.move_virtual_interface_into_default_ns(ctx, iface)
+                    .await,
+            ),
+            NSManagerRequest::SetVirtualInterfaceMac { iface, address } => {
+                encode(self.set_virtual_interface_mac(ctx, iface, address).await)
+            }
+            NSManagerRequest::SetVirtualInterfaceMtu { iface, mtu } => {
+                encode(self.set_virtual_interface_mtu(ctx, iface, mtu).await)
+            }
+            NSManagerRequest::SetVirtualInterfaceName { iface, name } => {
+                encode(self.set_virtual_interface_name(ctx, iface, name).await)
+            }
+            NSManagerRequest::DelVirtualInterfaceAddress { iface, addr } => {
+                encode(self.del_virtual_interface_address(ctx, iface, addr).await)
+            }
+            NSManagerRequest::GetVirtualInterfaceAddresses { iface } => {
+                encode(self.get_virtual_interface_addresses(ctx, iface).await)
+            }
+            NSManagerRequest::AddVirtualInterfaceAddress { iface, addr } => {
+                encode(self.add_virtual_interface_address(ctx, iface, addr).await)
+            }
+            NSManagerRequest::SetVirtualInterfaceMaster { iface, master } => {
+                encode(self.set_virtual_interface_master(ctx, iface, master).await)
+            }
+            NSManagerRequest::GetVirtualInterfaceMaster { iface } => {
+                encode(self.get_virtual_interface_master(ctx, iface).await)
+            }
+            NSManagerRequest::SetVirtualInterfaceNomaster { iface } => {
+                encode(self.set_virtual_interface_nomaster(ctx, iface).await)
+            }
+            NSManagerRequest::DelVirtualInterface { iface } => {
+                encode(self.del_virtual_interface(ctx, iface).await)
+            }
+            NSManagerRequest::AddVirtualInterfacePtpVxlan {
+                iface,
+                dev,
+                vni,
+                local_addr,
+                remote_addr,
+                port,
+            } => encode(
+                self.add_virtual_interface_ptp_vxlan(
+                    ctx,
+                    iface,
+                    dev,
+                    vni,
+                    local_addr,
+                    remote_addr,
+                    port,
+                )
+                .await,
+            ),
+            NSManagerRequest::AddVirtualInterfaceMcastVxlan {
+                iface,
+                dev,
+                vni,
+                mcast_addr,
+                port,
+            } => encode(
+                self.add_virtual_interface_mcast_vxlan(ctx, iface, dev, vni, mcast_addr, port)
+                    .await,
+            ),
+            NSManagerRequest::AddVirtualInterfaceVlan { iface, dev, tag } => {
+                encode(self.add_virtual_interface_vlan(ctx, iface, dev, tag).await)
+            }
+            NSManagerRequest::AddVirtualInterfaceIpTunnel {
+                iface,
+                kind,
+                local_addr,
+                remote_addr,
+                ttl,
+            } => encode(
+                self.add_virtual_interface_ip_tunnel(
+                    ctx,
+                    iface,
+                    kind,
+                    local_addr,
+                    remote_addr,
+                    ttl,
+                )
+                .await,
+            ),
+            NSManagerRequest::AddVirtualInterfaceVeth { iface_i, iface_e } => {
+                encode(self.add_virtual_interface_veth(ctx, iface_i, iface_e).await)
+            }
+            NSManagerRequest::AddVirtualInterfaceBridge { br_name } => {
+                encode(self.add_virtual_interface_bridge(ctx, br_name).await)
+            }
+            NSManagerRequest::ListInterfaces {} => encode(self.list_interfaces(ctx).await),
+        }
+    }
+
+    /// Serves a request in the span of `ctx`, linking the netlink spans
+    /// of the manager to the plugin operation that sent the request
+    async fn serve<T, F>(&self, ctx: SpanContext, method: &str, request: F) -> FResult<T>
+    where
+        F: Future<Output = FResult<T>>,
+    {
+        request
+            .instrument(ctx.request_span(method, self.uuid))
+            .await
+    }
+
+    pub async fn start(&mut self) -> (runtime::Sender<()>, runtime::JoinHandle<FResult<()>>) {
+        let (s, r) = runtime::bounded::<()>(1);
         let plugin = self.clone();
-        let h = async_std::task::spawn_blocking(move || {
-            async_std::task::block_on(async { plugin.run(r).await })
-        });
+        let h = runtime::spawn_blocking(move || runtime::block_on(async { plugin.run(r).await }));
         (s, h)
     }
 
-    pub async fn stop(&self, stop: async_std::channel::Sender<()>) -> FResult<()> {
+    pub async fn stop(&self, stop: runtime::Sender<()>) -> FResult<()> {
         log::info!("Stopping...");
         stop.send(()).await;
         log::info!("Stopped");
         Ok(())
     }
 
+    #[tracing::instrument(target = "netlink", level = "trace", skip(self), fields(ns_uuid = %self.uuid))]
     async fn create_bridge(&self, br_name: String) -> FResult<()> {
-        log::trace!("create_bridge {}", br_name);
         let mut state = self.state.write().await;
 
         state
@@ -318,6 +781,7 @@ impl NSManager {
             .map_err(|e| FError::NetworkingError(format!("{}", e)))
     }
 
+    #[tracing::instrument(target = "netlink", level = "trace", skip(self), fields(ns_uuid = %self.uuid))]
     async fn create_veth(&self, iface_i: String, iface_e: String) -> FResult<()> {
         let mut state = self.state.write().await;
 
@@ -331,6 +795,7 @@ impl NSManager {
             .map_err(|e| FError::NetworkingError(format!("{}", e)))
     }
 
+    #[tracing::instrument(target = "netlink", level = "trace", skip(self), fields(ns_uuid = %self.uuid))]
     async fn create_vlan(&self, iface: String, dev: String, tag: u16) -> FResult<()> {
         let mut state = self.state.write().await;
 
@@ -353,6 +818,7 @@ impl NSManager {
         }
     }
 
+    #[tracing::instrument(target = "netlink", level = "trace", skip(self), fields(ns_uuid = %self.uuid))]
     async fn create_mcast_vxlan(
         &self,
         iface: String,
@@ -361,14 +827,6 @@ impl NSManager {
         mcast_addr: IPAddress,
         port: u16,
     ) -> FResult<()> {
-        log::trace!(
-            "create_mcast_vxlan {} {} {} {} {}",
-            iface,
-            dev,
-            vni,
-            mcast_addr,
-            port
-        );
         let mut state = self.state.write().await;
 
         let mut links = state.nl_handler.link().get().set_name_filter(dev).execute();
@@ -399,6 +857,7 @@ impl NSManager {
         }
     }
 
+    #[tracing::instrument(target = "netlink", level = "trace", skip(self), fields(ns_uuid = %self.uuid))]
     async fn create_ptp_vxlan(
         &self,
         iface: String,
@@ -408,15 +867,6 @@ impl NSManager {
         remote_addr: IPAddress,
         port: u16,
     ) -> FResult<()> {
-        log::trace!(
-            "create_ptp_vxlan {} {} {} {} {} {}",
-            iface,
-            dev,
-            vni,
-            local_addr,
-            remote_addr,
-            port
-        );
         let mut state = self.state.write().await;
         let mut links = state.nl_handler.link().get().set_name_filter(dev).execute();
         if let Some(link) = links
@@ -451,8 +901,8 @@ impl NSManager {
         }
     }
 
+    #[tracing::instrument(target = "netlink", level = "trace", skip(self), fields(ns_uuid = %self.uuid))]
     async fn del_iface(&self, iface: String) -> FResult<()> {
-        log::trace!("del_iface {}", iface);
         let mut state = self.state.write().await;
         let mut links = state
             .nl_handler
@@ -477,8 +927,8 @@ impl NSManager {
         }
     }
 
+    #[tracing::instrument(target = "netlink", level = "trace", skip(self), fields(ns_uuid = %self.uuid))]
     async fn set_iface_master(&self, iface: String, master: String) -> FResult<()> {
-        log::trace!("set_iface_master {} {}", iface, master);
         let mut state = self.state.write().await;
         let mut links = state
             .nl_handler
@@ -520,8 +970,55 @@ impl NSManager {
         }
     }
 
+    #[tracing::instrument(target = "netlink", level = "trace", skip(self), fields(ns_uuid = %self.uuid))]
+    async fn get_iface_master(&self, iface: String) -> FResult<Option<String>> {
+        let mut state = self.state.write().await;
+        let mut links = state
+            .nl_handler
+            .link()
+            .get()
+            .set_name_filter(iface)
+            .execute();
+        if let Some(link) = links
+            .try_next()
+            .await
+            .map_err(|e| FError::NetworkingError(format!("{}", e)))?
+        {
+            let master_index = link.nlas.into_iter().find_map(|nla| match nla {
+                LinkNla::Master(index) => Some(index),
+                _ => None,
+            });
+            let master_index = match master_index {
+                Some(index) => index,
+                None => return Ok(None),
+            };
+            let mut masters = state
+                .nl_handler
+                .link()
+                .get()
+                .match_index(master_index)
+                .execute();
+            if let Some(master) = masters
+                .try_next()
+                .await
+                .map_err(|e| FError::NetworkingError(format!("{}", e)))?
+            {
+                Ok(master.nlas.into_iter().find_map(|nla| match nla {
+                    LinkNla::IfName(name) => Some(name),
+                    _ => None,
+                }))
+            } else {
+                log::error!("get_iface_master master not found");
+                Err(FError::NotFound)
+            }
+        } else {
+            log::error!("get_iface_master iface not found");
+            Err(FError::NotFound)
+        }
+    }
+
+    #[tracing::instrument(target = "netlink", level = "trace", skip(self), fields(ns_uuid = %self.uuid))]
     async fn del_iface_master(&self, iface: String) -> FResult<()> {
-        log::trace!("del_iface_master {}", iface);
         let mut state = self.state.write().await;
         let mut links = state
             .nl_handler
@@ -548,6 +1045,7 @@ impl NSManager {
         }
     }
 
+    #[tracing::instrument(target = "netlink", level = "trace", skip(self), fields(ns_uuid = %self.uuid))]
     async fn add_iface_address(&self, iface: String, addr: IPAddress, prefix: u8) -> FResult<()> {
         let mut state = self.state.write().await;
         let mut links = state
@@ -573,8 +1071,8 @@ impl NSManager {
         }
     }
 
+    #[tracing::instrument(target = "netlink", level = "trace", skip(self), fields(ns_uuid = %self.uuid))]
     async fn get_iface_addresses(&self, iface: String) -> FResult<Vec<IPAddress>> {
-        log::trace!("get_iface_addresses {}", iface);
         let mut state = self.state.write().await;
         use netlink_packet_route::rtnl::address::nlas::Nla;
         use netlink_packet_route::rtnl::address::AddressMessage;
@@ -630,6 +1128,7 @@ impl NSManager {
         }
     }
 
+    #[tracing::instrument(target = "netlink", level = "trace", skip(self), fields(ns_uuid = %self.uuid))]
     async fn del_iface_address(&self, iface: String, addr: IPAddress) -> FResult<()> {
         let mut state = self.state.write().await;
         use netlink_packet_route::rtnl::address::nlas::Nla;
@@ -692,6 +1191,7 @@ impl NSManager {
         }
     }
 
+    #[tracing::instrument(target = "netlink", level = "trace", skip(self), fields(ns_uuid = %self.uuid))]
     async fn set_iface_name(&self, iface: String, new_name: String) -> FResult<()> {
         let mut state = self.state.write().await;
         let mut links = state
@@ -718,6 +1218,51 @@ impl NSManager {
         }
     }
 
+    #[tracing::instrument(target = "netlink", level = "trace", skip(self), fields(ns_uuid = %self.uuid))]
+    async fn set_iface_mtu(&self, iface: String, mtu: u32) -> FResult<()> {
+        let mut state = self.state.write().await;
+        let mut links = state
+            .nl_handler
+            .link()
+            .get()
+            .set_name_filter(iface)
+            .execute();
+        if let Some(link) = links
+            .try_next()
+            .await
+            .map_err(|e| FError::NetworkingError(format!("{}", e)))?
+        {
+            let mut backoff = 100;
+            loop {
+                let res = state
+                    .nl_handler
+                    .link()
+                    .set(link.header.index)
+                    .mtu(mtu)
+                    .execute()
+                    .await;
+                match res {
+                    Ok(_) => return Ok(()),
+                    Err(rtnetlink::Error::NetlinkError(nl)) => {
+                        if nl.code == -16 {
+                            runtime::sleep(Duration::from_millis(backoff)).await;
+                        } else {
+                            return Err(FError::NetworkingError(format!("{}", nl)));
+                        }
+                    }
+                    Err(e) => return Err(FError::NetworkingError(format!("{}", e))),
+                }
+                backoff *= 2;
+                if backoff > 5000 {
+                    return Err(FError::NetworkingError("Timeout".to_string()));
+                }
+            }
+        } else {
+            Err(FError::NotFound)
+        }
+    }
+
+    #[tracing::instrument(target = "netlink", level = "trace", skip(self), fields(ns_uuid = %self.uuid))]
     async fn set_iface_mac(&self, iface: String, address: Vec<u8>) -> FResult<()> {
         let mut state = self.state.write().await;
         let mut links = state
@@ -744,6 +1289,7 @@ impl NSManager {
         }
     }
 
+    #[tracing::instrument(target = "netlink", level = "trace", skip(self), fields(ns_uuid = %self.uuid))]
     async fn set_iface_default_ns(&self, iface: String) -> FResult<()> {
         let mut state = self.state.write().await;
         let mut links = state
@@ -770,8 +1316,8 @@ impl NSManager {
         }
     }
 
+    #[tracing::instrument(target = "netlink", level = "trace", skip(self), fields(ns_uuid = %self.uuid))]
     async fn set_iface_up(&self, iface: String) -> FResult<()> {
-        log::trace!("set_iface_up {}", iface);
         let mut state = self.state.write().await;
         let mut links = state
             .nl_handler
@@ -797,6 +1343,7 @@ impl NSManager {
         }
     }
 
+    #[tracing::instrument(target = "netlink", level = "trace", skip(self), fields(ns_uuid = %self.uuid))]
     async fn set_iface_down(&self, iface: String) -> FResult<()> {
         let mut state = self.state.write().await;
         let mut links = state
@@ -823,8 +1370,8 @@ impl NSManager {
         }
     }
 
+    #[tracing::instrument(target = "netlink", level = "trace", skip(self), fields(ns_uuid = %self.uuid))]
     async fn iface_exists(&self, iface: String) -> FResult<bool> {
-        log::trace!("iface_exists {}", iface);
         let mut state = self.state.write().await;
         let mut links = state
             .nl_handler
@@ -843,8 +1390,264 @@ impl NSManager {
         }
     }
 
+    /// Returns a command that runs inside the managed namespace, the
+    /// one of the calling thread, for a shared manager the thread
+    /// serving the request
+    fn ns_command(&self, program: &str) -> Command {
+        Command::new(program)
+    }
+
+    #[tracing::instrument(target = "netlink", level = "trace", skip(self), fields(ns_uuid = %self.uuid))]
+    fn create_ip_tunnel(
+        &self,
+        iface: String,
+        kind: IPTunnelKind,
+        local_addr: IPAddress,
+        remote_addr: IPAddress,
+        ttl: Option<u8>,
+    ) -> FResult<()> {
+        let mut cmd = self.ns_command("ip");
+        cmd.arg("link")
+            .arg("add")
+            .arg("name")
+            .arg(iface)
+            .arg("type")
+            .arg(format!("{}", kind))
+            .arg("local")
+            .arg(format!("{}", local_addr))
+            .arg("remote")
+            .arg(format!("{}", remote_addr));
+        if let Some(ttl) = ttl {
+            cmd.arg("ttl").arg(format!("{}", ttl));
+        }
+        let output = cmd
+            .output()
+            .map_err(|e| FError::NetworkingError(format!("{}", e)))?;
+        if !output.status.success() {
+            return Err(FError::NetworkingError(
+                String::from_utf8_lossy(&output.stderr).to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Adds or replaces the route to `destination` through `gateway`
+    #[tracing::instrument(target = "netlink", level = "trace", skip(self), fields(ns_uuid = %self.uuid))]
+    fn add_route(&self, iface: String, destination: IpNetwork, gateway: IPAddress) -> FResult<()> {
+        self.ip_route("replace", iface, destination, gateway)
+    }
+
+    #[tracing::instrument(target = "netlink", level = "trace", skip(self), fields(ns_uuid = %self.uuid))]
+    fn del_route(&self, iface: String, destination: IpNetwork, gateway: IPAddress) -> FResult<()> {
+        self.ip_route("del", iface, destination, gateway)
+    }
+
+    /// Runs `ip route <cmd>` for the route to `destination` via `gateway`
+    fn ip_route(
+        &self,
+        cmd: &str,
+        iface: String,
+        destination: IpNetwork,
+        gateway: IPAddress,
+    ) -> FResult<()> {
+        let output = self
+            .ns_command("ip")
+            .arg("route")
+            .arg(cmd)
+            .arg(format!("{}", destination))
+            .arg("via")
+            .arg(format!("{}", gateway))
+            .arg("dev")
+            .arg(iface)
+            .output()
+            .map_err(|e| FError::NetworkingError(format!("{}", e)))?;
+        if !output.status.success() {
+            return Err(FError::NetworkingError(
+                String::from_utf8_lossy(&output.stderr).to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Default routes of the interface matching the given one,
+    /// the metric is part of what identifies the route
+    async fn find_default_routes(
+        &self,
+        state: &mut NSManagerState,
+        index: u32,
+        route: &DefaultRoute,
+    ) -> FResult<Vec<RouteMessage>> {
+        let version = match route.version {
+            IPVersion::IPV4 => rtnetlink::IpVersion::V4,
+            IPVersion::IPV6 => rtnetlink::IpVersion::V6,
+        };
+        let gateway = route.gateway.map(|gw| match gw {
+            IPAddress::V4(gw) => gw.octets().to_vec(),
+            IPAddress::V6(gw) => gw.octets().to_vec(),
+        });
+        let mut found = Vec::new();
+        let mut routes = state.nl_handler.route().get(version).execute();
+        while let Some(msg) = routes
+            .try_next()
+            .await
+            .map_err(|e| FError::NetworkingError(format!("{}", e)))?
+        {
+            if msg.header.destination_prefix_length != 0 {
+                continue;
+            }
+            let mut oif = None;
+            let mut via = None;
+            let mut priority = None;
+            for nla in msg.nlas.iter() {
+                match nla {
+                    RouteNla::Oif(i) => oif = Some(*i),
+                    RouteNla::Gateway(gw) => via = Some(gw.clone()),
+                    RouteNla::Priority(p) => priority = Some(*p),
+                    _ => (),
+                }
+            }
+            if oif == Some(index)
+                && (gateway.is_none() || via == gateway)
+                && priority.unwrap_or(0) == route.metric.unwrap_or(0)
+            {
+                found.push(msg);
+            }
+        }
+        Ok(found)
+    }
+
+    /// Adds or replaces (`replace`) or removes the given default route
+    #[tracing::instrument(target = "netlink", level = "trace", skip(self), fields(ns_uuid = %self.uuid))]
+    async fn set_default_route_entry(
+        &self,
+        replace: bool,
+        iface: String,
+        route: &DefaultRoute,
+    ) -> FResult<()> {
+        let v6 = matches!(route.version, IPVersion::IPV6);
+        if let Some(gateway) = route.gateway {
+            if gateway.is_ipv6() != v6 {
+                return Err(FError::NetworkingError(format!(
+                    "Gateway {} is not of the route family",
+                    gateway
+                )));
+            }
+        }
+        let mut state = self.state.write().await;
+        let mut links = state
+            .nl_handler
+            .link()
+            .get()
+            .set_name_filter(iface)
+            .execute();
+        let index = match links
+            .try_next()
+            .await
+            .map_err(|e| FError::NetworkingError(format!("{}", e)))?
+        {
+            Some(link) => link.header.index,
+            None => return Err(FError::NotFound),
+        };
+        let existing = self.find_default_routes(&mut state, index, route).await?;
+        if !replace && existing.is_empty() {
+            return Err(FError::NotFound);
+        }
+        for msg in existing {
+            state
+                .nl_handler
+                .route()
+                .del(msg)
+                .execute()
+                .await
+                .map_err(|e| FError::NetworkingError(format!("{}", e)))?;
+        }
+        if !replace {
+            return Ok(());
+        }
+        match route.gateway {
+            Some(IPAddress::V4(gw)) => {
+                let mut req = state
+                    .nl_handler
+                    .route()
+                    .add()
+                    .v4()
+                    .destination_prefix(std::net::Ipv4Addr::UNSPECIFIED, 0)
+                    .output_interface(index)
+                    .gateway(gw);
+                if let Some(metric) = route.metric {
+                    req.message_mut().nlas.push(RouteNla::Priority(metric));
+                }
+                req.execute().await
+            }
+            Some(IPAddress::V6(gw)) => {
+                let mut req = state
+                    .nl_handler
+                    .route()
+                    .add()
+                    .v6()
+                    .destination_prefix(std::net::Ipv6Addr::UNSPECIFIED, 0)
+                    .output_interface(index)
+                    .gateway(gw);
+                if let Some(metric) = route.metric {
+                    req.message_mut().nlas.push(RouteNla::Priority(metric));
+                }
+                req.execute().await
+            }
+            None if v6 => {
+                let mut req = state
+                    .nl_handler
+                    .route()
+                    .add()
+                    .v6()
+                    .destination_prefix(std::net::Ipv6Addr::UNSPECIFIED, 0)
+                    .output_interface(index);
+                if let Some(metric) = route.metric {
+                    req.message_mut().nlas.push(RouteNla::Priority(metric));
+                }
+                req.execute().await
+            }
+            None => {
+                let mut req = state
+                    .nl_handler
+                    .route()
+                    .add()
+                    .v4()
+                    .destination_prefix(std::net::Ipv4Addr::UNSPECIFIED, 0)
+                    .output_interface(index);
+                if let Some(metric) = route.metric {
+                    req.message_mut().nlas.push(RouteNla::Priority(metric));
+                }
+                req.execute().await
+            }
+        }
+        .map_err(|e| FError::NetworkingError(format!("{}", e)))
+    }
+
+    /// Sends one ICMP echo to the gateway through the interface
+    #[tracing::instrument(target = "netlink", level = "trace", skip(self), fields(ns_uuid = %self.uuid))]
+    async fn ping(&self, iface: String, gateway: IPAddress) -> FResult<bool> {
+        let mut cmd = self.ns_command("ping");
+        cmd.arg(if gateway.is_ipv6() { "-6" } else { "-4" })
+            .arg("-c")
+            .arg("1")
+            .arg("-W")
+            .arg("1")
+            .arg("-I")
+            .arg(iface)
+            .arg(format!("{}", gateway));
+        // the other requests are served while waiting for the answer, a
+        // shared manager already serves the request on its own thread
+        let output = if self.shared_netns.is_some() {
+            cmd.output()
+        } else {
+            runtime::spawn_blocking(move || cmd.output()).await
+        }
+        .map_err(|e| FError::NetworkingError(format!("{}", e)))?;
+        Ok(output.status.success())
+    }
+
+    #[tracing::instrument(target = "netlink", level = "trace", skip(self), fields(ns_uuid = %self.uuid))]
     async fn dump_links(&self) -> FResult<Vec<String>> {
-        log::trace!("dump_links");
         let mut ifaces = Vec::new();
         let mut state = self.state.write().await;
         let mut links = state.nl_handler.link().get().execute();
@@ -863,8 +1666,28 @@ impl NSManager {
         Ok(ifaces)
     }
 
+    /// Counters of all the links in the namespace, from a single dump
+    #[tracing::instrument(target = "netlink", level = "trace", skip(self), fields(ns_uuid = %self.uuid))]
+    async fn collect_link_stats(&self) -> FResult<LinkStatsDump> {
+        let mut dump = LinkStatsDump::default();
+        let mut state = self.state.write().await;
+        let mut links = state.nl_handler.link().get().execute();
+        while let Some(msg) = links
+            .try_next()
+            .await
+            .map_err(|e| FError::NetworkingError(format!("{}", e)))?
+        {
+            dump.add_link(msg.header.index, msg.nlas);
+        }
+        dump.collected_ms = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        Ok(dump)
+    }
+
+    #[tracing::instrument(target = "netlink", level = "trace", skip(self), fields(ns_uuid = %self.uuid))]
     async fn add_default_route(&self, iface: String) -> FResult<()> {
-        log::trace!("add_default_route({})", iface);
         let mut state = self.state.write().await;
         let mut links = state
             .nl_handler
@@ -895,76 +1718,245 @@ impl NSManager {
 
 #[znserver]
 impl NamespaceManager for NSManager {
-    async fn set_virtual_interface_up(&self, iface: String) -> FResult<()> {
-        self.set_iface_up(iface).await
+    async fn set_virtual_interface_up(&self, ctx: SpanContext, iface: String) -> FResult<()> {
+        self.serve(ctx, "set_virtual_interface_up", self.set_iface_up(iface))
+            .await
+    }
+    async fn set_virtual_interface_down(&self, ctx: SpanContext, iface: String) -> FResult<()> {
+        self.serve(
+            ctx,
+            "set_virtual_interface_down",
+            self.set_iface_down(iface),
+        )
+        .await
+    }
+    async fn set_default_route(&self, ctx: SpanContext, iface: String) -> FResult<()> {
+        self.serve(ctx, "set_default_route", self.add_default_route(iface))
+            .await
+    }
+    async fn add_virtual_interface_default_route(
+        &self,
+        ctx: SpanContext,
+        iface: String,
+        route: DefaultRoute,
+    ) -> FResult<()> {
+        self.serve(
+            ctx,
+            "add_virtual_interface_default_route",
+            self.set_default_route_entry(true, iface, &route),
+        )
+        .await
+    }
+    async fn del_virtual_interface_default_route(
+        &self,
+        ctx: SpanContext,
+        iface: String,
+        route: DefaultRoute,
+    ) -> FResult<()> {
+        self.serve(
+            ctx,
+            "del_virtual_interface_default_route",
+            self.set_default_route_entry(false, iface, &route),
+        )
+        .await
+    }
+    async fn probe_gateway(
+        &self,
+        ctx: SpanContext,
+        iface: String,
+        gateway: IPAddress,
+    ) -> FResult<bool> {
+        self.serve(ctx, "probe_gateway", self.ping(iface, gateway))
+            .await
+    }
+    async fn add_virtual_interface_route(
+        &self,
+        ctx: SpanContext,
+        iface: String,
+        destination: IpNetwork,
+        gateway: IPAddress,
+    ) -> FResult<()> {
+        self.serve(ctx, "add_virtual_interface_route", async {
+            self.add_route(iface, destination, gateway)
+        })
+        .await
     }
-    async fn set_virtual_interface_down(&self, iface: String) -> FResult<()> {
-        self.set_iface_down(iface).await
+    async fn del_virtual_interface_route(
+        &self,
+        ctx: SpanContext,
+        iface: String,
+        destination: IpNetwork,
+        gateway: IPAddress,
+    ) -> FResult<()> {
+        self.serve(ctx, "del_virtual_interface_route", async {
+            self.del_route(iface, destination, gateway)
+        })
+        .await
     }
-    async fn set_default_route(&self, iface: String) -> FResult<()> {
-        self.add_default_route(iface).await
+    async fn check_virtual_interface_exists(
+        &self,
+        ctx: SpanContext,
+        iface: String,
+    ) -> FResult<bool> {
+        self.serve(
+            ctx,
+            "check_virtual_interface_exists",
+            self.iface_exists(iface),
+        )
+        .await
     }
-    async fn check_virtual_interface_exists(&self, iface: String) -> FResult<bool> {
-        self.iface_exists(iface).await
+    async fn move_virtual_interface_into_default_ns(
+        &self,
+        ctx: SpanContext,
+        iface: String,
+    ) -> FResult<()> {
+        self.serve(
+            ctx,
+            "move_virtual_interface_into_default_ns",
+            self.set_iface_default_ns(iface),
+        )
+        .await
     }
-    async fn move_virtual_interface_into_default_ns(&self, iface: String) -> FResult<()> {
-        self.set_iface_default_ns(iface).await
+    async fn set_virtual_interface_mac(
+        &self,
+        ctx: SpanContext,
+        iface: String,
+        address: Vec<u8>,
+    ) -> FResult<()> {
+        self.serve(
+            ctx,
+            "set_virtual_interface_mac",
+            self.set_iface_mac(iface, address),
+        )
+        .await
     }
-    async fn set_virtual_interface_mac(&self, iface: String, address: Vec<u8>) -> FResult<()> {
-        self.set_iface_mac(iface, address).await
+    async fn set_virtual_interface_mtu(
+        &self,
+        ctx: SpanContext,
+        iface: String,
+        mtu: u32,
+    ) -> FResult<()> {
+        self.serve(
+            ctx,
+            "set_virtual_interface_mtu",
+            self.set_iface_mtu(iface, mtu),
+        )
+        .await
     }
-    async fn set_virtual_interface_name(&self, iface: String, name: String) -> FResult<()> {
-        self.set_iface_name(iface, name).await
+    async fn set_virtual_interface_name(
+        &self,
+        ctx: SpanContext,
+        iface: String,
+        name: String,
+    ) -> FResult<()> {
+        self.serve(
+            ctx,
+            "set_virtual_interface_name",
+            self.set_iface_name(iface, name),
+        )
+        .await
     }
-    async fn del_virtual_interface_address(&self, iface: String, addr: IPAddress) -> FResult<()> {
-        self.del_iface_address(iface, addr).await
+    async fn del_virtual_interface_address(
+        &self,
+        ctx: SpanContext,
+        iface: String,
+        addr: IPAddress,
+    ) -> FResult<()> {
+        self.serve(
+            ctx,
+            "del_virtual_interface_address",
+            self.del_iface_address(iface, addr),
+        )
+        .await
     }
 
-    async fn get_virtual_interface_addresses(&self, iface: String) -> FResult<Vec<IPAddress>> {
-        self.get_iface_addresses(iface).await
+    async fn get_virtual_interface_addresses(
+        &self,
+        ctx: SpanContext,
+        iface: String,
+    ) -> FResult<Vec<IPAddress>> {
+        self.serve(
+            ctx,
+            "get_virtual_interface_addresses",
+            self.get_iface_addresses(iface),
+        )
+        .await
     }
 
     async fn add_virtual_interface_address(
         &self,
+        ctx: SpanContext,
         iface: String,
         addr: Option<IpNetwork>,
     ) -> FResult<Vec<IPAddress>> {
-        log::trace!("add_virtual_interface_address {} {:?}", iface, addr);
-        match addr {
-            Some(addr) => {
-                self.add_iface_address(iface.clone(), addr.ip(), addr.prefix())
-                    .await?;
-                self.get_iface_addresses(iface).await
-            }
-            None => {
-                log::trace!("Using DHCP");
-                // If the address is None we spawn a DHCP client
-                // and then we the the address from netlink
-                let mut child = Command::new("dhclient")
-                    .arg("-i")
-                    .arg(iface.clone())
-                    .spawn()
-                    .map_err(|e| FError::NetworkingError(format!("{}", e)))?;
-                log::trace!("DHCP Client running {}", child.id());
-                let res = child
-                    .wait()
-                    .map_err(|e| FError::NetworkingError(format!("{}", e)))?;
-                log::trace!("DHCP Client exited with {:?}", res);
-                self.get_iface_addresses(iface).await
+        self.serve(ctx, "add_virtual_interface_address", async {
+            match addr {
+                Some(addr) => {
+                    self.add_iface_address(iface.clone(), addr.ip(), addr.prefix())
+                        .await?;
+                    self.get_iface_addresses(iface).await
+                }
+                None => {
+                    log::trace!("Using DHCP");
+                    // If the address is None we spawn a DHCP client
+                    // and then we the the address from netlink
+                    let mut child = self
+                        .ns_command("dhclient")
+                        .arg("-i")
+                        .arg(iface.clone())
+                        .spawn()
+                        .map_err(|e| FError::NetworkingError(format!("{}", e)))?;
+                    log::trace!("DHCP Client running {}", child.id());
+                    let res = child
+                        .wait()
+                        .map_err(|e| FError::NetworkingError(format!("{}", e)))?;
+                    log::trace!("DHCP Client exited with {:?}", res);
+                    self.get_iface_addresses(iface).await
+                }
             }
-        }
+        })
+        .await
     }
-    async fn set_virtual_interface_master(&self, iface: String, master: String) -> FResult<()> {
-        self.set_iface_master(iface, master).await
+    async fn set_virtual_interface_master(
+        &self,
+        ctx: SpanContext,
+        iface: String,
+        master: String,
+    ) -> FResult<()> {
+        self.serve(
+            ctx,
+            "set_virtual_interface_master",
+            self.set_iface_master(iface, master),
+        )
+        .await
+    }
+    async fn get_virtual_interface_master(
+        &self,
+        ctx: SpanContext,
+        iface: String,
+    ) -> FResult<Option<String>> {
+        self.serve(
+            ctx,
+            "get_virtual_interface_master",
+            self.get_iface_master(iface),
+        )
+        .await
     }
-    async fn set_virtual_interface_nomaster(&self, iface: String) -> FResult<()> {
-        self.del_iface_master(iface).await
+    async fn set_virtual_interface_nomaster(&self, ctx: SpanContext, iface: String) -> FResult<()> {
+        self.serve(
+            ctx,
+            "set_virtual_interface_nomaster",
+            self.del_iface_master(iface),
+        )
+        .await
     }
-    async fn del_virtual_interface(&self, iface: String) -> FResult<()> {
-        self.del_iface(iface).await
+    async fn del_virtual_interface(&self, ctx: SpanContext, iface: String) -> FResult<()> {
+        self.serve(ctx, "del_virtual_interface", self.del_iface(iface))
+            .await
     }
     async fn add_virtual_interface_ptp_vxlan(
         &self,
+        ctx: SpanContext,
         iface: String,
         dev: String,
         vni: u32,
@@ -972,41 +1964,155 @@ impl NamespaceManager for NSManager {
         remote_addr: IPAddress,
         port: u16,
     ) -> FResult<()> {
-        self.create_ptp_vxlan(iface, dev, vni, local_addr, remote_addr, port)
-            .await
+        self.serve(
+            ctx,
+            "add_virtual_interface_ptp_vxlan",
+            self.create_ptp_vxlan(iface, dev, vni, local_addr, remote_addr, port),
+        )
+        .await
     }
     async fn add_virtual_interface_mcast_vxlan(
         &self,
+        ctx: SpanContext,
         iface: String,
         dev: String,
         vni: u32,
         mcast_addr: IPAddress,
         port: u16,
     ) -> FResult<()> {
-        self.create_mcast_vxlan(iface.clone(), dev, vni, mcast_addr, port)
-            .await?;
-        self.set_iface_up(iface).await
+        self.serve(ctx, "add_virtual_interface_mcast_vxlan", async {
+            self.create_mcast_vxlan(iface.clone(), dev, vni, mcast_addr, port)
+                .await?;
+            self.set_iface_up(iface).await
+        })
+        .await
     }
     async fn add_virtual_interface_vlan(
         &self,
+        ctx: SpanContext,
         iface: String,
         dev: String,
         tag: u16,
     ) -> FResult<()> {
-        self.create_vlan(iface.clone(), dev, tag).await?;
-        self.set_iface_up(iface).await
+        self.serve(ctx, "add_virtual_interface_vlan", async {
+            self.create_vlan(iface.clone(), dev, tag).await?;
+            self.set_iface_up(iface).await
+        })
+        .await
     }
-    async fn add_virtual_interface_veth(&self, iface_i: String, iface_e: String) -> FResult<()> {
-        self.create_veth(iface_i.clone(), iface_e.clone()).await?;
-        self.set_iface_up(iface_i).await?;
-        self.set_iface_up(iface_e).await
+    async fn add_virtual_interface_ip_tunnel(
+        &self,
+        ctx: SpanContext,
+        iface: String,
+        kind: IPTunnelKind,
+        local_addr: IPAddress,
+        remote_addr: IPAddress,
+        ttl: Option<u8>,
+    ) -> FResult<()> {
+        self.serve(ctx, "add_virtual_interface_ip_tunnel", async {
+            self.create_ip_tunnel(iface.clone(), kind, local_addr, remote_addr, ttl)?;
+            self.set_iface_up(iface).await
+        })
+        .await
     }
-    async fn add_virtual_interface_bridge(&self, br_name: String) -> FResult<()> {
-        self.create_bridge(br_name.clone()).await?;
-        self.set_iface_up(br_name).await
+    async fn add_virtual_interface_veth(
+        &self,
+        ctx: SpanContext,
+        iface_i: String,
+        iface_e: String,
+    ) -> FResult<()> {
+        self.serve(ctx, "add_virtual_interface_veth", async {
+            self.create_veth(iface_i.clone(), iface_e.clone()).await?;
+            self.set_iface_up(iface_i).await?;
+            self.set_iface_up(iface_e).await
+        })
+        .await
+    }
+    async fn add_virtual_interface_bridge(&self, ctx: SpanContext, br_name: String) -> FResult<()> {
+        self.serve(ctx, "add_virtual_interface_bridge", async {
+            self.create_bridge(br_name.clone()).await?;
+            self.set_iface_up(br_name).await
+        })
+        .await
     }
 
-    async fn list_interfaces(&self) -> FResult<Vec<String>> {
-        self.dump_links().await
+    async fn list_interfaces(&self, ctx: SpanContext) -> FResult<Vec<String>> {
+        self.serve(ctx, "list_interfaces", self.dump_links()).await
+    }
+
+    async fn dump_link_stats(&self, ctx: SpanContext) -> FResult<LinkStatsDump> {
+        self.serve(ctx, "dump_link_stats", self.collect_link_stats())
+            .await
+    }
+
+    async fn set_reverse_path_filter(
+        &self,
+        ctx: SpanContext,
+        iface: Option<String>,
+        filter: ReversePathFilter,
+    ) -> FResult<()> {
+        self.serve(ctx, "set_reverse_path_filter", async {
+            sysctl::set_reverse_path_filter(|p| self.ns_command(p), iface.as_deref(), &filter)
+        })
+        .await
+    }
+
+    async fn get_martian_counters(&self, ctx: SpanContext) -> FResult<MartianCounters> {
+        self.serve(ctx, "get_martian_counters", async {
+            sysctl::read_martian_counters(|p| self.ns_command(p))
+        })
+        .await
+    }
+
+    async fn set_virtual_interface_bridge_port(
+        &self,
+        ctx: SpanContext,
+        iface: String,
+        config: BridgePortConfig,
+    ) -> FResult<()> {
+        self.serve(ctx, "set_virtual_interface_bridge_port", async {
+            config.validate()?;
+            let mut state = self.state.write().await;
+            let mut links = state
+                .nl_handler
+                .link()
+                .get()
+                .set_name_filter(iface)
+                .execute();
+            match links
+                .try_next()
+                .await
+                .map_err(|e| FError::NetworkingError(format!("{}", e)))?
+            {
+                Some(link) => set_bridge_port(&mut state.nl_handler, link.header.index, &config)
+                    .await
+                    .map_err(|e| FError::NetworkingError(format!("{}", e))),
+                None => Err(FError::NotFound),
+            }
+        })
+        .await
+    }
+
+    async fn flush_virtual_interface_fdb(&self, ctx: SpanContext, iface: String) -> FResult<()> {
+        self.serve(ctx, "flush_virtual_interface_fdb", async {
+            let mut state = self.state.write().await;
+            let mut links = state
+                .nl_handler
+                .link()
+                .get()
+                .set_name_filter(iface)
+                .execute();
+            match links
+                .try_next()
+                .await
+                .map_err(|e| FError::NetworkingError(format!("{}", e)))?
+            {
+                Some(link) => flush_bridge_fdb(&mut state.nl_handler, link.header.index)
+                    .await
+                    .map_err(|e| FError::NetworkingError(format!("{}", e))),
+                None => Err(FError::NotFound),
+            }
+        })
+        .await
     }
 }
@@ -26,6 +26,9 @@ use async_ctrlc::CtrlC;
 
 use structopt::StructOpt;
 
+use fog05_networking_linux::logging;
+use fog05_networking_linux::runtime;
+use fog05_networking_linux::simulation;
 use fog05_networking_linux::types::{deserialize_plugin_config, LinuxNetwork};
 
 static CONFIG_FILE: &str = "/etc/fos/linux-network/config.yaml";
@@ -35,17 +38,32 @@ struct LinuxNetArgs {
     /// Config file
     #[structopt(short, long, default_value = CONFIG_FILE)]
     config: String,
+    /// Records links, namespaces, nftables and processes in an in-memory
+    /// model instead of configuring the kernel, does not require root
+    #[structopt(long)]
+    simulate: bool,
 }
 
 async fn read_file(path: &Path) -> String {
     fs::read_to_string(path).await.unwrap()
 }
 
-#[async_std::main]
-async fn main() {
-    env_logger::init_from_env(
-        env_logger::Env::default().filter_or(env_logger::DEFAULT_FILTER_ENV, "info"),
-    );
+/// Dumps the plugin state and exits
+fn fatal(net: &LinuxNetwork, reason: String) -> ! {
+    log::error!("{}", reason);
+    match net.dump_state(&reason) {
+        Ok(path) => log::error!("State dumped to {}", path),
+        Err(e) => log::error!("Unable to dump state: {}", e),
+    }
+    process::exit(1)
+}
+
+fn main() {
+    runtime::block_on(async_main())
+}
+
+async fn async_main() {
+    logging::init("info");
 
     let args = LinuxNetArgs::from_args();
     log::info!("Linux Network Plugin -- bootstrap");
@@ -57,6 +75,16 @@ async fn main() {
         deserialize_plugin_config(&(read_file(&conf_file_path).await.into_bytes().as_slice()))
             .unwrap();
 
+    if let Some(settings) = config.log.clone() {
+        if let Err(e) = logging::apply(settings) {
+            log::error!("Invalid log settings: {}", e);
+        }
+    }
+
+    if args.simulate {
+        simulation::enable();
+    }
+
     let properties = format!("mode=client;peer={}", config.zlocator.clone());
     let zproperties = Properties::from(properties);
     let z = Arc::new(Zenoh::new(zproperties.clone().into()).await.unwrap());
@@ -67,6 +95,8 @@ async fn main() {
         .await
         .unwrap();
 
+    net.install_state_dump_hook();
+
     let (s, h) = net.start().await;
 
     //Creating the Ctrl-C handler and racing with agent.run
@@ -77,10 +107,14 @@ async fn main() {
     log::trace!("Received Ctrl-C start teardown");
 
     //Here we send the stop signal to the agent object and waits that it ends
-    net.stop(s).await.unwrap();
+    if let Err(e) = net.stop(s).await {
+        fatal(&net, format!("Stop failed: {}", e));
+    }
 
     //wait for the futures to ends
-    h.await.unwrap();
+    if let Err(e) = h.await {
+        fatal(&net, format!("Main loop failed: {}", e));
+    }
 
     //zconnector.close();
     //zenoh.close();
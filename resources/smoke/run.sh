@@ -0,0 +1,57 @@
+#!/usr/bin/env bash
+#
+# Copyright (c) 2017, 2021 ADLINK Technology Inc.
+#
+# This program and the accompanying materials are made available under the
+# terms of the Eclipse Public License 2.0 which is available at
+# http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+# which is available at https://www.apache.org/licenses/LICENSE-2.0.
+#
+# SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+#
+# Contributors:
+#   ADLINK fog05 team, <fog05@adlink-labs.tech>
+#
+# Runs the netlink smoke tests inside a new user and network namespace,
+# so no root is needed and the host links are left untouched.
+# With a target the tests are cross compiled and run under qemu-user:
+#
+#   resources/smoke/run.sh [armv7-unknown-linux-gnueabihf|aarch64-unknown-linux-gnu]
+#
+# Cross builds need the matching gcc and qemu-user packages and are done
+# without the nat feature, that links to the native nftables libraries.
+
+set -euo pipefail
+
+TARGET=${1:-}
+FEATURES="smoke-tests,dhcp"
+
+case "$TARGET" in
+    "")
+        RUNNER=""
+        BUILD_ARGS=(--features "$FEATURES")
+        ;;
+    armv7-unknown-linux-gnueabihf)
+        export CARGO_TARGET_ARMV7_UNKNOWN_LINUX_GNUEABIHF_LINKER=arm-linux-gnueabihf-gcc
+        RUNNER="qemu-arm -L /usr/arm-linux-gnueabihf"
+        BUILD_ARGS=(--target "$TARGET" --no-default-features --features "$FEATURES")
+        ;;
+    aarch64-unknown-linux-gnu)
+        export CARGO_TARGET_AARCH64_UNKNOWN_LINUX_GNU_LINKER=aarch64-linux-gnu-gcc
+        RUNNER="qemu-aarch64 -L /usr/aarch64-linux-gnu"
+        BUILD_ARGS=(--target "$TARGET" --no-default-features --features "$FEATURES")
+        ;;
+    *)
+        echo "Unsupported target $TARGET" >&2
+        exit 1
+        ;;
+esac
+
+TEST_BIN=$(cargo test "${BUILD_ARGS[@]}" --test netlink_smoke --no-run --message-format=json \
+    | grep '"executable":"[^"]*netlink_smoke' \
+    | sed 's/.*"executable":"\([^"]*\)".*/\1/' \
+    | tail -n 1)
+
+# tests share the namespace, run them one at a time
+unshare --user --map-root-user --net \
+    sh -c "ip link set lo up && $RUNNER $TEST_BIN --test-threads=1"
@@ -0,0 +1,901 @@
+/*********************************************************************************
+* Copyright (c) 2018,2021 ADLINK Technology Inc.
+*
+* This program and the accompanying materials are made available under the
+* terms of the Eclipse Public License 2.0 which is available at
+* http://www.eclipse.org/legal/epl-2.0, or the Apache Software License 2.0
+* which is available at https://www.apache.org/licenses/LICENSE-2.0.
+*
+* SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+* Contributors:
+*   ADLINK fog05 team, <fog05@adlink-labs.tech>
+*********************************************************************************/
+
+//! DHCP backends of the virtual networks.
+//!
+//! The backend starting new servers is chosen per node with `dhcp_backend`
+//! in the configuration. The one serving a network is recorded in its
+//! internals, so that the server is reloaded, respawned and stopped by
+//! the same backend after the configuration changes. A new backend
+//! implements [`DhcpBackend`] and gets a `DHCPBackend` variant.
+//!
+//! Servers started with `lease_hook` set run it on lease events with
+//! the arguments of a dnsmasq `dhcp-script`: `add`, `old` or `del`, the
+//! MAC and the address of the client. `DNSMASQ_INTERFACE` is the bridge
+//! of the network. The relay has no leases and does not run it.
+
+#[cfg(feature = "dhcp")]
+use std::error::Error;
+use std::process::{Child, Command, Stdio};
+
+use async_std::sync::RwLock;
+use async_trait::async_trait;
+
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+
+use serde::Serialize;
+
+use fog05_sdk::agent::os::OSClient;
+use fog05_sdk::fresult::{FError, FResult};
+use fog05_sdk::types::IPAddress;
+
+#[cfg(feature = "dhcp")]
+use tera::{Context, Tera};
+
+use uuid::Uuid;
+
+use crate::dhcp_server::{self, DHCPServerConfig, DHCP_LEASE_TIME};
+use crate::logging;
+use crate::simulation;
+use crate::types::{
+    DHCPBackend, DHCPBootOptions, DHCPOption, DHCPOptionValue, DHCPReservation, LinuxNetworkState,
+    VNetDHCP, VirtualNetworkInternals,
+};
+
+const DHCP_OPTION_DOMAIN_NAME: u8 = 15;
+const DHCP_OPTION_MTU: u8 = 26;
+
+/// Network to be served by a backend
+pub struct DHCPService<'a> {
+    pub vnet_uuid: Uuid,
+    /// Namespace of the interface, `None` for the default one
+    pub ns_name: Option<&'a str>,
+    pub iface: &'a str,
+    /// At least one, the server address is the gateway of each range
+    pub ranges: &'a [DNSMasqRange],
+    /// Path of the files of the server without extension,
+    /// eg. `<run_path>/<vnet_uuid>`
+    pub files_prefix: &'a str,
+    pub internals: Option<&'a VirtualNetworkInternals>,
+}
+
+impl<'a> DHCPService<'a> {
+    fn file(&self, ext: &str) -> String {
+        format!("{}.{}", self.files_prefix, ext)
+    }
+}
+
+#[async_trait]
+pub trait DhcpBackend: Send + Sync {
+    /// Starts serving the network, the returned internals are the ones
+    /// given back to the other operations. `routers` is left to the caller.
+    async fn start(&self, service: &DHCPService<'_>) -> FResult<VNetDHCP>;
+    /// Starts again a server that is no longer running, eg. after a reboot
+    async fn respawn(
+        &self,
+        vnet_uuid: Uuid,
+        ns_name: Option<&str>,
+        iface: &str,
+        dhcp: &VNetDHCP,
+    ) -> FResult<()>;
+    /// Applies the options and reservations in `internals`
+    async fn reload(
+        &self,
+        vnet_uuid: Uuid,
+        dhcp: &mut VNetDHCP,
+        internals: &VirtualNetworkInternals,
+    ) -> FResult<()>;
+    /// Stops the server and removes its files
+    async fn stop(&self, vnet_uuid: Uuid, dhcp: VNetDHCP) -> FResult<()>;
+}
+
+impl VNetDHCP {
+    /// Backend serving the network, the ones started before it was
+    /// recorded are told apart by the embedded configuration
+    pub fn backend_kind(&self) -> DHCPBackend {
+        match (self.backend, &self.embedded) {
+            (Some(backend), _) => backend,
+            (None, Some(_)) => DHCPBackend::EMBEDDED,
+            (None, None) => DHCPBackend::DNSMASQ,
+        }
+    }
+}
+
+/// dnsmasq, spawned with `ip netns exec` in the namespace of the network.
+/// Options and reservations are in files re-read on SIGHUP.
+pub struct DnsmasqBackend<'a> {
+    pub os: &'a OSClient,
+    /// Directory of the templates
+    pub path: &'a std::path::Path,
+    pub lease_hook: Option<&'a str>,
+}
+
+#[async_trait]
+impl<'a> DhcpBackend for DnsmasqBackend<'a> {
+    async fn start(&self, service: &DHCPService<'_>) -> FResult<VNetDHCP> {
+        // the DHCP options file carries IPv4 options only
+        let server_addr = service.ranges.iter().find(|r| !r.v6).map(|r| r.gateway);
+        let dhcp = VNetDHCP {
+            leases_file: service.file("leases"),
+            pid_file: service.file("pid"),
+            conf: service.file("conf"),
+            log_file: service.file("log"),
+            opts_file: Some(service.file("opts")),
+            router: Some(server_addr.unwrap_or(service.ranges[0].gateway)),
+            routers: Vec::new(),
+            embedded: None,
+            hosts_file: Some(service.file("hosts")),
+            backend: Some(DHCPBackend::DNSMASQ),
+        };
+        self.os
+            .store_file(
+                render_dhcp_opts(service.internals, server_addr)?.into_bytes(),
+                service.file("opts"),
+            )
+            .await??;
+        let reservations = service
+            .internals
+            .map(|i| i.dhcp_reservations.as_slice())
+            .unwrap_or_default();
+        self.os
+            .store_file(
+                render_dhcp_hosts(reservations).into_bytes(),
+                service.file("hosts"),
+            )
+            .await??;
+        let config = self.create_config(service.iface, service.ranges, &dhcp)?;
+        log::trace!(target: logging::DHCP, "dnsmasq config: {}", config);
+        self.os
+            .store_file(config.into_bytes(), dhcp.conf.clone())
+            .await??;
+        let child = spawn_dnsmasq(service.ns_name, &dhcp.conf)?;
+        log::debug!(
+            target: logging::DHCP,
+            "DHCP Process for {} running PID: {}",
+            service.vnet_uuid,
+            child.id()
+        );
+        Ok(dhcp)
+    }
+
+    async fn respawn(
+        &self,
+        _vnet_uuid: Uuid,
+        ns_name: Option<&str>,
+        _iface: &str,
+        dhcp: &VNetDHCP,
+    ) -> FResult<()> {
+        spawn_dnsmasq(ns_name, &dhcp.conf)?;
+        Ok(())
+    }
+
+    async fn reload(
+        &self,
+        vnet_uuid: Uuid,
+        dhcp: &mut VNetDHCP,
+        internals: &VirtualNetworkInternals,
+    ) -> FResult<()> {
+        let opts_file = dhcp.opts_file.clone().ok_or_else(|| {
+            FError::NetworkingError(format!("DHCP of {} does not support options", vnet_uuid))
+        })?;
+        let opts = render_dhcp_opts(Some(internals), dhcp.router)?;
+        self.os.store_file(opts.into_bytes(), opts_file).await??;
+        match &dhcp.hosts_file {
+            Some(hosts_file) => {
+                self.os
+                    .store_file(
+                        render_dhcp_hosts(&internals.dhcp_reservations).into_bytes(),
+                        hosts_file.clone(),
+                    )
+                    .await??;
+            }
+            // dnsmasq started by a previous version without the hosts file
+            None if !internals.dhcp_reservations.is_empty() => {
+                return Err(FError::NetworkingError(format!(
+                    "DHCP of {} does not support reservations",
+                    vnet_uuid
+                )))
+            }
+            None => (),
+        }
+        self.signal(dhcp, Signal::SIGHUP).await
+    }
+
+    async fn stop(&self, _vnet_uuid: Uuid, dhcp: VNetDHCP) -> FResult<()> {
+        self.signal(&dhcp, Signal::SIGKILL).await?;
+        async_std::fs::remove_file(async_std::path::Path::new(&dhcp.pid_file)).await?;
+        async_std::fs::remove_file(async_std::path::Path::new(&dhcp.leases_file)).await?;
+        async_std::fs::remove_file(async_std::path::Path::new(&dhcp.conf)).await?;
+        async_std::fs::remove_file(async_std::path::Path::new(&dhcp.log_file)).await?;
+        if let Some(opts_file) = dhcp.opts_file {
+            async_std::fs::remove_file(async_std::path::Path::new(&opts_file)).await?;
+        }
+        if let Some(hosts_file) = dhcp.hosts_file {
+            async_std::fs::remove_file(async_std::path::Path::new(&hosts_file)).await?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> DnsmasqBackend<'a> {
+    async fn signal(&self, dhcp: &VNetDHCP, signal: Signal) -> FResult<()> {
+        let pid = read_pid_file(self.os, &dhcp.pid_file).await?;
+        log::trace!(target: logging::DHCP, "Sending {:?} to dnsmasq {}", signal, pid);
+        kill(Pid::from_raw(pid), signal).map_err(|e| FError::NetworkingError(format!("{}", e)))
+    }
+
+    #[cfg(not(feature = "dhcp"))]
+    fn create_config(
+        &self,
+        _iface: &str,
+        _ranges: &[DNSMasqRange],
+        _dhcp: &VNetDHCP,
+    ) -> FResult<String> {
+        Err(FError::Unimplemented)
+    }
+
+    #[cfg(feature = "dhcp")]
+    fn create_config(
+        &self,
+        iface: &str,
+        ranges: &[DNSMasqRange],
+        dhcp: &VNetDHCP,
+    ) -> FResult<String> {
+        log::trace!(
+            target: logging::DHCP,
+            "create_dnsmasq_config {} {} {} {:?}",
+            iface,
+            dhcp.pid_file,
+            dhcp.leases_file,
+            ranges,
+        );
+        let mut context = Context::new();
+        context.insert("dhcp_interface", iface);
+        context.insert("lease_file", &dhcp.leases_file);
+        context.insert("dhcp_pid", &dhcp.pid_file);
+        context.insert("dhcp_log", &dhcp.log_file);
+        context.insert("ranges", ranges);
+        // router advertisements announce the IPv6 prefixes and gateways
+        context.insert("enable_ra", &ranges.iter().any(|r| r.v6));
+        context.insert("dhcp_opts", dhcp.opts_file.as_deref().unwrap_or(""));
+        context.insert("dhcp_hosts", dhcp.hosts_file.as_deref().unwrap_or(""));
+        context.insert("lease_hook", self.lease_hook.unwrap_or(""));
+
+        render_template(self.path, "dnsmasq.conf", &context)
+    }
+}
+
+/// dnsmasq relaying the DHCPv4 requests received on the bridge to the
+/// central server in `dhcp_relay`, with the address of the bridge as
+/// relay agent address (giaddr). Options and reservations are managed
+/// on the server.
+pub struct RelayBackend<'a> {
+    pub os: &'a OSClient,
+    /// Directory of the templates
+    pub path: &'a std::path::Path,
+}
+
+#[async_trait]
+impl<'a> DhcpBackend for RelayBackend<'a> {
+    async fn start(&self, service: &DHCPService<'_>) -> FResult<VNetDHCP> {
+        let server = service
+            .internals
+            .and_then(|i| i.dhcp_relay)
+            .ok_or_else(|| FError::NetworkingError("No DHCP server to relay to".to_string()))?;
+        let local = service.ranges.iter().find(|r| !r.v6).map(|r| r.gateway);
+        let local = match (local, server) {
+            (Some(local @ IPAddress::V4(_)), IPAddress::V4(_)) => local,
+            _ => {
+                return Err(FError::NetworkingError(
+                    "The DHCP relay serves IPv4 networks only".to_string(),
+                ))
+            }
+        };
+        let dhcp = VNetDHCP {
+            leases_file: String::new(),
+            pid_file: service.file("pid"),
+            conf: service.file("conf"),
+            log_file: service.file("log"),
+            opts_file: None,
+            router: Some(local),
+            routers: Vec::new(),
+            embedded: None,
+            hosts_file: None,
+            backend: Some(DHCPBackend::RELAY),
+        };
+        let config = self.create_config(service.iface, local, server, &dhcp)?;
+        log::trace!(target: logging::DHCP, "dnsmasq relay config: {}", config);
+        self.os
+            .store_file(config.into_bytes(), dhcp.conf.clone())
+            .await??;
+        let child = spawn_dnsmasq(service.ns_name, &dhcp.conf)?;
+        log::debug!(
+            target: logging::DHCP,
+            "DHCP relay for {} to {} running PID: {}",
+            service.vnet_uuid,
+            server,
+            child.id()
+        );
+        Ok(dhcp)
+    }
+
+    async fn respawn(
+        &self,
+        _vnet_uuid: Uuid,
+        ns_name: Option<&str>,
+        _iface: &str,
+        dhcp: &VNetDHCP,
+    ) -> FResult<()> {
+        spawn_dnsmasq(ns_name, &dhcp.conf)?;
+        Ok(())
+    }
+
+    async fn reload(
+        &self,
+        vnet_uuid: Uuid,
+        _dhcp: &mut VNetDHCP,
+        _internals: &VirtualNetworkInternals,
+    ) -> FResult<()> {
+        Err(FError::NetworkingError(format!(
+            "DHCP of {} is relayed, options and reservations are set on the server",
+            vnet_uuid
+        )))
+    }
+
+    async fn stop(&self, _vnet_uuid: Uuid, dhcp: VNetDHCP) -> FResult<()> {
+        let dnsmasq = DnsmasqBackend {
+            os: self.os,
+            path: self.path,
+            lease_hook: None,
+        };
+        dnsmasq.signal(&dhcp, Signal::SIGKILL).await?;
+        async_std::fs::remove_file(async_std::path::Path::new(&dhcp.pid_file)).await?;
+        async_std::fs::remove_file(async_std::path::Path::new(&dhcp.conf)).await?;
+        async_std::fs::remove_file(async_std::path::Path::new(&dhcp.log_file)).await?;
+        Ok(())
+    }
+}
+
+impl<'a> RelayBackend<'a> {
+    #[cfg(not(feature = "dhcp"))]
+    fn create_config(
+        &self,
+        _iface: &str,
+        _local: IPAddress,
+        _server: IPAddress,
+        _dhcp: &VNetDHCP,
+    ) -> FResult<String> {
+        Err(FError::Unimplemented)
+    }
+
+    #[cfg(feature = "dhcp")]
+    fn create_config(
+        &self,
+        iface: &str,
+        local: IPAddress,
+        server: IPAddress,
+        dhcp: &VNetDHCP,
+    ) -> FResult<String> {
+        let mut context = Context::new();
+        context.insert("dhcp_interface", iface);
+        context.insert("relay_local", &local);
+        context.insert("relay_server", &server);
+        context.insert("dhcp_pid", &dhcp.pid_file);
+        context.insert("dhcp_log", &dhcp.log_file);
+
+        render_template(self.path, "dhcp-relay.conf", &context)
+    }
+}
+
+/// DHCPv4 server running in the plugin, see `dhcp_server`
+pub struct EmbeddedBackend<'a> {
+    pub state: &'a RwLock<LinuxNetworkState>,
+    pub lease_hook: Option<&'a str>,
+}
+
+#[async_trait]
+impl<'a> DhcpBackend for EmbeddedBackend<'a> {
+    async fn start(&self, service: &DHCPService<'_>) -> FResult<VNetDHCP> {
+        let range = service.ranges.iter().find(|r| !r.v6).ok_or_else(|| {
+            FError::NetworkingError(
+                "The embedded DHCP server serves IPv4 networks only".to_string(),
+            )
+        })?;
+        if service.ranges.iter().any(|r| r.v6) {
+            log::warn!(
+                target: logging::DHCP,
+                "IPv6 range of {} not served by the embedded DHCP server",
+                service.vnet_uuid
+            );
+        }
+        let mut config = embedded_dhcp_config(range, service.internals)?;
+        config.lease_hook = self.lease_hook.map(String::from);
+        let dhcp = VNetDHCP {
+            leases_file: service.file("leases"),
+            pid_file: String::new(),
+            conf: String::new(),
+            log_file: String::new(),
+            opts_file: None,
+            router: Some(range.gateway),
+            routers: Vec::new(),
+            embedded: Some(config),
+            hosts_file: None,
+            backend: Some(DHCPBackend::EMBEDDED),
+        };
+        self.respawn(service.vnet_uuid, service.ns_name, service.iface, &dhcp)
+            .await?;
+        Ok(dhcp)
+    }
+
+    async fn respawn(
+        &self,
+        vnet_uuid: Uuid,
+        ns_name: Option<&str>,
+        iface: &str,
+        dhcp: &VNetDHCP,
+    ) -> FResult<()> {
+        let config = dhcp.embedded.clone().ok_or(FError::NotFound)?;
+        let server = dhcp_server::start(
+            ns_name.map(String::from),
+            iface.to_string(),
+            config,
+            dhcp.leases_file.clone(),
+        )
+        .await?;
+        log::debug!(target: logging::DHCP, "Embedded DHCP server for {} running", vnet_uuid);
+        let old = self
+            .state
+            .write()
+            .await
+            .dhcp_servers
+            .insert(vnet_uuid, server);
+        if let Some(old) = old {
+            old.stop().await;
+        }
+        Ok(())
+    }
+
+    async fn reload(
+        &self,
+        vnet_uuid: Uuid,
+        dhcp: &mut VNetDHCP,
+        internals: &VirtualNetworkInternals,
+    ) -> FResult<()> {
+        let mut config = dhcp.embedded.clone().ok_or(FError::NotFound)?;
+        set_embedded_dhcp_opts(&mut config, Some(internals))?;
+        if let Some(server) = self.state.read().await.dhcp_servers.get(&vnet_uuid) {
+            server.update(config.clone()).await;
+        }
+        dhcp.embedded = Some(config);
+        Ok(())
+    }
+
+    async fn stop(&self, vnet_uuid: Uuid, dhcp: VNetDHCP) -> FResult<()> {
+        let server = self.state.write().await.dhcp_servers.remove(&vnet_uuid);
+        if let Some(server) = server {
+            server.stop().await;
+        }
+        let leases_file = async_std::path::Path::new(&dhcp.leases_file);
+        if leases_file.exists().await {
+            async_std::fs::remove_file(leases_file).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Server of the simulated kernel, it is recorded as a process and
+/// does not serve anything. The internals are the ones of `kind`.
+pub struct SimulatedBackend {
+    pub kind: DHCPBackend,
+}
+
+impl SimulatedBackend {
+    fn argv(vnet_uuid: Uuid) -> Vec<String> {
+        vec![
+            "dhcp-server".to_string(),
+            "--vnet".to_string(),
+            format!("{}", vnet_uuid),
+        ]
+    }
+}
+
+#[async_trait]
+impl DhcpBackend for SimulatedBackend {
+    async fn start(&self, service: &DHCPService<'_>) -> FResult<VNetDHCP> {
+        let range = service
+            .ranges
+            .iter()
+            .find(|r| !r.v6)
+            .unwrap_or(&service.ranges[0]);
+        let dhcp = VNetDHCP {
+            leases_file: service.file("leases"),
+            pid_file: service.file("pid"),
+            conf: service.file("conf"),
+            log_file: service.file("log"),
+            opts_file: None,
+            router: Some(range.gateway),
+            routers: Vec::new(),
+            embedded: None,
+            hosts_file: None,
+            backend: Some(self.kind),
+        };
+        self.respawn(service.vnet_uuid, service.ns_name, service.iface, &dhcp)
+            .await?;
+        Ok(dhcp)
+    }
+
+    async fn respawn(
+        &self,
+        vnet_uuid: Uuid,
+        ns_name: Option<&str>,
+        _iface: &str,
+        _dhcp: &VNetDHCP,
+    ) -> FResult<()> {
+        simulation::kill_matching(&Self::argv(vnet_uuid))?;
+        simulation::spawn(ns_name, Self::argv(vnet_uuid))?;
+        Ok(())
+    }
+
+    async fn reload(
+        &self,
+        vnet_uuid: Uuid,
+        _dhcp: &mut VNetDHCP,
+        _internals: &VirtualNetworkInternals,
+    ) -> FResult<()> {
+        log::trace!(target: logging::DHCP, "Simulated reload of the DHCP server of {}", vnet_uuid);
+        Ok(())
+    }
+
+    async fn stop(&self, vnet_uuid: Uuid, _dhcp: VNetDHCP) -> FResult<()> {
+        simulation::kill_matching(&Self::argv(vnet_uuid))
+    }
+}
+
+/// Spawns dnsmasq, inside `ns_name` if given
+pub(crate) fn spawn_dnsmasq(ns_name: Option<&str>, config_file: &str) -> FResult<Child> {
+    let mut cmd = match ns_name {
+        Some(ns_name) => {
+            let mut cmd = Command::new("ip");
+            cmd.arg("netns").arg("exec").arg(ns_name).arg("dnsmasq");
+            cmd
+        }
+        None => Command::new("dnsmasq"),
+    };
+    cmd.arg("-C")
+        .arg(config_file)
+        .stdin(Stdio::null())
+        .spawn()
+        .map_err(|e| FError::NetworkingError(format!("{}", e)))
+}
+
+pub(crate) async fn read_pid_file(os: &OSClient, pid_file: &str) -> FResult<i32> {
+    let str_pid = String::from_utf8(os.read_file(pid_file.to_string()).await??)
+        .map_err(|e| FError::NetworkingError(format!("{}", e)))?;
+    str_pid
+        .trim()
+        .parse::<i32>()
+        .map_err(|e| FError::NetworkingError(format!("{}", e)))
+}
+
+/// Renders one of the `*.conf` templates in `path`
+#[cfg(feature = "dhcp")]
+pub(crate) fn render_template(
+    path: &std::path::Path,
+    name: &str,
+    context: &Context,
+) -> FResult<String> {
+    let template_path = path
+        .join("*.conf")
+        .to_str()
+        .ok_or(FError::EncodingError)?
+        .to_string();
+    let templates =
+        Tera::new(&template_path).map_err(|e| FError::NetworkingError(format!("{}", e)))?;
+    match templates.render(name, context) {
+        Ok(t) => Ok(t),
+        Err(e) => {
+            log::error!(target: logging::DHCP, "Parsing error(s): {} {}", e, e.source().unwrap());
+            Err(FError::NetworkingError(format!(
+                "{} {}",
+                e,
+                e.source().unwrap()
+            )))
+        }
+    }
+}
+
+/// Range served by dnsmasq for one address family, `gateway` is the
+/// address of dnsmasq on the bridge
+#[derive(Serialize, Debug, Clone)]
+pub struct DNSMasqRange {
+    pub start: IPAddress,
+    pub end: IPAddress,
+    pub prefix: u8,
+    pub gateway: IPAddress,
+    pub dns: Option<IPAddress>,
+    pub v6: bool,
+}
+
+/// Renders the dnsmasq options file from the DHCP settings of a network.
+/// Clients ignore the router option when 121 is present, so the default
+/// route through `router` is added to the static routes.
+fn render_dhcp_opts(
+    internals: Option<&VirtualNetworkInternals>,
+    router: Option<IPAddress>,
+) -> FResult<String> {
+    let internals = match internals {
+        Some(internals) => internals,
+        None => return Ok(String::new()),
+    };
+    let mut opts = String::new();
+    if !internals.static_routes.is_empty() {
+        let mut entries = Vec::new();
+        for route in &internals.static_routes {
+            match (route.destination, route.gateway) {
+                (IPAddress::V4(_), IPAddress::V4(_)) if route.prefix <= 32 => entries.push(
+                    format!("{}/{},{}", route.destination, route.prefix, route.gateway),
+                ),
+                _ => {
+                    return Err(FError::NetworkingError(format!(
+                        "Invalid IPv4 static route {:?}",
+                        route
+                    )))
+                }
+            }
+        }
+        if let Some(router) = router {
+            entries.push(format!("0.0.0.0/0,{}", router));
+        }
+        opts.push_str(&format!(
+            "option:classless-static-route,{}\n",
+            entries.join(",")
+        ));
+    }
+    if !internals.ntp_servers.is_empty() {
+        if internals.ntp_servers.iter().any(|addr| !addr.is_ipv4()) {
+            return Err(FError::NetworkingError(
+                "NTP servers have to be IPv4 addresses".to_string(),
+            ));
+        }
+        let servers: Vec<String> = internals
+            .ntp_servers
+            .iter()
+            .map(|addr| format!("{}", addr))
+            .collect();
+        opts.push_str(&format!("option:ntp-server,{}\n", servers.join(",")));
+    }
+    if let Some(boot) = &internals.boot {
+        validate_dhcp_boot(boot)?;
+        // quoted as the text options, validated not to contain quotes
+        opts.push_str(&format!("option:tftp-server,\"{}\"\n", boot.tftp_server));
+        opts.push_str(&format!("option:bootfile-name,\"{}\"\n", boot.filename));
+    }
+    for option in dhcp_extra_options(internals)? {
+        let value = match &option.value {
+            // quoted so that dnsmasq does not guess another type
+            DHCPOptionValue::Text(text) => format!("\"{}\"", text),
+            DHCPOptionValue::Addresses(addrs) => addrs
+                .iter()
+                .map(|a| format!("{}", a))
+                .collect::<Vec<String>>()
+                .join(","),
+            DHCPOptionValue::U8(v) => format!("{}", v),
+            // hex strings are sent as they are, whatever the option
+            value => encode_dhcp_option(value)
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect::<Vec<String>>()
+                .join(":"),
+        };
+        opts.push_str(&format!("{},{}\n", option.code, value));
+    }
+    Ok(opts)
+}
+
+/// Options set by the plugin, not accepted as extra options
+const RESERVED_DHCP_OPTIONS: [u8; 12] = [0, 1, 3, 6, 42, 51, 53, 54, 66, 67, 121, 255];
+
+/// Returns the MTU, the domain name and the other options of a network
+/// as extra options, checking that they can be sent
+fn dhcp_extra_options(internals: &VirtualNetworkInternals) -> FResult<Vec<DHCPOption>> {
+    let mut options = Vec::new();
+    if let Some(mtu) = internals.dhcp_mtu {
+        options.push(DHCPOption {
+            code: DHCP_OPTION_MTU,
+            value: DHCPOptionValue::U16(mtu),
+        });
+    }
+    if let Some(domain_name) = &internals.domain_name {
+        options.push(DHCPOption {
+            code: DHCP_OPTION_DOMAIN_NAME,
+            value: DHCPOptionValue::Text(domain_name.clone()),
+        });
+    }
+    options.extend(internals.dhcp_options.iter().cloned());
+    let mut codes = Vec::new();
+    for option in &options {
+        let invalid = |reason: &str| {
+            Err(FError::NetworkingError(format!(
+                "Invalid DHCP option {}: {}",
+                option.code, reason
+            )))
+        };
+        if RESERVED_DHCP_OPTIONS.contains(&option.code) {
+            return invalid("set by the plugin");
+        }
+        if codes.contains(&option.code) {
+            return invalid("set more than once");
+        }
+        codes.push(option.code);
+        match &option.value {
+            DHCPOptionValue::Text(text)
+                if text.is_empty() || text.contains(|c: char| c == '"' || c.is_control()) =>
+            {
+                return invalid("empty or with quotes or control characters")
+            }
+            DHCPOptionValue::Addresses(addrs)
+                if addrs.is_empty() || addrs.iter().any(|a| !a.is_ipv4()) =>
+            {
+                return invalid("addresses have to be IPv4")
+            }
+            value if encode_dhcp_option(value).len() > 255 => return invalid("too long"),
+            _ => (),
+        }
+    }
+    Ok(options)
+}
+
+/// Checks that the TFTP server and the boot file name can be sent,
+/// with the rules of the text options
+fn validate_dhcp_boot(boot: &DHCPBootOptions) -> FResult<()> {
+    for (name, value) in &[
+        ("TFTP server", &boot.tftp_server),
+        ("boot file name", &boot.filename),
+    ] {
+        if value.is_empty() || value.contains(|c: char| c == '"' || c.is_control()) {
+            return Err(FError::NetworkingError(format!(
+                "Invalid {}: empty or with quotes or control characters",
+                name
+            )));
+        }
+        if value.len() > 255 {
+            return Err(FError::NetworkingError(format!(
+                "Invalid {}: too long",
+                name
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Wire format of the value of an option
+fn encode_dhcp_option(value: &DHCPOptionValue) -> Vec<u8> {
+    match value {
+        DHCPOptionValue::Text(text) => text.as_bytes().to_vec(),
+        DHCPOptionValue::Addresses(addrs) => addrs
+            .iter()
+            .filter_map(|a| match a {
+                IPAddress::V4(addr) => Some(addr.octets().to_vec()),
+                IPAddress::V6(_) => None,
+            })
+            .flatten()
+            .collect(),
+        DHCPOptionValue::U8(v) => vec![*v],
+        DHCPOptionValue::U16(v) => v.to_be_bytes().to_vec(),
+        DHCPOptionValue::U32(v) => v.to_be_bytes().to_vec(),
+    }
+}
+
+/// Configuration of the embedded DHCP server for an IPv4 range,
+/// the options are the same dnsmasq gets from the options file.
+fn embedded_dhcp_config(
+    range: &DNSMasqRange,
+    internals: Option<&VirtualNetworkInternals>,
+) -> FResult<DHCPServerConfig> {
+    let v4 = |addr: IPAddress| match addr {
+        IPAddress::V4(addr) => Ok(addr),
+        IPAddress::V6(_) => Err(FError::NetworkingError(format!(
+            "{} is not an IPv4 address",
+            addr
+        ))),
+    };
+    let mut config = DHCPServerConfig {
+        start: v4(range.start)?,
+        end: v4(range.end)?,
+        prefix: range.prefix,
+        server: v4(range.gateway)?,
+        router: v4(range.gateway)?,
+        dns: range.dns.map(v4).transpose()?,
+        lease_time: DHCP_LEASE_TIME,
+        static_routes: Vec::new(),
+        ntp_servers: Vec::new(),
+        tftp_server: None,
+        bootfile: None,
+        reservations: Vec::new(),
+        extra_options: Vec::new(),
+        lease_hook: None,
+    };
+    set_embedded_dhcp_opts(&mut config, internals)?;
+    Ok(config)
+}
+
+/// Sets static routes, NTP servers, boot parameters and the other options
+/// of the embedded DHCP server, with the same checks done by
+/// `render_dhcp_opts`
+fn set_embedded_dhcp_opts(
+    config: &mut DHCPServerConfig,
+    internals: Option<&VirtualNetworkInternals>,
+) -> FResult<()> {
+    // validates the options
+    render_dhcp_opts(internals, Some(IPAddress::V4(config.router)))?;
+    config.static_routes.clear();
+    config.ntp_servers.clear();
+    config.tftp_server = None;
+    config.bootfile = None;
+    config.reservations.clear();
+    config.extra_options.clear();
+    let internals = match internals {
+        Some(internals) => internals,
+        None => return Ok(()),
+    };
+    for option in dhcp_extra_options(internals)? {
+        config
+            .extra_options
+            .push((option.code, encode_dhcp_option(&option.value)));
+    }
+    for route in &internals.static_routes {
+        if let (IPAddress::V4(dest), IPAddress::V4(gw)) = (route.destination, route.gateway) {
+            config.static_routes.push((dest, route.prefix, gw));
+        }
+    }
+    if !config.static_routes.is_empty() {
+        // clients ignore the router option when static routes are given
+        config
+            .static_routes
+            .push((std::net::Ipv4Addr::UNSPECIFIED, 0, config.router));
+    }
+    for addr in &internals.ntp_servers {
+        if let IPAddress::V4(addr) = addr {
+            config.ntp_servers.push(*addr);
+        }
+    }
+    if let Some(boot) = &internals.boot {
+        config.tftp_server = Some(boot.tftp_server.clone());
+        config.bootfile = Some(boot.filename.clone());
+    }
+    for reservation in &internals.dhcp_reservations {
+        if let IPAddress::V4(addr) = reservation.address {
+            config.reservations.push((reservation.mac.bytes(), addr));
+        }
+    }
+    Ok(())
+}
+
+/// Renders the dnsmasq hosts file, one `dhcp-host` entry per reservation
+fn render_dhcp_hosts(reservations: &[DHCPReservation]) -> String {
+    let mut hosts = String::new();
+    for reservation in reservations {
+        let address = match reservation.address {
+            IPAddress::V4(addr) => format!("{}", addr),
+            IPAddress::V6(addr) => format!("[{}]", addr),
+        };
+        match &reservation.hostname {
+            Some(hostname) => {
+                hosts.push_str(&format!("{},{},{}\n", reservation.mac, address, hostname))
+            }
+            None => hosts.push_str(&format!("{},{}\n", reservation.mac, address)),
+        }
+    }
+    hosts
+}
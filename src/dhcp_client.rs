@@ -0,0 +1,663 @@
+/*********************************************************************************
+* Copyright (c) 2018,2021 ADLINK Technology Inc.
+*
+* This program and the accompanying materials are made available under the
+* terms of the Eclipse Public License 2.0 which is available at
+* http://www.eclipse.org/legal/epl-2.0, or the Apache Software License 2.0
+* which is available at https://www.apache.org/licenses/LICENSE-2.0.
+*
+* SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+* Contributors:
+*   ADLINK fog05 team, <fog05@adlink-labs.tech>
+*********************************************************************************/
+
+//! Embedded DHCPv4 client.
+//!
+//! Used instead of dhclient when `dhcp_client` is `EMBEDDED`, for the
+//! interfaces assigned without an address. The client runs in the plugin,
+//! its socket is bound to the interface inside the namespace of the
+//! interface, as for the embedded server. It gets a lease with DISCOVER,
+//! OFFER, REQUEST and ACK, then renews it in the background until it is
+//! stopped or the lease is lost, the renewals are broadcast once the
+//! rebinding time is reached. When a renewal gives a different address the
+//! client swaps it on the interface. The lease is written to the lease file,
+//! a new client for the same interface asks for the same address first.
+//! The plugin sets the address of the lease on the interface, the default
+//! route through the router and, in a namespace, the DNS servers of the
+//! lease as its resolvers, as dhclient does.
+
+use std::net::{Ipv4Addr, SocketAddr};
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use async_std::net::UdpSocket;
+use async_std::prelude::*;
+use async_std::sync::{Arc, RwLock};
+
+use serde::{Deserialize, Serialize};
+
+use fog05_sdk::fresult::{FError, FResult};
+
+use crate::dhcp_server::{
+    self, BOOTP_BROADCAST, BOOTP_HEADER_LEN, BOOTP_REPLY, BOOTP_REQUEST, DHCP_ACK,
+    DHCP_CLIENT_PORT, DHCP_DISCOVER, DHCP_LEASE_TIME, DHCP_MAGIC, DHCP_NAK, DHCP_OFFER,
+    DHCP_RELEASE, DHCP_REQUEST, DHCP_SERVER_PORT, OPT_DNS, OPT_END, OPT_LEASE_TIME,
+    OPT_MESSAGE_TYPE, OPT_PAD, OPT_REQUESTED_IP, OPT_ROUTER, OPT_SERVER_ID, OPT_SUBNET_MASK,
+};
+use crate::logging;
+use crate::networking::exec_command;
+use crate::runtime;
+
+const OPT_PARAMETER_LIST: u8 = 55;
+
+/// Seconds waited for a reply before sending a message again
+const DHCP_CLIENT_TIMEOUTS: [u64; 4] = [2, 4, 8, 16];
+/// Seconds between renewals when the server does not answer
+const DHCP_RENEW_RETRY: u64 = 60;
+
+/// Lease held by a client, `obtained` is in seconds since the epoch
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DHCPLease {
+    pub address: Ipv4Addr,
+    pub prefix: u8,
+    pub router: Option<Ipv4Addr>,
+    /// Leases written before the DNS servers were kept have none
+    #[serde(default)]
+    pub dns_servers: Vec<Ipv4Addr>,
+    pub server: Ipv4Addr,
+    pub lease_time: u32,
+    pub obtained: u64,
+}
+
+impl DHCPLease {
+    pub fn expires(&self) -> u64 {
+        self.obtained + self.lease_time as u64
+    }
+
+    fn renew_at(&self) -> u64 {
+        self.obtained + self.lease_time as u64 / 2
+    }
+
+    /// After this time the lease is asked to any server, RFC 2131 4.4.5
+    fn rebind_at(&self) -> u64 {
+        self.obtained + self.lease_time as u64 * 7 / 8
+    }
+
+    fn cidr(&self) -> String {
+        format!("{}/{}", self.address, self.prefix)
+    }
+}
+
+/// Handle of a running client, dropping it stops the client
+/// without releasing the lease
+pub struct DHCPClientHandle {
+    lease: Arc<RwLock<DHCPLease>>,
+    running: Arc<AtomicBool>,
+    stop: runtime::Sender<bool>,
+    task: runtime::JoinHandle<()>,
+}
+
+impl DHCPClientHandle {
+    pub async fn lease(&self) -> DHCPLease {
+        self.lease.read().await.clone()
+    }
+
+    /// Lease updated by the client, it can be read without
+    /// borrowing the handle
+    pub fn shared_lease(&self) -> Arc<RwLock<DHCPLease>> {
+        self.lease.clone()
+    }
+
+    /// `false` once the lease has been refused or has expired
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::Relaxed)
+    }
+
+    /// Stops the client, with `release` the lease is given back to the server
+    pub async fn stop(self, release: bool) {
+        let _ = self.stop.send(release).await;
+        self.task.await;
+    }
+}
+
+/// Gets a lease for `iface`, inside `ns_name` if given, and starts
+/// renewing it in the background
+pub async fn start(
+    ns_name: Option<String>,
+    iface: String,
+    lease_file: String,
+) -> FResult<DHCPClientHandle> {
+    log::trace!(target: logging::DHCP, "Starting DHCP client on {} ({:?})", iface, ns_name);
+    let name = iface.clone();
+    let (socket, mac) = dhcp_server::in_netns(ns_name.clone(), move || {
+        let socket = dhcp_server::new_socket(&name, DHCP_CLIENT_PORT)?;
+        let mac = mac_address::mac_address_by_name(&name)
+            .map_err(|e| FError::NetworkingError(format!("{}", e)))?
+            .ok_or(FError::NotFound)?;
+        Ok((socket, mac.bytes()))
+    })
+    .await?;
+    let client = DHCPClient {
+        iface,
+        ns_name,
+        socket: UdpSocket::from(socket),
+        mac,
+        lease_file,
+    };
+    let previous = client
+        .read_lease()
+        .await
+        .filter(|l| l.expires() > dhcp_server::now_secs());
+    let lease = match previous {
+        Some(previous) => match client.reboot(&previous).await {
+            Ok(Some(lease)) => lease,
+            _ => client.discover().await?,
+        },
+        None => client.discover().await?,
+    };
+    client.write_lease(&lease).await?;
+    log::debug!(
+        target: logging::DHCP,
+        "DHCP client on {} got {}/{} from {}",
+        client.iface,
+        lease.address,
+        lease.prefix,
+        lease.server
+    );
+    let lease = Arc::new(RwLock::new(lease));
+    let running = Arc::new(AtomicBool::new(true));
+    let (stop, stopped) = runtime::bounded::<bool>(1);
+    let task = {
+        let lease = lease.clone();
+        let running = running.clone();
+        runtime::spawn(async move { client.run(lease, running, stopped).await })
+    };
+    Ok(DHCPClientHandle {
+        lease,
+        running,
+        stop,
+        task,
+    })
+}
+
+struct DHCPClient {
+    iface: String,
+    ns_name: Option<String>,
+    socket: UdpSocket,
+    mac: [u8; 6],
+    lease_file: String,
+}
+
+impl DHCPClient {
+    async fn run(
+        self,
+        lease: Arc<RwLock<DHCPLease>>,
+        running: Arc<AtomicBool>,
+        stopped: runtime::Receiver<bool>,
+    ) {
+        loop {
+            let current = lease.read().await.clone();
+            let now = dhcp_server::now_secs();
+            if now >= current.expires() {
+                log::warn!(
+                    target: logging::DHCP,
+                    "DHCP lease of {} on {} expired",
+                    current.address,
+                    self.iface
+                );
+                break;
+            }
+            let wait = if now < current.renew_at() {
+                current.renew_at() - now
+            } else {
+                DHCP_RENEW_RETRY.min(current.expires() - now)
+            };
+            let stop = async {
+                runtime::sleep(Duration::from_secs(wait)).await;
+                None
+            }
+            .race(async { Some(stopped.recv().await.unwrap_or(false)) })
+            .await;
+            if let Some(release) = stop {
+                if release {
+                    if let Err(e) = self.release(&current).await {
+                        log::warn!(
+                            target: logging::DHCP,
+                            "Unable to release {} on {}: {}",
+                            current.address,
+                            self.iface,
+                            e
+                        );
+                    }
+                }
+                break;
+            }
+            match self.extend(&current).await {
+                Ok(Some(renewed)) => {
+                    if let Err(e) = self.swap_address(&current, &renewed).await {
+                        log::warn!(
+                            target: logging::DHCP,
+                            "Unable to move {} from {} to {}: {}",
+                            self.iface,
+                            current.address,
+                            renewed.address,
+                            e
+                        );
+                    }
+                    if let Err(e) = self.write_lease(&renewed).await {
+                        log::warn!(target: logging::DHCP, "DHCP client on {}: {}", self.iface, e);
+                    }
+                    *lease.write().await = renewed;
+                }
+                Ok(None) => {
+                    log::warn!(
+                        target: logging::DHCP,
+                        "DHCP lease of {} on {} refused by {}",
+                        current.address,
+                        self.iface,
+                        current.server
+                    );
+                    break;
+                }
+                Err(e) => log::warn!(target: logging::DHCP, "DHCP client on {}: {}", self.iface, e),
+            }
+        }
+        running.store(false, Ordering::Relaxed);
+        log::trace!(target: logging::DHCP, "DHCP client on {} stopped", self.iface);
+    }
+
+    /// DISCOVER, then REQUEST of the first address offered
+    async fn discover(&self) -> FResult<DHCPLease> {
+        let discover = ClientMessage::new(DHCP_DISCOVER, self.mac);
+        let offer = self
+            .exchange(&discover, Ipv4Addr::BROADCAST, &[DHCP_OFFER])
+            .await?;
+        let server = offer.server_id.ok_or_else(|| {
+            FError::NetworkingError(format!("DHCP offer on {} without server", self.iface))
+        })?;
+        let mut request = ClientMessage::new(DHCP_REQUEST, self.mac);
+        request.xid = discover.xid;
+        request.requested_ip = Some(offer.yiaddr);
+        request.server_id = Some(server);
+        self.request(&request, Ipv4Addr::BROADCAST)
+            .await?
+            .ok_or_else(|| {
+                FError::NetworkingError(format!(
+                    "DHCP request of {} on {} refused",
+                    offer.yiaddr, self.iface
+                ))
+            })
+    }
+
+    /// REQUEST of the address of a previous lease, `None` if refused
+    async fn reboot(&self, previous: &DHCPLease) -> FResult<Option<DHCPLease>> {
+        let mut request = ClientMessage::new(DHCP_REQUEST, self.mac);
+        request.requested_ip = Some(previous.address);
+        self.request(&request, Ipv4Addr::BROADCAST).await
+    }
+
+    /// Renews or rebinds the lease, `None` if refused
+    async fn extend(&self, lease: &DHCPLease) -> FResult<Option<DHCPLease>> {
+        let (request, dest) = renewal_request(self.mac, lease, dhcp_server::now_secs());
+        self.request(&request, dest).await
+    }
+
+    /// Replaces the address of the previous lease on the interface when
+    /// the server gave a different one
+    async fn swap_address(&self, current: &DHCPLease, renewed: &DHCPLease) -> FResult<()> {
+        let commands = address_commands(&self.iface, current, renewed);
+        if commands.is_empty() {
+            return Ok(());
+        }
+        dhcp_server::in_netns(self.ns_name.clone(), move || {
+            for args in commands {
+                let mut cmd = Command::new("ip");
+                cmd.args(&args);
+                exec_command(cmd)?;
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    async fn release(&self, lease: &DHCPLease) -> FResult<()> {
+        let mut release = ClientMessage::new(DHCP_RELEASE, self.mac);
+        release.ciaddr = lease.address;
+        release.server_id = Some(lease.server);
+        self.socket
+            .send_to(
+                &release.encode(),
+                SocketAddr::from((lease.server, DHCP_SERVER_PORT)),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn request(&self, request: &ClientMessage, dest: Ipv4Addr) -> FResult<Option<DHCPLease>> {
+        let reply = self.exchange(request, dest, &[DHCP_ACK, DHCP_NAK]).await?;
+        if reply.message_type == DHCP_NAK {
+            return Ok(None);
+        }
+        let server = reply.server_id.or(request.server_id).unwrap_or(dest);
+        Ok(Some(DHCPLease {
+            address: reply.yiaddr,
+            prefix: reply
+                .subnet_mask
+                .map(|mask| u32::from(mask).count_ones() as u8)
+                .unwrap_or(32),
+            router: reply.router,
+            dns_servers: reply.dns_servers,
+            server,
+            lease_time: reply.lease_time.unwrap_or(DHCP_LEASE_TIME),
+            obtained: dhcp_server::now_secs(),
+        }))
+    }
+
+    /// Sends `msg` until a reply of one of the `expected` types is received
+    async fn exchange(
+        &self,
+        msg: &ClientMessage,
+        dest: Ipv4Addr,
+        expected: &[u8],
+    ) -> FResult<ClientMessage> {
+        let data = msg.encode();
+        let mut buf = vec![0u8; 1500];
+        for timeout in DHCP_CLIENT_TIMEOUTS.iter() {
+            self.socket
+                .send_to(&data, SocketAddr::from((dest, DHCP_SERVER_PORT)))
+                .await?;
+            let deadline = Instant::now() + Duration::from_secs(*timeout);
+            loop {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    break;
+                }
+                let received = async { Some(self.socket.recv_from(&mut buf).await) }
+                    .race(async {
+                        runtime::sleep(remaining).await;
+                        None
+                    })
+                    .await;
+                match received {
+                    Some(Ok((len, _))) => match ClientMessage::parse(&buf[..len]) {
+                        Some(reply)
+                            if reply.op == BOOTP_REPLY
+                                && reply.xid == msg.xid
+                                && reply.chaddr == self.mac
+                                && expected.contains(&reply.message_type) =>
+                        {
+                            return Ok(reply)
+                        }
+                        _ => continue,
+                    },
+                    Some(Err(e)) => return Err(e.into()),
+                    None => break,
+                }
+            }
+        }
+        Err(FError::NetworkingError(format!(
+            "No answer from a DHCP server on {}",
+            self.iface
+        )))
+    }
+
+    async fn read_lease(&self) -> Option<DHCPLease> {
+        let data = async_std::fs::read(&self.lease_file).await.ok()?;
+        serde_json::from_slice(&data).ok()
+    }
+
+    async fn write_lease(&self, lease: &DHCPLease) -> FResult<()> {
+        let data =
+            serde_json::to_vec(lease).map_err(|e| FError::NetworkingError(format!("{}", e)))?;
+        async_std::fs::write(&self.lease_file, data).await?;
+        Ok(())
+    }
+}
+
+/// REQUEST extending `lease` and where it is sent: the server of the lease
+/// until the rebinding time, then any server when the server of the lease
+/// did not answer the renewals
+fn renewal_request(mac: [u8; 6], lease: &DHCPLease, now: u64) -> (ClientMessage, Ipv4Addr) {
+    let mut request = ClientMessage::new(DHCP_REQUEST, mac);
+    request.ciaddr = lease.address;
+    if now >= lease.rebind_at() {
+        (request, Ipv4Addr::BROADCAST)
+    } else {
+        (request, lease.server)
+    }
+}
+
+/// `ip` arguments moving `iface` from the address of `current` to the one
+/// of `renewed`, the new address is added first so that the interface is
+/// never left without one
+fn address_commands(iface: &str, current: &DHCPLease, renewed: &DHCPLease) -> Vec<Vec<String>> {
+    if current.cidr() == renewed.cidr() {
+        return Vec::new();
+    }
+    let command = |op: &str, lease: &DHCPLease| {
+        vec![
+            "addr".to_string(),
+            op.to_string(),
+            lease.cidr(),
+            "dev".to_string(),
+            iface.to_string(),
+        ]
+    };
+    vec![command("add", renewed), command("del", current)]
+}
+
+/// The BOOTP fields and DHCP options used by the client
+struct ClientMessage {
+    op: u8,
+    xid: [u8; 4],
+    ciaddr: Ipv4Addr,
+    yiaddr: Ipv4Addr,
+    chaddr: [u8; 6],
+    message_type: u8,
+    requested_ip: Option<Ipv4Addr>,
+    server_id: Option<Ipv4Addr>,
+    lease_time: Option<u32>,
+    subnet_mask: Option<Ipv4Addr>,
+    router: Option<Ipv4Addr>,
+    dns_servers: Vec<Ipv4Addr>,
+}
+
+impl ClientMessage {
+    fn new(message_type: u8, chaddr: [u8; 6]) -> Self {
+        ClientMessage {
+            op: BOOTP_REQUEST,
+            xid: rand::random(),
+            ciaddr: Ipv4Addr::UNSPECIFIED,
+            yiaddr: Ipv4Addr::UNSPECIFIED,
+            chaddr,
+            message_type,
+            requested_ip: None,
+            server_id: None,
+            lease_time: None,
+            subnet_mask: None,
+            router: None,
+            dns_servers: Vec::new(),
+        }
+    }
+
+    fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() < BOOTP_HEADER_LEN + DHCP_MAGIC.len()
+            || data[BOOTP_HEADER_LEN..BOOTP_HEADER_LEN + 4] != DHCP_MAGIC
+        {
+            return None;
+        }
+        let addr = |value: &[u8]| Ipv4Addr::new(value[0], value[1], value[2], value[3]);
+        let mut msg = ClientMessage {
+            op: data[0],
+            xid: [data[4], data[5], data[6], data[7]],
+            ciaddr: addr(&data[12..16]),
+            yiaddr: addr(&data[16..20]),
+            chaddr: [data[28], data[29], data[30], data[31], data[32], data[33]],
+            message_type: 0,
+            requested_ip: None,
+            server_id: None,
+            lease_time: None,
+            subnet_mask: None,
+            router: None,
+            dns_servers: Vec::new(),
+        };
+        let mut i = BOOTP_HEADER_LEN + DHCP_MAGIC.len();
+        while i < data.len() {
+            let code = data[i];
+            if code == OPT_END {
+                break;
+            }
+            if code == OPT_PAD {
+                i += 1;
+                continue;
+            }
+            let len = *data.get(i + 1)? as usize;
+            let value = data.get(i + 2..i + 2 + len)?;
+            match (code, len) {
+                (OPT_MESSAGE_TYPE, 1) => msg.message_type = value[0],
+                (OPT_SERVER_ID, 4) => msg.server_id = Some(addr(value)),
+                (OPT_SUBNET_MASK, 4) => msg.subnet_mask = Some(addr(value)),
+                // the first router is the preferred one
+                (OPT_ROUTER, l) if l >= 4 => msg.router = Some(addr(value)),
+                (OPT_DNS, l) if l % 4 == 0 => msg.dns_servers = value.chunks(4).map(addr).collect(),
+                (OPT_LEASE_TIME, 4) => {
+                    msg.lease_time =
+                        Some(u32::from_be_bytes([value[0], value[1], value[2], value[3]]))
+                }
+                _ => (),
+            }
+            i += 2 + len;
+        }
+        Some(msg)
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut data = vec![0u8; BOOTP_HEADER_LEN];
+        data[0] = self.op;
+        data[1] = 1; // Ethernet
+        data[2] = 6;
+        data[4..8].copy_from_slice(&self.xid);
+        // without an address the replies can only be broadcast
+        if self.ciaddr.is_unspecified() {
+            data[10..12].copy_from_slice(&BOOTP_BROADCAST.to_be_bytes());
+        }
+        data[12..16].copy_from_slice(&self.ciaddr.octets());
+        data[28..34].copy_from_slice(&self.chaddr);
+        data.extend_from_slice(&DHCP_MAGIC);
+
+        let mut option = |code: u8, value: &[u8]| {
+            data.push(code);
+            data.push(value.len() as u8);
+            data.extend_from_slice(value);
+        };
+        option(OPT_MESSAGE_TYPE, &[self.message_type]);
+        if let Some(addr) = self.requested_ip {
+            option(OPT_REQUESTED_IP, &addr.octets());
+        }
+        if let Some(server) = self.server_id {
+            option(OPT_SERVER_ID, &server.octets());
+        }
+        if self.message_type != DHCP_RELEASE {
+            option(
+                OPT_PARAMETER_LIST,
+                &[
+                    OPT_SUBNET_MASK,
+                    OPT_ROUTER,
+                    OPT_DNS,
+                    OPT_LEASE_TIME,
+                    OPT_SERVER_ID,
+                ],
+            );
+        }
+        data.push(OPT_END);
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_ack_router_and_dns() {
+        let mut data = vec![0u8; BOOTP_HEADER_LEN];
+        data[0] = BOOTP_REPLY;
+        data[16..20].copy_from_slice(&[10, 0, 0, 2]);
+        data.extend_from_slice(&DHCP_MAGIC);
+        let options: [(u8, &[u8]); 4] = [
+            (OPT_MESSAGE_TYPE, &[DHCP_ACK]),
+            (OPT_SUBNET_MASK, &[255, 255, 255, 0]),
+            (OPT_ROUTER, &[10, 0, 0, 1]),
+            (OPT_DNS, &[10, 0, 0, 1, 8, 8, 8, 8]),
+        ];
+        for (code, value) in options.iter() {
+            data.push(*code);
+            data.push(value.len() as u8);
+            data.extend_from_slice(value);
+        }
+        data.push(OPT_END);
+        let reply = ClientMessage::parse(&data).unwrap();
+        assert_eq!(reply.message_type, DHCP_ACK);
+        assert_eq!(reply.yiaddr, Ipv4Addr::new(10, 0, 0, 2));
+        assert_eq!(reply.router, Some(Ipv4Addr::new(10, 0, 0, 1)));
+        assert_eq!(
+            reply.dns_servers,
+            vec![Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(8, 8, 8, 8)]
+        );
+    }
+
+    const MAC: [u8; 6] = [0x02, 0, 0, 0, 0, 1];
+
+    fn lease(address: Ipv4Addr) -> DHCPLease {
+        DHCPLease {
+            address,
+            prefix: 24,
+            router: None,
+            dns_servers: Vec::new(),
+            server: Ipv4Addr::new(10, 0, 0, 1),
+            lease_time: 3600,
+            obtained: 1000,
+        }
+    }
+
+    #[test]
+    fn renew_is_sent_to_the_server() {
+        let lease = lease(Ipv4Addr::new(10, 0, 0, 2));
+        let (request, dest) = renewal_request(MAC, &lease, lease.renew_at());
+        assert_eq!(dest, lease.server);
+        assert_eq!(request.message_type, DHCP_REQUEST);
+        assert_eq!(request.ciaddr, lease.address);
+        assert_eq!(request.requested_ip, None);
+        let data = request.encode();
+        assert_eq!(&data[12..16], &[10, 0, 0, 2]);
+        // with an address the server answers unicast
+        assert_eq!(&data[10..12], &[0, 0]);
+    }
+
+    #[test]
+    fn rebind_is_broadcast() {
+        let lease = lease(Ipv4Addr::new(10, 0, 0, 2));
+        let (_, dest) = renewal_request(MAC, &lease, lease.rebind_at() - 1);
+        assert_eq!(dest, lease.server);
+        let (request, dest) = renewal_request(MAC, &lease, lease.rebind_at());
+        assert_eq!(dest, Ipv4Addr::BROADCAST);
+        assert_eq!(request.ciaddr, lease.address);
+        assert_eq!(request.server_id, None);
+    }
+
+    #[test]
+    fn changed_address_is_swapped() {
+        let current = lease(Ipv4Addr::new(10, 0, 0, 2));
+        assert!(address_commands("eth0", &current, &current.clone()).is_empty());
+
+        let renewed = lease(Ipv4Addr::new(10, 0, 0, 7));
+        assert_eq!(
+            address_commands("eth0", &current, &renewed),
+            vec![
+                vec!["addr", "add", "10.0.0.7/24", "dev", "eth0"],
+                vec!["addr", "del", "10.0.0.2/24", "dev", "eth0"],
+            ]
+        );
+    }
+}
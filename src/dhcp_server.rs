@@ -0,0 +1,1015 @@
+/*********************************************************************************
+* Copyright (c) 2018,2021 ADLINK Technology Inc.
+*
+* This program and the accompanying materials are made available under the
+* terms of the Eclipse Public License 2.0 which is available at
+* http://www.eclipse.org/legal/epl-2.0, or the Apache Software License 2.0
+* which is available at https://www.apache.org/licenses/LICENSE-2.0.
+*
+* SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+* Contributors:
+*   ADLINK fog05 team, <fog05@adlink-labs.tech>
+*********************************************************************************/
+
+//! Embedded DHCPv4 server.
+//!
+//! Used instead of dnsmasq when `dhcp_backend` is `EMBEDDED`, one server
+//! runs in the plugin for each virtual network. Its socket is bound to the
+//! bridge of the network, inside the namespace of the network if any.
+//! Leases are kept in memory and written to the leases file, in the dnsmasq
+//! format, so that they survive a restart of the plugin.
+
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::process::Command;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use async_std::net::UdpSocket;
+use async_std::prelude::*;
+use async_std::sync::{Arc, Mutex, RwLock};
+
+use nix::sched::CloneFlags;
+use nix::sys::socket::{self, sockopt, AddressFamily, InetAddr, SockAddr, SockFlag, SockType};
+
+use serde::{Deserialize, Serialize};
+
+use fog05_sdk::fresult::{FError, FResult};
+
+use crate::logging;
+use crate::runtime;
+
+pub const DHCP_SERVER_PORT: u16 = 67;
+pub const DHCP_CLIENT_PORT: u16 = 68;
+/// Same lease time used with dnsmasq
+pub const DHCP_LEASE_TIME: u32 = 86400;
+/// Seconds an offered address is kept for the client
+const DHCP_OFFER_TIME: u64 = 60;
+const NETNS_PATH: &str = "/run/netns/";
+
+pub(crate) const BOOTP_REQUEST: u8 = 1;
+pub(crate) const BOOTP_REPLY: u8 = 2;
+pub(crate) const BOOTP_HEADER_LEN: usize = 236;
+pub(crate) const DHCP_MAGIC: [u8; 4] = [99, 130, 83, 99];
+pub(crate) const BOOTP_BROADCAST: u16 = 0x8000;
+
+pub(crate) const OPT_PAD: u8 = 0;
+pub(crate) const OPT_SUBNET_MASK: u8 = 1;
+pub(crate) const OPT_ROUTER: u8 = 3;
+pub(crate) const OPT_DNS: u8 = 6;
+const OPT_NTP: u8 = 42;
+pub(crate) const OPT_REQUESTED_IP: u8 = 50;
+pub(crate) const OPT_LEASE_TIME: u8 = 51;
+pub(crate) const OPT_MESSAGE_TYPE: u8 = 53;
+pub(crate) const OPT_SERVER_ID: u8 = 54;
+const OPT_TFTP_SERVER: u8 = 66;
+const OPT_BOOTFILE: u8 = 67;
+const OPT_CLASSLESS_ROUTES: u8 = 121;
+pub(crate) const OPT_END: u8 = 255;
+
+pub(crate) const DHCP_DISCOVER: u8 = 1;
+pub(crate) const DHCP_OFFER: u8 = 2;
+pub(crate) const DHCP_REQUEST: u8 = 3;
+const DHCP_DECLINE: u8 = 4;
+pub(crate) const DHCP_ACK: u8 = 5;
+pub(crate) const DHCP_NAK: u8 = 6;
+pub(crate) const DHCP_RELEASE: u8 = 7;
+const DHCP_INFORM: u8 = 8;
+
+/// What the embedded server hands out, kept in the network internals
+/// to restart the server with the same configuration
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DHCPServerConfig {
+    pub start: Ipv4Addr,
+    pub end: Ipv4Addr,
+    pub prefix: u8,
+    /// Address of the server on the bridge, also announced as router
+    pub server: Ipv4Addr,
+    pub router: Ipv4Addr,
+    pub dns: Option<Ipv4Addr>,
+    pub lease_time: u32,
+    /// Destination, prefix and gateway of the classless static routes
+    #[serde(default)]
+    pub static_routes: Vec<(Ipv4Addr, u8, Ipv4Addr)>,
+    #[serde(default)]
+    pub ntp_servers: Vec<Ipv4Addr>,
+    #[serde(default)]
+    pub tftp_server: Option<String>,
+    #[serde(default)]
+    pub bootfile: Option<String>,
+    /// Addresses given only to the client with the MAC
+    #[serde(default)]
+    pub reservations: Vec<([u8; 6], Ipv4Addr)>,
+    /// Code and encoded value of the other options
+    #[serde(default)]
+    pub extra_options: Vec<(u8, Vec<u8>)>,
+    /// Executable run on lease events, with the arguments dnsmasq gives
+    /// to its `dhcp-script`
+    #[serde(default)]
+    pub lease_hook: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Lease {
+    mac: [u8; 6],
+    expires: u64,
+}
+
+/// Handle of a running server, dropping it stops the server
+pub struct DHCPServerHandle {
+    config: Arc<RwLock<DHCPServerConfig>>,
+    stop: runtime::Sender<()>,
+    task: runtime::JoinHandle<()>,
+}
+
+impl DHCPServerHandle {
+    /// Replaces the configuration, used from the next reply on
+    pub async fn update(&self, config: DHCPServerConfig) {
+        *self.config.write().await = config;
+    }
+
+    pub async fn stop(self) {
+        let _ = self.stop.send(()).await;
+        self.task.await;
+    }
+}
+
+/// Starts a server on `iface`, inside `ns_name` if given. The leases
+/// already in `leases_file` are loaded before serving.
+pub async fn start(
+    ns_name: Option<String>,
+    iface: String,
+    config: DHCPServerConfig,
+    leases_file: String,
+) -> FResult<DHCPServerHandle> {
+    log::trace!(target: logging::DHCP, "Starting DHCP server on {} ({:?})", iface, ns_name);
+    let socket = bind_socket(ns_name, iface.clone()).await?;
+    let leases = read_leases(&leases_file).await;
+    let config = Arc::new(RwLock::new(config));
+    let (stop, stopped) = runtime::bounded::<()>(1);
+    let server = DHCPServer {
+        iface,
+        socket,
+        config: config.clone(),
+        leases: Arc::new(Mutex::new(leases)),
+        leases_file,
+    };
+    let task = runtime::spawn(async move { server.serve(stopped).await });
+    Ok(DHCPServerHandle { config, stop, task })
+}
+
+/// Runs `f` in a blocking thread that enters the namespace only for the
+/// time needed, sockets created by `f` keep living there.
+pub(crate) async fn in_netns<F, T>(ns_name: Option<String>, f: F) -> FResult<T>
+where
+    F: FnOnce() -> FResult<T> + Send + 'static,
+    T: Send + 'static,
+{
+    runtime::spawn_blocking(move || {
+        let current_ns = std::fs::File::open("/proc/thread-self/ns/net")?;
+        if let Some(ns_name) = &ns_name {
+            let target_ns = std::fs::File::open(format!("{}{}", NETNS_PATH, ns_name))?;
+            nix::sched::setns(target_ns.as_raw_fd(), CloneFlags::CLONE_NEWNET)
+                .map_err(|e| FError::NetworkingError(format!("{}", e)))?;
+        }
+        let res = f();
+        if ns_name.is_some() {
+            if let Err(e) = nix::sched::setns(current_ns.as_raw_fd(), CloneFlags::CLONE_NEWNET) {
+                // the thread would be reused by other tasks in the namespace
+                log::error!(
+                    target: logging::DHCP,
+                    "Unable to leave the namespace {:?}: {}",
+                    ns_name,
+                    e
+                );
+                std::process::abort();
+            }
+        }
+        res
+    })
+    .await
+}
+
+async fn bind_socket(ns_name: Option<String>, iface: String) -> FResult<UdpSocket> {
+    let socket = in_netns(ns_name, move || new_socket(&iface, DHCP_SERVER_PORT)).await?;
+    Ok(UdpSocket::from(socket))
+}
+
+/// UDP socket on `port` bound to `iface`, allowed to send broadcasts
+pub(crate) fn new_socket(iface: &str, port: u16) -> FResult<std::net::UdpSocket> {
+    let err = |e: nix::Error| FError::NetworkingError(format!("{}", e));
+    let fd = socket::socket(
+        AddressFamily::Inet,
+        SockType::Datagram,
+        SockFlag::SOCK_CLOEXEC,
+        None,
+    )
+    .map_err(err)?;
+    // from now on the fd is closed when the socket is dropped
+    let socket = unsafe { std::net::UdpSocket::from_raw_fd(fd) };
+    // several servers, or clients, listen on the same port in the
+    // default namespace, each one on its own interface
+    socket::setsockopt(fd, sockopt::BindToDevice, &std::ffi::OsString::from(iface)).map_err(err)?;
+    socket::setsockopt(fd, sockopt::ReuseAddr, &true).map_err(err)?;
+    let addr = InetAddr::from_std(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, port)));
+    socket::bind(fd, &SockAddr::new_inet(addr)).map_err(err)?;
+    socket.set_broadcast(true)?;
+    socket.set_nonblocking(true)?;
+    Ok(socket)
+}
+
+struct DHCPServer {
+    iface: String,
+    socket: UdpSocket,
+    config: Arc<RwLock<DHCPServerConfig>>,
+    leases: Arc<Mutex<HashMap<Ipv4Addr, Lease>>>,
+    leases_file: String,
+}
+
+enum Event {
+    Packet(std::io::Result<(usize, SocketAddr)>),
+    Stop,
+}
+
+impl DHCPServer {
+    async fn serve(self, stopped: runtime::Receiver<()>) {
+        let mut buf = vec![0u8; 1500];
+        loop {
+            let event = async { Event::Packet(self.socket.recv_from(&mut buf).await) }
+                .race(async {
+                    let _ = stopped.recv().await;
+                    Event::Stop
+                })
+                .await;
+            match event {
+                Event::Packet(Ok((len, _))) => {
+                    if let Err(e) = self.handle(&buf[..len]).await {
+                        log::warn!(target: logging::DHCP, "DHCP server on {}: {}", self.iface, e);
+                    }
+                }
+                Event::Packet(Err(e)) => {
+                    log::error!(
+                        target: logging::DHCP,
+                        "DHCP server on {} receive error: {}",
+                        self.iface,
+                        e
+                    );
+                    runtime::sleep(Duration::from_millis(100)).await;
+                }
+                Event::Stop => break,
+            }
+        }
+        log::trace!(target: logging::DHCP, "DHCP server on {} stopped", self.iface);
+    }
+
+    async fn handle(&self, data: &[u8]) -> FResult<()> {
+        let request = match DHCPMessage::parse(data) {
+            Some(request) if request.op == BOOTP_REQUEST => request,
+            _ => return Ok(()),
+        };
+        let config = self.config.read().await.clone();
+        let now = now_secs();
+        let reply = match request.message_type {
+            DHCP_DISCOVER => {
+                let addr = match self.allocate(&config, &request, now).await {
+                    Some(addr) => addr,
+                    None => {
+                        log::warn!(
+                            target: logging::DHCP,
+                            "DHCP range on {} is exhausted",
+                            self.iface
+                        );
+                        return Ok(());
+                    }
+                };
+                if !self.is_leased_to(addr, request.chaddr, now).await {
+                    self.bind_lease(addr, request.chaddr, now + DHCP_OFFER_TIME)
+                        .await;
+                }
+                Some(request.reply(DHCP_OFFER, addr, &config, true))
+            }
+            DHCP_REQUEST => {
+                if let Some(server) = request.server_id {
+                    if server != config.server {
+                        // the client chose another server, the offer is released
+                        let released = self.release_offers(&request, now).await;
+                        if !released.is_empty() {
+                            self.write_leases().await?;
+                        }
+                        for addr in released {
+                            self.run_lease_hook(&config, "del", request.chaddr, addr);
+                        }
+                        return Ok(());
+                    }
+                }
+                let addr = request.requested_ip.unwrap_or(request.ciaddr);
+                if self.is_available(&config, addr, request.chaddr, now).await {
+                    // offers are bound for a shorter time than leases
+                    let renewed = self
+                        .is_leased_to(addr, request.chaddr, now + DHCP_OFFER_TIME)
+                        .await;
+                    self.bind_lease(addr, request.chaddr, now + config.lease_time as u64)
+                        .await;
+                    self.write_leases().await?;
+                    let action = if renewed { "old" } else { "add" };
+                    self.run_lease_hook(&config, action, request.chaddr, addr);
+                    Some(request.reply(DHCP_ACK, addr, &config, true))
+                } else {
+                    Some(request.reply(DHCP_NAK, Ipv4Addr::UNSPECIFIED, &config, false))
+                }
+            }
+            DHCP_DECLINE => {
+                // the address is in use by someone else, it is not offered again
+                if let Some(addr) = request.requested_ip {
+                    let expires = now + config.lease_time as u64;
+                    if decline(
+                        &mut *self.leases.lock().await,
+                        addr,
+                        request.chaddr,
+                        expires,
+                    ) {
+                        self.write_leases().await?;
+                    } else {
+                        log::warn!(
+                            target: logging::DHCP,
+                            "Ignoring DHCP decline of {} on {}, not leased to {}",
+                            addr,
+                            self.iface,
+                            format_mac(&request.chaddr)
+                        );
+                    }
+                }
+                None
+            }
+            DHCP_RELEASE => {
+                for addr in self.release(&request).await {
+                    self.run_lease_hook(&config, "del", request.chaddr, addr);
+                }
+                self.write_leases().await?;
+                None
+            }
+            DHCP_INFORM => Some(request.reply(DHCP_ACK, Ipv4Addr::UNSPECIFIED, &config, false)),
+            _ => None,
+        };
+        if let Some(reply) = reply {
+            let dest = if !request.ciaddr.is_unspecified() && reply.message_type != DHCP_NAK {
+                SocketAddr::from((request.ciaddr, DHCP_CLIENT_PORT))
+            } else {
+                SocketAddr::from((Ipv4Addr::BROADCAST, DHCP_CLIENT_PORT))
+            };
+            self.socket.send_to(&reply.encode(&config), dest).await?;
+        }
+        Ok(())
+    }
+
+    async fn allocate(
+        &self,
+        config: &DHCPServerConfig,
+        request: &DHCPMessage,
+        now: u64,
+    ) -> Option<Ipv4Addr> {
+        allocate(config, &*self.leases.lock().await, request, now)
+    }
+
+    async fn is_available(
+        &self,
+        config: &DHCPServerConfig,
+        addr: Ipv4Addr,
+        mac: [u8; 6],
+        now: u64,
+    ) -> bool {
+        is_available(config, &*self.leases.lock().await, addr, mac, now)
+    }
+
+    async fn is_leased_to(&self, addr: Ipv4Addr, mac: [u8; 6], now: u64) -> bool {
+        self.leases
+            .lock()
+            .await
+            .get(&addr)
+            .map_or(false, |l| l.mac == mac && l.expires >= now)
+    }
+
+    /// Binds the address to the client, dropping its other leases
+    async fn bind_lease(&self, addr: Ipv4Addr, mac: [u8; 6], expires: u64) {
+        let mut leases = self.leases.lock().await;
+        leases.retain(|a, l| l.mac != mac || *a == addr);
+        leases.insert(addr, Lease { mac, expires });
+    }
+
+    /// Drops the leases of the client, returns their addresses
+    async fn release(&self, request: &DHCPMessage) -> Vec<Ipv4Addr> {
+        let mut leases = self.leases.lock().await;
+        let released: Vec<Ipv4Addr> = leases
+            .iter()
+            .filter(|(_, l)| l.mac == request.chaddr)
+            .map(|(addr, _)| *addr)
+            .collect();
+        leases.retain(|_, l| l.mac != request.chaddr);
+        released
+    }
+
+    /// Drops the addresses only offered to the client, returns them
+    async fn release_offers(&self, request: &DHCPMessage, now: u64) -> Vec<Ipv4Addr> {
+        release_offers(&mut *self.leases.lock().await, request.chaddr, now)
+    }
+
+    /// Runs the hook in the background, its failures are only logged
+    fn run_lease_hook(
+        &self,
+        config: &DHCPServerConfig,
+        action: &str,
+        mac: [u8; 6],
+        addr: Ipv4Addr,
+    ) {
+        let hook = match &config.lease_hook {
+            Some(hook) => hook.clone(),
+            None => return,
+        };
+        let mut cmd = Command::new(&hook);
+        cmd.arg(action)
+            .arg(format_mac(&mac))
+            .arg(addr.to_string())
+            .env("DNSMASQ_INTERFACE", &self.iface);
+        runtime::spawn_blocking(move || match cmd.status() {
+            Ok(status) if !status.success() => {
+                log::warn!(target: logging::DHCP, "Lease hook {} exited with {}", hook, status)
+            }
+            Err(e) => log::warn!(target: logging::DHCP, "Unable to run lease hook {}: {}", hook, e),
+            _ => (),
+        });
+    }
+
+    async fn write_leases(&self) -> FResult<()> {
+        let now = now_secs();
+        let mut content = String::new();
+        for (addr, lease) in self.leases.lock().await.iter() {
+            if lease.expires >= now && lease.mac != [0; 6] {
+                content.push_str(&format!(
+                    "{} {} {} * *\n",
+                    lease.expires,
+                    format_mac(&lease.mac),
+                    addr
+                ));
+            }
+        }
+        async_std::fs::write(&self.leases_file, content).await?;
+        Ok(())
+    }
+}
+
+/// The address reserved or already leased to the client, the requested
+/// one if available or the first free one in the range
+fn allocate(
+    config: &DHCPServerConfig,
+    leases: &HashMap<Ipv4Addr, Lease>,
+    request: &DHCPMessage,
+    now: u64,
+) -> Option<Ipv4Addr> {
+    if let Some((_, addr)) = config
+        .reservations
+        .iter()
+        .find(|(mac, _)| *mac == request.chaddr)
+    {
+        return Some(*addr);
+    }
+    if let Some((addr, _)) = leases.iter().find(|(_, l)| l.mac == request.chaddr) {
+        return Some(*addr);
+    }
+    if let Some(addr) = request.requested_ip {
+        if is_available(config, leases, addr, request.chaddr, now) {
+            return Some(addr);
+        }
+    }
+    (u32::from(config.start)..=u32::from(config.end))
+        .map(Ipv4Addr::from)
+        .filter(|addr| *addr != config.server && *addr != config.router)
+        .filter(|addr| !config.reservations.iter().any(|(_, a)| a == addr))
+        .find(|addr| leases.get(addr).map_or(true, |l| l.expires < now))
+}
+
+fn is_available(
+    config: &DHCPServerConfig,
+    leases: &HashMap<Ipv4Addr, Lease>,
+    addr: Ipv4Addr,
+    mac: [u8; 6],
+    now: u64,
+) -> bool {
+    if let Some((reserved_to, _)) = config.reservations.iter().find(|(_, a)| *a == addr) {
+        return *reserved_to == mac;
+    }
+    if addr < config.start || addr > config.end || addr == config.server || addr == config.router {
+        return false;
+    }
+    match leases.get(&addr) {
+        Some(lease) => lease.mac == mac || lease.expires < now,
+        None => true,
+    }
+}
+
+/// Marks the address declined by the client as in use, it must be
+/// offered or leased to the client, returns whether it was
+fn decline(
+    leases: &mut HashMap<Ipv4Addr, Lease>,
+    addr: Ipv4Addr,
+    mac: [u8; 6],
+    expires: u64,
+) -> bool {
+    if leases.get(&addr).map_or(true, |l| l.mac != mac) {
+        return false;
+    }
+    leases.insert(
+        addr,
+        Lease {
+            mac: [0; 6],
+            expires,
+        },
+    );
+    true
+}
+
+/// Drops the offers made to the client, its bound leases are kept
+fn release_offers(leases: &mut HashMap<Ipv4Addr, Lease>, mac: [u8; 6], now: u64) -> Vec<Ipv4Addr> {
+    let released: Vec<Ipv4Addr> = leases
+        .iter()
+        .filter(|(_, l)| l.mac == mac && l.expires <= now + DHCP_OFFER_TIME)
+        .map(|(addr, _)| *addr)
+        .collect();
+    for addr in &released {
+        leases.remove(addr);
+    }
+    released
+}
+
+/// Leases that are not expired, malformed lines are skipped
+async fn read_leases(leases_file: &str) -> HashMap<Ipv4Addr, Lease> {
+    let now = now_secs();
+    let content = async_std::fs::read_to_string(leases_file)
+        .await
+        .unwrap_or_default();
+    let mut leases = HashMap::new();
+    for line in content.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 3 {
+            continue;
+        }
+        if let (Ok(expires), Some(mac), Ok(addr)) = (
+            fields[0].parse::<u64>(),
+            parse_mac(fields[1]),
+            fields[2].parse::<Ipv4Addr>(),
+        ) {
+            if expires >= now {
+                leases.insert(addr, Lease { mac, expires });
+            }
+        }
+    }
+    leases
+}
+
+pub(crate) fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn format_mac(mac: &[u8; 6]) -> String {
+    mac.iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+fn parse_mac(mac: &str) -> Option<[u8; 6]> {
+    let bytes = mac
+        .split(':')
+        .map(|b| u8::from_str_radix(b, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    let mut res = [0u8; 6];
+    if bytes.len() != res.len() {
+        return None;
+    }
+    res.copy_from_slice(&bytes);
+    Some(res)
+}
+
+fn prefix_to_mask(prefix: u8) -> Ipv4Addr {
+    Ipv4Addr::from(
+        u32::MAX
+            .checked_shl(32u32.saturating_sub(prefix as u32))
+            .unwrap_or(0),
+    )
+}
+
+/// The BOOTP fields and DHCP options used by the server
+struct DHCPMessage {
+    op: u8,
+    xid: [u8; 4],
+    flags: u16,
+    ciaddr: Ipv4Addr,
+    yiaddr: Ipv4Addr,
+    giaddr: Ipv4Addr,
+    chaddr: [u8; 6],
+    message_type: u8,
+    requested_ip: Option<Ipv4Addr>,
+    server_id: Option<Ipv4Addr>,
+    lease_time: Option<u32>,
+}
+
+impl DHCPMessage {
+    fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() < BOOTP_HEADER_LEN + DHCP_MAGIC.len()
+            || data[BOOTP_HEADER_LEN..BOOTP_HEADER_LEN + 4] != DHCP_MAGIC
+        {
+            return None;
+        }
+        let addr = |offset: usize| {
+            Ipv4Addr::new(
+                data[offset],
+                data[offset + 1],
+                data[offset + 2],
+                data[offset + 3],
+            )
+        };
+        let mut msg = DHCPMessage {
+            op: data[0],
+            xid: [data[4], data[5], data[6], data[7]],
+            flags: u16::from_be_bytes([data[10], data[11]]),
+            ciaddr: addr(12),
+            yiaddr: addr(16),
+            giaddr: addr(24),
+            chaddr: [data[28], data[29], data[30], data[31], data[32], data[33]],
+            message_type: 0,
+            requested_ip: None,
+            server_id: None,
+            lease_time: None,
+        };
+        let mut i = BOOTP_HEADER_LEN + DHCP_MAGIC.len();
+        while i < data.len() {
+            let code = data[i];
+            if code == OPT_END {
+                break;
+            }
+            if code == OPT_PAD {
+                i += 1;
+                continue;
+            }
+            let len = *data.get(i + 1)? as usize;
+            let value = data.get(i + 2..i + 2 + len)?;
+            match (code, len) {
+                (OPT_MESSAGE_TYPE, 1) => msg.message_type = value[0],
+                (OPT_REQUESTED_IP, 4) => {
+                    msg.requested_ip = Some(Ipv4Addr::new(value[0], value[1], value[2], value[3]))
+                }
+                (OPT_SERVER_ID, 4) => {
+                    msg.server_id = Some(Ipv4Addr::new(value[0], value[1], value[2], value[3]))
+                }
+                _ => (),
+            }
+            i += 2 + len;
+        }
+        Some(msg)
+    }
+
+    fn reply(
+        &self,
+        message_type: u8,
+        yiaddr: Ipv4Addr,
+        config: &DHCPServerConfig,
+        with_lease: bool,
+    ) -> Self {
+        DHCPMessage {
+            op: BOOTP_REPLY,
+            xid: self.xid,
+            flags: self.flags | BOOTP_BROADCAST,
+            ciaddr: self.ciaddr,
+            yiaddr,
+            giaddr: self.giaddr,
+            chaddr: self.chaddr,
+            message_type,
+            requested_ip: None,
+            server_id: Some(config.server),
+            lease_time: if with_lease {
+                Some(config.lease_time)
+            } else {
+                None
+            },
+        }
+    }
+
+    fn encode(&self, config: &DHCPServerConfig) -> Vec<u8> {
+        let mut data = vec![0u8; BOOTP_HEADER_LEN];
+        data[0] = self.op;
+        data[1] = 1; // Ethernet
+        data[2] = 6;
+        data[4..8].copy_from_slice(&self.xid);
+        data[10..12].copy_from_slice(&self.flags.to_be_bytes());
+        data[12..16].copy_from_slice(&self.ciaddr.octets());
+        data[16..20].copy_from_slice(&self.yiaddr.octets());
+        data[20..24].copy_from_slice(&config.server.octets());
+        data[24..28].copy_from_slice(&self.giaddr.octets());
+        data[28..34].copy_from_slice(&self.chaddr);
+        if let Some(bootfile) = &config.bootfile {
+            let len = bootfile.len().min(127);
+            data[108..108 + len].copy_from_slice(&bootfile.as_bytes()[..len]);
+        }
+        data.extend_from_slice(&DHCP_MAGIC);
+
+        // values over 255 bytes are split in several instances of
+        // the option, concatenated by the client (RFC 3396)
+        let mut option = |code: u8, value: &[u8]| {
+            if value.is_empty() {
+                data.push(code);
+                data.push(0);
+            }
+            for chunk in value.chunks(u8::MAX as usize) {
+                data.push(code);
+                data.push(chunk.len() as u8);
+                data.extend_from_slice(chunk);
+            }
+        };
+        option(OPT_MESSAGE_TYPE, &[self.message_type]);
+        if let Some(server) = self.server_id {
+            option(OPT_SERVER_ID, &server.octets());
+        }
+        if self.message_type != DHCP_NAK {
+            if let Some(lease_time) = self.lease_time {
+                option(OPT_LEASE_TIME, &lease_time.to_be_bytes());
+            }
+            option(OPT_SUBNET_MASK, &prefix_to_mask(config.prefix).octets());
+            option(OPT_ROUTER, &config.router.octets());
+            if let Some(dns) = config.dns {
+                option(OPT_DNS, &dns.octets());
+            }
+            if !config.static_routes.is_empty() {
+                let mut routes = Vec::new();
+                for (dest, prefix, gw) in &config.static_routes {
+                    routes.push(*prefix);
+                    let significant = (*prefix as usize + 7) / 8;
+                    routes.extend_from_slice(&dest.octets()[..significant]);
+                    routes.extend_from_slice(&gw.octets());
+                }
+                option(OPT_CLASSLESS_ROUTES, &routes);
+            }
+            if !config.ntp_servers.is_empty() {
+                let servers: Vec<u8> = config
+                    .ntp_servers
+                    .iter()
+                    .flat_map(|s| s.octets().to_vec())
+                    .collect();
+                option(OPT_NTP, &servers);
+            }
+            if let Some(tftp_server) = &config.tftp_server {
+                option(OPT_TFTP_SERVER, tftp_server.as_bytes());
+            }
+            if let Some(bootfile) = &config.bootfile {
+                option(OPT_BOOTFILE, bootfile.as_bytes());
+            }
+            for (code, value) in &config.extra_options {
+                option(*code, value);
+            }
+        }
+        data.push(OPT_END);
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC: [u8; 6] = [0x02, 0, 0, 0, 0, 1];
+
+    fn config() -> DHCPServerConfig {
+        DHCPServerConfig {
+            start: Ipv4Addr::new(10, 0, 0, 1),
+            end: Ipv4Addr::new(10, 0, 0, 4),
+            prefix: 24,
+            server: Ipv4Addr::new(10, 0, 0, 1),
+            router: Ipv4Addr::new(10, 0, 0, 1),
+            dns: None,
+            lease_time: DHCP_LEASE_TIME,
+            static_routes: Vec::new(),
+            ntp_servers: Vec::new(),
+            tftp_server: None,
+            bootfile: None,
+            reservations: Vec::new(),
+            extra_options: Vec::new(),
+            lease_hook: None,
+        }
+    }
+
+    fn request(message_type: u8, options: &[(u8, &[u8])]) -> Vec<u8> {
+        let mut data = vec![0u8; BOOTP_HEADER_LEN];
+        data[0] = BOOTP_REQUEST;
+        data[4..8].copy_from_slice(&[1, 2, 3, 4]);
+        data[28..34].copy_from_slice(&MAC);
+        data.extend_from_slice(&DHCP_MAGIC);
+        data.extend_from_slice(&[OPT_MESSAGE_TYPE, 1, message_type, OPT_PAD]);
+        for (code, value) in options {
+            data.push(*code);
+            data.push(value.len() as u8);
+            data.extend_from_slice(value);
+        }
+        data.push(OPT_END);
+        data
+    }
+
+    /// Options of an encoded message, in order
+    fn options(data: &[u8]) -> Vec<(u8, Vec<u8>)> {
+        let mut options = Vec::new();
+        let mut i = BOOTP_HEADER_LEN + DHCP_MAGIC.len();
+        while data[i] != OPT_END {
+            let len = data[i + 1] as usize;
+            options.push((data[i], data[i + 2..i + 2 + len].to_vec()));
+            i += 2 + len;
+        }
+        options
+    }
+
+    fn message(requested_ip: Option<Ipv4Addr>) -> DHCPMessage {
+        let mut msg = DHCPMessage::parse(&request(DHCP_DISCOVER, &[])).unwrap();
+        msg.requested_ip = requested_ip;
+        msg
+    }
+
+    fn lease(mac: [u8; 6], expires: u64) -> Lease {
+        Lease { mac, expires }
+    }
+
+    #[test]
+    fn parse_request() {
+        let data = request(
+            DHCP_REQUEST,
+            &[
+                (OPT_REQUESTED_IP, &[10, 0, 0, 2]),
+                (OPT_SERVER_ID, &[10, 0, 0, 1]),
+                (OPT_DNS, &[8, 8, 8, 8]),
+            ],
+        );
+        let msg = DHCPMessage::parse(&data).unwrap();
+        assert_eq!(msg.op, BOOTP_REQUEST);
+        assert_eq!(msg.xid, [1, 2, 3, 4]);
+        assert_eq!(msg.chaddr, MAC);
+        assert_eq!(msg.message_type, DHCP_REQUEST);
+        assert_eq!(msg.requested_ip, Some(Ipv4Addr::new(10, 0, 0, 2)));
+        assert_eq!(msg.server_id, Some(Ipv4Addr::new(10, 0, 0, 1)));
+    }
+
+    #[test]
+    fn parse_rejects_malformed() {
+        let data = request(DHCP_DISCOVER, &[]);
+        assert!(DHCPMessage::parse(&data[..BOOTP_HEADER_LEN]).is_none());
+        let mut bad_magic = data.clone();
+        bad_magic[BOOTP_HEADER_LEN] = 0;
+        assert!(DHCPMessage::parse(&bad_magic).is_none());
+        // an option running past the end of the packet
+        let mut truncated = data[..data.len() - 1].to_vec();
+        truncated.extend_from_slice(&[OPT_REQUESTED_IP, 4, 10]);
+        assert!(DHCPMessage::parse(&truncated).is_none());
+    }
+
+    #[test]
+    fn encode_reply() {
+        let config = config();
+        let msg = message(None);
+        let data = msg
+            .reply(DHCP_OFFER, Ipv4Addr::new(10, 0, 0, 2), &config, true)
+            .encode(&config);
+        let reply = DHCPMessage::parse(&data).unwrap();
+        assert_eq!(reply.op, BOOTP_REPLY);
+        assert_eq!(reply.xid, msg.xid);
+        assert_eq!(reply.message_type, DHCP_OFFER);
+        assert_eq!(reply.yiaddr, Ipv4Addr::new(10, 0, 0, 2));
+        assert_eq!(reply.server_id, Some(config.server));
+        let options = options(&data);
+        assert!(options.contains(&(OPT_LEASE_TIME, DHCP_LEASE_TIME.to_be_bytes().to_vec())));
+        assert!(options.contains(&(OPT_SUBNET_MASK, vec![255, 255, 255, 0])));
+    }
+
+    #[test]
+    fn encode_splits_long_options() {
+        let mut config = config();
+        let value: Vec<u8> = (0..300).map(|i| i as u8).collect();
+        config.extra_options.push((224, value.clone()));
+        let data = message(None)
+            .reply(DHCP_ACK, Ipv4Addr::new(10, 0, 0, 2), &config, true)
+            .encode(&config);
+        let parts: Vec<Vec<u8>> = options(&data)
+            .into_iter()
+            .filter(|(code, _)| *code == 224)
+            .map(|(_, value)| value)
+            .collect();
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].len(), 255);
+        assert_eq!(parts.concat(), value);
+    }
+
+    #[test]
+    fn allocate_first_free() {
+        let config = config();
+        let mut leases = HashMap::new();
+        // the server address is never handed out
+        assert_eq!(
+            allocate(&config, &leases, &message(None), 100),
+            Some(Ipv4Addr::new(10, 0, 0, 2))
+        );
+        leases.insert(Ipv4Addr::new(10, 0, 0, 2), lease([1; 6], 200));
+        assert_eq!(
+            allocate(&config, &leases, &message(None), 100),
+            Some(Ipv4Addr::new(10, 0, 0, 3))
+        );
+        // expired leases are given again
+        assert_eq!(
+            allocate(&config, &leases, &message(None), 300),
+            Some(Ipv4Addr::new(10, 0, 0, 2))
+        );
+    }
+
+    #[test]
+    fn allocate_prefers_reservation_lease_and_request() {
+        let mut config = config();
+        let mut leases = HashMap::new();
+        let requested = Some(Ipv4Addr::new(10, 0, 0, 4));
+        assert_eq!(
+            allocate(&config, &leases, &message(requested), 100),
+            requested
+        );
+        leases.insert(Ipv4Addr::new(10, 0, 0, 3), lease(MAC, 200));
+        assert_eq!(
+            allocate(&config, &leases, &message(requested), 100),
+            Some(Ipv4Addr::new(10, 0, 0, 3))
+        );
+        config.reservations.push((MAC, Ipv4Addr::new(10, 0, 0, 2)));
+        assert_eq!(
+            allocate(&config, &leases, &message(requested), 100),
+            Some(Ipv4Addr::new(10, 0, 0, 2))
+        );
+    }
+
+    #[test]
+    fn allocate_skips_reserved_and_exhausted() {
+        let mut config = config();
+        config
+            .reservations
+            .push(([9; 6], Ipv4Addr::new(10, 0, 0, 2)));
+        let mut leases = HashMap::new();
+        // reserved to another client
+        assert_eq!(
+            allocate(
+                &config,
+                &leases,
+                &message(Some(Ipv4Addr::new(10, 0, 0, 2))),
+                100
+            ),
+            Some(Ipv4Addr::new(10, 0, 0, 3))
+        );
+        leases.insert(Ipv4Addr::new(10, 0, 0, 3), lease([1; 6], 200));
+        leases.insert(Ipv4Addr::new(10, 0, 0, 4), lease([2; 6], 200));
+        assert_eq!(allocate(&config, &leases, &message(None), 100), None);
+    }
+
+    #[test]
+    fn decline_only_own_lease() {
+        let addr = Ipv4Addr::new(10, 0, 0, 2);
+        let mut leases = HashMap::new();
+        assert!(!decline(&mut leases, addr, MAC, 1000));
+        assert!(leases.is_empty());
+        leases.insert(addr, lease([1; 6], 200));
+        assert!(!decline(&mut leases, addr, MAC, 1000));
+        assert_eq!(leases[&addr].mac, [1; 6]);
+        leases.insert(addr, lease(MAC, 200));
+        assert!(decline(&mut leases, addr, MAC, 1000));
+        assert_eq!(leases[&addr].mac, [0; 6]);
+        assert_eq!(leases[&addr].expires, 1000);
+    }
+
+    #[test]
+    fn release_offers_keeps_bound_leases() {
+        let offered = Ipv4Addr::new(10, 0, 0, 2);
+        let bound = Ipv4Addr::new(10, 0, 0, 3);
+        let other = Ipv4Addr::new(10, 0, 0, 4);
+        let mut leases = HashMap::new();
+        leases.insert(offered, lease(MAC, 100 + DHCP_OFFER_TIME));
+        leases.insert(bound, lease(MAC, 100 + DHCP_LEASE_TIME as u64));
+        leases.insert(other, lease([1; 6], 100 + DHCP_OFFER_TIME));
+        assert_eq!(release_offers(&mut leases, MAC, 100), vec![offered]);
+        assert!(leases.contains_key(&bound));
+        assert!(leases.contains_key(&other));
+    }
+
+    #[test]
+    fn allocate_refuses_requested_router() {
+        let mut config = config();
+        config.server = Ipv4Addr::new(10, 0, 0, 4);
+        let leases = HashMap::new();
+        assert_eq!(
+            allocate(
+                &config,
+                &leases,
+                &message(Some(Ipv4Addr::new(10, 0, 0, 1))),
+                100
+            ),
+            Some(Ipv4Addr::new(10, 0, 0, 2))
+        );
+    }
+}
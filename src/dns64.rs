@@ -0,0 +1,525 @@
+/*********************************************************************************
+* Copyright (c) 2018,2021 ADLINK Technology Inc.
+*
+* This program and the accompanying materials are made available under the
+* terms of the Eclipse Public License 2.0 which is available at
+* http://www.eclipse.org/legal/epl-2.0, or the Apache Software License 2.0
+* which is available at https://www.apache.org/licenses/LICENSE-2.0.
+*
+* SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+* Contributors:
+*   ADLINK fog05 team, <fog05@adlink-labs.tech>
+*********************************************************************************/
+
+//! DNS64 proxy of the NAT64 virtual networks.
+//!
+//! One proxy runs in the plugin for each network with NAT64, its socket is
+//! bound to the bridge of the network inside its namespace, as for the
+//! embedded DHCP server. Queries are forwarded to the upstream server;
+//! when an AAAA query gets no AAAA record the A records of the name are
+//! asked and returned as AAAA records under the NAT64 prefix (RFC 6147).
+//! Only UDP and /96 prefixes are supported.
+
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::os::unix::io::FromRawFd;
+use std::time::{Duration, Instant};
+
+use async_std::net::UdpSocket;
+use async_std::prelude::*;
+
+use fog05_sdk::fresult::{FError, FResult};
+
+use nix::sys::socket::{self, sockopt, AddressFamily, InetAddr, SockAddr, SockFlag, SockType};
+
+use crate::dhcp_server;
+use crate::logging;
+use crate::runtime;
+
+pub const DNS_PORT: u16 = 53;
+
+/// Seconds an upstream answer is waited for
+const DNS64_QUERY_TIMEOUT: u64 = 5;
+
+const DNS_HEADER_LEN: usize = 12;
+const DNS_TYPE_A: u16 = 1;
+const DNS_TYPE_AAAA: u16 = 28;
+const DNS_CLASS_IN: u16 = 1;
+const DNS_RCODE_MASK: u16 = 0x000f;
+
+/// Handle of a running proxy, dropping it stops the proxy
+pub struct DNS64Handle {
+    stop: runtime::Sender<()>,
+    task: runtime::JoinHandle<()>,
+}
+
+impl DNS64Handle {
+    pub async fn stop(self) {
+        let _ = self.stop.send(()).await;
+        self.task.await;
+    }
+}
+
+/// Starts a proxy on port 53 of `iface`, inside `ns_name` if given,
+/// forwarding to `upstream`
+pub async fn start(
+    ns_name: Option<String>,
+    iface: String,
+    prefix: Ipv6Addr,
+    upstream: SocketAddr,
+) -> FResult<DNS64Handle> {
+    log::trace!(target: logging::DHCP, "Starting DNS64 proxy on {} ({:?})", iface, ns_name);
+    let name = iface.clone();
+    let (socket, upstream_socket) = dhcp_server::in_netns(ns_name, move || {
+        let any = if upstream.is_ipv6() {
+            SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0))
+        } else {
+            SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0))
+        };
+        let upstream_socket = std::net::UdpSocket::bind(any)?;
+        upstream_socket.set_nonblocking(true)?;
+        Ok((new_socket(&name)?, upstream_socket))
+    })
+    .await?;
+    let (stop, stopped) = runtime::bounded::<()>(1);
+    let proxy = DNS64Proxy {
+        iface,
+        prefix,
+        upstream,
+        socket: UdpSocket::from(socket),
+        upstream_socket: UdpSocket::from(upstream_socket),
+    };
+    let task = runtime::spawn(async move { proxy.serve(stopped).await });
+    Ok(DNS64Handle { stop, task })
+}
+
+/// IPv6 socket on port 53 bound to `iface`, it gets the IPv4 queries too
+fn new_socket(iface: &str) -> FResult<std::net::UdpSocket> {
+    let err = |e: nix::Error| FError::NetworkingError(format!("{}", e));
+    let fd = socket::socket(
+        AddressFamily::Inet6,
+        SockType::Datagram,
+        SockFlag::SOCK_CLOEXEC,
+        None,
+    )
+    .map_err(err)?;
+    // from now on the fd is closed when the socket is dropped
+    let socket = unsafe { std::net::UdpSocket::from_raw_fd(fd) };
+    socket::setsockopt(fd, sockopt::BindToDevice, &std::ffi::OsString::from(iface)).map_err(err)?;
+    socket::setsockopt(fd, sockopt::ReuseAddr, &true).map_err(err)?;
+    let addr = InetAddr::from_std(&SocketAddr::from((Ipv6Addr::UNSPECIFIED, DNS_PORT)));
+    socket::bind(fd, &SockAddr::new_inet(addr)).map_err(err)?;
+    socket.set_nonblocking(true)?;
+    Ok(socket)
+}
+
+/// Query waiting for an upstream answer, `aaaa_query` is kept for the
+/// AAAA queries until they are answered with an AAAA record
+struct Pending {
+    client: SocketAddr,
+    id: [u8; 2],
+    aaaa_query: Option<Vec<u8>>,
+    /// Answer without AAAA records, returned if the name has no A record
+    aaaa_answer: Option<Vec<u8>>,
+    sent: Instant,
+}
+
+struct DNS64Proxy {
+    iface: String,
+    prefix: Ipv6Addr,
+    upstream: SocketAddr,
+    socket: UdpSocket,
+    upstream_socket: UdpSocket,
+}
+
+enum Event {
+    Query(std::io::Result<(usize, SocketAddr)>),
+    Answer(std::io::Result<(usize, SocketAddr)>),
+    Stop,
+}
+
+impl DNS64Proxy {
+    async fn serve(self, stopped: runtime::Receiver<()>) {
+        let mut pending: HashMap<u16, Pending> = HashMap::new();
+        let mut query_buf = vec![0u8; 4096];
+        let mut answer_buf = vec![0u8; 4096];
+        loop {
+            let event = async { Event::Query(self.socket.recv_from(&mut query_buf).await) }
+                .race(async {
+                    Event::Answer(self.upstream_socket.recv_from(&mut answer_buf).await)
+                })
+                .race(async {
+                    let _ = stopped.recv().await;
+                    Event::Stop
+                })
+                .await;
+            let timeout = Duration::from_secs(DNS64_QUERY_TIMEOUT);
+            pending.retain(|_, p| p.sent.elapsed() < timeout);
+            let res = match event {
+                Event::Query(Ok((len, client))) => {
+                    let query = &query_buf[..len];
+                    if len < DNS_HEADER_LEN {
+                        continue;
+                    }
+                    let aaaa_query = match question(query) {
+                        Some((_, DNS_TYPE_AAAA, DNS_CLASS_IN)) => Some(query.to_vec()),
+                        _ => None,
+                    };
+                    let p = Pending {
+                        client,
+                        id: [query[0], query[1]],
+                        aaaa_query,
+                        aaaa_answer: None,
+                        sent: Instant::now(),
+                    };
+                    self.forward(&mut pending, query, p).await
+                }
+                Event::Answer(Ok((len, from))) => {
+                    let answer = &answer_buf[..len];
+                    // only the upstream server answers, the others are spoofed
+                    if len < DNS_HEADER_LEN || from != self.upstream {
+                        continue;
+                    }
+                    let p = match pending.remove(&u16::from_be_bytes([answer[0], answer[1]])) {
+                        Some(p) => p,
+                        None => continue,
+                    };
+                    self.answer(&mut pending, answer, p).await
+                }
+                Event::Query(Err(e)) | Event::Answer(Err(e)) => {
+                    log::error!(
+                        target: logging::DHCP,
+                        "DNS64 proxy on {} receive error: {}",
+                        self.iface,
+                        e
+                    );
+                    runtime::sleep(Duration::from_millis(100)).await;
+                    Ok(())
+                }
+                Event::Stop => break,
+            };
+            if let Err(e) = res {
+                log::warn!(target: logging::DHCP, "DNS64 proxy on {}: {}", self.iface, e);
+            }
+        }
+        log::trace!(target: logging::DHCP, "DNS64 proxy on {} stopped", self.iface);
+    }
+
+    /// Sends `query` upstream under a random ID not in use
+    async fn forward(
+        &self,
+        pending: &mut HashMap<u16, Pending>,
+        query: &[u8],
+        p: Pending,
+    ) -> FResult<()> {
+        let id = loop {
+            let id: u16 = rand::random();
+            if !pending.contains_key(&id) {
+                break id;
+            }
+        };
+        let mut query = query.to_vec();
+        query[..2].copy_from_slice(&id.to_be_bytes());
+        self.upstream_socket.send_to(&query, self.upstream).await?;
+        pending.insert(id, p);
+        Ok(())
+    }
+
+    async fn answer(
+        &self,
+        pending: &mut HashMap<u16, Pending>,
+        answer: &[u8],
+        mut p: Pending,
+    ) -> FResult<()> {
+        let mut reply = match (p.aaaa_query.take(), p.aaaa_answer.take()) {
+            // answer to the A query sent for an AAAA one
+            (Some(_), Some(aaaa_answer)) => aaaa_reply(self.prefix, answer, aaaa_answer),
+            (Some(aaaa_query), None) if needs_synthesis(answer) => {
+                let mut a_query = aaaa_query.clone();
+                set_question_type(&mut a_query, DNS_TYPE_A).ok_or(FError::EncodingError)?;
+                p.aaaa_query = Some(aaaa_query);
+                p.aaaa_answer = Some(answer.to_vec());
+                return self.forward(pending, &a_query, p).await;
+            }
+            _ => answer.to_vec(),
+        };
+        reply[..2].copy_from_slice(&p.id);
+        self.socket.send_to(&reply, p.client).await?;
+        Ok(())
+    }
+}
+
+/// Answer to an AAAA query from the answer to the A query sent for it,
+/// `aaaa_answer` is returned as is when the name has no A record
+fn aaaa_reply(prefix: Ipv6Addr, a_answer: &[u8], aaaa_answer: Vec<u8>) -> Vec<u8> {
+    synthesize(prefix, a_answer).unwrap_or(aaaa_answer)
+}
+
+/// AAAA answer built from the A records of an answer,
+/// `None` if there are none
+fn synthesize(prefix: Ipv6Addr, answer: &[u8]) -> Option<Vec<u8>> {
+    let (question_end, _, _) = question(answer)?;
+    let ancount = u16::from_be_bytes([answer[6], answer[7]]);
+    let mut records = Vec::new();
+    let mut i = question_end;
+    for _ in 0..ancount {
+        i = skip_name(answer, i)?;
+        let fixed = answer.get(i..i + 10)?;
+        let rtype = u16::from_be_bytes([fixed[0], fixed[1]]);
+        let class = u16::from_be_bytes([fixed[2], fixed[3]]);
+        let ttl = &fixed[4..8];
+        let rdlen = u16::from_be_bytes([fixed[8], fixed[9]]) as usize;
+        let rdata = answer.get(i + 10..i + 10 + rdlen)?;
+        if rtype == DNS_TYPE_A && class == DNS_CLASS_IN && rdlen == 4 {
+            let addr = Ipv4Addr::new(rdata[0], rdata[1], rdata[2], rdata[3]);
+            records.push((ttl.to_vec(), translate(prefix, addr)));
+        }
+        i += 10 + rdlen;
+    }
+    if records.is_empty() {
+        return None;
+    }
+    let mut reply = answer[..question_end].to_vec();
+    // only the answers are kept
+    reply[6..8].copy_from_slice(&(records.len() as u16).to_be_bytes());
+    reply[8..12].copy_from_slice(&[0, 0, 0, 0]);
+    set_question_type(&mut reply, DNS_TYPE_AAAA)?;
+    for (ttl, addr) in records {
+        // owner is the name of the question
+        reply.extend_from_slice(&[0xc0, DNS_HEADER_LEN as u8]);
+        reply.extend_from_slice(&DNS_TYPE_AAAA.to_be_bytes());
+        reply.extend_from_slice(&DNS_CLASS_IN.to_be_bytes());
+        reply.extend_from_slice(&ttl);
+        reply.extend_from_slice(&16u16.to_be_bytes());
+        reply.extend_from_slice(&addr.octets());
+    }
+    Some(reply)
+}
+
+/// IPv4 address embedded in the last 32 bits of the /96 prefix
+fn translate(prefix: Ipv6Addr, addr: Ipv4Addr) -> Ipv6Addr {
+    let mut octets = prefix.octets();
+    octets[12..].copy_from_slice(&addr.octets());
+    Ipv6Addr::from(octets)
+}
+
+/// End offset, type and class of the first question
+fn question(msg: &[u8]) -> Option<(usize, u16, u16)> {
+    if u16::from_be_bytes([*msg.get(4)?, *msg.get(5)?]) == 0 {
+        return None;
+    }
+    let i = skip_name(msg, DNS_HEADER_LEN)?;
+    let fixed = msg.get(i..i + 4)?;
+    Some((
+        i + 4,
+        u16::from_be_bytes([fixed[0], fixed[1]]),
+        u16::from_be_bytes([fixed[2], fixed[3]]),
+    ))
+}
+
+fn set_question_type(msg: &mut [u8], qtype: u16) -> Option<()> {
+    let (end, _, _) = question(msg)?;
+    msg[end - 4..end - 2].copy_from_slice(&qtype.to_be_bytes());
+    Some(())
+}
+
+/// Offset after the name starting at `i`, compressed or not
+fn skip_name(msg: &[u8], mut i: usize) -> Option<usize> {
+    loop {
+        let len = *msg.get(i)? as usize;
+        match len {
+            0 => return Some(i + 1),
+            l if l & 0xc0 == 0xc0 => return Some(i + 2),
+            l => i += 1 + l,
+        }
+    }
+}
+
+/// Successful answer without any AAAA record
+fn needs_synthesis(answer: &[u8]) -> bool {
+    let flags = u16::from_be_bytes([answer[2], answer[3]]);
+    if flags & DNS_RCODE_MASK != 0 {
+        return false;
+    }
+    let ancount = u16::from_be_bytes([answer[6], answer[7]]);
+    let mut i = match question(answer) {
+        Some((end, _, _)) => end,
+        None => return false,
+    };
+    for _ in 0..ancount {
+        let j = match skip_name(answer, i) {
+            Some(j) => j,
+            None => return false,
+        };
+        let fixed = match answer.get(j..j + 10) {
+            Some(fixed) => fixed,
+            None => return false,
+        };
+        if u16::from_be_bytes([fixed[0], fixed[1]]) == DNS_TYPE_AAAA {
+            return false;
+        }
+        i = j + 10 + u16::from_be_bytes([fixed[8], fixed[9]]) as usize;
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NAME: &[u8] = b"\x03www\x07example\x03com\x00";
+    /// Pointer to the name of the question
+    const NAME_PTR: [u8; 2] = [0xc0, DNS_HEADER_LEN as u8];
+
+    fn prefix() -> Ipv6Addr {
+        "64:ff9b::".parse().unwrap()
+    }
+
+    fn message(flags: u16, qtype: u16, ancount: u16) -> Vec<u8> {
+        let mut msg = vec![0x12, 0x34];
+        msg.extend_from_slice(&flags.to_be_bytes());
+        msg.extend_from_slice(&1u16.to_be_bytes());
+        msg.extend_from_slice(&ancount.to_be_bytes());
+        msg.extend_from_slice(&[0, 0, 0, 0]);
+        msg.extend_from_slice(NAME);
+        msg.extend_from_slice(&qtype.to_be_bytes());
+        msg.extend_from_slice(&DNS_CLASS_IN.to_be_bytes());
+        msg
+    }
+
+    fn record(msg: &mut Vec<u8>, owner: &[u8], rtype: u16, rdata: &[u8]) {
+        msg.extend_from_slice(owner);
+        msg.extend_from_slice(&rtype.to_be_bytes());
+        msg.extend_from_slice(&DNS_CLASS_IN.to_be_bytes());
+        msg.extend_from_slice(&300u32.to_be_bytes());
+        msg.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        msg.extend_from_slice(rdata);
+    }
+
+    #[test]
+    fn skip_plain_and_compressed_names() {
+        let msg = message(0x0100, DNS_TYPE_A, 0);
+        assert_eq!(
+            skip_name(&msg, DNS_HEADER_LEN),
+            Some(DNS_HEADER_LEN + NAME.len())
+        );
+        let mut msg = msg;
+        let ptr = msg.len();
+        msg.extend_from_slice(&NAME_PTR);
+        assert_eq!(skip_name(&msg, ptr), Some(ptr + 2));
+        // label running past the end
+        assert_eq!(skip_name(b"\x05ab", 0), None);
+    }
+
+    #[test]
+    fn parse_question() {
+        let msg = message(0x0100, DNS_TYPE_AAAA, 0);
+        assert_eq!(
+            question(&msg),
+            Some((msg.len(), DNS_TYPE_AAAA, DNS_CLASS_IN))
+        );
+        let mut no_question = msg;
+        no_question[5] = 0;
+        assert_eq!(question(&no_question), None);
+    }
+
+    #[test]
+    fn synthesize_compressed_answer() {
+        let mut answer = message(0x8180, DNS_TYPE_A, 3);
+        let cname = answer.len();
+        record(
+            &mut answer,
+            &NAME_PTR,
+            5,
+            b"\x03web\xc0\x10", // web.example.com
+        );
+        // owners compressed to the name of the question and to the CNAME
+        record(&mut answer, &NAME_PTR, DNS_TYPE_A, &[192, 0, 2, 33]);
+        record(
+            &mut answer,
+            &[0xc0, (cname + 12) as u8],
+            DNS_TYPE_A,
+            &[198, 51, 100, 7],
+        );
+        // EDNS0 OPT record in the additional section
+        answer.extend_from_slice(&[0, 0, 41, 0x10, 0, 0, 0, 0, 0, 0, 0]);
+        answer[11] = 1;
+
+        let reply = synthesize(prefix(), &answer).unwrap();
+        assert_eq!(&reply[..2], &answer[..2]);
+        // two answers, no authority nor additional records
+        assert_eq!(&reply[6..12], &[0, 2, 0, 0, 0, 0]);
+        let (end, qtype, _) = question(&reply).unwrap();
+        assert_eq!(qtype, DNS_TYPE_AAAA);
+        let mut i = end;
+        let mut addrs = Vec::new();
+        for _ in 0..2 {
+            assert_eq!(&reply[i..i + 2], &NAME_PTR);
+            assert_eq!(&reply[i + 2..i + 4], &DNS_TYPE_AAAA.to_be_bytes());
+            assert_eq!(&reply[i + 6..i + 10], &300u32.to_be_bytes());
+            assert_eq!(&reply[i + 10..i + 12], &16u16.to_be_bytes());
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&reply[i + 12..i + 28]);
+            addrs.push(Ipv6Addr::from(octets));
+            i += 28;
+        }
+        assert_eq!(i, reply.len());
+        assert_eq!(
+            addrs,
+            vec![
+                "64:ff9b::c000:221".parse::<Ipv6Addr>().unwrap(),
+                "64:ff9b::c633:6407".parse::<Ipv6Addr>().unwrap(),
+            ]
+        );
+    }
+
+    #[test]
+    fn answer_without_a_records_is_kept() {
+        let aaaa_answer = message(0x8180, DNS_TYPE_AAAA, 0);
+        assert!(needs_synthesis(&aaaa_answer));
+        let mut a_answer = message(0x8180, DNS_TYPE_A, 1);
+        record(&mut a_answer, &NAME_PTR, 5, b"\x03web\xc0\x10");
+        assert_eq!(synthesize(prefix(), &a_answer), None);
+        assert_eq!(
+            aaaa_reply(prefix(), &a_answer, aaaa_answer.clone()),
+            aaaa_answer
+        );
+    }
+
+    #[test]
+    fn needs_synthesis_only_without_aaaa() {
+        let mut answer = message(0x8180, DNS_TYPE_AAAA, 1);
+        record(&mut answer, &NAME_PTR, DNS_TYPE_AAAA, &[0; 16]);
+        assert!(!needs_synthesis(&answer));
+        // NXDOMAIN
+        let answer = message(0x8183, DNS_TYPE_AAAA, 0);
+        assert!(!needs_synthesis(&answer));
+    }
+
+    #[test]
+    fn truncated_packets() {
+        let mut answer = message(0x8180, DNS_TYPE_A, 1);
+        record(&mut answer, &NAME_PTR, DNS_TYPE_A, &[192, 0, 2, 33]);
+        for len in DNS_HEADER_LEN..answer.len() {
+            let truncated = &answer[..len];
+            assert_eq!(synthesize(prefix(), truncated), None);
+            assert!(!needs_synthesis(truncated));
+        }
+        assert_eq!(question(&answer[..DNS_HEADER_LEN + 3]), None);
+        assert_eq!(question(&answer[..4]), None);
+    }
+
+    #[test]
+    fn translate_to_96_prefix() {
+        assert_eq!(
+            translate(prefix(), Ipv4Addr::new(192, 0, 2, 33)),
+            "64:ff9b::c000:221".parse::<Ipv6Addr>().unwrap()
+        );
+        // the last 32 bits of the prefix are replaced
+        let prefix: Ipv6Addr = "2001:db8:64::ffff:ffff".parse().unwrap();
+        assert_eq!(
+            translate(prefix, Ipv4Addr::new(10, 0, 0, 1)),
+            "2001:db8:64::a00:1".parse::<Ipv6Addr>().unwrap()
+        );
+    }
+}
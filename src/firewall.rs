@@ -0,0 +1,392 @@
+/*********************************************************************************
+* Copyright (c) 2018,2021 ADLINK Technology Inc.
+*
+* This program and the accompanying materials are made available under the
+* terms of the Eclipse Public License 2.0 which is available at
+* http://www.eclipse.org/legal/epl-2.0, or the Apache Software License 2.0
+* which is available at https://www.apache.org/licenses/LICENSE-2.0.
+*
+* SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+* Contributors:
+*   ADLINK fog05 team, <fog05@adlink-labs.tech>
+*********************************************************************************/
+
+//! Firewall backends of the NAT of the virtual networks.
+//!
+//! The backend is chosen per node with `firewall_backend` in the
+//! configuration: nftables by default, iptables-legacy on the systems
+//! without the `nft_*` modules. Only the source NAT of the networks goes
+//! through the backend, the name it is given is recorded in the network
+//! internals as one of `associated_tables`. Floating IPs, port forwards,
+//! set rules and the isolation of the networks need nftables.
+
+use std::process::Command;
+
+use ipnetwork::IpNetwork;
+
+use fog05_sdk::fresult::{FError, FResult};
+
+#[cfg(feature = "nat")]
+use std::ffi::CString;
+
+#[cfg(feature = "nat")]
+use nftnl::{nft_expr, nftnl_sys::libc, Batch, Chain, FinalizedBatch, ProtoFamily, Rule, Table};
+
+use crate::logging;
+use crate::networking::exec_command;
+use crate::simulation;
+use crate::types::SNATOptions;
+
+pub trait FirewallBackend: Send + Sync {
+    /// Translates the source of the traffic of `net` leaving through
+    /// `iface` as `snat` says, the rules are kept under `name`
+    fn configure_nat(
+        &self,
+        name: &str,
+        net: IpNetwork,
+        iface: &str,
+        snat: &SNATOptions,
+    ) -> FResult<()>;
+    /// Removes what `configure_nat` created under `name`
+    fn clean_nat(&self, name: &str) -> FResult<()>;
+}
+
+/// An inet table per network with a postrouting chain, written with nftnl
+#[cfg(feature = "nat")]
+pub struct NftablesBackend;
+
+/// Source address of the translation of `net`, `None` masquerades with
+/// the addresses of the interface. An address of the other family does
+/// not apply.
+fn snat_address(net: IpNetwork, snat: &SNATOptions) -> Option<String> {
+    match snat.address {
+        Some(addr) if addr.is_ipv6() == net.is_ipv6() => Some(if addr.is_ipv6() {
+            format!("[{}]", addr)
+        } else {
+            format!("{}", addr)
+        }),
+        _ => None,
+    }
+}
+
+/// Renders the nft commands creating the NAT table of a network with
+/// explicit SNAT options. Port ranges need a transport protocol, the
+/// other protocols only get the address translated.
+#[cfg(feature = "nat")]
+fn render_snat(table: &str, net: IpNetwork, iface: &str, snat: &SNATOptions) -> String {
+    let family = if net.is_ipv6() { "ip6" } else { "ip" };
+    let action = |ports: Option<(u16, u16)>| {
+        let ports = ports
+            .map(|(start, end)| format!(":{}-{}", start, end))
+            .unwrap_or_default();
+        match snat_address(net, snat) {
+            Some(addr) => format!("snat {} to {}{}", family, addr, ports),
+            None if ports.is_empty() => "masquerade".to_string(),
+            None => format!("masquerade to {}", ports),
+        }
+    };
+    let mut script = format!(
+        "add table inet {table}; \
+         add chain inet {table} postrouting {{ type nat hook postrouting priority 100; policy accept; }}",
+        table = table
+    );
+    let matcher = format!("{} saddr {} oifname \"{}\"", family, net, iface);
+    if snat.ports.is_some() {
+        script.push_str(&format!(
+            "; add rule inet {} postrouting {} meta l4proto {{ tcp, udp }} {}",
+            table,
+            matcher,
+            action(snat.ports)
+        ));
+    }
+    script.push_str(&format!(
+        "; add rule inet {} postrouting {} {}",
+        table,
+        matcher,
+        action(None)
+    ));
+    script
+}
+
+#[cfg(feature = "nat")]
+impl FirewallBackend for NftablesBackend {
+    fn configure_nat(
+        &self,
+        table_name: &str,
+        net: IpNetwork,
+        iface: &str,
+        snat: &SNATOptions,
+    ) -> FResult<()> {
+        if snat.address.is_some() || snat.ports.is_some() {
+            // not covered by the nftnl expressions
+            let mut cmd = Command::new("nft");
+            cmd.arg(render_snat(table_name, net, iface, snat));
+            exec_command(cmd)?;
+            return Ok(());
+        }
+        let chain_name = String::from("postrouting");
+        // Create a batch. This is used to store all the netlink messages we will later send.
+        // Creating a new batch also automatically writes the initial batch begin message needed
+        // to tell netlink this is a single transaction that might arrive over multiple netlink packets.
+        let mut batch = Batch::new();
+        // Create a netfilter table operating on both IPv4 and IPv6 (ProtoFamily::Inet)
+        let table = Table::new(
+            &CString::new(table_name).map_err(|e| FError::NetworkingError(format!("{}", e)))?,
+            ProtoFamily::Inet,
+        );
+        // Add the table to the batch with the `MsgType::Add` type, thus instructing netfilter to add
+        // this table under its `ProtoFamily::Inet` ruleset.
+        batch.add(&table, nftnl::MsgType::Add);
+
+        // Create a chain under the table we created above.
+        let mut chain = Chain::new(
+            &CString::new(chain_name).map_err(|e| FError::NetworkingError(format!("{}", e)))?,
+            &table,
+        );
+
+        // Hook the chains to the input and output event hooks, with highest priority (priority zero).
+        // See the `Chain::set_hook` documentation for details.
+        chain.set_hook(nftnl::Hook::PostRouting, 0);
+        // Set the chain type.
+        // See the `Chain::set_type` documentation for details.
+        chain.set_type(nftnl::ChainType::Nat);
+
+        // Add the two chains to the batch with the `MsgType` to tell netfilter to create the chains
+        // under the table.
+        batch.add(&chain, nftnl::MsgType::Add);
+
+        // Create a new rule object under the input chain.
+        let mut natting_rule = Rule::new(&chain);
+
+        // Lookup the interface index of the default gw interface.
+        let iface_index = iface_index(iface)?;
+        // the table is inet, the family is matched before the address
+        natting_rule.add_expr(&nft_expr!(meta nfproto));
+        match net {
+            IpNetwork::V4(_) => {
+                natting_rule.add_expr(&nft_expr!(cmp == libc::NFPROTO_IPV4 as u8));
+                //Type of payload is source address
+                natting_rule.add_expr(&nft_expr!(payload ipv4 saddr));
+                //netmask of the network
+                natting_rule.add_expr(&nft_expr!(bitwise mask net.mask(), xor 0u32));
+            }
+            IpNetwork::V6(_) => {
+                natting_rule.add_expr(&nft_expr!(cmp == libc::NFPROTO_IPV6 as u8));
+                natting_rule.add_expr(&nft_expr!(payload ipv6 saddr));
+                natting_rule.add_expr(&nft_expr!(
+                    bitwise mask net.mask(),
+                    xor std::net::Ipv6Addr::UNSPECIFIED
+                ));
+            }
+        }
+
+        //comparing ip portion of the address
+        natting_rule.add_expr(&nft_expr!(cmp == net.ip()));
+
+        // passing the index of output interface oif
+        natting_rule.add_expr(&nft_expr!(meta oif));
+
+        //use interface with this index
+        natting_rule.add_expr(&nft_expr!(cmp == iface_index));
+
+        // Add masquerading
+        natting_rule.add_expr(&nft_expr!(masquerade));
+
+        // Add the rule to the batch.
+        batch.add(&natting_rule, nftnl::MsgType::Add);
+
+        // === FINALIZE THE TRANSACTION AND SEND THE DATA TO NETFILTER ===
+
+        // Finalize the batch. This means the batch end message is written into the batch, telling
+        // netfilter the we reached the end of the transaction message. It's also converted to a type
+        // that implements `IntoIterator<Item = &'a [u8]>`, thus allowing us to get the raw netlink data
+        // out so it can be sent over a netlink socket to netfilter.
+        let finalized_batch = batch.finalize();
+
+        send_and_process(&finalized_batch)
+    }
+
+    fn clean_nat(&self, table_name: &str) -> FResult<()> {
+        // Create a batch. This is used to store all the netlink messages we will later send.
+        // Creating a new batch also automatically writes the initial batch begin message needed
+        // to tell netlink this is a single transaction that might arrive over multiple netlink packets.
+        let mut batch = Batch::new();
+        // Create a netfilter table operating on both IPv4 and IPv6 (ProtoFamily::Inet)
+        let table = Table::new(
+            &CString::new(table_name).map_err(|e| FError::NetworkingError(format!("{}", e)))?,
+            ProtoFamily::Inet,
+        );
+        // Add the table to the batch with the `MsgType::Del` type, thus instructing netfilter to remove
+        // this table under its `ProtoFamily::Inet` ruleset.
+        batch.add(&table, nftnl::MsgType::Del);
+
+        // === FINALIZE THE TRANSACTION AND SEND THE DATA TO NETFILTER ===
+
+        // Finalize the batch. This means the batch end message is written into the batch, telling
+        // netfilter the we reached the end of the transaction message. It's also converted to a type
+        // that implements `IntoIterator<Item = &'a [u8]>`, thus allowing us to get the raw netlink data
+        // out so it can be sent over a netlink socket to netfilter.
+        let finalized_batch = batch.finalize();
+
+        send_and_process(&finalized_batch)
+    }
+}
+
+#[cfg(feature = "nat")]
+fn send_and_process(batch: &FinalizedBatch) -> FResult<()> {
+    // Create a netlink socket to netfilter.
+    let socket = mnl::Socket::new(mnl::Bus::Netfilter)?;
+    // Send all the bytes in the batch.
+    socket.send_all(batch)?;
+    // Try to parse the messages coming back from netfilter. This part is still very unclear.
+    let portid = socket.portid();
+    let mut buffer = vec![0; nftnl::nft_nlmsg_maxsize() as usize];
+    let very_unclear_what_this_is_for = 2;
+    while let Some(message) = socket_recv(&socket, &mut buffer[..])? {
+        match mnl::cb_run(message, very_unclear_what_this_is_for, portid)? {
+            mnl::CbResult::Stop => {
+                break;
+            }
+            mnl::CbResult::Ok => (),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "nat")]
+fn socket_recv<'a>(socket: &mnl::Socket, buf: &'a mut [u8]) -> FResult<Option<&'a [u8]>> {
+    let ret = socket.recv(buf)?;
+    if ret > 0 {
+        Ok(Some(&buf[..ret]))
+    } else {
+        Ok(None)
+    }
+}
+
+// Look up the interface index for a given interface name.
+#[cfg(feature = "nat")]
+fn iface_index(name: &str) -> FResult<libc::c_uint> {
+    let c_name = CString::new(name).map_err(|e| FError::NetworkingError(format!("{}", e)))?;
+    let index = unsafe { libc::if_nametoindex(c_name.as_ptr()) };
+    if index == 0 {
+        Err(FError::from(std::io::Error::last_os_error()))
+    } else {
+        Ok(index)
+    }
+}
+
+/// A chain per network in the nat table of iptables-legacy, or of
+/// ip6tables-legacy for IPv6 networks, jumped to from POSTROUTING
+pub struct IptablesBackend;
+
+impl IptablesBackend {
+    fn command(v6: bool) -> Command {
+        Command::new(if v6 {
+            "ip6tables-legacy"
+        } else {
+            "iptables-legacy"
+        })
+    }
+
+    fn run(v6: bool, args: &[&str]) -> FResult<String> {
+        let mut cmd = Self::command(v6);
+        cmd.arg("-w").arg("-t").arg("nat").args(args);
+        exec_command(cmd)
+    }
+}
+
+impl FirewallBackend for IptablesBackend {
+    fn configure_nat(
+        &self,
+        chain: &str,
+        net: IpNetwork,
+        iface: &str,
+        snat: &SNATOptions,
+    ) -> FResult<()> {
+        let v6 = net.is_ipv6();
+        let source = format!("{}", net);
+        let append = |proto: Option<&str>, ports: Option<(u16, u16)>| {
+            let mut args = vec!["-A", chain, "-s", source.as_str(), "-o", iface];
+            if let Some(proto) = proto {
+                args.extend(&["-p", proto]);
+            }
+            let ports = ports.map(|(start, end)| format!("{}-{}", start, end));
+            let to = match (snat_address(net, snat), &ports) {
+                (Some(addr), Some(ports)) => Some(format!("{}:{}", addr, ports)),
+                (addr, _) => addr,
+            };
+            match (&to, &ports) {
+                (Some(to), _) => args.extend(&["-j", "SNAT", "--to-source", to.as_str()]),
+                (None, Some(ports)) => {
+                    args.extend(&["-j", "MASQUERADE", "--to-ports", ports.as_str()])
+                }
+                (None, None) => args.extend(&["-j", "MASQUERADE"]),
+            }
+            Self::run(v6, &args)
+        };
+        Self::run(v6, &["-N", chain])?;
+        // port ranges need a transport protocol
+        let res = match snat.ports {
+            Some(ports) => append(Some("tcp"), Some(ports))
+                .and_then(|_| append(Some("udp"), Some(ports)))
+                .and_then(|_| append(None, None)),
+            None => append(None, None),
+        }
+        .and_then(|_| Self::run(v6, &["-A", "POSTROUTING", "-j", chain]));
+        if res.is_err() {
+            let _ = Self::run(v6, &["-F", chain]);
+            let _ = Self::run(v6, &["-X", chain]);
+        }
+        res.map(|_| ())
+    }
+
+    fn clean_nat(&self, chain: &str) -> FResult<()> {
+        // the family of the chain is not recorded, it is in one of the two
+        let mut found = false;
+        for v6 in [false, true] {
+            if Self::run(v6, &["-L", chain, "-n"]).is_err() {
+                continue;
+            }
+            found = true;
+            Self::run(v6, &["-D", "POSTROUTING", "-j", chain])?;
+            Self::run(v6, &["-F", chain])?;
+            Self::run(v6, &["-X", chain])?;
+        }
+        if found {
+            Ok(())
+        } else {
+            Err(FError::NotFound)
+        }
+    }
+}
+
+/// Tables of the simulated kernel, whatever the configured backend
+pub struct SimulatedFirewall;
+
+impl FirewallBackend for SimulatedFirewall {
+    fn configure_nat(
+        &self,
+        name: &str,
+        net: IpNetwork,
+        iface: &str,
+        snat: &SNATOptions,
+    ) -> FResult<()> {
+        log::trace!(target: logging::NFT, "Simulated source NAT of {} in {}", net, name);
+        let action = match snat_address(net, snat) {
+            Some(addr) => format!("snat to {}", addr),
+            None => "masquerade".to_string(),
+        };
+        let ports = snat
+            .ports
+            .map(|(start, end)| format!(" ports {}-{}", start, end))
+            .unwrap_or_default();
+        simulation::add_nft_table(
+            name,
+            format!("postrouting {} {} oifname {}{}", action, net, iface, ports),
+        )
+    }
+
+    fn clean_nat(&self, name: &str) -> FResult<()> {
+        simulation::del_nft_table(name)
+    }
+}
@@ -0,0 +1,385 @@
+/*********************************************************************************
+* Copyright (c) 2018,2021 ADLINK Technology Inc.
+*
+* This program and the accompanying materials are made available under the
+* terms of the Eclipse Public License 2.0 which is available at
+* http://www.eclipse.org/legal/epl-2.0, or the Apache Software License 2.0
+* which is available at https://www.apache.org/licenses/LICENSE-2.0.
+*
+* SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+* Contributors:
+*   ADLINK fog05 team, <fog05@adlink-labs.tech>
+*********************************************************************************/
+
+//! IP address management.
+//!
+//! Subnets of the virtual networks are carved from the pools of the
+//! configuration, then every node instantiating a network reserves its own
+//! slice of the subnet for its DHCP server, and single addresses can be
+//! handed out of what is left. The allocations of a network are kept in the
+//! plugin internals of its global record, so that all the nodes see them,
+//! and are written by a single coordinator plugin comparing the version of
+//! the record; the functions here only compute the next free subnet, slice
+//! or address.
+
+use serde::{Deserialize, Serialize};
+
+use fog05_sdk::fresult::{FError, FResult};
+use fog05_sdk::types::IPAddress;
+
+use uuid::Uuid;
+
+/// Addresses reserved by a node for its DHCP server when the pool does
+/// not say otherwise
+pub const DEFAULT_NODE_RANGE_SIZE: u32 = 64;
+
+/// Network the subnets of the virtual networks are allocated from,
+/// every subnet has `subnet_prefix` as prefix length
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct IPAMPool {
+    pub name: String,
+    pub network: (IPAddress, u8),
+    pub subnet_prefix: u8,
+    pub node_range_size: Option<u32>,
+}
+
+/// Subnet allocated from a pool to a virtual network, published so that
+/// the networks not instantiated yet are known to the coordinator
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct IPAMSubnet {
+    pub pool: String,
+    pub subnet: (IPAddress, u8),
+}
+
+/// Slice of the subnet served by the DHCP server of a node
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct IPAMNodeRange {
+    pub node_uuid: Uuid,
+    pub start: IPAddress,
+    pub end: IPAddress,
+}
+
+/// Address handed out with `allocate_address`, `owner` is a free form
+/// label given by the caller
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct IPAMAddress {
+    pub address: IPAddress,
+    pub node_uuid: Uuid,
+    pub owner: Option<String>,
+}
+
+/// Allocations of a virtual network
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct VNetIPAM {
+    pub pool: String,
+    pub range_size: u32,
+    #[serde(default)]
+    pub node_ranges: Vec<IPAMNodeRange>,
+    #[serde(default)]
+    pub addresses: Vec<IPAMAddress>,
+}
+
+impl VNetIPAM {
+    fn is_taken(&self, addr: u128) -> bool {
+        self.node_ranges
+            .iter()
+            .any(|r| addr >= to_u128(r.start) && addr <= to_u128(r.end))
+            || self.addresses.iter().any(|a| to_u128(a.address) == addr)
+    }
+}
+
+fn to_u128(addr: IPAddress) -> u128 {
+    match addr {
+        IPAddress::V4(addr) => u128::from(u32::from(addr)),
+        IPAddress::V6(addr) => u128::from(addr),
+    }
+}
+
+fn from_u128(addr: u128, v6: bool) -> IPAddress {
+    if v6 {
+        IPAddress::V6(std::net::Ipv6Addr::from(addr))
+    } else {
+        IPAddress::V4(std::net::Ipv4Addr::from(addr as u32))
+    }
+}
+
+/// First and last address of a subnet
+fn bounds(subnet: (IPAddress, u8)) -> FResult<(u128, u128)> {
+    let (addr, prefix) = subnet;
+    let bits = if addr.is_ipv6() { 128 } else { 32 };
+    if prefix > bits {
+        return Err(FError::NetworkingError(format!(
+            "Invalid prefix length {} for {}",
+            prefix, addr
+        )));
+    }
+    let host_mask = u128::MAX
+        .checked_shr(u32::from(128 - bits + prefix))
+        .unwrap_or(0);
+    let first = to_u128(addr) & !host_mask;
+    Ok((first, first | host_mask))
+}
+
+/// First and last address usable by hosts, IPv4 leaves out the network
+/// and broadcast addresses
+fn host_bounds(subnet: (IPAddress, u8)) -> FResult<(u128, u128)> {
+    let (first, last) = bounds(subnet)?;
+    if subnet.0.is_ipv6() {
+        Ok((first + 1, last))
+    } else if last - first < 2 {
+        Err(FError::NetworkingError(format!(
+            "{}/{} has no host addresses",
+            subnet.0, subnet.1
+        )))
+    } else {
+        Ok((first + 1, last - 1))
+    }
+}
+
+/// Checks that the subnets of a pool fit in its network
+pub fn validate_pool(pool: &IPAMPool) -> FResult<()> {
+    bounds(pool.network)?;
+    let bits = if pool.network.0.is_ipv6() { 128 } else { 32 };
+    if pool.subnet_prefix < pool.network.1 || pool.subnet_prefix > bits {
+        return Err(FError::NetworkingError(format!(
+            "Pool {} cannot hold /{} subnets",
+            pool.name, pool.subnet_prefix
+        )));
+    }
+    Ok(())
+}
+
+/// Returns the first subnet of the pool not overlapping any of `used`
+pub fn allocate_subnet(pool: &IPAMPool, used: &[(IPAddress, u8)]) -> FResult<(IPAddress, u8)> {
+    validate_pool(pool)?;
+    let v6 = pool.network.0.is_ipv6();
+    let (first, last) = bounds(pool.network)?;
+    let used = used
+        .iter()
+        .filter(|(addr, _)| addr.is_ipv6() == v6)
+        .map(|subnet| bounds(*subnet))
+        .collect::<FResult<Vec<(u128, u128)>>>()?;
+    let mut start = first;
+    loop {
+        let (_, end) = bounds((from_u128(start, v6), pool.subnet_prefix))?;
+        if !used.iter().any(|(s, e)| start <= *e && *s <= end) {
+            return Ok((from_u128(start, v6), pool.subnet_prefix));
+        }
+        if end >= last {
+            return Err(FError::NetworkingError(format!(
+                "Pool {} is exhausted",
+                pool.name
+            )));
+        }
+        start = end + 1;
+    }
+}
+
+/// Gateway of a subnet allocated from a pool, its first host address
+pub fn subnet_gateway(subnet: (IPAddress, u8)) -> FResult<IPAddress> {
+    let (first, _) = host_bounds(subnet)?;
+    Ok(from_u128(first, subnet.0.is_ipv6()))
+}
+
+/// Returns the first `ipam.range_size` consecutive addresses of the
+/// subnet not taken by other nodes, by single addresses or by the gateway
+pub fn allocate_range(
+    subnet: (IPAddress, u8),
+    gateway: Option<IPAddress>,
+    ipam: &VNetIPAM,
+) -> FResult<(IPAddress, IPAddress)> {
+    if ipam.range_size == 0 {
+        return Err(FError::NetworkingError("Empty IPAM range size".to_string()));
+    }
+    let v6 = subnet.0.is_ipv6();
+    let (first, last) = host_bounds(subnet)?;
+    let gateway = gateway.map(to_u128);
+    let size = u128::from(ipam.range_size);
+    let mut start = first;
+    while let Some(end) = start.checked_add(size - 1).filter(|end| *end <= last) {
+        match (start..=end)
+            .rev()
+            .find(|a| ipam.is_taken(*a) || Some(*a) == gateway)
+        {
+            Some(taken) => match taken.checked_add(1) {
+                Some(next) => start = next,
+                None => break,
+            },
+            None => return Ok((from_u128(start, v6), from_u128(end, v6))),
+        }
+    }
+    Err(FError::NetworkingError(format!(
+        "No room left for a range of {} addresses in {}/{}",
+        ipam.range_size, subnet.0, subnet.1
+    )))
+}
+
+/// Returns the first address of the subnet not in use
+pub fn allocate_address(
+    subnet: (IPAddress, u8),
+    gateway: Option<IPAddress>,
+    ipam: &VNetIPAM,
+) -> FResult<IPAddress> {
+    let ranges: Vec<(IPAddress, IPAddress)> =
+        ipam.node_ranges.iter().map(|r| (r.start, r.end)).collect();
+    let taken: Vec<IPAddress> = ipam.addresses.iter().map(|a| a.address).collect();
+    first_free_address(subnet, gateway, &ranges, &taken)
+}
+
+/// Returns the first host address of the subnet that is not the gateway,
+/// is outside `ranges` and is not in `taken`
+pub fn first_free_address(
+    subnet: (IPAddress, u8),
+    gateway: Option<IPAddress>,
+    ranges: &[(IPAddress, IPAddress)],
+    taken: &[IPAddress],
+) -> FResult<IPAddress> {
+    let (first, last) = host_bounds(subnet)?;
+    let gateway = gateway.map(to_u128);
+    let ranges: Vec<(u128, u128)> = ranges
+        .iter()
+        .map(|(start, end)| (to_u128(*start), to_u128(*end)))
+        .collect();
+    let taken: Vec<u128> = taken.iter().copied().map(to_u128).collect();
+    (first..=last)
+        .find(|a| {
+            Some(*a) != gateway
+                && !taken.contains(a)
+                && !ranges.iter().any(|(start, end)| a >= start && a <= end)
+        })
+        .map(|a| from_u128(a, subnet.0.is_ipv6()))
+        .ok_or_else(|| FError::NetworkingError(format!("{}/{} is exhausted", subnet.0, subnet.1)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    fn v4(a: u8, b: u8, c: u8, d: u8) -> IPAddress {
+        IPAddress::V4(Ipv4Addr::new(a, b, c, d))
+    }
+
+    fn pool() -> IPAMPool {
+        IPAMPool {
+            name: "test".to_string(),
+            network: (v4(10, 0, 0, 0), 22),
+            subnet_prefix: 24,
+            node_range_size: None,
+        }
+    }
+
+    fn allocations(range_size: u32) -> VNetIPAM {
+        VNetIPAM {
+            pool: "test".to_string(),
+            range_size,
+            node_ranges: Vec::new(),
+            addresses: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn validate_pool_prefixes() {
+        assert!(validate_pool(&pool()).is_ok());
+        let mut wide = pool();
+        wide.subnet_prefix = 20;
+        assert!(validate_pool(&wide).is_err());
+        let mut long = pool();
+        long.subnet_prefix = 33;
+        assert!(validate_pool(&long).is_err());
+    }
+
+    #[test]
+    fn allocate_subnet_skips_used() {
+        let used = vec![(v4(10, 0, 0, 0), 24), (v4(10, 0, 1, 128), 25)];
+        assert_eq!(
+            allocate_subnet(&pool(), &used).unwrap(),
+            (v4(10, 0, 2, 0), 24)
+        );
+        // subnets of the other family do not overlap
+        let used = vec![(IPAddress::V6(Ipv6Addr::UNSPECIFIED), 0)];
+        assert_eq!(
+            allocate_subnet(&pool(), &used).unwrap(),
+            (v4(10, 0, 0, 0), 24)
+        );
+    }
+
+    #[test]
+    fn allocate_subnet_exhausted() {
+        let used = vec![(v4(10, 0, 0, 0), 16)];
+        assert!(allocate_subnet(&pool(), &used).is_err());
+    }
+
+    #[test]
+    fn allocate_subnet_v6() {
+        let pool = IPAMPool {
+            name: "v6".to_string(),
+            network: (IPAddress::V6("fd00::".parse().unwrap()), 48),
+            subnet_prefix: 64,
+            node_range_size: None,
+        };
+        let used = vec![(IPAddress::V6("fd00::".parse().unwrap()), 64)];
+        assert_eq!(
+            allocate_subnet(&pool, &used).unwrap(),
+            (IPAddress::V6("fd00:0:0:1::".parse().unwrap()), 64)
+        );
+    }
+
+    #[test]
+    fn gateway_is_first_host() {
+        assert_eq!(
+            subnet_gateway((v4(10, 0, 3, 0), 24)).unwrap(),
+            v4(10, 0, 3, 1)
+        );
+        assert!(subnet_gateway((v4(10, 0, 3, 0), 31)).is_err());
+    }
+
+    #[test]
+    fn allocate_range_avoids_gateway_and_taken() {
+        let subnet = (v4(10, 0, 0, 0), 24);
+        let mut ipam = allocations(4);
+        ipam.node_ranges.push(IPAMNodeRange {
+            node_uuid: Uuid::nil(),
+            start: v4(10, 0, 0, 2),
+            end: v4(10, 0, 0, 5),
+        });
+        ipam.addresses.push(IPAMAddress {
+            address: v4(10, 0, 0, 8),
+            node_uuid: Uuid::nil(),
+            owner: None,
+        });
+        assert_eq!(
+            allocate_range(subnet, Some(v4(10, 0, 0, 1)), &ipam).unwrap(),
+            (v4(10, 0, 0, 9), v4(10, 0, 0, 12))
+        );
+    }
+
+    #[test]
+    fn allocate_range_rejects_oversized() {
+        let subnet = (v4(10, 0, 0, 0), 29);
+        assert!(allocate_range(subnet, None, &allocations(7)).is_err());
+        assert!(allocate_range(subnet, None, &allocations(0)).is_err());
+        assert_eq!(
+            allocate_range(subnet, None, &allocations(6)).unwrap(),
+            (v4(10, 0, 0, 1), v4(10, 0, 0, 6))
+        );
+    }
+
+    #[test]
+    fn allocate_address_outside_ranges() {
+        let subnet = (v4(10, 0, 0, 0), 29);
+        let mut ipam = allocations(4);
+        ipam.node_ranges.push(IPAMNodeRange {
+            node_uuid: Uuid::nil(),
+            start: v4(10, 0, 0, 2),
+            end: v4(10, 0, 0, 4),
+        });
+        assert_eq!(
+            allocate_address(subnet, Some(v4(10, 0, 0, 1)), &ipam).unwrap(),
+            v4(10, 0, 0, 5)
+        );
+        let taken = [v4(10, 0, 0, 5), v4(10, 0, 0, 6)];
+        let ranges = [(v4(10, 0, 0, 2), v4(10, 0, 0, 4))];
+        assert!(first_free_address(subnet, Some(v4(10, 0, 0, 1)), &ranges, &taken).is_err());
+    }
+}
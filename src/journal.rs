@@ -0,0 +1,562 @@
+/*********************************************************************************
+* Copyright (c) 2018,2021 ADLINK Technology Inc.
+*
+* This program and the accompanying materials are made available under the
+* terms of the Eclipse Public License 2.0 which is available at
+* http://www.eclipse.org/legal/epl-2.0, or the Apache Software License 2.0
+* which is available at https://www.apache.org/licenses/LICENSE-2.0.
+*
+* SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+* Contributors:
+*   ADLINK fog05 team, <fog05@adlink-labs.tech>
+*********************************************************************************/
+
+//! Append-only journal of the local records written by the plugin.
+//!
+//! Every successful write or removal of a record is appended as a JSON
+//! line, folding the journal gives back the records as they were last
+//! written, which are used to rebuild the node when the connector data
+//! is lost (eg. after a reboot).
+
+use std::collections::HashMap;
+
+use async_std::fs;
+use async_std::path::Path;
+use async_std::prelude::*;
+
+use serde::{Deserialize, Serialize};
+
+use fog05_sdk::fresult::{FError, FResult};
+use fog05_sdk::types::{ConnectionPoint, NetworkNamespace, VirtualInterface, VirtualNetwork};
+
+use uuid::Uuid;
+
+use crate::sriov::VirtualFunction;
+use crate::sysctl::ReversePathFilter;
+use crate::types::{
+    BridgePortConfig, ConnectionPointQoS, ConnectionPointRateLimit, DataplanePort,
+    DefaultRouteFailover, IPTunnel, RecordMetadata,
+};
+
+pub const JOURNAL_FILE: &str = "journal.jsonl";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum JournalEntry {
+    Interface(VirtualInterface),
+    VirtualNetwork(VirtualNetwork),
+    NetworkNamespace(NetworkNamespace),
+    ConnectionPoint(ConnectionPoint),
+    RemoveInterface(Uuid),
+    RemoveVirtualNetwork(Uuid),
+    RemoveNetworkNamespace(Uuid),
+    RemoveConnectionPoint(Uuid),
+    /// STP settings of a bridge port, dropped with the interface
+    BridgePort(Uuid, BridgePortConfig),
+    /// Shaping of a connection point, dropped with it
+    ConnectionPointQoS(Uuid, ConnectionPointQoS),
+    RemoveConnectionPointQoS(Uuid),
+    /// Rate limits of a connection point, dropped with it
+    ConnectionPointRateLimit(Uuid, ConnectionPointRateLimit),
+    RemoveConnectionPointRateLimit(Uuid),
+    /// Default route failover of a namespace, dropped with it
+    RouteFailover(Uuid, DefaultRouteFailover),
+    RemoveRouteFailover(Uuid),
+    /// Reverse path filter of a namespace or an interface, dropped with it
+    ReversePathFilter(Uuid, ReversePathFilter),
+    /// Audit data of a record, dropped with it
+    Metadata(Uuid, RecordMetadata),
+    /// SR-IOV VF allocated to a connection point
+    VirtualFunction(VirtualFunction),
+    ReleaseVirtualFunction(Uuid),
+    /// Provisioning of a DPDK port, dropped with its interface
+    DataplanePort(DataplanePort),
+    /// IPIP or SIT tunnel, dropped with its interface
+    IPTunnel(IPTunnel),
+}
+
+/// Line of the journal, `timestamp` is in milliseconds since the epoch
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct JournalRecord {
+    pub timestamp: u64,
+    pub entry: JournalEntry,
+}
+
+/// Records resulting from the journal
+#[derive(Debug, Clone, Default)]
+pub struct JournalState {
+    pub interfaces: HashMap<Uuid, VirtualInterface>,
+    pub virtual_networks: HashMap<Uuid, VirtualNetwork>,
+    pub namespaces: HashMap<Uuid, NetworkNamespace>,
+    pub connection_points: HashMap<Uuid, ConnectionPoint>,
+    pub bridge_ports: HashMap<Uuid, BridgePortConfig>,
+    pub cp_qos: HashMap<Uuid, ConnectionPointQoS>,
+    pub cp_rate_limits: HashMap<Uuid, ConnectionPointRateLimit>,
+    pub route_failovers: HashMap<Uuid, DefaultRouteFailover>,
+    pub rp_filters: HashMap<Uuid, ReversePathFilter>,
+    pub metadata: HashMap<Uuid, RecordMetadata>,
+    pub virtual_functions: HashMap<Uuid, VirtualFunction>,
+    pub dataplane_ports: HashMap<Uuid, DataplanePort>,
+    pub ip_tunnels: HashMap<Uuid, IPTunnel>,
+}
+
+impl JournalState {
+    pub fn apply(&mut self, entry: JournalEntry) {
+        match entry {
+            JournalEntry::Interface(iface) => {
+                self.interfaces.insert(iface.uuid, iface);
+            }
+            JournalEntry::VirtualNetwork(vnet) => {
+                self.virtual_networks.insert(vnet.uuid, vnet);
+            }
+            JournalEntry::NetworkNamespace(netns) => {
+                self.namespaces.insert(netns.uuid, netns);
+            }
+            JournalEntry::ConnectionPoint(cp) => {
+                self.connection_points.insert(cp.uuid, cp);
+            }
+            JournalEntry::RemoveInterface(uuid) => {
+                self.interfaces.remove(&uuid);
+                self.bridge_ports.remove(&uuid);
+                self.dataplane_ports.remove(&uuid);
+                self.ip_tunnels.remove(&uuid);
+                self.rp_filters.remove(&uuid);
+                self.metadata.remove(&uuid);
+            }
+            JournalEntry::RemoveVirtualNetwork(uuid) => {
+                self.virtual_networks.remove(&uuid);
+                self.metadata.remove(&uuid);
+            }
+            JournalEntry::RemoveNetworkNamespace(uuid) => {
+                self.namespaces.remove(&uuid);
+                self.route_failovers.remove(&uuid);
+                self.rp_filters.remove(&uuid);
+                self.metadata.remove(&uuid);
+            }
+            JournalEntry::RemoveConnectionPoint(uuid) => {
+                self.connection_points.remove(&uuid);
+                self.cp_qos.remove(&uuid);
+                self.cp_rate_limits.remove(&uuid);
+                self.metadata.remove(&uuid);
+            }
+            JournalEntry::BridgePort(uuid, config) => {
+                self.bridge_ports.insert(uuid, config);
+            }
+            JournalEntry::ConnectionPointQoS(uuid, qos) => {
+                self.cp_qos.insert(uuid, qos);
+            }
+            JournalEntry::RemoveConnectionPointQoS(uuid) => {
+                self.cp_qos.remove(&uuid);
+            }
+            JournalEntry::ConnectionPointRateLimit(uuid, limit) => {
+                self.cp_rate_limits.insert(uuid, limit);
+            }
+            JournalEntry::RemoveConnectionPointRateLimit(uuid) => {
+                self.cp_rate_limits.remove(&uuid);
+            }
+            JournalEntry::RouteFailover(uuid, failover) => {
+                self.route_failovers.insert(uuid, failover);
+            }
+            JournalEntry::RemoveRouteFailover(uuid) => {
+                self.route_failovers.remove(&uuid);
+            }
+            JournalEntry::ReversePathFilter(uuid, filter) => {
+                self.rp_filters.insert(uuid, filter);
+            }
+            JournalEntry::Metadata(uuid, metadata) => {
+                self.metadata.insert(uuid, metadata);
+            }
+            JournalEntry::VirtualFunction(vf) => {
+                self.virtual_functions.insert(vf.uuid, vf);
+            }
+            JournalEntry::ReleaseVirtualFunction(uuid) => {
+                self.virtual_functions.remove(&uuid);
+            }
+            JournalEntry::DataplanePort(port) => {
+                self.dataplane_ports.insert(port.uuid, port);
+            }
+            JournalEntry::IPTunnel(tunnel) => {
+                self.ip_tunnels.insert(tunnel.uuid, tunnel);
+            }
+        }
+    }
+
+    /// Entries recreating the records, namespaces first
+    pub fn entries(&self) -> Vec<JournalEntry> {
+        let namespaces = self
+            .namespaces
+            .values()
+            .cloned()
+            .map(JournalEntry::NetworkNamespace);
+        let interfaces = self
+            .interfaces
+            .values()
+            .cloned()
+            .map(JournalEntry::Interface);
+        let bridge_ports = self
+            .bridge_ports
+            .iter()
+            .map(|(uuid, config)| JournalEntry::BridgePort(*uuid, config.clone()));
+        let vnets = self
+            .virtual_networks
+            .values()
+            .cloned()
+            .map(JournalEntry::VirtualNetwork);
+        let cps = self
+            .connection_points
+            .values()
+            .cloned()
+            .map(JournalEntry::ConnectionPoint);
+        let cp_qos = self
+            .cp_qos
+            .iter()
+            .map(|(uuid, qos)| JournalEntry::ConnectionPointQoS(*uuid, qos.clone()));
+        let cp_rate_limits = self
+            .cp_rate_limits
+            .iter()
+            .map(|(uuid, limit)| JournalEntry::ConnectionPointRateLimit(*uuid, limit.clone()));
+        let route_failovers = self
+            .route_failovers
+            .iter()
+            .map(|(uuid, failover)| JournalEntry::RouteFailover(*uuid, failover.clone()));
+        let rp_filters = self
+            .rp_filters
+            .iter()
+            .map(|(uuid, filter)| JournalEntry::ReversePathFilter(*uuid, filter.clone()));
+        let metadata = self
+            .metadata
+            .iter()
+            .map(|(uuid, metadata)| JournalEntry::Metadata(*uuid, metadata.clone()));
+        let vfs = self
+            .virtual_functions
+            .values()
+            .cloned()
+            .map(JournalEntry::VirtualFunction);
+        let dataplane_ports = self
+            .dataplane_ports
+            .values()
+            .cloned()
+            .map(JournalEntry::DataplanePort);
+        let ip_tunnels = self
+            .ip_tunnels
+            .values()
+            .cloned()
+            .map(JournalEntry::IPTunnel);
+        namespaces
+            .chain(interfaces)
+            .chain(bridge_ports)
+            .chain(vnets)
+            .chain(cps)
+            .chain(cp_qos)
+            .chain(cp_rate_limits)
+            .chain(route_failovers)
+            .chain(rp_filters)
+            .chain(metadata)
+            .chain(vfs)
+            .chain(dataplane_ports)
+            .chain(ip_tunnels)
+            .collect()
+    }
+}
+
+pub async fn append(path: &Path, timestamp: u64, entry: JournalEntry) -> FResult<()> {
+    let record = JournalRecord { timestamp, entry };
+    let mut line =
+        serde_json::to_string(&record).map_err(|e| FError::NetworkingError(format!("{}", e)))?;
+    line.push('\n');
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await?;
+    file.write_all(line.as_bytes()).await?;
+    file.sync_data().await?;
+    Ok(())
+}
+
+/// Folds the journal, a line that cannot be parsed is
+/// skipped as it is left by a write interrupted by a crash
+pub async fn read(path: &Path) -> FResult<JournalState> {
+    let mut state = JournalState::default();
+    if !path.exists().await {
+        return Ok(state);
+    }
+    let content = fs::read_to_string(path).await?;
+    for (n, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<JournalRecord>(line) {
+            Ok(record) => state.apply(record.entry),
+            Err(e) => log::warn!("Skipping journal line {}: {}", n + 1, e),
+        }
+    }
+    Ok(state)
+}
+
+/// Rewrites the journal with only the entries needed to get `state`
+pub async fn compact(path: &Path, timestamp: u64, state: &JournalState) -> FResult<()> {
+    let mut content = String::new();
+    for entry in state.entries() {
+        let record = JournalRecord { timestamp, entry };
+        content.push_str(
+            &serde_json::to_string(&record)
+                .map_err(|e| FError::NetworkingError(format!("{}", e)))?,
+        );
+        content.push('\n');
+    }
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, content).await?;
+    fs::rename(&tmp, path).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{DataplanePortKind, FailoverGateway, IPTunnelKind};
+    use async_std::path::PathBuf;
+    use async_std::task;
+    use fog05_sdk::types::IPAddress;
+    use std::net::Ipv4Addr;
+
+    fn metadata() -> RecordMetadata {
+        RecordMetadata {
+            created_at: 1,
+            updated_at: 2,
+            created_by: None,
+            version: None,
+        }
+    }
+
+    fn namespace(uuid: Uuid) -> NetworkNamespace {
+        NetworkNamespace {
+            uuid,
+            ns_name: format!("ns-{}", uuid),
+            interfaces: Vec::new(),
+        }
+    }
+
+    fn tunnel(uuid: Uuid) -> IPTunnel {
+        IPTunnel {
+            uuid,
+            if_name: "tun0".to_string(),
+            kind: IPTunnelKind::IPIP,
+            local_addr: IPAddress::V4(Ipv4Addr::new(10, 0, 0, 1)),
+            remote_addr: IPAddress::V4(Ipv4Addr::new(10, 0, 0, 2)),
+            ttl: None,
+            net_ns: None,
+        }
+    }
+
+    fn dataplane_port(uuid: Uuid) -> DataplanePort {
+        DataplanePort {
+            uuid,
+            if_name: "vhu0".to_string(),
+            kind: DataplanePortKind::VHOSTUSER,
+            socket_path: None,
+        }
+    }
+
+    fn failover(intf_uuid: Uuid) -> DefaultRouteFailover {
+        DefaultRouteFailover {
+            primary: FailoverGateway {
+                intf_uuid,
+                gateway: IPAddress::V4(Ipv4Addr::new(10, 0, 0, 1)),
+            },
+            backup: FailoverGateway {
+                intf_uuid,
+                gateway: IPAddress::V4(Ipv4Addr::new(10, 0, 1, 1)),
+            },
+            max_failures: None,
+        }
+    }
+
+    fn qos() -> ConnectionPointQoS {
+        ConnectionPointQoS {
+            rate_kbit: 1000,
+            burst_kb: 10,
+            latency_ms: None,
+        }
+    }
+
+    fn rate_limit() -> ConnectionPointRateLimit {
+        ConnectionPointRateLimit {
+            packets_per_sec: Some(100),
+            packets_burst: None,
+            kbytes_per_sec: None,
+            kbytes_burst: None,
+        }
+    }
+
+    fn journal_path() -> PathBuf {
+        PathBuf::from(std::env::temp_dir()).join(format!("journal-{}.jsonl", Uuid::new_v4()))
+    }
+
+    fn sorted_keys<T>(map: &HashMap<Uuid, T>) -> Vec<Uuid> {
+        let mut keys: Vec<Uuid> = map.keys().cloned().collect();
+        keys.sort();
+        keys
+    }
+
+    #[test]
+    fn apply_keeps_last_write() {
+        let uuid = Uuid::new_v4();
+        let mut state = JournalState::default();
+        state.apply(JournalEntry::NetworkNamespace(namespace(uuid)));
+        let mut renamed = namespace(uuid);
+        renamed.ns_name = "renamed".to_string();
+        state.apply(JournalEntry::NetworkNamespace(renamed));
+        assert_eq!(state.namespaces.len(), 1);
+        assert_eq!(state.namespaces[&uuid].ns_name, "renamed");
+
+        state.apply(JournalEntry::ConnectionPointQoS(uuid, qos()));
+        state.apply(JournalEntry::RemoveConnectionPointQoS(uuid));
+        assert!(state.cp_qos.is_empty());
+        assert!(state.namespaces.contains_key(&uuid));
+    }
+
+    #[test]
+    fn remove_interface_drops_dependent_records() {
+        let uuid = Uuid::new_v4();
+        let other = Uuid::new_v4();
+        let mut state = JournalState::default();
+        for id in [uuid, other] {
+            state.apply(JournalEntry::BridgePort(id, BridgePortConfig::default()));
+            state.apply(JournalEntry::DataplanePort(dataplane_port(id)));
+            state.apply(JournalEntry::IPTunnel(tunnel(id)));
+            state.apply(JournalEntry::ReversePathFilter(
+                id,
+                ReversePathFilter::default(),
+            ));
+            state.apply(JournalEntry::Metadata(id, metadata()));
+        }
+        state.apply(JournalEntry::RemoveInterface(uuid));
+        assert_eq!(sorted_keys(&state.bridge_ports), vec![other]);
+        assert_eq!(sorted_keys(&state.dataplane_ports), vec![other]);
+        assert_eq!(sorted_keys(&state.ip_tunnels), vec![other]);
+        assert_eq!(sorted_keys(&state.rp_filters), vec![other]);
+        assert_eq!(sorted_keys(&state.metadata), vec![other]);
+    }
+
+    #[test]
+    fn remove_namespace_drops_dependent_records() {
+        let uuid = Uuid::new_v4();
+        let mut state = JournalState::default();
+        state.apply(JournalEntry::NetworkNamespace(namespace(uuid)));
+        state.apply(JournalEntry::RouteFailover(uuid, failover(Uuid::new_v4())));
+        state.apply(JournalEntry::ReversePathFilter(
+            uuid,
+            ReversePathFilter::default(),
+        ));
+        state.apply(JournalEntry::Metadata(uuid, metadata()));
+        state.apply(JournalEntry::RemoveNetworkNamespace(uuid));
+        assert!(state.namespaces.is_empty());
+        assert!(state.route_failovers.is_empty());
+        assert!(state.rp_filters.is_empty());
+        assert!(state.metadata.is_empty());
+    }
+
+    #[test]
+    fn remove_connection_point_drops_dependent_records() {
+        let uuid = Uuid::new_v4();
+        let mut state = JournalState::default();
+        state.apply(JournalEntry::ConnectionPointQoS(uuid, qos()));
+        state.apply(JournalEntry::ConnectionPointRateLimit(uuid, rate_limit()));
+        state.apply(JournalEntry::Metadata(uuid, metadata()));
+        state.apply(JournalEntry::RemoveConnectionPoint(uuid));
+        assert!(state.cp_qos.is_empty());
+        assert!(state.cp_rate_limits.is_empty());
+        assert!(state.metadata.is_empty());
+    }
+
+    #[test]
+    fn remove_virtual_network_drops_metadata() {
+        let uuid = Uuid::new_v4();
+        let mut state = JournalState::default();
+        state.apply(JournalEntry::Metadata(uuid, metadata()));
+        state.apply(JournalEntry::RemoveVirtualNetwork(uuid));
+        assert!(state.metadata.is_empty());
+    }
+
+    #[test]
+    fn read_skips_truncated_last_line() {
+        task::block_on(async {
+            let path = journal_path();
+            let first = Uuid::new_v4();
+            let second = Uuid::new_v4();
+            append(&path, 1, JournalEntry::NetworkNamespace(namespace(first)))
+                .await
+                .unwrap();
+            append(&path, 2, JournalEntry::Metadata(first, metadata()))
+                .await
+                .unwrap();
+            // a crash in the middle of a write leaves a partial line
+            let line = serde_json::to_string(&JournalRecord {
+                timestamp: 3,
+                entry: JournalEntry::NetworkNamespace(namespace(second)),
+            })
+            .unwrap();
+            let mut file = fs::OpenOptions::new()
+                .append(true)
+                .open(&path)
+                .await
+                .unwrap();
+            file.write_all(line[..line.len() / 2].as_bytes())
+                .await
+                .unwrap();
+            drop(file);
+
+            let state = read(&path).await.unwrap();
+            fs::remove_file(&path).await.unwrap();
+            assert_eq!(sorted_keys(&state.namespaces), vec![first]);
+            assert_eq!(state.metadata.get(&first), Some(&metadata()));
+        })
+    }
+
+    #[test]
+    fn compact_then_read_gives_same_state() {
+        task::block_on(async {
+            let path = journal_path();
+            let ns = Uuid::new_v4();
+            let intf = Uuid::new_v4();
+            let cp = Uuid::new_v4();
+            let mut state = JournalState::default();
+            state.apply(JournalEntry::NetworkNamespace(namespace(ns)));
+            state.apply(JournalEntry::RouteFailover(ns, failover(intf)));
+            state.apply(JournalEntry::BridgePort(
+                intf,
+                BridgePortConfig {
+                    priority: Some(32),
+                    cost: Some(100),
+                },
+            ));
+            state.apply(JournalEntry::IPTunnel(tunnel(intf)));
+            state.apply(JournalEntry::DataplanePort(dataplane_port(intf)));
+            state.apply(JournalEntry::ReversePathFilter(
+                intf,
+                ReversePathFilter::default(),
+            ));
+            state.apply(JournalEntry::ConnectionPointQoS(cp, qos()));
+            state.apply(JournalEntry::ConnectionPointRateLimit(cp, rate_limit()));
+            state.apply(JournalEntry::Metadata(cp, metadata()));
+
+            compact(&path, 10, &state).await.unwrap();
+            let read_back = read(&path).await.unwrap();
+            fs::remove_file(&path).await.unwrap();
+
+            assert_eq!(sorted_keys(&read_back.namespaces), vec![ns]);
+            assert_eq!(sorted_keys(&read_back.route_failovers), vec![ns]);
+            assert_eq!(sorted_keys(&read_back.ip_tunnels), vec![intf]);
+            assert_eq!(sorted_keys(&read_back.dataplane_ports), vec![intf]);
+            assert_eq!(sorted_keys(&read_back.rp_filters), vec![intf]);
+            assert_eq!(read_back.bridge_ports, state.bridge_ports);
+            assert_eq!(read_back.cp_qos, state.cp_qos);
+            assert_eq!(read_back.cp_rate_limits, state.cp_rate_limits);
+            assert_eq!(read_back.metadata, state.metadata);
+            assert!(read_back.interfaces.is_empty());
+            assert!(read_back.virtual_networks.is_empty());
+            assert!(read_back.connection_points.is_empty());
+            assert!(read_back.virtual_functions.is_empty());
+        })
+    }
+}
@@ -0,0 +1,305 @@
+/*********************************************************************************
+* Copyright (c) 2018,2021 ADLINK Technology Inc.
+*
+* This program and the accompanying materials are made available under the
+* terms of the Eclipse Public License 2.0 which is available at
+* http://www.eclipse.org/legal/epl-2.0, or the Apache Software License 2.0
+* which is available at https://www.apache.org/licenses/LICENSE-2.0.
+*
+* SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+* Contributors:
+*   ADLINK fog05 team, <fog05@adlink-labs.tech>
+*********************************************************************************/
+
+//! Kernel backends of the links and namespaces of the default namespace.
+//!
+//! The plugin goes through rtnetlink, or iproute2 where rtnetlink has no
+//! builder, unless it is started with `--simulate`: the operations are
+//! then applied to the model of the `simulation` module. The links in the
+//! other namespaces are handled by their namespace managers.
+
+use async_trait::async_trait;
+
+use fog05_sdk::fresult::FResult;
+use fog05_sdk::types::IPAddress;
+
+use crate::simulation;
+use crate::sriov::VFConfig;
+use crate::types::{BridgePortConfig, IPTunnelKind, VXLANOptions};
+
+/// Links and namespaces of the node, link names are the ones of the
+/// default namespace
+#[async_trait]
+pub trait KernelBackend: Send + Sync {
+    /// Creates a network namespace
+    async fn add_netns(&self, ns_name: String) -> FResult<()>;
+
+    /// Removes a network namespace with its links
+    async fn del_netns(&self, ns_name: String) -> FResult<()>;
+
+    /// Creates a bridge in the default namespace
+    async fn create_bridge(&self, br_name: String) -> FResult<()>;
+
+    /// Creates a WireGuard link, it is configured separately
+    async fn create_wireguard(&self, iface: String) -> FResult<()>;
+
+    /// Sets the STP priority and cost of a bridge port
+    async fn set_iface_bridge_port(&self, iface: &str, config: &BridgePortConfig) -> FResult<()>;
+
+    /// Removes the learned entries from the forwarding database of a bridge
+    async fn flush_bridge_fdb(&self, br_name: &str) -> FResult<()>;
+
+    /// Applies MAC, VLAN and spoof checking to a VF of `pf`
+    async fn set_vf_config(&self, pf: &str, index: u32, config: &VFConfig) -> FResult<()>;
+
+    /// IPIP and SIT links are created with iproute2 as rtnetlink does
+    /// not provide builders for them
+    fn create_ip_tunnel_iface(
+        &self,
+        iface: &str,
+        kind: IPTunnelKind,
+        local_addr: IPAddress,
+        remote_addr: IPAddress,
+        ttl: Option<u8>,
+    ) -> FResult<()>;
+
+    /// Creates a veth pair in the default namespace
+    async fn create_veth(&self, iface_i: String, iface_e: String) -> FResult<()>;
+
+    /// Creates a VLAN link with tag `tag` on top of `dev`
+    async fn create_vlan(&self, iface: String, dev: String, tag: u16) -> FResult<()>;
+
+    /// Creates a VXLAN link on top of `dev` using a multicast group
+    async fn create_mcast_vxlan(
+        &self,
+        iface: String,
+        dev: String,
+        vni: u32,
+        mcast_addr: IPAddress,
+        port: u16,
+        options: &VXLANOptions,
+    ) -> FResult<()>;
+
+    /// Creates a VXLAN link on top of `dev` whose remote ends are
+    /// added to the forwarding database
+    async fn create_unicast_vxlan(
+        &self,
+        iface: String,
+        dev: String,
+        vni: u32,
+        local_addr: IPAddress,
+        port: u16,
+        options: &VXLANOptions,
+    ) -> FResult<()>;
+
+    /// Creates a point to point VXLAN link on top of `dev`
+    async fn create_ptp_vxlan(
+        &self,
+        iface: String,
+        dev: String,
+        vni: u32,
+        local_addr: IPAddress,
+        remote_addr: IPAddress,
+        port: u16,
+        options: &VXLANOptions,
+    ) -> FResult<()>;
+
+    /// Removes a link, the other end of a veth goes with it
+    async fn del_iface(&self, iface: String) -> FResult<()>;
+
+    /// Enslaves a link to `master`
+    async fn set_iface_master(&self, iface: String, master: String) -> FResult<()>;
+
+    /// Name of the master of a link, if any
+    async fn get_iface_master(&self, iface: String) -> FResult<Option<String>>;
+
+    /// Releases a link from its master
+    async fn del_iface_master(&self, iface: String) -> FResult<()>;
+
+    async fn add_iface_address(&self, iface: String, addr: IPAddress, prefix: u8) -> FResult<()>;
+
+    async fn del_iface_address(&self, iface: String, addr: IPAddress) -> FResult<()>;
+
+    async fn get_iface_addresses(&self, iface: String) -> FResult<Vec<IPAddress>>;
+
+    async fn set_iface_name(&self, iface: String, new_name: String) -> FResult<()>;
+
+    async fn get_iface_mac(&self, iface: String) -> FResult<Vec<u8>>;
+
+    async fn get_iface_mtu(&self, iface: String) -> FResult<u32>;
+
+    async fn set_iface_mtu(&self, iface: String, mtu: u32) -> FResult<()>;
+
+    async fn set_iface_mac(&self, iface: String, address: Vec<u8>) -> FResult<()>;
+
+    /// Moves a link to the namespace named `netns`
+    async fn set_iface_ns(&self, iface: String, netns: String) -> FResult<()>;
+
+    /// Moves a link back to the default namespace
+    async fn set_iface_default_ns(&self, iface: String) -> FResult<()>;
+
+    async fn set_iface_up(&self, iface: String) -> FResult<()>;
+
+    async fn set_iface_down(&self, iface: String) -> FResult<()>;
+
+    async fn iface_exists(&self, iface: String) -> FResult<bool>;
+}
+
+/// Model of the simulated kernel, the configuration of bridge ports, VFs
+/// and WireGuard links is not modelled, nor the forwarding databases
+pub struct SimulatedKernelBackend;
+
+#[async_trait]
+impl KernelBackend for SimulatedKernelBackend {
+    async fn add_netns(&self, ns_name: String) -> FResult<()> {
+        simulation::add_netns(&ns_name)
+    }
+
+    async fn del_netns(&self, ns_name: String) -> FResult<()> {
+        simulation::del_netns(&ns_name)
+    }
+
+    async fn create_bridge(&self, br_name: String) -> FResult<()> {
+        simulation::add_link(None, &br_name, "bridge")
+    }
+
+    async fn create_wireguard(&self, iface: String) -> FResult<()> {
+        simulation::add_link(None, &iface, "wireguard")
+    }
+
+    async fn set_iface_bridge_port(&self, _iface: &str, _config: &BridgePortConfig) -> FResult<()> {
+        Ok(())
+    }
+
+    async fn flush_bridge_fdb(&self, _br_name: &str) -> FResult<()> {
+        Ok(())
+    }
+
+    async fn set_vf_config(&self, _pf: &str, _index: u32, _config: &VFConfig) -> FResult<()> {
+        Ok(())
+    }
+
+    fn create_ip_tunnel_iface(
+        &self,
+        iface: &str,
+        _kind: IPTunnelKind,
+        _local_addr: IPAddress,
+        _remote_addr: IPAddress,
+        _ttl: Option<u8>,
+    ) -> FResult<()> {
+        simulation::add_link(None, iface, "iptunnel")
+    }
+
+    async fn create_veth(&self, iface_i: String, iface_e: String) -> FResult<()> {
+        simulation::add_veth(None, &iface_i, &iface_e)
+    }
+
+    async fn create_vlan(&self, iface: String, _dev: String, _tag: u16) -> FResult<()> {
+        simulation::add_link(None, &iface, "vlan")
+    }
+
+    async fn create_mcast_vxlan(
+        &self,
+        iface: String,
+        _dev: String,
+        _vni: u32,
+        _mcast_addr: IPAddress,
+        _port: u16,
+        _options: &VXLANOptions,
+    ) -> FResult<()> {
+        simulation::add_link(None, &iface, "vxlan")
+    }
+
+    async fn create_unicast_vxlan(
+        &self,
+        iface: String,
+        _dev: String,
+        _vni: u32,
+        _local_addr: IPAddress,
+        _port: u16,
+        _options: &VXLANOptions,
+    ) -> FResult<()> {
+        simulation::add_link(None, &iface, "vxlan")
+    }
+
+    async fn create_ptp_vxlan(
+        &self,
+        iface: String,
+        _dev: String,
+        _vni: u32,
+        _local_addr: IPAddress,
+        _remote_addr: IPAddress,
+        _port: u16,
+        _options: &VXLANOptions,
+    ) -> FResult<()> {
+        simulation::add_link(None, &iface, "vxlan")
+    }
+
+    async fn del_iface(&self, iface: String) -> FResult<()> {
+        simulation::del_link(None, &iface)
+    }
+
+    async fn set_iface_master(&self, iface: String, master: String) -> FResult<()> {
+        simulation::set_link_master(None, &iface, Some(&master))
+    }
+
+    async fn get_iface_master(&self, iface: String) -> FResult<Option<String>> {
+        simulation::get_link_master(None, &iface)
+    }
+
+    async fn del_iface_master(&self, iface: String) -> FResult<()> {
+        simulation::set_link_master(None, &iface, None)
+    }
+
+    async fn add_iface_address(&self, iface: String, addr: IPAddress, prefix: u8) -> FResult<()> {
+        simulation::add_address(None, &iface, addr, prefix)
+    }
+
+    async fn del_iface_address(&self, iface: String, addr: IPAddress) -> FResult<()> {
+        simulation::del_address(None, &iface, addr)
+    }
+
+    async fn get_iface_addresses(&self, iface: String) -> FResult<Vec<IPAddress>> {
+        simulation::get_addresses(None, &iface)
+    }
+
+    async fn set_iface_name(&self, iface: String, new_name: String) -> FResult<()> {
+        simulation::set_link_name(None, &iface, &new_name)
+    }
+
+    async fn get_iface_mac(&self, iface: String) -> FResult<Vec<u8>> {
+        simulation::get_link_mac(None, &iface)
+    }
+
+    async fn get_iface_mtu(&self, iface: String) -> FResult<u32> {
+        simulation::get_link_mtu(None, &iface)
+    }
+
+    async fn set_iface_mtu(&self, iface: String, mtu: u32) -> FResult<()> {
+        simulation::set_link_mtu(None, &iface, mtu)
+    }
+
+    async fn set_iface_mac(&self, iface: String, address: Vec<u8>) -> FResult<()> {
+        simulation::set_link_mac(None, &iface, address)
+    }
+
+    async fn set_iface_ns(&self, iface: String, netns: String) -> FResult<()> {
+        simulation::move_link(None, &iface, Some(&netns))
+    }
+
+    async fn set_iface_default_ns(&self, iface: String) -> FResult<()> {
+        simulation::move_link(None, &iface, None)
+    }
+
+    async fn set_iface_up(&self, iface: String) -> FResult<()> {
+        simulation::set_link_up(None, &iface, true)
+    }
+
+    async fn set_iface_down(&self, iface: String) -> FResult<()> {
+        simulation::set_link_up(None, &iface, false)
+    }
+
+    async fn iface_exists(&self, iface: String) -> FResult<bool> {
+        simulation::link_exists(None, &iface)
+    }
+}
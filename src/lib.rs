@@ -12,6 +12,20 @@
 *********************************************************************************/
 #![allow(clippy::upper_case_acronyms)]
 
+pub mod dhcp;
+pub mod dhcp_client;
+pub mod dhcp_server;
+pub mod dns64;
+pub mod firewall;
+pub mod ipam;
+pub mod journal;
+pub mod kernel;
+pub mod logging;
 pub mod networking;
+pub mod ns_channel;
+pub mod runtime;
+pub mod simulation;
+pub mod sriov;
+pub mod sysctl;
 // pub mod plugin;
 pub mod types;
@@ -0,0 +1,254 @@
+/*********************************************************************************
+* Copyright (c) 2018,2021 ADLINK Technology Inc.
+*
+* This program and the accompanying materials are made available under the
+* terms of the Eclipse Public License 2.0 which is available at
+* http://www.eclipse.org/legal/epl-2.0, or the Apache Software License 2.0
+* which is available at https://www.apache.org/licenses/LICENSE-2.0.
+*
+* SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+* Contributors:
+*   ADLINK fog05 team, <fog05@adlink-labs.tech>
+*********************************************************************************/
+
+//! Logger of the plugin binaries.
+//!
+//! Messages of the main subsystems use their own target, eg.
+//! `log::trace!(target: logging::NETLINK, ...)`, so that they can be
+//! filtered on their own. `RUST_LOG` gives the levels at startup, they can
+//! be overridden per target from the configuration or at run time, and
+//! trace messages of a target can be sampled, writing one out of `n`.
+//! A setting for a target also applies to the targets under it, eg. one
+//! for `fog05_networking_linux` applies to all the modules of the crate.
+//!
+//! Operations are also wrapped in `tracing` spans: the RPCs on records
+//! carry their UUIDs, the netlink helpers their arguments and the requests
+//! to the Namespace Managers their method, so that the steps of a slow or
+//! failing operation can be told apart. Without a tracing subscriber the
+//! spans are written by this logger under the `tracing::span` target.
+
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock, RwLock};
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+use serde::{Deserialize, Serialize};
+
+use fog05_sdk::fresult::{FError, FResult};
+
+/// Netlink operations on links, addresses and namespaces
+pub const NETLINK: &str = "netlink";
+/// nftables tables and rules
+pub const NFT: &str = "nft";
+/// Lifecycle of the Namespace Managers
+pub const NS_MANAGER: &str = "ns-manager";
+/// DHCP servers and relays
+pub const DHCP: &str = "dhcp";
+/// Periodic checks of the monitoring loop
+pub const RECONCILE: &str = "reconcile";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum LogLevel {
+    OFF,
+    ERROR,
+    WARN,
+    INFO,
+    DEBUG,
+    TRACE,
+}
+
+impl From<LogLevel> for LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::OFF => LevelFilter::Off,
+            LogLevel::ERROR => LevelFilter::Error,
+            LogLevel::WARN => LevelFilter::Warn,
+            LogLevel::INFO => LevelFilter::Info,
+            LogLevel::DEBUG => LevelFilter::Debug,
+            LogLevel::TRACE => LevelFilter::Trace,
+        }
+    }
+}
+
+/// Overrides of the `RUST_LOG` levels, by target
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct LogSettings {
+    #[serde(default)]
+    pub levels: HashMap<String, LogLevel>,
+    /// Only one trace message out of `n` is written for the target
+    #[serde(default)]
+    pub trace_sampling: HashMap<String, u32>,
+}
+
+impl LogSettings {
+    fn validate(&self) -> FResult<()> {
+        match self.trace_sampling.iter().find(|(_, rate)| **rate == 0) {
+            Some((target, _)) => Err(FError::NetworkingError(format!(
+                "Invalid trace sampling 0 for {}",
+                target
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Max level to let through the `log` macros
+    fn max_level(&self, default: LevelFilter) -> LevelFilter {
+        self.levels
+            .values()
+            .map(|level| LevelFilter::from(*level))
+            .fold(default, std::cmp::max)
+    }
+}
+
+/// Value of the most specific key of `map` matching `target`
+fn lookup<T: Copy>(map: &HashMap<String, T>, target: &str) -> Option<T> {
+    map.iter()
+        .filter(|(key, _)| {
+            target == key.as_str()
+                || (target.starts_with(key.as_str()) && target[key.len()..].starts_with("::"))
+        })
+        .max_by_key(|(key, _)| key.len())
+        .map(|(_, value)| *value)
+}
+
+struct Logger {
+    /// Writes the messages, it lets everything through
+    writer: env_logger::Logger,
+    /// Levels from `RUST_LOG`
+    filter: env_logger::filter::Filter,
+    settings: RwLock<LogSettings>,
+    /// Trace messages seen, by sampled target
+    sampled: Mutex<HashMap<String, u64>>,
+}
+
+impl Logger {
+    fn sample(&self, target: &str, rate: u32) -> bool {
+        let mut sampled = match self.sampled.lock() {
+            Ok(sampled) => sampled,
+            Err(_) => return true,
+        };
+        let seen = sampled.entry(target.to_string()).or_insert(0);
+        let keep = *seen % u64::from(rate) == 0;
+        *seen += 1;
+        keep
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        let level = self
+            .settings
+            .read()
+            .ok()
+            .and_then(|s| lookup(&s.levels, metadata.target()));
+        match level {
+            Some(level) => metadata.level() <= LevelFilter::from(level),
+            None => self.filter.enabled(metadata),
+        }
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        if record.level() == Level::Trace {
+            let rate = self
+                .settings
+                .read()
+                .ok()
+                .and_then(|s| lookup(&s.trace_sampling, record.target()));
+            if let Some(rate) = rate {
+                if !self.sample(record.target(), rate) {
+                    return;
+                }
+            }
+        }
+        self.writer.log(record)
+    }
+
+    fn flush(&self) {
+        self.writer.flush()
+    }
+}
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+/// Installs the logger, `default_filter` is used when `RUST_LOG` is not set
+pub fn init(default_filter: &str) {
+    let filter = env_logger::filter::Builder::new()
+        .parse(
+            &std::env::var(env_logger::DEFAULT_FILTER_ENV)
+                .unwrap_or_else(|_| default_filter.to_string()),
+        )
+        .build();
+    let mut writer = env_logger::Builder::new();
+    writer.filter_level(LevelFilter::Trace);
+    if let Ok(style) = std::env::var(env_logger::DEFAULT_WRITE_STYLE_ENV) {
+        writer.parse_write_style(&style);
+    }
+    let logger = LOGGER.get_or_init(|| Logger {
+        writer: writer.build(),
+        filter,
+        settings: RwLock::new(LogSettings::default()),
+        sampled: Mutex::new(HashMap::new()),
+    });
+    if log::set_logger(logger).is_ok() {
+        log::set_max_level(logger.filter.filter());
+    }
+}
+
+fn logger() -> FResult<&'static Logger> {
+    LOGGER
+        .get()
+        .ok_or_else(|| FError::NetworkingError("Logger not initialized".to_string()))
+}
+
+pub fn settings() -> FResult<LogSettings> {
+    logger()?
+        .settings
+        .read()
+        .map(|s| s.clone())
+        .map_err(|e| FError::NetworkingError(format!("{}", e)))
+}
+
+/// Replaces the overrides of the `RUST_LOG` levels
+pub fn apply(settings: LogSettings) -> FResult<()> {
+    settings.validate()?;
+    update(|current| *current = settings)
+}
+
+/// Sets the level of a target, `None` goes back to `RUST_LOG`
+pub fn set_level(target: &str, level: Option<LogLevel>) -> FResult<()> {
+    update(|s| match level {
+        Some(level) => {
+            s.levels.insert(target.to_string(), level);
+        }
+        None => {
+            s.levels.remove(target);
+        }
+    })
+}
+
+/// Writes one trace message out of `rate` for a target, `None` writes them all
+pub fn set_trace_sampling(target: &str, rate: Option<u32>) -> FResult<()> {
+    let mut settings = settings()?;
+    match rate {
+        Some(rate) => settings.trace_sampling.insert(target.to_string(), rate),
+        None => settings.trace_sampling.remove(target),
+    };
+    apply(settings)
+}
+
+fn update<F: FnOnce(&mut LogSettings)>(f: F) -> FResult<()> {
+    let logger = logger()?;
+    let mut settings = logger
+        .settings
+        .write()
+        .map_err(|e| FError::NetworkingError(format!("{}", e)))?;
+    f(&mut settings);
+    log::set_max_level(settings.max_level(logger.filter.filter()));
+    if let Ok(mut sampled) = logger.sampled.lock() {
+        sampled.retain(|target, _| lookup(&settings.trace_sampling, target).is_some());
+    }
+    Ok(())
+}
@@ -14,20 +14,24 @@
 #![allow(clippy::too_many_arguments)]
 extern crate tera;
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
 use std::convert::From;
-use std::error::Error;
-use std::ffi::{self, CString};
+use std::future::Future;
 use std::os::unix::io::IntoRawFd;
-use std::process::{Child, Command, Stdio};
+use std::process::{Command, Stdio};
 use std::time::Duration;
 
 use async_std::prelude::*;
-use async_std::sync::{Arc, RwLock};
-use async_std::task;
+use async_std::sync::{Arc, Mutex, RwLock};
+
+use async_trait::async_trait;
+
+use serde::Serialize;
 
 use log::{error, info, trace};
 
+use tracing::Instrument;
+
 use znrpc_macros::znserver;
 use zrpc::ZNServe;
 
@@ -45,31 +49,179 @@ use fog05_sdk::types::{
 
 use uuid::Uuid;
 
+use futures::stream::StreamExt;
 use futures::stream::TryStreamExt;
 
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
 
+use chacha20poly1305::aead::{Aead, NewAead, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+
 use netlink_packet_route::rtnl::address::nlas::Nla;
+use netlink_packet_route::IFF_UP;
+use rtnetlink::constants::{RTMGRP_IPV4_IFADDR, RTMGRP_IPV6_IFADDR, RTMGRP_LINK};
+use rtnetlink::packet::rtnl::link::nlas::Info as LinkInfo;
+use rtnetlink::packet::rtnl::link::nlas::{
+    InfoBridge, InfoData as LinkInfoData, InfoKind as LinkInfoKind, Nla as LinkNla,
+    State as LinkState,
+};
+use rtnetlink::packet::{
+    LinkMessage, NetlinkMessage, NetlinkPayload, RtnlMessage, NLM_F_ACK, NLM_F_REQUEST,
+};
+use rtnetlink::sys::SocketAddr as NetlinkSocketAddr;
 use rtnetlink::Error as nlError;
 use rtnetlink::NetworkNamespace as NetlinkNetworkNamespace;
-use rtnetlink::{new_connection, Handle};
+use rtnetlink::{new_connection, Handle, VxlanAddRequest};
 
 use nix::sys::signal::{kill, Signal};
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
 use nix::unistd::Pid;
 
 use ipnetwork::IpNetwork;
 
-use nftnl::{nft_expr, nftnl_sys::libc, Batch, Chain, FinalizedBatch, ProtoFamily, Rule, Table};
-
-use tera::{Context, Result, Tera};
+#[cfg(feature = "dhcp")]
+use tera::Context;
 
+#[cfg(feature = "dhcp")]
+use crate::dhcp::render_template;
+use crate::dhcp::{
+    read_pid_file, spawn_dnsmasq, DHCPService, DNSMasqRange, DhcpBackend, DnsmasqBackend,
+    EmbeddedBackend, RelayBackend, SimulatedBackend,
+};
+use crate::dhcp_client::{self, DHCPLease};
+use crate::dns64;
+#[cfg(feature = "nat")]
+use crate::firewall::{FirewallBackend, IptablesBackend, NftablesBackend, SimulatedFirewall};
+use crate::ipam::{self, IPAMAddress, IPAMNodeRange, IPAMPool, IPAMSubnet, VNetIPAM};
+use crate::journal::{self, JournalEntry, JournalState};
+use crate::kernel::{KernelBackend, SimulatedKernelBackend};
+use crate::logging::{self, LogLevel, LogSettings};
+use crate::ns_channel::NamespaceManagerChannel;
+#[cfg(feature = "local-ns-channel")]
+use crate::ns_channel::UnixNamespaceManagerClient;
+use crate::runtime;
+use crate::simulation::{self, SimulatedKernel};
+use crate::sriov::{self, SRIOVPhysicalFunction, VFConfig, VFPool, VirtualFunction};
+use crate::sysctl::{self, MartianCounters, ReversePathFilter};
 use crate::types::{
-    deserialize_network_internals, serialize_network_internals, LinuxNetwork, LinuxNetworkConfig,
-    LinuxNetworkState, LinuxNetworkStateGuard, NamespaceManagerClient, VNetDHCP, VNetNetns,
-    VirtualNetworkInternals,
+    deserialize_network_internals, serialize_network_internals, AddressMapping, AsyncOperation,
+    AsyncOperationStatus, BridgePortConfig, ChurnOperation, ConnectionPointQoS,
+    ConnectionPointRateLimit, DHCPBackend, DHCPClient, DHCPClientBackend, DHCPReservation,
+    DHCPStaticRoute, DNSRecord, DataplanePort, DataplanePortKind, DefaultRoute,
+    DefaultRouteFailover, DefaultRouteFailoverStatus, EgressAction, EgressRule, ExternalAddress,
+    FDBOptions, FirewallBackendKind, FirewallChain, FirewallRule, FirewallState, FirewallTable,
+    FirewallTableKind, FloatingIP, GlobalInternalsSwap, IPTunnel, IPTunnelKind,
+    InterfaceAdminState, InterfaceEnd, InterfaceInspection, InterfaceStatistics, InterfaceStats,
+    L2TPv3Info, LinkStatsDump, LinuxNetwork, LinuxNetworkConfig, LinuxNetworkExtension,
+    LinuxNetworkExtensionClient, LinuxNetworkState, LinuxNetworkStateGuard,
+    LinuxNetworkStateSnapshot, NFTSet, NFTSetKind, NSManagerSpawnStats, NSManagerTransport,
+    NamespaceManagerClient, NetlinkRetryStats, NetworkDiff, NetworkFieldDiff, NetworkKernelDiff,
+    NodeReplica, OperationRecord, PinnedBridgeMAC, PluginCapabilities, PortForward,
+    PortForwardProtocol, RecordMetadata, RecordVersion, RemoteEndpoint, ReplicaInfo,
+    ReplicatedSecret, SNATOptions, SealedGlobalSwap, SealedReplica, SetRule, SetRuleAction,
+    SetRuleDirection, SharedNamespaceManagerClient, SharedServices, SpawnPermits, StateDump,
+    StaticAddress, VNetDHCP, VNetL2TP, VNetMember, VNetMirror, VNetNAT64, VNetNamespaceAttachment,
+    VNetNetns, VNetResolver, VNetServicesLink, VNetVRF, VNetWireGuard, VXLANOptions,
+    VXLANTransportUpdate, VirtualNetworkInternals, VirtualNetworkUpdate, WireGuardInfo,
 };
 
+/// Default number of namespace managers that can start concurrently
+const DEFAULT_NS_MANAGER_CONCURRENT_SPAWNS: usize = 4;
+/// Seconds to wait for a namespace manager to be ready
+const NS_MANAGER_STARTUP_TIMEOUT: u64 = 30;
+/// Seconds to wait for a namespace manager to answer the healthcheck
+const HEALTHCHECK_NS_MANAGER_TIMEOUT: u64 = 5;
+/// Metric of the default routes of a failover when `default_route_metric`
+/// is not set
+const DEFAULT_FAILOVER_ROUTE_METRIC: u32 = 100;
+/// Metric added to the standby route of a failover
+const FAILOVER_STANDBY_METRIC_OFFSET: u32 = 100;
+/// Missed probes before switching to the backup gateway
+const DEFAULT_FAILOVER_MAX_FAILURES: u32 = 3;
+/// Seconds to wait for the probe of a failover gateway, a probe
+/// not answered in time counts as missed
+const FAILOVER_PROBE_TIMEOUT: u64 = 3;
+/// Priority of the ingress tc filters of the mirrors, the other filters
+/// of a port are left alone when the mirrors change
+const MIRROR_FILTER_PREF: u16 = 49000;
+/// Appends to the journal after which it is compacted
+const JOURNAL_COMPACTION_WRITES: usize = 500;
+/// Directory under `run_path` with the records replicated by the peers
+const REPLICAS_DIR: &str = "replicas";
+/// Milliseconds to wait for a namespace manager to exit before SIGKILL
+const DEFAULT_NS_MANAGER_KILL_TIMEOUT_MS: u64 = 5000;
+const NS_MANAGER_KILL_POLL_MS: u64 = 50;
+/// Outer Ethernet, IP, UDP and VXLAN headers
+const VXLAN_IPV4_OVERHEAD: u32 = 50;
+const VXLAN_IPV6_OVERHEAD: u32 = 70;
+/// Tenant of the virtual networks whose descriptor does not name one
+const DEFAULT_TENANT: &str = "default";
+/// Writes of the global record of a network lost to other nodes
+/// before giving up
+const IPAM_UPDATE_RETRIES: usize = 5;
+/// Where the subnets allocated from the IPAM pools are published
+const IPAM_SUBNETS_PATH: &str = "/fos/global/networking/ipam";
+/// Where the local names of the virtual networks are published
+const DNS_RECORDS_PATH: &str = "/fos/global/networking/dns";
+/// Addresses at the beginning of a subnet left out of derived DHCP ranges
+const DEFAULT_DHCP_RESERVED_WINDOW: u32 = 10;
+/// Entries cached by the per network resolvers
+const DEFAULT_DNS_CACHE_SIZE: u32 = 1000;
+/// Port of the resolver of a network when the DNS64 proxy takes port 53
+const NAT64_RESOLVER_PORT: u16 = 5353;
+/// nftables table dropping the traffic forwarded between virtual networks
+#[cfg(feature = "nat")]
+const ISOLATION_TABLE: &str = "fos_isolation";
+/// nftables table of a services network namespace, the tenants can only
+/// receive answers through their links
+const SERVICES_TABLE: &str = "fos_services";
+/// Link-local block the /30 subnets of the services links come from
+const SERVICES_LINK_POOL: (std::net::Ipv4Addr, u8) =
+    (std::net::Ipv4Addr::new(169, 254, 128, 0), 17);
+/// Marker of the nftables tables created with random names
+const NFT_TABLE_PREFIX: &str = "fos_nat_";
+/// Maximum time a packet can wait in the connection point shaper
+const DEFAULT_QOS_LATENCY_MS: u32 = 50;
+/// Operations kept for the state dumps
+const DEFAULT_STATE_DUMP_OPERATIONS: usize = 100;
+/// Finished async operations kept when `async_operations_kept` is not set
+const DEFAULT_ASYNC_OPERATIONS_KEPT: usize = 100;
+
+/// Steps of the creation of a virtual network,
+/// reported by the operations of `create_virtual_network_async`
+#[derive(Debug, Clone, Copy)]
+enum CreateStep {
+    DESCRIPTOR = 1,
+    DEVICES,
+    NAMESPACE,
+    DHCP,
+    RECORD,
+}
+
+impl CreateStep {
+    const COUNT: u32 = CreateStep::RECORD as u32;
+
+    fn description(self) -> &'static str {
+        match self {
+            CreateStep::DESCRIPTOR => "Reading the descriptor",
+            CreateStep::DEVICES => "Creating the devices",
+            CreateStep::NAMESPACE => "Creating the namespace",
+            CreateStep::DHCP => "Starting DHCP",
+            CreateStep::RECORD => "Storing the record",
+        }
+    }
+}
+/// Per namespace configuration files bind mounted by `ip netns exec`
+const NETNS_ETC_PATH: &str = "/etc/netns";
+/// ELAN networks use an unicast full-mesh unless configured otherwise
+const DEFAULT_ELAN_MULTICAST: bool = false;
+
+/// Addresses reported in the top talkers of `get_conntrack_stats`
+const CONNTRACK_TOP_TALKERS: usize = 10;
+/// Age after which the move of a link no longer explains its deletion
+const LINK_MOVE_GRACE_MS: u64 = 10_000;
+
 #[znserver]
 impl NetworkingPlugin for LinuxNetwork {
     /// Creates the default fosbr0 virtual network
@@ -158,7 +310,7 @@ impl NetworkingPlugin for LinuxNetwork {
             default_vnet.ip_configuration = Some(ip_conf);
         }
 
-        let v_bridge = VirtualInterface {
+        let mut v_bridge = VirtualInterface {
             uuid: default_br_uuid,
             if_name: default_br_name.clone(),
             net_ns: None,
@@ -170,9 +322,9 @@ impl NetworkingPlugin for LinuxNetwork {
             phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
         };
 
-        let res = self.create_bridge(default_br_name.clone()).await?;
+        let res = self.kernel().create_bridge(default_br_name.clone()).await?;
         log::trace!("Bridge creation res: {:?}", res);
-        self.set_iface_up(default_br_name.clone()).await?;
+        self.kernel().set_iface_up(default_br_name.clone()).await?;
 
         let v_vxl = VirtualInterface {
             uuid: default_vxl_uuid,
@@ -195,82 +347,68 @@ impl NetworkingPlugin for LinuxNetwork {
         };
 
         let res = self
+            .kernel()
             .create_mcast_vxlan(
                 default_vxl_name.clone(),
                 dafault_ext_if_name.clone(),
                 default_vni,
                 default_mcast_addr,
                 default_port,
+                &self.get_vxlan_options(None),
             )
             .await?;
 
         log::trace!("VXLAN creation res: {:?}", res);
         // Setting master for VXLAN interface and setting interface up
-        self.set_iface_master(default_vxl_name.clone(), default_br_name.clone())
+        self.kernel()
+            .set_iface_master(default_vxl_name.clone(), default_br_name.clone())
             .await?;
-        self.set_iface_up(default_vxl_name).await?;
+        self.kernel().set_iface_up(default_vxl_name).await?;
 
         // Adding address to bridge interface
-        self.add_iface_address(
-            default_br_name.clone(),
-            IPAddress::V4(std::net::Ipv4Addr::new(10, 240, 0, 1)),
-            16,
-        )
-        .await?;
+        self.kernel()
+            .add_iface_address(
+                default_br_name.clone(),
+                IPAddress::V4(std::net::Ipv4Addr::new(10, 240, 0, 1)),
+                16,
+            )
+            .await?;
 
         // Creating dnsmasq config
-        let dhcp_internal = if dhcp {
-            let lease_file_path = self
-                .get_run_path()
-                .join("fosbr0.leases")
-                .to_str()
-                .ok_or(FError::EncodingError)?
-                .to_string();
-            let pid_file_path = self
-                .get_run_path()
-                .join("fosbr0.pid")
-                .to_str()
-                .ok_or(FError::EncodingError)?
-                .to_string();
-            let log_file_path = self
-                .get_run_path()
-                .join("fosbr0.log")
-                .to_str()
-                .ok_or(FError::EncodingError)?
-                .to_string();
-            let conf_file_path = self
+        if dhcp && !cfg!(feature = "dhcp") {
+            log::warn!(
+                target: logging::DHCP,
+                "DHCP requested but the plugin was built without the dhcp feature"
+            );
+        }
+        let default_range = DNSMasqRange {
+            start: IPAddress::V4(std::net::Ipv4Addr::new(10, 240, 0, 2)),
+            end: IPAddress::V4(std::net::Ipv4Addr::new(10, 240, 255, 254)),
+            prefix: 16,
+            gateway: IPAddress::V4(std::net::Ipv4Addr::new(10, 240, 0, 1)),
+            dns: Some(IPAddress::V4(std::net::Ipv4Addr::new(208, 67, 222, 222))),
+            v6: false,
+        };
+        let dhcp_internal = if dhcp && cfg!(feature = "dhcp") {
+            let files_prefix = self
                 .get_run_path()
-                .join("fosbr0.conf")
+                .join("fosbr0")
                 .to_str()
                 .ok_or(FError::EncodingError)?
                 .to_string();
-
-            let config = self
-                .create_dnsmasq_config(
-                    &default_br_name,
-                    &pid_file_path,
-                    &lease_file_path,
-                    &log_file_path,
-                    IPAddress::V4(std::net::Ipv4Addr::new(10, 240, 0, 2)),
-                    IPAddress::V4(std::net::Ipv4Addr::new(10, 240, 255, 254)),
-                    IPAddress::V4(std::net::Ipv4Addr::new(10, 240, 0, 1)),
-                    IPAddress::V4(std::net::Ipv4Addr::new(208, 67, 222, 222)),
-                )
-                .await?;
-            log::trace!("dnsmasq config: {}", config);
-            self.os
-                .as_ref()
-                .unwrap()
-                .store_file(config.into_bytes(), conf_file_path.clone())
-                .await??;
-            let child = self.spawn_dnsmasq(conf_file_path.clone()).await?;
-            log::debug!("DHCP Process running PID: {}", child.id());
-            Some(VNetDHCP {
-                leases_file: lease_file_path,
-                pid_file: pid_file_path,
-                conf: conf_file_path,
-                log_file: log_file_path,
-            })
+            let service = DHCPService {
+                vnet_uuid: Uuid::nil(),
+                ns_name: None,
+                iface: &default_br_name,
+                ranges: &[default_range],
+                files_prefix: &files_prefix,
+                internals: None,
+            };
+            Some(
+                self.dhcp_backend(self.get_dhcp_backend())
+                    .start(&service)
+                    .await?,
+            )
         } else {
             None
         };
@@ -306,11 +444,11 @@ impl NetworkingPlugin for LinuxNetwork {
         //     .await?;
         // log::trace!("VEth Pair creation res: {:?}", res);
 
-        // self.set_iface_master(default_veth_i_name.clone(), default_br_name.clone())
+        // self.kernel().set_iface_master(default_veth_i_name.clone(), default_br_name.clone())
         //     .await?;
-        // self.set_iface_up(default_veth_i_name).await?;
+        // self.kernel().set_iface_up(default_veth_i_name).await?;
 
-        // let res = self.add_netns(default_netns_name.clone()).await?;
+        // let res = self.kernel().add_netns(default_netns_name.clone()).await?;
         // log::trace!("Netns creation res: {:?}", res);
 
         // Here we spawn the manager for the just created Namespace and
@@ -331,7 +469,7 @@ impl NetworkingPlugin for LinuxNetwork {
         //     .insert(default_netns_uuid, (child.id(), ns_manager_client));
         // drop(guard);
 
-        // let res = self.set_iface_up(default_veth_e_name.clone()).await?;
+        // let res = self.kernel().set_iface_up(default_veth_e_name.clone()).await?;
         // log::trace!("veth ext face up res: {:?}", res);
         // let res = self
         //     .set_iface_ns(default_veth_e_name.clone(), default_netns_name.clone())
@@ -348,33 +486,38 @@ impl NetworkingPlugin for LinuxNetwork {
         // 		ip saddr 10.240.0.0/16 oif "eno0" masquerade # handle 4
         // 	}
         // }
-        let nat_table = self
-            .configure_nat(
+        #[cfg(feature = "nat")]
+        let associated_tables = vec![
+            self.configure_nat(
                 IpNetwork::V4(
                     ipnetwork::Ipv4Network::new(std::net::Ipv4Addr::new(10, 240, 0, 0), 16)
                         .map_err(|e| FError::NetworkingError(format!("{}", e)))?,
                 ),
                 &self.get_overlay_face_from_config().await?.if_name,
+                &SNATOptions::default(),
             )
-            .await?;
+            .await?,
+        ];
+        #[cfg(not(feature = "nat"))]
+        let associated_tables = Vec::new();
 
-        self.connector.local.add_interface(&v_bridge).await?;
+        self.pin_bridge_mac(&mut v_bridge).await?;
+        self.store_interface(&v_bridge).await?;
 
-        self.connector.local.add_interface(&v_vxl).await?;
+        self.store_interface(&v_vxl).await?;
 
         let internals = VirtualNetworkInternals {
             // associated_netns_name: default_netns_name,
-            associated_netns: None,
             dhcp: dhcp_internal,
-            associated_tables: vec![nat_table],
+            associated_tables,
+            vxlan_options: self.config.vxlan_options.clone(),
+            ..Default::default()
         };
 
         default_vnet.plugin_internals = Some(serialize_network_internals(&internals)?);
 
-        self.connector
-            .local
-            .add_virutal_network(&default_vnet)
-            .await?;
+        self.store_virtual_network(&default_vnet).await?;
+        let default_vnet = self.register_virtual_network_bridge(default_vnet).await?;
 
         log::debug!(
             "leaving create_default_virtual_network with res: {:?}",
@@ -432,38 +575,67 @@ impl NetworkingPlugin for LinuxNetwork {
     ///  +--------------------------------------+
     ///
     async fn create_virtual_network(&self, vnet_uuid: Uuid) -> FResult<VirtualNetwork> {
-        let node_uuid = self.agent.as_ref().unwrap().get_node_uuid().await??;
-        match self.connector.global.get_virtual_network(vnet_uuid).await {
-            Ok(mut vnet) => {
-                if let Ok(net) = self.connector.local.get_virtual_network(vnet_uuid).await {
-                    return Ok(net);
-                }
-                match vnet.clone().link_kind {
-                    LinkKind::L2(link_kind_info) => {
-                        //Multicast-based VxLAN
-                        let vnet = self.mcast_vxlan_create(vnet, link_kind_info).await?;
-                        self.connector.local.add_virutal_network(&vnet).await?;
-                        Ok(vnet)
-                    }
-                    LinkKind::ELINE(link_kind_info) => {
-                        //P2P-based VxLAN
-                        let vnet = self.ptp_vxlan_create(vnet, link_kind_info).await?;
-                        self.connector.local.add_virutal_network(&vnet).await?;
-                        Ok(vnet)
-                    }
-                    // Unimplemented for other virtual networks kinds
-                    _ => Err(FError::Unimplemented),
-                }
-            }
-            Err(FError::NotFound) => {
-                // a virtual network with this UUID does not exists
-                Err(FError::NotFound)
-            }
-            Err(err) => {
-                //any other error just return the error
-                Err(err)
-            }
-        }
+        traced(
+            tracing::info_span!("create_virtual_network", %vnet_uuid),
+            async move {
+                let node_uuid = self.agent.as_ref().unwrap().get_node_uuid().await??;
+                match self.connector.global.get_virtual_network(vnet_uuid).await {
+                    Ok(mut vnet) => {
+                        if let Ok(net) = self.connector.local.get_virtual_network(vnet_uuid).await {
+                            return Ok(net);
+                        }
+                        let slot = self
+                            .check_network_churn(&vnet, ChurnOperation::CREATE)
+                            .await?;
+                        let res = async {
+                            match vnet.clone().link_kind {
+                                LinkKind::L2(link_kind_info) => {
+                                    //Multicast-based VxLAN
+                                    let vnet = self
+                                        .mcast_vxlan_create(vnet, link_kind_info, false)
+                                        .await?;
+                                    self.operation_step(vnet_uuid, CreateStep::RECORD).await;
+                                    self.store_virtual_network(&vnet).await?;
+                                    self.register_virtual_network_bridge(vnet).await
+                                }
+                                LinkKind::ELINE(link_kind_info) => {
+                                    //P2P-based VxLAN
+                                    let overlay_iface = self.get_overlay_face_from_config().await?;
+                                    let local_addr =
+                                        *overlay_iface.addresses.first().ok_or(FError::NotFound)?;
+                                    let mut vnet = self
+                                        .ptp_vxlan_create(
+                                            vnet,
+                                            link_kind_info,
+                                            overlay_iface.if_name,
+                                            local_addr,
+                                        )
+                                        .await?;
+                                    self.negotiate_eline_endpoint(&mut vnet, local_addr).await?;
+                                    self.operation_step(vnet_uuid, CreateStep::RECORD).await;
+                                    self.store_virtual_network(&vnet).await?;
+                                    self.register_virtual_network_bridge(vnet).await
+                                }
+                                // Unimplemented for other virtual networks kinds
+                                _ => Err(FError::Unimplemented),
+                            }
+                        }
+                        .await;
+                        self.settle_network_churn(slot, &res).await;
+                        res
+                    }
+                    Err(FError::NotFound) => {
+                        // a virtual network with this UUID does not exists
+                        Err(FError::NotFound)
+                    }
+                    Err(err) => {
+                        //any other error just return the error
+                        Err(err)
+                    }
+                }
+            },
+        )
+        .await
     }
 
     async fn get_virtual_network(&self, vnet_uuid: Uuid) -> FResult<VirtualNetwork> {
@@ -472,318 +644,470 @@ impl NetworkingPlugin for LinuxNetwork {
     }
 
     async fn delete_virtual_network(&self, vnet_uuid: Uuid) -> FResult<VirtualNetwork> {
-        let node_uuid = self.agent.as_ref().unwrap().get_node_uuid().await??;
-        match self.connector.local.get_virtual_network(vnet_uuid).await {
-            Err(_) => Err(FError::NotFound),
-            Ok(vnet) => {
-                // if !vnet.interfaces.is_empty() {
-                //     return Err(FError::NetworkingError(
-                //         "Cannot remove virtual network that has attached interfaces".into(),
-                //     ));
-                // }
-                for i in &vnet.interfaces {
-                    log::info!(
-                        "Deleting virtual interface: {:?}",
-                        self.delete_virtual_interface(*i).await?
-                    );
-                }
-
-                if !vnet.connection_points.is_empty() {
-                    return Err(FError::NetworkingError(
-                        "Cannot remove virtual network that has attached connection points".into(),
-                    ));
-                }
+        traced(
+            tracing::info_span!("delete_virtual_network", %vnet_uuid),
+            async move {
+                let node_uuid = self.agent.as_ref().unwrap().get_node_uuid().await??;
+                match self.connector.local.get_virtual_network(vnet_uuid).await {
+                    Err(_) => Err(FError::NotFound),
+                    Ok(vnet) => {
+                        let slot = self
+                            .check_network_churn(&vnet, ChurnOperation::DELETE)
+                            .await?;
+                        let res = async {
+                            // if !vnet.interfaces.is_empty() {
+                            //     return Err(FError::NetworkingError(
+                            //         "Cannot remove virtual network that has attached interfaces".into(),
+                            //     ));
+                            // }
+                            if !vnet.connection_points.is_empty() {
+                                return Err(FError::NetworkingError(
+                                "Cannot remove virtual network that has attached connection points"
+                                    .into(),
+                            ));
+                            }
+                            self.ordered_teardown(&vnet.interfaces, false).await?;
+
+                            // the ports are gone, the rest of the cleanup goes on
+                            // after a failure so that the record is always removed
+                            let mut failed = Vec::new();
+                            let net_info = match vnet.plugin_internals {
+                                Some(ref pl_net_info) => {
+                                    match deserialize_network_internals(pl_net_info) {
+                                        Ok(net_info) => Some(net_info),
+                                        Err(e) => {
+                                            cleanup_step(&mut failed, "read the internals", Err(e));
+                                            None
+                                        }
+                                    }
+                                }
+                                None => None,
+                            };
+                            if let Some(net_info) = net_info {
+                                if let Some(dhcp_internal) = net_info.dhcp {
+                                    let res = self.stop_vnet_dhcp(vnet_uuid, dhcp_internal).await;
+                                    cleanup_step(&mut failed, "stop the DHCP server", res);
+                                }
+                                if let Some(resolver) = net_info.resolver {
+                                    let mut guard = self.state.write().await;
+                                    guard.resolvers.remove(&vnet_uuid);
+                                    guard.remote_dns_records.remove(&vnet_uuid);
+                                    drop(guard);
+                                    let res = self.stop_resolver(resolver).await;
+                                    cleanup_step(&mut failed, "stop the resolver", res);
+                                    if !net_info.dns_records.is_empty() {
+                                        self.publish_dns_records(vnet_uuid, &[]).await;
+                                    }
+                                }
+                                if let Some(nat64) = net_info.nat64_gateway {
+                                    let res = self.stop_vnet_nat64(vnet_uuid, nat64).await;
+                                    cleanup_step(&mut failed, "stop the NAT64 gateway", res);
+                                }
+                                if let Some(flow_export) = net_info.flow_export {
+                                    let res = self.stop_flow_export(&flow_export).await;
+                                    cleanup_step(&mut failed, "stop the flow export", res);
+                                }
+                                for mapping in &net_info.address_mappings {
+                                    let res = self
+                                        .remove_static_nat(
+                                            &mapping.table,
+                                            mapping.iface.clone(),
+                                            mapping.external_ip,
+                                        )
+                                        .await;
+                                    cleanup_step(&mut failed, "remove an address mapping", res);
+                                }
+                                #[cfg(feature = "nat")]
+                                for table in &net_info.associated_tables {
+                                    let res = self.clean_nat(table.clone()).await;
+                                    cleanup_step(&mut failed, "remove a NAT table", res);
+                                }
+                                if !net_info.egress_rules.is_empty() {
+                                    let res = self.delete_egress_rules(vnet_uuid);
+                                    cleanup_step(&mut failed, "remove the egress rules", res);
+                                }
+                                // flows of the network masqueraded before the teardown
+                                for (addr, prefix) in vnet
+                                    .ip_configuration
+                                    .iter()
+                                    .chain(net_info.secondary_ip_configuration.iter())
+                                    .filter_map(|c| c.subnet)
+                                {
+                                    let family = if addr.is_ipv6() { "ipv6" } else { "ipv4" };
+                                    flush_conntrack(&[
+                                        "-f".to_string(),
+                                        family.to_string(),
+                                        "-s".to_string(),
+                                        format!("{}/{}", addr, prefix),
+                                    ]);
+                                }
+                                for link in &net_info.services_links {
+                                    let res = self.remove_services_link(link).await;
+                                    cleanup_step(&mut failed, "remove a services link", res);
+                                }
+                                if let Some(ns_info) = net_info.associated_netns {
+                                    let res = self
+                                        .delete_network_namespace(ns_info.ns_uuid)
+                                        .await
+                                        .map(|_| ());
+                                    cleanup_step(&mut failed, "delete the namespace", res);
+                                }
+                                for attachment in &net_info.namespace_attachments {
+                                    let res = self.remove_namespace_attachment(attachment).await;
+                                    cleanup_step(&mut failed, "remove a namespace attachment", res);
+                                }
+                                if let Some(vrf_info) = net_info.vrf {
+                                    let res = self.kernel().del_iface(vrf_info.if_name).await;
+                                    cleanup_step(&mut failed, "delete the VRF", res);
+                                }
+                                if let Some(l2tp_info) = net_info.l2tp {
+                                    let res = self.delete_l2tp_session(&l2tp_info);
+                                    cleanup_step(&mut failed, "delete the L2TPv3 session", res);
+                                    self.state.write().await.l2tp_sessions.remove(&vnet_uuid);
+                                }
+                                if net_info.ipam.is_some() {
+                                    if let Err(e) = self.release_ipam_range(vnet_uuid).await {
+                                        log::warn!(
+                                            "Unable to release the IPAM range of {}: {}",
+                                            vnet_uuid,
+                                            e
+                                        );
+                                    }
+                                }
+                                if let Some(wg_info) = net_info.wireguard {
+                                    let res = self.kernel().del_iface(wg_info.if_name).await;
+                                    cleanup_step(
+                                        &mut failed,
+                                        "delete the WireGuard interface",
+                                        res,
+                                    );
+                                    let res = async_std::fs::remove_file(
+                                        async_std::path::Path::new(&wg_info.private_key_file),
+                                    )
+                                    .await
+                                    .map_err(FError::from);
+                                    cleanup_step(&mut failed, "remove the WireGuard key", res);
+                                }
+                            }
 
-                if let Some(ref pl_net_info) = vnet.plugin_internals {
-                    let net_info = deserialize_network_internals(pl_net_info)?;
-                    if let Some(ns_info) = net_info.associated_netns {
-                        self.delete_network_namespace(ns_info.ns_uuid).await?;
+                            let mut guard = self.state.write().await;
+                            guard.resolved_endpoints.remove(&vnet_uuid);
+                            let unicast = guard.unicast_vnets.remove(&vnet_uuid).is_some();
+                            guard.scheduled_rules.remove(&vnet_uuid);
+                            let isolated = guard.isolated_bridges.remove(&vnet_uuid).is_some();
+                            drop(guard);
+                            if unicast {
+                                if let Err(e) = self.leave_elan(vnet_uuid).await {
+                                    log::warn!("Unable to leave the ELAN {}: {}", vnet_uuid, e);
+                                }
+                            }
+                            if let LinkKind::ELINE(_) = vnet.link_kind {
+                                if let Err(e) = self.release_eline_end(vnet_uuid).await {
+                                    log::warn!(
+                                        "Unable to release the end of the ELINE {}: {}",
+                                        vnet_uuid,
+                                        e
+                                    );
+                                }
+                            }
+                            if isolated {
+                                let res = self.update_isolation().await;
+                                cleanup_step(&mut failed, "update the isolation", res);
+                            }
+                            self.delete_traffic_counters(vnet_uuid);
+
+                            self.remove_virtual_network_record(vnet_uuid).await?;
+                            self.record_versions.lock().await.remove(&vnet_uuid);
+                            if !failed.is_empty() {
+                                return Err(FError::NetworkingError(format!(
+                                    "{} removed, unable to {}",
+                                    vnet_uuid,
+                                    failed.join(", ")
+                                )));
+                            }
+                            Ok(vnet)
+                        }
+                        .await;
+                        self.settle_network_churn(slot, &res).await;
+                        res
                     }
                 }
-
-                self.connector
-                    .local
-                    .remove_virtual_network(vnet_uuid)
-                    .await?;
-                Ok(vnet)
-            }
-        }
+            },
+        )
+        .await
     }
 
+    /// Creates a connection point: a namespace with an internal bridge
+    /// connected through a veth pair, whose external end is used to bind
+    /// the connection point to a virtual network.
     async fn create_connection_point(&self) -> FResult<ConnectionPoint> {
-        Err(FError::Unimplemented)
-        // let node_uuid = self.agent.as_ref().unwrap().get_node_uuid().await??;
-        // let cp_uuid = Uuid::new_v4();
-        // match self
-        //     .connector
-        //     .local
-        //     .get_connection_point(cp_uuid)
-        //     .await
-        // {
-        //     Err(_) => {
-        //         let cp = ConnectionPoint {
-        //             uuid: cp_uuid,
-        //             net_ns: Uuid::new_v4(),
-        //             bridge: Uuid::new_v4(),
-        //             internal_veth: Uuid::new_v4(),
-        //             external_veth: Uuid::new_v4(),
-        //         };
-        //         self.connector
-        //             .local
-        //             .add_connection_point(&cp)
-        //             .await?;
-        //         Ok(cp)
-        //     }
-        //     Ok(_) => Err(FError::AlreadyPresent),
-        // }
+        traced(tracing::info_span!("create_connection_point"), async move {
+            let node_uuid = self.agent.as_ref().unwrap().get_node_uuid().await??;
+            let mut netns = self.create_network_namespace().await?;
+            match self.build_connection_point(&mut netns).await {
+                Ok(cp) => Ok(cp),
+                Err(e) => {
+                    log::error!("Unable to create connection point: {}", e);
+                    self.ordered_teardown(&netns.interfaces, false).await?;
+                    self.delete_network_namespace(netns.uuid).await?;
+                    Err(e)
+                }
+            }
+        })
+        .await
     }
 
     async fn get_connection_point(&self, cp_uuid: Uuid) -> FResult<ConnectionPoint> {
-        Err(FError::Unimplemented)
-        // let node_uuid = self.agent.as_ref().unwrap().get_node_uuid().await??;
-        // self.connector
-        //     .local
-        //     .get_connection_point(cp_uuid)
-        //     .await
+        let node_uuid = self.agent.as_ref().unwrap().get_node_uuid().await??;
+        self.connector.local.get_connection_point(cp_uuid).await
     }
 
+    /// Removes the veth pair, the bridge and the namespace of a connection point
     async fn delete_connection_point(&self, cp_uuid: Uuid) -> FResult<Uuid> {
-        Err(FError::Unimplemented)
-        // let node_uuid = self.agent.as_ref().unwrap().get_node_uuid().await??;
-        // match self
-        //     .connector
-        //     .local
-        //     .get_connection_point(cp_uuid)
-        //     .await
-        // {
-        //     Err(_) => Err(FError::NotFound),
-        //     Ok(_) => {
-        //         self.connector
-        //             .local
-        //             .remove_connection_point(cp_uuid)
-        //             .await?;
-        //         Ok(cp_uuid)
-        //     }
-        // }
+        traced(
+            tracing::info_span!("delete_connection_point", %cp_uuid),
+            async move {
+                let node_uuid = self.agent.as_ref().unwrap().get_node_uuid().await??;
+                let cp = match self.connector.local.get_connection_point(cp_uuid).await {
+                    Err(_) => return Err(FError::NotFound),
+                    Ok(cp) => cp,
+                };
+                self.ordered_teardown(&[cp.external_veth, cp.internal_veth, cp.bridge], false)
+                    .await?;
+                self.delete_network_namespace(cp.net_ns).await?;
+                let mut guard = self.state.write().await;
+                guard.cp_qos.remove(&cp_uuid);
+                guard.cp_rate_limits.remove(&cp_uuid);
+                drop(guard);
+                self.remove_connection_point_record(cp_uuid).await?;
+                Ok(cp_uuid)
+            },
+        )
+        .await
     }
 
     async fn create_virtual_interface(
         &self,
         intf: VirtualInterfaceConfig,
     ) -> FResult<VirtualInterface> {
-        let node_uuid = self.agent.as_ref().unwrap().get_node_uuid().await??;
-        match intf.kind {
-            VirtualInterfaceConfigKind::VXLAN(conf) => {
-                let ext_face = self.get_overlay_face_from_config().await?;
-                let v_iface = VirtualInterface {
-                    uuid: Uuid::new_v4(),
-                    if_name: intf.if_name.clone(),
-                    net_ns: None,
-                    parent: None,
-                    kind: VirtualInterfaceKind::VXLAN(VXLANKind {
-                        vni: conf.vni,
-                        mcast_addr: conf.mcast_addr,
-                        port: conf.port,
-                        dev: ext_face.clone(),
-                    }),
-                    addresses: Vec::new(),
-                    phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
-                };
-
-                self.create_mcast_vxlan(
-                    intf.if_name,
-                    ext_face.if_name.clone(),
-                    conf.vni,
-                    conf.mcast_addr,
-                    conf.port,
-                )
-                .await?;
-
-                self.connector.local.add_interface(&v_iface).await?;
-                Ok(v_iface)
-            }
-            VirtualInterfaceConfigKind::BRIDGE => {
-                let v_iface = VirtualInterface {
-                    uuid: Uuid::new_v4(),
-                    if_name: intf.if_name.clone(),
-                    net_ns: None,
-                    parent: None,
-                    kind: VirtualInterfaceKind::BRIDGE(BridgeKind { childs: Vec::new() }),
-                    addresses: Vec::new(),
-                    phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
-                };
-
-                self.create_bridge(intf.if_name).await?;
-
-                self.connector.local.add_interface(&v_iface).await?;
-                Ok(v_iface)
-            }
-            VirtualInterfaceConfigKind::VETH => {
-                let external_face_name = self.generate_random_interface_name();
-                let internal_iface_uuid = Uuid::new_v4();
-                let external_iface_uuid = Uuid::new_v4();
-                let v_iface_internal = VirtualInterface {
-                    uuid: internal_iface_uuid,
-                    if_name: intf.if_name.clone(),
-                    net_ns: None,
-                    parent: None,
-                    kind: VirtualInterfaceKind::VETH(VETHKind {
-                        pair: external_iface_uuid,
-                        internal: true,
-                    }),
-                    addresses: Vec::new(),
-                    phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
-                };
-                let v_iface_external = VirtualInterface {
-                    uuid: external_iface_uuid,
-                    if_name: external_face_name.clone(),
-                    net_ns: None,
-                    parent: None,
-                    kind: VirtualInterfaceKind::VETH(VETHKind {
-                        pair: internal_iface_uuid,
-                        internal: false,
-                    }),
-                    addresses: Vec::new(),
-                    phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
-                };
-
-                self.create_veth(intf.if_name, external_face_name).await?;
+        traced(
+            tracing::info_span!("create_virtual_interface", if_name = %intf.if_name),
+            async move {
+                let node_uuid = self.agent.as_ref().unwrap().get_node_uuid().await??;
+                match intf.kind {
+                    VirtualInterfaceConfigKind::VXLAN(conf) => {
+                        let ext_face = self.get_overlay_face_from_config().await?;
+                        let v_iface = VirtualInterface {
+                            uuid: Uuid::new_v4(),
+                            if_name: intf.if_name.clone(),
+                            net_ns: None,
+                            parent: None,
+                            kind: VirtualInterfaceKind::VXLAN(VXLANKind {
+                                vni: conf.vni,
+                                mcast_addr: conf.mcast_addr,
+                                port: conf.port,
+                                dev: ext_face.clone(),
+                            }),
+                            addresses: Vec::new(),
+                            phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
+                        };
+
+                        self.kernel()
+                            .create_mcast_vxlan(
+                                intf.if_name,
+                                ext_face.if_name.clone(),
+                                conf.vni,
+                                conf.mcast_addr,
+                                conf.port,
+                                &self.get_vxlan_options(None),
+                            )
+                            .await?;
 
-                self.connector
-                    .local
-                    .add_interface(&v_iface_internal)
-                    .await?;
-                self.connector
-                    .local
-                    .add_interface(&v_iface_external)
-                    .await?;
-                Ok(v_iface_internal)
-            }
-            VirtualInterfaceConfigKind::VLAN(conf) => {
-                let ext_face = self.get_dataplane_from_config().await?;
-                let v_iface = VirtualInterface {
-                    uuid: Uuid::new_v4(),
-                    if_name: intf.if_name.clone(),
-                    net_ns: None,
-                    parent: None,
-                    kind: VirtualInterfaceKind::VLAN(VLANKind {
-                        tag: conf.tag,
-                        dev: ext_face.clone(),
-                    }),
-                    addresses: Vec::new(),
-                    phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
-                };
+                        self.store_interface(&v_iface).await?;
+                        Ok(v_iface)
+                    }
+                    VirtualInterfaceConfigKind::BRIDGE => {
+                        let v_iface = VirtualInterface {
+                            uuid: Uuid::new_v4(),
+                            if_name: intf.if_name.clone(),
+                            net_ns: None,
+                            parent: None,
+                            kind: VirtualInterfaceKind::BRIDGE(BridgeKind { childs: Vec::new() }),
+                            addresses: Vec::new(),
+                            phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
+                        };
+
+                        self.kernel().create_bridge(intf.if_name).await?;
+
+                        self.store_interface(&v_iface).await?;
+                        Ok(v_iface)
+                    }
+                    VirtualInterfaceConfigKind::VETH => {
+                        let external_face_name = self.generate_random_interface_name();
+                        let internal_iface_uuid = Uuid::new_v4();
+                        let external_iface_uuid = Uuid::new_v4();
+                        let v_iface_internal = VirtualInterface {
+                            uuid: internal_iface_uuid,
+                            if_name: intf.if_name.clone(),
+                            net_ns: None,
+                            parent: None,
+                            kind: VirtualInterfaceKind::VETH(VETHKind {
+                                pair: external_iface_uuid,
+                                internal: true,
+                            }),
+                            addresses: Vec::new(),
+                            phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
+                        };
+                        let v_iface_external = VirtualInterface {
+                            uuid: external_iface_uuid,
+                            if_name: external_face_name.clone(),
+                            net_ns: None,
+                            parent: None,
+                            kind: VirtualInterfaceKind::VETH(VETHKind {
+                                pair: internal_iface_uuid,
+                                internal: false,
+                            }),
+                            addresses: Vec::new(),
+                            phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
+                        };
+
+                        self.kernel()
+                            .create_veth(intf.if_name, external_face_name)
+                            .await?;
 
-                self.create_vlan(intf.if_name, ext_face.if_name, conf.tag)
-                    .await?;
+                        self.store_interface(&v_iface_internal).await?;
+                        self.store_interface(&v_iface_external).await?;
+                        Ok(v_iface_internal)
+                    }
+                    VirtualInterfaceConfigKind::VLAN(conf) => {
+                        let ext_face = self.get_dataplane_from_config().await?;
+                        let v_iface = VirtualInterface {
+                            uuid: Uuid::new_v4(),
+                            if_name: intf.if_name.clone(),
+                            net_ns: None,
+                            parent: None,
+                            kind: VirtualInterfaceKind::VLAN(VLANKind {
+                                tag: conf.tag,
+                                dev: ext_face.clone(),
+                            }),
+                            addresses: Vec::new(),
+                            phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
+                        };
+
+                        self.kernel()
+                            .create_vlan(intf.if_name, ext_face.if_name, conf.tag)
+                            .await?;
 
-                self.connector.local.add_interface(&v_iface).await?;
-                Ok(v_iface)
-            }
-            VirtualInterfaceConfigKind::MACVLAN => {
-                let v_iface = VirtualInterface {
-                    uuid: Uuid::new_v4(),
-                    if_name: intf.if_name,
-                    net_ns: None,
-                    parent: None,
-                    kind: VirtualInterfaceKind::MACVLAN(MACVLANKind {
-                        dev: self.get_dataplane_from_config().await?,
-                    }),
-                    addresses: Vec::new(),
-                    phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
-                };
-                Err(FError::Unimplemented)
-                // self.connector
-                //.local
-                //.add_interface(&v_iface)
-                //     .await?;
-                // Ok(v_iface)
-            }
-            VirtualInterfaceConfigKind::GRE(conf) => {
-                let v_iface = VirtualInterface {
-                    uuid: Uuid::new_v4(),
-                    if_name: intf.if_name,
-                    net_ns: None,
-                    parent: None,
-                    kind: VirtualInterfaceKind::GRE(GREKind {
-                        local_addr: conf.local_addr,
-                        remote_addr: conf.remote_addr,
-                        ttl: conf.ttl,
-                    }),
-                    addresses: Vec::new(),
-                    phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
-                };
-                Err(FError::Unimplemented)
-                // self.connector
-                //.local
-                //.add_interface(&v_iface)
-                //     .await?;
-                // Ok(v_iface)
-            }
-            VirtualInterfaceConfigKind::GRETAP(conf) => {
-                let v_iface = VirtualInterface {
-                    uuid: Uuid::new_v4(),
-                    if_name: intf.if_name,
-                    net_ns: None,
-                    parent: None,
-                    kind: VirtualInterfaceKind::GRETAP(GREKind {
-                        local_addr: conf.local_addr,
-                        remote_addr: conf.remote_addr,
-                        ttl: conf.ttl,
-                    }),
-                    addresses: Vec::new(),
-                    phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
-                };
-                Err(FError::Unimplemented)
-                // self.connector
-                //.local
-                //.add_interface(&v_iface)
-                //     .await?;
-                // Ok(v_iface)
-            }
-            VirtualInterfaceConfigKind::IP6GRE(conf) => {
-                let v_iface = VirtualInterface {
-                    uuid: Uuid::new_v4(),
-                    if_name: intf.if_name,
-                    net_ns: None,
-                    parent: None,
-                    kind: VirtualInterfaceKind::IP6GRE(GREKind {
-                        local_addr: conf.local_addr,
-                        remote_addr: conf.remote_addr,
-                        ttl: conf.ttl,
-                    }),
-                    addresses: Vec::new(),
-                    phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
-                };
-                Err(FError::Unimplemented)
-                // self.connector
-                //.local
-                //.add_interface(&v_iface)
-                //     .await?;
-                // Ok(v_iface)
-            }
-            VirtualInterfaceConfigKind::IP6GRETAP(conf) => {
-                let v_iface = VirtualInterface {
-                    uuid: Uuid::new_v4(),
-                    if_name: intf.if_name,
-                    net_ns: None,
-                    parent: None,
-                    kind: VirtualInterfaceKind::IP6GRETAP(GREKind {
-                        local_addr: conf.local_addr,
-                        remote_addr: conf.remote_addr,
-                        ttl: conf.ttl,
-                    }),
-                    addresses: Vec::new(),
-                    phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
-                };
-                Err(FError::Unimplemented)
-                // self.connector
-                //.local
-                //.add_interface(&v_iface)
-                //     .await?;
-                // Ok(v_iface)
-            }
-        }
+                        self.store_interface(&v_iface).await?;
+                        Ok(v_iface)
+                    }
+                    VirtualInterfaceConfigKind::MACVLAN => {
+                        let v_iface = VirtualInterface {
+                            uuid: Uuid::new_v4(),
+                            if_name: intf.if_name,
+                            net_ns: None,
+                            parent: None,
+                            kind: VirtualInterfaceKind::MACVLAN(MACVLANKind {
+                                dev: self.get_dataplane_from_config().await?,
+                            }),
+                            addresses: Vec::new(),
+                            phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
+                        };
+                        Err(FError::Unimplemented)
+                        // self.connector
+                        //.local
+                        //.add_interface(&v_iface)
+                        //     .await?;
+                        // Ok(v_iface)
+                    }
+                    VirtualInterfaceConfigKind::GRE(conf) => {
+                        let v_iface = VirtualInterface {
+                            uuid: Uuid::new_v4(),
+                            if_name: intf.if_name,
+                            net_ns: None,
+                            parent: None,
+                            kind: VirtualInterfaceKind::GRE(GREKind {
+                                local_addr: conf.local_addr,
+                                remote_addr: conf.remote_addr,
+                                ttl: conf.ttl,
+                            }),
+                            addresses: Vec::new(),
+                            phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
+                        };
+                        Err(FError::Unimplemented)
+                        // self.connector
+                        //.local
+                        //.add_interface(&v_iface)
+                        //     .await?;
+                        // Ok(v_iface)
+                    }
+                    VirtualInterfaceConfigKind::GRETAP(conf) => {
+                        let v_iface = VirtualInterface {
+                            uuid: Uuid::new_v4(),
+                            if_name: intf.if_name,
+                            net_ns: None,
+                            parent: None,
+                            kind: VirtualInterfaceKind::GRETAP(GREKind {
+                                local_addr: conf.local_addr,
+                                remote_addr: conf.remote_addr,
+                                ttl: conf.ttl,
+                            }),
+                            addresses: Vec::new(),
+                            phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
+                        };
+                        Err(FError::Unimplemented)
+                        // self.connector
+                        //.local
+                        //.add_interface(&v_iface)
+                        //     .await?;
+                        // Ok(v_iface)
+                    }
+                    VirtualInterfaceConfigKind::IP6GRE(conf) => {
+                        let v_iface = VirtualInterface {
+                            uuid: Uuid::new_v4(),
+                            if_name: intf.if_name,
+                            net_ns: None,
+                            parent: None,
+                            kind: VirtualInterfaceKind::IP6GRE(GREKind {
+                                local_addr: conf.local_addr,
+                                remote_addr: conf.remote_addr,
+                                ttl: conf.ttl,
+                            }),
+                            addresses: Vec::new(),
+                            phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
+                        };
+                        Err(FError::Unimplemented)
+                        // self.connector
+                        //.local
+                        //.add_interface(&v_iface)
+                        //     .await?;
+                        // Ok(v_iface)
+                    }
+                    VirtualInterfaceConfigKind::IP6GRETAP(conf) => {
+                        let v_iface = VirtualInterface {
+                            uuid: Uuid::new_v4(),
+                            if_name: intf.if_name,
+                            net_ns: None,
+                            parent: None,
+                            kind: VirtualInterfaceKind::IP6GRETAP(GREKind {
+                                local_addr: conf.local_addr,
+                                remote_addr: conf.remote_addr,
+                                ttl: conf.ttl,
+                            }),
+                            addresses: Vec::new(),
+                            phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
+                        };
+                        Err(FError::Unimplemented)
+                        // self.connector
+                        //.local
+                        //.add_interface(&v_iface)
+                        //     .await?;
+                        // Ok(v_iface)
+                    }
+                }
+            },
+        )
+        .await
     }
 
     async fn get_virtual_interface(&self, intf_uuid: Uuid) -> FResult<VirtualInterface> {
@@ -792,67 +1116,105 @@ impl NetworkingPlugin for LinuxNetwork {
     }
 
     async fn delete_virtual_interface(&self, intf_uuid: Uuid) -> FResult<VirtualInterface> {
-        log::trace!("delete_virtual_interface({})", intf_uuid);
-        let node_uuid = self.agent.as_ref().unwrap().get_node_uuid().await??;
-        match self.connector.local.get_interface(intf_uuid).await {
-            Err(e) => {
-                log::error!("Unable to find interface {}, error: {}", intf_uuid, e);
-                Err(FError::NotFound)
-            }
-            Ok(intf) => {
-                log::error!("Delete Interface: {:?}", intf);
-                match intf.net_ns {
-                    Some(ns_uuid) => {
-                        let netns = self.connector.local.get_network_namespace(ns_uuid).await?;
-                        let ns_manager = self.get_ns_manager(&ns_uuid).await?;
-                        let res = ns_manager.del_virtual_interface(intf.if_name.clone()).await;
-                        log::info!(
-                            "Result of del_virtual_interface({}) -> {:?}",
-                            intf.if_name.clone(),
-                            res
-                        );
-                        if let Err(e) = res? {
-                            log::warn!(
-                                "Got error {} from namespace manager when removing {}",
-                                e,
-                                intf.if_name
-                            );
-                            if let VirtualInterfaceKind::VETH(VETHKind { pair, internal }) =
-                                intf.kind
-                            {
-                                if let Err(e) = self.connector.local.get_interface(pair).await {
-                                    log::warn!("Other end of veth pair was already removed: {}", e);
-                                    return Ok(intf);
-                                }
-                                return Err(FError::NetworkingError(
+        traced(
+            tracing::info_span!("delete_virtual_interface", %intf_uuid),
+            async move {
+                let node_uuid = self.agent.as_ref().unwrap().get_node_uuid().await??;
+                match self.connector.local.get_interface(intf_uuid).await {
+                    Err(e) => {
+                        log::error!("Unable to find interface {}, error: {}", intf_uuid, e);
+                        Err(FError::NotFound)
+                    }
+                    Ok(intf) => {
+                        log::error!("Delete Interface: {:?}", intf);
+                        let mut guard = self.state.write().await;
+                        guard.pinned_bridge_macs.remove(&intf.uuid);
+                        guard.interface_states.remove(&intf.uuid);
+                        guard.interface_names.remove(&intf.uuid);
+                        guard.bridge_ports.remove(&intf.uuid);
+                        drop(guard);
+                        self.release_interface_lease(&intf).await;
+                        match intf.net_ns {
+                            Some(ns_uuid) => {
+                                let netns =
+                                    self.connector.local.get_network_namespace(ns_uuid).await?;
+                                let ns_manager = self.get_ns_manager(&ns_uuid).await?;
+                                let res =
+                                    ns_manager.del_virtual_interface(intf.if_name.clone()).await;
+                                log::info!(
+                                    "Result of del_virtual_interface({}) -> {:?}",
+                                    intf.if_name.clone(),
+                                    res
+                                );
+                                if let Err(e) = res? {
+                                    log::warn!(
+                                        "Got error {} from namespace manager when removing {}",
+                                        e,
+                                        intf.if_name
+                                    );
+                                    if let VirtualInterfaceKind::VETH(VETHKind { pair, internal }) =
+                                        intf.kind
+                                    {
+                                        if let Err(e) =
+                                            self.connector.local.get_interface(pair).await
+                                        {
+                                            log::warn!(
+                                                "Other end of veth pair was already removed: {}",
+                                                e
+                                            );
+                                            return Ok(intf);
+                                        }
+                                        return Err(FError::NetworkingError(
                                     "Veth peer not removed but interface not found in namespace"
                                         .to_string(),
                                 ));
+                                    }
+                                    return Err(e);
+                                }
+                                self.remove_interface_record(intf_uuid).await?;
+                                Ok(intf)
                             }
-                            return Err(e);
-                        }
-                        self.connector.local.remove_interface(intf_uuid).await?;
-                        Ok(intf)
-                    }
-                    None => {
-                        if let VirtualInterfaceKind::VETH(ref info) = intf.kind {
-                            if let Ok(pair) = self.connector.local.get_interface(info.pair).await {
-                                self.del_iface(intf.if_name.clone()).await;
-                                self.del_iface(pair.if_name.clone()).await;
-                                self.connector.local.remove_interface(info.pair).await?;
-                            } else {
-                                log::trace!("Peer was alredy removed...");
-                                self.del_iface(intf.if_name.clone()).await;
+                            None => {
+                                if let VirtualInterfaceKind::VETH(ref info) = intf.kind {
+                                    if let Ok(pair) =
+                                        self.connector.local.get_interface(info.pair).await
+                                    {
+                                        for if_name in [&intf.if_name, &pair.if_name] {
+                                            if let Err(e) =
+                                                self.kernel().del_iface(if_name.clone()).await
+                                            {
+                                                log::warn!(
+                                                    "Unable to remove interface {}: {}",
+                                                    if_name,
+                                                    e
+                                                );
+                                            }
+                                        }
+                                        self.remove_interface_record(info.pair).await?;
+                                    } else {
+                                        log::trace!("Peer was alredy removed...");
+                                        if let Err(e) =
+                                            self.kernel().del_iface(intf.if_name.clone()).await
+                                        {
+                                            log::warn!(
+                                                "Unable to remove interface {}: {}",
+                                                intf.if_name,
+                                                e
+                                            );
+                                        }
+                                    }
+                                } else {
+                                    self.kernel().del_iface(intf.if_name.clone()).await?;
+                                }
+                                self.remove_interface_record(intf_uuid).await?;
+                                Ok(intf)
                             }
-                        } else {
-                            self.del_iface(intf.if_name.clone()).await?;
                         }
-                        self.connector.local.remove_interface(intf_uuid).await?;
-                        Ok(intf)
                     }
                 }
-            }
-        }
+            },
+        )
+        .await
     }
 
     async fn create_virtual_bridge(&self, br_name: String) -> FResult<VirtualInterface> {
@@ -867,9 +1229,9 @@ impl NetworkingPlugin for LinuxNetwork {
             phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
         };
 
-        self.create_bridge(v_iface.if_name.clone()).await?;
+        self.kernel().create_bridge(v_iface.if_name.clone()).await?;
 
-        self.connector.local.add_interface(&v_iface).await?;
+        self.store_interface(&v_iface).await?;
         Ok(v_iface)
     }
 
@@ -895,13 +1257,13 @@ impl NetworkingPlugin for LinuxNetwork {
                     ns_manager
                         .del_virtual_interface(i.if_name.clone())
                         .await??;
-                    self.connector.local.remove_interface(br_uuid).await?;
+                    self.remove_interface_record(br_uuid).await?;
                     Ok(i)
                 }
                 None => match i.kind {
                     VirtualInterfaceKind::BRIDGE(_) => {
-                        self.del_iface(i.if_name.clone()).await?;
-                        self.connector.local.remove_interface(br_uuid).await?;
+                        self.kernel().del_iface(i.if_name.clone()).await?;
+                        self.remove_interface_record(br_uuid).await?;
                         Ok(i)
                     }
                     _ => Err(FError::WrongKind),
@@ -910,49 +1272,75 @@ impl NetworkingPlugin for LinuxNetwork {
         }
     }
 
+    /// Sets the IPv4 default route through the interface and, when the
+    /// interface has a global IPv6 address, the IPv6 one, both with the
+    /// `default_route_metric` of the configuration.
     async fn set_default_route_in_network_namespace(
         &self,
         ns_uuid: Uuid,
         intf_uuid: Uuid,
     ) -> FResult<()> {
         let node_uuid = self.agent.as_ref().unwrap().get_node_uuid().await??;
-        let mut netns = self.connector.local.get_network_namespace(ns_uuid).await?;
-        let iface = self.connector.local.get_interface(intf_uuid).await?;
-        match iface.net_ns {
-            None => Err(FError::NotConnected),
-            Some(nid) => {
-                if nid == netns.uuid {
-                    let ns_manager = self.get_ns_manager(&ns_uuid).await?;
-                    return ns_manager.set_default_route(iface.if_name.clone()).await?;
-                }
-                Err(FError::NotConnected)
-            }
+        let iface = self.get_namespace_interface(ns_uuid, intf_uuid).await?;
+        let ns_manager = self.get_ns_manager(&ns_uuid).await?;
+        let metric = self.config.default_route_metric;
+        let has_v6 = ns_manager
+            .get_virtual_interface_addresses(iface.if_name.clone())
+            .await??
+            .iter()
+            .any(|addr| match addr {
+                IPAddress::V6(addr) => (addr.segments()[0] & 0xffc0) != 0xfe80,
+                IPAddress::V4(_) => false,
+            });
+        let mut routes = vec![DefaultRoute {
+            version: IPVersion::IPV4,
+            gateway: None,
+            metric,
+        }];
+        if has_v6 {
+            routes.push(DefaultRoute {
+                version: IPVersion::IPV6,
+                gateway: None,
+                metric,
+            });
+        }
+        for route in routes {
+            ns_manager
+                .add_virtual_interface_default_route(iface.if_name.clone(), route)
+                .await??;
         }
+        Ok(())
     }
 
     async fn create_network_namespace(&self) -> FResult<NetworkNamespace> {
-        let node_uuid = self.agent.as_ref().unwrap().get_node_uuid().await??;
-        let ns_name = self.generate_random_netns_name();
-        let netns = NetworkNamespace {
-            uuid: Uuid::new_v4(),
-            ns_name: ns_name.clone(),
-            interfaces: Vec::new(),
-        };
-        self.add_netns(ns_name.clone()).await?;
+        traced(
+            tracing::info_span!("create_network_namespace"),
+            async move {
+                let node_uuid = self.agent.as_ref().unwrap().get_node_uuid().await??;
+                let ns_name = self.generate_random_netns_name();
+                let netns = NetworkNamespace {
+                    uuid: Uuid::new_v4(),
+                    ns_name: ns_name.clone(),
+                    interfaces: Vec::new(),
+                };
+                self.kernel().add_netns(ns_name.clone()).await?;
 
-        self.spawn_ns_manager(ns_name.clone(), netns.uuid).await?;
-        let ns_manager = self.get_ns_manager(&netns.uuid).await?;
+                self.spawn_ns_manager(ns_name.clone(), netns.uuid).await?;
+                let ns_manager = self.get_ns_manager(&netns.uuid).await?;
 
-        while !ns_manager.verify_server().await? {
-            task::sleep(Duration::from_micros((100))).await;
-        }
+                while !ns_manager.verify_server().await? {
+                    runtime::sleep(Duration::from_micros(100)).await;
+                }
 
-        ns_manager
-            .set_virtual_interface_up("lo".to_string())
-            .await??;
+                ns_manager
+                    .set_virtual_interface_up("lo".to_string())
+                    .await??;
 
-        self.connector.local.add_network_namespace(&netns).await?;
-        Ok(netns)
+                self.store_network_namespace(&netns).await?;
+                Ok(netns)
+            },
+        )
+        .await
     }
 
     async fn get_network_namespace(&self, ns_uuid: Uuid) -> FResult<NetworkNamespace> {
@@ -961,22 +1349,36 @@ impl NetworkingPlugin for LinuxNetwork {
     }
 
     async fn delete_network_namespace(&self, ns_uuid: Uuid) -> FResult<NetworkNamespace> {
-        let node_uuid = self.agent.as_ref().unwrap().get_node_uuid().await??;
-        match self.connector.local.get_network_namespace(ns_uuid).await {
-            Err(_) => Err(FError::NotFound),
-            Ok(netns) => {
-                self.del_netns(netns.ns_name.clone()).await?;
-                log::trace!("Taking guard to remove ns-manager");
-                self.kill_ns_manager(&netns.uuid).await?;
-                self.connector
-                    .local
-                    .remove_network_namespace(ns_uuid)
-                    .await?;
-                Ok(netns)
-            }
-        }
+        traced(
+            tracing::info_span!("delete_network_namespace", %ns_uuid),
+            async move {
+                let node_uuid = self.agent.as_ref().unwrap().get_node_uuid().await??;
+                match self.connector.local.get_network_namespace(ns_uuid).await {
+                    Err(_) => Err(FError::NotFound),
+                    Ok(netns) => {
+                        let mut guard = self.state.write().await;
+                        guard.route_failovers.remove(&ns_uuid);
+                        guard.rp_filters.remove(&ns_uuid);
+                        drop(guard);
+                        self.detach_networks_from_namespace(ns_uuid).await?;
+                        self.kernel().del_netns(netns.ns_name.clone()).await?;
+                        log::trace!(
+                            target: logging::NS_MANAGER,
+                            "Taking guard to remove ns-manager"
+                        );
+                        self.kill_ns_manager(&netns.uuid).await?;
+                        self.remove_network_namespace_record(ns_uuid).await?;
+                        Ok(netns)
+                    }
+                }
+            },
+        )
+        .await
     }
 
+    /// Moves the interface into the namespace of the connection point and
+    /// attaches it to its bridge, restricting the sources it can use
+    /// unless `anti_spoofing` is disabled.
     async fn bind_interface_to_connection_point(
         &self,
         intf_uuid: Uuid,
@@ -984,17 +1386,60 @@ impl NetworkingPlugin for LinuxNetwork {
     ) -> FResult<VirtualInterface> {
         let node_uuid = self.agent.as_ref().unwrap().get_node_uuid().await??;
         let cp = self.connector.local.get_connection_point(cp_uuid).await?;
-        let mut iface = self.connector.local.get_interface(intf_uuid).await?;
-
-        Err(FError::Unimplemented)
-        // iface.net_ns = Some(cp.net_ns);
-        // self.connector
-        //     .local
-        //     .add_interface(&iface)
-        //     .await?;
-        // Ok(iface)
+        let iface = self.connector.local.get_interface(intf_uuid).await?;
+        if iface.parent.is_some() {
+            return Err(FError::AlreadyPresent);
+        }
+        let previous_ns = iface.net_ns;
+        if previous_ns != Some(cp.net_ns) {
+            self.move_interface_info_namespace(intf_uuid, cp.net_ns)
+                .await?;
+        }
+        let iface = self
+            .attach_interface_to_bridge(intf_uuid, cp.bridge)
+            .await?;
+        if self.is_anti_spoofing_enabled() {
+            let res = async {
+                let netns = self
+                    .connector
+                    .local
+                    .get_network_namespace(cp.net_ns)
+                    .await?;
+                self.set_iface_anti_spoofing(&netns.ns_name, &iface)
+            }
+            .await;
+            if let Err(e) = res {
+                // an unfiltered interface must not stay bound
+                if let Err(e) = self.detach_interface_from_bridge(intf_uuid).await {
+                    log::warn!("Unable to detach {} after a failed bind: {}", intf_uuid, e);
+                }
+                if previous_ns != Some(cp.net_ns) {
+                    let moved = match previous_ns {
+                        Some(ns_uuid) => self
+                            .move_interface_info_namespace(intf_uuid, ns_uuid)
+                            .await
+                            .map(|_| ()),
+                        None => self
+                            .move_interface_into_default_namespace(intf_uuid)
+                            .await
+                            .map(|_| ()),
+                    };
+                    if let Err(e) = moved {
+                        log::warn!(
+                            "Unable to move back {} after a failed bind: {}",
+                            intf_uuid,
+                            e
+                        );
+                    }
+                }
+                return Err(e);
+            }
+        }
+        Ok(iface)
     }
 
+    /// Detaches the interface from the bridge of the connection point and
+    /// moves it back into the default namespace
     async fn unbind_interface_from_connection_point(
         &self,
         intf_uuid: Uuid,
@@ -1002,41 +1447,64 @@ impl NetworkingPlugin for LinuxNetwork {
     ) -> FResult<VirtualInterface> {
         let node_uuid = self.agent.as_ref().unwrap().get_node_uuid().await??;
         let cp = self.connector.local.get_connection_point(cp_uuid).await?;
-        let mut iface = self.connector.local.get_interface(intf_uuid).await?;
-
-        Err(FError::Unimplemented)
-
-        // match iface.net_ns {
-        //     Some(ns) => {
-        //         if ns == cp.net_ns {
-        //             iface.net_ns = None;
-        //             self.connector
-        //                 .loccal
-        //                 .add_interface(&iface)
-        //                 .await?;
-        //             return Ok(iface);
-        //         }
-        //         Err(FError::NotConnected)
-        //     }
-        //     None => Err(FError::NotConnected),
-        // }
+        let iface = self.connector.local.get_interface(intf_uuid).await?;
+        if iface.net_ns != Some(cp.net_ns) || iface.parent != Some(cp.bridge) {
+            return Err(FError::NotConnected);
+        }
+        if self.is_anti_spoofing_enabled() {
+            let netns = self
+                .connector
+                .local
+                .get_network_namespace(cp.net_ns)
+                .await?;
+            self.del_iface_anti_spoofing(&netns.ns_name, intf_uuid)?;
+        }
+        self.detach_interface_from_bridge(intf_uuid).await?;
+        self.move_interface_into_default_namespace(intf_uuid).await
     }
 
+    /// Attaches the external veth of the connection point to the bridge
+    /// of the virtual network, applying its QoS if any.
     async fn bind_connection_point_to_virtual_network(
         &self,
         cp_uuid: Uuid,
         vnet_uuid: Uuid,
     ) -> FResult<ConnectionPoint> {
-        let node_uuid = self.agent.as_ref().unwrap().get_node_uuid().await??;
-        let cp = self.connector.local.get_connection_point(cp_uuid).await?;
-        let mut vnet = self.connector.local.get_virtual_network(vnet_uuid).await?;
-        Err(FError::Unimplemented)
-        // vnet.connection_points.push(cp.uuid);
-        // self.connector
-        //     .local
-        //     .add_virutal_network(&vnet)
-        //     .await?;
-        // Ok(cp)
+        traced(
+            tracing::info_span!("bind_connection_point_to_virtual_network", %cp_uuid, %vnet_uuid),
+            async move {
+                let node_uuid = self.agent.as_ref().unwrap().get_node_uuid().await??;
+                let cp = self.connector.local.get_connection_point(cp_uuid).await?;
+                let mut vnet = self.connector.local.get_virtual_network(vnet_uuid).await?;
+                if vnet.connection_points.contains(&cp.uuid) {
+                    return Err(FError::AlreadyPresent);
+                }
+                let br_uuid = self.get_virtual_network_bridge(&vnet).await?;
+                let ext_veth = self
+                    .attach_interface_to_bridge(cp.external_veth, br_uuid)
+                    .await?;
+                let rate_limit = self
+                    .state
+                    .read()
+                    .await
+                    .cp_rate_limits
+                    .get(&cp.uuid)
+                    .cloned();
+                if let Some(rate_limit) = rate_limit {
+                    self.set_iface_rate_limit(cp.uuid, &ext_veth.if_name, &rate_limit)?;
+                }
+                if let Some(ref internals) = vnet.plugin_internals {
+                    let internals = deserialize_network_internals(internals)?;
+                    if !internals.mirrors.is_empty() {
+                        self.set_iface_mirrors(&ext_veth.if_name, &internals.mirrors)?;
+                    }
+                }
+                vnet.connection_points.push(cp.uuid);
+                self.store_virtual_network(&vnet).await?;
+                Ok(cp)
+            },
+        )
+        .await
     }
 
     async fn unbind_connection_point_from_virtual_network(
@@ -1044,21 +1512,57 @@ impl NetworkingPlugin for LinuxNetwork {
         cp_uuid: Uuid,
         vnet_uuid: Uuid,
     ) -> FResult<ConnectionPoint> {
-        let node_uuid = self.agent.as_ref().unwrap().get_node_uuid().await??;
-        let cp = self.connector.local.get_connection_point(cp_uuid).await?;
-        let mut vnet = self.connector.local.get_virtual_network(vnet_uuid).await?;
-        Err(FError::Unimplemented)
-        // match vnet.connection_points.iter().position(|&x| x == cp.uuid) {
-        //     Some(p) => {
-        //         vnet.connection_points.remove(p);
-        //         self.connector
-        //             .local
-        //             .add_virutal_network(&vnet)
-        //             .await?;
-        //         Ok(cp)
-        //     }
-        //     None => Err(FError::NotConnected),
-        // }
+        traced(
+            tracing::info_span!(
+                "unbind_connection_point_from_virtual_network",
+                %cp_uuid,
+                %vnet_uuid
+            ),
+            async move {
+                let node_uuid = self.agent.as_ref().unwrap().get_node_uuid().await??;
+                let cp = self.connector.local.get_connection_point(cp_uuid).await?;
+                let mut vnet = self.connector.local.get_virtual_network(vnet_uuid).await?;
+                match vnet.connection_points.iter().position(|&x| x == cp.uuid) {
+                    Some(p) => {
+                        let mut internals = match vnet.plugin_internals {
+                            Some(ref internals) => Some(deserialize_network_internals(internals)?),
+                            None => None,
+                        };
+                        // the floating IP is removed only once the detach succeeded,
+                        // so that a failed detach leaves it attached
+                        let ext_veth = self.detach_interface_from_bridge(cp.external_veth).await?;
+                        if let Some(ref mut internals) = internals {
+                            if let Some(pos) = internals
+                                .floating_ips
+                                .iter()
+                                .position(|f| f.cp_uuid == cp.uuid)
+                            {
+                                let floating_ip = internals.floating_ips.remove(pos);
+                                self.remove_floating_ip(&floating_ip).await?;
+                            }
+                            vnet.plugin_internals = Some(serialize_network_internals(internals)?);
+                        }
+                        if internals.map_or(false, |i| !i.mirrors.is_empty()) {
+                            self.set_iface_mirrors(&ext_veth.if_name, &[])?;
+                        }
+                        // the table may outlive the limits, eg. across a restart
+                        if let Err(e) = self.del_iface_rate_limit(cp.uuid) {
+                            log::trace!(
+                                target: logging::NFT,
+                                "No rate limits on {}: {}",
+                                cp.uuid,
+                                e
+                            );
+                        }
+                        vnet.connection_points.remove(p);
+                        self.store_virtual_network(&vnet).await?;
+                        Ok(cp)
+                    }
+                    None => Err(FError::NotConnected),
+                }
+            },
+        )
+        .await
     }
 
     async fn get_interface_addresses(&self, intf_uuid: Uuid) -> FResult<Vec<IPAddress>> {
@@ -1111,13 +1615,13 @@ impl NetworkingPlugin for LinuxNetwork {
                     ns_manager
                         .del_virtual_interface(i.if_name.clone())
                         .await??;
-                    self.connector.local.remove_interface(intf_uuid).await?;
+                    self.remove_interface_record(intf_uuid).await?;
                     Ok(i)
                 }
                 None => match i.kind {
                     VirtualInterfaceKind::MACVLAN(_) => {
-                        self.del_iface(i.if_name.clone()).await?;
-                        self.connector.local.remove_interface(intf_uuid).await?;
+                        self.kernel().del_iface(i.if_name.clone()).await?;
+                        self.remove_interface_record(intf_uuid).await?;
                         Ok(i)
                     }
                     _ => Err(FError::WrongKind),
@@ -1151,14 +1655,15 @@ impl NetworkingPlugin for LinuxNetwork {
                             .await??;
                         netns.interfaces.remove(p);
 
-                        self.set_iface_ns(iface.if_name.clone(), newns.ns_name.clone())
+                        self.kernel()
+                            .set_iface_ns(iface.if_name.clone(), newns.ns_name.clone())
                             .await?;
 
                         iface.net_ns = Some(newns.uuid);
                         newns.interfaces.push(iface.uuid);
 
-                        self.connector.local.add_interface(&iface).await?;
-                        self.connector.local.add_network_namespace(&netns).await?;
+                        self.store_interface(&iface).await?;
+                        self.store_network_namespace(&netns).await?;
                         Ok(iface)
                     }
                     None => Err(FError::NotConnected),
@@ -1167,14 +1672,15 @@ impl NetworkingPlugin for LinuxNetwork {
             None => {
                 let mut netns = self.connector.local.get_network_namespace(ns_uuid).await?;
 
-                self.set_iface_ns(iface.if_name.clone(), netns.ns_name.clone())
+                self.kernel()
+                    .set_iface_ns(iface.if_name.clone(), netns.ns_name.clone())
                     .await?;
 
                 iface.net_ns = Some(netns.uuid);
                 netns.interfaces.push(iface.uuid);
 
-                self.connector.local.add_interface(&iface).await?;
-                self.connector.local.add_network_namespace(&netns).await?;
+                self.store_interface(&iface).await?;
+                self.store_network_namespace(&netns).await?;
                 Ok(iface)
             }
         }
@@ -1198,11 +1704,11 @@ impl NetworkingPlugin for LinuxNetwork {
                     .move_virtual_interface_into_default_ns(iface.if_name.clone())
                     .await??;
                 iface.net_ns = None;
-                self.connector.local.add_interface(&iface).await?;
+                self.store_interface(&iface).await?;
                 match netns.interfaces.iter().position(|&x| x == iface.uuid) {
                     Some(p) => {
                         netns.interfaces.remove(p);
-                        self.connector.local.add_network_namespace(&netns).await?;
+                        self.store_network_namespace(&netns).await?;
                         Ok(iface)
                     }
                     None => Err(FError::NotConnected),
@@ -1227,14 +1733,15 @@ impl NetworkingPlugin for LinuxNetwork {
                     .set_virtual_interface_name(iface.if_name.clone(), intf_name.clone())
                     .await??;
                 iface.if_name = intf_name;
-                self.connector.local.add_interface(&iface).await?;
+                self.store_interface(&iface).await?;
                 Ok(iface)
             }
             None => {
-                self.set_iface_name(iface.if_name.clone(), intf_name.clone())
+                self.kernel()
+                    .set_iface_name(iface.if_name.clone(), intf_name.clone())
                     .await?;
                 iface.if_name = intf_name;
-                self.connector.local.add_interface(&iface).await?;
+                self.store_interface(&iface).await?;
                 Ok(iface)
             }
         }
@@ -1246,101 +1753,151 @@ impl NetworkingPlugin for LinuxNetwork {
         br_uuid: Uuid,
     ) -> FResult<VirtualInterface> {
         let node_uuid = self.agent.as_ref().unwrap().get_node_uuid().await??;
+        let bridge_lock = self.get_bridge_lock(br_uuid).await;
+        let _bridge_guard = bridge_lock.lock().await;
         let mut iface = self.connector.local.get_interface(intf_uuid).await?;
         let bridge = self.connector.local.get_interface(br_uuid).await?;
         match bridge.kind {
-            VirtualInterfaceKind::BRIDGE(mut info) => match (iface.net_ns, bridge.net_ns) {
-                (Some(ns_uuid), Some(_)) => {
-                    let netns = self.connector.local.get_network_namespace(ns_uuid).await?;
-                    let ns_manager = self.get_ns_manager(&ns_uuid).await?;
-                    ns_manager
-                        .set_virtual_interface_master(iface.if_name.clone(), bridge.if_name.clone())
-                        .await??;
-
-                    iface.parent = Some(bridge.uuid);
-                    info.childs.push(iface.uuid);
+            VirtualInterfaceKind::BRIDGE(mut info) => {
+                let master = match (iface.net_ns, bridge.net_ns) {
+                    (Some(ns_uuid), Some(_)) => {
+                        let netns = self.connector.local.get_network_namespace(ns_uuid).await?;
+                        let ns_manager = self.get_ns_manager(&ns_uuid).await?;
+                        ns_manager
+                            .set_virtual_interface_master(
+                                iface.if_name.clone(),
+                                bridge.if_name.clone(),
+                            )
+                            .await??;
+                        ns_manager
+                            .set_virtual_interface_up(iface.if_name.clone())
+                            .await??;
+                        ns_manager
+                            .get_virtual_interface_master(iface.if_name.clone())
+                            .await??
+                    }
+                    (Some(_), None) | (None, Some(_)) => {
+                        return Err(FError::NetworkingError(String::from(
+                            "Interface in different namespaces",
+                        )))
+                    }
+                    (None, None) => {
+                        self.kernel()
+                            .set_iface_master(iface.if_name.clone(), bridge.if_name.clone())
+                            .await?;
+                        self.kernel().set_iface_up(iface.if_name.clone()).await?;
+                        self.kernel()
+                            .get_iface_master(iface.if_name.clone())
+                            .await?
+                    }
+                };
 
-                    ns_manager
-                        .set_virtual_interface_up(iface.if_name.clone())
-                        .await??;
+                // the records are updated only if the kernel agrees
+                if master.as_ref() != Some(&bridge.if_name) {
+                    return Err(FError::NetworkingError(format!(
+                        "{} is not attached to {} (master {:?})",
+                        iface.if_name, bridge.if_name, master
+                    )));
+                }
 
-                    let mut new_bridge = self.connector.local.get_interface(br_uuid).await?;
-                    new_bridge.kind = VirtualInterfaceKind::BRIDGE(info);
-                    self.connector.local.add_interface(&iface).await?;
-                    self.connector.local.add_interface(&new_bridge).await?;
-                    Ok(iface)
+                // the STP settings of the port are lost when it leaves the bridge
+                let port_config = self
+                    .state
+                    .read()
+                    .await
+                    .bridge_ports
+                    .get(&iface.uuid)
+                    .cloned();
+                if let Some(config) = port_config {
+                    self.apply_bridge_port_config(&iface, &config).await?;
                 }
-                (Some(_), None) | (None, Some(_)) => Err(FError::NetworkingError(String::from(
-                    "Interface in different namespaces",
-                ))),
-                (None, None) => {
-                    self.set_iface_master(iface.if_name.clone(), bridge.if_name.clone())
-                        .await?;
 
-                    iface.parent = Some(bridge.uuid);
+                iface.parent = Some(bridge.uuid);
+                if !info.childs.contains(&iface.uuid) {
                     info.childs.push(iface.uuid);
-
-                    self.set_iface_up(iface.if_name.clone()).await?;
-
-                    let mut new_bridge = self.connector.local.get_interface(br_uuid).await?;
-                    new_bridge.kind = VirtualInterfaceKind::BRIDGE(info);
-                    self.connector.local.add_interface(&iface).await?;
-                    self.connector.local.add_interface(&new_bridge).await?;
-                    Ok(iface)
                 }
-            },
+
+                let mut new_bridge = self.connector.local.get_interface(br_uuid).await?;
+                new_bridge.kind = VirtualInterfaceKind::BRIDGE(info);
+                self.store_interface(&iface).await?;
+                self.store_interface(&new_bridge).await?;
+                Ok(iface)
+            }
             _ => Err(FError::WrongKind),
         }
     }
 
+    /// Detaches the interface from the bridge it is attached to in the kernel.
+    /// If the stored parent drifted from the kernel state the records are
+    /// fixed as part of the operation.
     async fn detach_interface_from_bridge(&self, intf_uuid: Uuid) -> FResult<VirtualInterface> {
         let node_uuid = self.agent.as_ref().unwrap().get_node_uuid().await??;
+        let stored_parent = self.connector.local.get_interface(intf_uuid).await?.parent;
+        let bridge_lock = match stored_parent {
+            Some(br_uuid) => Some(self.get_bridge_lock(br_uuid).await),
+            None => None,
+        };
+        let _bridge_guard = match bridge_lock {
+            Some(ref lock) => Some(lock.lock().await),
+            None => None,
+        };
+
+        // the interface may have been moved while waiting for the lock
         let mut iface = self.connector.local.get_interface(intf_uuid).await?;
-        match iface.parent {
-            None => Err(FError::NotConnected),
+        if iface.parent != stored_parent {
+            return Err(FError::NetworkingError(format!(
+                "{} was moved from bridge {:?} while detaching",
+                iface.if_name, stored_parent
+            )));
+        }
+        let bridge = match stored_parent {
             Some(br_uuid) => {
                 let bridge = self.connector.local.get_interface(br_uuid).await?;
                 match bridge.kind {
-                    VirtualInterfaceKind::BRIDGE(mut info) => match iface.net_ns {
-                        Some(ns_uuid) => {
-                            let ns_manager = self.get_ns_manager(&ns_uuid).await?;
+                    VirtualInterfaceKind::BRIDGE(_) => Some(bridge),
+                    _ => return Err(FError::WrongKind),
+                }
+            }
+            None => None,
+        };
 
-                            iface.parent = None;
+        let kernel_master = self.get_interface_master(&iface).await?;
+        let stored_master = bridge.as_ref().map(|b| b.if_name.clone());
+        match (&kernel_master, &stored_master) {
+            (None, None) => return Err(FError::NotConnected),
+            (Some(kernel), Some(stored)) if kernel == stored => (),
+            (Some(kernel), _) => log::warn!(
+                "Drift on {}: attached to {} but recorded parent is {:?}",
+                iface.if_name,
+                kernel,
+                stored_master
+            ),
+            (None, Some(stored)) => log::warn!(
+                "Drift on {}: recorded in {} but not attached to any bridge",
+                iface.if_name,
+                stored
+            ),
+        }
 
-                            match info.childs.iter().position(|&x| x == iface.uuid) {
-                                Some(p) => {
-                                    info.childs.remove(p);
-                                    let mut new_bridge =
-                                        self.connector.local.get_interface(br_uuid).await?;
-                                    ns_manager
-                                        .set_virtual_interface_nomaster(iface.if_name.clone())
-                                        .await??;
-                                    new_bridge.kind = VirtualInterfaceKind::BRIDGE(info);
-                                    self.connector.local.add_interface(&new_bridge).await?;
-                                    self.connector.local.add_interface(&iface).await?;
-                                    return Ok(iface);
-                                }
-                                None => return Err(FError::NotConnected),
-                            }
-                        }
-                        None => match info.childs.iter().position(|&x| x == iface.uuid) {
-                            Some(p) => {
-                                info.childs.remove(p);
-                                let mut new_bridge =
-                                    self.connector.local.get_interface(br_uuid).await?;
-                                self.del_iface_master(iface.if_name.clone()).await?;
-                                new_bridge.kind = VirtualInterfaceKind::BRIDGE(info);
-                                self.connector.local.add_interface(&new_bridge).await?;
-                                self.connector.local.add_interface(&iface).await?;
-                                return Ok(iface);
-                            }
-                            None => return Err(FError::NotConnected),
-                        },
-                    },
-                    _ => Err(FError::WrongKind),
-                }
+        if kernel_master.is_some() {
+            self.release_interface_master(&iface).await?;
+            if let Some(master) = self.get_interface_master(&iface).await? {
+                return Err(FError::NetworkingError(format!(
+                    "{} is still attached to {}",
+                    iface.if_name, master
+                )));
+            }
+        }
+
+        if let Some(mut bridge) = bridge {
+            if let VirtualInterfaceKind::BRIDGE(ref mut info) = bridge.kind {
+                info.childs.retain(|&x| x != iface.uuid);
             }
+            self.store_interface(&bridge).await?;
         }
+        iface.parent = None;
+        self.store_interface(&iface).await?;
+        Ok(iface)
 
         // match bridge.kind {
         //     VirtualInterfaceKind::BRIDGE(mut info) => match iface.parent {
@@ -1359,7 +1916,7 @@ impl NetworkingPlugin for LinuxNetwork {
         //                             .global
         //                             .get_node_interface(node_uuid, br_uuid)
         //                             .await?;
-        //                         self.del_iface_master(iface.if_name.clone()).await?;
+        //                         self.kernel().del_iface_master(iface.if_name.clone()).await?;
         //                         new_bridge.kind = VirtualInterfaceKind::BRIDGE(info);
         //                         self.connector
         //                             .global
@@ -1383,263 +1940,267 @@ impl NetworkingPlugin for LinuxNetwork {
         intf: VirtualInterfaceConfig,
         ns_uuid: Uuid,
     ) -> FResult<VirtualInterface> {
-        let node_uuid = self.agent.as_ref().unwrap().get_node_uuid().await??;
-        let mut netns = self.connector.local.get_network_namespace(ns_uuid).await?;
-        //Err(FError::Unimplemented)
-        match intf.kind {
-            VirtualInterfaceConfigKind::VXLAN(conf) => {
-                // let v_iface = VirtualInterface {
-                //     uuid: Uuid::new_v4(),
-                //     if_name: intf.if_name,
-                //     net_ns: Some(netns.uuid),
-                //     parent: None,
-                //     kind: VirtualInterfaceKind::VXLAN(VXLANKind {
-                //         vni: conf.vni,
-                //         mcast_addr: conf.mcast_addr,
-                //         port: conf.port,
-                //         dev: self.get_overlay_face_from_config().await?,
-                //     }),
-                //     addresses: Vec::new(),
-                //     phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
-                // };
-                // netns.interfaces.push(v_iface.uuid);
-                // self.connector
-                //     .local
-                //     .add_network_namespace(&netns)
-                //     .await?;
-                // self.connector
-                //     .local
-                //     .add_interface(&v_iface)
-                //     .await?;
-                // Ok(v_iface)
-                Err(FError::Unimplemented)
-            }
-            VirtualInterfaceConfigKind::BRIDGE => {
-                // let v_iface = VirtualInterface {
-                //     uuid: Uuid::new_v4(),
-                //     if_name: intf.if_name,
-                //     net_ns: Some(netns.uuid),
-                //     parent: None,
-                //     kind: VirtualInterfaceKind::BRIDGE(BridgeKind { childs: Vec::new() }),
-                //     addresses: Vec::new(),
-                //     phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
-                // };
-                // netns.interfaces.push(v_iface.uuid);
-                // self.connector
-                //     .local
-                //     .add_network_namespace(&netns)
-                //     .await?;
-                // self.connector
-                //     .local
-                //     .add_interface(&v_iface)
-                //     .await?;
-                // Ok(v_iface)
-                Err(FError::Unimplemented)
-            }
-            VirtualInterfaceConfigKind::VETH => {
-                let external_face_name = self.generate_random_interface_name();
-                let internal_iface_uuid = Uuid::new_v4();
-                let external_iface_uuid = Uuid::new_v4();
-                let v_iface_internal = VirtualInterface {
-                    uuid: internal_iface_uuid,
-                    if_name: intf.if_name,
-                    net_ns: Some(netns.uuid),
-                    parent: None,
-                    kind: VirtualInterfaceKind::VETH(VETHKind {
-                        pair: external_iface_uuid,
-                        internal: true,
-                    }),
-                    addresses: Vec::new(),
-                    phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
-                };
-                let v_iface_external = VirtualInterface {
-                    uuid: external_iface_uuid,
-                    if_name: external_face_name.clone(),
-                    net_ns: Some(netns.uuid),
-                    parent: None,
-                    kind: VirtualInterfaceKind::VETH(VETHKind {
-                        pair: internal_iface_uuid,
-                        internal: false,
-                    }),
-                    addresses: Vec::new(),
-                    phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
-                };
-                let ns_manager = self.get_ns_manager(&ns_uuid).await?;
+        traced(
+            tracing::info_span!(
+                "create_virtual_interface_in_namespace",
+                if_name = %intf.if_name,
+                %ns_uuid
+            ),
+            async move {
+                let node_uuid = self.agent.as_ref().unwrap().get_node_uuid().await??;
+                let mut netns = self.connector.local.get_network_namespace(ns_uuid).await?;
+                //Err(FError::Unimplemented)
+                match intf.kind {
+                    VirtualInterfaceConfigKind::VXLAN(conf) => {
+                        // let v_iface = VirtualInterface {
+                        //     uuid: Uuid::new_v4(),
+                        //     if_name: intf.if_name,
+                        //     net_ns: Some(netns.uuid),
+                        //     parent: None,
+                        //     kind: VirtualInterfaceKind::VXLAN(VXLANKind {
+                        //         vni: conf.vni,
+                        //         mcast_addr: conf.mcast_addr,
+                        //         port: conf.port,
+                        //         dev: self.get_overlay_face_from_config().await?,
+                        //     }),
+                        //     addresses: Vec::new(),
+                        //     phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
+                        // };
+                        // netns.interfaces.push(v_iface.uuid);
+                        // self.connector
+                        //     .local
+                        //     .add_network_namespace(&netns)
+                        //     .await?;
+                        // self.connector
+                        //     .local
+                        //     .add_interface(&v_iface)
+                        //     .await?;
+                        // Ok(v_iface)
+                        Err(FError::Unimplemented)
+                    }
+                    VirtualInterfaceConfigKind::BRIDGE => {
+                        // let v_iface = VirtualInterface {
+                        //     uuid: Uuid::new_v4(),
+                        //     if_name: intf.if_name,
+                        //     net_ns: Some(netns.uuid),
+                        //     parent: None,
+                        //     kind: VirtualInterfaceKind::BRIDGE(BridgeKind { childs: Vec::new() }),
+                        //     addresses: Vec::new(),
+                        //     phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
+                        // };
+                        // netns.interfaces.push(v_iface.uuid);
+                        // self.connector
+                        //     .local
+                        //     .add_network_namespace(&netns)
+                        //     .await?;
+                        // self.connector
+                        //     .local
+                        //     .add_interface(&v_iface)
+                        //     .await?;
+                        // Ok(v_iface)
+                        Err(FError::Unimplemented)
+                    }
+                    VirtualInterfaceConfigKind::VETH => {
+                        let external_face_name = self.generate_random_interface_name();
+                        let internal_iface_uuid = Uuid::new_v4();
+                        let external_iface_uuid = Uuid::new_v4();
+                        let v_iface_internal = VirtualInterface {
+                            uuid: internal_iface_uuid,
+                            if_name: intf.if_name,
+                            net_ns: Some(netns.uuid),
+                            parent: None,
+                            kind: VirtualInterfaceKind::VETH(VETHKind {
+                                pair: external_iface_uuid,
+                                internal: true,
+                            }),
+                            addresses: Vec::new(),
+                            phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
+                        };
+                        let v_iface_external = VirtualInterface {
+                            uuid: external_iface_uuid,
+                            if_name: external_face_name.clone(),
+                            net_ns: Some(netns.uuid),
+                            parent: None,
+                            kind: VirtualInterfaceKind::VETH(VETHKind {
+                                pair: internal_iface_uuid,
+                                internal: false,
+                            }),
+                            addresses: Vec::new(),
+                            phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
+                        };
+                        let ns_manager = self.get_ns_manager(&ns_uuid).await?;
 
-                ns_manager
-                    .add_virtual_interface_veth(
-                        v_iface_internal.if_name.clone(),
-                        external_face_name.clone(),
-                    )
-                    .await??;
+                        ns_manager
+                            .add_virtual_interface_veth(
+                                v_iface_internal.if_name.clone(),
+                                external_face_name.clone(),
+                            )
+                            .await??;
 
-                netns.interfaces.push(internal_iface_uuid);
-                netns.interfaces.push(external_iface_uuid);
-                self.connector.local.add_network_namespace(&netns).await?;
-                self.connector
-                    .local
-                    .add_interface(&v_iface_internal)
-                    .await?;
-                self.connector
-                    .local
-                    .add_interface(&v_iface_external)
-                    .await?;
-                Ok(v_iface_internal)
-            }
-            VirtualInterfaceConfigKind::VLAN(conf) => {
-                // let v_iface = VirtualInterface {
-                //     uuid: Uuid::new_v4(),
-                //     if_name: intf.if_name,
-                //     net_ns: Some(netns.uuid),
-                //     parent: None,
-                //     kind: VirtualInterfaceKind::VLAN(VLANKind {
-                //         tag: conf.tag,
-                //         dev: self.get_dataplane_from_config().await?,
-                //     }),
-                //     addresses: Vec::new(),
-                //     phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
-                // };
-                // netns.interfaces.push(v_iface.uuid);
-                // self.connector
-                //     .local
-                //     .add_network_namespace(&netns)
-                //     .await?;
-                // self.connector
-                //     .local
-                //     .add_interface(&v_iface)
-                //     .await?;
-                // Ok(v_iface)
-                Err(FError::Unimplemented)
-            }
-            VirtualInterfaceConfigKind::MACVLAN => {
-                // let v_iface = VirtualInterface {
-                //     uuid: Uuid::new_v4(),
-                //     if_name: intf.if_name,
-                //     net_ns: Some(netns.uuid),
-                //     parent: None,
-                //     kind: VirtualInterfaceKind::MACVLAN(MACVLANKind {
-                //         dev: self.get_dataplane_from_config().await?,
-                //     }),
-                //     addresses: Vec::new(),
-                //     phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
-                // };
-                // netns.interfaces.push(v_iface.uuid);
-                // self.connector
-                //     .local
-                //     .add_network_namespace(&netns)
-                //     .await?;
-                // self.connector
-                //     .local
-                //     .add_interface(&v_iface)
-                //     .await?;
-                // Ok(v_iface)
-                Err(FError::Unimplemented)
-            }
-            VirtualInterfaceConfigKind::GRE(conf) => {
-                Err(FError::Unimplemented)
-                // let v_iface = VirtualInterface {
-                //     uuid: Uuid::new_v4(),
-                //     if_name: intf.if_name,
-                //     net_ns: Some(netns.uuid),
-                //     parent: None,
-                //     kind: VirtualInterfaceKind::GRE(GREKind {
-                //         local_addr: conf.local_addr,
-                //         remote_addr: conf.remote_addr,
-                //         ttl: conf.ttl,
-                //     }),
-                //     addresses: Vec::new(),
-                //     phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
-                // };
-                // netns.interfaces.push(v_iface.uuid);
-                // self.connector
-                //     .local
-                //     .add_network_namespace(&netns)
-                //     .await?;
-                // self.connector
-                //     .local
-                //     .add_interface(&v_iface)
-                //     .await?;
-                // Ok(v_iface)
-            }
-            VirtualInterfaceConfigKind::GRETAP(conf) => {
-                Err(FError::Unimplemented)
-                // let v_iface = VirtualInterface {
-                //     uuid: Uuid::new_v4(),
-                //     if_name: intf.if_name,
-                //     net_ns: Some(netns.uuid),
-                //     parent: None,
-                //     kind: VirtualInterfaceKind::GRETAP(GREKind {
-                //         local_addr: conf.local_addr,
-                //         remote_addr: conf.remote_addr,
-                //         ttl: conf.ttl,
-                //     }),
-                //     addresses: Vec::new(),
-                //     phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
-                // };
-                // netns.interfaces.push(v_iface.uuid);
-                // self.connector
-                //     .local
-                //     .add_network_namespace(&netns)
-                //     .await?;
-                // self.connector
-                //     .local
-                //     .add_interface(&v_iface)
-                //     .await?;
-                // Ok(v_iface)
-            }
-            VirtualInterfaceConfigKind::IP6GRE(conf) => {
-                Err(FError::Unimplemented)
-                // let v_iface = VirtualInterface {
-                //     uuid: Uuid::new_v4(),
-                //     if_name: intf.if_name,
-                //     net_ns: Some(netns.uuid),
-                //     parent: None,
-                //     kind: VirtualInterfaceKind::IP6GRE(GREKind {
-                //         local_addr: conf.local_addr,
-                //         remote_addr: conf.remote_addr,
-                //         ttl: conf.ttl,
-                //     }),
-                //     addresses: Vec::new(),
-                //     phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
-                // };
-                // netns.interfaces.push(v_iface.uuid);
-                // self.connector
-                //     .local
-                //     .add_network_namespace(&netns)
-                //     .await?;
-                // self.connector
-                //     .local
-                //     .add_interface(&v_iface)
-                //     .await?;
-                // Ok(v_iface)
-            }
-            VirtualInterfaceConfigKind::IP6GRETAP(conf) => {
-                Err(FError::Unimplemented)
-                // let v_iface = VirtualInterface {
-                //     uuid: Uuid::new_v4(),
-                //     if_name: intf.if_name,
-                //     net_ns: Some(netns.uuid),
-                //     parent: None,
-                //     kind: VirtualInterfaceKind::IP6GRETAP(GREKind {
-                //         local_addr: conf.local_addr,
-                //         remote_addr: conf.remote_addr,
-                //         ttl: conf.ttl,
-                //     }),
-                //     addresses: Vec::new(),
-                //     phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
-                // };
-                // netns.interfaces.push(v_iface.uuid);
-                // self.connector
-                //     .local
-                //     .add_network_namespace(&netns)
-                //     .await?;
-                // self.connector
-                //     .local
-                //     .add_interface(&v_iface)
-                //     .await?;
-                // Ok(v_iface)
-            }
-        }
+                        netns.interfaces.push(internal_iface_uuid);
+                        netns.interfaces.push(external_iface_uuid);
+                        self.store_network_namespace(&netns).await?;
+                        self.store_interface(&v_iface_internal).await?;
+                        self.store_interface(&v_iface_external).await?;
+                        Ok(v_iface_internal)
+                    }
+                    VirtualInterfaceConfigKind::VLAN(conf) => {
+                        // let v_iface = VirtualInterface {
+                        //     uuid: Uuid::new_v4(),
+                        //     if_name: intf.if_name,
+                        //     net_ns: Some(netns.uuid),
+                        //     parent: None,
+                        //     kind: VirtualInterfaceKind::VLAN(VLANKind {
+                        //         tag: conf.tag,
+                        //         dev: self.get_dataplane_from_config().await?,
+                        //     }),
+                        //     addresses: Vec::new(),
+                        //     phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
+                        // };
+                        // netns.interfaces.push(v_iface.uuid);
+                        // self.connector
+                        //     .local
+                        //     .add_network_namespace(&netns)
+                        //     .await?;
+                        // self.connector
+                        //     .local
+                        //     .add_interface(&v_iface)
+                        //     .await?;
+                        // Ok(v_iface)
+                        Err(FError::Unimplemented)
+                    }
+                    VirtualInterfaceConfigKind::MACVLAN => {
+                        // let v_iface = VirtualInterface {
+                        //     uuid: Uuid::new_v4(),
+                        //     if_name: intf.if_name,
+                        //     net_ns: Some(netns.uuid),
+                        //     parent: None,
+                        //     kind: VirtualInterfaceKind::MACVLAN(MACVLANKind {
+                        //         dev: self.get_dataplane_from_config().await?,
+                        //     }),
+                        //     addresses: Vec::new(),
+                        //     phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
+                        // };
+                        // netns.interfaces.push(v_iface.uuid);
+                        // self.connector
+                        //     .local
+                        //     .add_network_namespace(&netns)
+                        //     .await?;
+                        // self.connector
+                        //     .local
+                        //     .add_interface(&v_iface)
+                        //     .await?;
+                        // Ok(v_iface)
+                        Err(FError::Unimplemented)
+                    }
+                    VirtualInterfaceConfigKind::GRE(conf) => {
+                        Err(FError::Unimplemented)
+                        // let v_iface = VirtualInterface {
+                        //     uuid: Uuid::new_v4(),
+                        //     if_name: intf.if_name,
+                        //     net_ns: Some(netns.uuid),
+                        //     parent: None,
+                        //     kind: VirtualInterfaceKind::GRE(GREKind {
+                        //         local_addr: conf.local_addr,
+                        //         remote_addr: conf.remote_addr,
+                        //         ttl: conf.ttl,
+                        //     }),
+                        //     addresses: Vec::new(),
+                        //     phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
+                        // };
+                        // netns.interfaces.push(v_iface.uuid);
+                        // self.connector
+                        //     .local
+                        //     .add_network_namespace(&netns)
+                        //     .await?;
+                        // self.connector
+                        //     .local
+                        //     .add_interface(&v_iface)
+                        //     .await?;
+                        // Ok(v_iface)
+                    }
+                    VirtualInterfaceConfigKind::GRETAP(conf) => {
+                        Err(FError::Unimplemented)
+                        // let v_iface = VirtualInterface {
+                        //     uuid: Uuid::new_v4(),
+                        //     if_name: intf.if_name,
+                        //     net_ns: Some(netns.uuid),
+                        //     parent: None,
+                        //     kind: VirtualInterfaceKind::GRETAP(GREKind {
+                        //         local_addr: conf.local_addr,
+                        //         remote_addr: conf.remote_addr,
+                        //         ttl: conf.ttl,
+                        //     }),
+                        //     addresses: Vec::new(),
+                        //     phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
+                        // };
+                        // netns.interfaces.push(v_iface.uuid);
+                        // self.connector
+                        //     .local
+                        //     .add_network_namespace(&netns)
+                        //     .await?;
+                        // self.connector
+                        //     .local
+                        //     .add_interface(&v_iface)
+                        //     .await?;
+                        // Ok(v_iface)
+                    }
+                    VirtualInterfaceConfigKind::IP6GRE(conf) => {
+                        Err(FError::Unimplemented)
+                        // let v_iface = VirtualInterface {
+                        //     uuid: Uuid::new_v4(),
+                        //     if_name: intf.if_name,
+                        //     net_ns: Some(netns.uuid),
+                        //     parent: None,
+                        //     kind: VirtualInterfaceKind::IP6GRE(GREKind {
+                        //         local_addr: conf.local_addr,
+                        //         remote_addr: conf.remote_addr,
+                        //         ttl: conf.ttl,
+                        //     }),
+                        //     addresses: Vec::new(),
+                        //     phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
+                        // };
+                        // netns.interfaces.push(v_iface.uuid);
+                        // self.connector
+                        //     .local
+                        //     .add_network_namespace(&netns)
+                        //     .await?;
+                        // self.connector
+                        //     .local
+                        //     .add_interface(&v_iface)
+                        //     .await?;
+                        // Ok(v_iface)
+                    }
+                    VirtualInterfaceConfigKind::IP6GRETAP(conf) => {
+                        Err(FError::Unimplemented)
+                        // let v_iface = VirtualInterface {
+                        //     uuid: Uuid::new_v4(),
+                        //     if_name: intf.if_name,
+                        //     net_ns: Some(netns.uuid),
+                        //     parent: None,
+                        //     kind: VirtualInterfaceKind::IP6GRETAP(GREKind {
+                        //         local_addr: conf.local_addr,
+                        //         remote_addr: conf.remote_addr,
+                        //         ttl: conf.ttl,
+                        //     }),
+                        //     addresses: Vec::new(),
+                        //     phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
+                        // };
+                        // netns.interfaces.push(v_iface.uuid);
+                        // self.connector
+                        //     .local
+                        //     .add_network_namespace(&netns)
+                        //     .await?;
+                        // self.connector
+                        //     .local
+                        //     .add_interface(&v_iface)
+                        //     .await?;
+                        // Ok(v_iface)
+                    }
+                }
+            },
+        )
+        .await
     }
 
     async fn delete_virtual_interface_in_namespace(
@@ -1647,34 +2208,41 @@ impl NetworkingPlugin for LinuxNetwork {
         intf_uuid: Uuid,
         ns_uuid: Uuid,
     ) -> FResult<VirtualInterface> {
-        let node_uuid = self.agent.as_ref().unwrap().get_node_uuid().await??;
-        let mut netns = self.connector.local.get_network_namespace(ns_uuid).await?;
-        let iface = self.connector.local.get_interface(intf_uuid).await?;
-        match iface.net_ns {
-            None => Err(FError::NotConnected),
-            Some(nid) => {
-                if nid == netns.uuid {
-                    let ns_manager = self.get_ns_manager(&ns_uuid).await?;
-                    ns_manager
-                        .del_virtual_interface(iface.if_name.clone())
-                        .await??;
+        traced(
+            tracing::info_span!("delete_virtual_interface_in_namespace", %intf_uuid, %ns_uuid),
+            async move {
+                let node_uuid = self.agent.as_ref().unwrap().get_node_uuid().await??;
+                let mut netns = self.connector.local.get_network_namespace(ns_uuid).await?;
+                let iface = self.connector.local.get_interface(intf_uuid).await?;
+                match iface.net_ns {
+                    None => Err(FError::NotConnected),
+                    Some(nid) => {
+                        if nid == netns.uuid {
+                            self.release_interface_lease(&iface).await;
+                            let ns_manager = self.get_ns_manager(&ns_uuid).await?;
+                            ns_manager
+                                .del_virtual_interface(iface.if_name.clone())
+                                .await??;
 
-                    match netns.interfaces.iter().position(|&x| x == iface.uuid) {
-                        Some(p) => {
-                            netns.interfaces.remove(p);
-                            if let VirtualInterfaceKind::VETH(ref info) = iface.kind {
-                                self.connector.local.remove_interface(info.pair).await?;
+                            match netns.interfaces.iter().position(|&x| x == iface.uuid) {
+                                Some(p) => {
+                                    netns.interfaces.remove(p);
+                                    if let VirtualInterfaceKind::VETH(ref info) = iface.kind {
+                                        self.remove_interface_record(info.pair).await?;
+                                    }
+                                    self.store_network_namespace(&netns).await?;
+                                    self.remove_interface_record(intf_uuid).await?;
+                                    return Ok(iface);
+                                }
+                                None => return Err(FError::NotConnected),
                             }
-                            self.connector.local.add_network_namespace(&netns).await?;
-                            self.connector.local.remove_interface(intf_uuid).await?;
-                            return Ok(iface);
                         }
-                        None => return Err(FError::NotConnected),
+                        Err(FError::NotConnected)
                     }
                 }
-                Err(FError::NotConnected)
-            }
-        }
+            },
+        )
+        .await
     }
 
     async fn assing_address_to_interface(
@@ -1689,35 +2257,49 @@ impl NetworkingPlugin for LinuxNetwork {
             Some(ns_uuid) => {
                 let netns = self.connector.local.get_network_namespace(ns_uuid).await?;
                 let ns_manager = self.get_ns_manager(&ns_uuid).await?;
-                let addresses = ns_manager
-                    .add_virtual_interface_address(iface.if_name.clone(), address)
-                    .await??;
+                let addresses = match address {
+                    Some(_) => {
+                        ns_manager
+                            .add_virtual_interface_address(iface.if_name.clone(), address)
+                            .await??
+                    }
+                    None => {
+                        self.start_dhcp_client(
+                            intf_uuid,
+                            iface.if_name.clone(),
+                            Some(netns.ns_name.clone()),
+                        )
+                        .await?;
+                        ns_manager
+                            .get_virtual_interface_addresses(iface.if_name.clone())
+                            .await??
+                    }
+                };
                 iface.addresses = addresses;
-                self.connector.local.add_interface(&iface).await?;
+                self.store_interface(&iface).await?;
+                self.refresh_iface_anti_spoofing(&iface).await?;
                 Ok(iface)
             }
             None => match address {
                 Some(address) => {
-                    self.add_iface_address(iface.if_name.clone(), address.ip(), address.prefix())
+                    self.kernel()
+                        .add_iface_address(iface.if_name.clone(), address.ip(), address.prefix())
                         .await?;
                     iface.addresses.push(address.ip());
-                    self.connector.local.add_interface(&iface).await?;
+                    self.store_interface(&iface).await?;
                     Ok(iface)
                 }
                 None => {
                     // If the address is None we spawn a DHCP client
                     // and then we the the address from netlink
-                    let mut child = Command::new("dhclient")
-                        .arg("-i")
-                        .arg(&iface.if_name.clone())
-                        .spawn()
-                        .map_err(|e| FError::NetworkingError(format!("{}", e)))?;
-                    child
-                        .wait()
-                        .map_err(|e| FError::NetworkingError(format!("{}", e)))?;
-                    let addresses = self.get_iface_addresses(iface.if_name.clone()).await?;
+                    self.start_dhcp_client(intf_uuid, iface.if_name.clone(), None)
+                        .await?;
+                    let addresses = self
+                        .kernel()
+                        .get_iface_addresses(iface.if_name.clone())
+                        .await?;
                     iface.addresses = addresses;
-                    self.connector.local.add_interface(&iface).await?;
+                    self.store_interface(&iface).await?;
                     Ok(iface)
                 }
             },
@@ -1739,18 +2321,21 @@ impl NetworkingPlugin for LinuxNetwork {
                     let addresses = ns_manager
                         .del_virtual_interface_address(iface.if_name.clone(), address)
                         .await??;
+                    remove_netns_resolv_conf(&netns.ns_name, intf_uuid)?;
                     iface.addresses.remove(p);
-                    self.connector.local.add_interface(&iface).await?;
+                    self.store_interface(&iface).await?;
+                    self.refresh_iface_anti_spoofing(&iface).await?;
                     Ok(iface)
                 }
                 None => Err(FError::NotConnected),
             },
             None => match iface.addresses.iter().position(|&x| x == address) {
                 Some(p) => {
-                    self.del_iface_address(iface.if_name.clone(), address)
+                    self.kernel()
+                        .del_iface_address(iface.if_name.clone(), address)
                         .await?;
                     iface.addresses.remove(p);
-                    self.connector.local.add_interface(&iface).await?;
+                    self.store_interface(&iface).await?;
                     Ok(iface)
                 }
                 None => Err(FError::NotConnected),
@@ -1777,825 +2362,12647 @@ impl NetworkingPlugin for LinuxNetwork {
                     .set_virtual_interface_mac(iface.if_name.clone(), vec_addr)
                     .await??;
                 iface.phy_address = address;
-                self.connector.local.add_interface(&iface).await?;
+                self.store_interface(&iface).await?;
                 Ok(iface)
             }
             None => {
-                self.set_iface_mac(iface.if_name.clone(), vec_addr).await?;
+                self.kernel()
+                    .set_iface_mac(iface.if_name.clone(), vec_addr)
+                    .await?;
                 iface.phy_address = address;
-                self.connector.local.add_interface(&iface).await?;
+                self.store_interface(&iface).await?;
                 Ok(iface)
             }
         }
     }
 }
 
-impl LinuxNetwork {
-    pub async fn new(
-        z: Arc<zenoh::net::Session>,
-        connector: Arc<fog05_sdk::zconnector::ZConnector>,
-        pid: u32,
-        config: LinuxNetworkConfig,
-    ) -> FResult<Self> {
-        // this will be removed once netlink merges the async-std support
-        let (connection, handle, _) = new_connection().unwrap();
-        async_std::task::spawn(connection);
-
-        let state = LinuxNetworkState {
-            uuid: None,
-            nl_handler: handle,
-            ns_managers: HashMap::new(),
-        };
+#[znserver]
+impl LinuxNetworkExtension for LinuxNetwork {
+    /// Creates the given ELINE virtual network using a WireGuard interface
+    /// as underlay for the VXLAN tunnel, the tunnel addresses and the
+    /// remote peer are taken from `wg_info`.
+    /// The public key generated for this node is stored in the plugin
+    /// internals of the virtual network.
+    async fn create_wireguard_virtual_network(
+        &self,
+        vnet_uuid: Uuid,
+        wg_info: WireGuardInfo,
+    ) -> FResult<VirtualNetwork> {
+        let vnet = self.connector.global.get_virtual_network(vnet_uuid).await?;
+        if let Ok(net) = self.connector.local.get_virtual_network(vnet_uuid).await {
+            return Ok(net);
+        }
+        let slot = self
+            .check_network_churn(&vnet, ChurnOperation::CREATE)
+            .await?;
+        let res = async {
+            match vnet.clone().link_kind {
+                LinkKind::ELINE(link_kind_info) => {
+                    let vnet = self
+                        .wireguard_vxlan_create(vnet, link_kind_info, wg_info)
+                        .await?;
+                    self.store_virtual_network(&vnet).await?;
+                    Ok(vnet)
+                }
+                _ => Err(FError::WrongKind),
+            }
+        }
+        .await;
+        self.settle_network_churn(slot, &res).await;
+        res
+    }
 
-        Ok(Self {
-            z,
-            connector,
-            pid,
-            agent: None,
-            os: None,
-            config,
-            state: Arc::new(RwLock::new(state)),
-        })
+    /// Creates the given ELINE virtual network pointing the tunnel to
+    /// `remote` instead of the address present in the virtual network
+    /// descriptor, hostnames are resolved before creating the tunnel.
+    async fn create_eline_virtual_network(
+        &self,
+        vnet_uuid: Uuid,
+        remote: RemoteEndpoint,
+    ) -> FResult<VirtualNetwork> {
+        let vnet = self.connector.global.get_virtual_network(vnet_uuid).await?;
+        if let Ok(net) = self.connector.local.get_virtual_network(vnet_uuid).await {
+            return Ok(net);
+        }
+        let slot = self
+            .check_network_churn(&vnet, ChurnOperation::CREATE)
+            .await?;
+        let res = async {
+            match vnet.clone().link_kind {
+                LinkKind::ELINE(mut link_kind_info) => {
+                    let remote_addr = self.resolve_remote_endpoint(&remote).await?;
+                    link_kind_info.remote_addr = remote_addr;
+                    let overlay_iface = self.get_overlay_face_from_config().await?;
+                    let local_addr = *overlay_iface.addresses.first().ok_or(FError::NotFound)?;
+                    let mut vnet = self
+                        .ptp_vxlan_create(vnet, link_kind_info, overlay_iface.if_name, local_addr)
+                        .await?;
+                    if let LinkKind::ELINE(ref mut info) = vnet.link_kind {
+                        info.remote_addr = remote_addr;
+                    }
+                    self.set_remote_endpoint(&mut vnet, remote).await?;
+                    self.store_virtual_network(&vnet).await?;
+                    Ok(vnet)
+                }
+                _ => Err(FError::WrongKind),
+            }
+        }
+        .await;
+        self.settle_network_churn(slot, &res).await;
+        res
     }
 
-    async fn run(&self, stop: async_std::channel::Receiver<()>) -> FResult<()> {
-        info!("LinuxNetwork main loop starting...");
+    /// Re-points the tunnel of an existing ELINE virtual network
+    /// to the given remote endpoint.
+    async fn update_eline_remote_endpoint(
+        &self,
+        vnet_uuid: Uuid,
+        remote: RemoteEndpoint,
+    ) -> FResult<VirtualNetwork> {
+        let mut vnet = self.connector.local.get_virtual_network(vnet_uuid).await?;
+        match vnet.link_kind {
+            LinkKind::ELINE(_) => {
+                let remote_addr = self.resolve_remote_endpoint(&remote).await?;
+                self.set_ptp_vxlan_remote(&mut vnet, remote_addr).await?;
+                self.set_remote_endpoint(&mut vnet, remote).await?;
+                self.store_virtual_network(&vnet).await?;
+                Ok(vnet)
+            }
+            _ => Err(FError::WrongKind),
+        }
+    }
 
-        //starting the Agent-Plugin Server
-        let hv_server = self
-            .clone()
-            .get_networking_plugin_server(self.z.clone(), None);
-        let (stopper, _h) = hv_server.connect().await?;
-        hv_server.initialize().await?;
-
-        let mut guard = self.state.write().await;
-        guard.uuid = Some(hv_server.instance_uuid());
-        drop(guard);
-
-        hv_server.register().await?;
-
-        let (shv, _hhv) = hv_server.start().await?;
-
-        let monitoring = async {
-            loop {
-                info!("Monitoring loop started");
-                task::sleep(Duration::from_secs(60)).await;
+    /// Creates the given L2 virtual network using unicast VXLAN,
+    /// for underlays that do not support multicast.
+    /// The node joins the members kept in the global record of the
+    /// network, the endpoints of the other members are added to the
+    /// VXLAN forwarding database and kept updated by the monitoring loop.
+    async fn create_unicast_vxlan_virtual_network(
+        &self,
+        vnet_uuid: Uuid,
+    ) -> FResult<VirtualNetwork> {
+        let vnet = self.connector.global.get_virtual_network(vnet_uuid).await?;
+        if let Ok(net) = self.connector.local.get_virtual_network(vnet_uuid).await {
+            return Ok(net);
+        }
+        let slot = self
+            .check_network_churn(&vnet, ChurnOperation::CREATE)
+            .await?;
+        let res = async {
+            match vnet.clone().link_kind {
+                LinkKind::L2(link_kind_info) => {
+                    let mut vnet = self.mcast_vxlan_create(vnet, link_kind_info, true).await?;
+                    let vxl_name = self.get_vxlan_iface_name(&vnet).await?;
+                    let mut internals = deserialize_network_internals(
+                        vnet.plugin_internals.as_ref().ok_or(FError::NotFound)?,
+                    )?;
+                    internals.unicast_peers = Some(Vec::new());
+                    vnet.plugin_internals = Some(serialize_network_internals(&internals)?);
+                    self.store_virtual_network(&vnet).await?;
+
+                    let mut guard = self.state.write().await;
+                    guard.unicast_vnets.insert(vnet_uuid, vxl_name);
+                    drop(guard);
+
+                    self.join_elan(vnet_uuid).await?;
+                    self.update_unicast_peers(vnet_uuid).await?;
+                    let vnet = self.connector.local.get_virtual_network(vnet_uuid).await?;
+                    let vnet = self.register_virtual_network_bridge(vnet).await?;
+                    Ok(vnet)
+                }
+                _ => Err(FError::WrongKind),
             }
-        };
-
-        self.agent
-            .clone()
-            .unwrap()
-            .register_plugin(hv_server.instance_uuid(), PluginKind::NETWORKING)
-            .await??;
-
-        match monitoring.race(stop.recv()).await {
-            Ok(_) => trace!("Monitoring ending correct"),
-            Err(e) => trace!("Monitoring ending got error: {}", e),
         }
-
-        self.agent
-            .clone()
-            .unwrap()
-            .unregister_plugin(hv_server.instance_uuid())
-            .await??;
-
-        hv_server.stop(shv).await?;
-        hv_server.unregister().await?;
-        hv_server.disconnect(stopper).await?;
-
-        info!("LinuxNetwork main loop exiting");
-        Ok(())
+        .await;
+        self.settle_network_churn(slot, &res).await;
+        res
     }
 
-    pub async fn start(
-        &mut self,
-    ) -> (
-        async_std::channel::Sender<()>,
-        async_std::task::JoinHandle<FResult<()>>,
-    ) {
-        let local_os = OSClient::find_local_servers(self.z.clone()).await.unwrap();
-        if local_os.is_empty() {
-            error!("Unable to find a local OS interface");
-            panic!("No OS Server");
+    /// Creates the given L2 virtual network as an ELAN, the nodes that
+    /// instantiated it are connected by a full-mesh of unicast VXLAN
+    /// tunnels, or by multicast VXLAN when `elan_multicast` is set.
+    /// The mesh follows the nodes joining and leaving the network.
+    async fn create_elan_virtual_network(&self, vnet_uuid: Uuid) -> FResult<VirtualNetwork> {
+        let vnet = self.connector.global.get_virtual_network(vnet_uuid).await?;
+        if let Ok(net) = self.connector.local.get_virtual_network(vnet_uuid).await {
+            return Ok(net);
         }
-
-        let local_agent = AgentPluginInterfaceClient::find_local_servers(self.z.clone())
-            .await
-            .unwrap();
-        if local_agent.is_empty() {
-            error!("Unable to find a local Agent interface");
-            panic!("No Agent Server");
+        match vnet.clone().link_kind {
+            LinkKind::L2(link_kind_info) => {
+                if !self.config.elan_multicast.unwrap_or(DEFAULT_ELAN_MULTICAST) {
+                    return self.create_unicast_vxlan_virtual_network(vnet_uuid).await;
+                }
+                let slot = self
+                    .check_network_churn(&vnet, ChurnOperation::CREATE)
+                    .await?;
+                let res = async {
+                    let vnet = self.mcast_vxlan_create(vnet, link_kind_info, false).await?;
+                    self.store_virtual_network(&vnet).await?;
+                    self.register_virtual_network_bridge(vnet).await
+                }
+                .await;
+                self.settle_network_churn(slot, &res).await;
+                res
+            }
+            _ => Err(FError::WrongKind),
         }
+    }
 
-        let os = OSClient::new(self.z.clone(), local_os[0]);
-        let agent = AgentPluginInterfaceClient::new(self.z.clone(), local_agent[0]);
-
-        self.agent = Some(agent);
-        self.os = Some(os);
-
-        // Starting main loop in a task
-        let (s, r) = async_std::channel::bounded::<()>(1);
-        let plugin = self.clone();
-        let h = async_std::task::spawn_blocking(move || {
-            async_std::task::block_on(async { plugin.run(r).await })
-        });
-        (s, h)
+    /// Returns the underlay address used by this node for the given
+    /// virtual network, used by the other nodes to discover VXLAN peers.
+    async fn get_vxlan_endpoint(&self, vnet_uuid: Uuid) -> FResult<IPAddress> {
+        self.connector.local.get_virtual_network(vnet_uuid).await?;
+        let overlay_iface = self.get_overlay_face_from_config().await?;
+        Ok(*overlay_iface.addresses.first().ok_or(FError::NotFound)?)
     }
 
-    pub async fn stop(&self, stop: async_std::channel::Sender<()>) -> FResult<()> {
-        log::debug!("Linux Network Stopping");
-        stop.send(()).await;
+    /// Called by the other end of an ELINE virtual network when it is
+    /// instantiated after this node, the tunnel is pointed to its address
+    /// and the VXLAN endpoint of this node is returned. The announcing
+    /// node has to own the other end in the global record of the network.
+    async fn announce_eline_endpoint(
+        &self,
+        vnet_uuid: Uuid,
+        node_uuid: Uuid,
+        remote_addr: IPAddress,
+    ) -> FResult<IPAddress> {
+        let mut vnet = self.connector.local.get_virtual_network(vnet_uuid).await?;
+        let current_addr = match vnet.link_kind {
+            LinkKind::ELINE(ref info) => info.remote_addr,
+            _ => return Err(FError::WrongKind),
+        };
+        let my_node = self.agent.as_ref().unwrap().get_node_uuid().await??;
+        let global = self.connector.global.get_virtual_network(vnet_uuid).await?;
+        let owner = VNetMember {
+            node_uuid,
+            endpoint: remote_addr,
+        };
+        if node_uuid == my_node
+            || !global_network_internals(&global)?
+                .eline_ends
+                .contains(&owner)
+        {
+            return Err(FError::NetworkingError(format!(
+                "{} at {} does not own the other end of {}",
+                node_uuid, remote_addr, vnet_uuid
+            )));
+        }
+        if current_addr != remote_addr {
+            log::info!(
+                "Remote end of {} announced at {} (was {})",
+                vnet_uuid,
+                remote_addr,
+                current_addr
+            );
+            self.set_ptp_vxlan_remote(&mut vnet, remote_addr).await?;
+            self.store_virtual_network(&vnet).await?;
+        }
+        self.get_vxlan_endpoint(vnet_uuid).await
+    }
 
-        let node_uuid = self.agent.as_ref().unwrap().get_node_uuid().await??;
-        let default_vnet = self
-            .connector
-            .local
-            .get_virtual_network(Uuid::nil())
+    /// Realizes the given virtual network as a VRF bound to `table`
+    /// instead of a namespace, interfaces are then added with `add_vrf_member`.
+    async fn create_vrf_virtual_network(
+        &self,
+        vnet_uuid: Uuid,
+        table: u32,
+    ) -> FResult<VirtualNetwork> {
+        let mut vnet = self.connector.global.get_virtual_network(vnet_uuid).await?;
+        if let Ok(net) = self.connector.local.get_virtual_network(vnet_uuid).await {
+            return Ok(net);
+        }
+        // 0 is not a table, 253-255 are the default, main and local tables
+        if table == 0 || (253..=255).contains(&table) {
+            return Err(FError::NetworkingError(format!(
+                "Routing table {} cannot be used by a VRF",
+                table
+            )));
+        }
+        let slot = self
+            .check_network_churn(&vnet, ChurnOperation::CREATE)
             .await?;
 
-        for iface_uuid in default_vnet.interfaces {
-            let iface = self.connector.local.get_interface(iface_uuid).await?;
-            match iface.net_ns {
-                None => {
-                    self.del_iface(iface.if_name.clone()).await?;
-                    self.connector.local.remove_interface(iface_uuid).await?;
+        let res = async {
+            let vrf_name = self.generate_random_interface_name();
+            self.create_vrf(&vrf_name, table)?;
+            let res = async {
+                self.kernel().set_iface_up(vrf_name.clone()).await?;
+                let internals = VirtualNetworkInternals {
+                    vrf: Some(VNetVRF {
+                        if_name: vrf_name.clone(),
+                        table,
+                        members: Vec::new(),
+                    }),
+                    ..Default::default()
+                };
+                vnet.plugin_internals = Some(serialize_network_internals(&internals)?);
+                self.store_virtual_network(&vnet).await
+            }
+            .await;
+            if let Err(e) = res {
+                log::error!("Unable to create VRF network {}: {}", vnet_uuid, e);
+                if let Err(e) = self.kernel().del_iface(vrf_name.clone()).await {
+                    log::warn!("Unable to remove {}: {}", vrf_name, e);
                 }
-                Some(_) => continue,
+                return Err(e);
             }
+            Ok(vnet)
         }
+        .await;
+        self.settle_network_churn(slot, &res).await;
+        res
+    }
 
-        if let Some(internals) = default_vnet.plugin_internals {
-            let internals = deserialize_network_internals(internals.as_slice())?;
-
-            // Removing namespace if present
-            if let Some(ns_internals) = internals.associated_netns {
-                self.connector
-                    .local
-                    .get_network_namespace(ns_internals.ns_uuid)
-                    .await?;
-
-                self.del_netns(ns_internals.ns_name).await?;
-
-                log::trace!("Taking guard to remove ns-manager");
-                self.kill_ns_manager(&ns_internals.ns_uuid).await?;
-                self.connector
-                    .local
-                    .remove_network_namespace(ns_internals.ns_uuid)
-                    .await?;
-            }
-
-            // Killing dhcp if present
-            if let Some(dhcp_internal) = internals.dhcp {
-                let str_pid = String::from_utf8(
-                    self.os
-                        .as_ref()
-                        .unwrap()
-                        .read_file(dhcp_internal.pid_file.clone())
-                        .await??,
-                )
-                .map_err(|e| FError::NetworkingError(format!("{}", e)))?;
-                let pid = str_pid
-                    .trim()
-                    .parse::<i32>()
-                    .map_err(|e| FError::NetworkingError(format!("{}", e)))?;
-
-                log::trace!("Killing dnsmasq {}", pid);
+    async fn add_vrf_member(&self, vnet_uuid: Uuid, iface: String) -> FResult<VirtualNetwork> {
+        let mut vnet = self.connector.local.get_virtual_network(vnet_uuid).await?;
+        let mut internals =
+            deserialize_network_internals(vnet.plugin_internals.as_ref().ok_or(FError::NotFound)?)?;
+        let vrf = internals.vrf.as_mut().ok_or(FError::WrongKind)?;
+        if vrf.members.contains(&iface) {
+            return Err(FError::AlreadyPresent);
+        }
+        self.kernel()
+            .set_iface_master(iface.clone(), vrf.if_name.clone())
+            .await?;
+        vrf.members.push(iface);
+        vnet.plugin_internals = Some(serialize_network_internals(&internals)?);
+        self.store_virtual_network(&vnet).await?;
+        Ok(vnet)
+    }
 
-                kill(Pid::from_raw(pid), Signal::SIGKILL)
-                    .map_err(|e| FError::NetworkingError(format!("{}", e)))?;
+    async fn remove_vrf_member(&self, vnet_uuid: Uuid, iface: String) -> FResult<VirtualNetwork> {
+        let mut vnet = self.connector.local.get_virtual_network(vnet_uuid).await?;
+        let mut internals =
+            deserialize_network_internals(vnet.plugin_internals.as_ref().ok_or(FError::NotFound)?)?;
+        let vrf = internals.vrf.as_mut().ok_or(FError::WrongKind)?;
+        let pos = vrf
+            .members
+            .iter()
+            .position(|m| *m == iface)
+            .ok_or(FError::NotFound)?;
+        self.kernel().del_iface_master(iface).await?;
+        vrf.members.remove(pos);
+        vnet.plugin_internals = Some(serialize_network_internals(&internals)?);
+        self.store_virtual_network(&vnet).await?;
+        Ok(vnet)
+    }
 
-                async_std::fs::remove_file(async_std::path::Path::new(&dhcp_internal.pid_file))
-                    .await?;
-                async_std::fs::remove_file(async_std::path::Path::new(&dhcp_internal.leases_file))
-                    .await?;
-                async_std::fs::remove_file(async_std::path::Path::new(&dhcp_internal.conf)).await?;
-                async_std::fs::remove_file(async_std::path::Path::new(&dhcp_internal.log_file))
-                    .await?;
-            }
+    /// Creates a virtual network terminating on an L2TPv3 pseudowire,
+    /// the session interface is bridged and the bridge is the network
+    /// interface FDUs are attached to.
+    async fn create_l2tpv3_virtual_network(
+        &self,
+        vnet_uuid: Uuid,
+        info: L2TPv3Info,
+    ) -> FResult<VirtualNetwork> {
+        let mut vnet = self.connector.global.get_virtual_network(vnet_uuid).await?;
+        if let Ok(net) = self.connector.local.get_virtual_network(vnet_uuid).await {
+            return Ok(net);
+        }
+        let slot = self
+            .check_network_churn(&vnet, ChurnOperation::CREATE)
+            .await?;
 
-            for table in internals.associated_tables {
-                self.clean_nat(table).await?;
+        let res = async {
+            let mut guard = self.state.write().await;
+            let tunnel_id = allocate_l2tp_id(
+                guard.l2tp_sessions.values().map(|s| s.tunnel_id),
+                info.tunnel_id,
+            )?;
+            let session_id = allocate_l2tp_id(
+                guard.l2tp_sessions.values().map(|s| s.session_id),
+                info.session_id,
+            )?;
+            let l2tp_info = VNetL2TP {
+                if_name: self.generate_random_interface_name(),
+                bridge: self.generate_random_interface_name(),
+                tunnel_id,
+                peer_tunnel_id: info.peer_tunnel_id,
+                session_id,
+                peer_session_id: info.peer_session_id,
+            };
+            // ids are reserved before creating the tunnel,
+            // so concurrent creations do not pick the same ones
+            guard.l2tp_sessions.insert(vnet_uuid, l2tp_info.clone());
+            drop(guard);
+
+            match self.create_l2tp_bridge(&mut vnet, &l2tp_info, &info).await {
+                Ok(_) => Ok(vnet),
+                Err(e) => {
+                    log::error!("Unable to create L2TPv3 network {}: {}", vnet_uuid, e);
+                    if let Err(e) = self.delete_l2tp_session(&l2tp_info) {
+                        log::warn!(
+                            "Unable to remove the L2TPv3 session of {}: {}",
+                            vnet_uuid,
+                            e
+                        );
+                    }
+                    match self.kernel().iface_exists(l2tp_info.bridge.clone()).await {
+                        Ok(true) => {
+                            if let Err(e) = self.kernel().del_iface(l2tp_info.bridge.clone()).await
+                            {
+                                log::warn!("Unable to remove {}: {}", l2tp_info.bridge, e);
+                            }
+                        }
+                        Ok(false) => (),
+                        Err(e) => log::warn!("Unable to look for {}: {}", l2tp_info.bridge, e),
+                    }
+                    self.state.write().await.l2tp_sessions.remove(&vnet_uuid);
+                    Err(e)
+                }
             }
         }
+        .await;
+        self.settle_network_churn(slot, &res).await;
+        res
+    }
 
-        self.connector
-            .local
-            .remove_virtual_network(Uuid::nil())
-            .await?;
+    async fn get_l2tpv3_session(&self, vnet_uuid: Uuid) -> FResult<VNetL2TP> {
+        let vnet = self.connector.local.get_virtual_network(vnet_uuid).await?;
+        let internals =
+            deserialize_network_internals(vnet.plugin_internals.as_ref().ok_or(FError::NotFound)?)?;
+        internals.l2tp.ok_or(FError::WrongKind)
+    }
 
-        // Here we should remove and kill all the others ns-managers and clean-up
+    /// Replaces the static routes pushed by the DHCP server of a virtual network
+    async fn set_dhcp_static_routes(
+        &self,
+        vnet_uuid: Uuid,
+        routes: Vec<DHCPStaticRoute>,
+    ) -> FResult<VirtualNetwork> {
+        let update = VirtualNetworkUpdate {
+            static_routes: Some(routes),
+            ..Default::default()
+        };
+        self.update_virtual_network(vnet_uuid, update).await
+    }
 
-        Ok(())
+    async fn get_dhcp_static_routes(&self, vnet_uuid: Uuid) -> FResult<Vec<DHCPStaticRoute>> {
+        let vnet = self.connector.local.get_virtual_network(vnet_uuid).await?;
+        let internals =
+            deserialize_network_internals(vnet.plugin_internals.as_ref().ok_or(FError::NotFound)?)?;
+        Ok(internals.static_routes)
     }
 
-    /// Spawns and insert a new Namespace Manager into the Plugin state
-    async fn spawn_ns_manager(&self, ns_name: String, ns_uuid: Uuid) -> FResult<()> {
-        let mut guard = self.state.write().await;
-        let child = Command::new("fos-net-linux-ns-manager")
-            .arg("--netns")
-            .arg(&ns_name)
-            .arg("--id")
-            .arg(format!("{}", ns_uuid))
-            .arg("--locator")
-            .arg(self.config.zfilelocator.clone())
-            .spawn()
-            .map_err(|e| FError::NetworkingError(format!("{}", e)))?;
-        let ns_manager_client = NamespaceManagerClient::new(self.z.clone(), ns_uuid);
-        guard
-            .ns_managers
-            .insert(ns_uuid, (child.id(), ns_manager_client));
-        drop(guard);
-        Ok(())
+    /// Adds or replaces a local name served by the resolver of a virtual network
+    async fn add_dns_record(&self, vnet_uuid: Uuid, record: DNSRecord) -> FResult<VirtualNetwork> {
+        validate_dns_name(&record.name)?;
+        let mut vnet = self.connector.local.get_virtual_network(vnet_uuid).await?;
+        let mut internals =
+            deserialize_network_internals(vnet.plugin_internals.as_ref().ok_or(FError::NotFound)?)?;
+        internals.dns_records.retain(|r| r.name != record.name);
+        internals.dns_records.push(record);
+        self.reload_resolver_records(vnet_uuid, &internals).await?;
+        vnet.plugin_internals = Some(serialize_network_internals(&internals)?);
+        self.store_virtual_network(&vnet).await?;
+        self.publish_dns_records(vnet_uuid, &internals.dns_records)
+            .await;
+        Ok(vnet)
     }
 
-    async fn get_ns_manager(&self, ns_uuid: &Uuid) -> FResult<NamespaceManagerClient> {
-        let mut guard = self.state.read().await;
-        let (_, ns_manager) = guard
-            .ns_managers
-            .get(ns_uuid)
-            .ok_or_else(|| FError::NetworkingError("Manager not found".to_string()))?;
-        Ok(ns_manager.clone())
+    async fn remove_dns_record(&self, vnet_uuid: Uuid, name: String) -> FResult<VirtualNetwork> {
+        let mut vnet = self.connector.local.get_virtual_network(vnet_uuid).await?;
+        let mut internals =
+            deserialize_network_internals(vnet.plugin_internals.as_ref().ok_or(FError::NotFound)?)?;
+        let pos = internals
+            .dns_records
+            .iter()
+            .position(|r| r.name == name)
+            .ok_or(FError::NotFound)?;
+        internals.dns_records.remove(pos);
+        self.reload_resolver_records(vnet_uuid, &internals).await?;
+        vnet.plugin_internals = Some(serialize_network_internals(&internals)?);
+        self.store_virtual_network(&vnet).await?;
+        self.publish_dns_records(vnet_uuid, &internals.dns_records)
+            .await;
+        Ok(vnet)
     }
 
-    async fn remove_ns_manager(&self, ns_uuid: &Uuid) -> FResult<(u32, NamespaceManagerClient)> {
-        let mut guard = self.state.write().await;
-        let (pid, ns_manager) = guard
-            .ns_managers
-            .remove(&ns_uuid)
-            .ok_or_else(|| FError::NetworkingError("Manager not found".to_string()))?;
-        Ok((pid, ns_manager))
+    async fn list_dns_records(&self, vnet_uuid: Uuid) -> FResult<Vec<DNSRecord>> {
+        let vnet = self.connector.local.get_virtual_network(vnet_uuid).await?;
+        let internals =
+            deserialize_network_internals(vnet.plugin_internals.as_ref().ok_or(FError::NotFound)?)?;
+        Ok(internals.dns_records)
     }
 
-    /// Removes and kills a Namespaces Manager
-    async fn kill_ns_manager(&self, ns_uuid: &Uuid) -> FResult<()> {
-        let (pid, ns_manager) = self.remove_ns_manager(ns_uuid).await?;
-        kill(Pid::from_raw(pid as i32), Signal::SIGTERM)
-            .map_err(|e| FError::NetworkingError(format!("{}", e)))?;
-        Ok(())
+    /// Registers the name of an FDU in the resolver of its virtual network,
+    /// so that the other FDUs of the network can resolve it. The name is
+    /// removed with `remove_dns_record`.
+    async fn register_dns_name(
+        &self,
+        vnet_uuid: Uuid,
+        name: String,
+        ip: IPAddress,
+    ) -> FResult<VirtualNetwork> {
+        let vnet = self.connector.local.get_virtual_network(vnet_uuid).await?;
+        let internals =
+            deserialize_network_internals(vnet.plugin_internals.as_ref().ok_or(FError::NotFound)?)?;
+        if internals.resolver.is_none() {
+            return Err(FError::NetworkingError(format!(
+                "Virtual network {} has no resolver, see dns_cache",
+                vnet_uuid
+            )));
+        }
+        let in_subnet = vnet
+            .ip_configuration
+            .iter()
+            .chain(internals.secondary_ip_configuration.iter())
+            .filter_map(|c| c.subnet)
+            .filter_map(|(addr, prefix)| IpNetwork::new(addr, prefix).ok())
+            .any(|subnet| subnet.contains(ip));
+        if !in_subnet {
+            return Err(FError::NetworkingError(format!(
+                "{} is not in the subnets of {}",
+                ip, vnet_uuid
+            )));
+        }
+        self.add_dns_record(vnet_uuid, DNSRecord { name, address: ip })
+            .await
     }
 
-    async fn mcast_vxlan_create(
+    /// Reserves an address to a MAC on the DHCP server of a virtual network,
+    /// a reservation for the same MAC is replaced.
+    async fn add_dhcp_reservation(
         &self,
-        mut vnet: VirtualNetwork,
-        vxlan_info: MCastVXLANInfo,
+        vnet_uuid: Uuid,
+        reservation: DHCPReservation,
     ) -> FResult<VirtualNetwork> {
-        let node_uuid = self.agent.as_ref().unwrap().get_node_uuid().await??;
+        log::trace!("add_dhcp_reservation {} {:?}", vnet_uuid, reservation);
+        let mut vnet = self.connector.local.get_virtual_network(vnet_uuid).await?;
+        let mut internals =
+            deserialize_network_internals(vnet.plugin_internals.as_ref().ok_or(FError::NotFound)?)?;
+        let conf = reservation_ip_configuration(&vnet, &internals, reservation.address);
+        validate_dhcp_reservation(&reservation, conf, &internals.dhcp_reservations)?;
+        internals
+            .dhcp_reservations
+            .retain(|r| r.mac != reservation.mac);
+        internals.dhcp_reservations.push(reservation);
+        self.reload_vnet_dhcp(vnet_uuid, &mut internals).await?;
+        vnet.plugin_internals = Some(serialize_network_internals(&internals)?);
+        self.store_virtual_network(&vnet).await?;
+        Ok(vnet)
+    }
 
-        // Generating Names
+    async fn remove_dhcp_reservation(
+        &self,
+        vnet_uuid: Uuid,
+        mac: MACAddress,
+    ) -> FResult<VirtualNetwork> {
+        log::trace!("remove_dhcp_reservation {} {}", vnet_uuid, mac);
+        let mut vnet = self.connector.local.get_virtual_network(vnet_uuid).await?;
+        let mut internals =
+            deserialize_network_internals(vnet.plugin_internals.as_ref().ok_or(FError::NotFound)?)?;
+        let pos = internals
+            .dhcp_reservations
+            .iter()
+            .position(|r| r.mac == mac)
+            .ok_or(FError::NotFound)?;
+        internals.dhcp_reservations.remove(pos);
+        self.reload_vnet_dhcp(vnet_uuid, &mut internals).await?;
+        vnet.plugin_internals = Some(serialize_network_internals(&internals)?);
+        self.store_virtual_network(&vnet).await?;
+        Ok(vnet)
+    }
 
-        let br_uuid = Uuid::new_v4();
-        let br_name = self.generate_random_interface_name();
+    async fn list_dhcp_reservations(&self, vnet_uuid: Uuid) -> FResult<Vec<DHCPReservation>> {
+        let vnet = self.connector.local.get_virtual_network(vnet_uuid).await?;
+        let internals =
+            deserialize_network_internals(vnet.plugin_internals.as_ref().ok_or(FError::NotFound)?)?;
+        Ok(internals.dhcp_reservations)
+    }
 
-        let vxl_uuid = Uuid::new_v4();
-        let vxl_name = self.generate_random_interface_name();
+    /// Gives an interface a fixed address of the subnet of a virtual
+    /// network, for FDUs that cannot use DHCP. The interface has to be
+    /// attached to the bridge of the network or be in one of its
+    /// namespaces. The address is taken outside the DHCP range, from IPAM
+    /// when the network uses it or from the static addresses of its
+    /// global record otherwise, and is reserved to the MAC of the
+    /// interface so that the DHCP server never hands it out.
+    async fn assign_static_address(
+        &self,
+        vnet_uuid: Uuid,
+        intf_uuid: Uuid,
+    ) -> FResult<VirtualInterface> {
+        log::trace!("assign_static_address {} {}", vnet_uuid, intf_uuid);
+        let mut vnet = self.connector.local.get_virtual_network(vnet_uuid).await?;
+        let mut internals =
+            deserialize_network_internals(vnet.plugin_internals.as_ref().ok_or(FError::NotFound)?)?;
+        let iface = self.connector.local.get_interface(intf_uuid).await?;
+        if !self.is_attached_to_vnet(&vnet, &internals, &iface).await {
+            return Err(FError::NetworkingError(format!(
+                "{} is not attached to the virtual network {}",
+                iface.if_name, vnet_uuid
+            )));
+        }
+        if internals
+            .static_addresses
+            .iter()
+            .any(|s| s.intf_uuid == intf_uuid)
+            || internals
+                .dhcp_reservations
+                .iter()
+                .any(|r| r.mac == iface.phy_address)
+        {
+            return Err(FError::AlreadyPresent);
+        }
+        let conf = vnet.ip_configuration.clone().ok_or(FError::NotFound)?;
+        let (net_addr, prefix) = conf.subnet.ok_or(FError::NotFound)?;
+        let address = match internals.ipam {
+            Some(_) => {
+                self.allocate_address(vnet_uuid, Some(intf_uuid.to_string()))
+                    .await?
+            }
+            None => {
+                let range = self.get_dnsmasq_range(&vnet, Some(&internals), &conf);
+                let gateway = conf.gateway.or_else(|| range.as_ref().map(|r| r.gateway));
+                let ranges: Vec<(IPAddress, IPAddress)> =
+                    range.iter().map(|r| (r.start, r.end)).collect();
+                let mut taken: Vec<IPAddress> = self
+                    .known_interfaces()
+                    .await
+                    .into_iter()
+                    .flat_map(|i| i.addresses)
+                    .collect();
+                taken.extend(internals.dhcp_reservations.iter().map(|r| r.address));
+                taken.extend(internals.static_addresses.iter().map(|s| s.address));
+                let mut address = None;
+                self.update_global_internals(vnet_uuid, |_, global| {
+                    let mut taken = taken.clone();
+                    taken.extend(global.static_addresses.iter().map(|s| s.address));
+                    let free =
+                        ipam::first_free_address((net_addr, prefix), gateway, &ranges, &taken)?;
+                    global.static_addresses.push(StaticAddress {
+                        intf_uuid,
+                        address: free,
+                    });
+                    address = Some(free);
+                    Ok(true)
+                })
+                .await?;
+                address.ok_or(FError::NotFound)?
+            }
+        };
+        let network = IpNetwork::new(address, prefix)
+            .map_err(|e| FError::NetworkingError(format!("{}", e)))?;
+        let iface = match self
+            .assing_address_to_interface(intf_uuid, Some(network))
+            .await
+        {
+            Ok(iface) => iface,
+            Err(e) => {
+                self.release_allocated_address(vnet_uuid, &internals, address)
+                    .await;
+                return Err(e);
+            }
+        };
+        if internals.dhcp.is_some() {
+            let reservation = DHCPReservation {
+                mac: iface.phy_address,
+                address,
+                hostname: None,
+            };
+            internals.dhcp_reservations.push(reservation.clone());
+            if let Err(e) = self.reload_vnet_dhcp(vnet_uuid, &mut internals).await {
+                internals.dhcp_reservations.retain(|r| *r != reservation);
+                if let Err(e) = self.remove_address_from_interface(intf_uuid, address).await {
+                    log::warn!("Unable to remove {} from {}: {}", address, iface.if_name, e);
+                }
+                self.release_allocated_address(vnet_uuid, &internals, address)
+                    .await;
+                return Err(e);
+            }
+        }
+        internals
+            .static_addresses
+            .push(StaticAddress { intf_uuid, address });
+        vnet.plugin_internals = Some(serialize_network_internals(&internals)?);
+        self.store_virtual_network(&vnet).await?;
+        log::debug!("Assigned {} to {} in {}", address, iface.if_name, vnet_uuid);
+        Ok(iface)
+    }
 
-        let internal_br_uuid = Uuid::new_v4();
-        let internal_br_name = self.generate_random_interface_name();
+    /// Removes the address given with `assign_static_address` from the
+    /// interface, it goes back to the free addresses of the network
+    async fn release_static_address(
+        &self,
+        vnet_uuid: Uuid,
+        intf_uuid: Uuid,
+    ) -> FResult<VirtualInterface> {
+        log::trace!("release_static_address {} {}", vnet_uuid, intf_uuid);
+        let mut vnet = self.connector.local.get_virtual_network(vnet_uuid).await?;
+        let mut internals =
+            deserialize_network_internals(vnet.plugin_internals.as_ref().ok_or(FError::NotFound)?)?;
+        let pos = internals
+            .static_addresses
+            .iter()
+            .position(|s| s.intf_uuid == intf_uuid)
+            .ok_or(FError::NotFound)?;
+        let assigned = internals.static_addresses.remove(pos);
+        let mut iface = self.connector.local.get_interface(intf_uuid).await?;
+        if iface.addresses.contains(&assigned.address) {
+            iface = self
+                .remove_address_from_interface(intf_uuid, assigned.address)
+                .await?;
+        }
+        let reservations = internals.dhcp_reservations.len();
+        internals
+            .dhcp_reservations
+            .retain(|r| r.mac != iface.phy_address || r.address != assigned.address);
+        if internals.dhcp_reservations.len() != reservations {
+            self.reload_vnet_dhcp(vnet_uuid, &mut internals).await?;
+        }
+        self.release_allocated_address(vnet_uuid, &internals, assigned.address)
+            .await;
+        vnet.plugin_internals = Some(serialize_network_internals(&internals)?);
+        self.store_virtual_network(&vnet).await?;
+        Ok(iface)
+    }
 
-        let internal_veth_uuid = Uuid::new_v4();
-        let internal_veth_name = self.generate_random_interface_name();
+    /// Updates NTP servers, boot parameters and static routes of a virtual
+    /// network, the DHCP options file is regenerated and the running
+    /// dnsmasq re-reads it on SIGHUP. The embedded DHCP server gets the
+    /// new options directly.
+    async fn update_virtual_network(
+        &self,
+        vnet_uuid: Uuid,
+        update: VirtualNetworkUpdate,
+    ) -> FResult<VirtualNetwork> {
+        let mut vnet = self.connector.local.get_virtual_network(vnet_uuid).await?;
+        let mut internals =
+            deserialize_network_internals(vnet.plugin_internals.as_ref().ok_or(FError::NotFound)?)?;
+        if let Some(ntp_servers) = update.ntp_servers {
+            internals.ntp_servers = ntp_servers;
+        }
+        if let Some(boot) = update.boot {
+            internals.boot = boot;
+        }
+        if let Some(static_routes) = update.static_routes {
+            internals.static_routes = static_routes;
+        }
+        if let Some(mtu) = update.mtu {
+            internals.dhcp_mtu = mtu;
+        }
+        if let Some(domain_name) = update.domain_name {
+            internals.domain_name = domain_name;
+        }
+        if let Some(dhcp_options) = update.dhcp_options {
+            internals.dhcp_options = dhcp_options;
+        }
+        if let Some(dns_upstream) = update.dns_upstream {
+            internals.dns_upstream = dns_upstream;
+            self.reload_resolver_upstream(&vnet, &internals).await?;
+        }
+        self.reload_vnet_dhcp(vnet_uuid, &mut internals).await?;
+        vnet.plugin_internals = Some(serialize_network_internals(&internals)?);
+        self.store_virtual_network(&vnet).await?;
+        Ok(vnet)
+    }
 
-        let external_veth_uuid = Uuid::new_v4();
-        let external_veth_name = self.generate_random_interface_name();
+    /// Sets learning, TTL, TOS and UDP checksum of the VXLAN interface
+    /// of a virtual network, the interface is recreated with the new options.
+    /// The previous options are stored back when the interface cannot be
+    /// recreated.
+    async fn set_vxlan_options(
+        &self,
+        vnet_uuid: Uuid,
+        options: VXLANOptions,
+    ) -> FResult<VirtualNetwork> {
+        let mut vnet = self.connector.local.get_virtual_network(vnet_uuid).await?;
+        let mut internals =
+            deserialize_network_internals(vnet.plugin_internals.as_ref().ok_or(FError::NotFound)?)?;
+        // the transport update reads the options from the internals
+        let previous = internals.vxlan_options.replace(options);
+        vnet.plugin_internals = Some(serialize_network_internals(&internals)?);
+        self.store_virtual_network(&vnet).await?;
+        match self
+            .update_network_transport(vnet_uuid, VXLANTransportUpdate::default())
+            .await
+        {
+            Ok(vnet) => Ok(vnet),
+            Err(e) => {
+                let mut vnet = self.connector.local.get_virtual_network(vnet_uuid).await?;
+                let mut internals = deserialize_network_internals(
+                    vnet.plugin_internals.as_ref().ok_or(FError::NotFound)?,
+                )?;
+                internals.vxlan_options = previous;
+                vnet.plugin_internals = Some(serialize_network_internals(&internals)?);
+                self.store_virtual_network(&vnet).await?;
+                Err(e)
+            }
+        }
+    }
 
-        let mut associated_ns = NetworkNamespace {
-            uuid: vnet.uuid,
-            ns_name: self.generate_random_netns_name(),
-            interfaces: vec![
-                external_veth_uuid,
-                internal_veth_uuid,
-                internal_br_uuid,
-                vxl_uuid,
-                br_uuid,
-            ],
+    /// Changes VNI, port, multicast group or remote address of a virtual network.
+    /// Only the VXLAN interface is recreated, the bridges, the namespace and
+    /// the connected interfaces are kept as they are.
+    async fn update_network_transport(
+        &self,
+        vnet_uuid: Uuid,
+        update: VXLANTransportUpdate,
+    ) -> FResult<VirtualNetwork> {
+        let mut vnet = self.connector.local.get_virtual_network(vnet_uuid).await?;
+        let mut internals =
+            deserialize_network_internals(vnet.plugin_internals.as_ref().ok_or(FError::NotFound)?)?;
+
+        let mut vxl_iface = None;
+        for iface_uuid in &vnet.interfaces {
+            let iface = self.connector.local.get_interface(*iface_uuid).await?;
+            if let VirtualInterfaceKind::VXLAN(_) = iface.kind {
+                vxl_iface = Some(iface);
+                break;
+            }
+        }
+        let mut vxl_iface = vxl_iface.ok_or(FError::NotFound)?;
+        let br_uuid = vxl_iface.parent.ok_or(FError::NotConnected)?;
+        let bridge = self.connector.local.get_interface(br_uuid).await?;
+        let mut vxl_info = match vxl_iface.kind {
+            VirtualInterfaceKind::VXLAN(ref info) => info.clone(),
+            _ => return Err(FError::WrongKind),
         };
 
-        // Generating Structs
+        match (&vnet.link_kind, update.mcast_addr, update.remote_addr) {
+            (LinkKind::L2(_), _, None) | (LinkKind::ELINE(_), None, _) => (),
+            (LinkKind::L2(_), _, Some(_)) | (LinkKind::ELINE(_), Some(_), _) => {
+                return Err(FError::WrongKind)
+            }
+            _ => return Err(FError::Unimplemented),
+        }
 
-        let v_bridge = VirtualInterface {
-            uuid: br_uuid,
-            if_name: br_name.clone(),
-            net_ns: None,
-            parent: None,
-            kind: VirtualInterfaceKind::BRIDGE(BridgeKind {
-                childs: vec![external_veth_uuid, vxl_uuid],
-            }),
-            addresses: Vec::new(),
-            phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
+        let old_info = vxl_info.clone();
+        vxl_info.vni = update.vni.unwrap_or(vxl_info.vni);
+        vxl_info.port = update.port.unwrap_or(vxl_info.port);
+        let eline = match vnet.link_kind {
+            LinkKind::L2(ref mut info) => {
+                vxl_info.mcast_addr = update.mcast_addr.unwrap_or(vxl_info.mcast_addr);
+                info.vni = vxl_info.vni;
+                info.port = vxl_info.port;
+                info.mcast_addr = vxl_info.mcast_addr;
+                false
+            }
+            LinkKind::ELINE(ref mut info) => {
+                vxl_info.mcast_addr = update.remote_addr.unwrap_or(vxl_info.mcast_addr);
+                info.vni = vxl_info.vni;
+                info.port = vxl_info.port;
+                info.remote_addr = vxl_info.mcast_addr;
+                true
+            }
+            _ => return Err(FError::Unimplemented),
         };
+        let unicast = !eline && internals.unicast_peers.is_some();
+
+        self.kernel().del_iface(vxl_iface.if_name.clone()).await?;
+        if let Err(e) = self
+            .create_transport_vxlan(
+                &vxl_iface.if_name,
+                &vxl_info,
+                eline,
+                unicast,
+                &internals,
+                &bridge.if_name,
+            )
+            .await
+        {
+            log::error!(
+                "Unable to update the transport of {}, restoring it: {}",
+                vnet_uuid,
+                e
+            );
+            if self
+                .kernel()
+                .iface_exists(vxl_iface.if_name.clone())
+                .await?
+            {
+                self.kernel().del_iface(vxl_iface.if_name.clone()).await?;
+            }
+            self.create_transport_vxlan(
+                &vxl_iface.if_name,
+                &old_info,
+                eline,
+                unicast,
+                &internals,
+                &bridge.if_name,
+            )
+            .await?;
+            return Err(e);
+        }
+        if unicast {
+            // peers are added again to the new interface
+            internals.unicast_peers = Some(Vec::new());
+        }
 
-        let v_internal_bridge = VirtualInterface {
-            uuid: internal_br_uuid,
-            if_name: internal_br_name.clone(),
-            net_ns: Some(associated_ns.uuid),
-            parent: None,
-            kind: VirtualInterfaceKind::BRIDGE(BridgeKind {
-                childs: vec![internal_veth_uuid],
-            }),
-            addresses: Vec::new(),
-            phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
-        };
+        vxl_iface.kind = VirtualInterfaceKind::VXLAN(vxl_info);
+        self.store_interface(&vxl_iface).await?;
+        vnet.plugin_internals = Some(serialize_network_internals(&internals)?);
+        self.store_virtual_network(&vnet).await?;
 
-        let vxl_iface = VirtualInterface {
-            uuid: vxl_uuid,
-            if_name: vxl_name.clone(),
-            net_ns: None,
-            parent: Some(br_uuid),
-            kind: VirtualInterfaceKind::VXLAN(VXLANKind {
-                vni: vxlan_info.vni,
-                port: vxlan_info.port,
-                mcast_addr: vxlan_info.mcast_addr,
-                dev: Interface {
-                    if_name: self.get_overlay_iface().await?,
-                    kind: InterfaceKind::ETHERNET,
-                    addresses: Vec::new(),
-                    phy_address: None,
-                },
-            }),
-            addresses: Vec::new(),
-            phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
-        };
+        let guard = self.state.read().await;
+        let is_unicast = guard.unicast_vnets.contains_key(&vnet_uuid);
+        drop(guard);
+        if is_unicast {
+            self.update_unicast_peers(vnet_uuid).await?;
+        }
+        self.connector.local.get_virtual_network(vnet_uuid).await
+    }
 
-        let v_veth_i = VirtualInterface {
-            uuid: internal_veth_uuid,
-            if_name: internal_veth_name.clone(),
-            net_ns: Some(associated_ns.uuid),
-            parent: Some(internal_br_uuid),
-            kind: VirtualInterfaceKind::VETH(VETHKind {
-                pair: external_veth_uuid,
-                internal: true,
-            }),
-            addresses: Vec::new(),
-            phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
-        };
+    async fn get_ns_manager_spawn_stats(&self) -> FResult<NSManagerSpawnStats> {
+        let guard = self.state.read().await;
+        Ok(guard.ns_manager_spawn_stats.clone())
+    }
 
-        let v_veth_e = VirtualInterface {
-            uuid: external_veth_uuid,
-            if_name: external_veth_name.clone(),
-            net_ns: None,
-            parent: Some(br_uuid),
-            kind: VirtualInterfaceKind::VETH(VETHKind {
-                pair: internal_veth_uuid,
-                internal: false,
-            }),
-            addresses: Vec::new(),
-            phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
-        };
+    /// Returns the EBUSY retries of the netlink operations
+    async fn get_netlink_retry_stats(&self) -> FResult<HashMap<String, NetlinkRetryStats>> {
+        Ok(self.netlink_retries.lock().await.clone())
+    }
 
-        // Creating Virtual network bridge
+    async fn get_capabilities(&self) -> FResult<PluginCapabilities> {
+        Ok(PluginCapabilities {
+            nat: cfg!(feature = "nat"),
+            dhcp: cfg!(feature = "dhcp"),
+            qos: cfg!(feature = "qos"),
+            flow_export: cfg!(feature = "flow-export"),
+            local_ns_channel: cfg!(feature = "local-ns-channel"),
+        })
+    }
 
-        self.create_bridge(br_name.clone()).await?;
-        self.connector.local.add_interface(&v_bridge).await?;
+    async fn replay_journal(&self) -> FResult<JournalReplay> {
+        self.restore_from_journal().await
+    }
 
-        vnet.interfaces.push(br_uuid);
+    async fn get_node_uuid(&self) -> FResult<Uuid> {
+        self.agent.as_ref().unwrap().get_node_uuid().await?
+    }
 
-        self.set_iface_up(br_name.clone()).await?;
+    /// Keeps the records replicated by the `replication_peer`, replacing
+    /// the previous ones. The replica must be sealed with the replication
+    /// key. The file is readable only by the owner as it holds secrets.
+    async fn store_replica(&self, sealed: SealedReplica) -> FResult<()> {
+        log::trace!("store_replica from {}", sealed.node_uuid);
+        if self.config.replication_peer != Some(sealed.node_uuid) {
+            return Err(FError::NetworkingError(format!(
+                "{} is not the replication peer",
+                sealed.node_uuid
+            )));
+        }
+        let replica = open_replica(&self.replication_cipher()?, &sealed)?;
+        let path = self.get_replica_path(replica.node_uuid);
+        if let Some(dir) = path.parent() {
+            async_std::fs::create_dir_all(dir).await?;
+        }
+        let content =
+            serde_json::to_vec(&replica).map_err(|e| FError::NetworkingError(format!("{}", e)))?;
+        self.store_private_file(path.to_str().ok_or(FError::EncodingError)?, &content)
+    }
 
-        // Creating VXLAN Interface
+    /// Writes the internals of a global record for another node, see
+    /// `compare_and_swap_global_internals`. The write must be sealed with
+    /// the global key by the node it comes from, as the replicas are.
+    async fn swap_global_internals(&self, sealed: SealedGlobalSwap) -> FResult<bool> {
+        log::trace!("swap_global_internals from {}", sealed.node_uuid);
+        let swap = open_global_swap(&self.global_cipher()?, &sealed)?;
+        self.compare_and_swap_global_internals(swap.vnet_uuid, swap.expected, swap.internals)
+            .await
+    }
 
-        self.create_mcast_vxlan(
-            vxl_name.clone(),
-            self.get_overlay_iface().await?,
-            vxlan_info.vni,
-            vxlan_info.mcast_addr,
-            vxlan_info.port,
-        )
-        .await?;
-        self.connector.local.add_interface(&vxl_iface).await?;
+    async fn list_replicas(&self) -> FResult<Vec<ReplicaInfo>> {
+        let dir = async_std::path::Path::new(self.get_run_path().as_os_str()).join(REPLICAS_DIR);
+        let mut replicas = Vec::new();
+        if !dir.exists().await {
+            return Ok(replicas);
+        }
+        let mut entries = async_std::fs::read_dir(&dir).await?;
+        while let Some(entry) = entries.next().await {
+            let path = entry?.path();
+            match self.read_replica_file(&path).await {
+                Ok(replica) => replicas.push(ReplicaInfo {
+                    node_uuid: replica.node_uuid,
+                    timestamp: replica.timestamp,
+                    records: replica.entries.len(),
+                }),
+                Err(e) => log::warn!("Skipping replica {}: {}", path.display(), e),
+            }
+        }
+        Ok(replicas)
+    }
 
-        vnet.interfaces.push(vxl_uuid);
+    /// Re-instantiates locally the networks of a dead peer from the records
+    /// it replicated here, as done for the journal after a reboot. The peer
+    /// must no longer answer, so that the networks do not run twice.
+    async fn takeover(&self, peer_node_uuid: Uuid) -> FResult<JournalReplay> {
+        log::info!("Taking over the networks of {}", peer_node_uuid);
+        if self.config.replication_peer != Some(peer_node_uuid) {
+            return Err(FError::NetworkingError(format!(
+                "{} is not the replication peer",
+                peer_node_uuid
+            )));
+        }
+        if self.find_node_server(peer_node_uuid).await?.is_some() {
+            return Err(FError::NetworkingError(format!(
+                "{} is still running",
+                peer_node_uuid
+            )));
+        }
+        let path = self.get_replica_path(peer_node_uuid);
+        let replica = self.read_replica_file(&path).await?;
+        let mut state = JournalState::default();
+        for entry in replica.entries {
+            state.apply(entry);
+        }
+        // the keys are written under the local run path whatever the
+        // path recorded by the peer
+        for secret in &replica.secrets {
+            let vnet = match state.virtual_networks.get_mut(&secret.vnet_uuid) {
+                Some(vnet) => vnet,
+                None => continue,
+            };
+            let mut internals = match vnet.plugin_internals.as_ref() {
+                Some(raw) => deserialize_network_internals(raw)?,
+                None => continue,
+            };
+            if let Some(wg) = internals.wireguard.as_mut() {
+                let key_file_path = self
+                    .get_run_path()
+                    .join(format!("{}.key", secret.vnet_uuid))
+                    .to_str()
+                    .ok_or(FError::EncodingError)?
+                    .to_string();
+                self.store_private_file(&key_file_path, &secret.content)?;
+                wg.private_key_file = key_file_path;
+                vnet.plugin_internals = Some(serialize_network_internals(&internals)?);
+            }
+        }
+        let replay = self.replay_records(&state).await;
+        {
+            let _guard = self.journal_lock.lock().await;
+            let journal_path = self.get_journal_path();
+            let state = journal::read(&journal_path).await?;
+            journal::compact(&journal_path, now_ms(), &state).await?;
+        }
+        // kept to retry the records that failed
+        if replay.failures.is_empty() {
+            async_std::fs::remove_file(&path).await?;
+        }
+        Ok(replay)
+    }
 
-        self.set_iface_master(vxl_name.clone(), br_name.clone())
-            .await?;
-        self.set_iface_up(vxl_name).await?;
+    /// Sets or removes the QoS of a connection point, the shaper is on its
+    /// external veth so it follows the connection point across binds
+    async fn set_connection_point_qos(
+        &self,
+        cp_uuid: Uuid,
+        qos: Option<ConnectionPointQoS>,
+    ) -> FResult<ConnectionPoint> {
+        if qos.is_some() && !cfg!(feature = "qos") {
+            return Err(FError::Unimplemented);
+        }
+        let cp = self.connector.local.get_connection_point(cp_uuid).await?;
+        let ext_veth = self.connector.local.get_interface(cp.external_veth).await?;
+        let previous = self.state.read().await.cp_qos.get(&cp_uuid).cloned();
+        match (qos, previous) {
+            (Some(qos), _) => {
+                self.set_iface_qos(&ext_veth.if_name, &qos)?;
+                self.state.write().await.cp_qos.insert(cp_uuid, qos.clone());
+                self.append_journal(JournalEntry::ConnectionPointQoS(cp_uuid, qos))
+                    .await;
+            }
+            (None, Some(_)) => {
+                self.del_iface_qos(&ext_veth.if_name)?;
+                self.state.write().await.cp_qos.remove(&cp_uuid);
+                self.append_journal(JournalEntry::RemoveConnectionPointQoS(cp_uuid))
+                    .await;
+            }
+            (None, None) => (),
+        }
+        Ok(cp)
+    }
 
-        // Creating netns and spawing the namespace manager
-        self.add_netns(associated_ns.ns_name.clone()).await?;
-        self.spawn_ns_manager(associated_ns.ns_name.clone(), associated_ns.uuid)
-            .await?;
+    async fn get_connection_point_qos(&self, cp_uuid: Uuid) -> FResult<Option<ConnectionPointQoS>> {
+        self.connector.local.get_connection_point(cp_uuid).await?;
+        Ok(self.state.read().await.cp_qos.get(&cp_uuid).cloned())
+    }
 
-        self.connector
-            .local
-            .add_network_namespace(&associated_ns)
+    /// Maps a public address of the node to the address of a connection
+    /// point: the address is added to the overlay interface, traffic to it
+    /// is DNATed to the connection point and traffic from the connection
+    /// point leaving from the overlay interface is SNATed to it.
+    /// The connection point has to be bound to a virtual network, where
+    /// the mapping is recorded and removed when it is unbound.
+    async fn assign_floating_ip(&self, cp_uuid: Uuid, public_ip: IPAddress) -> FResult<FloatingIP> {
+        let cp = self.connector.local.get_connection_point(cp_uuid).await?;
+        let mut vnet = self
+            .get_connection_point_network(cp_uuid)
+            .await?
+            .ok_or(FError::NotConnected)?;
+        let mut internals =
+            deserialize_network_internals(vnet.plugin_internals.as_ref().ok_or(FError::NotFound)?)?;
+        // management and provider networks are never NATed
+        if vnet.is_mgmt || internals.provider_vlan.is_some() {
+            return Err(FError::WrongKind);
+        }
+        if internals.floating_ips.iter().any(|f| f.cp_uuid == cp_uuid) {
+            return Err(FError::AlreadyPresent);
+        }
+        let private_ip = self.get_connection_point_address(&cp).await?;
+        if private_ip.is_ipv4() != public_ip.is_ipv4() {
+            return Err(FError::NetworkingError(format!(
+                "Floating IP {} and connection point address {} are of different families",
+                public_ip, private_ip
+            )));
+        }
+        let iface = self.get_overlay_face_from_config().await?.if_name;
+        let subnet = vnet
+            .ip_configuration
+            .as_ref()
+            .and_then(|c| c.subnet)
+            .and_then(|(addr, prefix)| IpNetwork::new(addr, prefix).ok());
+        let table = self
+            .configure_static_nat(public_ip, private_ip, &iface, subnet)
             .await?;
+        let floating_ip = FloatingIP {
+            cp_uuid,
+            public_ip,
+            private_ip,
+            iface,
+            table,
+        };
+        internals.floating_ips.push(floating_ip.clone());
+        vnet.plugin_internals = Some(serialize_network_internals(&internals)?);
+        self.store_virtual_network(&vnet).await?;
+        Ok(floating_ip)
+    }
 
-        // Creating veth pair
-        self.create_veth(external_veth_name.clone(), internal_veth_name.clone())
-            .await?;
+    async fn release_floating_ip(&self, cp_uuid: Uuid) -> FResult<FloatingIP> {
+        let mut vnet = self
+            .get_connection_point_network(cp_uuid)
+            .await?
+            .ok_or(FError::NotFound)?;
+        let mut internals =
+            deserialize_network_internals(vnet.plugin_internals.as_ref().ok_or(FError::NotFound)?)?;
+        let pos = internals
+            .floating_ips
+            .iter()
+            .position(|f| f.cp_uuid == cp_uuid)
+            .ok_or(FError::NotFound)?;
+        let floating_ip = internals.floating_ips.remove(pos);
+        self.remove_floating_ip(&floating_ip).await?;
+        vnet.plugin_internals = Some(serialize_network_internals(&internals)?);
+        self.store_virtual_network(&vnet).await?;
+        Ok(floating_ip)
+    }
 
-        self.connector.local.add_interface(&v_veth_e).await?;
+    async fn get_floating_ip(&self, cp_uuid: Uuid) -> FResult<FloatingIP> {
+        let vnet = self
+            .get_connection_point_network(cp_uuid)
+            .await?
+            .ok_or(FError::NotFound)?;
+        let internals =
+            deserialize_network_internals(vnet.plugin_internals.as_ref().ok_or(FError::NotFound)?)?;
+        internals
+            .floating_ips
+            .into_iter()
+            .find(|f| f.cp_uuid == cp_uuid)
+            .ok_or(FError::NotFound)
+    }
 
-        vnet.interfaces.push(internal_veth_uuid);
+    /// Forwards a port of the overlay interface to an address behind the
+    /// NAT of a virtual network, the DNAT rules are kept in a prerouting
+    /// chain of the NAT table of the network.
+    async fn add_port_forward(
+        &self,
+        vnet_uuid: Uuid,
+        proto: PortForwardProtocol,
+        ext_port: u16,
+        internal_ip: IPAddress,
+        internal_port: u16,
+    ) -> FResult<VirtualNetwork> {
+        let mut vnet = self.connector.local.get_virtual_network(vnet_uuid).await?;
+        let mut internals =
+            deserialize_network_internals(vnet.plugin_internals.as_ref().ok_or(FError::NotFound)?)?;
+        if vnet.is_mgmt || internals.provider_vlan.is_some() {
+            return Err(FError::WrongKind);
+        }
+        if !internal_ip.is_ipv4() {
+            return Err(FError::NetworkingError(
+                "Port forwards have to point to IPv4 addresses".to_string(),
+            ));
+        }
+        if internals
+            .port_forwards
+            .iter()
+            .any(|f| f.proto == proto && f.ext_port == ext_port)
+        {
+            return Err(FError::AlreadyPresent);
+        }
+        internals.port_forwards.push(PortForward {
+            proto,
+            ext_port,
+            internal_ip,
+            internal_port,
+        });
+        self.apply_port_forwards(&vnet, &internals).await?;
+        vnet.plugin_internals = Some(serialize_network_internals(&internals)?);
+        self.store_virtual_network(&vnet).await?;
+        Ok(vnet)
+    }
 
-        self.connector.local.add_interface(&v_veth_i).await?;
+    async fn remove_port_forward(
+        &self,
+        vnet_uuid: Uuid,
+        proto: PortForwardProtocol,
+        ext_port: u16,
+    ) -> FResult<VirtualNetwork> {
+        let mut vnet = self.connector.local.get_virtual_network(vnet_uuid).await?;
+        let mut internals =
+            deserialize_network_internals(vnet.plugin_internals.as_ref().ok_or(FError::NotFound)?)?;
+        let pos = internals
+            .port_forwards
+            .iter()
+            .position(|f| f.proto == proto && f.ext_port == ext_port)
+            .ok_or(FError::NotFound)?;
+        let forward = internals.port_forwards.remove(pos);
+        self.apply_port_forwards(&vnet, &internals).await?;
+        flush_conntrack(&[
+            "-p".to_string(),
+            forward.proto.to_string(),
+            "--orig-port-dst".to_string(),
+            forward.ext_port.to_string(),
+            "--reply-src".to_string(),
+            forward.internal_ip.to_string(),
+        ]);
+        vnet.plugin_internals = Some(serialize_network_internals(&internals)?);
+        self.store_virtual_network(&vnet).await?;
+        Ok(vnet)
+    }
 
-        vnet.interfaces.push(external_veth_uuid);
+    async fn list_port_forwards(&self, vnet_uuid: Uuid) -> FResult<Vec<PortForward>> {
+        let vnet = self.connector.local.get_virtual_network(vnet_uuid).await?;
+        let internals =
+            deserialize_network_internals(vnet.plugin_internals.as_ref().ok_or(FError::NotFound)?)?;
+        Ok(internals.port_forwards)
+    }
 
-        self.set_iface_master(external_veth_name.clone(), br_name.clone())
-            .await?;
-        self.set_iface_up(external_veth_name).await?;
+    async fn add_nft_set(&self, vnet_uuid: Uuid, mut set: NFTSet) -> FResult<VirtualNetwork> {
+        let mut vnet = self.connector.local.get_virtual_network(vnet_uuid).await?;
+        let mut internals =
+            deserialize_network_internals(vnet.plugin_internals.as_ref().ok_or(FError::NotFound)?)?;
+        if vnet.is_mgmt || internals.provider_vlan.is_some() {
+            return Err(FError::WrongKind);
+        }
+        validate_nft_set_name(&set.name)?;
+        if internals.nft_sets.iter().any(|s| s.name == set.name) {
+            return Err(FError::AlreadyPresent);
+        }
+        let mut elements = Vec::new();
+        for element in &set.elements {
+            let element = parse_set_element(set.kind, element)?;
+            if !elements.contains(&element) {
+                elements.push(element);
+            }
+        }
+        set.elements = elements;
+        internals.nft_sets.push(set);
+        self.apply_set_rules(&vnet, &internals).await?;
+        vnet.plugin_internals = Some(serialize_network_internals(&internals)?);
+        self.store_virtual_network(&vnet).await?;
+        Ok(vnet)
+    }
 
-        self.set_iface_ns(
-            internal_veth_name.clone(),
-            associated_ns.ns_name.clone().clone(),
-        )
+    /// Removes a set that is not referenced by any rule
+    async fn remove_nft_set(&self, vnet_uuid: Uuid, name: String) -> FResult<VirtualNetwork> {
+        let mut vnet = self.connector.local.get_virtual_network(vnet_uuid).await?;
+        let mut internals =
+            deserialize_network_internals(vnet.plugin_internals.as_ref().ok_or(FError::NotFound)?)?;
+        let pos = internals
+            .nft_sets
+            .iter()
+            .position(|s| s.name == name)
+            .ok_or(FError::NotFound)?;
+        if internals.set_rules.iter().any(|r| r.set == name) {
+            return Err(FError::NetworkingError(format!(
+                "Set {} is used by set rules",
+                name
+            )));
+        }
+        internals.nft_sets.remove(pos);
+        self.update_nat_table(&internals, &|table| {
+            format!("delete set inet {} {}", table, name)
+        })
         .await?;
+        vnet.plugin_internals = Some(serialize_network_internals(&internals)?);
+        self.store_virtual_network(&vnet).await?;
+        Ok(vnet)
+    }
 
-        // create internal bridge
-        let ns_manager = self.get_ns_manager(&associated_ns.uuid).await?;
-
-        // This is used to wait that the namespace manager is ready to serve
-        while !ns_manager.verify_server().await? {}
+    async fn list_nft_sets(&self, vnet_uuid: Uuid) -> FResult<Vec<NFTSet>> {
+        let vnet = self.connector.local.get_virtual_network(vnet_uuid).await?;
+        let internals =
+            deserialize_network_internals(vnet.plugin_internals.as_ref().ok_or(FError::NotFound)?)?;
+        Ok(internals.nft_sets)
+    }
 
-        ns_manager
-            .set_virtual_interface_up("lo".to_string())
-            .await??;
+    /// Adds an element to a set in place, the rules are not rewritten
+    async fn add_set_element(
+        &self,
+        vnet_uuid: Uuid,
+        name: String,
+        element: String,
+    ) -> FResult<VirtualNetwork> {
+        let mut vnet = self.connector.local.get_virtual_network(vnet_uuid).await?;
+        let mut internals =
+            deserialize_network_internals(vnet.plugin_internals.as_ref().ok_or(FError::NotFound)?)?;
+        let set = internals
+            .nft_sets
+            .iter_mut()
+            .find(|s| s.name == name)
+            .ok_or(FError::NotFound)?;
+        let element = parse_set_element(set.kind, &element)?;
+        if set.elements.contains(&element) {
+            return Err(FError::AlreadyPresent);
+        }
+        set.elements.push(element.clone());
+        self.update_nat_table(&internals, &|table| {
+            format!("add element inet {} {} {{ {} }}", table, name, element)
+        })
+        .await?;
+        vnet.plugin_internals = Some(serialize_network_internals(&internals)?);
+        self.store_virtual_network(&vnet).await?;
+        Ok(vnet)
+    }
 
-        ns_manager
-            .add_virtual_interface_bridge(internal_br_name.clone())
-            .await??;
+    async fn del_set_element(
+        &self,
+        vnet_uuid: Uuid,
+        name: String,
+        element: String,
+    ) -> FResult<VirtualNetwork> {
+        let mut vnet = self.connector.local.get_virtual_network(vnet_uuid).await?;
+        let mut internals =
+            deserialize_network_internals(vnet.plugin_internals.as_ref().ok_or(FError::NotFound)?)?;
+        let set = internals
+            .nft_sets
+            .iter_mut()
+            .find(|s| s.name == name)
+            .ok_or(FError::NotFound)?;
+        let element = parse_set_element(set.kind, &element)?;
+        let pos = set
+            .elements
+            .iter()
+            .position(|e| *e == element)
+            .ok_or(FError::NotFound)?;
+        set.elements.remove(pos);
+        self.update_nat_table(&internals, &|table| {
+            format!("delete element inet {} {} {{ {} }}", table, name, element)
+        })
+        .await?;
+        vnet.plugin_internals = Some(serialize_network_internals(&internals)?);
+        self.store_virtual_network(&vnet).await?;
+        Ok(vnet)
+    }
 
-        ns_manager
-            .set_virtual_interface_up(internal_br_name.clone())
-            .await??;
+    /// Appends a rule referencing one of the sets of the network
+    async fn add_set_rule(&self, vnet_uuid: Uuid, rule: SetRule) -> FResult<VirtualNetwork> {
+        let mut vnet = self.connector.local.get_virtual_network(vnet_uuid).await?;
+        let mut internals =
+            deserialize_network_internals(vnet.plugin_internals.as_ref().ok_or(FError::NotFound)?)?;
+        let set = internals
+            .nft_sets
+            .iter()
+            .find(|s| s.name == rule.set)
+            .ok_or(FError::NotFound)?;
+        if set.kind == NFTSetKind::PORT && rule.proto.is_none() {
+            return Err(FError::NetworkingError(format!(
+                "Rules on port set {} need a protocol",
+                set.name
+            )));
+        }
+        if let Some(schedule) = &rule.schedule {
+            if schedule.start >= 24 * 60
+                || schedule.end >= 24 * 60
+                || schedule.utc_offset.abs() > 14 * 60
+            {
+                return Err(FError::NetworkingError(format!(
+                    "Invalid rule schedule {:?}",
+                    schedule
+                )));
+            }
+        }
+        if internals.set_rules.contains(&rule) {
+            return Err(FError::AlreadyPresent);
+        }
+        internals.set_rules.push(rule);
+        self.apply_set_rules(&vnet, &internals).await?;
+        vnet.plugin_internals = Some(serialize_network_internals(&internals)?);
+        self.store_virtual_network(&vnet).await?;
+        Ok(vnet)
+    }
 
-        vnet.interfaces.push(internal_br_uuid);
+    async fn remove_set_rule(&self, vnet_uuid: Uuid, rule: SetRule) -> FResult<VirtualNetwork> {
+        let mut vnet = self.connector.local.get_virtual_network(vnet_uuid).await?;
+        let mut internals =
+            deserialize_network_internals(vnet.plugin_internals.as_ref().ok_or(FError::NotFound)?)?;
+        let pos = internals
+            .set_rules
+            .iter()
+            .position(|r| *r == rule)
+            .ok_or(FError::NotFound)?;
+        internals.set_rules.remove(pos);
+        self.apply_set_rules(&vnet, &internals).await?;
+        vnet.plugin_internals = Some(serialize_network_internals(&internals)?);
+        self.store_virtual_network(&vnet).await?;
+        Ok(vnet)
+    }
 
-        self.connector
-            .local
-            .add_interface(&v_internal_bridge)
-            .await?;
+    async fn list_set_rules(&self, vnet_uuid: Uuid) -> FResult<Vec<SetRule>> {
+        let vnet = self.connector.local.get_virtual_network(vnet_uuid).await?;
+        let internals =
+            deserialize_network_internals(vnet.plugin_internals.as_ref().ok_or(FError::NotFound)?)?;
+        Ok(internals.set_rules)
+    }
 
-        ns_manager
-            .set_virtual_interface_master(internal_veth_name.clone(), internal_br_name.clone())
-            .await??;
+    /// Creates a connection point that is not a member of the virtual
+    /// network but receives a copy of all its traffic, for monitoring
+    /// FDUs (eg. IDS). The frames entering the bridge of the network from
+    /// any port are mirrored to the external veth of the connection point.
+    async fn create_mirror_connection_point(&self, vnet_uuid: Uuid) -> FResult<ConnectionPoint> {
+        if !cfg!(feature = "qos") {
+            return Err(FError::Unimplemented);
+        }
+        let mut vnet = self.connector.local.get_virtual_network(vnet_uuid).await?;
+        let mut internals =
+            deserialize_network_internals(vnet.plugin_internals.as_ref().ok_or(FError::NotFound)?)?;
+        let cp = self.create_connection_point().await?;
+        let ext_veth = self.connector.local.get_interface(cp.external_veth).await?;
+        internals.mirrors.push(VNetMirror {
+            cp_uuid: cp.uuid,
+            iface: ext_veth.if_name,
+        });
+        let res = self.apply_mirrors(&vnet, &internals.mirrors).await;
+        if let Err(e) = res {
+            log::error!("Unable to mirror {} to {}: {}", vnet_uuid, cp.uuid, e);
+            internals.mirrors.retain(|m| m.cp_uuid != cp.uuid);
+            self.apply_mirrors(&vnet, &internals.mirrors).await?;
+            self.delete_connection_point(cp.uuid).await?;
+            return Err(e);
+        }
+        vnet.plugin_internals = Some(serialize_network_internals(&internals)?);
+        self.store_virtual_network(&vnet).await?;
+        Ok(cp)
+    }
 
-        ns_manager
-            .set_virtual_interface_up(internal_veth_name.clone())
-            .await??;
+    async fn delete_mirror_connection_point(
+        &self,
+        vnet_uuid: Uuid,
+        cp_uuid: Uuid,
+    ) -> FResult<Uuid> {
+        let mut vnet = self.connector.local.get_virtual_network(vnet_uuid).await?;
+        let mut internals =
+            deserialize_network_internals(vnet.plugin_internals.as_ref().ok_or(FError::NotFound)?)?;
+        let pos = internals
+            .mirrors
+            .iter()
+            .position(|m| m.cp_uuid == cp_uuid)
+            .ok_or(FError::NotFound)?;
+        internals.mirrors.remove(pos);
+        self.apply_mirrors(&vnet, &internals.mirrors).await?;
+        vnet.plugin_internals = Some(serialize_network_internals(&internals)?);
+        self.store_virtual_network(&vnet).await?;
+        self.delete_connection_point(cp_uuid).await
+    }
 
-        // NAT configuration, skip it for the time being...
-        // let nat_table = self
-        //     .configure_nat(
-        //         IpNetwork::V4(
-        //             ipnetwork::Ipv4Network::new(
-        //                 std::net::Ipv4Addr::new(10, 240, 0, 0),
-        //                 16,
-        //             )
-        //             .map_err(|e| FError::NetworkingError(format!("{}", e)))?,
-        //         ),
-        //         &self.get_overlay_face_from_config().await?.if_name,
-        //     )
-        //     .await?;
+    async fn list_mirror_connection_points(&self, vnet_uuid: Uuid) -> FResult<Vec<Uuid>> {
+        let vnet = self.connector.local.get_virtual_network(vnet_uuid).await?;
+        let internals =
+            deserialize_network_internals(vnet.plugin_internals.as_ref().ok_or(FError::NotFound)?)?;
+        Ok(internals.mirrors.into_iter().map(|m| m.cp_uuid).collect())
+    }
 
-        // DHCP configuration and spawn
+    /// Sets the size and ageing of the forwarding database of the
+    /// bridge of a virtual network, kept in the network internals.
+    async fn set_fdb_options(
+        &self,
+        vnet_uuid: Uuid,
+        options: FDBOptions,
+    ) -> FResult<VirtualNetwork> {
+        let mut vnet = self.connector.local.get_virtual_network(vnet_uuid).await?;
+        let mut internals =
+            deserialize_network_internals(vnet.plugin_internals.as_ref().ok_or(FError::NotFound)?)?;
+        let br_uuid = self.get_virtual_network_bridge(&vnet).await?;
+        let bridge = self.connector.local.get_interface(br_uuid).await?;
+        self.set_bridge_fdb_options(&bridge.if_name, &options)?;
+        internals.fdb = Some(options);
+        vnet.plugin_internals = Some(serialize_network_internals(&internals)?);
+        self.store_virtual_network(&vnet).await?;
+        Ok(vnet)
+    }
 
-        let dhcp_internal = match &vnet.ip_configuration {
-            Some(conf) => None,
-            None => None,
-        };
+    /// Removes the learned entries from the forwarding database of the
+    /// bridge of a virtual network, eg. after a topology change
+    async fn flush_fdb(&self, vnet_uuid: Uuid) -> FResult<()> {
+        let vnet = self.connector.local.get_virtual_network(vnet_uuid).await?;
+        let br_uuid = self.get_virtual_network_bridge(&vnet).await?;
+        let bridge = self.connector.local.get_interface(br_uuid).await?;
+        match bridge.net_ns {
+            Some(ns_uuid) => {
+                let ns_manager = self.get_ns_manager(&ns_uuid).await?;
+                ns_manager
+                    .flush_virtual_interface_fdb(bridge.if_name.clone())
+                    .await?
+            }
+            None => self.kernel().flush_bridge_fdb(&bridge.if_name).await,
+        }
+    }
 
-        let ns_info = Some(VNetNetns {
-            ns_name: associated_ns.ns_name.clone(),
-            ns_uuid: associated_ns.uuid,
-        });
+    /// Allows the traffic forwarded between the bridges of two
+    /// virtual networks, by default it is dropped.
+    async fn allow_peering(&self, vnet_a: Uuid, vnet_b: Uuid) -> FResult<()> {
+        if !cfg!(feature = "nat") {
+            return Err(FError::Unimplemented);
+        }
+        if vnet_a == vnet_b {
+            return Err(FError::NetworkingError(
+                "A virtual network cannot be peered with itself".to_string(),
+            ));
+        }
+        for vnet_uuid in &[vnet_a, vnet_b] {
+            if self
+                .connector
+                .local
+                .get_virtual_network(*vnet_uuid)
+                .await?
+                .is_mgmt
+            {
+                return Err(FError::NetworkingError(format!(
+                    "{} is a management network and cannot be peered",
+                    vnet_uuid
+                )));
+            }
+        }
+        self.set_peering(vnet_a, vnet_b, true).await?;
+        self.set_peering(vnet_b, vnet_a, true).await?;
+        self.update_isolation().await
+    }
 
-        let internals = VirtualNetworkInternals {
-            associated_netns: ns_info,
-            dhcp: dhcp_internal,
-            associated_tables: vec![],
-        };
+    async fn deny_peering(&self, vnet_a: Uuid, vnet_b: Uuid) -> FResult<()> {
+        if !cfg!(feature = "nat") {
+            return Err(FError::Unimplemented);
+        }
+        self.set_peering(vnet_a, vnet_b, false).await?;
+        self.set_peering(vnet_b, vnet_a, false).await?;
+        self.update_isolation().await
+    }
+
+    /// Replaces the tenants of a services network. The namespace of each
+    /// tenant is linked to the one of the services network by a routed
+    /// veth pair, recorded in the internals of the services network, and
+    /// only the replies are sent back through it
+    async fn set_shared_services(&self, services: SharedServices) -> FResult<SharedServices> {
+        if !cfg!(feature = "nat") {
+            return Err(FError::Unimplemented);
+        }
+        let mut tenants = Vec::new();
+        for tenant in &services.tenants {
+            if *tenant == services.services_vnet {
+                return Err(FError::NetworkingError(
+                    "A services network cannot be its own tenant".to_string(),
+                ));
+            }
+            if !tenants.contains(tenant) {
+                tenants.push(*tenant);
+            }
+        }
+        for vnet_uuid in std::iter::once(&services.services_vnet).chain(&tenants) {
+            if self
+                .connector
+                .local
+                .get_virtual_network(*vnet_uuid)
+                .await?
+                .is_mgmt
+            {
+                return Err(FError::NetworkingError(format!(
+                    "{} is a management network and cannot share services",
+                    vnet_uuid
+                )));
+            }
+        }
+        let mut vnet = self
+            .connector
+            .local
+            .get_virtual_network(services.services_vnet)
+            .await?;
+        let mut internals =
+            deserialize_network_internals(vnet.plugin_internals.as_ref().ok_or(FError::NotFound)?)?;
+        let (kept, removed): (Vec<VNetServicesLink>, Vec<VNetServicesLink>) = internals
+            .services_links
+            .drain(..)
+            .partition(|l| tenants.contains(&l.tenant));
+        for link in &removed {
+            self.remove_services_link(link).await?;
+        }
+        internals.services_links = kept;
+        for tenant in &tenants {
+            if internals.services_links.iter().any(|l| l.tenant == *tenant) {
+                continue;
+            }
+            let link_net = self.allocate_services_link().await?;
+            let link = self
+                .create_services_link(&vnet, &internals, *tenant, link_net)
+                .await?;
+            internals.services_links.push(link);
+        }
+        internals.services_tenants = tenants.clone();
+        self.apply_services_filter(&internals).await?;
         vnet.plugin_internals = Some(serialize_network_internals(&internals)?);
-        Ok(vnet)
+        self.store_virtual_network(&vnet).await?;
+        self.update_isolation().await?;
+        Ok(SharedServices {
+            services_vnet: services.services_vnet,
+            tenants,
+        })
     }
 
-    async fn ptp_vxlan_create(
-        &self,
-        mut vnet: VirtualNetwork,
-        vxlan_info: P2PVXLANInfo,
-    ) -> FResult<VirtualNetwork> {
-        let node_uuid = self.agent.as_ref().unwrap().get_node_uuid().await??;
+    async fn remove_shared_services(&self, services_vnet: Uuid) -> FResult<SharedServices> {
+        let services = self.get_shared_services(services_vnet).await?;
+        if services.tenants.is_empty() {
+            return Err(FError::NotFound);
+        }
+        self.set_shared_services(SharedServices {
+            services_vnet,
+            tenants: Vec::new(),
+        })
+        .await?;
+        Ok(services)
+    }
 
-        // Generating Names
+    async fn get_shared_services(&self, services_vnet: Uuid) -> FResult<SharedServices> {
+        let vnet = self
+            .connector
+            .local
+            .get_virtual_network(services_vnet)
+            .await?;
+        let internals =
+            deserialize_network_internals(vnet.plugin_internals.as_ref().ok_or(FError::NotFound)?)?;
+        Ok(SharedServices {
+            services_vnet,
+            tenants: internals.services_tenants,
+        })
+    }
 
-        let br_uuid = Uuid::new_v4();
-        let br_name = self.generate_random_interface_name();
+    async fn list_virtual_networks(&self) -> FResult<Vec<VirtualNetwork>> {
+        let candidates: Vec<Uuid> = self
+            .state
+            .read()
+            .await
+            .known_vnets
+            .iter()
+            .copied()
+            .collect();
+        let mut vnets = Vec::new();
+        for vnet_uuid in candidates {
+            match self.connector.local.get_virtual_network(vnet_uuid).await {
+                Ok(vnet) => vnets.push(vnet),
+                Err(_) => {
+                    self.state.write().await.known_vnets.remove(&vnet_uuid);
+                }
+            }
+        }
+        Ok(vnets)
+    }
 
-        let vxl_uuid = Uuid::new_v4();
-        let vxl_name = self.generate_random_interface_name();
+    async fn list_virtual_interfaces(&self) -> FResult<Vec<VirtualInterface>> {
+        Ok(self.known_interfaces().await)
+    }
 
-        let internal_br_uuid = Uuid::new_v4();
-        let internal_br_name = self.generate_random_interface_name();
+    async fn list_network_namespaces(&self) -> FResult<Vec<NetworkNamespace>> {
+        let candidates: Vec<Uuid> = self
+            .state
+            .read()
+            .await
+            .known_namespaces
+            .iter()
+            .copied()
+            .collect();
+        let mut namespaces = Vec::new();
+        for ns_uuid in candidates {
+            match self.connector.local.get_network_namespace(ns_uuid).await {
+                Ok(netns) => namespaces.push(netns),
+                Err(_) => {
+                    self.state.write().await.known_namespaces.remove(&ns_uuid);
+                }
+            }
+        }
+        Ok(namespaces)
+    }
 
-        let internal_veth_uuid = Uuid::new_v4();
-        let internal_veth_name = self.generate_random_interface_name();
+    async fn list_connection_points(&self) -> FResult<Vec<ConnectionPoint>> {
+        let candidates: Vec<Uuid> = self.state.read().await.known_cps.iter().copied().collect();
+        let mut cps = Vec::new();
+        for cp_uuid in candidates {
+            match self.connector.local.get_connection_point(cp_uuid).await {
+                Ok(cp) => cps.push(cp),
+                Err(_) => {
+                    self.state.write().await.known_cps.remove(&cp_uuid);
+                }
+            }
+        }
+        Ok(cps)
+    }
 
-        let external_veth_uuid = Uuid::new_v4();
-        let external_veth_name = self.generate_random_interface_name();
+    /// Returns the other end of a veth pair
+    async fn get_interface_peer(&self, intf_uuid: Uuid) -> FResult<InterfaceEnd> {
+        let iface = self.connector.local.get_interface(intf_uuid).await?;
+        match iface.kind {
+            VirtualInterfaceKind::VETH(info) => {
+                let peer = self.connector.local.get_interface(info.pair).await?;
+                self.get_interface_end(&peer).await
+            }
+            _ => Err(FError::WrongKind),
+        }
+    }
 
-        let mut associated_ns = NetworkNamespace {
-            uuid: vnet.uuid,
-            ns_name: self.generate_random_netns_name(),
-            interfaces: vec![
-                external_veth_uuid,
-                internal_veth_uuid,
-                internal_br_uuid,
-                vxl_uuid,
-                br_uuid,
-            ],
+    async fn inspect_virtual_interface(&self, intf_uuid: Uuid) -> FResult<InterfaceInspection> {
+        let iface = self.connector.local.get_interface(intf_uuid).await?;
+        let ns_name = self.get_interface_end(&iface).await?.ns_name;
+        let peer = match iface.kind {
+            VirtualInterfaceKind::VETH(_) => Some(self.get_interface_peer(intf_uuid).await?),
+            _ => None,
         };
+        let guard = self.state.read().await;
+        let metadata = guard.record_metadata.get(&intf_uuid).cloned();
+        let last_stats = guard.interface_stats.get(&intf_uuid).copied();
+        let oper_state = guard.link_oper_states.get(&intf_uuid).copied();
+        drop(guard);
+        Ok(InterfaceInspection {
+            iface,
+            ns_name,
+            peer,
+            metadata,
+            last_stats,
+            oper_state,
+        })
+    }
 
-        // Generating Structs
+    /// Counters of an interface from the dump of the last monitoring cycle,
+    /// its namespace is dumped now if it was not collected yet
+    async fn get_virtual_interface_stats(&self, intf_uuid: Uuid) -> FResult<InterfaceStats> {
+        let iface = self.connector.local.get_interface(intf_uuid).await?;
+        Ok(self.cached_interface_statistics(&iface).await?.stats)
+    }
 
-        let v_bridge = VirtualInterface {
-            uuid: br_uuid,
-            if_name: br_name.clone(),
-            net_ns: None,
-            parent: None,
-            kind: VirtualInterfaceKind::BRIDGE(BridgeKind {
-                childs: vec![external_veth_uuid, vxl_uuid],
-            }),
-            addresses: Vec::new(),
-            phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
-        };
+    /// Sets `rp_filter` and `log_martians` of all the interfaces of
+    /// a namespace, including the ones created later
+    async fn set_namespace_reverse_path_filter(
+        &self,
+        ns_uuid: Uuid,
+        filter: ReversePathFilter,
+    ) -> FResult<()> {
+        log::trace!("set_namespace_reverse_path_filter {} {:?}", ns_uuid, filter);
+        let ns_manager = self.get_ns_manager(&ns_uuid).await?;
+        ns_manager
+            .set_reverse_path_filter(None, filter.clone())
+            .await??;
+        self.record_reverse_path_filter(ns_uuid, &filter).await;
+        Ok(())
+    }
 
-        let v_internal_bridge = VirtualInterface {
-            uuid: internal_br_uuid,
-            if_name: internal_br_name.clone(),
-            net_ns: Some(associated_ns.uuid),
-            parent: None,
-            kind: VirtualInterfaceKind::BRIDGE(BridgeKind {
-                childs: vec![internal_veth_uuid],
-            }),
-            addresses: Vec::new(),
-            phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
-        };
+    /// Sets `rp_filter` and `log_martians` of a single interface,
+    /// in the default namespace or through its namespace manager
+    async fn set_virtual_interface_reverse_path_filter(
+        &self,
+        intf_uuid: Uuid,
+        filter: ReversePathFilter,
+    ) -> FResult<()> {
+        log::trace!(
+            "set_virtual_interface_reverse_path_filter {} {:?}",
+            intf_uuid,
+            filter
+        );
+        let iface = self.connector.local.get_interface(intf_uuid).await?;
+        self.apply_reverse_path_filter(&iface, &filter).await?;
+        self.record_reverse_path_filter(intf_uuid, &filter).await;
+        Ok(())
+    }
 
-        let vxl_iface = VirtualInterface {
-            uuid: vxl_uuid,
-            if_name: vxl_name.clone(),
-            net_ns: None,
-            parent: Some(br_uuid),
-            kind: VirtualInterfaceKind::VXLAN(VXLANKind {
-                vni: vxlan_info.vni,
-                port: vxlan_info.port,
-                mcast_addr: vxlan_info.remote_addr,
-                dev: Interface {
-                    if_name: self.get_overlay_iface().await?,
-                    kind: InterfaceKind::ETHERNET,
-                    addresses: Vec::new(),
-                    phy_address: None,
-                },
-            }),
-            addresses: Vec::new(),
-            phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
-        };
+    async fn get_namespace_martian_counters(&self, ns_uuid: Uuid) -> FResult<MartianCounters> {
+        let ns_manager = self.get_ns_manager(&ns_uuid).await?;
+        ns_manager.get_martian_counters().await?
+    }
 
-        let v_veth_i = VirtualInterface {
-            uuid: internal_veth_uuid,
-            if_name: internal_veth_name.clone(),
-            net_ns: Some(associated_ns.uuid),
-            parent: Some(internal_br_uuid),
+    /// Brings an interface administratively up or down,
+    /// in the default namespace or through its namespace manager.
+    async fn set_virtual_interface_state(
+        &self,
+        intf_uuid: Uuid,
+        state: InterfaceAdminState,
+    ) -> FResult<VirtualInterface> {
+        let iface = self.connector.local.get_interface(intf_uuid).await?;
+        match (iface.net_ns, state) {
+            (Some(ns_uuid), InterfaceAdminState::UP) => {
+                let ns_manager = self.get_ns_manager(&ns_uuid).await?;
+                ns_manager
+                    .set_virtual_interface_up(iface.if_name.clone())
+                    .await??;
+            }
+            (Some(ns_uuid), InterfaceAdminState::DOWN) => {
+                let ns_manager = self.get_ns_manager(&ns_uuid).await?;
+                ns_manager
+                    .set_virtual_interface_down(iface.if_name.clone())
+                    .await??;
+            }
+            (None, InterfaceAdminState::UP) => {
+                self.kernel().set_iface_up(iface.if_name.clone()).await?
+            }
+            (None, InterfaceAdminState::DOWN) => {
+                self.kernel().set_iface_down(iface.if_name.clone()).await?
+            }
+        }
+        let mut guard = self.state.write().await;
+        guard.interface_states.insert(intf_uuid, state);
+        drop(guard);
+        self.store_interface(&iface).await?;
+        Ok(iface)
+    }
+
+    /// Returns the last administrative state set through the plugin,
+    /// interfaces are brought up when created.
+    async fn get_virtual_interface_state(&self, intf_uuid: Uuid) -> FResult<InterfaceAdminState> {
+        self.connector.local.get_interface(intf_uuid).await?;
+        let guard = self.state.read().await;
+        Ok(*guard
+            .interface_states
+            .get(&intf_uuid)
+            .unwrap_or(&InterfaceAdminState::UP))
+    }
+
+    /// Sets STP priority and cost of an interface attached to a bridge,
+    /// the settings are kept in the journal and applied again when the
+    /// interface is recreated
+    async fn set_bridge_port_config(
+        &self,
+        intf_uuid: Uuid,
+        config: BridgePortConfig,
+    ) -> FResult<VirtualInterface> {
+        let iface = self.connector.local.get_interface(intf_uuid).await?;
+        if iface.parent.is_none() {
+            return Err(FError::NetworkingError(format!(
+                "{} is not attached to a bridge",
+                iface.if_name
+            )));
+        }
+        let mut merged = self
+            .state
+            .read()
+            .await
+            .bridge_ports
+            .get(&intf_uuid)
+            .cloned()
+            .unwrap_or_default();
+        if config.priority.is_some() {
+            merged.priority = config.priority;
+        }
+        if config.cost.is_some() {
+            merged.cost = config.cost;
+        }
+        self.apply_bridge_port_config(&iface, &config).await?;
+        self.state
+            .write()
+            .await
+            .bridge_ports
+            .insert(intf_uuid, merged.clone());
+        self.append_journal(JournalEntry::BridgePort(intf_uuid, merged))
+            .await;
+        Ok(iface)
+    }
+
+    async fn get_bridge_port_config(&self, intf_uuid: Uuid) -> FResult<BridgePortConfig> {
+        self.connector.local.get_interface(intf_uuid).await?;
+        let guard = self.state.read().await;
+        Ok(guard
+            .bridge_ports
+            .get(&intf_uuid)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    async fn set_interface_mtu(&self, intf_uuid: Uuid, mtu: u32) -> FResult<VirtualInterface> {
+        let iface = self.connector.local.get_interface(intf_uuid).await?;
+        self.set_interface_mtu_of(&iface, mtu).await?;
+        Ok(iface)
+    }
+
+    /// Looks up an interface record by its kernel name in the given namespace,
+    /// or in the default one when `ns_uuid` is `None`.
+    async fn find_interface_by_name(
+        &self,
+        if_name: String,
+        ns_uuid: Option<Uuid>,
+    ) -> FResult<VirtualInterface> {
+        for iface in self.known_interfaces().await {
+            if iface.if_name == if_name && iface.net_ns == ns_uuid {
+                return Ok(iface);
+            }
+        }
+        Err(FError::NotFound)
+    }
+
+    /// Looks up an interface record by MAC address, matching either the
+    /// address stored in the record or, for interfaces in the default
+    /// namespace, the one currently set in the kernel.
+    async fn find_interface_by_mac(&self, mac: MACAddress) -> FResult<VirtualInterface> {
+        for iface in self.known_interfaces().await {
+            if iface.phy_address == mac {
+                return Ok(iface);
+            }
+            if iface.net_ns.is_none() {
+                match self.kernel().get_iface_mac(iface.if_name.clone()).await {
+                    Ok(address) if address == mac.bytes().to_vec() => return Ok(iface),
+                    _ => continue,
+                }
+            }
+        }
+        Err(FError::NotFound)
+    }
+
+    /// Assigns an address allotted outside of fog05 to an interface
+    /// connected to the given virtual network, no DHCP client is used.
+    /// The interface has to be in the namespace of the network or in one
+    /// attached to it, never in the default one. The address is checked
+    /// against the IP configuration of the network and the addresses in
+    /// use on this node, then the routes through the gateway are added
+    /// and the resolvers are written for the namespace, they are removed
+    /// with the address.
+    async fn assign_external_address(
+        &self,
+        intf_uuid: Uuid,
+        vnet_uuid: Uuid,
+        external: ExternalAddress,
+    ) -> FResult<VirtualInterface> {
+        log::trace!(
+            "assign_external_address {} {} {:?}",
+            intf_uuid,
+            vnet_uuid,
+            external
+        );
+        let mut iface = self.connector.local.get_interface(intf_uuid).await?;
+        let vnet = self.connector.local.get_virtual_network(vnet_uuid).await?;
+        let ns_uuid = iface.net_ns.ok_or_else(|| {
+            FError::NetworkingError(format!(
+                "{} is in the default namespace, external addresses are not assigned there",
+                iface.if_name
+            ))
+        })?;
+        let internals =
+            deserialize_network_internals(vnet.plugin_internals.as_ref().ok_or(FError::NotFound)?)?;
+        let in_vnet = internals
+            .associated_netns
+            .as_ref()
+            .map_or(false, |netns| netns.ns_uuid == ns_uuid)
+            || internals
+                .namespace_attachments
+                .iter()
+                .any(|a| a.ns_uuid == ns_uuid);
+        if !in_vnet {
+            return Err(FError::NetworkingError(format!(
+                "{} is not in a namespace of the virtual network {}",
+                iface.if_name, vnet_uuid
+            )));
+        }
+        let in_use: Vec<IPAddress> = self
+            .known_interfaces()
+            .await
+            .into_iter()
+            .filter(|i| i.uuid != intf_uuid)
+            .flat_map(|i| i.addresses)
+            .collect();
+        validate_external_address(&external, vnet.ip_configuration.as_ref(), &in_use)?;
+
+        let mut routes = Vec::new();
+        if let Some(gw) = external.gateway {
+            let any = match gw {
+                IPAddress::V4(_) => IPAddress::V4(std::net::Ipv4Addr::UNSPECIFIED),
+                IPAddress::V6(_) => IPAddress::V6(std::net::Ipv6Addr::UNSPECIFIED),
+            };
+            routes.push((
+                IpNetwork::new(any, 0).map_err(|e| FError::NetworkingError(format!("{}", e)))?,
+                gw,
+            ));
+        }
+        for route in &external.routes {
+            routes.push((
+                IpNetwork::new(route.destination, route.prefix)
+                    .map_err(|e| FError::NetworkingError(format!("{}", e)))?,
+                route.gateway,
+            ));
+        }
+
+        let netns = self.connector.local.get_network_namespace(ns_uuid).await?;
+        let ns_manager = self.get_ns_manager(&ns_uuid).await?;
+        iface.addresses = ns_manager
+            .add_virtual_interface_address(iface.if_name.clone(), Some(external.address))
+            .await??;
+        let mut added = Vec::new();
+        let res = async {
+            for (destination, gateway) in routes {
+                ns_manager
+                    .add_virtual_interface_route(iface.if_name.clone(), destination, gateway)
+                    .await??;
+                added.push((destination, gateway));
+            }
+            if !external.dns_servers.is_empty() {
+                write_netns_resolv_conf(&netns.ns_name, intf_uuid, &external.dns_servers)?;
+            }
+            self.store_interface(&iface).await
+        }
+        .await;
+        if let Err(e) = res {
+            // the interface is left as it was before the assignment
+            for (destination, gateway) in added.into_iter().rev() {
+                if let Err(e) = ns_manager
+                    .del_virtual_interface_route(iface.if_name.clone(), destination, gateway)
+                    .await
+                    .and_then(|r| r)
+                {
+                    log::error!(
+                        "Unable to remove route {} via {} from {}: {}",
+                        destination,
+                        gateway,
+                        iface.if_name,
+                        e
+                    );
+                }
+            }
+            if let Err(e) = ns_manager
+                .del_virtual_interface_address(iface.if_name.clone(), external.address.ip())
+                .await
+                .and_then(|r| r)
+            {
+                log::error!(
+                    "Unable to remove {} from {}: {}",
+                    external.address,
+                    iface.if_name,
+                    e
+                );
+            }
+            return Err(e);
+        }
+        Ok(iface)
+    }
+
+    /// Adds default routes through an interface of a namespace, eg. an
+    /// IPv4 and an IPv6 one for dual-stack, or two of the same family
+    /// with different metrics. Routes with the same family and metric
+    /// are replaced.
+    async fn set_default_routes_in_network_namespace(
+        &self,
+        ns_uuid: Uuid,
+        intf_uuid: Uuid,
+        routes: Vec<DefaultRoute>,
+    ) -> FResult<()> {
+        log::trace!(
+            "set_default_routes_in_network_namespace {} {} {:?}",
+            ns_uuid,
+            intf_uuid,
+            routes
+        );
+        let iface = self.get_namespace_interface(ns_uuid, intf_uuid).await?;
+        let ns_manager = self.get_ns_manager(&ns_uuid).await?;
+        for route in routes {
+            ns_manager
+                .add_virtual_interface_default_route(iface.if_name.clone(), route)
+                .await??;
+        }
+        Ok(())
+    }
+
+    async fn del_default_route_in_network_namespace(
+        &self,
+        ns_uuid: Uuid,
+        intf_uuid: Uuid,
+        route: DefaultRoute,
+    ) -> FResult<()> {
+        let iface = self.get_namespace_interface(ns_uuid, intf_uuid).await?;
+        let ns_manager = self.get_ns_manager(&ns_uuid).await?;
+        ns_manager
+            .del_virtual_interface_default_route(iface.if_name, route)
+            .await?
+    }
+
+    /// Installs both default routes of the failover, the primary one
+    /// preferred, and starts probing its gateway.
+    async fn set_default_route_failover(
+        &self,
+        ns_uuid: Uuid,
+        failover: DefaultRouteFailover,
+    ) -> FResult<DefaultRouteFailoverStatus> {
+        log::trace!("set_default_route_failover {} {:?}", ns_uuid, failover);
+        if failover.primary.gateway.is_ipv4() != failover.backup.gateway.is_ipv4() {
+            return Err(FError::NetworkingError(
+                "The gateways of a failover have to be of the same family".to_string(),
+            ));
+        }
+        let old = self.state.write().await.route_failovers.remove(&ns_uuid);
+        if let Some((old, _)) = old {
+            self.clear_failover_routes(ns_uuid, &old).await;
+        }
+        self.apply_failover_routes(ns_uuid, &failover, true).await?;
+        let status = DefaultRouteFailoverStatus {
+            primary_active: true,
+            failures: 0,
+            switches: 0,
+        };
+        self.state
+            .write()
+            .await
+            .route_failovers
+            .insert(ns_uuid, (failover.clone(), status.clone()));
+        self.append_journal(JournalEntry::RouteFailover(ns_uuid, failover))
+            .await;
+        Ok(status)
+    }
+
+    /// Stops probing and removes both default routes of the failover
+    async fn remove_default_route_failover(&self, ns_uuid: Uuid) -> FResult<()> {
+        log::trace!("remove_default_route_failover {}", ns_uuid);
+        let removed = self.state.write().await.route_failovers.remove(&ns_uuid);
+        let (failover, _) = removed.ok_or(FError::NotFound)?;
+        self.append_journal(JournalEntry::RemoveRouteFailover(ns_uuid))
+            .await;
+        self.clear_failover_routes(ns_uuid, &failover).await;
+        Ok(())
+    }
+
+    async fn get_default_route_failover_status(
+        &self,
+        ns_uuid: Uuid,
+    ) -> FResult<DefaultRouteFailoverStatus> {
+        self.state
+            .read()
+            .await
+            .route_failovers
+            .get(&ns_uuid)
+            .map(|(_, status)| status.clone())
+            .ok_or(FError::NotFound)
+    }
+
+    /// Connects a virtual network to an existing namespace, eg. the one of
+    /// a connection point bound to another network, through a new veth
+    /// pair. The external end is attached to the bridge of the network and
+    /// the internal one, that is returned, is moved in the namespace.
+    /// The pair is recorded both in the namespace and in the internals of
+    /// the network.
+    async fn attach_network_to_namespace(
+        &self,
+        vnet_uuid: Uuid,
+        ns_uuid: Uuid,
+    ) -> FResult<VirtualInterface> {
+        log::trace!("attach_network_to_namespace {} {}", vnet_uuid, ns_uuid);
+        let mut vnet = self.connector.local.get_virtual_network(vnet_uuid).await?;
+        let mut internals =
+            deserialize_network_internals(vnet.plugin_internals.as_ref().ok_or(FError::NotFound)?)?;
+        if internals
+            .namespace_attachments
+            .iter()
+            .any(|a| a.ns_uuid == ns_uuid)
+        {
+            return Err(FError::AlreadyPresent);
+        }
+        let mut netns = self.connector.local.get_network_namespace(ns_uuid).await?;
+        let br_uuid = self.get_virtual_network_bridge(&vnet).await?;
+
+        let attachment = VNetNamespaceAttachment {
+            ns_uuid,
+            internal_veth: Uuid::new_v4(),
+            external_veth: Uuid::new_v4(),
+        };
+        let internal_veth_name = self.generate_random_interface_name();
+        let external_veth_name = self.generate_random_interface_name();
+        let v_veth_i = VirtualInterface {
+            uuid: attachment.internal_veth,
+            if_name: internal_veth_name.clone(),
+            net_ns: Some(ns_uuid),
+            parent: None,
             kind: VirtualInterfaceKind::VETH(VETHKind {
-                pair: external_veth_uuid,
+                pair: attachment.external_veth,
                 internal: true,
             }),
             addresses: Vec::new(),
             phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
         };
-
         let v_veth_e = VirtualInterface {
-            uuid: external_veth_uuid,
+            uuid: attachment.external_veth,
             if_name: external_veth_name.clone(),
             net_ns: None,
-            parent: Some(br_uuid),
+            parent: None,
             kind: VirtualInterfaceKind::VETH(VETHKind {
-                pair: internal_veth_uuid,
+                pair: attachment.internal_veth,
                 internal: false,
             }),
             addresses: Vec::new(),
             phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
         };
 
-        // Creating Virtual network bridge
+        self.kernel()
+            .create_veth(external_veth_name.clone(), internal_veth_name.clone())
+            .await?;
+        self.store_interface(&v_veth_e).await?;
+        self.store_interface(&v_veth_i).await?;
+        netns.interfaces.push(attachment.external_veth);
+        netns.interfaces.push(attachment.internal_veth);
+        self.store_network_namespace(&netns).await?;
+
+        let res: FResult<()> = async {
+            self.kernel()
+                .set_iface_up(external_veth_name.clone())
+                .await?;
+            self.kernel()
+                .set_iface_ns(internal_veth_name.clone(), netns.ns_name.clone())
+                .await?;
+            let ns_manager = self.get_ns_manager(&ns_uuid).await?;
+            ns_manager
+                .set_virtual_interface_up(internal_veth_name)
+                .await??;
+            self.attach_interface_to_bridge(attachment.external_veth, br_uuid)
+                .await?;
+            if !internals.mirrors.is_empty() {
+                self.set_iface_mirrors(&external_veth_name, &internals.mirrors)?;
+            }
+            Ok(())
+        }
+        .await;
+        if let Err(e) = res {
+            log::error!("Unable to attach {} to {}: {}", vnet_uuid, ns_uuid, e);
+            self.remove_namespace_attachment(&attachment).await?;
+            return Err(e);
+        }
 
-        self.create_bridge(br_name.clone()).await?;
-        self.connector.local.add_interface(&v_bridge).await?;
+        internals.namespace_attachments.push(attachment);
+        vnet.plugin_internals = Some(serialize_network_internals(&internals)?);
+        self.store_virtual_network(&vnet).await?;
+        self.connector.local.get_interface(v_veth_i.uuid).await
+    }
 
-        vnet.interfaces.push(br_uuid);
+    /// Removes the veth pair connecting a virtual network to a namespace,
+    /// from both the namespace and the internals of the network.
+    async fn detach_network_from_namespace(&self, vnet_uuid: Uuid, ns_uuid: Uuid) -> FResult<()> {
+        log::trace!("detach_network_from_namespace {} {}", vnet_uuid, ns_uuid);
+        let mut vnet = self.connector.local.get_virtual_network(vnet_uuid).await?;
+        let mut internals =
+            deserialize_network_internals(vnet.plugin_internals.as_ref().ok_or(FError::NotFound)?)?;
+        let pos = internals
+            .namespace_attachments
+            .iter()
+            .position(|a| a.ns_uuid == ns_uuid)
+            .ok_or(FError::NotConnected)?;
+        let attachment = internals.namespace_attachments.remove(pos);
+        self.remove_namespace_attachment(&attachment).await?;
+        vnet.plugin_internals = Some(serialize_network_internals(&internals)?);
+        self.store_virtual_network(&vnet).await
+    }
 
-        self.set_iface_up(br_name.clone()).await?;
+    async fn list_sriov_pfs(&self) -> FResult<Vec<SRIOVPhysicalFunction>> {
+        sriov::list_physical_functions(self.config.sriov_pfs.as_ref()).await
+    }
 
-        // Creating VXLAN Interface
+    /// Allocates a free VF, from the given PF if any, and applies
+    /// the configuration, the VF is then passed through by the hypervisor.
+    async fn allocate_vf(&self, pf: Option<String>, config: VFConfig) -> FResult<VirtualFunction> {
+        self.refresh_vf_pool().await?;
+        let (pf, index) = self.state.write().await.vf_pool.take(pf.as_deref())?;
+        let res = async {
+            self.kernel().set_vf_config(&pf, index, &config).await?;
+            sriov::get_vf_pci_address(&pf, index).await
+        }
+        .await;
+        match res {
+            Ok(pci_address) => {
+                let vf = VirtualFunction {
+                    uuid: Uuid::new_v4(),
+                    pf,
+                    index,
+                    pci_address,
+                    config,
+                };
+                self.state
+                    .write()
+                    .await
+                    .vf_pool
+                    .allocated
+                    .insert(vf.uuid, vf.clone());
+                self.append_journal(JournalEntry::VirtualFunction(vf.clone()))
+                    .await;
+                Ok(vf)
+            }
+            Err(e) => {
+                self.state.write().await.vf_pool.give_back(pf, index);
+                Err(e)
+            }
+        }
+    }
 
-        let overlay_iface_address = *self
-            .get_overlay_face_from_config()
-            .await?
-            .addresses
-            .first()
+    async fn get_vf(&self, vf_uuid: Uuid) -> FResult<VirtualFunction> {
+        let guard = self.state.read().await;
+        guard
+            .vf_pool
+            .allocated
+            .get(&vf_uuid)
+            .cloned()
+            .ok_or(FError::NotFound)
+    }
+
+    async fn release_vf(&self, vf_uuid: Uuid) -> FResult<VirtualFunction> {
+        let vf = self
+            .state
+            .write()
+            .await
+            .vf_pool
+            .allocated
+            .remove(&vf_uuid)
             .ok_or(FError::NotFound)?;
-        self.create_ptp_vxlan(
-            vxl_name.clone(),
-            self.get_overlay_iface().await?,
-            vxlan_info.vni,
-            overlay_iface_address,
-            vxlan_info.remote_addr,
-            vxlan_info.port,
-        )
-        .await?;
-        self.connector.local.add_interface(&vxl_iface).await?;
+        self.append_journal(JournalEntry::ReleaseVirtualFunction(vf_uuid))
+            .await;
+        if let Err(e) = self
+            .kernel()
+            .set_vf_config(&vf.pf, vf.index, &sriov::reset_config())
+            .await
+        {
+            log::warn!("Unable to reset VF {} of {}: {}", vf.index, vf.pf, e);
+        }
+        self.state
+            .write()
+            .await
+            .vf_pool
+            .give_back(vf.pf.clone(), vf.index);
+        Ok(vf)
+    }
 
-        vnet.interfaces.push(vxl_uuid);
+    /// Returns the version of an interface or virtual network record
+    /// as last written by this plugin.
+    async fn get_record_version(&self, record_uuid: Uuid) -> FResult<RecordVersion> {
+        let versions = self.record_versions.lock().await;
+        versions.get(&record_uuid).cloned().ok_or(FError::NotFound)
+    }
 
-        self.set_iface_master(vxl_name.clone(), br_name.clone())
-            .await?;
-        self.set_iface_up(vxl_name).await?;
+    async fn get_record_metadata(&self, record_uuid: Uuid) -> FResult<RecordMetadata> {
+        let guard = self.state.read().await;
+        guard
+            .record_metadata
+            .get(&record_uuid)
+            .cloned()
+            .ok_or(FError::NotFound)
+    }
 
-        // Creating netns and spawing the namespace manager
-        self.add_netns(associated_ns.ns_name.clone()).await?;
-        self.spawn_ns_manager(associated_ns.ns_name.clone(), associated_ns.uuid)
-            .await?;
+    /// Audit data of all the networks, interfaces, namespaces and
+    /// connection points managed by the plugin
+    async fn list_record_metadata(&self) -> FResult<HashMap<Uuid, RecordMetadata>> {
+        Ok(self.state.read().await.record_metadata.clone())
+    }
 
-        self.connector
-            .local
-            .add_network_namespace(&associated_ns)
-            .await?;
+    /// Creates an IPIP or SIT tunnel interface, in the default namespace
+    /// or inside the given namespace through its manager, and records it
+    /// as an interface of the node, and of the namespace.
+    async fn create_ip_tunnel(
+        &self,
+        if_name: String,
+        kind: IPTunnelKind,
+        local_addr: IPAddress,
+        remote_addr: IPAddress,
+        ttl: Option<u8>,
+        ns_uuid: Option<Uuid>,
+    ) -> FResult<IPTunnel> {
+        match (kind, local_addr, remote_addr) {
+            (_, IPAddress::V4(_), IPAddress::V4(_)) => (),
+            _ => {
+                return Err(FError::NetworkingError(
+                    "IPIP and SIT tunnels require IPv4 endpoints".to_string(),
+                ))
+            }
+        }
+        let tunnel = IPTunnel {
+            uuid: Uuid::new_v4(),
+            if_name,
+            kind,
+            local_addr,
+            remote_addr,
+            ttl,
+            net_ns: ns_uuid,
+        };
+        let netns = match ns_uuid {
+            Some(ns_uuid) => Some(self.connector.local.get_network_namespace(ns_uuid).await?),
+            None => None,
+        };
+        self.provision_ip_tunnel(&tunnel).await?;
+        let res = async {
+            self.store_interface(&ip_tunnel_interface(&tunnel)).await?;
+            match netns {
+                Some(mut netns) => {
+                    netns.interfaces.push(tunnel.uuid);
+                    self.store_network_namespace(&netns).await
+                }
+                None => Ok(()),
+            }
+        }
+        .await;
+        if let Err(e) = res {
+            if let Err(e) = self.deprovision_ip_tunnel(&tunnel).await {
+                log::warn!("Unable to remove the tunnel {}: {}", tunnel.if_name, e);
+            }
+            if self
+                .connector
+                .local
+                .get_interface(tunnel.uuid)
+                .await
+                .is_ok()
+            {
+                let _ = self.remove_interface_record(tunnel.uuid).await;
+            }
+            return Err(e);
+        }
+        self.append_journal(JournalEntry::IPTunnel(tunnel.clone()))
+            .await;
+        let mut guard = self.state.write().await;
+        guard.ip_tunnels.insert(tunnel.uuid, tunnel.clone());
+        Ok(tunnel)
+    }
 
-        // Creating veth pair
-        self.create_veth(external_veth_name.clone(), internal_veth_name.clone())
-            .await?;
+    async fn get_ip_tunnel(&self, tunnel_uuid: Uuid) -> FResult<IPTunnel> {
+        let guard = self.state.read().await;
+        guard
+            .ip_tunnels
+            .get(&tunnel_uuid)
+            .cloned()
+            .ok_or(FError::NotFound)
+    }
 
-        self.connector.local.add_interface(&v_veth_e).await?;
+    async fn delete_ip_tunnel(&self, tunnel_uuid: Uuid) -> FResult<IPTunnel> {
+        let tunnel = self.get_ip_tunnel(tunnel_uuid).await?;
+        self.deprovision_ip_tunnel(&tunnel).await?;
+        if let Some(ns_uuid) = tunnel.net_ns {
+            let mut netns = self.connector.local.get_network_namespace(ns_uuid).await?;
+            netns.interfaces.retain(|i| *i != tunnel_uuid);
+            self.store_network_namespace(&netns).await?;
+        }
+        self.remove_interface_record(tunnel_uuid).await?;
+        Ok(tunnel)
+    }
 
-        vnet.interfaces.push(internal_veth_uuid);
+    /// Provisions a port on the DPDK dataplane and records it as an
+    /// interface of the node.
+    /// vhost-user ports are added to the configured DPDK bridge, the
+    /// socket is placed under the run path and is created by the hypervisor
+    /// that uses it.
+    /// vDPA ports are created from the given management device.
+    async fn create_dataplane_port(
+        &self,
+        if_name: String,
+        kind: DataplanePortKind,
+    ) -> FResult<DataplanePort> {
+        let socket_path = match kind {
+            DataplanePortKind::VHOSTUSER => Some(
+                self.get_run_path()
+                    .join(format!("{}.sock", if_name))
+                    .to_str()
+                    .ok_or(FError::EncodingError)?
+                    .to_string(),
+            ),
+            DataplanePortKind::VDPA(_) => None,
+        };
+        let port = DataplanePort {
+            uuid: Uuid::new_v4(),
+            if_name,
+            kind,
+            socket_path,
+        };
+        self.provision_dataplane_port(&port)?;
+        let res = async {
+            let iface = self.dataplane_port_interface(&port)?;
+            self.store_interface(&iface).await
+        }
+        .await;
+        if let Err(e) = res {
+            if let Err(e) = self.deprovision_dataplane_port(&port).await {
+                log::warn!("Unable to remove the port {}: {}", port.if_name, e);
+            }
+            return Err(e);
+        }
+        self.append_journal(JournalEntry::DataplanePort(port.clone()))
+            .await;
+        let mut guard = self.state.write().await;
+        guard.dataplane_ports.insert(port.uuid, port.clone());
+        Ok(port)
+    }
+
+    async fn get_dataplane_port(&self, port_uuid: Uuid) -> FResult<DataplanePort> {
+        let guard = self.state.read().await;
+        guard
+            .dataplane_ports
+            .get(&port_uuid)
+            .cloned()
+            .ok_or(FError::NotFound)
+    }
+
+    async fn delete_dataplane_port(&self, port_uuid: Uuid) -> FResult<DataplanePort> {
+        let port = self.get_dataplane_port(port_uuid).await?;
+        self.deprovision_dataplane_port(&port).await?;
+        self.remove_interface_record(port_uuid).await?;
+        let mut guard = self.state.write().await;
+        guard.dataplane_ports.remove(&port_uuid);
+        Ok(port)
+    }
+
+    /// Allocates the subnet of a virtual network from an IPAM pool and
+    /// writes it in the global record, so it has to be done before the
+    /// network is created on the nodes. The allocation is done by the
+    /// coordinator, see `reserve_pool_subnet`.
+    async fn allocate_vnet_subnet(
+        &self,
+        vnet_uuid: Uuid,
+        pool: String,
+    ) -> FResult<IPConfiguration> {
+        let pool = self
+            .config
+            .ipam_pools
+            .as_ref()
+            .and_then(|pools| pools.iter().find(|p| p.name == pool))
+            .cloned()
+            .ok_or(FError::NotFound)?;
+        ipam::validate_pool(&pool)?;
+        match self.global_coordinator().await? {
+            Some(client) => Ok(client.reserve_pool_subnet(vnet_uuid, pool).await??),
+            None => self.reserve_pool_subnet(vnet_uuid, pool).await,
+        }
+    }
 
-        self.connector.local.add_interface(&v_veth_i).await?;
+    /// Allocates the subnet of a virtual network on the coordinator. The
+    /// subnets in use are the ones of the networks allocated from the
+    /// pools, published under `IPAM_SUBNETS_PATH`, and the ones of the
+    /// networks instantiated on the nodes.
+    async fn reserve_pool_subnet(
+        &self,
+        vnet_uuid: Uuid,
+        pool: IPAMPool,
+    ) -> FResult<IPConfiguration> {
+        let _guard = self.global_lock.lock().await;
+        if self.global_coordinator().await?.is_some() {
+            return Err(FError::NetworkingError(
+                "This plugin is not the coordinator".to_string(),
+            ));
+        }
+        let mut vnet = self.connector.global.get_virtual_network(vnet_uuid).await?;
+        let mut internals = global_network_internals(&vnet)?;
+        if let Some(conf) = vnet
+            .ip_configuration
+            .as_ref()
+            .filter(|c| c.subnet.is_some())
+        {
+            return match internals.ipam {
+                Some(ref allocations) if allocations.pool == pool.name => Ok(conf.clone()),
+                _ => Err(FError::AlreadyPresent),
+            };
+        }
+        if pool.network.0.is_ipv6() != matches!(vnet.ip_version, IPVersion::IPV6) {
+            return Err(FError::WrongKind);
+        }
+        let mut used = self.collect_ipam_subnets().await?;
+        used.extend(
+            self.list_pool_subnets()
+                .await?
+                .into_iter()
+                .map(|s| s.subnet),
+        );
+        let subnet = ipam::allocate_subnet(&pool, &used)?;
+        let conf = IPConfiguration {
+            subnet: Some(subnet),
+            gateway: Some(ipam::subnet_gateway(subnet)?),
+            dhcp_range: None,
+            dns: vnet.ip_configuration.as_ref().and_then(|c| c.dns.clone()),
+        };
+        internals.ipam = Some(VNetIPAM {
+            pool: pool.name.clone(),
+            range_size: pool
+                .node_range_size
+                .unwrap_or(ipam::DEFAULT_NODE_RANGE_SIZE),
+            node_ranges: Vec::new(),
+            addresses: Vec::new(),
+        });
+        internals.global_version += 1;
+        vnet.ip_configuration = Some(conf.clone());
+        vnet.plugin_internals = Some(serialize_network_internals(&internals)?);
+        self.connector.global.add_virutal_network(&vnet).await?;
+        self.publish_pool_subnet(
+            vnet_uuid,
+            &IPAMSubnet {
+                pool: pool.name.clone(),
+                subnet,
+            },
+        )
+        .await?;
+        log::info!(
+            "Allocated {}/{} from {} to {}",
+            subnet.0,
+            subnet.1,
+            pool.name,
+            vnet_uuid
+        );
+        Ok(conf)
+    }
+
+    /// Subnets of the virtual networks instantiated on this node
+    async fn list_ipam_subnets(&self) -> FResult<Vec<(IPAddress, u8)>> {
+        let mut subnets = Vec::new();
+        for vnet in self.list_virtual_networks().await? {
+            let secondary = vnet
+                .plugin_internals
+                .as_ref()
+                .and_then(|raw| deserialize_network_internals(raw).ok())
+                .and_then(|i| i.secondary_ip_configuration);
+            subnets.extend(
+                vnet.ip_configuration
+                    .iter()
+                    .chain(secondary.iter())
+                    .filter_map(|c| c.subnet),
+            );
+        }
+        Ok(subnets)
+    }
+
+    async fn get_ipam_allocations(&self, vnet_uuid: Uuid) -> FResult<VNetIPAM> {
+        let vnet = self.connector.global.get_virtual_network(vnet_uuid).await?;
+        global_network_internals(&vnet)?
+            .ipam
+            .ok_or(FError::NotFound)
+    }
+
+    /// Hands out an address of the subnet of a virtual network outside
+    /// the DHCP ranges of the nodes, it stays in the global record until
+    /// it is released
+    async fn allocate_address(&self, vnet_uuid: Uuid, owner: Option<String>) -> FResult<IPAddress> {
+        let node_uuid = self.agent.as_ref().unwrap().get_node_uuid().await??;
+        let address = self
+            .update_global_ipam(vnet_uuid, |allocations, subnet, gateway| {
+                let address = ipam::allocate_address(subnet, gateway, allocations)?;
+                allocations.addresses.push(IPAMAddress {
+                    address,
+                    node_uuid,
+                    owner: owner.clone(),
+                });
+                Ok(address)
+            })
+            .await?;
+        log::debug!("Allocated {} in {}", address, vnet_uuid);
+        Ok(address)
+    }
+
+    async fn release_address(&self, vnet_uuid: Uuid, address: IPAddress) -> FResult<()> {
+        self.update_global_ipam(vnet_uuid, |allocations, _, _| {
+            let before = allocations.addresses.len();
+            allocations.addresses.retain(|a| a.address != address);
+            if allocations.addresses.len() == before {
+                return Err(FError::NotFound);
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_simulated_topology(&self) -> FResult<SimulatedKernel> {
+        simulation::snapshot().ok_or_else(|| {
+            FError::NetworkingError("The plugin is not running with --simulate".to_string())
+        })
+    }
+
+    async fn get_dhcp_client(&self, intf_uuid: Uuid) -> FResult<DHCPClient> {
+        let guard = self.state.read().await;
+        let mut client = guard
+            .dhcp_clients
+            .get(&intf_uuid)
+            .cloned()
+            .ok_or(FError::NotFound)?;
+        // renewed in the background
+        let lease = guard
+            .dhcp_client_handles
+            .get(&intf_uuid)
+            .map(|handle| handle.shared_lease());
+        drop(guard);
+        if let Some(lease) = lease {
+            client.lease = Some(lease.read().await.clone());
+        }
+        Ok(client)
+    }
+
+    async fn renew_dhcp_lease(&self, intf_uuid: Uuid) -> FResult<VirtualInterface> {
+        let mut iface = self.connector.local.get_interface(intf_uuid).await?;
+        let client = self.stop_dhcp_client(intf_uuid, false).await?;
+        self.start_dhcp_client(intf_uuid, client.if_name, client.ns_name)
+            .await?;
+        iface.addresses = self.read_interface_addresses(&iface).await?;
+        self.store_interface(&iface).await?;
+        self.refresh_iface_anti_spoofing(&iface).await?;
+        Ok(iface)
+    }
+
+    async fn get_log_settings(&self) -> FResult<LogSettings> {
+        logging::settings()
+    }
+
+    async fn set_log_level(&self, target: String, level: Option<LogLevel>) -> FResult<()> {
+        log::info!("Log level of {} set to {:?}", target, level);
+        logging::set_level(&target, level)
+    }
+
+    async fn set_log_trace_sampling(&self, target: String, rate: Option<u32>) -> FResult<()> {
+        log::info!("Trace sampling of {} set to {:?}", target, rate);
+        logging::set_trace_sampling(&target, rate)
+    }
+
+    async fn diff_network(&self, vnet_uuid: Uuid) -> FResult<NetworkDiff> {
+        let desired = self
+            .connector
+            .global
+            .get_virtual_network(vnet_uuid)
+            .await
+            .ok();
+        let recorded = self
+            .connector
+            .local
+            .get_virtual_network(vnet_uuid)
+            .await
+            .ok();
+        if desired.is_none() && recorded.is_none() {
+            return Err(FError::NotFound);
+        }
+        let desired_fields = desired
+            .as_ref()
+            .map(network_fields)
+            .transpose()?
+            .unwrap_or_default();
+        let recorded_fields = recorded
+            .as_ref()
+            .map(network_fields)
+            .transpose()?
+            .unwrap_or_default();
+        let names: BTreeSet<&String> = desired_fields
+            .keys()
+            .chain(recorded_fields.keys())
+            .collect();
+        let fields = names
+            .into_iter()
+            .filter(|name| desired_fields.get(*name) != recorded_fields.get(*name))
+            .map(|name| NetworkFieldDiff {
+                field: name.clone(),
+                desired: desired_fields.get(name).map(|v| v.to_string()),
+                recorded: recorded_fields.get(name).map(|v| v.to_string()),
+            })
+            .collect();
+        let kernel = match &recorded {
+            Some(vnet) => self.kernel_network_diff(vnet).await?,
+            None => Vec::new(),
+        };
+        Ok(NetworkDiff {
+            vnet_uuid,
+            has_descriptor: desired.is_some(),
+            has_record: recorded.is_some(),
+            fields,
+            kernel,
+        })
+    }
+
+    async fn create_virtual_network_async(&self, vnet_uuid: Uuid) -> FResult<Uuid> {
+        let op = AsyncOperation {
+            uuid: Uuid::new_v4(),
+            operation: "create_virtual_network".to_string(),
+            target: vnet_uuid,
+            status: AsyncOperationStatus::RUNNING,
+            step: CreateStep::DESCRIPTOR as u32,
+            steps: CreateStep::COUNT,
+            description: CreateStep::DESCRIPTOR.description().to_string(),
+            error: None,
+            started_at: now_ms(),
+            finished_at: None,
+        };
+        let mut guard = self.state.write().await;
+        if guard
+            .async_operations
+            .values()
+            .any(|o| o.target == vnet_uuid && o.status == AsyncOperationStatus::RUNNING)
+        {
+            return Err(FError::AlreadyPresent);
+        }
+        guard.async_operations.insert(op.uuid, op.clone());
+        drop(guard);
+        self.publish_operation(&op).await;
+
+        let plugin = self.clone();
+        let op_uuid = op.uuid;
+        runtime::spawn(async move {
+            use futures::future::FutureExt;
+            use std::panic::AssertUnwindSafe;
+            // a panic would otherwise leave the operation running forever
+            let res = AssertUnwindSafe(plugin.create_virtual_network(vnet_uuid))
+                .catch_unwind()
+                .await;
+            let error = match res {
+                Ok(res) => res.err(),
+                Err(_) => Some(FError::NetworkingError(format!(
+                    "Creation of {} panicked",
+                    vnet_uuid
+                ))),
+            };
+            plugin.finish_operation(op_uuid, error).await;
+        });
+        Ok(op_uuid)
+    }
+
+    async fn get_async_operation(&self, op_uuid: Uuid) -> FResult<AsyncOperation> {
+        self.state
+            .read()
+            .await
+            .async_operations
+            .get(&op_uuid)
+            .cloned()
+            .ok_or(FError::NotFound)
+    }
+
+    async fn list_async_operations(&self) -> FResult<Vec<AsyncOperation>> {
+        let mut ops: Vec<AsyncOperation> = self
+            .state
+            .read()
+            .await
+            .async_operations
+            .values()
+            .cloned()
+            .collect();
+        ops.sort_by_key(|op| op.started_at);
+        Ok(ops)
+    }
+
+    async fn get_firewall_state(&self, vnet_uuid: Uuid) -> FResult<FirewallState> {
+        let vnet = self.connector.local.get_virtual_network(vnet_uuid).await?;
+        let mut tables = Vec::new();
+        if let Some(raw) = &vnet.plugin_internals {
+            let internals = deserialize_network_internals(raw)?;
+            for table in &internals.associated_tables {
+                tables.push(self.read_firewall_table(table, "inet", FirewallTableKind::NAT, None));
+            }
+            for floating_ip in &internals.floating_ips {
+                tables.push(self.read_firewall_table(
+                    &floating_ip.table,
+                    "inet",
+                    FirewallTableKind::FLOATINGIP,
+                    None,
+                ));
+            }
+            for mapping in &internals.address_mappings {
+                tables.push(self.read_firewall_table(
+                    &mapping.table,
+                    "inet",
+                    FirewallTableKind::ADDRESSMAPPING,
+                    None,
+                ));
+            }
+            if let (Some(nat64), Some(ns_info)) =
+                (&internals.nat64_gateway, &internals.associated_netns)
+            {
+                tables.push(self.read_firewall_table(
+                    &nat64.nft_table,
+                    "ip",
+                    FirewallTableKind::NAT64,
+                    Some(ns_info.ns_name.clone()),
+                ));
+            }
+        }
+        Ok(FirewallState { vnet_uuid, tables })
+    }
+
+    /// Sets or removes the rate limits of a connection point, applied
+    /// immediately if the connection point is bound to a virtual network.
+    async fn set_connection_point_rate_limit(
+        &self,
+        cp_uuid: Uuid,
+        limit: Option<ConnectionPointRateLimit>,
+    ) -> FResult<ConnectionPoint> {
+        if let Some(ref limit) = limit {
+            validate_rate_limit(limit)?;
+        }
+        let cp = self.connector.local.get_connection_point(cp_uuid).await?;
+        let ext_veth = self.connector.local.get_interface(cp.external_veth).await?;
+        let mut guard = self.state.write().await;
+        let previous = match limit {
+            Some(ref limit) => guard.cp_rate_limits.insert(cp_uuid, limit.clone()),
+            None => guard.cp_rate_limits.remove(&cp_uuid),
+        };
+        drop(guard);
+        match limit {
+            Some(ref limit) => {
+                self.append_journal(JournalEntry::ConnectionPointRateLimit(
+                    cp_uuid,
+                    limit.clone(),
+                ))
+                .await
+            }
+            None if previous.is_some() => {
+                self.append_journal(JournalEntry::RemoveConnectionPointRateLimit(cp_uuid))
+                    .await
+            }
+            None => (),
+        }
+        // the veth has a master only while the CP is bound
+        if ext_veth.parent.is_some() {
+            match (limit, previous) {
+                (Some(limit), _) => {
+                    self.set_iface_rate_limit(cp_uuid, &ext_veth.if_name, &limit)?
+                }
+                (None, Some(_)) => self.del_iface_rate_limit(cp_uuid)?,
+                (None, None) => (),
+            }
+        }
+        Ok(cp)
+    }
+
+    async fn get_connection_point_rate_limit(
+        &self,
+        cp_uuid: Uuid,
+    ) -> FResult<Option<ConnectionPointRateLimit>> {
+        self.connector.local.get_connection_point(cp_uuid).await?;
+        Ok(self
+            .state
+            .read()
+            .await
+            .cp_rate_limits
+            .get(&cp_uuid)
+            .cloned())
+    }
+
+    /// Appends a rule filtering the traffic of the network by destination
+    async fn add_egress_rule(&self, vnet_uuid: Uuid, rule: EgressRule) -> FResult<VirtualNetwork> {
+        let mut vnet = self.connector.local.get_virtual_network(vnet_uuid).await?;
+        let mut internals =
+            deserialize_network_internals(vnet.plugin_internals.as_ref().ok_or(FError::NotFound)?)?;
+        let nets = egress_networks(&vnet, &internals)?;
+        validate_egress_rule(&rule, &nets)?;
+        if internals.egress_rules.contains(&rule) {
+            return Err(FError::AlreadyPresent);
+        }
+        internals.egress_rules.push(rule);
+        self.apply_egress_rules(&vnet, &internals).await?;
+        vnet.plugin_internals = Some(serialize_network_internals(&internals)?);
+        self.store_virtual_network(&vnet).await?;
+        Ok(vnet)
+    }
+
+    async fn remove_egress_rule(
+        &self,
+        vnet_uuid: Uuid,
+        rule: EgressRule,
+    ) -> FResult<VirtualNetwork> {
+        let mut vnet = self.connector.local.get_virtual_network(vnet_uuid).await?;
+        let mut internals =
+            deserialize_network_internals(vnet.plugin_internals.as_ref().ok_or(FError::NotFound)?)?;
+        let pos = internals
+            .egress_rules
+            .iter()
+            .position(|r| *r == rule)
+            .ok_or(FError::NotFound)?;
+        internals.egress_rules.remove(pos);
+        self.apply_egress_rules(&vnet, &internals).await?;
+        vnet.plugin_internals = Some(serialize_network_internals(&internals)?);
+        self.store_virtual_network(&vnet).await?;
+        Ok(vnet)
+    }
+
+    async fn list_egress_rules(&self, vnet_uuid: Uuid) -> FResult<Vec<EgressRule>> {
+        let vnet = self.connector.local.get_virtual_network(vnet_uuid).await?;
+        let internals =
+            deserialize_network_internals(vnet.plugin_internals.as_ref().ok_or(FError::NotFound)?)?;
+        Ok(internals.egress_rules)
+    }
+
+    /// Maps an address of the node to an address of the network, like a
+    /// floating IP but not tied to a connection point: the external
+    /// address is added to the overlay interface, traffic to it is DNATed
+    /// to the internal address and traffic from the internal address
+    /// leaving from the overlay interface is SNATed to it.
+    async fn map_address(
+        &self,
+        vnet_uuid: Uuid,
+        internal_ip: IPAddress,
+        external_ip: IPAddress,
+    ) -> FResult<AddressMapping> {
+        let mut vnet = self.connector.local.get_virtual_network(vnet_uuid).await?;
+        let mut internals =
+            deserialize_network_internals(vnet.plugin_internals.as_ref().ok_or(FError::NotFound)?)?;
+        // management and provider networks are never NATed
+        if vnet.is_mgmt || internals.provider_vlan.is_some() {
+            return Err(FError::WrongKind);
+        }
+        if internal_ip.is_ipv4() != external_ip.is_ipv4() {
+            return Err(FError::NetworkingError(format!(
+                "External address {} and internal address {} are of different families",
+                external_ip, internal_ip
+            )));
+        }
+        let subnet = subnet_of(
+            &vnet,
+            internals.secondary_ip_configuration.as_ref(),
+            internal_ip,
+        )
+        .ok_or_else(|| {
+            FError::NetworkingError(format!(
+                "{} is not in the subnets of the network",
+                internal_ip
+            ))
+        })?;
+        if internals
+            .address_mappings
+            .iter()
+            .any(|m| m.internal_ip == internal_ip || m.external_ip == external_ip)
+            || internals
+                .floating_ips
+                .iter()
+                .any(|f| f.public_ip == external_ip)
+        {
+            return Err(FError::AlreadyPresent);
+        }
+        let iface = self.get_overlay_face_from_config().await?.if_name;
+        let table = self
+            .configure_static_nat(external_ip, internal_ip, &iface, Some(subnet))
+            .await?;
+        let mapping = AddressMapping {
+            internal_ip,
+            external_ip,
+            iface,
+            table,
+        };
+        internals.address_mappings.push(mapping.clone());
+        vnet.plugin_internals = Some(serialize_network_internals(&internals)?);
+        self.store_virtual_network(&vnet).await?;
+        Ok(mapping)
+    }
+
+    async fn unmap_address(
+        &self,
+        vnet_uuid: Uuid,
+        external_ip: IPAddress,
+    ) -> FResult<AddressMapping> {
+        let mut vnet = self.connector.local.get_virtual_network(vnet_uuid).await?;
+        let mut internals =
+            deserialize_network_internals(vnet.plugin_internals.as_ref().ok_or(FError::NotFound)?)?;
+        let pos = internals
+            .address_mappings
+            .iter()
+            .position(|m| m.external_ip == external_ip)
+            .ok_or(FError::NotFound)?;
+        let mapping = internals.address_mappings.remove(pos);
+        self.remove_static_nat(&mapping.table, mapping.iface.clone(), external_ip)
+            .await?;
+        // flows to and from the external address
+        flush_conntrack(&["-d".to_string(), format!("{}", external_ip)]);
+        flush_conntrack(&["-q".to_string(), format!("{}", external_ip)]);
+        vnet.plugin_internals = Some(serialize_network_internals(&internals)?);
+        self.store_virtual_network(&vnet).await?;
+        Ok(mapping)
+    }
+
+    async fn list_address_mappings(&self, vnet_uuid: Uuid) -> FResult<Vec<AddressMapping>> {
+        let vnet = self.connector.local.get_virtual_network(vnet_uuid).await?;
+        let internals =
+            deserialize_network_internals(vnet.plugin_internals.as_ref().ok_or(FError::NotFound)?)?;
+        Ok(internals.address_mappings)
+    }
+
+    /// Flows of the subnets of a virtual network in the conntrack table of
+    /// the default namespace, where the traffic of the network is NATed
+    async fn get_conntrack_stats(&self, vnet_uuid: Uuid) -> FResult<ConntrackStats> {
+        let vnet = self.connector.local.get_virtual_network(vnet_uuid).await?;
+        let secondary = match &vnet.plugin_internals {
+            Some(raw) => deserialize_network_internals(raw)?.secondary_ip_configuration,
+            None => None,
+        };
+        let subnets: Vec<IpNetwork> = vnet
+            .ip_configuration
+            .iter()
+            .chain(secondary.iter())
+            .filter_map(|c| c.subnet)
+            .filter_map(|(addr, prefix)| IpNetwork::new(addr, prefix).ok())
+            .collect();
+        let mut listing = String::new();
+        for net in &subnets {
+            let family = if net.is_ipv6() { "ipv6" } else { "ipv4" };
+            // opened from the subnet, then forwarded to it
+            for filter in &["--src", "--reply-src"] {
+                let mut cmd = Command::new("conntrack");
+                cmd.arg("-L")
+                    .arg("-f")
+                    .arg(family)
+                    .arg(filter)
+                    .arg(net.to_string());
+                listing.push_str(&exec_command(cmd)?);
+            }
+        }
+        Ok(conntrack_stats(vnet_uuid, &subnets, &listing))
+    }
+
+    /// Counters of an interface from the dump of the last monitoring cycle,
+    /// with the time they were collected
+    async fn get_interface_statistics(&self, intf_uuid: Uuid) -> FResult<InterfaceStatistics> {
+        let iface = self.connector.local.get_interface(intf_uuid).await?;
+        self.cached_interface_statistics(&iface).await
+    }
+
+    /// Status published by the last monitoring cycle, collected now when
+    /// no cycle completed yet
+    async fn get_node_networking_status(&self) -> FResult<NodeNetworkingStatus> {
+        let published = self.state.read().await.node_status.clone();
+        match published {
+            Some(status) => Ok(status),
+            None => Ok(self.collect_node_status().await),
+        }
+    }
+
+    /// Checks netlink, the namespace managers, the DHCP servers and the
+    /// recorded interfaces now, a failing component does not stop the
+    /// others from being checked
+    async fn healthcheck(&self) -> FResult<HealthReport> {
+        let mut components = Vec::new();
+        let mut dumps = HashMap::new();
+
+        match self.dump_namespace_link_stats(None).await {
+            Ok(dump) => {
+                dumps.insert(None, dump);
+                components.push(ComponentHealth {
+                    component: "netlink".to_string(),
+                    healthy: true,
+                    detail: None,
+                });
+            }
+            Err(e) => components.push(ComponentHealth {
+                component: "netlink".to_string(),
+                healthy: false,
+                detail: Some(format!("{}", e)),
+            }),
+        }
+
+        let ns_managers: Vec<(Uuid, NamespaceManagerChannel)> = self
+            .state
+            .read()
+            .await
+            .ns_managers
+            .iter()
+            .map(|(ns_uuid, (_, channel))| (*ns_uuid, channel.clone()))
+            .collect();
+        for (ns_uuid, ns_manager) in ns_managers {
+            let verified = async_std::future::timeout(
+                Duration::from_secs(HEALTHCHECK_NS_MANAGER_TIMEOUT),
+                ns_manager.verify_server(),
+            )
+            .await;
+            let detail = match verified {
+                Ok(Ok(true)) => match self.dump_namespace_link_stats(Some(ns_uuid)).await {
+                    Ok(dump) => {
+                        dumps.insert(Some(ns_uuid), dump);
+                        None
+                    }
+                    Err(e) => Some(format!("Unable to dump the links: {}", e)),
+                },
+                Ok(Ok(false)) => Some("Server not verified".to_string()),
+                Ok(Err(e)) => Some(format!("{}", e)),
+                Err(_) => Some("Timeout".to_string()),
+            };
+            components.push(ComponentHealth {
+                component: format!("ns_manager/{}", ns_uuid),
+                healthy: detail.is_none(),
+                detail,
+            });
+        }
+
+        let vnets = match self.list_virtual_networks().await {
+            Ok(vnets) => vnets,
+            Err(e) => {
+                components.push(ComponentHealth {
+                    component: "virtual_networks".to_string(),
+                    healthy: false,
+                    detail: Some(format!("{}", e)),
+                });
+                Vec::new()
+            }
+        };
+        for vnet in vnets {
+            match self.check_vnet_dhcp(&vnet).await {
+                Ok((_, Some(running))) => components.push(ComponentHealth {
+                    component: format!("dhcp/{}", vnet.uuid),
+                    healthy: running,
+                    detail: if running {
+                        None
+                    } else {
+                        Some("DHCP server is not running".to_string())
+                    },
+                }),
+                Ok((_, None)) => (),
+                Err(e) => components.push(ComponentHealth {
+                    component: format!("virtual_network/{}", vnet.uuid),
+                    healthy: false,
+                    detail: Some(format!("Unable to read the internals: {}", e)),
+                }),
+            }
+        }
+
+        let interfaces = self.known_links().await;
+        let (missing, _) = check_interface_links(&interfaces, &dumps);
+        for iface in interfaces {
+            // reported with the namespace manager or netlink
+            if !dumps.contains_key(&iface.net_ns) {
+                continue;
+            }
+            let present = !missing.contains(&iface.uuid);
+            components.push(ComponentHealth {
+                component: format!("interface/{}", iface.uuid),
+                healthy: present,
+                detail: if present {
+                    None
+                } else {
+                    Some(format!("Link {} not found", iface.if_name))
+                },
+            });
+        }
+
+        Ok(HealthReport {
+            checked_ms: now_ms(),
+            healthy: components.iter().all(|c| c.healthy),
+            components,
+        })
+    }
+
+    /// Traffic routed into and out of a virtual network since its bridge
+    /// was registered, traffic between its own ports is not counted
+    async fn get_vnet_traffic(&self, vnet_uuid: Uuid) -> FResult<VNetTraffic> {
+        self.connector.local.get_virtual_network(vnet_uuid).await?;
+        let (counter_in, counter_out) = traffic_counters(vnet_uuid);
+        let (packets_in, bytes_in) = self.read_traffic_counter(vnet_uuid, &counter_in)?;
+        let (packets_out, bytes_out) = self.read_traffic_counter(vnet_uuid, &counter_out)?;
+        Ok(VNetTraffic {
+            vnet_uuid,
+            collected_ms: now_ms(),
+            packets_in,
+            bytes_in,
+            packets_out,
+            bytes_out,
+        })
+    }
+
+    /// Starts exporting the flows of the bridge of a network to
+    /// `flow_collector`, `sampling_rate` overrides `flow_sampling_rate`
+    async fn enable_flow_export(
+        &self,
+        vnet_uuid: Uuid,
+        sampling_rate: Option<u32>,
+    ) -> FResult<VNetFlowExport> {
+        if !cfg!(feature = "flow-export") {
+            return Err(FError::Unimplemented);
+        }
+        let collector =
+            self.config.flow_collector.clone().ok_or_else(|| {
+                FError::NetworkingError("No flow collector configured".to_string())
+            })?;
+        validate_flow_collector(&collector)?;
+        let sampling_rate = sampling_rate
+            .or(self.config.flow_sampling_rate)
+            .unwrap_or(1);
+        if sampling_rate == 0 {
+            return Err(FError::NetworkingError(
+                "Invalid sampling rate 0".to_string(),
+            ));
+        }
+        let mut vnet = self.connector.local.get_virtual_network(vnet_uuid).await?;
+        let mut internals =
+            deserialize_network_internals(vnet.plugin_internals.as_ref().ok_or(FError::NotFound)?)?;
+        if internals.flow_export.is_some() {
+            return Err(FError::AlreadyPresent);
+        }
+        let br_uuid = self.get_virtual_network_bridge(&vnet).await?;
+        let bridge = self.connector.local.get_interface(br_uuid).await?;
+        let file_path = |ext: &str| -> FResult<String> {
+            Ok(self
+                .get_run_path()
+                .join(format!("{}-flows.{}", vnet_uuid, ext))
+                .to_str()
+                .ok_or(FError::EncodingError)?
+                .to_string())
+        };
+        let flow_export = VNetFlowExport {
+            bridge: bridge.if_name,
+            collector,
+            sampling_rate,
+            pid_file: file_path("pid")?,
+            ctl_socket: file_path("ctl")?,
+        };
+        self.start_flow_export(&flow_export).await?;
+        internals.flow_export = Some(flow_export.clone());
+        vnet.plugin_internals = Some(serialize_network_internals(&internals)?);
+        if let Err(e) = self.store_virtual_network(&vnet).await {
+            let _ = self.stop_flow_export(&flow_export).await;
+            return Err(e);
+        }
+        Ok(flow_export)
+    }
+
+    async fn disable_flow_export(&self, vnet_uuid: Uuid) -> FResult<VirtualNetwork> {
+        let mut vnet = self.connector.local.get_virtual_network(vnet_uuid).await?;
+        let mut internals =
+            deserialize_network_internals(vnet.plugin_internals.as_ref().ok_or(FError::NotFound)?)?;
+        let flow_export = internals.flow_export.take().ok_or(FError::NotFound)?;
+        self.stop_flow_export(&flow_export).await?;
+        vnet.plugin_internals = Some(serialize_network_internals(&internals)?);
+        self.store_virtual_network(&vnet).await?;
+        Ok(vnet)
+    }
+}
+
+/// Fields of a virtual network by name, the plugin internals are decoded
+/// and compared one by one. Null and empty values are left out, the
+/// records written by the plugin carry them while descriptors do not.
+fn network_fields(vnet: &VirtualNetwork) -> FResult<BTreeMap<String, serde_json::Value>> {
+    let is_set = |value: &serde_json::Value| match value {
+        serde_json::Value::Null => false,
+        serde_json::Value::Array(values) => !values.is_empty(),
+        serde_json::Value::Object(values) => !values.is_empty(),
+        _ => true,
+    };
+    let mut fields = BTreeMap::new();
+    if let serde_json::Value::Object(values) =
+        serde_json::to_value(vnet).map_err(|e| FError::NetworkingError(format!("{}", e)))?
+    {
+        fields.extend(
+            values
+                .into_iter()
+                .filter(|(name, value)| name != "plugin_internals" && is_set(value)),
+        );
+    }
+    if let Some(raw) = &vnet.plugin_internals {
+        match serde_json::from_slice(raw) {
+            Ok(serde_json::Value::Object(values)) => fields.extend(
+                values
+                    .into_iter()
+                    .filter(|(_, value)| is_set(value))
+                    .map(|(name, value)| (format!("plugin_internals.{}", name), value)),
+            ),
+            _ => {
+                fields.insert(
+                    "plugin_internals".to_string(),
+                    serde_json::Value::String(String::from_utf8_lossy(raw).to_string()),
+                );
+            }
+        }
+    }
+    Ok(fields)
+}
+
+/// Computes the etag of a record from its content,
+/// FNV-1a keeps it stable across builds and nodes
+fn record_etag<T: Serialize>(record: &T) -> FResult<String> {
+    let data = serde_json::to_vec(record).map_err(|e| FError::NetworkingError(format!("{}", e)))?;
+    let hash = data.iter().fold(0xcbf2_9ce4_8422_2325u64, |hash, b| {
+        (hash ^ u64::from(*b)).wrapping_mul(0x0100_0000_01b3)
+    });
+    Ok(format!("{:016x}", hash))
+}
+
+/// Encrypts a message of `node_uuid`, the node UUID is authenticated
+/// along with it. Returns the nonce and the ciphertext
+fn seal_message(
+    cipher: &ChaCha20Poly1305,
+    node_uuid: Uuid,
+    msg: &[u8],
+) -> FResult<(Vec<u8>, Vec<u8>)> {
+    let nonce: [u8; 12] = rand::random();
+    let ciphertext = cipher
+        .encrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg,
+                aad: node_uuid.as_bytes(),
+            },
+        )
+        .map_err(|_| FError::NetworkingError("Unable to encrypt the message".to_string()))?;
+    Ok((nonce.to_vec(), ciphertext))
+}
+
+/// Decrypts a message, failing when it was not sealed with the same key
+/// or by another node than the one it claims
+fn open_message(
+    cipher: &ChaCha20Poly1305,
+    node_uuid: Uuid,
+    nonce: &[u8],
+    ciphertext: &[u8],
+) -> FResult<Vec<u8>> {
+    if nonce.len() != 12 {
+        return Err(FError::NetworkingError("Invalid nonce".to_string()));
+    }
+    cipher
+        .decrypt(
+            Nonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad: node_uuid.as_bytes(),
+            },
+        )
+        .map_err(|_| {
+            FError::NetworkingError(format!(
+                "Unable to authenticate the message of {}",
+                node_uuid
+            ))
+        })
+}
+
+/// Encrypts a replica, the node UUID is authenticated along with it
+fn seal_replica(cipher: &ChaCha20Poly1305, replica: &NodeReplica) -> FResult<SealedReplica> {
+    let msg = serde_json::to_vec(replica).map_err(|e| FError::NetworkingError(format!("{}", e)))?;
+    let (nonce, ciphertext) = seal_message(cipher, replica.node_uuid, &msg)?;
+    Ok(SealedReplica {
+        node_uuid: replica.node_uuid,
+        nonce,
+        ciphertext,
+    })
+}
+
+/// Decrypts a replica, failing when it was not sealed with the same key
+/// or by another node than the one it claims
+fn open_replica(cipher: &ChaCha20Poly1305, sealed: &SealedReplica) -> FResult<NodeReplica> {
+    let msg = open_message(cipher, sealed.node_uuid, &sealed.nonce, &sealed.ciphertext)?;
+    let replica: NodeReplica =
+        serde_json::from_slice(&msg).map_err(|e| FError::NetworkingError(format!("{}", e)))?;
+    if replica.node_uuid != sealed.node_uuid {
+        return Err(FError::WrongKind);
+    }
+    Ok(replica)
+}
+
+/// Encrypts a write of global internals asked by `node_uuid` to the
+/// coordinator
+fn seal_global_swap(
+    cipher: &ChaCha20Poly1305,
+    node_uuid: Uuid,
+    swap: &GlobalInternalsSwap,
+) -> FResult<SealedGlobalSwap> {
+    let msg = serde_json::to_vec(swap).map_err(|e| FError::NetworkingError(format!("{}", e)))?;
+    let (nonce, ciphertext) = seal_message(cipher, node_uuid, &msg)?;
+    Ok(SealedGlobalSwap {
+        node_uuid,
+        nonce,
+        ciphertext,
+    })
+}
+
+/// Decrypts a write of global internals, failing when it was not sealed
+/// with the global key by the node it claims
+fn open_global_swap(
+    cipher: &ChaCha20Poly1305,
+    sealed: &SealedGlobalSwap,
+) -> FResult<GlobalInternalsSwap> {
+    let msg = open_message(cipher, sealed.node_uuid, &sealed.nonce, &sealed.ciphertext)?;
+    serde_json::from_slice(&msg).map_err(|e| FError::NetworkingError(format!("{}", e)))
+}
+
+/// Cipher keyed with the base64 of the 32 bytes key in `path`
+fn read_key_cipher(path: &str) -> FResult<ChaCha20Poly1305> {
+    let encoded = std::fs::read_to_string(path)?;
+    let key =
+        base64::decode(encoded.trim()).map_err(|e| FError::NetworkingError(format!("{}", e)))?;
+    if key.len() != 32 {
+        return Err(FError::NetworkingError(format!(
+            "The key in {} is not 32 bytes long",
+            path
+        )));
+    }
+    Ok(ChaCha20Poly1305::new(Key::from_slice(&key)))
+}
+
+/// Checks that the stored record still matches `last`, the last version
+/// written by this plugin, and returns the version for the new content.
+fn next_record_version<T: Serialize>(
+    last: Option<&RecordVersion>,
+    uuid: Uuid,
+    current: Option<&T>,
+    new: &T,
+) -> FResult<RecordVersion> {
+    let generation = match last {
+        Some(version) => {
+            if let Some(current) = current {
+                if record_etag(current)? != version.etag {
+                    return Err(FError::NetworkingError(format!(
+                        "Conflict: record {} was modified after generation {}",
+                        uuid, version.generation
+                    )));
+                }
+            }
+            version.generation + 1
+        }
+        None => 1,
+    };
+    Ok(RecordVersion {
+        generation,
+        etag: record_etag(new)?,
+    })
+}
+
+/// Takes the version out of the internals of a virtual network record
+fn take_record_version(
+    mut vnet: VirtualNetwork,
+) -> FResult<(VirtualNetwork, Option<RecordVersion>)> {
+    let version = match vnet.plugin_internals.as_ref() {
+        Some(raw) => {
+            let mut internals = deserialize_network_internals(raw)?;
+            let version = internals.record_version.take();
+            vnet.plugin_internals = Some(serialize_network_internals(&internals)?);
+            version
+        }
+        None => None,
+    };
+    Ok((vnet, version))
+}
+
+/// Applies the VXLAN options that are set, the others keep the kernel defaults
+fn apply_vxlan_options(vxlan: VxlanAddRequest, options: &VXLANOptions) -> VxlanAddRequest {
+    let vxlan = match options.learning {
+        Some(learning) => vxlan.learning(u8::from(learning)),
+        None => vxlan,
+    };
+    let vxlan = match options.ttl {
+        Some(ttl) => vxlan.ttl(ttl),
+        None => vxlan,
+    };
+    let vxlan = match options.tos {
+        Some(tos) => vxlan.tos(tos),
+        None => vxlan,
+    };
+    match options.udp_csum {
+        Some(udp_csum) => vxlan.udp_csum(u8::from(udp_csum)),
+        None => vxlan,
+    }
+}
+
+/// Leaves the gateway out of the range `start..=end`. A gateway in the
+/// middle splits it and the larger part is kept, as a network is
+/// served with a single range.
+fn exclude_gateway(start: u128, end: u128, gw: u128) -> Option<(u128, u128)> {
+    if gw < start || gw > end {
+        Some((start, end))
+    } else if gw == start {
+        Some((start.checked_add(1)?, end))
+    } else if gw == end {
+        Some((start, end.checked_sub(1)?))
+    } else if end - gw > gw - start {
+        Some((gw + 1, end))
+    } else {
+        Some((start, gw - 1))
+    }
+}
+
+/// Derives a DHCP range from a subnet, the network address, the first
+/// `reserved` hosts, the gateway and the broadcast address are left out.
+/// Returns `None` when the subnet is too small.
+fn derive_dhcp_range(
+    subnet: (IPAddress, u8),
+    gateway: Option<IPAddress>,
+    reserved: u32,
+) -> Option<(IPAddress, IPAddress)> {
+    match subnet {
+        (IPAddress::V4(addr), prefix) => {
+            let net = ipnetwork::Ipv4Network::new(addr, prefix).ok()?;
+            let network = u32::from(net.network());
+            let broadcast = u32::from(net.broadcast());
+            let mut start = network.checked_add(reserved)?.checked_add(1)?;
+            let mut end = broadcast.checked_sub(1)?;
+            if let Some(IPAddress::V4(gw)) = gateway {
+                let (s, e) = exclude_gateway(
+                    u128::from(start),
+                    u128::from(end),
+                    u128::from(u32::from(gw)),
+                )?;
+                start = s as u32;
+                end = e as u32;
+            }
+            if start > end {
+                return None;
+            }
+            Some((
+                IPAddress::V4(std::net::Ipv4Addr::from(start)),
+                IPAddress::V4(std::net::Ipv4Addr::from(end)),
+            ))
+        }
+        // IPv6 has no broadcast, the last address of the subnet is a host
+        (IPAddress::V6(addr), prefix) => {
+            let net = ipnetwork::Ipv6Network::new(addr, prefix).ok()?;
+            let network = u128::from(net.network());
+            let last = network | u128::MAX.checked_shr(u32::from(prefix)).unwrap_or(0);
+            let mut start = network.checked_add(u128::from(reserved))?.checked_add(1)?;
+            let mut end = last;
+            if let Some(IPAddress::V6(gw)) = gateway {
+                let (s, e) = exclude_gateway(start, end, u128::from(gw))?;
+                start = s;
+                end = e;
+            }
+            if start > end {
+                return None;
+            }
+            Some((
+                IPAddress::V6(std::net::Ipv6Addr::from(start)),
+                IPAddress::V6(std::net::Ipv6Addr::from(end)),
+            ))
+        }
+    }
+}
+
+/// Checks that the subnet of the IP configuration of a virtual network
+/// matches its IP version, and that the secondary configuration of a
+/// dual-stack network is of the other address family
+fn check_ip_configurations(
+    vnet: &VirtualNetwork,
+    conf: &IPConfiguration,
+    secondary: Option<&IPConfiguration>,
+) -> FResult<()> {
+    let v6 = matches!(vnet.ip_version, IPVersion::IPV6);
+    if let Some((net_addr, _)) = conf.subnet {
+        if net_addr.is_ipv6() != v6 {
+            return Err(FError::NetworkingError(format!(
+                "The subnet {} does not match the IP version of {}",
+                net_addr, vnet.uuid
+            )));
+        }
+    }
+    if let Some((net_addr, _)) = secondary.and_then(|c| c.subnet) {
+        if net_addr.is_ipv6() == v6 {
+            return Err(FError::NetworkingError(format!(
+                "The secondary subnet {} of {} must be of the other address family",
+                net_addr, vnet.uuid
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Checks an externally allotted address against the IP configuration of
+/// its virtual network and the addresses already in use
+fn validate_external_address(
+    external: &ExternalAddress,
+    conf: Option<&IPConfiguration>,
+    in_use: &[IPAddress],
+) -> FResult<()> {
+    let addr = external.address.ip();
+    if in_use.contains(&addr) {
+        return Err(FError::AlreadyPresent);
+    }
+    if let Some((net_addr, prefix)) = conf.and_then(|c| c.subnet) {
+        let subnet = IpNetwork::new(net_addr, prefix)
+            .map_err(|e| FError::NetworkingError(format!("{}", e)))?;
+        if external.address.prefix() != prefix || !subnet.contains(addr) {
+            return Err(FError::NetworkingError(format!(
+                "{} is not in the subnet {}",
+                external.address, subnet
+            )));
+        }
+        if let IpNetwork::V4(net) = subnet {
+            if addr == IPAddress::V4(net.network()) || addr == IPAddress::V4(net.broadcast()) {
+                return Err(FError::NetworkingError(format!(
+                    "{} is not a host address of {}",
+                    addr, subnet
+                )));
+            }
+        }
+        if let Some(gw) = external.gateway {
+            if !subnet.contains(gw) {
+                return Err(FError::NetworkingError(format!(
+                    "The gateway {} is not in the subnet {}",
+                    gw, subnet
+                )));
+            }
+        }
+    }
+    if let Some(conf) = conf {
+        if conf.gateway == Some(addr) {
+            return Err(FError::AlreadyPresent);
+        }
+        if let Some((start, end)) = conf.dhcp_range {
+            if addr >= start && addr <= end {
+                return Err(FError::NetworkingError(format!(
+                    "{} is inside the DHCP range {} - {}",
+                    addr, start, end
+                )));
+            }
+        }
+    }
+    for route in &external.routes {
+        if !external.address.contains(route.gateway) {
+            return Err(FError::NetworkingError(format!(
+                "The gateway {} of the route to {}/{} is not reachable from {}",
+                route.gateway, route.destination, route.prefix, external.address
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Writes the resolvers used by the processes started in a network
+/// namespace with `ip netns exec`
+fn write_netns_resolv_conf(ns_name: &str, intf_uuid: Uuid, servers: &[IPAddress]) -> FResult<()> {
+    let dir = std::path::Path::new(NETNS_ETC_PATH).join(ns_name);
+    std::fs::create_dir_all(&dir)?;
+    let mut content = format!("# fog05 {}\n", intf_uuid);
+    for server in servers {
+        content.push_str(&format!("nameserver {}\n", server));
+    }
+    std::fs::write(dir.join("resolv.conf"), content)?;
+    Ok(())
+}
+
+/// Removes the resolvers written for an interface of a namespace by
+/// `write_netns_resolv_conf`, the ones written for another interface
+/// or by someone else are left
+fn remove_netns_resolv_conf(ns_name: &str, intf_uuid: Uuid) -> FResult<()> {
+    let dir = std::path::Path::new(NETNS_ETC_PATH).join(ns_name);
+    let path = dir.join("resolv.conf");
+    match std::fs::read_to_string(&path) {
+        Ok(content)
+            if content.lines().next() == Some(format!("# fog05 {}", intf_uuid).as_str()) =>
+        {
+            std::fs::remove_file(&path)?;
+            // the directory is left when it has other files
+            let _ = std::fs::remove_dir(&dir);
+            Ok(())
+        }
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Logs the failure of a best-effort cleanup step, it is kept to be
+/// reported once the cleanup is done
+fn cleanup_step(failed: &mut Vec<String>, step: &str, res: FResult<()>) {
+    if let Err(e) = res {
+        log::error!("Unable to {}: {}", step, e);
+        failed.push(format!("{} ({})", step, e));
+    }
+}
+
+/// Entry of an operation in the churn history of its tenant, `None` when
+/// the operation is not limited
+type ChurnSlot = Option<(String, ChurnOperation, u64)>;
+
+/// Tenant a virtual network is accounted to, set in the descriptor internals
+fn network_tenant(vnet: &VirtualNetwork) -> Option<String> {
+    vnet.plugin_internals
+        .as_ref()
+        .and_then(|raw| deserialize_network_internals(raw).ok())
+        .and_then(|i| i.tenant)
+}
+
+/// Internals of the global record of a virtual network, empty when the
+/// descriptor has none
+fn global_network_internals(vnet: &VirtualNetwork) -> FResult<VirtualNetworkInternals> {
+    deserialize_network_internals(vnet.plugin_internals.as_deref().unwrap_or(&b"{}"[..]))
+}
+
+/// Decodes a base64 WireGuard key
+fn decode_wireguard_key(key: &str) -> FResult<[u8; 32]> {
+    let raw = base64::decode(key.trim()).map_err(|e| FError::NetworkingError(format!("{}", e)))?;
+    let mut key = [0u8; 32];
+    if raw.len() != key.len() {
+        return Err(FError::NetworkingError(format!(
+            "WireGuard keys are 32 bytes, got {}",
+            raw.len()
+        )));
+    }
+    key.copy_from_slice(&raw);
+    Ok(key)
+}
+
+/// Returns the requested L2TP id if not in use, or the lowest free one
+fn allocate_l2tp_id<I: Iterator<Item = u32>>(used: I, requested: Option<u32>) -> FResult<u32> {
+    let used: Vec<u32> = used.collect();
+    match requested {
+        Some(id) if used.contains(&id) => Err(FError::AlreadyPresent),
+        Some(0) => Err(FError::NetworkingError(
+            "0 is not a valid L2TP id".to_string(),
+        )),
+        Some(id) => Ok(id),
+        None => (1..=u32::MAX)
+            .find(|id| !used.contains(id))
+            .ok_or_else(|| FError::NetworkingError("No free L2TP ids".to_string())),
+    }
+}
+
+/// Returns the range holding the address of the DHCP relay on the bridge,
+/// the gateway of the IPv4 subnet or its first host address
+fn get_relay_range(conf: &IPConfiguration) -> Option<DNSMasqRange> {
+    let (net_addr, prefix) = conf.subnet?;
+    if !net_addr.is_ipv4() {
+        return None;
+    }
+    let gateway = match conf.gateway {
+        Some(gw) => gw,
+        None => derive_dhcp_range((net_addr, prefix), None, 0)?.0,
+    };
+    Some(DNSMasqRange {
+        start: gateway,
+        end: gateway,
+        prefix,
+        gateway,
+        dns: None,
+        v6: false,
+    })
+}
+
+/// Names written to the hosts file of a resolver: dot separated labels
+/// of letters, digits and hyphens not starting or ending with a hyphen
+fn validate_dns_name(name: &str) -> FResult<()> {
+    let valid_label = |l: &str| {
+        !l.is_empty()
+            && l.len() <= 63
+            && !l.starts_with('-')
+            && !l.ends_with('-')
+            && l.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    };
+    if name.len() > 253 || !name.split('.').all(valid_label) {
+        return Err(FError::NetworkingError(format!(
+            "Invalid DNS name {:?}",
+            name
+        )));
+    }
+    Ok(())
+}
+
+/// IP configuration of the network with the family of the address
+fn reservation_ip_configuration<'a>(
+    vnet: &'a VirtualNetwork,
+    internals: &'a VirtualNetworkInternals,
+    addr: IPAddress,
+) -> Option<&'a IPConfiguration> {
+    if addr.is_ipv4()
+        == vnet
+            .ip_configuration
+            .as_ref()
+            .and_then(|c| c.subnet)
+            .map_or(true, |(addr, _)| addr.is_ipv4())
+    {
+        vnet.ip_configuration.as_ref()
+    } else {
+        internals.secondary_ip_configuration.as_ref()
+    }
+}
+
+/// Checks all the reservations of a network, each one against the
+/// ones before it
+fn validate_dhcp_reservations(
+    vnet: &VirtualNetwork,
+    internals: &VirtualNetworkInternals,
+) -> FResult<()> {
+    let reservations = &internals.dhcp_reservations;
+    for (i, reservation) in reservations.iter().enumerate() {
+        let conf = reservation_ip_configuration(vnet, internals, reservation.address);
+        validate_dhcp_reservation(reservation, conf, &reservations[..i])?;
+    }
+    Ok(())
+}
+
+/// Checks that the reserved address is a host address of the subnet of
+/// the network, not its gateway and not reserved to another MAC
+fn validate_dhcp_reservation(
+    reservation: &DHCPReservation,
+    conf: Option<&IPConfiguration>,
+    reservations: &[DHCPReservation],
+) -> FResult<()> {
+    let addr = reservation.address;
+    if reservations
+        .iter()
+        .any(|r| r.address == addr && r.mac != reservation.mac)
+    {
+        return Err(FError::AlreadyPresent);
+    }
+    if let Some(hostname) = &reservation.hostname {
+        if hostname.is_empty() || hostname.contains(|c: char| c == ',' || c.is_whitespace()) {
+            return Err(FError::NetworkingError(format!(
+                "Invalid hostname {:?}",
+                hostname
+            )));
+        }
+    }
+    let conf = conf.ok_or_else(|| {
+        FError::NetworkingError(format!(
+            "No {} subnet for the reservation of {}",
+            if addr.is_ipv4() { "IPv4" } else { "IPv6" },
+            addr
+        ))
+    })?;
+    if let Some((net_addr, prefix)) = conf.subnet {
+        let subnet = IpNetwork::new(net_addr, prefix)
+            .map_err(|e| FError::NetworkingError(format!("{}", e)))?;
+        if !subnet.contains(addr) {
+            return Err(FError::NetworkingError(format!(
+                "{} is not in the subnet {}",
+                addr, subnet
+            )));
+        }
+        if let IpNetwork::V4(net) = subnet {
+            if addr == IPAddress::V4(net.network()) || addr == IPAddress::V4(net.broadcast()) {
+                return Err(FError::NetworkingError(format!(
+                    "{} is not a host address of {}",
+                    addr, subnet
+                )));
+            }
+        }
+    }
+    if conf.gateway == Some(addr) {
+        return Err(FError::AlreadyPresent);
+    }
+    Ok(())
+}
+
+/// Renders the nft commands replacing the port forwards of a NAT table.
+/// With `hairpin`, the subnet of the network and the addresses of the
+/// overlay interface, clients of the network reach the forwarded ports
+/// through the public addresses too: they are masqueraded so that the
+/// replies go back through the node.
+#[cfg(feature = "nat")]
+fn render_port_forwards(
+    table: &str,
+    iface: &str,
+    forwards: &[PortForward],
+    hairpin: Option<(IpNetwork, &[IPAddress])>,
+) -> String {
+    let mut script = format!(
+        "add chain inet {table} prerouting {{ type nat hook prerouting priority -100; }}; \
+         flush chain inet {table} prerouting; \
+         add chain inet {table} hairpin {{ type nat hook postrouting priority 100; }}; \
+         flush chain inet {table} hairpin",
+        table = table
+    );
+    let hairpin = hairpin.and_then(|(net, public)| {
+        let public: Vec<String> = public
+            .iter()
+            .filter(|a| a.is_ipv4())
+            .map(|a| a.to_string())
+            .collect();
+        match net {
+            IpNetwork::V4(_) if !public.is_empty() => Some((net, public.join(", "))),
+            _ => None,
+        }
+    });
+    for f in forwards {
+        script.push_str(&format!(
+            "; add rule inet {} prerouting iifname \"{}\" {} dport {} dnat ip to {}:{}",
+            table, iface, f.proto, f.ext_port, f.internal_ip, f.internal_port
+        ));
+        if let Some((net, public)) = &hairpin {
+            script.push_str(&format!(
+                "; add rule inet {table} prerouting ip saddr {net} ip daddr {{ {public} }} \
+                 {proto} dport {ext_port} dnat ip to {ip}:{port}\
+                 ; add rule inet {table} hairpin ip saddr {net} ip daddr {ip} \
+                 {proto} dport {port} ct status dnat masquerade",
+                table = table,
+                net = net,
+                public = public,
+                proto = f.proto,
+                ext_port = f.ext_port,
+                ip = f.internal_ip,
+                port = f.internal_port
+            ));
+        }
+    }
+    script
+}
+
+/// Renders the nft commands creating the sets of a virtual network and
+/// replacing its filter chain. Rules only match the traffic forwarded to
+/// or from `net`, as the NAT tables of the other networks are hooked too.
+fn render_set_rules(table: &str, net: IpNetwork, sets: &[NFTSet], rules: &[SetRule]) -> String {
+    let mut script = String::new();
+    for set in sets {
+        let set_type = match set.kind {
+            NFTSetKind::IPV4 => "ipv4_addr",
+            NFTSetKind::PORT => "inet_service",
+        };
+        script.push_str(&format!(
+            "add set inet {} {} {{ type {}; flags interval; }}; ",
+            table, set.name, set_type
+        ));
+        if !set.elements.is_empty() {
+            script.push_str(&format!(
+                "add element inet {} {} {{ {} }}; ",
+                table,
+                set.name,
+                set.elements.join(", ")
+            ));
+        }
+    }
+    script.push_str(&format!(
+        "add chain inet {table} filter {{ type filter hook forward priority 0; policy accept; }}; \
+         flush chain inet {table} filter",
+        table = table
+    ));
+    for rule in rules {
+        let set = match sets.iter().find(|s| s.name == rule.set) {
+            Some(set) => set,
+            None => continue,
+        };
+        let (local, remote) = match rule.direction {
+            SetRuleDirection::INGRESS => ("daddr", "saddr"),
+            SetRuleDirection::EGRESS => ("saddr", "daddr"),
+        };
+        let matcher = match (set.kind, rule.proto) {
+            (NFTSetKind::IPV4, Some(proto)) => {
+                format!("ip {} @{} meta l4proto {}", remote, set.name, proto)
+            }
+            (NFTSetKind::IPV4, None) => format!("ip {} @{}", remote, set.name),
+            (NFTSetKind::PORT, Some(proto)) => format!("{} dport @{}", proto, set.name),
+            (NFTSetKind::PORT, None) => continue,
+        };
+        let action = match rule.action {
+            SetRuleAction::ACCEPT => "accept",
+            SetRuleAction::DROP => "drop",
+        };
+        script.push_str(&format!(
+            "; add rule inet {} filter ip {} {} {} {}",
+            table, local, net, matcher, action
+        ));
+    }
+    script
+}
+
+fn validate_snat(snat: &SNATOptions) -> FResult<()> {
+    match snat.ports {
+        Some((start, end)) if start == 0 || start > end => Err(FError::NetworkingError(format!(
+            "Invalid SNAT port range {}-{}",
+            start, end
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Subnet of a virtual network holding `addr`
+fn subnet_of(
+    vnet: &VirtualNetwork,
+    secondary: Option<&IPConfiguration>,
+    addr: IPAddress,
+) -> Option<IpNetwork> {
+    vnet.ip_configuration
+        .iter()
+        .chain(secondary)
+        .filter_map(|c| c.subnet)
+        .filter_map(|(net_addr, prefix)| IpNetwork::new(net_addr, prefix).ok())
+        .find(|subnet| subnet.contains(addr))
+}
+
+/// inet table of the egress rules of a virtual network
+fn egress_table(vnet_uuid: Uuid) -> String {
+    format!("fos_egress_{}", vnet_uuid.to_simple())
+}
+
+/// Subnets of a virtual network its egress rules match the sources with
+fn egress_networks(
+    vnet: &VirtualNetwork,
+    internals: &VirtualNetworkInternals,
+) -> FResult<Vec<IpNetwork>> {
+    let nets = vnet
+        .ip_configuration
+        .iter()
+        .chain(internals.secondary_ip_configuration.iter())
+        .filter_map(|c| c.subnet)
+        .map(|(addr, prefix)| {
+            IpNetwork::new(addr, prefix).map_err(|e| FError::NetworkingError(format!("{}", e)))
+        })
+        .collect::<FResult<Vec<IpNetwork>>>()?;
+    if nets.is_empty() {
+        return Err(FError::NetworkingError(
+            "Egress rules need a subnet".to_string(),
+        ));
+    }
+    Ok(nets)
+}
+
+fn validate_egress_rule(rule: &EgressRule, nets: &[IpNetwork]) -> FResult<()> {
+    let (addr, prefix) = rule.destination;
+    IpNetwork::new(addr, prefix).map_err(|e| FError::NetworkingError(format!("{}", e)))?;
+    if !nets.iter().any(|net| addr.is_ipv6() == net.is_ipv6()) {
+        return Err(FError::NetworkingError(format!(
+            "Destination {}/{} is not of the family of a subnet of the network",
+            addr, prefix
+        )));
+    }
+    if rule.port.is_some() && rule.proto.is_none() {
+        return Err(FError::NetworkingError(
+            "Egress rules on a port need a protocol".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Renders the nft commands replacing the egress chain of a virtual
+/// network. Rules only match the traffic forwarded from `nets`, as the
+/// tables of the other networks are hooked too, a rule applies to the
+/// subnets of the family of its destination.
+fn render_egress_rules(table: &str, nets: &[IpNetwork], rules: &[EgressRule]) -> String {
+    let mut script = format!(
+        "add table inet {table}; \
+         add chain inet {table} egress {{ type filter hook forward priority 0; policy accept; }}; \
+         flush chain inet {table} egress",
+        table = table
+    );
+    for rule in rules {
+        let (addr, prefix) = rule.destination;
+        let family = if addr.is_ipv6() { "ip6" } else { "ip" };
+        let mut matcher = format!("{} daddr {}/{}", family, addr, prefix);
+        match (rule.proto, rule.port) {
+            (Some(proto), Some(port)) => matcher.push_str(&format!(" {} dport {}", proto, port)),
+            (Some(proto), None) => matcher.push_str(&format!(" meta l4proto {}", proto)),
+            (None, _) => (),
+        }
+        let action = match rule.action {
+            EgressAction::ALLOW => "accept",
+            EgressAction::DENY => "drop",
+        };
+        for net in nets.iter().filter(|net| net.is_ipv6() == addr.is_ipv6()) {
+            script.push_str(&format!(
+                "; add rule inet {} egress {} saddr {} {} {}",
+                table, family, net, matcher, action
+            ));
+        }
+    }
+    script
+}
+
+/// Returns which of the rules are active at `unix_secs`
+fn active_set_rules(rules: &[SetRule], unix_secs: u64) -> Vec<bool> {
+    rules
+        .iter()
+        .map(|r| r.schedule.as_ref().map_or(true, |s| s.is_active(unix_secs)))
+        .collect()
+}
+
+/// Set names end up in nft commands, only identifiers are accepted
+fn validate_nft_set_name(name: &str) -> FResult<()> {
+    let mut chars = name.chars();
+    let valid = chars.next().map_or(false, |c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && name.len() <= 64;
+    if !valid {
+        return Err(FError::NetworkingError(format!(
+            "Invalid set name {}",
+            name
+        )));
+    }
+    Ok(())
+}
+
+/// Returns the element in the form nft lists it, so that it can be
+/// found again when removed: CIDRs are reduced to their network
+/// address and host routes to the plain address
+fn parse_set_element(kind: NFTSetKind, element: &str) -> FResult<String> {
+    let invalid = || FError::NetworkingError(format!("Invalid {:?} set element {}", kind, element));
+    match kind {
+        NFTSetKind::IPV4 => match element.trim().parse::<IpNetwork>() {
+            Ok(IpNetwork::V4(net)) if net.prefix() == 32 => Ok(net.ip().to_string()),
+            Ok(IpNetwork::V4(net)) => Ok(format!("{}/{}", net.network(), net.prefix())),
+            _ => Err(invalid()),
+        },
+        NFTSetKind::PORT => {
+            let mut bounds = element.splitn(2, '-').map(|p| p.trim().parse::<u16>());
+            match (bounds.next(), bounds.next()) {
+                (Some(Ok(port)), None) => Ok(port.to_string()),
+                (Some(Ok(first)), Some(Ok(last))) if first < last => {
+                    Ok(format!("{}-{}", first, last))
+                }
+                _ => Err(invalid()),
+            }
+        }
+    }
+}
+
+/// Chains of a table listed by `nft -a list table`, sets and maps are
+/// skipped
+fn parse_nft_chains(listing: &str) -> Vec<FirewallChain> {
+    let mut chains = Vec::new();
+    let mut current: Option<FirewallChain> = None;
+    // braces still open in the set or map being skipped
+    let mut skipped: usize = 0;
+    for line in listing.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        if skipped > 0 {
+            skipped =
+                (skipped + line.matches('{').count()).saturating_sub(line.matches('}').count());
+            continue;
+        }
+        let (body, handle) = match line.rsplit_once(" # handle ") {
+            Some((body, handle)) => (body.trim(), handle.trim().parse::<u64>().ok()),
+            None => (line, None),
+        };
+        if let Some(chain) = current.as_mut() {
+            if body == "}" {
+                chains.extend(current.take());
+            } else if body.starts_with("type ") {
+                parse_nft_chain_header(chain, body);
+            } else {
+                chain.rules.push(FirewallRule {
+                    handle,
+                    rule: body.to_string(),
+                });
+            }
+        } else if let Some(name) = body.strip_prefix("chain ") {
+            current = Some(FirewallChain {
+                name: name.trim_end_matches('{').trim().to_string(),
+                chain_type: None,
+                hook: None,
+                priority: None,
+                policy: None,
+                rules: Vec::new(),
+            });
+        } else if !body.starts_with("table ") {
+            skipped = body
+                .matches('{')
+                .count()
+                .saturating_sub(body.matches('}').count());
+        }
+    }
+    chains
+}
+
+/// Reads `type nat hook postrouting priority srcnat; policy accept;`
+fn parse_nft_chain_header(chain: &mut FirewallChain, header: &str) {
+    const KEYWORDS: [&str; 5] = ["type", "hook", "device", "priority", "policy"];
+    let mut key: Option<&str> = None;
+    let mut value: Vec<&str> = Vec::new();
+    let tokens = header
+        .split(|c: char| c == ';' || c.is_whitespace())
+        .filter(|t| !t.is_empty());
+    for token in tokens.map(Some).chain(std::iter::once(None)) {
+        if token.map(|t| KEYWORDS.contains(&t)).unwrap_or(true) {
+            let joined = Some(value.join(" "));
+            match key {
+                Some("type") => chain.chain_type = joined,
+                Some("hook") => chain.hook = joined,
+                Some("priority") => chain.priority = joined,
+                Some("policy") => chain.policy = joined,
+                _ => (),
+            }
+            key = token;
+            value.clear();
+        } else if let Some(token) = token {
+            value.push(token);
+        }
+    }
+}
+
+/// netdev table of the rate limits of a connection point
+fn rate_limit_table(cp_uuid: Uuid) -> String {
+    format!("fos_ratelimit_{}", cp_uuid.to_simple())
+}
+
+fn validate_rate_limit(limit: &ConnectionPointRateLimit) -> FResult<()> {
+    if limit.packets_per_sec.is_none() && limit.kbytes_per_sec.is_none() {
+        return Err(FError::NetworkingError(
+            "A rate limit needs packets_per_sec or kbytes_per_sec".to_string(),
+        ));
+    }
+    if limit.packets_per_sec == Some(0) || limit.kbytes_per_sec == Some(0) {
+        return Err(FError::NetworkingError(
+            "Rate limits cannot be 0".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Renders the nft commands replacing the ingress chain of the rate
+/// limits of a connection point
+fn render_rate_limit(table: &str, iface: &str, limit: &ConnectionPointRateLimit) -> String {
+    let mut script = format!(
+        "add table netdev {table}; \
+         add chain netdev {table} ingress {{ type filter hook ingress device \"{iface}\" priority 0; policy accept; }}; \
+         flush chain netdev {table} ingress",
+        table = table,
+        iface = iface
+    );
+    if let Some(rate) = limit.packets_per_sec {
+        script.push_str(&format!(
+            "; add rule netdev {} ingress limit rate over {}/second burst {} packets drop",
+            table,
+            rate,
+            limit.packets_burst.unwrap_or(rate)
+        ));
+    }
+    if let Some(rate) = limit.kbytes_per_sec {
+        script.push_str(&format!(
+            "; add rule netdev {} ingress limit rate over {} kbytes/second burst {} kbytes drop",
+            table,
+            rate,
+            limit.kbytes_burst.unwrap_or(rate)
+        ));
+    }
+    script
+}
+
+/// netdev table of the anti-spoofing rules of an interface
+fn anti_spoofing_table(intf_uuid: Uuid) -> String {
+    format!("fos_antispoof_{}", intf_uuid.to_simple())
+}
+
+/// Renders the nft commands replacing the ingress chain that drops what
+/// an interface sends from another MAC or address than its own. A family
+/// the interface has no address of is not filtered, so that it can still
+/// get one with DHCP or SLAAC.
+fn render_anti_spoofing(table: &str, iface: &VirtualInterface) -> String {
+    let mut script = format!(
+        "add table netdev {table}; \
+         add chain netdev {table} ingress {{ type filter hook ingress device \"{iface}\" priority -10; policy accept; }}; \
+         flush chain netdev {table} ingress; \
+         add rule netdev {table} ingress ether saddr != {mac} drop",
+        table = table,
+        iface = iface.if_name,
+        mac = iface.phy_address
+    );
+    let join = |v6: bool| {
+        iface
+            .addresses
+            .iter()
+            .filter(|a| a.is_ipv6() == v6)
+            .map(|a| format!("{}", a))
+            .collect::<Vec<String>>()
+            .join(", ")
+    };
+    let v4 = join(false);
+    if !v4.is_empty() {
+        script.push_str(&format!(
+            "; add rule netdev {table} ingress arp saddr ip != {{ {addrs} }} drop\
+             ; add rule netdev {table} ingress ip saddr 0.0.0.0 udp dport 67 accept\
+             ; add rule netdev {table} ingress ip saddr != {{ {addrs} }} drop",
+            table = table,
+            addrs = v4
+        ));
+    }
+    let v6 = join(true);
+    if !v6.is_empty() {
+        // link local addresses and duplicate address detection
+        script.push_str(&format!(
+            "; add rule netdev {table} ingress ip6 saddr {{ ::, fe80::/10 }} accept\
+             ; add rule netdev {table} ingress ip6 saddr != {{ {addrs} }} drop",
+            table = table,
+            addrs = v6
+        ));
+    }
+    script
+}
+
+/// Tables created with `generate_random_nft_table_name`, older versions
+/// named them `table` followed by 10 alphanumeric characters
+#[cfg(feature = "nat")]
+fn is_random_nft_table(table: &str) -> bool {
+    table.starts_with(NFT_TABLE_PREFIX)
+        || (table.len() == 15
+            && table.starts_with("table")
+            && table[5..].chars().all(|c| c.is_ascii_alphanumeric()))
+}
+
+/// Tables of the default namespace referenced by a virtual network
+#[cfg(feature = "nat")]
+fn nft_tables_of(vnet: &VirtualNetwork) -> FResult<Vec<String>> {
+    let internals = match &vnet.plugin_internals {
+        Some(raw) => deserialize_network_internals(raw)?,
+        None => return Ok(Vec::new()),
+    };
+    Ok(internals
+        .associated_tables
+        .into_iter()
+        .chain(internals.floating_ips.into_iter().map(|f| f.table))
+        .chain(internals.address_mappings.into_iter().map(|m| m.table))
+        .collect())
+}
+
+/// Masquerades the addresses of the NAT64 pool leaving the namespace,
+/// the traffic back to the IPv6 clients goes through `tun`
+fn render_nat64_masquerade(table: &str, tun: &str, pool: (IPAddress, u8)) -> String {
+    format!(
+        "add table ip {table}; \
+         add chain ip {table} postrouting {{ type nat hook postrouting priority 100; policy accept; }}; \
+         flush chain ip {table} postrouting; \
+         add rule ip {table} postrouting ip saddr {addr}/{len} oifname != \"{tun}\" masquerade",
+        table = table,
+        addr = pool.0,
+        len = pool.1,
+        tun = tun
+    )
+}
+
+/// The collector is passed to softflowd as `address:port`
+fn validate_flow_collector(collector: &str) -> FResult<()> {
+    collector
+        .parse::<std::net::SocketAddr>()
+        .map(|_| ())
+        .map_err(|_| FError::NetworkingError(format!("Invalid flow collector {}", collector)))
+}
+
+/// Name, operational state and administrative state of a link message
+fn link_event_state(link: &LinkMessage) -> Option<(String, LinkOperState, bool)> {
+    let if_name = link.nlas.iter().find_map(|nla| match nla {
+        LinkNla::IfName(name) => Some(name.clone()),
+        _ => None,
+    })?;
+    let up = link
+        .nlas
+        .iter()
+        .any(|nla| matches!(nla, LinkNla::OperState(LinkState::Up | LinkState::Unknown)));
+    let oper_state = if up {
+        LinkOperState::UP
+    } else {
+        LinkOperState::DOWN
+    };
+    Some((if_name, oper_state, link.header.flags & IFF_UP != 0))
+}
+
+/// Whether a managed link raises its alert, a bridge without ports up is
+/// operationally down too so only its administrative state counts
+fn managed_link_down(kind: ManagedLinkKind, oper_state: LinkOperState, admin_up: bool) -> bool {
+    match kind {
+        ManagedLinkKind::BRIDGE => oper_state == LinkOperState::REMOVED || !admin_up,
+        _ => oper_state != LinkOperState::UP,
+    }
+}
+
+/// Interfaces whose link is missing from the dump of their namespace and
+/// the ones whose link is down, the namespaces without a dump are not
+/// checked
+fn check_interface_links(
+    interfaces: &[VirtualInterface],
+    dumps: &HashMap<Option<Uuid>, LinkStatsDump>,
+) -> (Vec<Uuid>, Vec<Uuid>) {
+    let mut missing = Vec::new();
+    let mut down = Vec::new();
+    for iface in interfaces {
+        let dump = match dumps.get(&iface.net_ns) {
+            Some(dump) => dump,
+            None => continue,
+        };
+        match dump.indexes.get(&iface.if_name) {
+            None => missing.push(iface.uuid),
+            Some(index) if dump.down.contains(index) => down.push(iface.uuid),
+            Some(_) => (),
+        }
+    }
+    (missing, down)
+}
+
+/// Renders the nft commands replacing the forward chain of the isolation
+/// table, peered bridges are accepted before the others are dropped.
+/// `services` pairs a services network with a tenant: the tenant can
+/// open connections to it, only the replies are forwarded back.
+/// Everything forwarded through the bridges of management networks is
+/// dropped, peerings included.
+#[cfg(feature = "nat")]
+fn render_isolation(
+    table: &str,
+    bridges: &HashMap<Uuid, String>,
+    peerings: &[(Uuid, Uuid)],
+    services: &[(Uuid, Uuid)],
+    mgmt: &[Uuid],
+) -> String {
+    let mut script = format!(
+        "add table inet {table}; \
+         add chain inet {table} forward {{ type filter hook forward priority 0; policy accept; }}; \
+         flush chain inet {table} forward",
+        table = table
+    );
+    // management networks are not routed to or from any other interface
+    for br in mgmt.iter().filter_map(|u| bridges.get(u)) {
+        script.push_str(&format!(
+            "; add rule inet {table} forward iifname \"{br}\" oifname != \"{br}\" drop\
+             ; add rule inet {table} forward oifname \"{br}\" iifname != \"{br}\" drop",
+            table = table,
+            br = br
+        ));
+    }
+    for (a, b) in peerings {
+        if let (Some(br_a), Some(br_b)) = (bridges.get(a), bridges.get(b)) {
+            script.push_str(&format!(
+                "; add rule inet {} forward iifname \"{}\" oifname \"{}\" accept",
+                table, br_a, br_b
+            ));
+        }
+    }
+    for (svc, tenant) in services {
+        if let (Some(br_svc), Some(br_tenant)) = (bridges.get(svc), bridges.get(tenant)) {
+            script.push_str(&format!(
+                "; add rule inet {table} forward iifname \"{tenant}\" oifname \"{svc}\" accept\
+                 ; add rule inet {table} forward iifname \"{svc}\" oifname \"{tenant}\" \
+                 ct state established,related accept",
+                table = table,
+                svc = br_svc,
+                tenant = br_tenant
+            ));
+        }
+    }
+    for br in bridges.values() {
+        let others: Vec<String> = bridges
+            .values()
+            .filter(|o| *o != br)
+            .map(|o| format!("\"{}\"", o))
+            .collect();
+        if !others.is_empty() {
+            script.push_str(&format!(
+                "; add rule inet {} forward iifname \"{}\" oifname {{ {} }} drop",
+                table,
+                br,
+                others.join(", ")
+            ));
+        }
+    }
+    script
+}
+
+/// Name of the link an interface is stacked on, if any
+fn underlay_of(kind: &VirtualInterfaceKind) -> Option<&str> {
+    match kind {
+        VirtualInterfaceKind::VXLAN(info) => Some(&info.dev.if_name),
+        VirtualInterfaceKind::VLAN(info) => Some(&info.dev.if_name),
+        VirtualInterfaceKind::MACVLAN(info) => Some(&info.dev.if_name),
+        _ => None,
+    }
+}
+
+/// Named counters of the traffic routed into and out of a virtual
+/// network, kept in its traffic table
+fn traffic_counters(vnet_uuid: Uuid) -> (String, String) {
+    let id = vnet_uuid.to_simple();
+    (format!("fos_in_{}", id), format!("fos_out_{}", id))
+}
+
+/// inet table counting the traffic of a virtual network, whether it is
+/// isolated or not
+fn traffic_table(vnet_uuid: Uuid) -> String {
+    format!("fos_traffic_{}", vnet_uuid.to_simple())
+}
+
+/// Renders the nft commands creating the counters of a virtual network
+/// and replacing its accounting chain. Counters that exist already are
+/// kept, the chain is hooked after the filter chains so that dropped
+/// packets are left out.
+fn render_traffic_counters(table: &str, vnet_uuid: Uuid, br: &str) -> String {
+    let (counter_in, counter_out) = traffic_counters(vnet_uuid);
+    format!(
+        "add table inet {table}\
+         ; add counter inet {table} {counter_in}\
+         ; add counter inet {table} {counter_out}\
+         ; add chain inet {table} accounting {{ type filter hook forward priority 10; policy accept; }}\
+         ; flush chain inet {table} accounting\
+         ; add rule inet {table} accounting oifname \"{br}\" iifname != \"{br}\" counter name {counter_in}\
+         ; add rule inet {table} accounting iifname \"{br}\" oifname != \"{br}\" counter name {counter_out}",
+        table = table,
+        br = br,
+        counter_in = counter_in,
+        counter_out = counter_out
+    )
+}
+
+/// Packets and bytes of a counter listed by `nft list counter`
+fn parse_nft_counter(listing: &str) -> Option<(u64, u64)> {
+    let mut words = listing.split_whitespace();
+    let mut packets = None;
+    let mut bytes = None;
+    while let Some(word) = words.next() {
+        match word {
+            "packets" => packets = words.next().and_then(|v| v.parse().ok()),
+            "bytes" => bytes = words.next().and_then(|v| v.parse().ok()),
+            _ => (),
+        }
+    }
+    Some((packets?, bytes?))
+}
+
+/// Default route of a failover through the given gateway
+fn failover_route(gateway: &IPAddress, metric: u32) -> DefaultRoute {
+    DefaultRoute {
+        version: if gateway.is_ipv6() {
+            IPVersion::IPV6
+        } else {
+            IPVersion::IPV4
+        },
+        gateway: Some(*gateway),
+        metric: Some(metric),
+    }
+}
+
+/// Interface record of an IPIP or SIT tunnel. The SDK has no kind for
+/// them, they are recorded as GRE devices with the same endpoints, a TTL
+/// of 0 inherits the one of the inner packets.
+fn ip_tunnel_interface(tunnel: &IPTunnel) -> VirtualInterface {
+    VirtualInterface {
+        uuid: tunnel.uuid,
+        if_name: tunnel.if_name.clone(),
+        net_ns: tunnel.net_ns,
+        parent: None,
+        kind: VirtualInterfaceKind::GRE(GREKind {
+            local_addr: tunnel.local_addr,
+            remote_addr: tunnel.remote_addr,
+            ttl: tunnel.ttl.unwrap_or(0),
+        }),
+        addresses: Vec::new(),
+        phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
+    }
+}
+
+/// Default route through the router of a DHCP lease
+fn lease_route(router: std::net::Ipv4Addr) -> DefaultRoute {
+    DefaultRoute {
+        version: IPVersion::IPV4,
+        gateway: Some(IPAddress::V4(router)),
+        metric: None,
+    }
+}
+
+/// Milliseconds since the epoch
+fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Renders the hosts file served by a resolver
+fn render_hosts(records: &[DNSRecord]) -> String {
+    records
+        .iter()
+        .map(|r| format!("{} {}\n", r.address, r.name))
+        .collect()
+}
+
+/// Runs the given command and waits for it, returns its standard output
+/// or its standard error wrapped in a `FError::NetworkingError`
+pub(crate) fn exec_command(mut cmd: Command) -> FResult<String> {
+    log::trace!("exec_command {:?}", cmd);
+    if simulation::is_enabled() {
+        return simulation::run_command(&cmd);
+    }
+    let output = cmd
+        .output()
+        .map_err(|e| FError::NetworkingError(format!("{}", e)))?;
+    if !output.status.success() {
+        return Err(FError::NetworkingError(
+            String::from_utf8_lossy(&output.stderr).to_string(),
+        ));
+    }
+    String::from_utf8(output.stdout).map_err(|e| FError::NetworkingError(format!("{}", e)))
+}
+
+/// Retries a netlink operation while the kernel answers EBUSY, with the
+/// backoff of the plugin operations, the last error is returned once the
+/// backoff goes over 5 seconds
+pub async fn retry_busy<T, F, Fut>(mut operation: F) -> Result<T, nlError>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Result<T, nlError>>,
+{
+    let mut backoff = 100;
+    loop {
+        match operation().await {
+            Err(nlError::NetlinkError(nl)) if nl.code == -16 && backoff <= 5000 => {
+                runtime::sleep(Duration::from_millis(backoff)).await;
+                backoff *= 2;
+            }
+            res => return res,
+        }
+    }
+}
+
+/// Applies STP settings to a bridge port: the link info of the port is
+/// changed with a RTM_NEWLINK carrying only the slave data, as
+/// `ip link set type bridge_slave` does
+pub async fn set_bridge_port(
+    handle: &mut Handle,
+    index: u32,
+    config: &BridgePortConfig,
+) -> Result<(), nlError> {
+    let mut msg = LinkMessage::default();
+    msg.header.index = index;
+    msg.nlas.push(LinkNla::Info(vec![LinkInfo::SlaveData(
+        config.slave_data(),
+    )]));
+    let mut req = NetlinkMessage::from(RtnlMessage::NewLink(msg));
+    req.header.flags = NLM_F_REQUEST | NLM_F_ACK;
+    let mut response = handle.request(req)?;
+    while let Some(message) = response.next().await {
+        if let NetlinkPayload::Error(err) = message.payload {
+            return Err(nlError::NetlinkError(err));
+        }
+    }
+    Ok(())
+}
+
+/// Removes the learned entries from the forwarding database of a bridge,
+/// the link info carries only the flush, as `ip link set type bridge
+/// fdb_flush` does
+pub async fn flush_bridge_fdb(handle: &mut Handle, index: u32) -> Result<(), nlError> {
+    let mut msg = LinkMessage::default();
+    msg.header.index = index;
+    msg.nlas.push(LinkNla::Info(vec![
+        LinkInfo::Kind(LinkInfoKind::Bridge),
+        LinkInfo::Data(LinkInfoData::Bridge(vec![InfoBridge::FdbFlush])),
+    ]));
+    let mut req = NetlinkMessage::from(RtnlMessage::NewLink(msg));
+    req.header.flags = NLM_F_REQUEST | NLM_F_ACK;
+    let mut response = handle.request(req)?;
+    while let Some(message) = response.next().await {
+        if let NetlinkPayload::Error(err) = message.payload {
+            return Err(nlError::NetlinkError(err));
+        }
+    }
+    Ok(())
+}
+
+/// Deletes the conntrack entries of the default namespace matching
+/// `filter`, so that the flows established through NAT rules that are
+/// gone stop instead of being translated until they expire. conntrack
+/// fails when nothing matches, so errors are only logged.
+fn flush_conntrack(filter: &[String]) {
+    let mut cmd = Command::new("conntrack");
+    cmd.arg("-D").args(filter);
+    match exec_command(cmd) {
+        Ok(_) => log::trace!(target: logging::NFT, "Flushed conntrack entries {:?}", filter),
+        Err(e) => log::debug!(
+            target: logging::NFT,
+            "No conntrack entries flushed for {:?}: {}",
+            filter,
+            e
+        ),
+    }
+}
+
+/// Counts the flows of `conntrack -L` listings. A flow belongs to the
+/// address of `subnets` that is its original source, or its reply source
+/// for the flows forwarded to the subnets; the ones listed twice are
+/// only counted once.
+fn conntrack_stats(vnet_uuid: Uuid, subnets: &[IpNetwork], listing: &str) -> ConntrackStats {
+    let mut stats = ConntrackStats {
+        vnet_uuid,
+        flows: 0,
+        protocols: BTreeMap::new(),
+        tcp_states: BTreeMap::new(),
+        top_talkers: Vec::new(),
+    };
+    let mut seen = HashSet::new();
+    let mut talkers: HashMap<IPAddress, ConntrackTalker> = HashMap::new();
+    for line in listing.lines() {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let proto = match tokens.first() {
+            Some(proto) => *proto,
+            None => continue,
+        };
+        let tuple: Vec<&str> = tokens
+            .iter()
+            .copied()
+            .filter(|t| {
+                ["src=", "dst=", "sport=", "dport="]
+                    .iter()
+                    .any(|k| t.starts_with(k))
+            })
+            .collect();
+        if !seen.insert((proto, tuple)) {
+            continue;
+        }
+        let address = tokens
+            .iter()
+            .filter_map(|t| t.strip_prefix("src="))
+            .filter_map(|a| a.parse::<IPAddress>().ok())
+            .find(|a| subnets.iter().any(|net| net.contains(*a)));
+        let address = match address {
+            Some(address) => address,
+            None => continue,
+        };
+        stats.flows += 1;
+        *stats.protocols.entry(proto.to_string()).or_insert(0) += 1;
+        // tcp <number> <timeout> <state> src=...
+        if let Some(state) = tokens.get(3).filter(|t| proto == "tcp" && !t.contains('=')) {
+            *stats.tcp_states.entry(state.to_string()).or_insert(0) += 1;
+        }
+        let bytes = tokens
+            .iter()
+            .filter_map(|t| t.strip_prefix("bytes="))
+            .filter_map(|b| b.parse::<u64>().ok())
+            .reduce(|a, b| a + b);
+        let talker = talkers.entry(address).or_insert(ConntrackTalker {
+            address,
+            flows: 0,
+            bytes: None,
+        });
+        talker.flows += 1;
+        if let Some(bytes) = bytes {
+            talker.bytes = Some(talker.bytes.unwrap_or(0) + bytes);
+        }
+    }
+    let mut top_talkers: Vec<ConntrackTalker> = talkers.into_values().collect();
+    top_talkers.sort_by(|a, b| b.flows.cmp(&a.flows).then(b.bytes.cmp(&a.bytes)));
+    top_talkers.truncate(CONNTRACK_TOP_TALKERS);
+    stats.top_talkers = top_talkers;
+    stats
+}
+
+/// Addresses of the services and tenant ends of a services link
+fn services_link_addresses(link: IpNetwork) -> FResult<(IPAddress, IPAddress)> {
+    match link {
+        IpNetwork::V4(net) if net.prefix() == 30 => {
+            let base = u32::from(net.network());
+            Ok((
+                IPAddress::V4(std::net::Ipv4Addr::from(base + 1)),
+                IPAddress::V4(std::net::Ipv4Addr::from(base + 2)),
+            ))
+        }
+        _ => Err(FError::NetworkingError(format!(
+            "{} is not a services link subnet",
+            link
+        ))),
+    }
+}
+
+/// Renders the nft commands replacing the chains of the services table:
+/// connections are accepted from the links, only the answers are sent
+/// back through them, forwarded or from the namespace itself
+fn render_services_filter(table: &str, links: &[String]) -> String {
+    let mut script = format!(
+        "add table inet {table}\
+         ; add chain inet {table} forward {{ type filter hook forward priority 0; policy accept; }}\
+         ; flush chain inet {table} forward\
+         ; add chain inet {table} output {{ type filter hook output priority 0; policy accept; }}\
+         ; flush chain inet {table} output",
+        table = table
+    );
+    for link in links {
+        for chain in &["forward", "output"] {
+            script.push_str(&format!(
+                "; add rule inet {table} {chain} oifname \"{link}\" ct state established,related accept\
+                 ; add rule inet {table} {chain} oifname \"{link}\" drop",
+                table = table,
+                chain = chain,
+                link = link
+            ));
+        }
+    }
+    script
+}
+
+/// Command running `program` inside `ns_name`
+fn netns_command(ns_name: &str, program: &str) -> Command {
+    let mut cmd = Command::new("ip");
+    cmd.arg("netns").arg("exec").arg(ns_name).arg(program);
+    cmd
+}
+
+/// Runs an operation on a record in its span, a failure is reported in
+/// the span so that it is tied to the netlink and ns-manager spans of the
+/// failing step
+async fn traced<T, F>(span: tracing::Span, operation: F) -> FResult<T>
+where
+    F: Future<Output = FResult<T>>,
+{
+    let res = operation.instrument(span.clone()).await;
+    if let Err(ref e) = res {
+        span.in_scope(|| tracing::warn!(error = %e, "operation failed"));
+    }
+    res
+}
+
+impl LinuxNetwork {
+    pub async fn new(
+        z: Arc<zenoh::net::Session>,
+        connector: Arc<fog05_sdk::zconnector::ZConnector>,
+        pid: u32,
+        config: LinuxNetworkConfig,
+    ) -> FResult<Self> {
+        // this will be removed once netlink merges the async-std support
+        let (connection, handle, _) = new_connection().unwrap();
+        runtime::spawn(connection);
+
+        let state = LinuxNetworkState {
+            uuid: None,
+            nl_handler: handle,
+            ns_managers: HashMap::new(),
+            dataplane_ports: HashMap::new(),
+            resolved_endpoints: HashMap::new(),
+            unicast_vnets: HashMap::new(),
+            ns_manager_spawn_stats: NSManagerSpawnStats::default(),
+            shared_ns_manager: None,
+            ip_tunnels: HashMap::new(),
+            vf_pool: VFPool::default(),
+            pinned_bridge_macs: HashMap::new(),
+            interface_states: HashMap::new(),
+            interface_names: HashMap::new(),
+            l2tp_sessions: HashMap::new(),
+            resolvers: HashMap::new(),
+            remote_dns_records: HashMap::new(),
+            cp_qos: HashMap::new(),
+            rp_filters: HashMap::new(),
+            cp_rate_limits: HashMap::new(),
+            isolated_bridges: HashMap::new(),
+            known_vnets: HashSet::new(),
+            known_namespaces: HashSet::new(),
+            known_cps: HashSet::new(),
+            dhcp_servers: HashMap::new(),
+            route_failovers: HashMap::new(),
+            link_stats: HashMap::new(),
+            interface_stats: HashMap::new(),
+            node_status: None,
+            link_oper_states: HashMap::new(),
+            managed_links_down: HashSet::new(),
+            moved_links: HashMap::new(),
+            replicated_etag: None,
+            scheduled_rules: HashMap::new(),
+            bridge_ports: HashMap::new(),
+            record_metadata: HashMap::new(),
+            churn_history: HashMap::new(),
+            dhcp_clients: HashMap::new(),
+            dhcp_client_handles: HashMap::new(),
+            dns64_proxies: HashMap::new(),
+            async_operations: HashMap::new(),
+        };
+
+        let permits = SpawnPermits::new(
+            config
+                .ns_manager_max_concurrent_spawns
+                .unwrap_or(DEFAULT_NS_MANAGER_CONCURRENT_SPAWNS),
+        );
+
+        Ok(Self {
+            z,
+            connector,
+            pid,
+            agent: None,
+            os: None,
+            config,
+            state: Arc::new(RwLock::new(state)),
+            ns_manager_permits: permits,
+            record_versions: Arc::new(Mutex::new(HashMap::new())),
+            bridge_locks: Arc::new(Mutex::new(HashMap::new())),
+            global_lock: Arc::new(Mutex::new(())),
+            recent_operations: Arc::new(Mutex::new(VecDeque::new())),
+            netlink_retries: Arc::new(Mutex::new(HashMap::new())),
+            journal_lock: Arc::new(Mutex::new(0)),
+        })
+    }
+
+    async fn run(&self, stop: runtime::Receiver<()>) -> FResult<()> {
+        info!("LinuxNetwork main loop starting...");
+
+        //starting the Agent-Plugin Server
+        let hv_server = self
+            .clone()
+            .get_networking_plugin_server(self.z.clone(), None);
+        let (stopper, _h) = hv_server.connect().await?;
+        hv_server.initialize().await?;
+
+        let mut guard = self.state.write().await;
+        guard.uuid = Some(hv_server.instance_uuid());
+        drop(guard);
+
+        hv_server.register().await?;
+
+        let (shv, _hhv) = hv_server.start().await?;
+
+        //starting the Linux specific extension server
+        let ext_server = self
+            .clone()
+            .get_linux_network_extension_server(self.z.clone(), Some(hv_server.instance_uuid()));
+        let (ext_stopper, _he) = ext_server.connect().await?;
+        ext_server.initialize().await?;
+        ext_server.register().await?;
+        let (sext, _hext) = ext_server.start().await?;
+
+        if let Err(e) = self.refresh_vf_pool().await {
+            log::warn!("Unable to enumerate SR-IOV devices: {}", e);
+        }
+
+        let journal = {
+            let _guard = self.journal_lock.lock().await;
+            journal::read(&self.get_journal_path()).await
+        };
+        let indexed = match journal {
+            Ok(state) => {
+                self.index_records(&state).await;
+                true
+            }
+            Err(e) => {
+                log::warn!("Unable to read the journal: {}", e);
+                false
+            }
+        };
+        self.adopt_dhcp_servers().await;
+        self.restore_vnet_state().await;
+        self.restore_route_failovers().await;
+        self.restore_reverse_path_filters().await;
+        // without the journal the networks of the node are not known
+        if indexed {
+            if let Err(e) = self.clean_orphaned_nft_tables().await {
+                log::warn!(target: logging::NFT, "Unable to clean the orphaned tables: {}", e);
+            }
+        }
+
+        let monitoring = async {
+            loop {
+                info!(target: logging::RECONCILE, "Monitoring loop started");
+                self.collect_link_stats().await;
+                if let Err(e) = self.reassert_bridge_macs().await {
+                    log::warn!(
+                        target: logging::RECONCILE,
+                        "Unable to re-assert bridge MACs: {}",
+                        e
+                    );
+                }
+                self.refresh_remote_endpoints().await;
+                self.supervise_resolvers().await;
+                self.supervise_dhcp_clients().await;
+                self.probe_route_failovers().await;
+                self.apply_rule_schedules().await;
+                if let Err(e) = self.replicate_to_peer().await {
+                    log::warn!(
+                        target: logging::RECONCILE,
+                        "Unable to replicate to the peer: {}",
+                        e
+                    );
+                }
+                let guard = self.state.read().await;
+                let unicast_vnets: Vec<Uuid> = guard.unicast_vnets.keys().copied().collect();
+                drop(guard);
+                for vnet_uuid in unicast_vnets {
+                    if let Err(e) = self.update_unicast_peers(vnet_uuid).await {
+                        log::warn!(
+                            target: logging::RECONCILE,
+                            "Unable to update VXLAN peers of {}: {}",
+                            vnet_uuid,
+                            e
+                        );
+                    }
+                }
+                self.publish_node_status().await;
+                runtime::sleep(Duration::from_secs(self.config.monitoring_interveal)).await;
+            }
+        };
+
+        self.agent
+            .clone()
+            .unwrap()
+            .register_plugin(hv_server.instance_uuid(), PluginKind::NETWORKING)
+            .await??;
+
+        let link_events = async {
+            self.watch_link_events().await;
+            // the monitoring loop keeps running without the events
+            futures::future::pending().await
+        };
+        let dns_records = async {
+            if let Err(e) = self.watch_dns_records().await {
+                log::error!("Unable to watch the DNS records of the other nodes: {}", e);
+            }
+            futures::future::pending().await
+        };
+
+        match monitoring
+            .race(link_events)
+            .race(dns_records)
+            .race(stop.recv())
+            .await
+        {
+            Ok(_) => trace!("Monitoring ending correct"),
+            Err(e) => trace!("Monitoring ending got error: {}", e),
+        }
+
+        self.agent
+            .clone()
+            .unwrap()
+            .unregister_plugin(hv_server.instance_uuid())
+            .await??;
+
+        ext_server.stop(sext).await?;
+        ext_server.unregister().await?;
+        ext_server.disconnect(ext_stopper).await?;
+
+        hv_server.stop(shv).await?;
+        hv_server.unregister().await?;
+        hv_server.disconnect(stopper).await?;
+
+        info!("LinuxNetwork main loop exiting");
+        Ok(())
+    }
+
+    pub async fn start(&mut self) -> (runtime::Sender<()>, runtime::JoinHandle<FResult<()>>) {
+        let local_os = OSClient::find_local_servers(self.z.clone()).await.unwrap();
+        if local_os.is_empty() {
+            error!("Unable to find a local OS interface");
+            panic!("No OS Server");
+        }
+
+        let local_agent = AgentPluginInterfaceClient::find_local_servers(self.z.clone())
+            .await
+            .unwrap();
+        if local_agent.is_empty() {
+            error!("Unable to find a local Agent interface");
+            panic!("No Agent Server");
+        }
+
+        let os = OSClient::new(self.z.clone(), local_os[0]);
+        let agent = AgentPluginInterfaceClient::new(self.z.clone(), local_agent[0]);
+
+        self.agent = Some(agent);
+        self.os = Some(os);
+
+        // Starting main loop in a task
+        let (s, r) = runtime::bounded::<()>(1);
+        let plugin = self.clone();
+        let h = runtime::spawn_blocking(move || runtime::block_on(async { plugin.run(r).await }));
+        (s, h)
+    }
+
+    pub async fn stop(&self, stop: runtime::Sender<()>) -> FResult<()> {
+        log::debug!("Linux Network Stopping");
+        stop.send(()).await;
+
+        let node_uuid = self.agent.as_ref().unwrap().get_node_uuid().await??;
+        let default_vnet = self
+            .connector
+            .local
+            .get_virtual_network(Uuid::nil())
+            .await?;
+
+        // interfaces inside the namespace are removed with it
+        self.ordered_teardown(&default_vnet.interfaces, true)
+            .await?;
+
+        if let Some(internals) = default_vnet.plugin_internals {
+            let internals = deserialize_network_internals(internals.as_slice())?;
+
+            // Removing namespace if present
+            if let Some(ns_internals) = internals.associated_netns {
+                self.connector
+                    .local
+                    .get_network_namespace(ns_internals.ns_uuid)
+                    .await?;
+
+                self.kernel().del_netns(ns_internals.ns_name).await?;
+
+                log::trace!(target: logging::NS_MANAGER, "Taking guard to remove ns-manager");
+                self.kill_ns_manager(&ns_internals.ns_uuid).await?;
+                self.remove_network_namespace_record(ns_internals.ns_uuid)
+                    .await?;
+            }
+
+            // Killing dhcp if present
+            if let Some(dhcp_internal) = internals.dhcp {
+                self.stop_vnet_dhcp(Uuid::nil(), dhcp_internal).await?;
+            }
+
+            #[cfg(feature = "nat")]
+            for table in internals.associated_tables {
+                self.clean_nat(table).await?;
+            }
+        }
+
+        #[cfg(feature = "nat")]
+        if !self.state.read().await.isolated_bridges.is_empty() {
+            self.delete_nft_table(ISOLATION_TABLE)?;
+        }
+        self.delete_traffic_counters(Uuid::nil());
+
+        self.remove_virtual_network_record(Uuid::nil()).await?;
+
+        // Here we should remove and kill all the others ns-managers and clean-up
+
+        let shared_ns_manager = self.state.read().await.shared_ns_manager.clone();
+        if let Some((pid, _)) = shared_ns_manager {
+            log::trace!(target: logging::NS_MANAGER, "Killing shared ns-manager {}", pid);
+            self.terminate_process(pid).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Spawns and insert a new Namespace Manager into the Plugin state.
+    /// At most `ns_manager_max_concurrent_spawns` managers are starting
+    /// at the same time, this returns once the manager is ready to serve.
+    async fn spawn_ns_manager(&self, ns_name: String, ns_uuid: Uuid) -> FResult<()> {
+        self.ns_manager_permits.acquire().await;
+        let res = self.start_ns_manager(ns_name, ns_uuid).await;
+        self.ns_manager_permits.release().await;
+
+        let mut guard = self.state.write().await;
+        match res {
+            Ok(latency) => {
+                let latency = latency.as_millis() as u64;
+                let stats = &mut guard.ns_manager_spawn_stats;
+                stats.spawned += 1;
+                stats.last_latency_ms = latency;
+                stats.total_latency_ms += latency;
+                if latency > stats.max_latency_ms {
+                    stats.max_latency_ms = latency;
+                }
+                log::debug!(
+                    target: logging::NS_MANAGER,
+                    "ns-manager for {} ready in {}ms",
+                    ns_uuid,
+                    latency
+                );
+                Ok(())
+            }
+            Err(e) => {
+                guard.ns_manager_spawn_stats.failures += 1;
+                log::error!(
+                    target: logging::NS_MANAGER,
+                    "Unable to start ns-manager for {}: {}",
+                    ns_uuid,
+                    e
+                );
+                Err(e)
+            }
+        }
+    }
+
+    /// Spawns the Namespace Manager process and waits until its server is
+    /// available, returns the time needed by the manager to start.
+    async fn start_ns_manager(&self, ns_name: String, ns_uuid: Uuid) -> FResult<Duration> {
+        if let Some(delay) = self.config.ns_manager_spawn_delay_ms {
+            runtime::sleep(Duration::from_millis(delay)).await;
+        }
+        let start = std::time::Instant::now();
+        let (pid, ns_manager_client) = if simulation::is_enabled() {
+            let argv = vec![
+                "fos-net-linux-ns-manager".to_string(),
+                "--netns".to_string(),
+                ns_name.clone(),
+                "--id".to_string(),
+                format!("{}", ns_uuid),
+            ];
+            (
+                simulation::spawn(Some(&ns_name), argv)?,
+                NamespaceManagerChannel::Simulated(ns_name.clone()),
+            )
+        } else if self.is_ns_manager_shared() {
+            let (pid, shared_manager) = self.get_shared_ns_manager().await?;
+            shared_manager
+                .add_namespace(ns_name.clone(), ns_uuid)
+                .await??;
+            (
+                pid,
+                NamespaceManagerChannel::Shared(shared_manager, ns_uuid),
+            )
+        } else {
+            let mut cmd = Command::new("fos-net-linux-ns-manager");
+            cmd.arg("--netns")
+                .arg(&ns_name)
+                .arg("--id")
+                .arg(format!("{}", ns_uuid));
+            let ns_manager_client = match self
+                .config
+                .ns_manager_transport
+                .unwrap_or(NSManagerTransport::ZENOH)
+            {
+                NSManagerTransport::ZENOH => {
+                    cmd.arg("--locator").arg(self.config.zfilelocator.clone());
+                    NamespaceManagerChannel::Zenoh(NamespaceManagerClient::new(
+                        self.z.clone(),
+                        ns_uuid,
+                    ))
+                }
+                #[cfg(feature = "local-ns-channel")]
+                NSManagerTransport::UNIX => {
+                    let socket_path = self
+                        .config
+                        .run_path
+                        .join(format!("ns-manager-{}.sock", ns_uuid));
+                    cmd.arg("--socket").arg(&socket_path);
+                    NamespaceManagerChannel::Unix(UnixNamespaceManagerClient::new(socket_path))
+                }
+                #[cfg(not(feature = "local-ns-channel"))]
+                NSManagerTransport::UNIX => {
+                    return Err(FError::NetworkingError(
+                        "ns-manager unix transport is not enabled in this build".to_string(),
+                    ))
+                }
+            };
+            let child = cmd
+                .spawn()
+                .map_err(|e| FError::NetworkingError(format!("{}", e)))?;
+            (child.id(), ns_manager_client)
+        };
+        let mut guard = self.state.write().await;
+        guard
+            .ns_managers
+            .insert(ns_uuid, (pid, ns_manager_client.clone()));
+        drop(guard);
+
+        while !matches!(ns_manager_client.verify_server().await, Ok(true)) {
+            if start.elapsed() > Duration::from_secs(NS_MANAGER_STARTUP_TIMEOUT) {
+                self.kill_ns_manager(&ns_uuid).await?;
+                return Err(FError::NetworkingError(
+                    "Timeout waiting for ns-manager".to_string(),
+                ));
+            }
+            runtime::sleep(Duration::from_millis(10)).await;
+        }
+        Ok(start.elapsed())
+    }
+
+    fn is_ns_manager_shared(&self) -> bool {
+        // simulated namespaces have no manager process to share
+        self.config.shared_ns_manager.unwrap_or(false) && !simulation::is_enabled()
+    }
+
+    /// Returns the shared Namespace Manager, spawning it if it is not running.
+    async fn get_shared_ns_manager(&self) -> FResult<(u32, SharedNamespaceManagerClient)> {
+        let mut guard = self.state.write().await;
+        let (pid, shared_manager) = match guard.shared_ns_manager {
+            Some(ref shared) => shared.clone(),
+            None => {
+                let shared_uuid = Uuid::new_v4();
+                let child = Command::new("fos-net-linux-ns-manager")
+                    .arg("--shared")
+                    .arg("--id")
+                    .arg(format!("{}", shared_uuid))
+                    .arg("--locator")
+                    .arg(self.config.zfilelocator.clone())
+                    .spawn()
+                    .map_err(|e| FError::NetworkingError(format!("{}", e)))?;
+                let shared_manager = SharedNamespaceManagerClient::new(self.z.clone(), shared_uuid);
+                guard.shared_ns_manager = Some((child.id(), shared_manager.clone()));
+                (child.id(), shared_manager)
+            }
+        };
+        drop(guard);
+
+        let start = std::time::Instant::now();
+        while !matches!(shared_manager.verify_server().await, Ok(true)) {
+            if start.elapsed() > Duration::from_secs(NS_MANAGER_STARTUP_TIMEOUT) {
+                return Err(FError::NetworkingError(
+                    "Timeout waiting for shared ns-manager".to_string(),
+                ));
+            }
+            runtime::sleep(Duration::from_millis(10)).await;
+        }
+        Ok((pid, shared_manager))
+    }
+
+    async fn get_ns_manager(&self, ns_uuid: &Uuid) -> FResult<NamespaceManagerChannel> {
+        let mut guard = self.state.read().await;
+        let (_, ns_manager) = guard
+            .ns_managers
+            .get(ns_uuid)
+            .ok_or_else(|| FError::NetworkingError("Manager not found".to_string()))?;
+        Ok(ns_manager.clone())
+    }
+
+    async fn remove_ns_manager(&self, ns_uuid: &Uuid) -> FResult<(u32, NamespaceManagerChannel)> {
+        let mut guard = self.state.write().await;
+        let (pid, ns_manager) = guard
+            .ns_managers
+            .remove(&ns_uuid)
+            .ok_or_else(|| FError::NetworkingError("Manager not found".to_string()))?;
+        Ok((pid, ns_manager))
+    }
+
+    /// Returns the stored virtual network a connection point is bound to
+    async fn get_connection_point_network(&self, cp_uuid: Uuid) -> FResult<Option<VirtualNetwork>> {
+        Ok(self
+            .list_virtual_networks()
+            .await?
+            .into_iter()
+            .find(|vnet| vnet.connection_points.contains(&cp_uuid)))
+    }
+
+    /// Whether an interface is attached to the bridge of a virtual
+    /// network or is in one of its namespaces
+    async fn is_attached_to_vnet(
+        &self,
+        vnet: &VirtualNetwork,
+        internals: &VirtualNetworkInternals,
+        iface: &VirtualInterface,
+    ) -> bool {
+        let in_namespace = iface.net_ns.map_or(false, |ns_uuid| {
+            internals
+                .associated_netns
+                .as_ref()
+                .map_or(false, |netns| netns.ns_uuid == ns_uuid)
+                || internals
+                    .namespace_attachments
+                    .iter()
+                    .any(|a| a.ns_uuid == ns_uuid)
+        });
+        if in_namespace {
+            return true;
+        }
+        match (iface.parent, self.get_virtual_network_bridge(vnet).await) {
+            (Some(parent), Ok(br_uuid)) => parent == br_uuid,
+            _ => false,
+        }
+    }
+
+    /// Gives back an address taken by `assign_static_address`, to IPAM
+    /// or to the static addresses of the global record
+    async fn release_allocated_address(
+        &self,
+        vnet_uuid: Uuid,
+        internals: &VirtualNetworkInternals,
+        address: IPAddress,
+    ) {
+        let res = match internals.ipam {
+            Some(_) => self.release_address(vnet_uuid, address).await,
+            None => self
+                .update_global_internals(vnet_uuid, |_, global| {
+                    let before = global.static_addresses.len();
+                    global.static_addresses.retain(|s| s.address != address);
+                    Ok(global.static_addresses.len() != before)
+                })
+                .await
+                .map(|_| ()),
+        };
+        if let Err(e) = res {
+            log::warn!("Unable to release {} in {}: {}", address, vnet_uuid, e);
+        }
+    }
+
+    /// Returns the bridge in the default namespace of a virtual network
+    async fn get_virtual_network_bridge(&self, vnet: &VirtualNetwork) -> FResult<Uuid> {
+        for intf_uuid in &vnet.interfaces {
+            let iface = self.connector.local.get_interface(*intf_uuid).await?;
+            if let (VirtualInterfaceKind::BRIDGE(_), None) = (&iface.kind, iface.net_ns) {
+                return Ok(iface.uuid);
+            }
+        }
+        Err(FError::NotFound)
+    }
+
+    async fn build_connection_point(
+        &self,
+        netns: &mut NetworkNamespace,
+    ) -> FResult<ConnectionPoint> {
+        let cp = ConnectionPoint {
+            uuid: Uuid::new_v4(),
+            net_ns: netns.uuid,
+            bridge: Uuid::new_v4(),
+            internal_veth: Uuid::new_v4(),
+            external_veth: Uuid::new_v4(),
+        };
+        let br_name = self.generate_random_interface_name();
+        let internal_veth_name = self.generate_random_interface_name();
+        let external_veth_name = self.generate_random_interface_name();
+
+        let mut v_bridge = VirtualInterface {
+            uuid: cp.bridge,
+            if_name: br_name.clone(),
+            net_ns: Some(netns.uuid),
+            parent: None,
+            kind: VirtualInterfaceKind::BRIDGE(BridgeKind {
+                childs: vec![cp.internal_veth],
+            }),
+            addresses: Vec::new(),
+            phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
+        };
+
+        let v_veth_i = VirtualInterface {
+            uuid: cp.internal_veth,
+            if_name: internal_veth_name.clone(),
+            net_ns: Some(netns.uuid),
+            parent: Some(cp.bridge),
+            kind: VirtualInterfaceKind::VETH(VETHKind {
+                pair: cp.external_veth,
+                internal: true,
+            }),
+            addresses: Vec::new(),
+            phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
+        };
+
+        let v_veth_e = VirtualInterface {
+            uuid: cp.external_veth,
+            if_name: external_veth_name.clone(),
+            net_ns: None,
+            parent: None,
+            kind: VirtualInterfaceKind::VETH(VETHKind {
+                pair: cp.internal_veth,
+                internal: false,
+            }),
+            addresses: Vec::new(),
+            phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
+        };
+
+        // Creating veth pair
+        self.kernel()
+            .create_veth(external_veth_name.clone(), internal_veth_name.clone())
+            .await?;
+        self.store_interface(&v_veth_e).await?;
+        self.store_interface(&v_veth_i).await?;
+        netns.interfaces.push(cp.external_veth);
+        netns.interfaces.push(cp.internal_veth);
+
+        self.kernel().set_iface_up(external_veth_name).await?;
+        self.kernel()
+            .set_iface_ns(internal_veth_name.clone(), netns.ns_name.clone())
+            .await?;
+
+        // create internal bridge
+        let ns_manager = self.get_ns_manager(&netns.uuid).await?;
+        ns_manager
+            .add_virtual_interface_bridge(br_name.clone())
+            .await??;
+        netns.interfaces.push(cp.bridge);
+        ns_manager
+            .set_virtual_interface_up(br_name.clone())
+            .await??;
+        self.pin_bridge_mac(&mut v_bridge).await?;
+        self.store_interface(&v_bridge).await?;
+
+        ns_manager
+            .set_virtual_interface_master(internal_veth_name.clone(), br_name)
+            .await??;
+        ns_manager
+            .set_virtual_interface_up(internal_veth_name)
+            .await??;
+
+        self.store_network_namespace(netns).await?;
+        self.store_connection_point(&cp).await?;
+        Ok(cp)
+    }
+
+    async fn set_peering(&self, vnet_uuid: Uuid, peer: Uuid, allowed: bool) -> FResult<()> {
+        let mut vnet = self.connector.local.get_virtual_network(vnet_uuid).await?;
+        let mut internals =
+            deserialize_network_internals(vnet.plugin_internals.as_ref().ok_or(FError::NotFound)?)?;
+        internals.peerings.retain(|p| *p != peer);
+        if allowed {
+            internals.peerings.push(peer);
+        }
+        vnet.plugin_internals = Some(serialize_network_internals(&internals)?);
+        self.store_virtual_network(&vnet).await
+    }
+
+    /// Metrics of the primary and backup routes of a failover
+    fn failover_metrics(&self, primary_active: bool) -> (u32, u32) {
+        let active = self
+            .config
+            .default_route_metric
+            .unwrap_or(DEFAULT_FAILOVER_ROUTE_METRIC);
+        let standby = active + FAILOVER_STANDBY_METRIC_OFFSET;
+        if primary_active {
+            (active, standby)
+        } else {
+            (standby, active)
+        }
+    }
+
+    /// Sets the metrics of the two default routes of a failover, the new
+    /// routes are added before the ones with the old metrics are removed
+    /// so the namespace is never left without a default route.
+    async fn apply_failover_routes(
+        &self,
+        ns_uuid: Uuid,
+        failover: &DefaultRouteFailover,
+        primary_active: bool,
+    ) -> FResult<()> {
+        let ns_manager = self.get_ns_manager(&ns_uuid).await?;
+        let (primary_metric, backup_metric) = self.failover_metrics(primary_active);
+        for (gw, metric, old_metric) in &[
+            (&failover.primary, primary_metric, backup_metric),
+            (&failover.backup, backup_metric, primary_metric),
+        ] {
+            let iface = self.get_namespace_interface(ns_uuid, gw.intf_uuid).await?;
+            ns_manager
+                .add_virtual_interface_default_route(
+                    iface.if_name.clone(),
+                    failover_route(&gw.gateway, *metric),
+                )
+                .await??;
+            // not there yet when the failover is installed
+            if let Err(e) = ns_manager
+                .del_virtual_interface_default_route(
+                    iface.if_name,
+                    failover_route(&gw.gateway, *old_metric),
+                )
+                .await?
+            {
+                log::trace!("No default route via {} to remove: {}", gw.gateway, e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes the default routes of a failover, whichever their metric
+    async fn clear_failover_routes(&self, ns_uuid: Uuid, failover: &DefaultRouteFailover) {
+        let ns_manager = match self.get_ns_manager(&ns_uuid).await {
+            Ok(ns_manager) => ns_manager,
+            Err(_) => return,
+        };
+        let (active, standby) = self.failover_metrics(true);
+        for gw in &[&failover.primary, &failover.backup] {
+            let iface = match self.get_namespace_interface(ns_uuid, gw.intf_uuid).await {
+                Ok(iface) => iface,
+                Err(_) => continue,
+            };
+            for metric in &[active, standby] {
+                let _ = ns_manager
+                    .del_virtual_interface_default_route(
+                        iface.if_name.clone(),
+                        failover_route(&gw.gateway, *metric),
+                    )
+                    .await;
+            }
+        }
+    }
+
+    /// Installs the routes of the failovers recorded by a previous run,
+    /// with the primary gateway active
+    async fn restore_route_failovers(&self) {
+        let failovers: Vec<(Uuid, DefaultRouteFailover)> = self
+            .state
+            .read()
+            .await
+            .route_failovers
+            .iter()
+            .map(|(ns_uuid, (f, _))| (*ns_uuid, f.clone()))
+            .collect();
+        for (ns_uuid, failover) in failovers {
+            if let Err(e) = self.apply_failover_routes(ns_uuid, &failover, true).await {
+                log::warn!(
+                    target: logging::RECONCILE,
+                    "Unable to restore the default routes of {}: {}",
+                    ns_uuid,
+                    e
+                );
+            }
+        }
+    }
+
+    async fn apply_reverse_path_filter(
+        &self,
+        iface: &VirtualInterface,
+        filter: &ReversePathFilter,
+    ) -> FResult<()> {
+        match iface.net_ns {
+            Some(ns_uuid) => {
+                let ns_manager = self.get_ns_manager(&ns_uuid).await?;
+                ns_manager
+                    .set_reverse_path_filter(Some(iface.if_name.clone()), filter.clone())
+                    .await?
+            }
+            None => sysctl::set_reverse_path_filter(Command::new, Some(&iface.if_name), filter),
+        }
+    }
+
+    /// Merges the filter with the one already set on the namespace or
+    /// interface and records the result in the journal
+    async fn record_reverse_path_filter(&self, uuid: Uuid, filter: &ReversePathFilter) {
+        let merged = {
+            let mut guard = self.state.write().await;
+            let merged = match guard.rp_filters.get(&uuid) {
+                Some(older) => filter.merge(older),
+                None => filter.clone(),
+            };
+            guard.rp_filters.insert(uuid, merged.clone());
+            merged
+        };
+        self.append_journal(JournalEntry::ReversePathFilter(uuid, merged))
+            .await;
+    }
+
+    /// Sets again the reverse path filters recorded by a previous run,
+    /// they are lost when the namespaces or interfaces are recreated
+    async fn restore_reverse_path_filters(&self) {
+        let filters: Vec<(Uuid, ReversePathFilter)> = self
+            .state
+            .read()
+            .await
+            .rp_filters
+            .iter()
+            .map(|(uuid, filter)| (*uuid, filter.clone()))
+            .collect();
+        for (uuid, filter) in filters {
+            let res = if self.state.read().await.known_namespaces.contains(&uuid) {
+                match self.get_ns_manager(&uuid).await {
+                    Ok(ns_manager) => ns_manager
+                        .set_reverse_path_filter(None, filter)
+                        .await
+                        .map_err(FError::from)
+                        .and_then(|r| r),
+                    Err(e) => Err(e),
+                }
+            } else {
+                match self.connector.local.get_interface(uuid).await {
+                    Ok(iface) => self.apply_reverse_path_filter(&iface, &filter).await,
+                    Err(e) => Err(e),
+                }
+            };
+            if let Err(e) = res {
+                log::warn!(
+                    target: logging::RECONCILE,
+                    "Unable to restore the reverse path filter of {}: {}",
+                    uuid,
+                    e
+                );
+            }
+        }
+    }
+
+    /// Probes the primary gateway of each failover, all at once, the
+    /// metrics are swapped after `max_failures` missed probes and swapped
+    /// back on the first answered one.
+    async fn probe_route_failovers(&self) {
+        let failovers: Vec<(Uuid, DefaultRouteFailover, DefaultRouteFailoverStatus)> = self
+            .state
+            .read()
+            .await
+            .route_failovers
+            .iter()
+            .map(|(ns_uuid, (f, s))| (*ns_uuid, f.clone(), s.clone()))
+            .collect();
+        futures::future::join_all(failovers.into_iter().map(|(ns_uuid, failover, status)| {
+            self.probe_route_failover(ns_uuid, failover, status)
+        }))
+        .await;
+    }
+
+    async fn probe_route_failover(
+        &self,
+        ns_uuid: Uuid,
+        failover: DefaultRouteFailover,
+        mut status: DefaultRouteFailoverStatus,
+    ) {
+        let probe = async_std::future::timeout(
+            Duration::from_secs(FAILOVER_PROBE_TIMEOUT),
+            self.probe_failover_gateway(ns_uuid, &failover),
+        )
+        .await;
+        let reachable = match probe {
+            Ok(Ok(reachable)) => reachable,
+            Err(_) => false,
+            Ok(Err(e)) => {
+                log::warn!(
+                    target: logging::RECONCILE,
+                    "Unable to probe the gateway of {}: {}",
+                    ns_uuid,
+                    e
+                );
+                return;
+            }
+        };
+        let max_failures = failover
+            .max_failures
+            .unwrap_or(DEFAULT_FAILOVER_MAX_FAILURES);
+        status.failures = if reachable { 0 } else { status.failures + 1 };
+        let primary_active = if reachable {
+            true
+        } else if status.failures >= max_failures {
+            false
+        } else {
+            status.primary_active
+        };
+        if primary_active != status.primary_active {
+            log::info!(
+                target: logging::RECONCILE,
+                "Default route of {} switching to the {} gateway",
+                ns_uuid,
+                if primary_active { "primary" } else { "backup" }
+            );
+            if let Err(e) = self
+                .apply_failover_routes(ns_uuid, &failover, primary_active)
+                .await
+            {
+                log::error!(
+                    target: logging::RECONCILE,
+                    "Unable to switch the default route of {}: {}",
+                    ns_uuid,
+                    e
+                );
+                return;
+            }
+            status.primary_active = primary_active;
+            status.switches += 1;
+        }
+        if let Some((_, s)) = self.state.write().await.route_failovers.get_mut(&ns_uuid) {
+            *s = status;
+        }
+    }
+
+    async fn probe_failover_gateway(
+        &self,
+        ns_uuid: Uuid,
+        failover: &DefaultRouteFailover,
+    ) -> FResult<bool> {
+        let iface = self
+            .get_namespace_interface(ns_uuid, failover.primary.intf_uuid)
+            .await?;
+        let ns_manager = self.get_ns_manager(&ns_uuid).await?;
+        ns_manager
+            .probe_gateway(iface.if_name, failover.primary.gateway)
+            .await?
+    }
+
+    /// Returns the interface if it is inside the given namespace
+    async fn get_namespace_interface(
+        &self,
+        ns_uuid: Uuid,
+        intf_uuid: Uuid,
+    ) -> FResult<VirtualInterface> {
+        let netns = self.connector.local.get_network_namespace(ns_uuid).await?;
+        let iface = self.connector.local.get_interface(intf_uuid).await?;
+        match iface.net_ns {
+            Some(nid) if nid == netns.uuid => Ok(iface),
+            _ => Err(FError::NotConnected),
+        }
+    }
+
+    /// Connects the namespace of a services network to the one of a tenant
+    /// with a veth pair, each end routing the subnet of the other network
+    async fn create_services_link(
+        &self,
+        services: &VirtualNetwork,
+        services_internals: &VirtualNetworkInternals,
+        tenant_uuid: Uuid,
+        link_net: IpNetwork,
+    ) -> FResult<VNetServicesLink> {
+        let tenant = self
+            .connector
+            .local
+            .get_virtual_network(tenant_uuid)
+            .await?;
+        let tenant_internals = deserialize_network_internals(
+            tenant.plugin_internals.as_ref().ok_or(FError::NotFound)?,
+        )?;
+        let (services_ns, tenant_ns) = match (
+            &services_internals.associated_netns,
+            &tenant_internals.associated_netns,
+        ) {
+            (Some(s), Some(t)) => (s.clone(), t.clone()),
+            _ => {
+                return Err(FError::NetworkingError(format!(
+                    "{} and {} need a namespace on this node to be linked",
+                    services.uuid, tenant_uuid
+                )))
+            }
+        };
+        let (services_addr, tenant_addr) = services_link_addresses(link_net)?;
+        let link = VNetServicesLink {
+            tenant: tenant_uuid,
+            services_veth: Uuid::new_v4(),
+            tenant_veth: Uuid::new_v4(),
+            link: link_net,
+        };
+        let services_veth_name = self.generate_random_interface_name();
+        let tenant_veth_name = self.generate_random_interface_name();
+        for (uuid, name, ns_uuid, pair) in &[
+            (
+                link.services_veth,
+                &services_veth_name,
+                services_ns.ns_uuid,
+                link.tenant_veth,
+            ),
+            (
+                link.tenant_veth,
+                &tenant_veth_name,
+                tenant_ns.ns_uuid,
+                link.services_veth,
+            ),
+        ] {
+            let v_veth = VirtualInterface {
+                uuid: *uuid,
+                if_name: name.to_string(),
+                net_ns: Some(*ns_uuid),
+                parent: None,
+                kind: VirtualInterfaceKind::VETH(VETHKind {
+                    pair: *pair,
+                    internal: true,
+                }),
+                addresses: Vec::new(),
+                phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
+            };
+            if *uuid == link.services_veth {
+                self.kernel()
+                    .create_veth(services_veth_name.clone(), tenant_veth_name.clone())
+                    .await?;
+            }
+            self.store_interface(&v_veth).await?;
+            let mut netns = self.connector.local.get_network_namespace(*ns_uuid).await?;
+            netns.interfaces.push(*uuid);
+            self.store_network_namespace(&netns).await?;
+        }
+
+        let res: FResult<()> = async {
+            for (name, ns, addr, other_vnet, other_addr) in &[
+                (
+                    &services_veth_name,
+                    &services_ns,
+                    services_addr,
+                    &tenant,
+                    tenant_addr,
+                ),
+                (
+                    &tenant_veth_name,
+                    &tenant_ns,
+                    tenant_addr,
+                    services,
+                    services_addr,
+                ),
+            ] {
+                self.kernel()
+                    .set_iface_ns(name.to_string(), ns.ns_name.clone())
+                    .await?;
+                let ns_manager = self.get_ns_manager(&ns.ns_uuid).await?;
+                let addr = IpNetwork::new(*addr, link_net.prefix())
+                    .map_err(|e| FError::NetworkingError(format!("{}", e)))?;
+                ns_manager
+                    .add_virtual_interface_address(name.to_string(), Some(addr))
+                    .await??;
+                ns_manager
+                    .set_virtual_interface_up(name.to_string())
+                    .await??;
+                if let Some((net_addr, prefix)) =
+                    other_vnet.ip_configuration.as_ref().and_then(|c| c.subnet)
+                {
+                    let net = IpNetwork::new(net_addr, prefix)
+                        .map_err(|e| FError::NetworkingError(format!("{}", e)))?;
+                    ns_manager
+                        .add_virtual_interface_route(name.to_string(), net, *other_addr)
+                        .await??;
+                }
+            }
+            Ok(())
+        }
+        .await;
+        if let Err(e) = res {
+            log::error!(
+                "Unable to link {} to the services of {}: {}",
+                tenant_uuid,
+                services.uuid,
+                e
+            );
+            self.remove_services_link(&link).await?;
+            return Err(e);
+        }
+        Ok(link)
+    }
+
+    /// Deletes the veth pair of a services link and its records, the pair
+    /// may be already gone with one of the namespaces
+    async fn remove_services_link(&self, link: &VNetServicesLink) -> FResult<()> {
+        for uuid in &[link.services_veth, link.tenant_veth] {
+            let iface = match self.connector.local.get_interface(*uuid).await {
+                Ok(iface) => iface,
+                Err(_) => continue,
+            };
+            if let Some(ns_uuid) = iface.net_ns {
+                if let Ok(ns_manager) = self.get_ns_manager(&ns_uuid).await {
+                    if let Ok(Ok(true)) = ns_manager
+                        .check_virtual_interface_exists(iface.if_name.clone())
+                        .await
+                    {
+                        if let Err(e) = ns_manager.del_virtual_interface(iface.if_name).await? {
+                            log::warn!("Unable to remove {}: {}", uuid, e);
+                        }
+                    }
+                }
+                if let Ok(mut netns) = self.connector.local.get_network_namespace(ns_uuid).await {
+                    netns.interfaces.retain(|u| u != uuid);
+                    self.store_network_namespace(&netns).await?;
+                }
+            }
+            self.remove_interface_record(*uuid).await?;
+        }
+        Ok(())
+    }
+
+    /// First /30 of `SERVICES_LINK_POOL` not used by a link of this node
+    async fn allocate_services_link(&self) -> FResult<IpNetwork> {
+        let mut used = Vec::new();
+        for vnet in self.list_virtual_networks().await? {
+            if let Some(Ok(internals)) = vnet
+                .plugin_internals
+                .as_ref()
+                .map(|raw| deserialize_network_internals(raw))
+            {
+                used.extend(internals.services_links.iter().map(|l| l.link));
+            }
+        }
+        let (pool_addr, pool_prefix) = SERVICES_LINK_POOL;
+        let start = u32::from(pool_addr);
+        (0..1u32 << (30 - pool_prefix))
+            .map(|i| std::net::Ipv4Addr::from(start + (i << 2)))
+            .filter_map(|addr| IpNetwork::new(IPAddress::V4(addr), 30).ok())
+            .find(|net| !used.contains(net))
+            .ok_or_else(|| FError::NetworkingError("No free services link subnet".to_string()))
+    }
+
+    /// Lets the tenants open connections to the services network through
+    /// their links, only the answers go back. Kept in the namespace of the
+    /// services network.
+    async fn apply_services_filter(&self, internals: &VirtualNetworkInternals) -> FResult<()> {
+        let ns_info = match &internals.associated_netns {
+            Some(ns_info) => ns_info,
+            None => return Ok(()),
+        };
+        let mut links = Vec::new();
+        for link in &internals.services_links {
+            links.push(
+                self.connector
+                    .local
+                    .get_interface(link.services_veth)
+                    .await?
+                    .if_name,
+            );
+        }
+        let script = render_services_filter(SERVICES_TABLE, &links);
+        log::trace!(target: logging::NFT, "apply_services_filter: {}", script);
+        let mut cmd = netns_command(&ns_info.ns_name, "nft");
+        cmd.arg(script);
+        exec_command(cmd)?;
+        Ok(())
+    }
+
+    /// Creates again the links of a services network lost with the
+    /// namespaces, eg. after a reboot
+    async fn restore_services_links(
+        &self,
+        vnet: &VirtualNetwork,
+        internals: &VirtualNetworkInternals,
+    ) -> FResult<()> {
+        let mut restored = internals.clone();
+        let mut changed = false;
+        for link in restored.services_links.iter_mut() {
+            let exists = match self.connector.local.get_interface(link.services_veth).await {
+                Ok(iface) => match iface.net_ns {
+                    Some(ns_uuid) => {
+                        self.get_ns_manager(&ns_uuid)
+                            .await?
+                            .check_virtual_interface_exists(iface.if_name)
+                            .await??
+                    }
+                    None => false,
+                },
+                Err(_) => false,
+            };
+            if exists {
+                continue;
+            }
+            self.remove_services_link(link).await?;
+            *link = self
+                .create_services_link(vnet, internals, link.tenant, link.link)
+                .await?;
+            changed = true;
+        }
+        self.apply_services_filter(&restored).await?;
+        if changed {
+            let mut vnet = vnet.clone();
+            vnet.plugin_internals = Some(serialize_network_internals(&restored)?);
+            self.store_virtual_network(&vnet).await?;
+        }
+        Ok(())
+    }
+
+    /// Deletes the veth pair of a namespace attachment and removes it from
+    /// the namespace record, the pair may be already gone with the namespace.
+    async fn remove_namespace_attachment(
+        &self,
+        attachment: &VNetNamespaceAttachment,
+    ) -> FResult<()> {
+        if let Ok(ext_veth) = self
+            .connector
+            .local
+            .get_interface(attachment.external_veth)
+            .await
+        {
+            if let Err(e) = self.kernel().del_iface(ext_veth.if_name).await {
+                log::warn!("Unable to remove {}: {}", attachment.external_veth, e);
+            }
+            self.remove_interface_record(attachment.external_veth)
+                .await?;
+        }
+        if self
+            .connector
+            .local
+            .get_interface(attachment.internal_veth)
+            .await
+            .is_ok()
+        {
+            self.remove_interface_record(attachment.internal_veth)
+                .await?;
+        }
+        if let Ok(mut netns) = self
+            .connector
+            .local
+            .get_network_namespace(attachment.ns_uuid)
+            .await
+        {
+            netns
+                .interfaces
+                .retain(|u| *u != attachment.internal_veth && *u != attachment.external_veth);
+            self.store_network_namespace(&netns).await?;
+        }
+        Ok(())
+    }
+
+    /// Drops the attachments to a namespace from the internals of the
+    /// virtual networks, used when the namespace is deleted.
+    async fn detach_networks_from_namespace(&self, ns_uuid: Uuid) -> FResult<()> {
+        let candidates: Vec<Uuid> = self
+            .state
+            .read()
+            .await
+            .known_vnets
+            .iter()
+            .copied()
+            .collect();
+        for vnet_uuid in candidates {
+            let mut vnet = match self.connector.local.get_virtual_network(vnet_uuid).await {
+                Ok(vnet) => vnet,
+                Err(_) => continue,
+            };
+            let mut internals = match vnet.plugin_internals {
+                Some(ref internals) => deserialize_network_internals(internals)?,
+                None => continue,
+            };
+            let pos = match internals
+                .namespace_attachments
+                .iter()
+                .position(|a| a.ns_uuid == ns_uuid)
+            {
+                Some(pos) => pos,
+                None => continue,
+            };
+            let attachment = internals.namespace_attachments.remove(pos);
+            self.remove_namespace_attachment(&attachment).await?;
+            vnet.plugin_internals = Some(serialize_network_internals(&internals)?);
+            self.store_virtual_network(&vnet).await?;
+        }
+        Ok(())
+    }
+
+    /// Adds the bridge of a virtual network to the ones isolated from each
+    /// other, for a management network the management interface of the
+    /// node, if configured, is attached to the bridge.
+    async fn register_virtual_network_bridge(
+        &self,
+        mut vnet: VirtualNetwork,
+    ) -> FResult<VirtualNetwork> {
+        let br_uuid = self.get_virtual_network_bridge(&vnet).await?;
+        let bridge = self.connector.local.get_interface(br_uuid).await?;
+        if let (true, Some(mgmt_iface)) = (vnet.is_mgmt, &self.config.mgmt_iface) {
+            log::info!(
+                "Attaching {} to the management network {}",
+                mgmt_iface,
+                vnet.uuid
+            );
+            self.kernel()
+                .set_iface_master(mgmt_iface.clone(), bridge.if_name.clone())
+                .await?;
+        }
+        self.add_traffic_counters(vnet.uuid, &bridge.if_name)?;
+        // the isolation is enforced with nftables
+        if !cfg!(feature = "nat") {
+            return Ok(vnet);
+        }
+        let mut internals =
+            deserialize_network_internals(vnet.plugin_internals.as_ref().ok_or(FError::NotFound)?)?;
+        internals.isolated_bridge = Some(bridge.if_name.clone());
+        vnet.plugin_internals = Some(serialize_network_internals(&internals)?);
+        self.store_virtual_network(&vnet).await?;
+        self.state
+            .write()
+            .await
+            .isolated_bridges
+            .insert(vnet.uuid, bridge.if_name);
+        self.update_isolation().await?;
+        Ok(vnet)
+    }
+
+    #[cfg(not(feature = "nat"))]
+    async fn update_isolation(&self) -> FResult<()> {
+        Err(FError::Unimplemented)
+    }
+
+    /// Rewrites the forward chain of the isolation table from the
+    /// isolated bridges, the peerings of their virtual networks and
+    /// the tenants of the services networks
+    #[cfg(feature = "nat")]
+    async fn update_isolation(&self) -> FResult<()> {
+        let bridges = self.state.read().await.isolated_bridges.clone();
+        let mut peerings = Vec::new();
+        let mut services = Vec::new();
+        let mut mgmt = Vec::new();
+        for vnet_uuid in bridges.keys() {
+            let vnet = self.connector.local.get_virtual_network(*vnet_uuid).await?;
+            if vnet.is_mgmt {
+                mgmt.push(*vnet_uuid);
+            }
+            if let Some(ref internals) = vnet.plugin_internals {
+                let internals = deserialize_network_internals(internals)?;
+                for peer in internals.peerings {
+                    peerings.push((*vnet_uuid, peer));
+                }
+                for tenant in internals.services_tenants {
+                    services.push((*vnet_uuid, tenant));
+                }
+            }
+        }
+        let script = render_isolation(ISOLATION_TABLE, &bridges, &peerings, &services, &mgmt);
+        log::trace!(target: logging::NFT, "update_isolation: {}", script);
+        let mut cmd = Command::new("nft");
+        cmd.arg(script);
+        exec_command(cmd)?;
+        Ok(())
+    }
+
+    /// Creates the traffic table of a virtual network, or rewrites its
+    /// accounting chain when it exists, keeping the counters
+    fn add_traffic_counters(&self, vnet_uuid: Uuid, br: &str) -> FResult<()> {
+        let table = traffic_table(vnet_uuid);
+        let script = render_traffic_counters(&table, vnet_uuid, br);
+        log::trace!(target: logging::NFT, "add_traffic_counters: {}", script);
+        if simulation::is_enabled() {
+            return simulation::add_nft_table(&table, script);
+        }
+        let mut cmd = Command::new("nft");
+        cmd.arg(script);
+        exec_command(cmd)?;
+        Ok(())
+    }
+
+    /// Drops the traffic table of a virtual network, a failure is only
+    /// logged as the network is going away
+    fn delete_traffic_counters(&self, vnet_uuid: Uuid) {
+        let table = traffic_table(vnet_uuid);
+        let res = if simulation::is_enabled() {
+            simulation::del_nft_table(&table)
+        } else {
+            let mut cmd = Command::new("nft");
+            cmd.arg("delete").arg("table").arg("inet").arg(&table);
+            exec_command(cmd).map(|_| ())
+        };
+        if let Err(e) = res {
+            log::warn!(
+                target: logging::NFT,
+                "Unable to delete the traffic counters of {}: {}",
+                vnet_uuid,
+                e
+            );
+        }
+    }
+
+    /// Packets and bytes of a counter of the traffic table of a virtual
+    /// network, the simulated kernel does not count
+    fn read_traffic_counter(&self, vnet_uuid: Uuid, name: &str) -> FResult<(u64, u64)> {
+        if simulation::is_enabled() {
+            return Ok((0, 0));
+        }
+        let mut cmd = Command::new("nft");
+        cmd.arg("list")
+            .arg("counter")
+            .arg("inet")
+            .arg(traffic_table(vnet_uuid))
+            .arg(name);
+        let listing = exec_command(cmd)?;
+        parse_nft_counter(&listing)
+            .ok_or_else(|| FError::NetworkingError(format!("Unable to parse counter {}", name)))
+    }
+
+    /// Returns the first address configured inside the namespace of a
+    /// connection point, the veth pair of the connection point is skipped.
+    async fn get_connection_point_address(&self, cp: &ConnectionPoint) -> FResult<IPAddress> {
+        let netns = self
+            .connector
+            .local
+            .get_network_namespace(cp.net_ns)
+            .await?;
+        let ns_manager = self.get_ns_manager(&cp.net_ns).await?;
+        for intf_uuid in netns
+            .interfaces
+            .iter()
+            .filter(|u| **u != cp.internal_veth && **u != cp.external_veth)
+        {
+            let iface = self.connector.local.get_interface(*intf_uuid).await?;
+            let addresses = ns_manager
+                .get_virtual_interface_addresses(iface.if_name)
+                .await??;
+            if let Some(addr) = addresses
+                .into_iter()
+                .find(|a| !a.is_loopback() && !a.is_multicast())
+            {
+                return Ok(addr);
+            }
+        }
+        Err(FError::NotFound)
+    }
+
+    /// Removes and kills a Namespaces Manager,
+    /// in shared mode the namespace is removed from the shared manager
+    async fn kill_ns_manager(&self, ns_uuid: &Uuid) -> FResult<()> {
+        let (pid, ns_manager) = self.remove_ns_manager(ns_uuid).await?;
+        if self.is_ns_manager_shared() {
+            let guard = self.state.read().await;
+            let (_, shared_manager) = guard
+                .shared_ns_manager
+                .clone()
+                .ok_or_else(|| FError::NetworkingError("Manager not found".to_string()))?;
+            drop(guard);
+            return shared_manager.remove_namespace(*ns_uuid).await?;
+        }
+        self.terminate_process(pid).await?;
+        if let Err(e) = ns_manager.release_endpoint().await {
+            log::error!(
+                target: logging::NS_MANAGER,
+                "ns-manager for {} not cleaned up: {}",
+                ns_uuid,
+                e
+            );
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Sends SIGTERM to a process and reaps it, SIGKILL is sent if it is
+    /// still running after `ns_manager_kill_timeout_ms`. Processes that are
+    /// not children of the plugin, eg. spawned before a restart, cannot be
+    /// reaped and are polled until they are gone.
+    async fn terminate_process(&self, pid: u32) -> FResult<()> {
+        if simulation::is_enabled() {
+            return simulation::kill(pid);
+        }
+        let pid = Pid::from_raw(pid as i32);
+        let timeout = Duration::from_millis(
+            self.config
+                .ns_manager_kill_timeout_ms
+                .unwrap_or(DEFAULT_NS_MANAGER_KILL_TIMEOUT_MS),
+        );
+        match kill(pid, Signal::SIGTERM) {
+            Err(nix::errno::Errno::ESRCH) => return Ok(()),
+            Err(e) => return Err(FError::NetworkingError(format!("{}", e))),
+            Ok(_) => (),
+        }
+        let start = std::time::Instant::now();
+        let mut killed = false;
+        loop {
+            let exited = match waitpid(pid, Some(WaitPidFlag::WNOHANG)) {
+                Ok(WaitStatus::StillAlive) => false,
+                Ok(_) => true,
+                Err(nix::errno::Errno::ECHILD) => kill(pid, None).is_err(),
+                Err(e) => return Err(FError::NetworkingError(format!("{}", e))),
+            };
+            if exited {
+                return Ok(());
+            }
+            if start.elapsed() > timeout {
+                if killed {
+                    return Err(FError::NetworkingError(format!(
+                        "Process {} did not exit after SIGKILL",
+                        pid
+                    )));
+                }
+                log::warn!(
+                    target: logging::NS_MANAGER,
+                    "Process {} ignored SIGTERM, sending SIGKILL",
+                    pid
+                );
+                kill(pid, Signal::SIGKILL)
+                    .map_err(|e| FError::NetworkingError(format!("{}", e)))?;
+                killed = true;
+            }
+            runtime::sleep(Duration::from_millis(NS_MANAGER_KILL_POLL_MS)).await;
+        }
+    }
+
+    /// Creates an L2 virtual network, if `unicast` is set the VXLAN
+    /// interface is created without multicast group and the
+    /// remote peers have to be added to its forwarding database.
+    /// When the descriptor internals set `provider_vlan` the bridge is
+    /// connected to a VLAN sub-interface of the dataplane interface
+    /// instead, so that the network joins an existing VLAN of the
+    /// external switches.
+    async fn mcast_vxlan_create(
+        &self,
+        mut vnet: VirtualNetwork,
+        vxlan_info: MCastVXLANInfo,
+        unicast: bool,
+    ) -> FResult<VirtualNetwork> {
+        let node_uuid = self.agent.as_ref().unwrap().get_node_uuid().await??;
+        let requested = vnet
+            .plugin_internals
+            .as_ref()
+            .and_then(|raw| deserialize_network_internals(raw).ok());
+        let snat = requested.as_ref().and_then(|i| i.snat.clone());
+        if let Some(snat) = &snat {
+            validate_snat(snat)?;
+        }
+        self.check_nat64_supported(requested.as_ref())?;
+        let provider_vlan = requested.as_ref().and_then(|i| i.provider_vlan);
+        if unicast && provider_vlan.is_some() {
+            return Err(FError::WrongKind);
+        }
+
+        self.operation_step(vnet.uuid, CreateStep::DEVICES).await;
+
+        // Generating Names
+
+        let br_uuid = Uuid::new_v4();
+        let br_name = self.generate_random_interface_name();
+
+        let vxl_uuid = Uuid::new_v4();
+        let vxl_name = self.generate_random_interface_name();
+
+        let internal_br_uuid = Uuid::new_v4();
+        let internal_br_name = self.generate_random_interface_name();
+
+        let internal_veth_uuid = Uuid::new_v4();
+        let internal_veth_name = self.generate_random_interface_name();
+
+        let external_veth_uuid = Uuid::new_v4();
+        let external_veth_name = self.generate_random_interface_name();
+
+        let mut associated_ns = NetworkNamespace {
+            uuid: vnet.uuid,
+            ns_name: self.generate_random_netns_name(),
+            interfaces: vec![
+                external_veth_uuid,
+                internal_veth_uuid,
+                internal_br_uuid,
+                vxl_uuid,
+                br_uuid,
+            ],
+        };
+
+        // Generating Structs
+
+        let mut v_bridge = VirtualInterface {
+            uuid: br_uuid,
+            if_name: br_name.clone(),
+            net_ns: None,
+            parent: None,
+            kind: VirtualInterfaceKind::BRIDGE(BridgeKind {
+                childs: vec![external_veth_uuid, vxl_uuid],
+            }),
+            addresses: Vec::new(),
+            phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
+        };
+
+        let mut v_internal_bridge = VirtualInterface {
+            uuid: internal_br_uuid,
+            if_name: internal_br_name.clone(),
+            net_ns: Some(associated_ns.uuid),
+            parent: None,
+            kind: VirtualInterfaceKind::BRIDGE(BridgeKind {
+                childs: vec![internal_veth_uuid],
+            }),
+            addresses: Vec::new(),
+            phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
+        };
+
+        let vxl_kind = match provider_vlan {
+            Some(tag) => VirtualInterfaceKind::VLAN(VLANKind {
+                tag,
+                dev: self.get_dataplane_from_config().await?,
+            }),
+            None => VirtualInterfaceKind::VXLAN(VXLANKind {
+                vni: vxlan_info.vni,
+                port: vxlan_info.port,
+                mcast_addr: vxlan_info.mcast_addr,
+                dev: Interface {
+                    if_name: self.get_overlay_iface().await?,
+                    kind: InterfaceKind::ETHERNET,
+                    addresses: Vec::new(),
+                    phy_address: None,
+                },
+            }),
+        };
+
+        let vxl_iface = VirtualInterface {
+            uuid: vxl_uuid,
+            if_name: vxl_name.clone(),
+            net_ns: None,
+            parent: Some(br_uuid),
+            kind: vxl_kind,
+            addresses: Vec::new(),
+            phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
+        };
+
+        let v_veth_i = VirtualInterface {
+            uuid: internal_veth_uuid,
+            if_name: internal_veth_name.clone(),
+            net_ns: Some(associated_ns.uuid),
+            parent: Some(internal_br_uuid),
+            kind: VirtualInterfaceKind::VETH(VETHKind {
+                pair: external_veth_uuid,
+                internal: true,
+            }),
+            addresses: Vec::new(),
+            phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
+        };
+
+        let v_veth_e = VirtualInterface {
+            uuid: external_veth_uuid,
+            if_name: external_veth_name.clone(),
+            net_ns: None,
+            parent: Some(br_uuid),
+            kind: VirtualInterfaceKind::VETH(VETHKind {
+                pair: internal_veth_uuid,
+                internal: false,
+            }),
+            addresses: Vec::new(),
+            phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
+        };
+
+        // Creating Virtual network bridge
+
+        self.kernel().create_bridge(br_name.clone()).await?;
+        self.pin_bridge_mac(&mut v_bridge).await?;
+        self.store_interface(&v_bridge).await?;
+
+        vnet.interfaces.push(br_uuid);
+
+        self.kernel().set_iface_up(br_name.clone()).await?;
+
+        // Creating VXLAN Interface, or the VLAN one of a provider network
+
+        if let Some(tag) = provider_vlan {
+            self.kernel()
+                .create_vlan(
+                    vxl_name.clone(),
+                    self.get_dataplane_from_config().await?.if_name,
+                    tag,
+                )
+                .await?;
+        } else if unicast {
+            let overlay_iface = self.get_overlay_face_from_config().await?;
+            let local_addr = *overlay_iface.addresses.first().ok_or(FError::NotFound)?;
+            self.kernel()
+                .create_unicast_vxlan(
+                    vxl_name.clone(),
+                    overlay_iface.if_name,
+                    vxlan_info.vni,
+                    local_addr,
+                    vxlan_info.port,
+                    &self.get_vxlan_options(None),
+                )
+                .await?;
+        } else {
+            self.kernel()
+                .create_mcast_vxlan(
+                    vxl_name.clone(),
+                    self.get_overlay_iface().await?,
+                    vxlan_info.vni,
+                    vxlan_info.mcast_addr,
+                    vxlan_info.port,
+                    &self.get_vxlan_options(None),
+                )
+                .await?;
+        }
+        self.store_interface(&vxl_iface).await?;
+
+        vnet.interfaces.push(vxl_uuid);
+
+        self.kernel()
+            .set_iface_master(vxl_name.clone(), br_name.clone())
+            .await?;
+        self.kernel().set_iface_up(vxl_name).await?;
+
+        // Creating netns and spawing the namespace manager
+        self.operation_step(vnet.uuid, CreateStep::NAMESPACE).await;
+        self.kernel()
+            .add_netns(associated_ns.ns_name.clone())
+            .await?;
+        self.spawn_ns_manager(associated_ns.ns_name.clone(), associated_ns.uuid)
+            .await?;
+
+        self.store_network_namespace(&associated_ns).await?;
+
+        // Creating veth pair
+        self.kernel()
+            .create_veth(external_veth_name.clone(), internal_veth_name.clone())
+            .await?;
+
+        self.store_interface(&v_veth_e).await?;
+
+        vnet.interfaces.push(internal_veth_uuid);
+
+        self.store_interface(&v_veth_i).await?;
+
+        vnet.interfaces.push(external_veth_uuid);
+
+        self.kernel()
+            .set_iface_master(external_veth_name.clone(), br_name.clone())
+            .await?;
+        self.kernel().set_iface_up(external_veth_name).await?;
+
+        self.kernel()
+            .set_iface_ns(
+                internal_veth_name.clone(),
+                associated_ns.ns_name.clone().clone(),
+            )
+            .await?;
+
+        // create internal bridge
+        let ns_manager = self.get_ns_manager(&associated_ns.uuid).await?;
+
+        // This is used to wait that the namespace manager is ready to serve
+        while !ns_manager.verify_server().await? {}
+
+        ns_manager
+            .set_virtual_interface_up("lo".to_string())
+            .await??;
+
+        ns_manager
+            .add_virtual_interface_bridge(internal_br_name.clone())
+            .await??;
+
+        ns_manager
+            .set_virtual_interface_up(internal_br_name.clone())
+            .await??;
+
+        vnet.interfaces.push(internal_br_uuid);
+
+        self.pin_bridge_mac(&mut v_internal_bridge).await?;
+        self.store_interface(&v_internal_bridge).await?;
+
+        ns_manager
+            .set_virtual_interface_master(internal_veth_name.clone(), internal_br_name.clone())
+            .await??;
+
+        ns_manager
+            .set_virtual_interface_up(internal_veth_name.clone())
+            .await??;
+
+        if provider_vlan.is_none() {
+            let underlay = if unicast {
+                *self
+                    .get_overlay_face_from_config()
+                    .await?
+                    .addresses
+                    .first()
+                    .ok_or(FError::NotFound)?
+            } else {
+                vxlan_info.mcast_addr
+            };
+            let mtu = self
+                .get_vxlan_mtu(self.get_overlay_iface().await?, underlay)
+                .await?;
+            // the devices of the network carry the VXLAN encapsulation overhead
+            self.set_interfaces_mtu(
+                mtu,
+                &[
+                    &v_bridge,
+                    &vxl_iface,
+                    &v_veth_e,
+                    &v_veth_i,
+                    &v_internal_bridge,
+                ],
+            )
+            .await?;
+        }
+
+        // NAT configuration, skip it for the time being...
+        // let nat_table = self
+        //     .configure_nat(
+        //         IpNetwork::V4(
+        //             ipnetwork::Ipv4Network::new(
+        //                 std::net::Ipv4Addr::new(10, 240, 0, 0),
+        //                 16,
+        //             )
+        //             .map_err(|e| FError::NetworkingError(format!("{}", e)))?,
+        //         ),
+        //         &self.get_overlay_face_from_config().await?.if_name,
+        //     )
+        //     .await?;
+
+        // DHCP configuration and spawn
+        self.operation_step(vnet.uuid, CreateStep::DHCP).await;
+
+        let dhcp_internal = self
+            .start_vnet_dhcp(&vnet, requested.as_ref(), &associated_ns, &internal_br_name)
+            .await?;
+        let resolver = match &dhcp_internal {
+            Some(dhcp) if self.is_dns_cache_enabled(requested.as_ref()) => Some(
+                self.start_vnet_resolver(
+                    &vnet,
+                    &associated_ns,
+                    &internal_br_name,
+                    dhcp,
+                    requested
+                        .as_ref()
+                        .map(|i| i.dns_records.as_slice())
+                        .unwrap_or_default(),
+                )
+                .await?,
+            ),
+            _ => None,
+        };
+        let nat64_gateway = match (&dhcp_internal, &requested) {
+            (Some(dhcp), Some(requested)) if self.is_nat64_enabled(Some(requested)) => Some(
+                self.start_vnet_nat64(
+                    &vnet,
+                    requested,
+                    &associated_ns,
+                    &internal_br_name,
+                    dhcp,
+                    resolver.as_ref(),
+                )
+                .await?,
+            ),
+            _ => None,
+        };
+
+        #[cfg(feature = "nat")]
+        let associated_tables = match &snat {
+            Some(snat) if !snat.disabled => self.configure_vnet_nat(&vnet, None, snat).await?,
+            _ => Vec::new(),
+        };
+        #[cfg(not(feature = "nat"))]
+        let associated_tables = Vec::new();
+
+        let ns_info = Some(VNetNetns {
+            ns_name: associated_ns.ns_name.clone(),
+            ns_uuid: associated_ns.uuid,
+        });
+
+        let internals = VirtualNetworkInternals {
+            associated_netns: ns_info,
+            dhcp: dhcp_internal,
+            associated_tables,
+            vxlan_options: self.config.vxlan_options.clone(),
+            static_routes: requested
+                .as_ref()
+                .map(|i| i.static_routes.clone())
+                .unwrap_or_default(),
+            ntp_servers: requested
+                .as_ref()
+                .map(|i| i.ntp_servers.clone())
+                .unwrap_or_default(),
+            boot: requested.as_ref().and_then(|i| i.boot.clone()),
+            dhcp_mtu: requested.as_ref().and_then(|i| i.dhcp_mtu),
+            domain_name: requested.as_ref().and_then(|i| i.domain_name.clone()),
+            dhcp_options: requested
+                .as_ref()
+                .map(|i| i.dhcp_options.clone())
+                .unwrap_or_default(),
+            dns_upstream: requested
+                .as_ref()
+                .map(|i| i.dns_upstream.clone())
+                .unwrap_or_default(),
+            resolver,
+            nat64_gateway,
+            snat,
+            dhcp_reservations: requested
+                .as_ref()
+                .map(|i| i.dhcp_reservations.clone())
+                .unwrap_or_default(),
+            dhcp_relay: requested.as_ref().and_then(|i| i.dhcp_relay),
+            ipam: requested.as_ref().and_then(|i| i.ipam.clone()),
+            tenant: requested.as_ref().and_then(|i| i.tenant.clone()),
+            dns_records: requested.map(|i| i.dns_records).unwrap_or_default(),
+            provider_vlan,
+            ..Default::default()
+        };
+        vnet.plugin_internals = Some(serialize_network_internals(&internals)?);
+        Ok(vnet)
+    }
+
+    /// Creates an ELINE virtual network, the VXLAN tunnel is created
+    /// over `dev` using `local_addr` as source address.
+    async fn ptp_vxlan_create(
+        &self,
+        mut vnet: VirtualNetwork,
+        vxlan_info: P2PVXLANInfo,
+        dev: String,
+        local_addr: IPAddress,
+    ) -> FResult<VirtualNetwork> {
+        let node_uuid = self.agent.as_ref().unwrap().get_node_uuid().await??;
+        let requested = vnet
+            .plugin_internals
+            .as_ref()
+            .and_then(|raw| deserialize_network_internals(raw).ok());
+        let snat = requested.as_ref().and_then(|i| i.snat.clone());
+        if let Some(snat) = &snat {
+            validate_snat(snat)?;
+        }
+        self.check_nat64_supported(requested.as_ref())?;
+
+        self.operation_step(vnet.uuid, CreateStep::DEVICES).await;
+
+        // Generating Names
+
+        let br_uuid = Uuid::new_v4();
+        let br_name = self.generate_random_interface_name();
+
+        let vxl_uuid = Uuid::new_v4();
+        let vxl_name = self.generate_random_interface_name();
+
+        let internal_br_uuid = Uuid::new_v4();
+        let internal_br_name = self.generate_random_interface_name();
+
+        let internal_veth_uuid = Uuid::new_v4();
+        let internal_veth_name = self.generate_random_interface_name();
+
+        let external_veth_uuid = Uuid::new_v4();
+        let external_veth_name = self.generate_random_interface_name();
+
+        let mut associated_ns = NetworkNamespace {
+            uuid: vnet.uuid,
+            ns_name: self.generate_random_netns_name(),
+            interfaces: vec![
+                external_veth_uuid,
+                internal_veth_uuid,
+                internal_br_uuid,
+                vxl_uuid,
+                br_uuid,
+            ],
+        };
+
+        // Generating Structs
+
+        let mut v_bridge = VirtualInterface {
+            uuid: br_uuid,
+            if_name: br_name.clone(),
+            net_ns: None,
+            parent: None,
+            kind: VirtualInterfaceKind::BRIDGE(BridgeKind {
+                childs: vec![external_veth_uuid, vxl_uuid],
+            }),
+            addresses: Vec::new(),
+            phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
+        };
+
+        let mut v_internal_bridge = VirtualInterface {
+            uuid: internal_br_uuid,
+            if_name: internal_br_name.clone(),
+            net_ns: Some(associated_ns.uuid),
+            parent: None,
+            kind: VirtualInterfaceKind::BRIDGE(BridgeKind {
+                childs: vec![internal_veth_uuid],
+            }),
+            addresses: Vec::new(),
+            phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
+        };
+
+        let vxl_iface = VirtualInterface {
+            uuid: vxl_uuid,
+            if_name: vxl_name.clone(),
+            net_ns: None,
+            parent: Some(br_uuid),
+            kind: VirtualInterfaceKind::VXLAN(VXLANKind {
+                vni: vxlan_info.vni,
+                port: vxlan_info.port,
+                mcast_addr: vxlan_info.remote_addr,
+                dev: Interface {
+                    if_name: dev.clone(),
+                    kind: InterfaceKind::ETHERNET,
+                    addresses: Vec::new(),
+                    phy_address: None,
+                },
+            }),
+            addresses: Vec::new(),
+            phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
+        };
+
+        let v_veth_i = VirtualInterface {
+            uuid: internal_veth_uuid,
+            if_name: internal_veth_name.clone(),
+            net_ns: Some(associated_ns.uuid),
+            parent: Some(internal_br_uuid),
+            kind: VirtualInterfaceKind::VETH(VETHKind {
+                pair: external_veth_uuid,
+                internal: true,
+            }),
+            addresses: Vec::new(),
+            phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
+        };
+
+        let v_veth_e = VirtualInterface {
+            uuid: external_veth_uuid,
+            if_name: external_veth_name.clone(),
+            net_ns: None,
+            parent: Some(br_uuid),
+            kind: VirtualInterfaceKind::VETH(VETHKind {
+                pair: internal_veth_uuid,
+                internal: false,
+            }),
+            addresses: Vec::new(),
+            phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
+        };
+
+        // Creating Virtual network bridge
+
+        self.kernel().create_bridge(br_name.clone()).await?;
+        self.pin_bridge_mac(&mut v_bridge).await?;
+        self.store_interface(&v_bridge).await?;
+
+        vnet.interfaces.push(br_uuid);
+
+        self.kernel().set_iface_up(br_name.clone()).await?;
+
+        // Creating VXLAN Interface
+
+        let dev_name = dev.clone();
+        self.kernel()
+            .create_ptp_vxlan(
+                vxl_name.clone(),
+                dev,
+                vxlan_info.vni,
+                local_addr,
+                vxlan_info.remote_addr,
+                vxlan_info.port,
+                &self.get_vxlan_options(None),
+            )
+            .await?;
+        self.store_interface(&vxl_iface).await?;
+
+        vnet.interfaces.push(vxl_uuid);
+
+        self.kernel()
+            .set_iface_master(vxl_name.clone(), br_name.clone())
+            .await?;
+        self.kernel().set_iface_up(vxl_name).await?;
+
+        // Creating netns and spawing the namespace manager
+        self.operation_step(vnet.uuid, CreateStep::NAMESPACE).await;
+        self.kernel()
+            .add_netns(associated_ns.ns_name.clone())
+            .await?;
+        self.spawn_ns_manager(associated_ns.ns_name.clone(), associated_ns.uuid)
+            .await?;
+
+        self.store_network_namespace(&associated_ns).await?;
+
+        // Creating veth pair
+        self.kernel()
+            .create_veth(external_veth_name.clone(), internal_veth_name.clone())
+            .await?;
+
+        self.store_interface(&v_veth_e).await?;
+
+        vnet.interfaces.push(internal_veth_uuid);
+
+        self.store_interface(&v_veth_i).await?;
+
+        vnet.interfaces.push(external_veth_uuid);
+
+        self.kernel()
+            .set_iface_master(external_veth_name.clone(), br_name.clone())
+            .await?;
+        self.kernel().set_iface_up(external_veth_name).await?;
+
+        self.kernel()
+            .set_iface_ns(
+                internal_veth_name.clone(),
+                associated_ns.ns_name.clone().clone(),
+            )
+            .await?;
+
+        // create internal bridge
+        let ns_manager = self.get_ns_manager(&associated_ns.uuid).await?;
+
+        // This is used to wait that the namespace manager is ready to serve
+        while !ns_manager.verify_server().await? {}
+
+        ns_manager
+            .set_virtual_interface_up("lo".to_string())
+            .await??;
+
+        ns_manager
+            .add_virtual_interface_bridge(internal_br_name.clone())
+            .await??;
+
+        ns_manager
+            .set_virtual_interface_up(internal_br_name.clone())
+            .await??;
+
+        vnet.interfaces.push(internal_br_uuid);
+
+        self.pin_bridge_mac(&mut v_internal_bridge).await?;
+        self.store_interface(&v_internal_bridge).await?;
+
+        ns_manager
+            .set_virtual_interface_master(internal_veth_name.clone(), internal_br_name.clone())
+            .await??;
+
+        ns_manager
+            .set_virtual_interface_up(internal_veth_name.clone())
+            .await??;
+
+        let mtu = self.get_vxlan_mtu(dev_name, local_addr).await?;
+        // the devices of the network carry the VXLAN encapsulation overhead
+        self.set_interfaces_mtu(
+            mtu,
+            &[
+                &v_bridge,
+                &vxl_iface,
+                &v_veth_e,
+                &v_veth_i,
+                &v_internal_bridge,
+            ],
+        )
+        .await?;
+
+        // NAT configuration, skip it for the time being...
+        // let nat_table = self
+        //     .configure_nat(
+        //         IpNetwork::V4(
+        //             ipnetwork::Ipv4Network::new(
+        //                 std::net::Ipv4Addr::new(10, 240, 0, 0),
+        //                 16,
+        //             )
+        //             .map_err(|e| FError::NetworkingError(format!("{}", e)))?,
+        //         ),
+        //         &self.get_overlay_face_from_config().await?.if_name,
+        //     )
+        //     .await?;
+
+        // DHCP configuration and spawn
+        self.operation_step(vnet.uuid, CreateStep::DHCP).await;
+
+        let dhcp_internal = self
+            .start_vnet_dhcp(&vnet, requested.as_ref(), &associated_ns, &internal_br_name)
+            .await?;
+        let resolver = match &dhcp_internal {
+            Some(dhcp) if self.is_dns_cache_enabled(requested.as_ref()) => Some(
+                self.start_vnet_resolver(
+                    &vnet,
+                    &associated_ns,
+                    &internal_br_name,
+                    dhcp,
+                    requested
+                        .as_ref()
+                        .map(|i| i.dns_records.as_slice())
+                        .unwrap_or_default(),
+                )
+                .await?,
+            ),
+            _ => None,
+        };
+        let nat64_gateway = match (&dhcp_internal, &requested) {
+            (Some(dhcp), Some(requested)) if self.is_nat64_enabled(Some(requested)) => Some(
+                self.start_vnet_nat64(
+                    &vnet,
+                    requested,
+                    &associated_ns,
+                    &internal_br_name,
+                    dhcp,
+                    resolver.as_ref(),
+                )
+                .await?,
+            ),
+            _ => None,
+        };
+
+        #[cfg(feature = "nat")]
+        let associated_tables = match &snat {
+            Some(snat) if !snat.disabled => self.configure_vnet_nat(&vnet, None, snat).await?,
+            _ => Vec::new(),
+        };
+        #[cfg(not(feature = "nat"))]
+        let associated_tables = Vec::new();
+
+        let ns_info = Some(VNetNetns {
+            ns_name: associated_ns.ns_name.clone(),
+            ns_uuid: associated_ns.uuid,
+        });
+
+        let internals = VirtualNetworkInternals {
+            associated_netns: ns_info,
+            dhcp: dhcp_internal,
+            associated_tables,
+            vxlan_options: self.config.vxlan_options.clone(),
+            static_routes: requested
+                .as_ref()
+                .map(|i| i.static_routes.clone())
+                .unwrap_or_default(),
+            ntp_servers: requested
+                .as_ref()
+                .map(|i| i.ntp_servers.clone())
+                .unwrap_or_default(),
+            boot: requested.as_ref().and_then(|i| i.boot.clone()),
+            dhcp_mtu: requested.as_ref().and_then(|i| i.dhcp_mtu),
+            domain_name: requested.as_ref().and_then(|i| i.domain_name.clone()),
+            dhcp_options: requested
+                .as_ref()
+                .map(|i| i.dhcp_options.clone())
+                .unwrap_or_default(),
+            dns_upstream: requested
+                .as_ref()
+                .map(|i| i.dns_upstream.clone())
+                .unwrap_or_default(),
+            resolver,
+            nat64_gateway,
+            snat,
+            dhcp_reservations: requested
+                .as_ref()
+                .map(|i| i.dhcp_reservations.clone())
+                .unwrap_or_default(),
+            dhcp_relay: requested.as_ref().and_then(|i| i.dhcp_relay),
+            ipam: requested.as_ref().and_then(|i| i.ipam.clone()),
+            tenant: requested.as_ref().and_then(|i| i.tenant.clone()),
+            dns_records: requested.map(|i| i.dns_records).unwrap_or_default(),
+            ..Default::default()
+        };
+        vnet.plugin_internals = Some(serialize_network_internals(&internals)?);
+        Ok(vnet)
+    }
+
+    async fn wireguard_vxlan_create(
+        &self,
+        vnet: VirtualNetwork,
+        vxlan_info: P2PVXLANInfo,
+        wg_info: WireGuardInfo,
+    ) -> FResult<VirtualNetwork> {
+        let wg_name = self.generate_random_interface_name();
+        let (private_key, public_key) = self.generate_wireguard_keys()?;
+        let key_file_path = self
+            .get_run_path()
+            .join(format!("{}.key", wg_name))
+            .to_str()
+            .ok_or(FError::EncodingError)?
+            .to_string();
+        self.store_private_file(&key_file_path, private_key.as_bytes())?;
+
+        if let Err(e) = self.kernel().create_wireguard(wg_name.clone()).await {
+            let _ = std::fs::remove_file(&key_file_path);
+            return Err(e);
+        }
+        let res = async {
+            self.configure_wireguard(&wg_name, &private_key, &wg_info)?;
+            self.kernel()
+                .add_iface_address(
+                    wg_name.clone(),
+                    wg_info.local_tunnel_addr.ip(),
+                    wg_info.local_tunnel_addr.prefix(),
+                )
+                .await?;
+            self.kernel().set_iface_up(wg_name.clone()).await?;
+            self.ptp_vxlan_create(
+                vnet,
+                vxlan_info,
+                wg_name.clone(),
+                wg_info.local_tunnel_addr.ip(),
+            )
+            .await
+        }
+        .await;
+        let mut vnet = match res {
+            Ok(vnet) => vnet,
+            Err(e) => {
+                log::error!("Unable to create WireGuard underlay {}: {}", wg_name, e);
+                if let Err(e) = self.kernel().del_iface(wg_name.clone()).await {
+                    log::warn!("Unable to remove {}: {}", wg_name, e);
+                }
+                let _ = std::fs::remove_file(&key_file_path);
+                return Err(e);
+            }
+        };
+
+        let mut internals =
+            deserialize_network_internals(vnet.plugin_internals.as_ref().ok_or(FError::NotFound)?)?;
+        internals.wireguard = Some(VNetWireGuard {
+            if_name: wg_name,
+            public_key,
+            private_key_file: key_file_path,
+            listen_port: wg_info.listen_port,
+        });
+        vnet.plugin_internals = Some(serialize_network_internals(&internals)?);
+        Ok(vnet)
+    }
+
+    async fn resolve_remote_endpoint(&self, remote: &RemoteEndpoint) -> FResult<IPAddress> {
+        use async_std::net::ToSocketAddrs;
+        match remote {
+            RemoteEndpoint::Address(addr) => Ok(*addr),
+            RemoteEndpoint::Hostname(host) => {
+                let mut addrs = (host.as_str(), 0)
+                    .to_socket_addrs()
+                    .await
+                    .map_err(|e| FError::NetworkingError(format!("{}", e)))?;
+                addrs.next().map(|a| a.ip()).ok_or(FError::NotFound)
+            }
+        }
+    }
+
+    /// Stores the remote endpoint in the virtual network internals and keeps
+    /// track of the networks whose endpoint has to be periodically resolved.
+    async fn set_remote_endpoint(
+        &self,
+        vnet: &mut VirtualNetwork,
+        remote: RemoteEndpoint,
+    ) -> FResult<()> {
+        let mut internals =
+            deserialize_network_internals(vnet.plugin_internals.as_ref().ok_or(FError::NotFound)?)?;
+        let mut guard = self.state.write().await;
+        match remote {
+            RemoteEndpoint::Hostname(ref host) => {
+                guard.resolved_endpoints.insert(vnet.uuid, host.clone());
+            }
+            RemoteEndpoint::Address(_) => {
+                guard.resolved_endpoints.remove(&vnet.uuid);
+            }
+        }
+        drop(guard);
+        internals.remote_endpoint = Some(remote);
+        vnet.plugin_internals = Some(serialize_network_internals(&internals)?);
+        Ok(())
+    }
+
+    /// Replaces the default destination of the VXLAN interfaces
+    /// of an ELINE virtual network.
+    async fn set_ptp_vxlan_remote(
+        &self,
+        vnet: &mut VirtualNetwork,
+        remote_addr: IPAddress,
+    ) -> FResult<()> {
+        for iface_uuid in &vnet.interfaces {
+            let mut iface = self.connector.local.get_interface(*iface_uuid).await?;
+            if let VirtualInterfaceKind::VXLAN(ref mut info) = iface.kind {
+                let mut cmd = Command::new("bridge");
+                cmd.arg("fdb")
+                    .arg("replace")
+                    .arg("00:00:00:00:00:00")
+                    .arg("dev")
+                    .arg(&iface.if_name)
+                    .arg("dst")
+                    .arg(format!("{}", remote_addr));
+                exec_command(cmd)?;
+                info.mcast_addr = remote_addr;
+                self.store_interface(&iface).await?;
+            }
+        }
+        if let LinkKind::ELINE(ref mut info) = vnet.link_kind {
+            info.remote_addr = remote_addr;
+        }
+        Ok(())
+    }
+
+    /// Resolves again the hostnames of ELINE remote endpoints and
+    /// re-points the tunnels whose address has changed.
+    async fn refresh_remote_endpoints(&self) {
+        let guard = self.state.read().await;
+        let endpoints = guard.resolved_endpoints.clone();
+        drop(guard);
+        for (vnet_uuid, host) in endpoints {
+            if let Err(e) = self.refresh_remote_endpoint(vnet_uuid, &host).await {
+                log::warn!(
+                    target: logging::RECONCILE,
+                    "Unable to refresh the remote endpoint {} of {}: {}",
+                    host,
+                    vnet_uuid,
+                    e
+                );
+            }
+        }
+    }
+
+    async fn refresh_remote_endpoint(&self, vnet_uuid: Uuid, host: &str) -> FResult<()> {
+        let mut vnet = self.connector.local.get_virtual_network(vnet_uuid).await?;
+        let current_addr = match vnet.link_kind {
+            LinkKind::ELINE(ref info) => info.remote_addr,
+            _ => return Ok(()),
+        };
+        let remote_addr = self
+            .resolve_remote_endpoint(&RemoteEndpoint::Hostname(host.to_string()))
+            .await?;
+        if remote_addr != current_addr {
+            log::info!(
+                target: logging::RECONCILE,
+                "Remote endpoint {} of {} moved from {} to {}",
+                host,
+                vnet_uuid,
+                current_addr,
+                remote_addr
+            );
+            self.set_ptp_vxlan_remote(&mut vnet, remote_addr).await?;
+            self.store_virtual_network(&vnet).await?;
+        }
+        Ok(())
+    }
+
+    /// Creates the VXLAN interface carrying a virtual network
+    /// and attaches it to the bridge of the network
+    async fn create_transport_vxlan(
+        &self,
+        if_name: &str,
+        info: &VXLANKind,
+        eline: bool,
+        unicast: bool,
+        internals: &VirtualNetworkInternals,
+        br_name: &str,
+    ) -> FResult<()> {
+        let dev = info.dev.if_name.clone();
+        let options = self.get_vxlan_options(Some(internals));
+        if eline || unicast {
+            let local_addr = *self
+                .kernel()
+                .get_iface_addresses(dev.clone())
+                .await?
+                .first()
+                .ok_or(FError::NotFound)?;
+            if eline {
+                self.kernel()
+                    .create_ptp_vxlan(
+                        if_name.to_string(),
+                        dev,
+                        info.vni,
+                        local_addr,
+                        info.mcast_addr,
+                        info.port,
+                        &options,
+                    )
+                    .await?;
+            } else {
+                self.kernel()
+                    .create_unicast_vxlan(
+                        if_name.to_string(),
+                        dev,
+                        info.vni,
+                        local_addr,
+                        info.port,
+                        &options,
+                    )
+                    .await?;
+            }
+        } else {
+            self.kernel()
+                .create_mcast_vxlan(
+                    if_name.to_string(),
+                    dev,
+                    info.vni,
+                    info.mcast_addr,
+                    info.port,
+                    &options,
+                )
+                .await?;
+        }
+        self.kernel()
+            .set_iface_master(if_name.to_string(), br_name.to_string())
+            .await?;
+        self.kernel().set_iface_up(if_name.to_string()).await
+    }
+
+    /// Returns the name of the VXLAN interface of a virtual network
+    async fn get_vxlan_iface_name(&self, vnet: &VirtualNetwork) -> FResult<String> {
+        for iface_uuid in &vnet.interfaces {
+            let iface = self.connector.local.get_interface(*iface_uuid).await?;
+            if let VirtualInterfaceKind::VXLAN(_) = iface.kind {
+                return Ok(iface.if_name);
+            }
+        }
+        Err(FError::NotFound)
+    }
+
+    /// Looks for the node holding the other end of an ELINE virtual
+    /// network, when found the local address is announced to it and the
+    /// tunnel is pointed to the address it answers with. When this node
+    /// is the first to instantiate the network the tunnel is left as in
+    /// the descriptor, and it is updated when the other end announces.
+    async fn negotiate_eline_endpoint(
+        &self,
+        vnet: &mut VirtualNetwork,
+        local_addr: IPAddress,
+    ) -> FResult<()> {
+        let my_uuid = self.state.read().await.uuid;
+        let node_uuid = self.agent.as_ref().unwrap().get_node_uuid().await??;
+        self.claim_eline_end(vnet.uuid, node_uuid, local_addr)
+            .await?;
+        let servers = LinuxNetworkExtensionClient::find_servers(self.z.clone()).await?;
+        for server_uuid in servers {
+            if Some(server_uuid) == my_uuid {
+                continue;
+            }
+            let client = LinuxNetworkExtensionClient::new(self.z.clone(), server_uuid);
+            match client
+                .announce_eline_endpoint(vnet.uuid, node_uuid, local_addr)
+                .await
+            {
+                Ok(Ok(remote_addr)) => {
+                    let current_addr = match vnet.link_kind {
+                        LinkKind::ELINE(ref info) => info.remote_addr,
+                        _ => return Err(FError::WrongKind),
+                    };
+                    if current_addr != remote_addr {
+                        log::info!(
+                            "Remote end of {} negotiated at {} (was {})",
+                            vnet.uuid,
+                            remote_addr,
+                            current_addr
+                        );
+                        self.set_ptp_vxlan_remote(vnet, remote_addr).await?;
+                    }
+                    return Ok(());
+                }
+                Ok(Err(_)) => continue,
+                Err(e) => log::warn!("Unable to contact {}: {}", server_uuid, e),
+            }
+        }
+        log::debug!("No remote end found yet for {}", vnet.uuid);
+        Ok(())
+    }
+
+    /// Records this node as the owner of an end of an ELINE virtual
+    /// network in its global record, a network has two ends
+    async fn claim_eline_end(
+        &self,
+        vnet_uuid: Uuid,
+        node_uuid: Uuid,
+        endpoint: IPAddress,
+    ) -> FResult<()> {
+        self.update_global_internals(vnet_uuid, |_, internals| {
+            let end = VNetMember {
+                node_uuid,
+                endpoint,
+            };
+            if internals.eline_ends.contains(&end) {
+                return Ok(false);
+            }
+            internals.eline_ends.retain(|e| e.node_uuid != node_uuid);
+            if internals.eline_ends.len() >= 2 {
+                return Err(FError::NetworkingError(format!(
+                    "Both ends of {} are already owned",
+                    vnet_uuid
+                )));
+            }
+            internals.eline_ends.push(end);
+            Ok(true)
+        })
+        .await
+        .map(|_| ())
+    }
+
+    async fn release_eline_end(&self, vnet_uuid: Uuid) -> FResult<()> {
+        let node_uuid = self.agent.as_ref().unwrap().get_node_uuid().await??;
+        self.update_global_internals(vnet_uuid, |_, internals| {
+            let before = internals.eline_ends.len();
+            internals.eline_ends.retain(|e| e.node_uuid != node_uuid);
+            Ok(internals.eline_ends.len() != before)
+        })
+        .await
+        .map(|_| ())
+    }
+
+    /// VXLAN endpoints of the other nodes of an ELAN virtual network,
+    /// from the members in its global record
+    async fn discover_vxlan_peers(&self, vnet_uuid: Uuid) -> FResult<Vec<IPAddress>> {
+        let node_uuid = self.agent.as_ref().unwrap().get_node_uuid().await??;
+        let vnet = self.connector.global.get_virtual_network(vnet_uuid).await?;
+        Ok(global_network_internals(&vnet)?
+            .elan_members
+            .into_iter()
+            .filter(|m| m.node_uuid != node_uuid)
+            .map(|m| m.endpoint)
+            .collect())
+    }
+
+    /// Adds this node and its VXLAN endpoint to the members of an ELAN
+    /// virtual network in its global record
+    async fn join_elan(&self, vnet_uuid: Uuid) -> FResult<()> {
+        let node_uuid = self.agent.as_ref().unwrap().get_node_uuid().await??;
+        let endpoint = self.get_vxlan_endpoint(vnet_uuid).await?;
+        self.update_global_internals(vnet_uuid, |_, internals| {
+            if internals
+                .elan_members
+                .iter()
+                .any(|m| m.node_uuid == node_uuid && m.endpoint == endpoint)
+            {
+                return Ok(false);
+            }
+            internals.elan_members.retain(|m| m.node_uuid != node_uuid);
+            internals.elan_members.push(VNetMember {
+                node_uuid,
+                endpoint,
+            });
+            Ok(true)
+        })
+        .await
+        .map(|_| ())
+    }
+
+    async fn leave_elan(&self, vnet_uuid: Uuid) -> FResult<()> {
+        let node_uuid = self.agent.as_ref().unwrap().get_node_uuid().await??;
+        self.update_global_internals(vnet_uuid, |_, internals| {
+            let before = internals.elan_members.len();
+            internals.elan_members.retain(|m| m.node_uuid != node_uuid);
+            Ok(internals.elan_members.len() != before)
+        })
+        .await
+        .map(|_| ())
+    }
+
+    /// Subnets of the virtual networks of all the nodes
+    async fn collect_ipam_subnets(&self) -> FResult<Vec<(IPAddress, u8)>> {
+        let my_uuid = self.state.read().await.uuid;
+        let mut subnets = self.list_ipam_subnets().await?;
+        let servers = LinuxNetworkExtensionClient::find_servers(self.z.clone()).await?;
+        for server_uuid in servers {
+            if Some(server_uuid) == my_uuid {
+                continue;
+            }
+            let client = LinuxNetworkExtensionClient::new(self.z.clone(), server_uuid);
+            match client.list_ipam_subnets().await {
+                Ok(Ok(mut remote)) => subnets.append(&mut remote),
+                Ok(Err(_)) => continue,
+                Err(e) => log::warn!("Unable to contact {}: {}", server_uuid, e),
+            }
+        }
+        Ok(subnets)
+    }
+
+    /// Applies `update` to the internals of the global record of a
+    /// virtual network and writes the record back when `update` returns
+    /// true. The write is a compare and swap on the version of the
+    /// record done by the coordinator, when another node wrote it in the
+    /// meantime the update is done again on its version.
+    async fn update_global_internals<F>(&self, vnet_uuid: Uuid, mut update: F) -> FResult<bool>
+    where
+        F: FnMut(&VirtualNetwork, &mut VirtualNetworkInternals) -> FResult<bool>,
+    {
+        for _ in 0..IPAM_UPDATE_RETRIES {
+            let vnet = self.connector.global.get_virtual_network(vnet_uuid).await?;
+            let mut internals = global_network_internals(&vnet)?;
+            let expected = internals.global_version;
+            if !update(&vnet, &mut internals)? {
+                return Ok(false);
+            }
+            let swapped = match self.global_coordinator().await? {
+                Some(client) => {
+                    let node_uuid = self.agent.as_ref().unwrap().get_node_uuid().await??;
+                    let swap = GlobalInternalsSwap {
+                        vnet_uuid,
+                        expected,
+                        internals,
+                    };
+                    let sealed = seal_global_swap(&self.global_cipher()?, node_uuid, &swap)?;
+                    client.swap_global_internals(sealed).await??
+                }
+                None => {
+                    self.compare_and_swap_global_internals(vnet_uuid, expected, internals)
+                        .await?
+                }
+            };
+            if swapped {
+                return Ok(true);
+            }
+            log::debug!("Global record of {} changed, retrying", vnet_uuid);
+        }
+        Err(FError::NetworkingError(format!(
+            "Unable to update the global record of {}",
+            vnet_uuid
+        )))
+    }
+
+    /// Writes `internals` in the global record of a virtual network if
+    /// the record is still at version `expected`, returns false
+    /// otherwise. Only the coordinator writes and it does one write at a
+    /// time, so that the comparison and the write cannot interleave.
+    async fn compare_and_swap_global_internals(
+        &self,
+        vnet_uuid: Uuid,
+        expected: u64,
+        mut internals: VirtualNetworkInternals,
+    ) -> FResult<bool> {
+        let _guard = self.global_lock.lock().await;
+        if self.global_coordinator().await?.is_some() {
+            return Err(FError::NetworkingError(
+                "This plugin is not the coordinator".to_string(),
+            ));
+        }
+        let mut vnet = self.connector.global.get_virtual_network(vnet_uuid).await?;
+        if global_network_internals(&vnet)?.global_version != expected {
+            return Ok(false);
+        }
+        internals.global_version = expected + 1;
+        vnet.plugin_internals = Some(serialize_network_internals(&internals)?);
+        self.connector.global.add_virutal_network(&vnet).await?;
+        Ok(true)
+    }
+
+    /// Client of the plugin writing the global records, the running one
+    /// with the lowest UUID, `None` when it is this plugin. Plugins that
+    /// do not see the same set of servers can pick different
+    /// coordinators, the coordinator refuses the writes then.
+    async fn global_coordinator(&self) -> FResult<Option<LinuxNetworkExtensionClient>> {
+        let my_uuid = self.state.read().await.uuid;
+        let servers = LinuxNetworkExtensionClient::find_servers(self.z.clone()).await?;
+        match servers.into_iter().chain(my_uuid).min() {
+            Some(uuid) if Some(uuid) != my_uuid => {
+                Ok(Some(LinuxNetworkExtensionClient::new(self.z.clone(), uuid)))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Publishes the subnet allocated to a virtual network under
+    /// `/fos/global/networking/ipam/<vnet uuid>`
+    async fn publish_pool_subnet(&self, vnet_uuid: Uuid, subnet: &IPAMSubnet) -> FResult<()> {
+        let path = format!("{}/{}", IPAM_SUBNETS_PATH, vnet_uuid);
+        let data =
+            serde_json::to_vec(subnet).map_err(|e| FError::NetworkingError(format!("{}", e)))?;
+        self.z
+            .write(&zenoh::net::ResKey::RName(path), data.into())
+            .await?;
+        Ok(())
+    }
+
+    /// Subnets allocated from the pools, the ones of the networks whose
+    /// global record is gone are left out
+    async fn list_pool_subnets(&self) -> FResult<Vec<IPAMSubnet>> {
+        let selector = zenoh::net::ResKey::RName(format!("{}/*", IPAM_SUBNETS_PATH));
+        let mut replies = self
+            .z
+            .query(
+                &selector,
+                "",
+                zenoh::net::QueryTarget::default(),
+                zenoh::net::QueryConsolidation::default(),
+            )
+            .await?;
+        let mut subnets = Vec::new();
+        while let Some(reply) = replies.next().await {
+            let vnet_uuid = match reply
+                .data
+                .res_name
+                .rsplit('/')
+                .next()
+                .and_then(|p| Uuid::parse_str(p).ok())
+            {
+                Some(vnet_uuid) => vnet_uuid,
+                None => continue,
+            };
+            match serde_json::from_slice::<IPAMSubnet>(&reply.data.payload.to_vec()) {
+                Ok(subnet) => {
+                    if self
+                        .connector
+                        .global
+                        .get_virtual_network(vnet_uuid)
+                        .await
+                        .is_ok()
+                    {
+                        subnets.push(subnet);
+                    }
+                }
+                Err(e) => log::warn!(
+                    "Unable to decode the subnet at {}: {}",
+                    reply.data.res_name,
+                    e
+                ),
+            }
+        }
+        Ok(subnets)
+    }
+
+    /// Applies `update` to the IPAM allocations in the global record of
+    /// a virtual network, see `update_global_internals`
+    async fn update_global_ipam<T, F>(&self, vnet_uuid: Uuid, mut update: F) -> FResult<T>
+    where
+        F: FnMut(&mut VNetIPAM, (IPAddress, u8), Option<IPAddress>) -> FResult<T>,
+    {
+        let mut res = None;
+        self.update_global_internals(vnet_uuid, |vnet, internals| {
+            let (subnet, gateway) = match (internals.ipam.as_ref(), &vnet.ip_configuration) {
+                (Some(_), Some(conf)) => (conf.subnet.ok_or(FError::NotFound)?, conf.gateway),
+                _ => {
+                    return Err(FError::NetworkingError(format!(
+                        "The addresses of {} are not managed by IPAM",
+                        vnet_uuid
+                    )))
+                }
+            };
+            let allocations = internals.ipam.as_mut().ok_or(FError::NotFound)?;
+            res = Some(update(allocations, subnet, gateway)?);
+            Ok(true)
+        })
+        .await?;
+        res.ok_or(FError::NotFound)
+    }
+
+    /// Slice of the subnet of a virtual network served by the DHCP
+    /// server of this node, reserved the first time
+    async fn reserve_ipam_range(&self, vnet_uuid: Uuid) -> FResult<(IPAddress, IPAddress)> {
+        let node_uuid = self.agent.as_ref().unwrap().get_node_uuid().await??;
+        self.update_global_ipam(vnet_uuid, |allocations, subnet, gateway| {
+            if let Some(range) = allocations
+                .node_ranges
+                .iter()
+                .find(|r| r.node_uuid == node_uuid)
+            {
+                return Ok((range.start, range.end));
+            }
+            let (start, end) = ipam::allocate_range(subnet, gateway, allocations)?;
+            allocations.node_ranges.push(IPAMNodeRange {
+                node_uuid,
+                start,
+                end,
+            });
+            Ok((start, end))
+        })
+        .await
+    }
+
+    async fn release_ipam_range(&self, vnet_uuid: Uuid) -> FResult<()> {
+        let node_uuid = self.agent.as_ref().unwrap().get_node_uuid().await??;
+        self.update_global_ipam(vnet_uuid, |allocations, _, _| {
+            allocations.node_ranges.retain(|r| r.node_uuid != node_uuid);
+            Ok(())
+        })
+        .await
+    }
+
+    /// Publishes the local names of a virtual network under
+    /// `/fos/global/networking/dns/<vnet uuid>/<plugin uuid>`, the other
+    /// nodes serve them from their resolvers, see `watch_dns_records`
+    async fn publish_dns_records(&self, vnet_uuid: Uuid, records: &[DNSRecord]) {
+        let plugin_uuid = match self.state.read().await.uuid {
+            Some(uuid) => uuid,
+            None => return,
+        };
+        let path = format!("{}/{}/{}", DNS_RECORDS_PATH, vnet_uuid, plugin_uuid);
+        let data = match serde_json::to_vec(records) {
+            Ok(data) => data,
+            Err(e) => {
+                log::warn!("Unable to encode the DNS records of {}: {}", vnet_uuid, e);
+                return;
+            }
+        };
+        if let Err(e) = self
+            .z
+            .write(&zenoh::net::ResKey::RName(path), data.into())
+            .await
+        {
+            log::warn!("Unable to publish the DNS records of {}: {}", vnet_uuid, e);
+        }
+    }
+
+    /// Keeps the names published by the other nodes, the ones already
+    /// published are queried first. The resolver of a network is
+    /// reloaded when the names of another node change.
+    async fn watch_dns_records(&self) -> FResult<()> {
+        let selector = zenoh::net::ResKey::RName(format!("{}/**", DNS_RECORDS_PATH));
+        let mut subscriber = self
+            .z
+            .declare_subscriber(&selector, &zenoh::net::SubInfo::default())
+            .await?;
+        let mut replies = self
+            .z
+            .query(
+                &selector,
+                "",
+                zenoh::net::QueryTarget::default(),
+                zenoh::net::QueryConsolidation::default(),
+            )
+            .await?;
+        while let Some(reply) = replies.next().await {
+            self.on_dns_records(&reply.data.res_name, reply.data.payload.to_vec())
+                .await;
+        }
+        while let Some(sample) = subscriber.receiver().next().await {
+            self.on_dns_records(&sample.res_name, sample.payload.to_vec())
+                .await;
+        }
+        Ok(())
+    }
+
+    async fn on_dns_records(&self, path: &str, data: Vec<u8>) {
+        let mut parts = path.rsplit('/');
+        let (plugin_uuid, vnet_uuid) = match (
+            parts.next().and_then(|p| Uuid::parse_str(p).ok()),
+            parts.next().and_then(|p| Uuid::parse_str(p).ok()),
+        ) {
+            (Some(plugin_uuid), Some(vnet_uuid)) => (plugin_uuid, vnet_uuid),
+            _ => return,
+        };
+        let records = match serde_json::from_slice::<Vec<DNSRecord>>(&data) {
+            Ok(records) => records,
+            Err(e) => {
+                log::warn!("Unable to decode the DNS records at {}: {}", path, e);
+                return;
+            }
+        };
+        let mut guard = self.state.write().await;
+        if guard.uuid == Some(plugin_uuid) {
+            return;
+        }
+        let remote = guard.remote_dns_records.entry(vnet_uuid).or_default();
+        if remote.get(&plugin_uuid) == Some(&records) {
+            return;
+        }
+        if records.is_empty() {
+            remote.remove(&plugin_uuid);
+        } else {
+            remote.insert(plugin_uuid, records);
+        }
+        let served = guard.resolvers.contains_key(&vnet_uuid);
+        drop(guard);
+        if !served {
+            return;
+        }
+        let res = async {
+            let vnet = self.connector.local.get_virtual_network(vnet_uuid).await?;
+            let internals = deserialize_network_internals(
+                vnet.plugin_internals.as_ref().ok_or(FError::NotFound)?,
+            )?;
+            self.reload_resolver_records(vnet_uuid, &internals).await
+        }
+        .await;
+        if let Err(e) = res {
+            log::warn!("Unable to update the DNS records of {}: {}", vnet_uuid, e);
+        }
+    }
+
+    /// Synchronizes the forwarding database of an unicast VXLAN
+    /// virtual network with the discovered peers.
+    async fn update_unicast_peers(&self, vnet_uuid: Uuid) -> FResult<()> {
+        let guard = self.state.read().await;
+        let vxl_name = guard
+            .unicast_vnets
+            .get(&vnet_uuid)
+            .cloned()
+            .ok_or(FError::NotFound)?;
+        drop(guard);
+
+        let mut vnet = self.connector.local.get_virtual_network(vnet_uuid).await?;
+        let mut internals =
+            deserialize_network_internals(vnet.plugin_internals.as_ref().ok_or(FError::NotFound)?)?;
+        let current_peers = internals.unicast_peers.clone().unwrap_or_default();
+        let peers = self.discover_vxlan_peers(vnet_uuid).await?;
+        if peers == current_peers {
+            return Ok(());
+        }
+
+        for peer in peers.iter().filter(|p| !current_peers.contains(p)) {
+            log::debug!("Adding VXLAN peer {} to {}", peer, vxl_name);
+            let mut cmd = Command::new("bridge");
+            cmd.arg("fdb")
+                .arg("append")
+                .arg("00:00:00:00:00:00")
+                .arg("dev")
+                .arg(&vxl_name)
+                .arg("dst")
+                .arg(format!("{}", peer));
+            exec_command(cmd)?;
+        }
+        for peer in current_peers.iter().filter(|p| !peers.contains(p)) {
+            log::debug!("Removing VXLAN peer {} from {}", peer, vxl_name);
+            let mut cmd = Command::new("bridge");
+            cmd.arg("fdb")
+                .arg("del")
+                .arg("00:00:00:00:00:00")
+                .arg("dev")
+                .arg(&vxl_name)
+                .arg("dst")
+                .arg(format!("{}", peer));
+            exec_command(cmd)?;
+        }
+
+        internals.unicast_peers = Some(peers);
+        vnet.plugin_internals = Some(serialize_network_internals(&internals)?);
+        self.store_virtual_network(&vnet).await
+    }
+
+    async fn get_overlay_face_from_config(&self) -> FResult<Interface> {
+        let iface = self.config.overlay_iface.as_ref().ok_or(FError::NotFound)?;
+        let addresses = self.kernel().get_iface_addresses(iface.clone()).await?;
+        Ok(Interface {
+            if_name: iface.to_string(),
+            kind: InterfaceKind::ETHERNET,
+            addresses,
+            phy_address: None,
+        })
+    }
+
+    async fn get_dataplane_from_config(&self) -> FResult<Interface> {
+        let iface = self
+            .config
+            .dataplane_iface
+            .as_ref()
+            .ok_or(FError::NotFound)?;
+        let addresses = self.kernel().get_iface_addresses(iface.clone()).await?;
+        Ok(Interface {
+            if_name: iface.to_string(),
+            kind: InterfaceKind::ETHERNET,
+            addresses,
+            phy_address: None,
+        })
+    }
+
+    fn get_dpdk_bridge_from_config(&self) -> FResult<String> {
+        self.config
+            .dpdk_bridge
+            .clone()
+            .ok_or_else(|| FError::NetworkingError("DPDK dataplane not configured".to_string()))
+    }
+
+    fn get_domain_socket_locator(&self) -> String {
+        self.config.zfilelocator.clone()
+    }
+
+    fn get_path(&self) -> Box<std::path::Path> {
+        self.config.path.clone()
+    }
+
+    fn get_run_path(&self) -> Box<std::path::Path> {
+        self.config.run_path.clone()
+    }
+
+    fn generate_random_interface_name(&self) -> String {
+        let iface: String = thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(8)
+            .map(char::from)
+            .collect();
+        iface
+    }
+
+    fn generate_random_netns_name(&self) -> String {
+        let ns: String = thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(8)
+            .map(char::from)
+            .collect();
+        format!("ns-{}", ns)
+    }
+
+    #[cfg(feature = "nat")]
+    fn generate_random_nft_table_name(&self) -> String {
+        let tab: String = thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(10)
+            .map(char::from)
+            .collect();
+        format!("{}{}", NFT_TABLE_PREFIX, tab)
+    }
+
+    /// Counts an EBUSY retry of a netlink operation and waits before it
+    async fn netlink_backoff(&self, operation: &str, backoff: u64) {
+        let mut guard = self.netlink_retries.lock().await;
+        let stats = guard.entry(operation.to_string()).or_default();
+        stats.retries += 1;
+        stats.backoff_ms += backoff;
+        drop(guard);
+        runtime::sleep(Duration::from_millis(backoff)).await;
+    }
+
+    /// Counts a netlink operation given up after its retries
+    async fn netlink_timeout(&self, operation: &str) -> FError {
+        log::warn!(
+            target: logging::NETLINK,
+            "Netlink operation {} timed out, netlink is busy",
+            operation
+        );
+        let mut guard = self.netlink_retries.lock().await;
+        guard.entry(operation.to_string()).or_default().timeouts += 1;
+        FError::NetworkingError("Timeout".to_string())
+    }
+
+    /// Generates a WireGuard key pair,
+    /// returns (private key, public key) encoded in base64
+    fn generate_wireguard_keys(&self) -> FResult<(String, String)> {
+        let mut private_key: [u8; 32] = thread_rng().gen();
+        // clamping as done by `wg genkey`
+        private_key[0] &= 248;
+        private_key[31] = (private_key[31] & 127) | 64;
+        let public_key = x25519_dalek::x25519(private_key, x25519_dalek::X25519_BASEPOINT_BYTES);
+        Ok((base64::encode(private_key), base64::encode(public_key)))
+    }
+
+    /// Configures key, listen port and peer of a WireGuard interface
+    /// through the WireGuard generic netlink family
+    fn configure_wireguard(
+        &self,
+        iface: &str,
+        private_key: &str,
+        wg_info: &WireGuardInfo,
+    ) -> FResult<()> {
+        log::trace!("configure_wireguard {} {:?}", iface, wg_info);
+        if simulation::is_enabled() {
+            return Ok(());
+        }
+        let private_key = decode_wireguard_key(private_key)?;
+        let peer_key = decode_wireguard_key(&wg_info.peer_public_key)?;
+        let allowed_ips = if wg_info.allowed_ips.is_empty() {
+            vec![IpNetwork::from(wg_info.peer_tunnel_addr)]
+        } else {
+            wg_info.allowed_ips.clone()
+        };
+        let allowed_addrs = allowed_ips.iter().map(|n| n.ip()).collect::<Vec<_>>();
+        let endpoint = wg_info
+            .peer_endpoint
+            .map(|(addr, port)| std::net::SocketAddr::new(addr, port));
+
+        let mut peer = wireguard_uapi::set::Peer::from_public_key(&peer_key)
+            .flags(vec![wireguard_uapi::set::WgPeerF::ReplaceAllowedIps])
+            .allowed_ips(
+                allowed_ips
+                    .iter()
+                    .zip(allowed_addrs.iter())
+                    .map(|(net, addr)| wireguard_uapi::set::AllowedIp {
+                        ipaddr: addr,
+                        cidr_mask: Some(net.prefix()),
+                    })
+                    .collect(),
+            );
+        if let Some(endpoint) = endpoint.as_ref() {
+            peer = peer.endpoint(endpoint);
+        }
+        if let Some(keepalive) = wg_info.persistent_keepalive {
+            peer = peer.persistent_keepalive_interval(keepalive);
+        }
+        let device = wireguard_uapi::set::Device::from_ifname(iface)
+            .flags(vec![wireguard_uapi::set::WgDeviceF::ReplacePeers])
+            .private_key(&private_key)
+            .listen_port(wg_info.listen_port)
+            .peers(vec![peer]);
+
+        let mut socket = wireguard_uapi::WgSocket::connect()
+            .map_err(|e| FError::NetworkingError(format!("{}", e)))?;
+        socket
+            .set_device(device)
+            .map_err(|e| FError::NetworkingError(format!("{}", e)))
+    }
+
+    /// Writes a file readable only by the owner, used for secrets
+    fn store_private_file(&self, path: &str, content: &[u8]) -> FResult<()> {
+        use std::io::Write;
+        use std::os::unix::fs::OpenOptionsExt;
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(path)?;
+        file.write_all(content)?;
+        Ok(())
+    }
+
+    /// Returns the interfaces written by this plugin whose record still
+    /// exists, entries for records removed elsewhere are dropped.
+    async fn known_interfaces(&self) -> Vec<VirtualInterface> {
+        let candidates: Vec<Uuid> = self
+            .state
+            .read()
+            .await
+            .interface_names
+            .keys()
+            .copied()
+            .collect();
+        let mut known = Vec::new();
+        for intf_uuid in candidates {
+            match self.connector.local.get_interface(intf_uuid).await {
+                Ok(iface) => known.push(iface),
+                Err(_) => {
+                    self.state.write().await.interface_names.remove(&intf_uuid);
+                }
+            }
+        }
+        known
+    }
+
+    /// Recorded interfaces that are links of the kernel, the DPDK ports
+    /// live in the switch
+    async fn known_links(&self) -> Vec<VirtualInterface> {
+        let mut interfaces = self.known_interfaces().await;
+        let guard = self.state.read().await;
+        interfaces.retain(|iface| !guard.dataplane_ports.contains_key(&iface.uuid));
+        interfaces
+    }
+
+    /// Writes an interface record, fails if the stored record
+    /// was changed by another writer since the last write from this plugin.
+    async fn store_interface(&self, iface: &VirtualInterface) -> FResult<()> {
+        let mut versions = self.record_versions.lock().await;
+        self.write_interface_record(&mut versions, iface).await
+    }
+
+    /// Applies `update` to the stored interface record and writes it back,
+    /// no other write of the plugin lands in between. Nothing is written
+    /// when `update` returns `false`.
+    async fn update_interface_record<F>(
+        &self,
+        intf_uuid: Uuid,
+        update: F,
+    ) -> FResult<Option<VirtualInterface>>
+    where
+        F: FnOnce(&mut VirtualInterface) -> bool,
+    {
+        let mut versions = self.record_versions.lock().await;
+        let mut iface = self.connector.local.get_interface(intf_uuid).await?;
+        if !update(&mut iface) {
+            return Ok(None);
+        }
+        self.write_interface_record(&mut versions, &iface).await?;
+        Ok(Some(iface))
+    }
+
+    async fn write_interface_record(
+        &self,
+        versions: &mut HashMap<Uuid, RecordVersion>,
+        iface: &VirtualInterface,
+    ) -> FResult<()> {
+        let current = self.connector.local.get_interface(iface.uuid).await.ok();
+        let version = next_record_version(
+            versions.get(&iface.uuid),
+            iface.uuid,
+            current.as_ref(),
+            iface,
+        )?;
+        let res = self.connector.local.add_interface(iface).await;
+        self.record_operation("store_interface", iface.uuid, &res)
+            .await;
+        res?;
+        self.append_journal(JournalEntry::Interface(iface.clone()))
+            .await;
+        self.touch_record(iface.uuid, Some(version.clone())).await;
+        versions.insert(iface.uuid, version);
+        self.state
+            .write()
+            .await
+            .interface_names
+            .insert(iface.uuid, (iface.if_name.clone(), iface.net_ns));
+        Ok(())
+    }
+
+    /// Writes a virtual network record, fails if the stored record
+    /// was changed by another writer since the last write from this plugin.
+    /// The version is written in the internals of the record, so that
+    /// writes from other instances of the plugin are detected as well.
+    async fn store_virtual_network(&self, vnet: &VirtualNetwork) -> FResult<()> {
+        let mut versions = self.record_versions.lock().await;
+        self.write_network_record(&mut versions, vnet).await
+    }
+
+    /// Applies `update` to the stored virtual network record and writes it
+    /// back, no other write of the plugin lands in between. Nothing is
+    /// written when `update` returns `false`.
+    async fn update_network_record<F>(&self, vnet_uuid: Uuid, update: F) -> FResult<bool>
+    where
+        F: FnOnce(&mut VirtualNetwork) -> FResult<bool>,
+    {
+        let mut versions = self.record_versions.lock().await;
+        let mut vnet = self.connector.local.get_virtual_network(vnet_uuid).await?;
+        if !update(&mut vnet)? {
+            return Ok(false);
+        }
+        self.write_network_record(&mut versions, &vnet).await?;
+        Ok(true)
+    }
+
+    async fn write_network_record(
+        &self,
+        versions: &mut HashMap<Uuid, RecordVersion>,
+        vnet: &VirtualNetwork,
+    ) -> FResult<()> {
+        let (current, stored) = match self.connector.local.get_virtual_network(vnet.uuid).await {
+            Ok(current) => {
+                let (current, stored) = take_record_version(current)?;
+                (Some(current), stored)
+            }
+            Err(_) => (None, None),
+        };
+        if let (Some(last), Some(stored)) = (versions.get(&vnet.uuid), stored.as_ref()) {
+            if last.generation != stored.generation {
+                return Err(FError::NetworkingError(format!(
+                    "Conflict: record {} is at generation {}, last written {}",
+                    vnet.uuid, stored.generation, last.generation
+                )));
+            }
+        }
+        let last = stored.or_else(|| versions.get(&vnet.uuid).cloned());
+        let (mut record, _) = take_record_version(vnet.clone())?;
+        let version = next_record_version(last.as_ref(), vnet.uuid, current.as_ref(), &record)?;
+        if let Some(raw) = record.plugin_internals.as_ref() {
+            let mut internals = deserialize_network_internals(raw)?;
+            internals.record_version = Some(version.clone());
+            record.plugin_internals = Some(serialize_network_internals(&internals)?);
+        }
+        let res = self.connector.local.add_virutal_network(&record).await;
+        self.record_operation("store_virtual_network", vnet.uuid, &res)
+            .await;
+        res?;
+        self.append_journal(JournalEntry::VirtualNetwork(record))
+            .await;
+        self.touch_record(vnet.uuid, None).await;
+        versions.insert(vnet.uuid, version);
+        self.state.write().await.known_vnets.insert(vnet.uuid);
+        Ok(())
+    }
+
+    async fn store_network_namespace(&self, netns: &NetworkNamespace) -> FResult<()> {
+        let res = self.connector.local.add_network_namespace(netns).await;
+        self.record_operation("store_network_namespace", netns.uuid, &res)
+            .await;
+        if res.is_ok() {
+            self.append_journal(JournalEntry::NetworkNamespace(netns.clone()))
+                .await;
+            self.touch_record(netns.uuid, None).await;
+            self.state.write().await.known_namespaces.insert(netns.uuid);
+        }
+        res
+    }
+
+    async fn store_connection_point(&self, cp: &ConnectionPoint) -> FResult<()> {
+        let res = self.connector.local.add_connection_point(cp).await;
+        self.record_operation("store_connection_point", cp.uuid, &res)
+            .await;
+        if res.is_ok() {
+            self.append_journal(JournalEntry::ConnectionPoint(cp.clone()))
+                .await;
+            self.touch_record(cp.uuid, None).await;
+            self.state.write().await.known_cps.insert(cp.uuid);
+        }
+        res
+    }
+
+    /// Adds a DPDK port to the switch, the vhost-user ones are added
+    /// only if missing
+    fn provision_dataplane_port(&self, port: &DataplanePort) -> FResult<()> {
+        match &port.kind {
+            DataplanePortKind::VHOSTUSER => {
+                let dpdk_bridge = self.get_dpdk_bridge_from_config()?;
+                let socket_path = port.socket_path.as_ref().ok_or(FError::NotFound)?;
+                let mut cmd = Command::new("ovs-vsctl");
+                cmd.arg("--may-exist")
+                    .arg("add-port")
+                    .arg(&dpdk_bridge)
+                    .arg(&port.if_name)
+                    .arg("--")
+                    .arg("set")
+                    .arg("Interface")
+                    .arg(&port.if_name)
+                    .arg("type=dpdkvhostuserclient")
+                    .arg(format!("options:vhost-server-path={}", socket_path));
+                exec_command(cmd)?;
+            }
+            DataplanePortKind::VDPA(mgmt_dev) => {
+                let mut cmd = Command::new("vdpa");
+                cmd.arg("dev")
+                    .arg("add")
+                    .arg("name")
+                    .arg(&port.if_name)
+                    .arg("mgmtdev")
+                    .arg(mgmt_dev);
+                exec_command(cmd)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Creates the link of an IPIP or SIT tunnel, inside its namespace
+    /// through the manager
+    async fn provision_ip_tunnel(&self, tunnel: &IPTunnel) -> FResult<()> {
+        match tunnel.net_ns {
+            Some(ns_uuid) => {
+                let ns_manager = self.get_ns_manager(&ns_uuid).await?;
+                ns_manager
+                    .add_virtual_interface_ip_tunnel(
+                        tunnel.if_name.clone(),
+                        tunnel.kind,
+                        tunnel.local_addr,
+                        tunnel.remote_addr,
+                        tunnel.ttl,
+                    )
+                    .await??;
+            }
+            None => {
+                self.kernel().create_ip_tunnel_iface(
+                    &tunnel.if_name,
+                    tunnel.kind,
+                    tunnel.local_addr,
+                    tunnel.remote_addr,
+                    tunnel.ttl,
+                )?;
+                self.kernel().set_iface_up(tunnel.if_name.clone()).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn deprovision_ip_tunnel(&self, tunnel: &IPTunnel) -> FResult<()> {
+        match tunnel.net_ns {
+            Some(ns_uuid) => {
+                let ns_manager = self.get_ns_manager(&ns_uuid).await?;
+                ns_manager
+                    .del_virtual_interface(tunnel.if_name.clone())
+                    .await?
+            }
+            None => self.kernel().del_iface(tunnel.if_name.clone()).await,
+        }
+    }
+
+    async fn deprovision_dataplane_port(&self, port: &DataplanePort) -> FResult<()> {
+        match port.kind {
+            DataplanePortKind::VHOSTUSER => {
+                let mut cmd = Command::new("ovs-vsctl");
+                cmd.arg("--if-exists")
+                    .arg("del-port")
+                    .arg(self.get_dpdk_bridge_from_config()?)
+                    .arg(&port.if_name);
+                exec_command(cmd)?;
+                if let Some(ref socket_path) = port.socket_path {
+                    if async_std::path::Path::new(socket_path).exists().await {
+                        async_std::fs::remove_file(async_std::path::Path::new(socket_path)).await?;
+                    }
+                }
+            }
+            DataplanePortKind::VDPA(_) => {
+                let mut cmd = Command::new("vdpa");
+                cmd.arg("dev").arg("del").arg(&port.if_name);
+                exec_command(cmd)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Interface record of a DPDK port. The SDK has no kind for them, they
+    /// are recorded as MACVLAN devices on top of the DPDK bridge or of the
+    /// vDPA management device.
+    fn dataplane_port_interface(&self, port: &DataplanePort) -> FResult<VirtualInterface> {
+        let dev = match &port.kind {
+            DataplanePortKind::VHOSTUSER => Interface {
+                if_name: self.get_dpdk_bridge_from_config()?,
+                kind: InterfaceKind::BRIDGE,
+                addresses: Vec::new(),
+                phy_address: None,
+            },
+            DataplanePortKind::VDPA(mgmt_dev) => Interface {
+                if_name: mgmt_dev.clone(),
+                kind: InterfaceKind::ETHERNET,
+                addresses: Vec::new(),
+                phy_address: None,
+            },
+        };
+        Ok(VirtualInterface {
+            uuid: port.uuid,
+            if_name: port.if_name.clone(),
+            net_ns: None,
+            parent: None,
+            kind: VirtualInterfaceKind::MACVLAN(MACVLANKind { dev }),
+            addresses: Vec::new(),
+            phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
+        })
+    }
+
+    async fn remove_interface_record(&self, intf_uuid: Uuid) -> FResult<()> {
+        let res = self.connector.local.remove_interface(intf_uuid).await;
+        self.record_operation("remove_interface", intf_uuid, &res)
+            .await;
+        if res.is_ok() {
+            self.append_journal(JournalEntry::RemoveInterface(intf_uuid))
+                .await;
+            let mut guard = self.state.write().await;
+            guard.record_metadata.remove(&intf_uuid);
+            guard.interface_stats.remove(&intf_uuid);
+            guard.link_oper_states.remove(&intf_uuid);
+            guard.ip_tunnels.remove(&intf_uuid);
+        }
+        res
+    }
+
+    async fn remove_virtual_network_record(&self, vnet_uuid: Uuid) -> FResult<()> {
+        let res = self.connector.local.remove_virtual_network(vnet_uuid).await;
+        self.record_operation("remove_virtual_network", vnet_uuid, &res)
+            .await;
+        if res.is_ok() {
+            self.append_journal(JournalEntry::RemoveVirtualNetwork(vnet_uuid))
+                .await;
+            let mut guard = self.state.write().await;
+            guard.known_vnets.remove(&vnet_uuid);
+            guard.record_metadata.remove(&vnet_uuid);
+        }
+        res
+    }
+
+    async fn remove_network_namespace_record(&self, ns_uuid: Uuid) -> FResult<()> {
+        let res = self.connector.local.remove_network_namespace(ns_uuid).await;
+        self.record_operation("remove_network_namespace", ns_uuid, &res)
+            .await;
+        if res.is_ok() {
+            self.append_journal(JournalEntry::RemoveNetworkNamespace(ns_uuid))
+                .await;
+            let mut guard = self.state.write().await;
+            guard.known_namespaces.remove(&ns_uuid);
+            guard.record_metadata.remove(&ns_uuid);
+        }
+        res
+    }
+
+    async fn remove_connection_point_record(&self, cp_uuid: Uuid) -> FResult<()> {
+        let res = self.connector.local.remove_connection_point(cp_uuid).await;
+        self.record_operation("remove_connection_point", cp_uuid, &res)
+            .await;
+        if res.is_ok() {
+            self.append_journal(JournalEntry::RemoveConnectionPoint(cp_uuid))
+                .await;
+            let mut guard = self.state.write().await;
+            guard.known_cps.remove(&cp_uuid);
+            guard.record_metadata.remove(&cp_uuid);
+        }
+        res
+    }
+
+    /// Tenant of a virtual network with the most `operation`s its churn
+    /// limit allows and the window of the limit in seconds, `None` when the
+    /// operation is not limited. The tenant is taken from the local record,
+    /// then from the global one.
+    async fn network_churn_limit(
+        &self,
+        vnet: &VirtualNetwork,
+        operation: ChurnOperation,
+    ) -> Option<(String, u32, u64)> {
+        let limits = self.config.churn_limits.as_ref()?;
+        let tenant = match network_tenant(vnet) {
+            Some(tenant) => tenant,
+            None => self
+                .connector
+                .global
+                .get_virtual_network(vnet.uuid)
+                .await
+                .ok()
+                .and_then(|global| network_tenant(&global))
+                .unwrap_or_else(|| DEFAULT_TENANT.to_string()),
+        };
+        let limit = limits.tenants.get(&tenant).or(limits.default.as_ref())?;
+        let max = match operation {
+            ChurnOperation::CREATE => limit.max_creates,
+            ChurnOperation::DELETE => limit.max_deletes,
+        }?;
+        Some((tenant, max, limit.window_secs))
+    }
+
+    /// Fails with a `ThrottledError` when the tenant of a virtual network
+    /// already did as many creates or deletes as its churn limit allows in
+    /// the window. Otherwise the operation is counted right away, so that
+    /// concurrent operations cannot all pass the check, and the returned
+    /// slot is given back by `settle_network_churn` if it fails.
+    async fn check_network_churn(
+        &self,
+        vnet: &VirtualNetwork,
+        operation: ChurnOperation,
+    ) -> FResult<ChurnSlot> {
+        let (tenant, max, window_secs) = match self.network_churn_limit(vnet, operation).await {
+            Some(limit) => limit,
+            None => return Ok(None),
+        };
+        let now = now_ms();
+        let window_ms = window_secs.saturating_mul(1000);
+        let mut guard = self.state.write().await;
+        let history = guard
+            .churn_history
+            .entry((tenant.clone(), operation))
+            .or_insert_with(VecDeque::new);
+        while history
+            .front()
+            .map(|t| now.saturating_sub(*t) >= window_ms)
+            .unwrap_or(false)
+        {
+            history.pop_front();
+        }
+        if history.len() >= max as usize {
+            let retry_after_ms = history
+                .front()
+                .map(|t| (t + window_ms).saturating_sub(now))
+                .unwrap_or(window_ms);
+            log::warn!(
+                "Throttling {:?} of {} for tenant {}, retry in {}ms",
+                operation,
+                vnet.uuid,
+                tenant,
+                retry_after_ms
+            );
+            return Err(ThrottledError {
+                tenant,
+                operation,
+                limit: max,
+                window_secs,
+                retry_after_ms,
+            }
+            .into());
+        }
+        history.push_back(now);
+        Ok(Some((tenant, operation, now)))
+    }
+
+    /// Gives back the slot taken by `check_network_churn` when the
+    /// operation failed, only the ones that succeeded are counted
+    async fn settle_network_churn<T>(&self, slot: ChurnSlot, res: &FResult<T>) {
+        if let (Some((tenant, operation, timestamp)), Err(_)) = (slot, res) {
+            let mut guard = self.state.write().await;
+            if let Some(history) = guard.churn_history.get_mut(&(tenant, operation)) {
+                if let Some(pos) = history.iter().rposition(|t| *t == timestamp) {
+                    history.remove(pos);
+                }
+            }
+        }
+    }
+
+    /// Updates the audit data of a record just written, the creation is
+    /// kept from the first write and the version from the last one
+    /// giving it
+    async fn touch_record(&self, uuid: Uuid, version: Option<RecordVersion>) {
+        let now = now_ms();
+        let existing = self.state.read().await.record_metadata.get(&uuid).cloned();
+        let metadata = match existing {
+            Some(metadata) => RecordMetadata {
+                updated_at: now,
+                version: version.or(metadata.version),
+                ..metadata
+            },
+            None => RecordMetadata {
+                created_at: now,
+                updated_at: now,
+                created_by: match self.agent.as_ref() {
+                    Some(agent) => agent.get_node_uuid().await.ok().and_then(|r| r.ok()),
+                    None => None,
+                },
+                version,
+            },
+        };
+        self.state
+            .write()
+            .await
+            .record_metadata
+            .insert(uuid, metadata.clone());
+        self.append_journal(JournalEntry::Metadata(uuid, metadata))
+            .await;
+    }
+
+    /// Adds the records found in the journal to the ones listed by the
+    /// plugin, records no longer present are dropped when listing.
+    async fn index_records(&self, state: &JournalState) {
+        let mut guard = self.state.write().await;
+        for iface in state.interfaces.values() {
+            guard
+                .interface_names
+                .entry(iface.uuid)
+                .or_insert_with(|| (iface.if_name.clone(), iface.net_ns));
+        }
+        guard.known_vnets.extend(state.virtual_networks.keys());
+        guard.known_namespaces.extend(state.namespaces.keys());
+        guard.known_cps.extend(state.connection_points.keys());
+        guard.bridge_ports.extend(
+            state
+                .bridge_ports
+                .iter()
+                .map(|(uuid, config)| (*uuid, config.clone())),
+        );
+        guard
+            .cp_qos
+            .extend(state.cp_qos.iter().map(|(uuid, qos)| (*uuid, qos.clone())));
+        guard.cp_rate_limits.extend(
+            state
+                .cp_rate_limits
+                .iter()
+                .map(|(uuid, limit)| (*uuid, limit.clone())),
+        );
+        guard.dataplane_ports.extend(
+            state
+                .dataplane_ports
+                .iter()
+                .map(|(uuid, port)| (*uuid, port.clone())),
+        );
+        guard.ip_tunnels.extend(
+            state
+                .ip_tunnels
+                .iter()
+                .map(|(uuid, tunnel)| (*uuid, tunnel.clone())),
+        );
+        guard.rp_filters.extend(
+            state
+                .rp_filters
+                .iter()
+                .map(|(uuid, filter)| (*uuid, filter.clone())),
+        );
+        for (ns_uuid, failover) in &state.route_failovers {
+            let status = DefaultRouteFailoverStatus {
+                primary_active: true,
+                failures: 0,
+                switches: 0,
+            };
+            guard
+                .route_failovers
+                .insert(*ns_uuid, (failover.clone(), status));
+        }
+        for (uuid, metadata) in &state.metadata {
+            guard
+                .record_metadata
+                .entry(*uuid)
+                .or_insert_with(|| metadata.clone());
+        }
+        for (uuid, vf) in &state.virtual_functions {
+            guard.vf_pool.allocated.insert(*uuid, vf.clone());
+            if let Some(free) = guard.vf_pool.free.get_mut(&vf.pf) {
+                free.retain(|index| *index != vf.index);
+            }
+        }
+        drop(guard);
+
+        let mut versions = self.record_versions.lock().await;
+        for (uuid, metadata) in &state.metadata {
+            if let Some(version) = &metadata.version {
+                versions.entry(*uuid).or_insert_with(|| version.clone());
+            }
+        }
+        for (uuid, vnet) in &state.virtual_networks {
+            let version = vnet
+                .plugin_internals
+                .as_ref()
+                .and_then(|raw| deserialize_network_internals(raw).ok())
+                .and_then(|internals| internals.record_version);
+            if let Some(version) = version {
+                versions.insert(*uuid, version);
+            }
+        }
+    }
+
+    fn get_journal_path(&self) -> async_std::path::PathBuf {
+        async_std::path::Path::new(self.get_run_path().as_os_str()).join(journal::JOURNAL_FILE)
+    }
+
+    /// Appends to the journal, failures are logged as the
+    /// record is already written
+    async fn append_journal(&self, entry: JournalEntry) {
+        let mut writes = self.journal_lock.lock().await;
+        let path = self.get_journal_path();
+        if let Err(e) = journal::append(&path, now_ms(), entry).await {
+            log::warn!("Unable to append to the journal: {}", e);
+            return;
+        }
+        *writes += 1;
+        if *writes < JOURNAL_COMPACTION_WRITES {
+            return;
+        }
+        let res = async {
+            let state = journal::read(&path).await?;
+            journal::compact(&path, now_ms(), &state).await
+        }
+        .await;
+        match res {
+            Ok(_) => *writes = 0,
+            Err(e) => log::warn!("Unable to compact the journal: {}", e),
+        }
+    }
+
+    /// Rebuilds the in-memory state kept for the virtual networks
+    /// recorded by a previous run of the plugin from their internals
+    async fn restore_vnet_state(&self) {
+        let vnets: Vec<Uuid> = self
+            .state
+            .read()
+            .await
+            .known_vnets
+            .iter()
+            .copied()
+            .collect();
+        for vnet_uuid in vnets {
+            let vnet = match self.connector.local.get_virtual_network(vnet_uuid).await {
+                Ok(vnet) => vnet,
+                Err(_) => continue,
+            };
+            let internals = match vnet
+                .plugin_internals
+                .as_ref()
+                .map(|raw| deserialize_network_internals(raw))
+            {
+                Some(Ok(internals)) => internals,
+                _ => continue,
+            };
+            if let Err(e) = self.restore_vnet(&vnet, &internals).await {
+                log::error!(
+                    target: logging::RECONCILE,
+                    "Unable to restore the state of {}: {}",
+                    vnet_uuid,
+                    e
+                );
+            }
+        }
+        if !self.state.read().await.isolated_bridges.is_empty() {
+            if let Err(e) = self.update_isolation().await {
+                log::error!(
+                    target: logging::RECONCILE,
+                    "Unable to restore the isolation of the virtual networks: {}",
+                    e
+                );
+            }
+        }
+    }
+
+    async fn restore_vnet(
+        &self,
+        vnet: &VirtualNetwork,
+        internals: &VirtualNetworkInternals,
+    ) -> FResult<()> {
+        let mut guard = self.state.write().await;
+        if let Some(RemoteEndpoint::Hostname(host)) = &internals.remote_endpoint {
+            guard.resolved_endpoints.insert(vnet.uuid, host.clone());
+        }
+        if let Some(l2tp_info) = &internals.l2tp {
+            guard.l2tp_sessions.insert(vnet.uuid, l2tp_info.clone());
+        }
+        if let Some(bridge) = &internals.isolated_bridge {
+            guard.isolated_bridges.insert(vnet.uuid, bridge.clone());
+        }
+        drop(guard);
+        if internals.unicast_peers.is_some() {
+            let vxl_name = self.get_vxlan_iface_name(vnet).await?;
+            self.state
+                .write()
+                .await
+                .unicast_vnets
+                .insert(vnet.uuid, vxl_name);
+            self.join_elan(vnet.uuid).await?;
+        }
+        if !internals.dns_records.is_empty() {
+            self.publish_dns_records(vnet.uuid, &internals.dns_records)
+                .await;
+        }
+        if !internals.services_links.is_empty() {
+            self.restore_services_links(vnet, internals).await?;
+        }
+        // gone with a reboot, kept otherwise
+        let counters = async {
+            let br_uuid = self.get_virtual_network_bridge(vnet).await?;
+            let bridge = self.connector.local.get_interface(br_uuid).await?;
+            self.add_traffic_counters(vnet.uuid, &bridge.if_name)
+        }
+        .await;
+        if let Err(e) = counters {
+            log::warn!(
+                target: logging::RECONCILE,
+                "Unable to restore the traffic counters of {}: {}",
+                vnet.uuid,
+                e
+            );
+        }
+        // the rules enabled by the schedules may have changed while the
+        // plugin was not running, this also resumes their monitoring
+        if internals.set_rules.iter().any(|r| r.schedule.is_some()) {
+            self.apply_set_rules(vnet, internals).await?;
+        }
+        Ok(())
+    }
+
+    /// Takes back the DHCP servers and resolvers of the virtual networks
+    /// recorded by a previous run of the plugin: the dnsmasq instances
+    /// still running are found through their pid files, the missing ones
+    /// and the embedded servers are started again
+    async fn adopt_dhcp_servers(&self) {
+        let vnets: Vec<Uuid> = self
+            .state
+            .read()
+            .await
+            .known_vnets
+            .iter()
+            .copied()
+            .collect();
+        for vnet_uuid in vnets {
+            let vnet = match self.connector.local.get_virtual_network(vnet_uuid).await {
+                Ok(vnet) => vnet,
+                Err(_) => continue,
+            };
+            let internals = match vnet
+                .plugin_internals
+                .as_ref()
+                .map(|raw| deserialize_network_internals(raw))
+            {
+                Some(Ok(internals)) => internals,
+                _ => continue,
+            };
+            if let Err(e) = self.adopt_vnet_dhcp(&vnet, &internals).await {
+                log::error!(
+                    target: logging::DHCP,
+                    "Unable to take back the DHCP server of {}: {}",
+                    vnet_uuid,
+                    e
+                );
+            }
+        }
+    }
+
+    async fn adopt_vnet_dhcp(
+        &self,
+        vnet: &VirtualNetwork,
+        internals: &VirtualNetworkInternals,
+    ) -> FResult<()> {
+        let ns_info = internals.associated_netns.as_ref();
+        let ns_name = ns_info.map(|ns| ns.ns_name.clone());
+        if let (Some(resolver), Some(ns_name)) = (&internals.resolver, &ns_name) {
+            // restarted by the monitoring loop if it is not running
+            self.state
+                .write()
+                .await
+                .resolvers
+                .insert(vnet.uuid, (ns_name.clone(), resolver.clone()));
+        }
+        if let (Some(nat64), Some(ns_info)) = (&internals.nat64_gateway, ns_info) {
+            let bridge = self
+                .vnet_namespace_bridge(vnet, Some(ns_info.ns_uuid))
+                .await?;
+            self.adopt_vnet_nat64(vnet, internals, nat64, &ns_info.ns_name, &bridge.if_name)
+                .await?;
+        }
+        let dhcp = match &internals.dhcp {
+            Some(dhcp) => dhcp,
+            None => return Ok(()),
+        };
+        if self.vnet_dhcp_running(vnet.uuid, dhcp).await {
+            log::debug!(
+                target: logging::DHCP,
+                "DHCP server of {} still running, adopted",
+                vnet.uuid
+            );
+            return Ok(());
+        }
+        let bridge = self
+            .vnet_namespace_bridge(vnet, ns_info.map(|ns| ns.ns_uuid))
+            .await?;
+        log::info!(
+            target: logging::DHCP,
+            "DHCP server of {} not running, starting it again",
+            vnet.uuid
+        );
+        self.respawn_vnet_dhcp(vnet.uuid, ns_name.as_deref(), &bridge.if_name, dhcp)
+            .await
+    }
+
+    /// Bridge of a virtual network in the namespace `ns_uuid`
+    async fn vnet_namespace_bridge(
+        &self,
+        vnet: &VirtualNetwork,
+        ns_uuid: Option<Uuid>,
+    ) -> FResult<VirtualInterface> {
+        for iface_uuid in &vnet.interfaces {
+            if let Ok(iface) = self.connector.local.get_interface(*iface_uuid).await {
+                if matches!(iface.kind, VirtualInterfaceKind::BRIDGE(_)) && iface.net_ns == ns_uuid
+                {
+                    return Ok(iface);
+                }
+            }
+        }
+        Err(FError::NotFound)
+    }
+
+    /// Recreates namespaces, interfaces and virtual networks found in the
+    /// journal whose local record is missing, as after a reboot of the
+    /// node. The journal is then compacted.
+    pub async fn restore_from_journal(&self) -> FResult<JournalReplay> {
+        let path = self.get_journal_path();
+        let state = {
+            let _guard = self.journal_lock.lock().await;
+            journal::read(&path).await?
+        };
+        let replay = self.replay_records(&state).await;
+
+        let mut writes = self.journal_lock.lock().await;
+        let state = journal::read(&path).await?;
+        journal::compact(&path, now_ms(), &state).await?;
+        *writes = 0;
+        Ok(replay)
+    }
+
+    /// Recreates the records of `state` that are missing locally
+    async fn replay_records(&self, state: &JournalState) -> JournalReplay {
+        let mut replay = JournalReplay::default();
+        self.index_records(state).await;
+
+        for netns in state.namespaces.values() {
+            if self
+                .connector
+                .local
+                .get_network_namespace(netns.uuid)
+                .await
+                .is_ok()
+            {
+                continue;
+            }
+            match self.replay_namespace(netns).await {
+                Ok(_) => replay.namespaces.push(netns.uuid),
+                Err(e) => replay
+                    .failures
+                    .push(format!("namespace {}: {}", netns.uuid, e)),
+            }
+        }
+
+        let mut missing = Vec::new();
+        for iface in state.interfaces.values() {
+            if self
+                .connector
+                .local
+                .get_interface(iface.uuid)
+                .await
+                .is_err()
+            {
+                missing.push(iface.clone());
+            }
+        }
+        // bridges have to exist before their ports are enslaved
+        missing.sort_by_key(|i| !matches!(i.kind, VirtualInterfaceKind::BRIDGE(_)));
+        let mut created = Vec::new();
+        for iface in &missing {
+            if created.contains(&iface.uuid) {
+                continue;
+            }
+            match self.replay_interface_link(iface, state).await {
+                Ok(mut uuids) => created.append(&mut uuids),
+                Err(e) => replay
+                    .failures
+                    .push(format!("interface {}: {}", iface.uuid, e)),
+            }
+        }
+        for mut iface in missing {
+            if !created.contains(&iface.uuid) {
+                continue;
+            }
+            match self.replay_interface_config(&mut iface, state).await {
+                Ok(_) => replay.interfaces.push(iface.uuid),
+                Err(e) => replay
+                    .failures
+                    .push(format!("interface {}: {}", iface.uuid, e)),
+            }
+        }
+
+        for vnet in state.virtual_networks.values() {
+            if self
+                .connector
+                .local
+                .get_virtual_network(vnet.uuid)
+                .await
+                .is_ok()
+            {
+                continue;
+            }
+            match self.replay_virtual_network(vnet.clone(), state).await {
+                Ok(_) => replay.virtual_networks.push(vnet.uuid),
+                Err(e) => replay
+                    .failures
+                    .push(format!("virtual network {}: {}", vnet.uuid, e)),
+            }
+        }
+
+        for cp in state.connection_points.values() {
+            if self
+                .connector
+                .local
+                .get_connection_point(cp.uuid)
+                .await
+                .is_ok()
+            {
+                continue;
+            }
+            let res = async {
+                self.store_connection_point(cp).await?;
+                // the shaper and the limits went away with the veth
+                if let Some(qos) = state.cp_qos.get(&cp.uuid) {
+                    let ext_veth = self.connector.local.get_interface(cp.external_veth).await?;
+                    self.set_iface_qos(&ext_veth.if_name, qos)?;
+                }
+                if let Some(limit) = state.cp_rate_limits.get(&cp.uuid) {
+                    let ext_veth = self.connector.local.get_interface(cp.external_veth).await?;
+                    if ext_veth.parent.is_some() {
+                        self.set_iface_rate_limit(cp.uuid, &ext_veth.if_name, limit)?;
+                    }
+                }
+                Ok::<(), FError>(())
+            }
+            .await;
+            match res {
+                Ok(_) => replay.connection_points.push(cp.uuid),
+                Err(e) => replay
+                    .failures
+                    .push(format!("connection point {}: {}", cp.uuid, e)),
+            }
+        }
+        replay
+    }
+
+    fn get_replica_path(&self, node_uuid: Uuid) -> async_std::path::PathBuf {
+        async_std::path::Path::new(self.get_run_path().as_os_str())
+            .join(REPLICAS_DIR)
+            .join(format!("{}.json", node_uuid))
+    }
+
+    /// Cipher of the replicas, from the key shared with the peer
+    fn replication_cipher(&self) -> FResult<ChaCha20Poly1305> {
+        let path = self.config.replication_key_file.as_ref().ok_or_else(|| {
+            FError::NetworkingError("No replication_key_file configured".to_string())
+        })?;
+        read_key_cipher(path)
+    }
+
+    /// Cipher of the writes of the global records asked to the
+    /// coordinator, from the key shared by all the nodes
+    fn global_cipher(&self) -> FResult<ChaCha20Poly1305> {
+        let path =
+            self.config.global_key_file.as_ref().ok_or_else(|| {
+                FError::NetworkingError("No global_key_file configured".to_string())
+            })?;
+        read_key_cipher(path)
+    }
+
+    async fn read_replica_file(&self, path: &async_std::path::Path) -> FResult<NodeReplica> {
+        let content = async_std::fs::read(path).await?;
+        serde_json::from_slice(&content).map_err(|e| FError::NetworkingError(format!("{}", e)))
+    }
+
+    /// Returns the extension server of the plugin running on a node
+    async fn find_node_server(&self, node_uuid: Uuid) -> FResult<Option<Uuid>> {
+        let my_uuid = self.state.read().await.uuid;
+        let servers = LinuxNetworkExtensionClient::find_servers(self.z.clone()).await?;
+        for server_uuid in servers {
+            if Some(server_uuid) == my_uuid {
+                continue;
+            }
+            let client = LinuxNetworkExtensionClient::new(self.z.clone(), server_uuid);
+            match client.get_node_uuid().await {
+                Ok(Ok(uuid)) if uuid == node_uuid => return Ok(Some(server_uuid)),
+                Ok(_) => continue,
+                Err(e) => log::warn!("Unable to contact {}: {}", server_uuid, e),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Sends the local records and the secrets they use to the
+    /// `replication_peer`, only when they changed since the last time
+    async fn replicate_to_peer(&self) -> FResult<()> {
+        let peer = match self.config.replication_peer {
+            Some(peer) => peer,
+            None => return Ok(()),
+        };
+        let state = {
+            let _guard = self.journal_lock.lock().await;
+            journal::read(&self.get_journal_path()).await?
+        };
+        let replica = NodeReplica {
+            node_uuid: self.agent.as_ref().unwrap().get_node_uuid().await??,
+            timestamp: now_ms(),
+            entries: state.entries(),
+            secrets: self.collect_secrets(&state).await,
+        };
+        // the entries come from maps, their order is not stable
+        let mut records = replica
+            .entries
+            .iter()
+            .map(serde_json::to_string)
+            .collect::<Result<Vec<String>, _>>()
+            .map_err(|e| FError::NetworkingError(format!("{}", e)))?;
+        records.sort();
+        let etag = record_etag(&(records, &replica.secrets))?;
+        if self.state.read().await.replicated_etag.as_ref() == Some(&etag) {
+            return Ok(());
+        }
+        let server_uuid = self
+            .find_node_server(peer)
+            .await?
+            .ok_or_else(|| FError::NetworkingError(format!("{} not found", peer)))?;
+        log::debug!(
+            target: logging::RECONCILE,
+            "Replicating {} records to {}",
+            replica.entries.len(),
+            peer
+        );
+        let sealed = seal_replica(&self.replication_cipher()?, &replica)?;
+        LinuxNetworkExtensionClient::new(self.z.clone(), server_uuid)
+            .store_replica(sealed)
+            .await??;
+        self.state.write().await.replicated_etag = Some(etag);
+        Ok(())
+    }
+
+    /// Private keys of the WireGuard interfaces of the virtual networks
+    async fn collect_secrets(&self, state: &JournalState) -> Vec<ReplicatedSecret> {
+        let mut secrets = Vec::new();
+        for vnet in state.virtual_networks.values() {
+            let wireguard = vnet
+                .plugin_internals
+                .as_ref()
+                .and_then(|i| deserialize_network_internals(i).ok())
+                .and_then(|i| i.wireguard);
+            if let Some(wg) = wireguard {
+                match async_std::fs::read(&wg.private_key_file).await {
+                    Ok(content) => secrets.push(ReplicatedSecret {
+                        vnet_uuid: vnet.uuid,
+                        content,
+                    }),
+                    Err(e) => log::warn!("Unable to read the key of {}: {}", vnet.uuid, e),
+                }
+            }
+        }
+        secrets
+    }
+
+    async fn replay_namespace(&self, netns: &NetworkNamespace) -> FResult<()> {
+        if !std::path::Path::new("/run/netns")
+            .join(&netns.ns_name)
+            .exists()
+        {
+            self.kernel().add_netns(netns.ns_name.clone()).await?;
+        }
+        self.spawn_ns_manager(netns.ns_name.clone(), netns.uuid)
+            .await?;
+        let ns_manager = self.get_ns_manager(&netns.uuid).await?;
+        ns_manager
+            .set_virtual_interface_up("lo".to_string())
+            .await??;
+        self.store_network_namespace(netns).await
+    }
+
+    fn get_journal_ns_name(&self, ns_uuid: Uuid, state: &JournalState) -> FResult<String> {
+        state
+            .namespaces
+            .get(&ns_uuid)
+            .map(|ns| ns.ns_name.clone())
+            .ok_or(FError::NotFound)
+    }
+
+    /// Creates the link of an interface, returns the interfaces created,
+    /// both ends for veth pairs.
+    async fn replay_interface_link(
+        &self,
+        iface: &VirtualInterface,
+        state: &JournalState,
+    ) -> FResult<Vec<Uuid>> {
+        // re-adopted by the switch when it still has them
+        if let Some(port) = state.dataplane_ports.get(&iface.uuid) {
+            if let DataplanePortKind::VDPA(_) = port.kind {
+                let mut cmd = Command::new("vdpa");
+                cmd.arg("dev").arg("show").arg(&port.if_name);
+                if exec_command(cmd).is_ok() {
+                    return Ok(vec![iface.uuid]);
+                }
+            }
+            self.provision_dataplane_port(port)?;
+            return Ok(vec![iface.uuid]);
+        }
+        if let Some(tunnel) = state.ip_tunnels.get(&iface.uuid) {
+            let exists = match tunnel.net_ns {
+                Some(ns_uuid) => {
+                    self.get_ns_manager(&ns_uuid)
+                        .await?
+                        .check_virtual_interface_exists(tunnel.if_name.clone())
+                        .await??
+                }
+                None => self.kernel().iface_exists(tunnel.if_name.clone()).await?,
+            };
+            if !exists {
+                self.provision_ip_tunnel(tunnel).await?;
+            }
+            return Ok(vec![iface.uuid]);
+        }
+        match (&iface.kind, iface.net_ns) {
+            (VirtualInterfaceKind::BRIDGE(_), None) => {
+                if !self.kernel().iface_exists(iface.if_name.clone()).await? {
+                    self.kernel().create_bridge(iface.if_name.clone()).await?;
+                }
+            }
+            (VirtualInterfaceKind::BRIDGE(_), Some(ns_uuid)) => {
+                let ns_manager = self.get_ns_manager(&ns_uuid).await?;
+                if !ns_manager
+                    .check_virtual_interface_exists(iface.if_name.clone())
+                    .await??
+                {
+                    ns_manager
+                        .add_virtual_interface_bridge(iface.if_name.clone())
+                        .await??;
+                }
+            }
+            (VirtualInterfaceKind::VETH(info), _) => {
+                let pair = state.interfaces.get(&info.pair).ok_or(FError::NotFound)?;
+                self.kernel()
+                    .create_veth(iface.if_name.clone(), pair.if_name.clone())
+                    .await?;
+                for end in &[iface, pair] {
+                    if let Some(ns_uuid) = end.net_ns {
+                        self.kernel()
+                            .set_iface_ns(
+                                end.if_name.clone(),
+                                self.get_journal_ns_name(ns_uuid, state)?,
+                            )
+                            .await?;
+                    }
+                }
+                return Ok(vec![iface.uuid, pair.uuid]);
+            }
+            (VirtualInterfaceKind::VXLAN(info), None) => {
+                let options = self.get_vxlan_options(None);
+                if info.mcast_addr.is_multicast() {
+                    self.kernel()
+                        .create_mcast_vxlan(
+                            iface.if_name.clone(),
+                            info.dev.if_name.clone(),
+                            info.vni,
+                            info.mcast_addr,
+                            info.port,
+                            &options,
+                        )
+                        .await?;
+                } else {
+                    let local_addr = *self
+                        .kernel()
+                        .get_iface_addresses(info.dev.if_name.clone())
+                        .await?
+                        .first()
+                        .ok_or(FError::NotFound)?;
+                    self.kernel()
+                        .create_ptp_vxlan(
+                            iface.if_name.clone(),
+                            info.dev.if_name.clone(),
+                            info.vni,
+                            local_addr,
+                            info.mcast_addr,
+                            info.port,
+                            &options,
+                        )
+                        .await?;
+                }
+            }
+            (VirtualInterfaceKind::VLAN(info), None) => {
+                self.kernel()
+                    .create_vlan(iface.if_name.clone(), info.dev.if_name.clone(), info.tag)
+                    .await?;
+            }
+            _ => return Err(FError::Unimplemented),
+        }
+        Ok(vec![iface.uuid])
+    }
+
+    /// Enslaves the interface to its parent and brings it up,
+    /// bridges get a new pinned MAC.
+    async fn replay_interface_config(
+        &self,
+        iface: &mut VirtualInterface,
+        state: &JournalState,
+    ) -> FResult<()> {
+        if let Some(parent) = iface.parent {
+            let master = state.interfaces.get(&parent).ok_or(FError::NotFound)?;
+            match iface.net_ns {
+                Some(ns_uuid) => {
+                    let ns_manager = self.get_ns_manager(&ns_uuid).await?;
+                    ns_manager
+                        .set_virtual_interface_master(iface.if_name.clone(), master.if_name.clone())
+                        .await??;
+                }
+                None => {
+                    self.kernel()
+                        .set_iface_master(iface.if_name.clone(), master.if_name.clone())
+                        .await?
+                }
+            }
+        }
+        if let (Some(_), Some(config)) = (iface.parent, state.bridge_ports.get(&iface.uuid)) {
+            self.apply_bridge_port_config(iface, config).await?;
+            self.state
+                .write()
+                .await
+                .bridge_ports
+                .insert(iface.uuid, config.clone());
+        }
+        if let VirtualInterfaceKind::BRIDGE(_) = iface.kind {
+            self.pin_bridge_mac(iface).await?;
+        }
+        match iface.net_ns {
+            Some(ns_uuid) => {
+                let ns_manager = self.get_ns_manager(&ns_uuid).await?;
+                ns_manager
+                    .set_virtual_interface_up(iface.if_name.clone())
+                    .await??;
+            }
+            None => self.kernel().set_iface_up(iface.if_name.clone()).await?,
+        }
+        self.store_interface(iface).await
+    }
+
+    async fn apply_bridge_port_config(
+        &self,
+        iface: &VirtualInterface,
+        config: &BridgePortConfig,
+    ) -> FResult<()> {
+        config.validate()?;
+        match iface.net_ns {
+            Some(ns_uuid) => {
+                let ns_manager = self.get_ns_manager(&ns_uuid).await?;
+                ns_manager
+                    .set_virtual_interface_bridge_port(iface.if_name.clone(), config.clone())
+                    .await?
+            }
+            None => {
+                self.kernel()
+                    .set_iface_bridge_port(&iface.if_name, config)
+                    .await
+            }
+        }
+    }
+
+    /// Adds the gateway address to the bridge served by the DHCP server,
+    /// restarts it and recreates the NAT table and port forwards.
+    async fn replay_virtual_network(
+        &self,
+        mut vnet: VirtualNetwork,
+        state: &JournalState,
+    ) -> FResult<()> {
+        let mut internals = match vnet.plugin_internals {
+            Some(ref internals) => deserialize_network_internals(internals)?,
+            None => return self.store_virtual_network(&vnet).await,
+        };
+        let ns_uuid = internals.associated_netns.as_ref().map(|ns| ns.ns_uuid);
+        let bridge = vnet
+            .interfaces
+            .iter()
+            .filter_map(|u| state.interfaces.get(u))
+            .find(|i| matches!(i.kind, VirtualInterfaceKind::BRIDGE(_)) && i.net_ns == ns_uuid)
+            .cloned();
+        let subnet = vnet.ip_configuration.as_ref().and_then(|c| c.subnet);
+
+        if let Some(ref fdb) = internals.fdb {
+            let default_bridge = vnet
+                .interfaces
+                .iter()
+                .filter_map(|u| state.interfaces.get(u))
+                .find(|i| matches!(i.kind, VirtualInterfaceKind::BRIDGE(_)) && i.net_ns.is_none());
+            if let Some(default_bridge) = default_bridge {
+                self.set_bridge_fdb_options(&default_bridge.if_name, fdb)?;
+            }
+        }
+
+        if let (Some(dhcp), Some(bridge)) = (&internals.dhcp, &bridge) {
+            validate_dhcp_reservations(&vnet, &internals)?;
+            let mut routers = dhcp.routers.clone();
+            // written before the dual-stack support
+            if let (true, Some((_, prefix))) = (routers.is_empty(), subnet) {
+                let router = dhcp
+                    .router
+                    .or_else(|| vnet.ip_configuration.as_ref().and_then(|c| c.gateway))
+                    .ok_or(FError::NotFound)?;
+                routers.push(
+                    IpNetwork::new(router, prefix)
+                        .map_err(|e| FError::NetworkingError(format!("{}", e)))?,
+                );
+            }
+            match &internals.associated_netns {
+                Some(ns_info) => {
+                    let ns_manager = self.get_ns_manager(&ns_info.ns_uuid).await?;
+                    for router in routers {
+                        ns_manager
+                            .add_virtual_interface_address(bridge.if_name.clone(), Some(router))
+                            .await??;
+                    }
+                    self.respawn_vnet_dhcp(
+                        vnet.uuid,
+                        Some(&ns_info.ns_name),
+                        &bridge.if_name,
+                        dhcp,
+                    )
+                    .await?;
+                }
+                None => {
+                    for router in routers {
+                        self.kernel()
+                            .add_iface_address(bridge.if_name.clone(), router.ip(), router.prefix())
+                            .await?;
+                    }
+                    self.respawn_vnet_dhcp(vnet.uuid, None, &bridge.if_name, dhcp)
+                        .await?;
+                }
+            }
+        }
+
+        #[cfg(feature = "nat")]
+        if !internals.associated_tables.is_empty() {
+            subnet.ok_or(FError::NotFound)?;
+            internals.associated_tables = self
+                .configure_vnet_nat(
+                    &vnet,
+                    internals.secondary_ip_configuration.as_ref(),
+                    &internals.snat.clone().unwrap_or_default(),
+                )
+                .await?;
+            if !internals.port_forwards.is_empty() {
+                self.apply_port_forwards(&vnet, &internals).await?;
+            }
+            if !internals.nft_sets.is_empty() {
+                self.apply_set_rules(&vnet, &internals).await?;
+            }
+        }
+        if !internals.egress_rules.is_empty() {
+            self.apply_egress_rules(&vnet, &internals).await?;
+        }
+        if !internals.address_mappings.is_empty() {
+            let iface = self.get_overlay_face_from_config().await?.if_name;
+            for mapping in internals.address_mappings.iter_mut() {
+                let subnet = subnet_of(
+                    &vnet,
+                    internals.secondary_ip_configuration.as_ref(),
+                    mapping.internal_ip,
+                );
+                mapping.table = self
+                    .configure_static_nat(mapping.external_ip, mapping.internal_ip, &iface, subnet)
+                    .await?;
+                mapping.iface = iface.clone();
+            }
+        }
+        if let (Some(flow_export), Some(bridge)) = (&internals.flow_export, &bridge) {
+            if bridge.net_ns.is_none() {
+                self.start_flow_export(flow_export).await?;
+            }
+        }
+        // connection points are bound again by their owners
+        internals.floating_ips.clear();
+        vnet.connection_points.clear();
+
+        vnet.plugin_internals = Some(serialize_network_internals(&internals)?);
+        self.store_virtual_network(&vnet).await
+    }
+
+    /// Keeps the last `state_dump_operations` operations for the state dumps
+    async fn record_operation<T>(&self, operation: &str, target: Uuid, res: &FResult<T>) {
+        let max = self
+            .config
+            .state_dump_operations
+            .unwrap_or(DEFAULT_STATE_DUMP_OPERATIONS);
+        let mut operations = self.recent_operations.lock().await;
+        while !operations.is_empty() && operations.len() >= max {
+            operations.pop_front();
+        }
+        if max > 0 {
+            operations.push_back(OperationRecord {
+                timestamp: now_ms(),
+                operation: operation.to_string(),
+                target,
+                error: res.as_ref().err().map(|e| format!("{}", e)),
+            });
+        }
+    }
+
+    /// Moves the running operation on `target`, if any, to `step`
+    async fn operation_step(&self, target: Uuid, step: CreateStep) {
+        let mut guard = self.state.write().await;
+        let op = match guard
+            .async_operations
+            .values_mut()
+            .find(|o| o.target == target && o.status == AsyncOperationStatus::RUNNING)
+        {
+            Some(op) => {
+                op.step = step as u32;
+                op.description = step.description().to_string();
+                op.clone()
+            }
+            None => return,
+        };
+        drop(guard);
+        self.publish_operation(&op).await;
+    }
+
+    /// Marks an operation as finished, dropping the oldest finished
+    /// ones beyond `async_operations_kept`
+    async fn finish_operation(&self, op_uuid: Uuid, error: Option<FError>) {
+        let kept = self
+            .config
+            .async_operations_kept
+            .unwrap_or(DEFAULT_ASYNC_OPERATIONS_KEPT);
+        let mut guard = self.state.write().await;
+        let op = match guard.async_operations.get_mut(&op_uuid) {
+            Some(op) => {
+                op.finished_at = Some(now_ms());
+                match error {
+                    Some(e) => {
+                        op.status = AsyncOperationStatus::FAILED;
+                        op.error = Some(format!("{}", e));
+                    }
+                    None => {
+                        op.status = AsyncOperationStatus::COMPLETED;
+                        op.step = op.steps;
+                    }
+                }
+                op.clone()
+            }
+            None => return,
+        };
+        let mut finished: Vec<(u64, Uuid)> = guard
+            .async_operations
+            .values()
+            .filter_map(|o| o.finished_at.map(|t| (t, o.uuid)))
+            .collect();
+        finished.sort();
+        let extra = finished.len().saturating_sub(kept);
+        for (_, uuid) in finished.into_iter().take(extra) {
+            guard.async_operations.remove(&uuid);
+        }
+        drop(guard);
+        self.publish_operation(&op).await;
+    }
+
+    /// Publishes the status of an operation under
+    /// `/fos/local/<plugin uuid>/operations/<operation uuid>`
+    async fn publish_operation(&self, op: &AsyncOperation) {
+        let plugin_uuid = match self.state.read().await.uuid {
+            Some(uuid) => uuid,
+            None => return,
+        };
+        let path = format!("/fos/local/{}/operations/{}", plugin_uuid, op.uuid);
+        let data = match serde_json::to_vec(op) {
+            Ok(data) => data,
+            Err(e) => {
+                log::warn!("Unable to encode operation {}: {}", op.uuid, e);
+                return;
+            }
+        };
+        if let Err(e) = self
+            .z
+            .write(&zenoh::net::ResKey::RName(path), data.into())
+            .await
+        {
+            log::warn!("Unable to publish operation {}: {}", op.uuid, e);
+        }
+    }
+
+    /// Writes the state of the plugin and the last operations to
+    /// `run_path`, returns the path of the dump. It does not wait for the
+    /// locks, so it can be used from the panic hook.
+    pub fn dump_state(&self, reason: &str) -> FResult<String> {
+        let state = self
+            .state
+            .try_read()
+            .map(|guard| LinuxNetworkStateSnapshot {
+                uuid: guard.uuid,
+                ns_managers: guard
+                    .ns_managers
+                    .iter()
+                    .map(|(k, (pid, _))| (*k, *pid))
+                    .collect(),
+                shared_ns_manager: guard.shared_ns_manager.as_ref().map(|(pid, _)| *pid),
+                ns_manager_spawn_stats: guard.ns_manager_spawn_stats.clone(),
+                dataplane_ports: guard.dataplane_ports.clone(),
+                resolved_endpoints: guard.resolved_endpoints.clone(),
+                unicast_vnets: guard.unicast_vnets.clone(),
+                ip_tunnels: guard.ip_tunnels.clone(),
+                pinned_bridge_macs: guard.pinned_bridge_macs.clone(),
+                interface_states: guard.interface_states.clone(),
+                interface_names: guard.interface_names.clone(),
+                l2tp_sessions: guard.l2tp_sessions.clone(),
+                resolvers: guard.resolvers.clone(),
+                remote_dns_records: guard.remote_dns_records.clone(),
+                cp_qos: guard.cp_qos.clone(),
+                rp_filters: guard.rp_filters.clone(),
+                isolated_bridges: guard.isolated_bridges.clone(),
+                known_vnets: guard.known_vnets.clone(),
+                known_namespaces: guard.known_namespaces.clone(),
+                known_cps: guard.known_cps.clone(),
+                dhcp_servers: guard.dhcp_servers.keys().copied().collect(),
+                route_failovers: guard.route_failovers.clone(),
+                dhcp_clients: guard.dhcp_clients.clone(),
+                async_operations: guard.async_operations.clone(),
+                dns64_proxies: guard.dns64_proxies.keys().copied().collect(),
+                cp_rate_limits: guard.cp_rate_limits.clone(),
+            });
+        let operations = self
+            .recent_operations
+            .try_lock()
+            .map(|ops| ops.iter().cloned().collect())
+            .unwrap_or_default();
+        let netlink_retries = self
+            .netlink_retries
+            .try_lock()
+            .map(|stats| stats.clone())
+            .unwrap_or_default();
+        let timestamp = now_ms();
+        let dump = StateDump {
+            timestamp,
+            reason: reason.to_string(),
+            state,
+            operations,
+            netlink_retries,
+            simulation: simulation::snapshot(),
+        };
+        let path = self
+            .get_run_path()
+            .join(format!("state-dump-{}.json", timestamp))
+            .to_str()
+            .ok_or(FError::EncodingError)?
+            .to_string();
+        let data = serde_json::to_vec_pretty(&dump)
+            .map_err(|e| FError::NetworkingError(format!("{}", e)))?;
+        std::fs::write(&path, data)?;
+        Ok(path)
+    }
+
+    /// Installs a panic hook writing a state dump before
+    /// calling the previous hook.
+    pub fn install_state_dump_hook(&self) {
+        let plugin = self.clone();
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            match plugin.dump_state(&format!("{}", info)) {
+                Ok(path) => error!("State dumped to {}", path),
+                Err(e) => error!("Unable to dump state: {}", e),
+            }
+            previous(info);
+        }));
+    }
+
+    /// Removes the given interfaces: ports are brought down, detached and
+    /// deleted, then bridges are brought down and deleted. Namespaces are
+    /// removed by the callers once the interfaces are gone. A failure does
+    /// not stop the teardown, the interfaces not deleted are reported.
+    async fn ordered_teardown(&self, ifaces: &[Uuid], skip_namespaced: bool) -> FResult<()> {
+        let mut ports = Vec::new();
+        let mut bridges = Vec::new();
+        for iface_uuid in ifaces {
+            match self.connector.local.get_interface(*iface_uuid).await {
+                Ok(iface) if skip_namespaced && iface.net_ns.is_some() => continue,
+                Ok(iface) => match iface.kind {
+                    VirtualInterfaceKind::BRIDGE(_) => bridges.push(iface),
+                    _ => ports.push(iface),
+                },
+                Err(_) => log::trace!("Interface {} already removed", iface_uuid),
+            }
+        }
+
+        for port in &ports {
+            if let Err(e) = self.set_interface_down_of(port).await {
+                log::warn!("Unable to bring {} down: {}", port.if_name, e);
+            }
+            if port.parent.is_some() {
+                if let Err(e) = self.release_interface_master(port).await {
+                    log::warn!("Unable to detach {}: {}", port.if_name, e);
+                }
+            }
+        }
+        let mut failed = Vec::new();
+        for port in ports {
+            // removing one end of a veth pair removes also the other one
+            if self.connector.local.get_interface(port.uuid).await.is_ok() {
+                match self.delete_virtual_interface(port.uuid).await {
+                    Ok(iface) => log::info!("Deleting virtual interface: {:?}", iface),
+                    Err(e) => {
+                        log::error!("Unable to delete {}: {}", port.if_name, e);
+                        failed.push(format!("{}: {}", port.if_name, e));
+                    }
+                }
+            }
+        }
+
+        for bridge in &bridges {
+            if let Err(e) = self.set_interface_down_of(bridge).await {
+                log::warn!("Unable to bring {} down: {}", bridge.if_name, e);
+            }
+        }
+        for bridge in bridges {
+            match self.delete_virtual_interface(bridge.uuid).await {
+                Ok(iface) => log::info!("Deleting virtual bridge: {:?}", iface),
+                Err(e) => {
+                    log::error!("Unable to delete {}: {}", bridge.if_name, e);
+                    failed.push(format!("{}: {}", bridge.if_name, e));
+                }
+            }
+        }
+        if !failed.is_empty() {
+            return Err(FError::NetworkingError(format!(
+                "Unable to delete {}",
+                failed.join(", ")
+            )));
+        }
+        Ok(())
+    }
+
+    async fn set_interface_down_of(&self, iface: &VirtualInterface) -> FResult<()> {
+        match iface.net_ns {
+            Some(ns_uuid) => {
+                let ns_manager = self.get_ns_manager(&ns_uuid).await?;
+                ns_manager
+                    .set_virtual_interface_down(iface.if_name.clone())
+                    .await?
+            }
+            None => self.kernel().set_iface_down(iface.if_name.clone()).await,
+        }
+    }
+
+    async fn get_interface_end(&self, iface: &VirtualInterface) -> FResult<InterfaceEnd> {
+        let ns_name = match iface.net_ns {
+            Some(ns_uuid) => Some(
+                self.connector
+                    .local
+                    .get_network_namespace(ns_uuid)
+                    .await?
+                    .ns_name,
+            ),
+            None => None,
+        };
+        Ok(InterfaceEnd {
+            uuid: iface.uuid,
+            if_name: iface.if_name.clone(),
+            net_ns: iface.net_ns,
+            ns_name,
+        })
+    }
+
+    /// Returns the VXLAN options of a network, falling back to the ones
+    /// in the plugin configuration.
+    fn get_vxlan_options(&self, internals: Option<&VirtualNetworkInternals>) -> VXLANOptions {
+        internals
+            .and_then(|i| i.vxlan_options.clone())
+            .or_else(|| self.config.vxlan_options.clone())
+            .unwrap_or_default()
+    }
+
+    /// Returns the name of the master of the interface as seen by the kernel
+    async fn get_interface_master(&self, iface: &VirtualInterface) -> FResult<Option<String>> {
+        match iface.net_ns {
+            Some(ns_uuid) => {
+                let ns_manager = self.get_ns_manager(&ns_uuid).await?;
+                ns_manager
+                    .get_virtual_interface_master(iface.if_name.clone())
+                    .await?
+            }
+            None => self.kernel().get_iface_master(iface.if_name.clone()).await,
+        }
+    }
+
+    async fn release_interface_master(&self, iface: &VirtualInterface) -> FResult<()> {
+        match iface.net_ns {
+            Some(ns_uuid) => {
+                let ns_manager = self.get_ns_manager(&ns_uuid).await?;
+                ns_manager
+                    .set_virtual_interface_nomaster(iface.if_name.clone())
+                    .await?
+            }
+            None => self.kernel().del_iface_master(iface.if_name.clone()).await,
+        }
+    }
+
+    /// Allocates a locally administered unicast MAC not used by other bridges
+    async fn allocate_bridge_mac(&self) -> [u8; 6] {
+        let guard = self.state.read().await;
+        loop {
+            let mut address: [u8; 6] = thread_rng().gen();
+            address[0] = (address[0] | 0x02) & 0xfe;
+            if !guard
+                .pinned_bridge_macs
+                .values()
+                .any(|pin| pin.address == address)
+            {
+                return address;
+            }
+        }
+    }
+
+    /// Sets a fixed MAC on the bridge, otherwise the kernel changes it
+    /// as ports are added and removed breaking the ARP caches of the
+    /// workloads using the bridge as gateway.
+    async fn pin_bridge_mac(&self, bridge: &mut VirtualInterface) -> FResult<()> {
+        let address = self.allocate_bridge_mac().await;
+        match bridge.net_ns {
+            Some(ns_uuid) => {
+                let ns_manager = self.get_ns_manager(&ns_uuid).await?;
+                ns_manager
+                    .set_virtual_interface_mac(bridge.if_name.clone(), address.to_vec())
+                    .await??;
+            }
+            None => {
+                self.kernel()
+                    .set_iface_mac(bridge.if_name.clone(), address.to_vec())
+                    .await?
+            }
+        }
+        bridge.phy_address = MACAddress::new(
+            address[0], address[1], address[2], address[3], address[4], address[5],
+        );
+        let mut guard = self.state.write().await;
+        guard.pinned_bridge_macs.insert(
+            bridge.uuid,
+            PinnedBridgeMAC {
+                if_name: bridge.if_name.clone(),
+                net_ns: bridge.net_ns,
+                address,
+            },
+        );
+        Ok(())
+    }
+
+    /// Restores the pinned MAC of the bridges that lost it
+    async fn reassert_bridge_macs(&self) -> FResult<()> {
+        let guard = self.state.read().await;
+        let pinned: Vec<PinnedBridgeMAC> = guard.pinned_bridge_macs.values().cloned().collect();
+        drop(guard);
+        for pin in pinned {
+            let res = match pin.net_ns {
+                // the namespace managers do not report the MAC, it is set again
+                Some(ns_uuid) => match self.get_ns_manager(&ns_uuid).await {
+                    Ok(ns_manager) => ns_manager
+                        .set_virtual_interface_mac(pin.if_name.clone(), pin.address.to_vec())
+                        .await
+                        .and_then(|r| r),
+                    Err(e) => Err(e),
+                },
+                None => match self.kernel().get_iface_mac(pin.if_name.clone()).await {
+                    Ok(current) if current == pin.address => Ok(()),
+                    Ok(current) => {
+                        log::warn!(
+                            target: logging::RECONCILE,
+                            "MAC of bridge {} changed to {:02x?}, restoring it",
+                            pin.if_name,
+                            current
+                        );
+                        self.kernel()
+                            .set_iface_mac(pin.if_name.clone(), pin.address.to_vec())
+                            .await
+                    }
+                    Err(e) => Err(e),
+                },
+            };
+            if let Err(e) = res {
+                log::warn!(
+                    target: logging::RECONCILE,
+                    "Unable to re-assert MAC of bridge {}: {}",
+                    pin.if_name,
+                    e
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the MTU available inside a VXLAN carried by `dev`,
+    /// the overhead depends on the family of the underlay
+    async fn get_vxlan_mtu(&self, dev: String, underlay: IPAddress) -> FResult<u32> {
+        let overhead = match underlay {
+            IPAddress::V4(_) => VXLAN_IPV4_OVERHEAD,
+            IPAddress::V6(_) => VXLAN_IPV6_OVERHEAD,
+        };
+        let mtu = self.kernel().get_iface_mtu(dev.clone()).await?;
+        mtu.checked_sub(overhead).ok_or_else(|| {
+            FError::NetworkingError(format!("MTU of {} is too small for VXLAN", dev))
+        })
+    }
+
+    async fn set_interface_mtu_of(&self, iface: &VirtualInterface, mtu: u32) -> FResult<()> {
+        match iface.net_ns {
+            Some(ns_uuid) => {
+                let ns_manager = self.get_ns_manager(&ns_uuid).await?;
+                ns_manager
+                    .set_virtual_interface_mtu(iface.if_name.clone(), mtu)
+                    .await?
+            }
+            None => {
+                self.kernel()
+                    .set_iface_mtu(iface.if_name.clone(), mtu)
+                    .await
+            }
+        }
+    }
+
+    async fn set_interfaces_mtu(&self, mtu: u32, ifaces: &[&VirtualInterface]) -> FResult<()> {
+        for iface in ifaces {
+            self.set_interface_mtu_of(iface, mtu).await?;
+        }
+        Ok(())
+    }
+
+    /// Returns the lock serializing the changes to a bridge and its ports
+    async fn get_bridge_lock(&self, br_uuid: Uuid) -> Arc<Mutex<()>> {
+        let mut locks = self.bridge_locks.lock().await;
+        locks
+            .entry(br_uuid)
+            .or_insert_with(|| Arc::new(Mutex::new(())))
+            .clone()
+    }
+
+    /// Adds to the pool the VFs of the SR-IOV devices present on the node
+    async fn refresh_vf_pool(&self) -> FResult<()> {
+        let pfs = sriov::list_physical_functions(self.config.sriov_pfs.as_ref()).await?;
+        let mut guard = self.state.write().await;
+        for pf in pfs {
+            guard.vf_pool.add_pf(&pf);
+        }
+        Ok(())
+    }
+
+    /// VRF links are created with iproute2 as rtnetlink does
+    /// not provide a builder for them
+    #[tracing::instrument(target = "netlink", level = "trace", skip(self))]
+    fn create_vrf(&self, iface: &str, table: u32) -> FResult<()> {
+        let mut cmd = Command::new("ip");
+        cmd.arg("link")
+            .arg("add")
+            .arg(iface)
+            .arg("type")
+            .arg("vrf")
+            .arg("table")
+            .arg(format!("{}", table));
+        exec_command(cmd)?;
+        Ok(())
+    }
+
+    async fn create_l2tp_bridge(
+        &self,
+        vnet: &mut VirtualNetwork,
+        l2tp_info: &VNetL2TP,
+        info: &L2TPv3Info,
+    ) -> FResult<()> {
+        self.create_l2tp_session(l2tp_info, info)?;
+
+        let mut v_bridge = VirtualInterface {
+            uuid: Uuid::new_v4(),
+            if_name: l2tp_info.bridge.clone(),
+            net_ns: None,
+            parent: None,
+            kind: VirtualInterfaceKind::BRIDGE(BridgeKind { childs: Vec::new() }),
+            addresses: Vec::new(),
+            phy_address: MACAddress::new(0, 0, 0, 0, 0, 0),
+        };
+        self.kernel()
+            .create_bridge(l2tp_info.bridge.clone())
+            .await?;
+        self.pin_bridge_mac(&mut v_bridge).await?;
+        self.kernel()
+            .set_iface_master(l2tp_info.if_name.clone(), l2tp_info.bridge.clone())
+            .await?;
+        self.kernel()
+            .set_iface_up(l2tp_info.if_name.clone())
+            .await?;
+        self.kernel().set_iface_up(l2tp_info.bridge.clone()).await?;
+        self.store_interface(&v_bridge).await?;
+        vnet.interfaces.push(v_bridge.uuid);
+
+        let internals = VirtualNetworkInternals {
+            l2tp: Some(l2tp_info.clone()),
+            ..Default::default()
+        };
+        vnet.plugin_internals = Some(serialize_network_internals(&internals)?);
+        self.store_virtual_network(vnet).await
+    }
+
+    #[cfg(not(feature = "qos"))]
+    fn set_iface_qos(&self, _iface: &str, _qos: &ConnectionPointQoS) -> FResult<()> {
+        Err(FError::Unimplemented)
+    }
+
+    /// Shapes the traffic sent out of the interface with a token bucket filter
+    #[cfg(feature = "qos")]
+    fn set_iface_qos(&self, iface: &str, qos: &ConnectionPointQoS) -> FResult<()> {
+        log::trace!("set_iface_qos {} {:?}", iface, qos);
+        let mut cmd = Command::new("tc");
+        cmd.arg("qdisc")
+            .arg("replace")
+            .arg("dev")
+            .arg(iface)
+            .arg("root")
+            .arg("tbf")
+            .arg("rate")
+            .arg(format!("{}kbit", qos.rate_kbit))
+            .arg("burst")
+            .arg(format!("{}kb", qos.burst_kb))
+            .arg("latency")
+            .arg(format!(
+                "{}ms",
+                qos.latency_ms.unwrap_or(DEFAULT_QOS_LATENCY_MS)
+            ));
+        exec_command(cmd)?;
+        Ok(())
+    }
+
+    /// Mirrors the frames entering the bridge of a virtual network to the
+    /// given connection points, the mirror filters of every port of the
+    /// bridge are replaced. Ports joining later are handled on bind.
+    async fn apply_mirrors(&self, vnet: &VirtualNetwork, mirrors: &[VNetMirror]) -> FResult<()> {
+        let br_uuid = self.get_virtual_network_bridge(vnet).await?;
+        let bridge = self.connector.local.get_interface(br_uuid).await?;
+        let ports = std::fs::read_dir(format!("/sys/class/net/{}/brif", bridge.if_name))?;
+        for port in ports {
+            let port = port?.file_name().to_string_lossy().to_string();
+            self.set_iface_mirrors(&port, mirrors)?;
+        }
+        Ok(())
+    }
+
+    #[cfg(not(feature = "qos"))]
+    fn set_iface_mirrors(&self, iface: &str, mirrors: &[VNetMirror]) -> FResult<()> {
+        if mirrors.iter().all(|m| m.iface == iface) {
+            return Ok(());
+        }
+        Err(FError::Unimplemented)
+    }
+
+    /// Replaces the mirror filters on the ingress of `iface` with one
+    /// mirred action per mirror, the clsact qdisc and the other filters
+    /// are kept
+    #[cfg(feature = "qos")]
+    fn set_iface_mirrors(&self, iface: &str, mirrors: &[VNetMirror]) -> FResult<()> {
+        log::trace!("set_iface_mirrors {} {:?}", iface, mirrors);
+        let mut cmd = Command::new("tc");
+        cmd.arg("filter")
+            .arg("del")
+            .arg("dev")
+            .arg(iface)
+            .arg("ingress")
+            .arg("pref")
+            .arg(MIRROR_FILTER_PREF.to_string());
+        // fails when there is no clsact qdisc or no mirror filter yet
+        let _ = exec_command(cmd);
+        let targets: Vec<&VNetMirror> = mirrors.iter().filter(|m| m.iface != iface).collect();
+        if targets.is_empty() {
+            return Ok(());
+        }
+        let mut cmd = Command::new("tc");
+        cmd.arg("qdisc")
+            .arg("replace")
+            .arg("dev")
+            .arg(iface)
+            .arg("clsact");
+        exec_command(cmd)?;
+        for mirror in targets {
+            let mut cmd = Command::new("tc");
+            cmd.arg("filter")
+                .arg("add")
+                .arg("dev")
+                .arg(iface)
+                .arg("ingress")
+                .arg("pref")
+                .arg(MIRROR_FILTER_PREF.to_string())
+                .arg("matchall")
+                .arg("action")
+                .arg("mirred")
+                .arg("egress")
+                .arg("mirror")
+                .arg("dev")
+                .arg(&mirror.iface);
+            exec_command(cmd)?;
+        }
+        Ok(())
+    }
+
+    fn set_bridge_fdb_options(&self, br_name: &str, options: &FDBOptions) -> FResult<()> {
+        log::trace!("set_bridge_fdb_options {} {:?}", br_name, options);
+        if options == &FDBOptions::default() {
+            return Ok(());
+        }
+        let mut cmd = Command::new("ip");
+        cmd.arg("link")
+            .arg("set")
+            .arg("dev")
+            .arg(br_name)
+            .arg("type")
+            .arg("bridge");
+        if let Some(max_learned) = options.max_learned {
+            cmd.arg("fdb_max_learned").arg(format!("{}", max_learned));
+        }
+        if let Some(ageing_time) = options.ageing_time_s {
+            // in hundredths of a second
+            cmd.arg("ageing_time")
+                .arg(format!("{}", u64::from(ageing_time) * 100));
+        }
+        exec_command(cmd)?;
+        Ok(())
+    }
+
+    #[cfg(not(feature = "qos"))]
+    fn del_iface_qos(&self, _iface: &str) -> FResult<()> {
+        Ok(())
+    }
+
+    #[cfg(feature = "qos")]
+    fn del_iface_qos(&self, iface: &str) -> FResult<()> {
+        log::trace!("del_iface_qos {}", iface);
+        let mut cmd = Command::new("tc");
+        cmd.arg("qdisc")
+            .arg("del")
+            .arg("dev")
+            .arg(iface)
+            .arg("root");
+        exec_command(cmd)?;
+        Ok(())
+    }
+
+    /// Drops the packets sent by a connection point over its limits, in a
+    /// netdev table hooked to the ingress of its external veth. tc is not
+    /// used as the ingress filters of the veth belong to the mirrors.
+    fn set_iface_rate_limit(
+        &self,
+        cp_uuid: Uuid,
+        iface: &str,
+        limit: &ConnectionPointRateLimit,
+    ) -> FResult<()> {
+        let script = render_rate_limit(&rate_limit_table(cp_uuid), iface, limit);
+        log::trace!(target: logging::NFT, "set_iface_rate_limit: {}", script);
+        let mut cmd = Command::new("nft");
+        cmd.arg(script);
+        exec_command(cmd)?;
+        Ok(())
+    }
+
+    fn del_iface_rate_limit(&self, cp_uuid: Uuid) -> FResult<()> {
+        let table = rate_limit_table(cp_uuid);
+        log::trace!(target: logging::NFT, "del_iface_rate_limit {}", table);
+        let mut cmd = Command::new("nft");
+        cmd.arg("delete").arg("table").arg("netdev").arg(table);
+        exec_command(cmd)?;
+        Ok(())
+    }
+
+    fn is_anti_spoofing_enabled(&self) -> bool {
+        self.config.anti_spoofing.unwrap_or(true)
+    }
+
+    /// Drops what an interface bound to a connection point sends from
+    /// another MAC or address than its own, in a netdev table of the
+    /// namespace of the connection point hooked to the ingress of the
+    /// interface
+    fn set_iface_anti_spoofing(&self, ns_name: &str, iface: &VirtualInterface) -> FResult<()> {
+        let script = render_anti_spoofing(&anti_spoofing_table(iface.uuid), iface);
+        log::trace!(target: logging::NFT, "set_iface_anti_spoofing: {}", script);
+        let mut cmd = netns_command(ns_name, "nft");
+        cmd.arg(script);
+        exec_command(cmd)?;
+        Ok(())
+    }
+
+    /// Renders again the rules of an interface bound to a connection point
+    /// once its addresses changed, they would otherwise drop what it sends
+    /// from the new ones
+    async fn refresh_iface_anti_spoofing(&self, iface: &VirtualInterface) -> FResult<()> {
+        if !self.is_anti_spoofing_enabled() {
+            return Ok(());
+        }
+        let (ns_uuid, bridge) = match (iface.net_ns, iface.parent) {
+            (Some(ns_uuid), Some(bridge)) => (ns_uuid, bridge),
+            _ => return Ok(()),
+        };
+        let bound = self
+            .list_connection_points()
+            .await?
+            .iter()
+            .any(|cp| cp.net_ns == ns_uuid && cp.bridge == bridge);
+        if !bound {
+            return Ok(());
+        }
+        let netns = self.connector.local.get_network_namespace(ns_uuid).await?;
+        self.set_iface_anti_spoofing(&netns.ns_name, iface)
+    }
+
+    fn del_iface_anti_spoofing(&self, ns_name: &str, intf_uuid: Uuid) -> FResult<()> {
+        let table = anti_spoofing_table(intf_uuid);
+        log::trace!(target: logging::NFT, "del_iface_anti_spoofing {}", table);
+        let mut cmd = netns_command(ns_name, "nft");
+        cmd.arg("delete").arg("table").arg("netdev").arg(table);
+        exec_command(cmd)?;
+        Ok(())
+    }
+
+    /// L2TPv3 tunnels and sessions are managed with iproute2 as rtnetlink
+    /// does not support the L2TP generic netlink family
+    fn create_l2tp_session(&self, l2tp_info: &VNetL2TP, info: &L2TPv3Info) -> FResult<()> {
+        log::trace!("create_l2tp_session {:?} {:?}", l2tp_info, info);
+        let mut cmd = Command::new("ip");
+        cmd.arg("l2tp")
+            .arg("add")
+            .arg("tunnel")
+            .arg("tunnel_id")
+            .arg(format!("{}", l2tp_info.tunnel_id))
+            .arg("peer_tunnel_id")
+            .arg(format!("{}", l2tp_info.peer_tunnel_id))
+            .arg("encap")
+            .arg(format!("{}", info.encap))
+            .arg("local")
+            .arg(format!("{}", info.local_addr))
+            .arg("remote")
+            .arg(format!("{}", info.remote_addr));
+        if let Some(port) = info.udp_sport {
+            cmd.arg("udp_sport").arg(format!("{}", port));
+        }
+        if let Some(port) = info.udp_dport {
+            cmd.arg("udp_dport").arg(format!("{}", port));
+        }
+        exec_command(cmd)?;
+
+        let mut cmd = Command::new("ip");
+        cmd.arg("l2tp")
+            .arg("add")
+            .arg("session")
+            .arg("name")
+            .arg(&l2tp_info.if_name)
+            .arg("tunnel_id")
+            .arg(format!("{}", l2tp_info.tunnel_id))
+            .arg("session_id")
+            .arg(format!("{}", l2tp_info.session_id))
+            .arg("peer_session_id")
+            .arg(format!("{}", l2tp_info.peer_session_id));
+        if let Err(e) = exec_command(cmd) {
+            let mut cmd = Command::new("ip");
+            cmd.arg("l2tp")
+                .arg("del")
+                .arg("tunnel")
+                .arg("tunnel_id")
+                .arg(format!("{}", l2tp_info.tunnel_id));
+            exec_command(cmd)?;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Removes the session and then its tunnel
+    fn delete_l2tp_session(&self, l2tp_info: &VNetL2TP) -> FResult<()> {
+        log::trace!("delete_l2tp_session {:?}", l2tp_info);
+        let mut cmd = Command::new("ip");
+        cmd.arg("l2tp")
+            .arg("del")
+            .arg("session")
+            .arg("tunnel_id")
+            .arg(format!("{}", l2tp_info.tunnel_id))
+            .arg("session_id")
+            .arg(format!("{}", l2tp_info.session_id));
+        exec_command(cmd)?;
+        let mut cmd = Command::new("ip");
+        cmd.arg("l2tp")
+            .arg("del")
+            .arg("tunnel")
+            .arg("tunnel_id")
+            .arg(format!("{}", l2tp_info.tunnel_id));
+        exec_command(cmd)?;
+        Ok(())
+    }
+
+    /// Starts the DHCP server of a virtual network inside its namespace,
+    /// serving on `br_name` with the backend configured for the node, or
+    /// the relay when the network sets `dhcp_relay`. The range is the one
+    /// in the IP configuration or, if enabled, one derived from the subnet.
+    async fn start_vnet_dhcp(
+        &self,
+        vnet: &VirtualNetwork,
+        requested: Option<&VirtualNetworkInternals>,
+        netns: &NetworkNamespace,
+        br_name: &str,
+    ) -> FResult<Option<VNetDHCP>> {
+        if !cfg!(feature = "dhcp") {
+            return Ok(None);
+        }
+        let conf = match &vnet.ip_configuration {
+            Some(conf) => conf,
+            None => return Ok(None),
+        };
+        let secondary = requested.and_then(|i| i.secondary_ip_configuration.as_ref());
+        check_ip_configurations(vnet, conf, secondary)?;
+        if let Some(requested) = requested {
+            validate_dhcp_reservations(vnet, requested)?;
+        }
+
+        let relay = requested.and_then(|i| i.dhcp_relay);
+        // with IPAM every node serves its own slice of the subnet
+        let mut primary = conf.clone();
+        if relay.is_none() && requested.and_then(|i| i.ipam.as_ref()).is_some() {
+            primary.dhcp_range = Some(self.reserve_ipam_range(vnet.uuid).await?);
+        }
+        let mut ranges = Vec::new();
+        if relay.is_some() {
+            // addresses are managed by the central server
+            ranges.extend(get_relay_range(conf));
+        } else {
+            for conf in std::iter::once(&primary).chain(secondary) {
+                if let Some(range) = self.get_dnsmasq_range(vnet, requested, conf) {
+                    ranges.push(range);
+                }
+            }
+        }
+        if ranges.is_empty() {
+            return Ok(None);
+        }
+
+        // dnsmasq needs an address in the subnet on the interface it serves
+        let ns_manager = self.get_ns_manager(&netns.uuid).await?;
+        let mut routers = Vec::new();
+        for range in &ranges {
+            let router = IpNetwork::new(range.gateway, range.prefix)
+                .map_err(|e| FError::NetworkingError(format!("{}", e)))?;
+            ns_manager
+                .add_virtual_interface_address(br_name.to_string(), Some(router))
+                .await??;
+            routers.push(router);
+        }
+        let files_prefix = self
+            .get_run_path()
+            .join(vnet.uuid.to_string())
+            .to_str()
+            .ok_or(FError::EncodingError)?
+            .to_string();
+        let service = DHCPService {
+            vnet_uuid: vnet.uuid,
+            ns_name: Some(&netns.ns_name),
+            iface: br_name,
+            ranges: &ranges,
+            files_prefix: &files_prefix,
+            internals: requested,
+        };
+        let kind = match relay {
+            Some(_) => DHCPBackend::RELAY,
+            None => self.get_dhcp_backend(),
+        };
+        let mut dhcp_internal = self.dhcp_backend(kind).start(&service).await?;
+        dhcp_internal.routers = routers;
+        Ok(Some(dhcp_internal))
+    }
+
+    /// Returns the range served for one IP configuration of a virtual
+    /// network, the one in the configuration or, if enabled, one derived
+    /// from the subnet. `None` when there is nothing to serve.
+    fn get_dnsmasq_range(
+        &self,
+        vnet: &VirtualNetwork,
+        requested: Option<&VirtualNetworkInternals>,
+        conf: &IPConfiguration,
+    ) -> Option<DNSMasqRange> {
+        let (net_addr, prefix) = conf.subnet?;
+        let auto = requested
+            .and_then(|i| i.auto_dhcp_range)
+            .or(self.config.auto_dhcp_range)
+            .unwrap_or(false);
+        let reserved = self
+            .config
+            .dhcp_reserved_window
+            .unwrap_or(DEFAULT_DHCP_RESERVED_WINDOW);
+        let (start, end) = match conf.dhcp_range {
+            Some(range) => range,
+            None if auto => match derive_dhcp_range((net_addr, prefix), conf.gateway, reserved) {
+                Some(range) => range,
+                None => {
+                    log::warn!(
+                        target: logging::DHCP,
+                        "Unable to derive a DHCP range for {}",
+                        vnet.uuid
+                    );
+                    return None;
+                }
+            },
+            None => return None,
+        };
+        log::debug!(target: logging::DHCP, "DHCP range for {}: {} - {}", vnet.uuid, start, end);
+
+        let gateway = match conf.gateway {
+            Some(gw) => gw,
+            None => derive_dhcp_range((net_addr, prefix), None, 0)?.0,
+        };
+        let v6 = net_addr.is_ipv6();
+        // with the caching resolver or DNS64 clients use the one on the bridge
+        let dns = if self.is_dns_cache_enabled(requested) || self.is_nat64_enabled(requested) {
+            Some(gateway)
+        } else {
+            conf.dns
+                .as_ref()
+                .and_then(|dns| dns.iter().find(|d| d.is_ipv6() == v6).copied())
+                .or_else(|| {
+                    if v6 {
+                        None
+                    } else {
+                        Some(IPAddress::V4(std::net::Ipv4Addr::new(208, 67, 222, 222)))
+                    }
+                })
+        };
+        Some(DNSMasqRange {
+            start,
+            end,
+            prefix,
+            gateway,
+            dns,
+            v6,
+        })
+    }
+
+    fn is_dns_cache_enabled(&self, requested: Option<&VirtualNetworkInternals>) -> bool {
+        // the resolver is a plain dnsmasq, not modelled by the simulation
+        !simulation::is_enabled()
+            && requested
+                .and_then(|i| i.dns_cache)
+                .or(self.config.dns_cache)
+                .unwrap_or(false)
+    }
+
+    #[cfg(not(feature = "dhcp"))]
+    async fn start_vnet_resolver(
+        &self,
+        _vnet: &VirtualNetwork,
+        _netns: &NetworkNamespace,
+        _br_name: &str,
+        _dhcp_internal: &VNetDHCP,
+        _records: &[DNSRecord],
+    ) -> FResult<VNetResolver> {
+        Err(FError::Unimplemented)
+    }
+
+    /// Spawns a caching dnsmasq, without DHCP, in the namespace of a
+    /// virtual network, listening on the address of the DHCP server.
+    #[cfg(feature = "dhcp")]
+    async fn start_vnet_resolver(
+        &self,
+        vnet: &VirtualNetwork,
+        netns: &NetworkNamespace,
+        br_name: &str,
+        dhcp_internal: &VNetDHCP,
+        records: &[DNSRecord],
+    ) -> FResult<VNetResolver> {
+        let listen_addr = dhcp_internal.router.ok_or(FError::NotFound)?;
+        for record in records {
+            validate_dns_name(&record.name)?;
+        }
+        let requested = vnet
+            .plugin_internals
+            .as_ref()
+            .and_then(|raw| deserialize_network_internals(raw).ok());
+        let file_path = |ext: &str| -> FResult<String> {
+            Ok(self
+                .get_run_path()
+                .join(format!("{}-dns.{}", vnet.uuid, ext))
+                .to_str()
+                .ok_or(FError::EncodingError)?
+                .to_string())
+        };
+        let resolver = VNetResolver {
+            pid_file: file_path("pid")?,
+            conf: file_path("conf")?,
+            log_file: file_path("log")?,
+            hosts_file: file_path("hosts")?,
+            servers_file: Some(file_path("servers")?),
+        };
+
+        let mut context = Context::new();
+        context.insert("dns_interface", br_name);
+        context.insert("listen_addr", &format!("{}", listen_addr));
+        context.insert("servers_file", &resolver.servers_file);
+        // the DNS64 proxy answers on port 53 and forwards to the resolver
+        let dns_port = if self.is_nat64_enabled(requested.as_ref()) {
+            NAT64_RESOLVER_PORT
+        } else {
+            dns64::DNS_PORT
+        };
+        context.insert("dns_port", &dns_port);
+        context.insert(
+            "cache_size",
+            &self.config.dns_cache_size.unwrap_or(DEFAULT_DNS_CACHE_SIZE),
+        );
+        context.insert("hosts_file", &resolver.hosts_file);
+        context.insert("dns_pid", &resolver.pid_file);
+        context.insert("dns_log", &resolver.log_file);
+        let config = render_template(&self.config.path, "resolver.conf", &context)?;
+        log::trace!("resolver config: {}", config);
+        self.os
+            .as_ref()
+            .unwrap()
+            .store_file(config.into_bytes(), resolver.conf.clone())
+            .await??;
+        self.os
+            .as_ref()
+            .unwrap()
+            .store_file(
+                render_hosts(records).into_bytes(),
+                resolver.hosts_file.clone(),
+            )
+            .await??;
+        self.write_resolver_servers(&resolver, &self.resolver_upstream(vnet, requested.as_ref()))
+            .await?;
+        let child = spawn_dnsmasq(Some(&netns.ns_name), &resolver.conf)?;
+        log::debug!("Resolver for {} running PID: {}", vnet.uuid, child.id());
+        self.state
+            .write()
+            .await
+            .resolvers
+            .insert(vnet.uuid, (netns.ns_name.clone(), resolver.clone()));
+        Ok(resolver)
+    }
+
+    /// Rewrites the hosts file of the resolver with the local records
+    /// and the ones of the other nodes, dnsmasq re-reads it on SIGHUP
+    async fn reload_resolver_records(
+        &self,
+        vnet_uuid: Uuid,
+        internals: &VirtualNetworkInternals,
+    ) -> FResult<()> {
+        if let Some(resolver) = &internals.resolver {
+            let mut records = internals.dns_records.clone();
+            let guard = self.state.read().await;
+            if let Some(remote) = guard.remote_dns_records.get(&vnet_uuid) {
+                // local names take precedence
+                // the names come from other nodes, invalid ones are skipped
+                records.extend(
+                    remote
+                        .values()
+                        .flatten()
+                        .filter(|r| !internals.dns_records.iter().any(|l| l.name == r.name))
+                        .filter(|r| validate_dns_name(&r.name).is_ok())
+                        .cloned(),
+                );
+            }
+            drop(guard);
+            self.os
+                .as_ref()
+                .unwrap()
+                .store_file(
+                    render_hosts(&records).into_bytes(),
+                    resolver.hosts_file.clone(),
+                )
+                .await??;
+            let pid = read_pid_file(self.os.as_ref().unwrap(), &resolver.pid_file).await?;
+            kill(Pid::from_raw(pid), Signal::SIGHUP)
+                .map_err(|e| FError::NetworkingError(format!("{}", e)))?;
+        }
+        Ok(())
+    }
+
+    /// Servers the resolver of a network forwards to: the ones of the
+    /// network internals, then `dns_upstream` of the configuration, then
+    /// the DNS servers of the network
+    fn resolver_upstream(
+        &self,
+        vnet: &VirtualNetwork,
+        internals: Option<&VirtualNetworkInternals>,
+    ) -> Vec<IPAddress> {
+        match internals.map(|i| &i.dns_upstream) {
+            Some(upstream) if !upstream.is_empty() => upstream.clone(),
+            _ => self
+                .config
+                .dns_upstream
+                .clone()
+                .filter(|upstream| !upstream.is_empty())
+                .or_else(|| {
+                    vnet.ip_configuration
+                        .as_ref()
+                        .and_then(|conf| conf.dns.clone())
+                })
+                .unwrap_or_else(|| vec![IPAddress::V4(std::net::Ipv4Addr::new(208, 67, 222, 222))]),
+        }
+    }
+
+    async fn write_resolver_servers(
+        &self,
+        resolver: &VNetResolver,
+        upstream: &[IPAddress],
+    ) -> FResult<()> {
+        let servers_file = resolver
+            .servers_file
+            .as_ref()
+            .ok_or(FError::Unimplemented)?;
+        let servers: String = upstream
+            .iter()
+            .map(|addr| format!("server={}\n", addr))
+            .collect();
+        self.os
+            .as_ref()
+            .unwrap()
+            .store_file(servers.into_bytes(), servers_file.clone())
+            .await??;
+        Ok(())
+    }
+
+    /// Rewrites the upstream servers of the resolver of a network,
+    /// dnsmasq re-reads them on SIGHUP. Resolvers started before the
+    /// servers file existed keep their servers until restarted.
+    async fn reload_resolver_upstream(
+        &self,
+        vnet: &VirtualNetwork,
+        internals: &VirtualNetworkInternals,
+    ) -> FResult<()> {
+        if let Some(resolver) = &internals.resolver {
+            self.write_resolver_servers(resolver, &self.resolver_upstream(vnet, Some(internals)))
+                .await?;
+            let pid = read_pid_file(self.os.as_ref().unwrap(), &resolver.pid_file).await?;
+            kill(Pid::from_raw(pid), Signal::SIGHUP)
+                .map_err(|e| FError::NetworkingError(format!("{}", e)))?;
+        }
+        Ok(())
+    }
+
+    async fn stop_resolver(&self, resolver: VNetResolver) -> FResult<()> {
+        let pid = read_pid_file(self.os.as_ref().unwrap(), &resolver.pid_file).await?;
+        log::trace!("Killing resolver {}", pid);
+        kill(Pid::from_raw(pid), Signal::SIGKILL)
+            .map_err(|e| FError::NetworkingError(format!("{}", e)))?;
+        async_std::fs::remove_file(async_std::path::Path::new(&resolver.pid_file)).await?;
+        async_std::fs::remove_file(async_std::path::Path::new(&resolver.conf)).await?;
+        async_std::fs::remove_file(async_std::path::Path::new(&resolver.log_file)).await?;
+        async_std::fs::remove_file(async_std::path::Path::new(&resolver.hosts_file)).await?;
+        if let Some(servers_file) = &resolver.servers_file {
+            async_std::fs::remove_file(async_std::path::Path::new(servers_file)).await?;
+        }
+        Ok(())
+    }
+
+    fn is_nat64_enabled(&self, requested: Option<&VirtualNetworkInternals>) -> bool {
+        requested.and_then(|i| i.nat64.as_ref()).is_some()
+    }
+
+    /// Refuses the networks requesting NAT64 when simulating, tayga and
+    /// the DNS64 proxy are not modelled by the simulation
+    fn check_nat64_supported(&self, requested: Option<&VirtualNetworkInternals>) -> FResult<()> {
+        if simulation::is_enabled() && self.is_nat64_enabled(requested) {
+            return Err(FError::NetworkingError(
+                "NAT64 is not supported by the simulation".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Starts the NAT64 gateway of an IPv6-only virtual network in its
+    /// namespace, then the DNS64 proxy on the bridge. The translated
+    /// traffic leaves through the IPv4 default route of the namespace.
+    async fn start_vnet_nat64(
+        &self,
+        vnet: &VirtualNetwork,
+        requested: &VirtualNetworkInternals,
+        netns: &NetworkNamespace,
+        br_name: &str,
+        dhcp_internal: &VNetDHCP,
+        resolver: Option<&VNetResolver>,
+    ) -> FResult<VNetNAT64> {
+        let options = requested.nat64.clone().unwrap_or_default();
+        let (net_addr, net_prefix) = vnet
+            .ip_configuration
+            .as_ref()
+            .and_then(|c| c.subnet)
+            .ok_or(FError::NotFound)?;
+        if !net_addr.is_ipv6() || requested.secondary_ip_configuration.is_some() {
+            return Err(FError::NetworkingError(format!(
+                "NAT64 needs an IPv6-only network, {} is not",
+                vnet.uuid
+            )));
+        }
+        let prefix = options.prefix.unwrap_or((
+            IPAddress::V6(std::net::Ipv6Addr::new(0x64, 0xff9b, 0, 0, 0, 0, 0, 0)),
+            96,
+        ));
+        if !prefix.0.is_ipv6() || prefix.1 != 96 {
+            return Err(FError::NetworkingError(format!(
+                "Unsupported NAT64 prefix {}/{}, only /96 ones are",
+                prefix.0, prefix.1
+            )));
+        }
+        let pool = options
+            .pool
+            .unwrap_or((IPAddress::V4(std::net::Ipv4Addr::new(192, 168, 255, 0)), 24));
+        if !pool.0.is_ipv4() {
+            return Err(FError::NetworkingError(format!(
+                "NAT64 pool {}/{} is not IPv4",
+                pool.0, pool.1
+            )));
+        }
+        let host_mask = u128::MAX.checked_shr(u32::from(net_prefix)).unwrap_or(0);
+        let ipv6_addr = match net_addr {
+            IPAddress::V6(addr) => {
+                IPAddress::V6(std::net::Ipv6Addr::from(u128::from(addr) | host_mask))
+            }
+            IPAddress::V4(_) => return Err(FError::EncodingError),
+        };
+        let file_path = |ext: &str| -> FResult<String> {
+            Ok(self
+                .get_run_path()
+                .join(format!("{}-nat64.{}", vnet.uuid, ext))
+                .to_str()
+                .ok_or(FError::EncodingError)?
+                .to_string())
+        };
+        let nat64 = VNetNAT64 {
+            tun: format!("nat64-{}", self.generate_random_interface_name()),
+            prefix,
+            pool,
+            ipv6_addr,
+            conf: file_path("conf")?,
+            pid_file: file_path("pid")?,
+            data_dir: file_path("d")?,
+            nft_table: self.generate_random_nft_table_name(),
+        };
+        async_std::fs::create_dir_all(&nat64.data_dir).await?;
+        let config = format!(
+            "tun-device {}\nipv4-addr {}\nipv6-addr {}\nprefix {}/{}\ndynamic-pool {}/{}\ndata-dir {}\n",
+            nat64.tun,
+            ipam::subnet_gateway(pool)?,
+            nat64.ipv6_addr,
+            prefix.0,
+            prefix.1,
+            pool.0,
+            pool.1,
+            nat64.data_dir
+        );
+        log::trace!("tayga config: {}", config);
+        self.os
+            .as_ref()
+            .unwrap()
+            .store_file(config.into_bytes(), nat64.conf.clone())
+            .await??;
+        self.run_tayga(&netns.ns_name, &nat64)?;
+
+        let router = dhcp_internal.router.ok_or(FError::NotFound)?;
+        let upstream = self.dns64_upstream(vnet, Some(requested), resolver.map(|_| router))?;
+        self.start_dns64_proxy(vnet.uuid, &netns.ns_name, br_name, &nat64, upstream)
+            .await?;
+        Ok(nat64)
+    }
+
+    /// Creates the tun device of tayga with its routes and the
+    /// masquerade of the pool, then spawns tayga. Everything is replaced
+    /// if already there, so it is run again when tayga is not running.
+    fn run_tayga(&self, ns_name: &str, nat64: &VNetNAT64) -> FResult<()> {
+        let mut cmd = netns_command(ns_name, "sysctl");
+        cmd.arg("-w")
+            .arg("net/ipv4/ip_forward=1")
+            .arg("net/ipv6/conf/all/forwarding=1");
+        exec_command(cmd)?;
+        let mut cmd = netns_command(ns_name, "tayga");
+        cmd.arg("-c").arg(&nat64.conf).arg("--mktun");
+        exec_command(cmd)?;
+        let mut cmd = netns_command(ns_name, "ip");
+        cmd.arg("link").arg("set").arg(&nat64.tun).arg("up");
+        exec_command(cmd)?;
+        let routes = [
+            format!("{}/{}", nat64.pool.0, nat64.pool.1),
+            format!("{}/{}", nat64.prefix.0, nat64.prefix.1),
+            format!("{}/128", nat64.ipv6_addr),
+        ];
+        for route in &routes {
+            let mut cmd = netns_command(ns_name, "ip");
+            cmd.arg("route")
+                .arg("replace")
+                .arg(route)
+                .arg("dev")
+                .arg(&nat64.tun);
+            exec_command(cmd)?;
+        }
+        let script = render_nat64_masquerade(&nat64.nft_table, &nat64.tun, nat64.pool);
+        log::trace!(target: logging::NFT, "run_tayga: {}", script);
+        let mut cmd = netns_command(ns_name, "nft");
+        cmd.arg(script);
+        exec_command(cmd)?;
+        let mut cmd = netns_command(ns_name, "tayga");
+        cmd.arg("-c")
+            .arg(&nat64.conf)
+            .arg("-p")
+            .arg(&nat64.pid_file);
+        exec_command(cmd)?;
+        Ok(())
+    }
+
+    /// Server the DNS64 proxy forwards to: the resolver of the network
+    /// listening on `resolver_addr` if there is one, otherwise its first
+    /// upstream server
+    fn dns64_upstream(
+        &self,
+        vnet: &VirtualNetwork,
+        internals: Option<&VirtualNetworkInternals>,
+        resolver_addr: Option<IPAddress>,
+    ) -> FResult<std::net::SocketAddr> {
+        match resolver_addr {
+            Some(addr) => Ok(std::net::SocketAddr::new(addr, NAT64_RESOLVER_PORT)),
+            None => self
+                .resolver_upstream(vnet, internals)
+                .first()
+                .map(|addr| std::net::SocketAddr::new(*addr, dns64::DNS_PORT))
+                .ok_or(FError::NotFound),
+        }
+    }
+
+    async fn start_dns64_proxy(
+        &self,
+        vnet_uuid: Uuid,
+        ns_name: &str,
+        br_name: &str,
+        nat64: &VNetNAT64,
+        upstream: std::net::SocketAddr,
+    ) -> FResult<()> {
+        let prefix = match nat64.prefix.0 {
+            IPAddress::V6(prefix) => prefix,
+            IPAddress::V4(_) => return Err(FError::EncodingError),
+        };
+        let handle = dns64::start(
+            Some(ns_name.to_string()),
+            br_name.to_string(),
+            prefix,
+            upstream,
+        )
+        .await?;
+        log::debug!("DNS64 proxy for {} forwarding to {}", vnet_uuid, upstream);
+        let previous = self
+            .state
+            .write()
+            .await
+            .dns64_proxies
+            .insert(vnet_uuid, handle);
+        if let Some(previous) = previous {
+            previous.stop().await;
+        }
+        Ok(())
+    }
+
+    /// Starts again the DNS64 proxy of a NAT64 network, and tayga if it
+    /// is not running anymore, after a restart of the plugin
+    async fn adopt_vnet_nat64(
+        &self,
+        vnet: &VirtualNetwork,
+        internals: &VirtualNetworkInternals,
+        nat64: &VNetNAT64,
+        ns_name: &str,
+        br_name: &str,
+    ) -> FResult<()> {
+        let running = match read_pid_file(self.os.as_ref().unwrap(), &nat64.pid_file).await {
+            Ok(pid) => kill(Pid::from_raw(pid), None).is_ok(),
+            Err(_) => false,
+        };
+        if !running {
+            log::info!(
+                "NAT64 gateway of {} not running, starting it again",
+                vnet.uuid
+            );
+            self.run_tayga(ns_name, nat64)?;
+        }
+        let router = internals
+            .dhcp
+            .as_ref()
+            .and_then(|dhcp| dhcp.router)
+            .ok_or(FError::NotFound)?;
+        let upstream = self.dns64_upstream(
+            vnet,
+            Some(internals),
+            internals.resolver.as_ref().map(|_| router),
+        )?;
+        self.start_dns64_proxy(vnet.uuid, ns_name, br_name, nat64, upstream)
+            .await
+    }
+
+    #[cfg(not(feature = "flow-export"))]
+    async fn start_flow_export(&self, _flow_export: &VNetFlowExport) -> FResult<()> {
+        Err(FError::Unimplemented)
+    }
+
+    /// Runs softflowd on the bridge of a network, it daemonizes
+    #[cfg(feature = "flow-export")]
+    async fn start_flow_export(&self, flow_export: &VNetFlowExport) -> FResult<()> {
+        let mut cmd = Command::new("softflowd");
+        cmd.arg("-i")
+            .arg(&flow_export.bridge)
+            .arg("-n")
+            .arg(&flow_export.collector)
+            .arg("-v")
+            .arg("9")
+            .arg("-s")
+            .arg(flow_export.sampling_rate.to_string())
+            .arg("-p")
+            .arg(&flow_export.pid_file)
+            .arg("-c")
+            .arg(&flow_export.ctl_socket);
+        // recorded by the simulated kernel, not spawned
+        if simulation::is_enabled() {
+            let argv = std::iter::once(cmd.get_program())
+                .chain(cmd.get_args())
+                .map(|a| a.to_string_lossy().to_string())
+                .collect();
+            simulation::spawn(None, argv)?;
+            return Ok(());
+        }
+        exec_command(cmd)?;
+        log::info!(
+            "Exporting the flows of {} to {}",
+            flow_export.bridge,
+            flow_export.collector
+        );
+        Ok(())
+    }
+
+    #[cfg(not(feature = "flow-export"))]
+    async fn stop_flow_export(&self, _flow_export: &VNetFlowExport) -> FResult<()> {
+        Ok(())
+    }
+
+    /// Stops softflowd, the pending flows are sent on SIGTERM. Best effort,
+    /// softflowd may already be gone.
+    #[cfg(feature = "flow-export")]
+    async fn stop_flow_export(&self, flow_export: &VNetFlowExport) -> FResult<()> {
+        // processes of the simulated kernel are not signalled
+        if simulation::is_enabled() {
+            return Ok(());
+        }
+        match read_pid_file(self.os.as_ref().unwrap(), &flow_export.pid_file).await {
+            Ok(pid) => {
+                log::trace!("Killing softflowd {}", pid);
+                if let Err(e) = kill(Pid::from_raw(pid), Signal::SIGTERM) {
+                    log::warn!(
+                        "Unable to kill softflowd {} of {}: {}",
+                        pid,
+                        flow_export.bridge,
+                        e
+                    );
+                }
+            }
+            Err(e) => log::warn!(
+                "Unable to read the softflowd pid of {}: {}",
+                flow_export.bridge,
+                e
+            ),
+        }
+        if let Err(e) =
+            async_std::fs::remove_file(async_std::path::Path::new(&flow_export.pid_file)).await
+        {
+            log::warn!("Unable to remove {}: {}", flow_export.pid_file, e);
+        }
+        Ok(())
+    }
+
+    /// Stops the DNS64 proxy and tayga, the tun device and the
+    /// masquerade go away with the namespace
+    async fn stop_vnet_nat64(&self, vnet_uuid: Uuid, nat64: VNetNAT64) -> FResult<()> {
+        let proxy = self.state.write().await.dns64_proxies.remove(&vnet_uuid);
+        if let Some(proxy) = proxy {
+            proxy.stop().await;
+        }
+        // best effort, tayga may already be gone
+        match read_pid_file(self.os.as_ref().unwrap(), &nat64.pid_file).await {
+            Ok(pid) => {
+                log::trace!("Killing tayga {}", pid);
+                if let Err(e) = kill(Pid::from_raw(pid), Signal::SIGTERM) {
+                    log::warn!("Unable to kill tayga {} of {}: {}", pid, vnet_uuid, e);
+                }
+            }
+            Err(e) => log::warn!("Unable to read the tayga pid of {}: {}", vnet_uuid, e),
+        }
+        for file in &[&nat64.pid_file, &nat64.conf] {
+            if let Err(e) = async_std::fs::remove_file(async_std::path::Path::new(file)).await {
+                log::warn!("Unable to remove {}: {}", file, e);
+            }
+        }
+        if let Err(e) =
+            async_std::fs::remove_dir_all(async_std::path::Path::new(&nat64.data_dir)).await
+        {
+            log::warn!("Unable to remove {}: {}", nat64.data_dir, e);
+        }
+        Ok(())
+    }
+
+    /// Collects the counters of the links of the default namespace and of
+    /// the managed ones, with one dump per namespace instead of one query
+    /// per interface. The result is shared by the stats consumers until
+    /// the next monitoring cycle.
+    async fn collect_link_stats(&self) {
+        let namespaces: Vec<Uuid> = self
+            .state
+            .read()
+            .await
+            .known_namespaces
+            .iter()
+            .copied()
+            .collect();
+        let mut link_stats = HashMap::new();
+        for net_ns in std::iter::once(None).chain(namespaces.into_iter().map(Some)) {
+            match self.dump_namespace_link_stats(net_ns).await {
+                Ok(dump) => {
+                    link_stats.insert(net_ns, dump);
+                }
+                Err(e) => {
+                    log::warn!(
+                        target: logging::RECONCILE,
+                        "Unable to collect link stats of {:?}: {}",
+                        net_ns,
+                        e
+                    )
+                }
+            }
+        }
+        self.state.write().await.link_stats = link_stats;
+    }
+
+    /// Counters of an interface from the dump of the last monitoring cycle,
+    /// its namespace is dumped now if it was not collected yet
+    async fn cached_interface_statistics(
+        &self,
+        iface: &VirtualInterface,
+    ) -> FResult<InterfaceStatistics> {
+        let cached = self
+            .state
+            .read()
+            .await
+            .link_stats
+            .get(&iface.net_ns)
+            .and_then(|dump| {
+                Some(InterfaceStatistics {
+                    intf_uuid: iface.uuid,
+                    collected_ms: dump.collected_ms,
+                    stats: dump.get(&iface.if_name)?,
+                })
+            });
+        match cached {
+            Some(stats) => {
+                self.state
+                    .write()
+                    .await
+                    .interface_stats
+                    .insert(iface.uuid, stats);
+                Ok(stats)
+            }
+            None => self.read_interface_statistics(iface).await,
+        }
+    }
+
+    /// Dumps the namespace of an interface, the dump replaces the one of
+    /// the last monitoring cycle and the counters of the interface are
+    /// kept as its last seen ones
+    async fn read_interface_statistics(
+        &self,
+        iface: &VirtualInterface,
+    ) -> FResult<InterfaceStatistics> {
+        let dump = self.dump_namespace_link_stats(iface.net_ns).await?;
+        let stats = dump.get(&iface.if_name).map(|stats| InterfaceStatistics {
+            intf_uuid: iface.uuid,
+            collected_ms: dump.collected_ms,
+            stats,
+        });
+        let mut guard = self.state.write().await;
+        guard.link_stats.insert(iface.net_ns, dump);
+        let stats = stats.ok_or(FError::NotFound)?;
+        guard.interface_stats.insert(iface.uuid, stats);
+        Ok(stats)
+    }
+
+    /// Dumps the links of a namespace, through its namespace manager
+    /// or with the handle of the plugin for the default one
+    async fn dump_namespace_link_stats(&self, net_ns: Option<Uuid>) -> FResult<LinkStatsDump> {
+        if let Some(ns_uuid) = net_ns {
+            let ns_manager = self.get_ns_manager(&ns_uuid).await?;
+            return ns_manager.dump_link_stats().await?;
+        }
+        if simulation::is_enabled() {
+            return Ok(LinkStatsDump {
+                collected_ms: now_ms(),
+                ..LinkStatsDump::default()
+            });
+        }
+        let mut dump = LinkStatsDump::default();
+        let mut state = self.state.write().await;
+        let mut links = state.nl_handler.link().get().execute();
+        while let Some(msg) = links
+            .try_next()
+            .await
+            .map_err(|e| FError::NetworkingError(format!("{}", e)))?
+        {
+            dump.add_link(msg.header.index, msg.nlas);
+        }
+        dump.collected_ms = now_ms();
+        Ok(dump)
+    }
+
+    /// Follows the link and address events of the default namespace and
+    /// updates the stored interfaces, the links moved to other namespaces
+    /// are left to the monitoring loop. Returns when the subscription fails.
+    async fn watch_link_events(&self) {
+        if simulation::is_enabled() {
+            return;
+        }
+        let (mut connection, handle, mut messages) = match new_connection() {
+            Ok(conn) => conn,
+            Err(e) => {
+                log::warn!(target: logging::NETLINK, "Unable to open the events socket: {}", e);
+                return;
+            }
+        };
+        let groups = RTMGRP_LINK | RTMGRP_IPV4_IFADDR | RTMGRP_IPV6_IFADDR;
+        if let Err(e) = connection
+            .socket_mut()
+            .bind(&NetlinkSocketAddr::new(0, groups))
+        {
+            log::warn!(target: logging::NETLINK, "Unable to subscribe to the link events: {}", e);
+            return;
+        }
+        runtime::spawn(connection);
+        log::info!(target: logging::NETLINK, "Subscribed to the link events");
+        // what changed while the plugin was not running, the events that
+        // follow are applied on top
+        if let Err(e) = self.reconcile_managed_links(&handle).await {
+            log::warn!(target: logging::NETLINK, "Unable to reconcile the managed links: {}", e);
+        }
+        while let Some((msg, _)) = messages.next().await {
+            let res = match msg.payload {
+                NetlinkPayload::InnerMessage(RtnlMessage::NewLink(link)) => {
+                    match link_event_state(&link) {
+                        Some((if_name, oper_state, admin_up)) => {
+                            self.on_link_event(&if_name, oper_state, admin_up).await
+                        }
+                        None => Ok(()),
+                    }
+                }
+                NetlinkPayload::InnerMessage(RtnlMessage::DelLink(link)) => {
+                    match link.nlas.iter().find_map(|nla| match nla {
+                        LinkNla::IfName(name) => Some(name.clone()),
+                        _ => None,
+                    }) {
+                        Some(if_name) if self.take_moved_link(&if_name).await => {
+                            log::trace!(target: logging::NETLINK, "Link {} moved to a namespace", if_name);
+                            Ok(())
+                        }
+                        Some(if_name) => {
+                            self.on_link_event(&if_name, LinkOperState::REMOVED, false)
+                                .await
+                        }
+                        None => Ok(()),
+                    }
+                }
+                NetlinkPayload::InnerMessage(RtnlMessage::NewAddress(addr))
+                | NetlinkPayload::InnerMessage(RtnlMessage::DelAddress(addr)) => {
+                    let mut links = handle.link().get().match_index(addr.header.index).execute();
+                    match links.try_next().await {
+                        Ok(Some(link)) => match link.nlas.iter().find_map(|nla| match nla {
+                            LinkNla::IfName(name) => Some(name.clone()),
+                            _ => None,
+                        }) {
+                            Some(if_name) => self.on_address_event(&if_name).await,
+                            None => Ok(()),
+                        },
+                        // the link is already gone, its deletion follows
+                        _ => Ok(()),
+                    }
+                }
+                _ => Ok(()),
+            };
+            if let Err(e) = res {
+                log::warn!(target: logging::NETLINK, "Unable to handle a link event: {}", e);
+            }
+        }
+        log::warn!(target: logging::NETLINK, "The link events socket was closed");
+    }
+
+    /// Whether the link left the default namespace with a move of the
+    /// plugin, the mark is consumed and the stale ones are dropped
+    async fn take_moved_link(&self, if_name: &str) -> bool {
+        let now = now_ms();
+        let mut guard = self.state.write().await;
+        guard
+            .moved_links
+            .retain(|_, moved_ms| now.saturating_sub(*moved_ms) < LINK_MOVE_GRACE_MS);
+        guard.moved_links.remove(if_name).is_some()
+    }
+
+    /// Stored interface of the default namespace with the given name
+    async fn default_ns_interface(&self, if_name: &str) -> Option<VirtualInterface> {
+        let intf_uuid = self
+            .state
+            .read()
+            .await
+            .interface_names
+            .iter()
+            .find(|(_, (name, net_ns))| name == if_name && net_ns.is_none())
+            .map(|(intf_uuid, _)| *intf_uuid)?;
+        self.connector.local.get_interface(intf_uuid).await.ok()
+    }
+
+    async fn on_link_event(
+        &self,
+        if_name: &str,
+        oper_state: LinkOperState,
+        admin_up: bool,
+    ) -> FResult<()> {
+        if let Err(e) = self.check_managed_link(if_name, oper_state, admin_up).await {
+            log::warn!(
+                target: logging::NETLINK,
+                "Unable to update the alert of {}: {}",
+                if_name,
+                e
+            );
+        }
+        let iface = match self.default_ns_interface(if_name).await {
+            Some(iface) => iface,
+            None => return Ok(()),
+        };
+        let previous = self
+            .state
+            .write()
+            .await
+            .link_oper_states
+            .insert(iface.uuid, oper_state);
+        if previous == Some(oper_state) {
+            return Ok(());
+        }
+        log::info!(
+            target: logging::NETLINK,
+            "Link {} ({}) is now {:?}",
+            if_name,
+            iface.uuid,
+            oper_state
+        );
+        self.publish_link_change(&iface, oper_state).await;
+        Ok(())
+    }
+
+    /// Raises or clears the alert of a managed link, the overlay and
+    /// dataplane interfaces and the bridges of the virtual networks, and
+    /// marks the networks depending on it as degraded or not
+    async fn check_managed_link(
+        &self,
+        if_name: &str,
+        oper_state: LinkOperState,
+        admin_up: bool,
+    ) -> FResult<()> {
+        let (kind, bridge) = match self.managed_link_kind(if_name).await {
+            Some(managed) => managed,
+            None => return Ok(()),
+        };
+        let down = managed_link_down(kind, oper_state, admin_up);
+        let changed = {
+            let mut guard = self.state.write().await;
+            if down {
+                guard.managed_links_down.insert(if_name.to_string())
+            } else {
+                guard.managed_links_down.remove(if_name)
+            }
+        };
+        if !changed {
+            return Ok(());
+        }
+        if down {
+            log::warn!(target: logging::NETLINK, "Managed link {} is down", if_name);
+        } else {
+            log::info!(target: logging::NETLINK, "Managed link {} is up again", if_name);
+        }
+        let (affected_vnets, _) = self.mark_degraded_vnets(if_name, bridge, down).await?;
+        self.publish_managed_link_alert(if_name, kind, down, affected_vnets)
+            .await;
+        Ok(())
+    }
+
+    /// Kind of a managed link, with the interface of a bridge, `None` when
+    /// the link is not managed
+    async fn managed_link_kind(&self, if_name: &str) -> Option<(ManagedLinkKind, Option<Uuid>)> {
+        if self.config.overlay_iface.as_deref() == Some(if_name) {
+            return Some((ManagedLinkKind::OVERLAY, None));
+        }
+        if self.config.dataplane_iface.as_deref() == Some(if_name) {
+            return Some((ManagedLinkKind::DATAPLANE, None));
+        }
+        match self.default_ns_interface(if_name).await {
+            Some(iface) if matches!(iface.kind, VirtualInterfaceKind::BRIDGE(_)) => {
+                Some((ManagedLinkKind::BRIDGE, Some(iface.uuid)))
+            }
+            _ => None,
+        }
+    }
+
+    /// Adds or removes the link from the `degraded_by` of the networks
+    /// depending on it, with a versioned write of each record. Returns the
+    /// networks depending on the link and whether a record was changed.
+    async fn mark_degraded_vnets(
+        &self,
+        if_name: &str,
+        bridge: Option<Uuid>,
+        down: bool,
+    ) -> FResult<(Vec<Uuid>, bool)> {
+        let mut affected_vnets = Vec::new();
+        let mut changed = false;
+        for vnet in self.list_virtual_networks().await? {
+            let mut depends = false;
+            for intf_uuid in &vnet.interfaces {
+                if Some(*intf_uuid) == bridge {
+                    depends = true;
+                    break;
+                }
+                if let Ok(iface) = self.connector.local.get_interface(*intf_uuid).await {
+                    if underlay_of(&iface.kind) == Some(if_name) {
+                        depends = true;
+                        break;
+                    }
+                }
+            }
+            if !depends {
+                continue;
+            }
+            let written = self
+                .update_network_record(vnet.uuid, |vnet| {
+                    let mut internals = match vnet.plugin_internals.as_ref() {
+                        Some(raw) => deserialize_network_internals(raw)?,
+                        None => return Ok(false),
+                    };
+                    if internals.degraded_by.iter().any(|name| name == if_name) == down {
+                        return Ok(false);
+                    }
+                    internals.degraded_by.retain(|name| name != if_name);
+                    if down {
+                        internals.degraded_by.push(if_name.to_string());
+                    }
+                    vnet.plugin_internals = Some(serialize_network_internals(&internals)?);
+                    Ok(true)
+                })
+                .await?;
+            changed |= written;
+            affected_vnets.push(vnet.uuid);
+        }
+        Ok((affected_vnets, changed))
+    }
+
+    async fn publish_managed_link_alert(
+        &self,
+        if_name: &str,
+        kind: ManagedLinkKind,
+        down: bool,
+        affected_vnets: Vec<Uuid>,
+    ) {
+        let alert = LinkAlert {
+            if_name: if_name.to_string(),
+            kind,
+            down,
+            affected_vnets,
+            timestamp_ms: now_ms(),
+        };
+        self.publish_link_alert(&alert).await;
+    }
+
+    /// Brings the alerts and the degraded networks in line with the links
+    /// of the default namespace when the events subscription starts: the
+    /// managed links already down raise their alert, the networks still
+    /// degraded by a link that is up again or no longer managed are
+    /// cleared
+    async fn reconcile_managed_links(&self, handle: &Handle) -> FResult<()> {
+        let mut links = HashMap::new();
+        let mut dump = handle.link().get().execute();
+        while let Some(link) = dump
+            .try_next()
+            .await
+            .map_err(|e| FError::NetworkingError(format!("{}", e)))?
+        {
+            if let Some((if_name, oper_state, admin_up)) = link_event_state(&link) {
+                links.insert(if_name, (oper_state, admin_up));
+            }
+        }
+        let vnets = self.list_virtual_networks().await?;
+        let mut candidates: HashSet<String> = links.keys().cloned().collect();
+        candidates.extend(self.config.overlay_iface.iter().cloned());
+        candidates.extend(self.config.dataplane_iface.iter().cloned());
+        for vnet in &vnets {
+            if let Some(raw) = vnet.plugin_internals.as_ref() {
+                if let Ok(internals) = deserialize_network_internals(raw) {
+                    candidates.extend(internals.degraded_by);
+                }
+            }
+        }
+        for if_name in candidates {
+            let (oper_state, admin_up) = links
+                .get(&if_name)
+                .copied()
+                .unwrap_or((LinkOperState::REMOVED, false));
+            match self.managed_link_kind(&if_name).await {
+                Some((kind, bridge)) => {
+                    let down = managed_link_down(kind, oper_state, admin_up);
+                    if down {
+                        self.state
+                            .write()
+                            .await
+                            .managed_links_down
+                            .insert(if_name.clone());
+                        log::warn!(target: logging::NETLINK, "Managed link {} is down", if_name);
+                    }
+                    let (affected_vnets, changed) =
+                        self.mark_degraded_vnets(&if_name, bridge, down).await?;
+                    if down || changed {
+                        self.publish_managed_link_alert(&if_name, kind, down, affected_vnets)
+                            .await;
+                    }
+                }
+                None => {
+                    for vnet in &vnets {
+                        self.update_network_record(vnet.uuid, |vnet| {
+                            let mut internals = match vnet.plugin_internals.as_ref() {
+                                Some(raw) => deserialize_network_internals(raw)?,
+                                None => return Ok(false),
+                            };
+                            if !internals.degraded_by.contains(&if_name) {
+                                return Ok(false);
+                            }
+                            internals.degraded_by.retain(|name| *name != if_name);
+                            vnet.plugin_internals = Some(serialize_network_internals(&internals)?);
+                            Ok(true)
+                        })
+                        .await?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Publishes an alert under
+    /// `/fos/local/<plugin uuid>/alerts/links/<if name>`
+    async fn publish_link_alert(&self, alert: &LinkAlert) {
+        let plugin_uuid = match self.state.read().await.uuid {
+            Some(uuid) => uuid,
+            None => return,
+        };
+        let path = format!("/fos/local/{}/alerts/links/{}", plugin_uuid, alert.if_name);
+        let data = match serde_json::to_vec(alert) {
+            Ok(data) => data,
+            Err(e) => {
+                log::warn!(target: logging::NETLINK, "Unable to encode the alert of {}: {}", alert.if_name, e);
+                return;
+            }
+        };
+        if let Err(e) = self
+            .z
+            .write(&zenoh::net::ResKey::RName(path), data.into())
+            .await
+        {
+            log::warn!(target: logging::NETLINK, "Unable to publish the alert of {}: {}", alert.if_name, e);
+        }
+    }
+
+    async fn on_address_event(&self, if_name: &str) -> FResult<()> {
+        let iface = match self.default_ns_interface(if_name).await {
+            Some(iface) => iface,
+            None => return Ok(()),
+        };
+        let addresses = self
+            .kernel()
+            .get_iface_addresses(iface.if_name.clone())
+            .await?;
+        // the record may have been rewritten since it was read, only the
+        // addresses of the stored one are replaced
+        let iface = match self
+            .update_interface_record(iface.uuid, |stored| {
+                if stored.net_ns.is_some() || stored.addresses == addresses {
+                    return false;
+                }
+                stored.addresses = addresses.clone();
+                true
+            })
+            .await?
+        {
+            Some(iface) => iface,
+            None => return Ok(()),
+        };
+        log::info!(
+            target: logging::NETLINK,
+            "Addresses of {} ({}) changed to {:?}",
+            if_name,
+            iface.uuid,
+            iface.addresses
+        );
+        let oper_state = self
+            .state
+            .read()
+            .await
+            .link_oper_states
+            .get(&iface.uuid)
+            .copied()
+            .unwrap_or(LinkOperState::UP);
+        self.publish_link_change(&iface, oper_state).await;
+        Ok(())
+    }
+
+    /// Publishes a change of a stored interface under
+    /// `/fos/local/<plugin uuid>/links/<interface uuid>`
+    async fn publish_link_change(&self, iface: &VirtualInterface, oper_state: LinkOperState) {
+        let plugin_uuid = match self.state.read().await.uuid {
+            Some(uuid) => uuid,
+            None => return,
+        };
+        let change = LinkChange {
+            intf_uuid: iface.uuid,
+            if_name: iface.if_name.clone(),
+            oper_state,
+            addresses: iface.addresses.clone(),
+            timestamp_ms: now_ms(),
+        };
+        let path = format!("/fos/local/{}/links/{}", plugin_uuid, iface.uuid);
+        let data = match serde_json::to_vec(&change) {
+            Ok(data) => data,
+            Err(e) => {
+                log::warn!(target: logging::NETLINK, "Unable to encode the change of {}: {}", iface.uuid, e);
+                return;
+            }
+        };
+        if let Err(e) = self
+            .z
+            .write(&zenoh::net::ResKey::RName(path), data.into())
+            .await
+        {
+            log::warn!(target: logging::NETLINK, "Unable to publish the change of {}: {}", iface.uuid, e);
+        }
+    }
+
+    async fn resolver_running(&self, resolver: &VNetResolver) -> bool {
+        match read_pid_file(self.os.as_ref().unwrap(), &resolver.pid_file).await {
+            Ok(pid) => kill(Pid::from_raw(pid), None).is_ok(),
+            Err(_) => false,
+        }
+    }
+
+    /// Internals of a virtual network with whether its DHCP server runs,
+    /// `None` when it has none. Shared by the healthcheck and the node
+    /// status.
+    async fn check_vnet_dhcp(
+        &self,
+        vnet: &VirtualNetwork,
+    ) -> FResult<(Option<VirtualNetworkInternals>, Option<bool>)> {
+        let internals = match vnet.plugin_internals.as_ref() {
+            Some(raw) => deserialize_network_internals(raw)?,
+            None => return Ok((None, None)),
+        };
+        let running = match internals.dhcp.as_ref() {
+            Some(dhcp) => Some(self.vnet_dhcp_running(vnet.uuid, dhcp).await),
+            None => None,
+        };
+        Ok((Some(internals), running))
+    }
+
+    /// Checks the interfaces against the link dumps of the monitoring
+    /// cycle and the servers and namespace managers of the node
+    async fn collect_node_status(&self) -> NodeNetworkingStatus {
+        let interfaces = self.known_links().await;
+        let guard = self.state.read().await;
+        let (interfaces_missing, interfaces_down) =
+            check_interface_links(&interfaces, &guard.link_stats);
+        let ns_managers = guard
+            .ns_managers
+            .iter()
+            .map(|(ns_uuid, (pid, _))| NSManagerStatus {
+                ns_uuid: *ns_uuid,
+                pid: *pid,
+                alive: kill(Pid::from_raw(*pid as i32), None).is_ok(),
+            })
+            .collect();
+        let resolvers = guard.resolvers.clone();
+        drop(guard);
+
+        let mut virtual_networks = Vec::new();
+        for vnet in self.list_virtual_networks().await.unwrap_or_default() {
+            let bridge_present = match self.get_virtual_network_bridge(&vnet).await {
+                Ok(br_uuid) => {
+                    interfaces.iter().any(|i| i.uuid == br_uuid)
+                        && !interfaces_missing.contains(&br_uuid)
+                }
+                Err(_) => false,
+            };
+            let (internals, dhcp_running) =
+                self.check_vnet_dhcp(&vnet).await.unwrap_or((None, None));
+            let degraded_by = internals
+                .as_ref()
+                .map(|i| i.degraded_by.clone())
+                .unwrap_or_default();
+            let resolver_running = match resolvers.get(&vnet.uuid) {
+                Some((_, resolver)) => Some(self.resolver_running(resolver).await),
+                None => None,
+            };
+            virtual_networks.push(VNetStatus {
+                vnet_uuid: vnet.uuid,
+                bridge_present,
+                dhcp_running,
+                resolver_running,
+                connection_points: vnet.connection_points.len(),
+                degraded_by,
+            });
+        }
+        NodeNetworkingStatus {
+            collected_ms: now_ms(),
+            interfaces: interfaces.len(),
+            interfaces_missing,
+            interfaces_down,
+            virtual_networks,
+            ns_managers,
+        }
+    }
+
+    /// Publishes the networking status of the node under
+    /// `/fos/local/<plugin uuid>/status`
+    async fn publish_node_status(&self) {
+        let status = self.collect_node_status().await;
+        let plugin_uuid = {
+            let mut guard = self.state.write().await;
+            guard.node_status = Some(status.clone());
+            match guard.uuid {
+                Some(uuid) => uuid,
+                None => return,
+            }
+        };
+        let path = format!("/fos/local/{}/status", plugin_uuid);
+        let data = match serde_json::to_vec(&status) {
+            Ok(data) => data,
+            Err(e) => {
+                log::warn!(target: logging::RECONCILE, "Unable to encode the node status: {}", e);
+                return;
+            }
+        };
+        if let Err(e) = self
+            .z
+            .write(&zenoh::net::ResKey::RName(path), data.into())
+            .await
+        {
+            log::warn!(target: logging::RECONCILE, "Unable to publish the node status: {}", e);
+        }
+    }
+
+    /// Respawns the resolvers that are no longer running
+    async fn supervise_resolvers(&self) {
+        let resolvers: Vec<(Uuid, (String, VNetResolver))> = self
+            .state
+            .read()
+            .await
+            .resolvers
+            .iter()
+            .map(|(k, v)| (*k, v.clone()))
+            .collect();
+        for (vnet_uuid, (ns_name, resolver)) in resolvers {
+            if !self.resolver_running(&resolver).await {
+                log::warn!(
+                    target: logging::RECONCILE,
+                    "Resolver of {} is not running, restarting it",
+                    vnet_uuid
+                );
+                if let Err(e) = spawn_dnsmasq(Some(&ns_name), &resolver.conf) {
+                    log::error!(
+                        target: logging::RECONCILE,
+                        "Unable to restart resolver of {}: {}",
+                        vnet_uuid,
+                        e
+                    );
+                }
+            }
+        }
+    }
+
+    /// dhclient command for the interface of a client, run in its namespace
+    fn dhclient_command(&self, client: &DHCPClient) -> Command {
+        let mut cmd = match &client.ns_name {
+            Some(ns_name) => {
+                let mut cmd = Command::new("ip");
+                cmd.arg("netns").arg("exec").arg(ns_name).arg("dhclient");
+                cmd
+            }
+            None => Command::new("dhclient"),
+        };
+        cmd.arg("-pf")
+            .arg(&client.pid_file)
+            .arg("-lf")
+            .arg(&client.lease_file);
+        cmd
+    }
+
+    /// Runs dhclient until it gets a lease for the interface, the client
+    /// then keeps running in the background to renew it
+    async fn start_dhcp_client(
+        &self,
+        intf_uuid: Uuid,
+        if_name: String,
+        ns_name: Option<String>,
+    ) -> FResult<()> {
+        if self
+            .state
+            .read()
+            .await
+            .dhcp_clients
+            .contains_key(&intf_uuid)
+        {
+            return Err(FError::AlreadyPresent);
+        }
+        let file_path = |ext: &str| -> FResult<String> {
+            Ok(self
+                .get_run_path()
+                .join(format!("dhclient-{}.{}", intf_uuid, ext))
+                .to_str()
+                .ok_or(FError::EncodingError)?
+                .to_string())
+        };
+        let backend = self.get_dhcp_client_backend();
+        let mut client = DHCPClient {
+            if_name,
+            ns_name,
+            backend,
+            pid: 0,
+            pid_file: match backend {
+                DHCPClientBackend::DHCLIENT => file_path("pid")?,
+                DHCPClientBackend::EMBEDDED => String::new(),
+            },
+            lease_file: file_path("leases")?,
+            lease: None,
+        };
+        let mut cmd = self.dhclient_command(&client);
+        // -1 gives up instead of retrying forever when no server answers
+        cmd.arg("-1").arg(&client.if_name);
+        log::trace!(target: logging::DHCP, "Starting DHCP client {:?}", cmd);
+        client.pid = if simulation::is_enabled() {
+            let argv = std::iter::once(cmd.get_program())
+                .chain(cmd.get_args())
+                .map(|a| a.to_string_lossy().to_string())
+                .collect();
+            simulation::spawn(client.ns_name.as_deref(), argv)?
+        } else if backend == DHCPClientBackend::EMBEDDED {
+            let handle = dhcp_client::start(
+                client.ns_name.clone(),
+                client.if_name.clone(),
+                client.lease_file.clone(),
+            )
+            .await?;
+            let lease = handle.lease().await;
+            if let Err(e) = self.apply_dhcp_lease(intf_uuid, &lease).await {
+                handle.stop(true).await;
+                return Err(e);
+            }
+            client.lease = Some(lease);
+            self.state
+                .write()
+                .await
+                .dhcp_client_handles
+                .insert(intf_uuid, handle);
+            self.pid
+        } else {
+            cmd.stdin(Stdio::null()).stdout(Stdio::null());
+            // dhclient -1 blocks until it gets a lease or gives up
+            let status = runtime::spawn_blocking(move || cmd.status())
+                .await
+                .map_err(|e| FError::NetworkingError(format!("{}", e)))?;
+            if !status.success() {
+                return Err(FError::NetworkingError(format!(
+                    "No DHCP lease for {}, dhclient exited with {}",
+                    client.if_name, status
+                )));
+            }
+            read_pid_file(self.os.as_ref().unwrap(), &client.pid_file).await? as u32
+        };
+        log::debug!(
+            target: logging::DHCP,
+            "DHCP client of {} running PID: {}",
+            client.if_name,
+            client.pid
+        );
+        self.state
+            .write()
+            .await
+            .dhcp_clients
+            .insert(intf_uuid, client);
+        Ok(())
+    }
+
+    /// Stops the DHCP client of an interface, with `release` the lease is
+    /// given back to the server, otherwise it is kept to be renewed by the
+    /// next client
+    async fn stop_dhcp_client(&self, intf_uuid: Uuid, release: bool) -> FResult<DHCPClient> {
+        let client = self
+            .state
+            .write()
+            .await
+            .dhcp_clients
+            .remove(&intf_uuid)
+            .ok_or(FError::NotFound)?;
+        log::trace!(
+            target: logging::DHCP,
+            "Stopping DHCP client of {}, release: {}",
+            client.if_name,
+            release
+        );
+        let handle = self
+            .state
+            .write()
+            .await
+            .dhcp_client_handles
+            .remove(&intf_uuid);
+        if simulation::is_enabled() {
+            simulation::kill(client.pid)?;
+        } else if let Some(handle) = handle {
+            let lease = handle.lease().await;
+            handle.stop(release).await;
+            if release {
+                self.withdraw_dhcp_lease(intf_uuid, &lease).await;
+            }
+        } else if client.backend == DHCPClientBackend::DHCLIENT {
+            // both signal the client running with the pid file
+            let mut cmd = self.dhclient_command(&client);
+            cmd.arg(if release { "-r" } else { "-x" })
+                .arg(&client.if_name);
+            exec_command(cmd)?;
+        }
+        let pid_file = async_std::path::Path::new(&client.pid_file);
+        if pid_file.exists().await {
+            async_std::fs::remove_file(pid_file).await?;
+        }
+        let lease_file = async_std::path::Path::new(&client.lease_file);
+        if release && lease_file.exists().await {
+            async_std::fs::remove_file(lease_file).await?;
+        }
+        Ok(client)
+    }
+
+    /// Applies a lease of the embedded client as dhclient does: its address
+    /// is set on the interface, then in a namespace the default route goes
+    /// through the router of the lease and the DNS servers become the
+    /// resolvers of the namespace. The default namespace keeps the routes
+    /// and resolvers of the node.
+    async fn apply_dhcp_lease(&self, intf_uuid: Uuid, lease: &DHCPLease) -> FResult<()> {
+        let iface = self.connector.local.get_interface(intf_uuid).await?;
+        let addr = IPAddress::V4(lease.address);
+        // the address of a renewed lease is already there
+        let assigned = self.read_interface_addresses(&iface).await?.contains(&addr);
+        let ns_uuid = match iface.net_ns {
+            Some(ns_uuid) => ns_uuid,
+            None => {
+                if !assigned {
+                    self.kernel()
+                        .add_iface_address(iface.if_name.clone(), addr, lease.prefix)
+                        .await?;
+                }
+                return Ok(());
+            }
+        };
+        let ns_manager = self.get_ns_manager(&ns_uuid).await?;
+        if !assigned {
+            let address = IpNetwork::new(addr, lease.prefix)
+                .map_err(|e| FError::NetworkingError(format!("{}", e)))?;
+            ns_manager
+                .add_virtual_interface_address(iface.if_name.clone(), Some(address))
+                .await??;
+        }
+        if let Some(router) = lease.router {
+            ns_manager
+                .add_virtual_interface_default_route(iface.if_name.clone(), lease_route(router))
+                .await??;
+        }
+        if !lease.dns_servers.is_empty() {
+            let netns = self.connector.local.get_network_namespace(ns_uuid).await?;
+            let servers: Vec<IPAddress> = lease
+                .dns_servers
+                .iter()
+                .map(|s| IPAddress::V4(*s))
+                .collect();
+            write_netns_resolv_conf(&netns.ns_name, intf_uuid, &servers)?;
+        }
+        Ok(())
+    }
+
+    /// Removes what `apply_dhcp_lease` set for a released lease, the
+    /// interface can be already gone
+    async fn withdraw_dhcp_lease(&self, intf_uuid: Uuid, lease: &DHCPLease) {
+        let iface = match self.connector.local.get_interface(intf_uuid).await {
+            Ok(iface) => iface,
+            Err(_) => return,
+        };
+        let addr = IPAddress::V4(lease.address);
+        let res = match iface.net_ns {
+            Some(ns_uuid) => match self.get_ns_manager(&ns_uuid).await {
+                Ok(ns_manager) => {
+                    if let Some(router) = lease.router {
+                        let _ = ns_manager
+                            .del_virtual_interface_default_route(
+                                iface.if_name.clone(),
+                                lease_route(router),
+                            )
+                            .await;
+                    }
+                    let netns = self.connector.local.get_network_namespace(ns_uuid).await;
+                    if let Ok(netns) = netns {
+                        if let Err(e) = remove_netns_resolv_conf(&netns.ns_name, intf_uuid) {
+                            log::trace!(
+                                target: logging::DHCP,
+                                "Unable to remove the resolvers of {}: {}",
+                                iface.if_name,
+                                e
+                            );
+                        }
+                    }
+                    ns_manager
+                        .del_virtual_interface_address(iface.if_name.clone(), addr)
+                        .await
+                        .map_err(FError::from)
+                        .and_then(|res| res)
+                }
+                Err(e) => Err(e),
+            },
+            None => {
+                self.kernel()
+                    .del_iface_address(iface.if_name.clone(), addr)
+                    .await
+            }
+        };
+        if let Err(e) = res {
+            log::trace!(
+                target: logging::DHCP,
+                "Unable to remove {} from {}: {}",
+                addr,
+                iface.if_name,
+                e
+            );
+        }
+    }
+
+    /// Releases the DHCP lease of an interface being removed, if any
+    async fn release_interface_lease(&self, iface: &VirtualInterface) {
+        if !self
+            .state
+            .read()
+            .await
+            .dhcp_clients
+            .contains_key(&iface.uuid)
+        {
+            return;
+        }
+        if let Err(e) = self.stop_dhcp_client(iface.uuid, true).await {
+            log::warn!(
+                target: logging::DHCP,
+                "Unable to release the DHCP lease of {}: {}",
+                iface.if_name,
+                e
+            );
+        }
+    }
+
+    /// Current addresses of an interface, from its namespace
+    async fn read_interface_addresses(&self, iface: &VirtualInterface) -> FResult<Vec<IPAddress>> {
+        match iface.net_ns {
+            Some(ns_uuid) => {
+                let ns_manager = self.get_ns_manager(&ns_uuid).await?;
+                ns_manager
+                    .get_virtual_interface_addresses(iface.if_name.clone())
+                    .await?
+            }
+            None => {
+                self.kernel()
+                    .get_iface_addresses(iface.if_name.clone())
+                    .await
+            }
+        }
+    }
+
+    /// Kernel objects of a virtual network not matching its record: the
+    /// namespace, the interfaces with their addresses, the DHCP server
+    /// and the nftables tables
+    async fn kernel_network_diff(&self, vnet: &VirtualNetwork) -> FResult<Vec<NetworkKernelDiff>> {
+        let mut diffs = Vec::new();
+        let internals = match &vnet.plugin_internals {
+            Some(raw) => Some(deserialize_network_internals(raw)?),
+            None => None,
+        };
+        if let Some(ns_info) = internals.as_ref().and_then(|i| i.associated_netns.as_ref()) {
+            if !self.netns_exists(&ns_info.ns_name) {
+                diffs.push(NetworkKernelDiff {
+                    object: "namespace".to_string(),
+                    ns_name: Some(ns_info.ns_name.clone()),
+                    recorded: Some(format!("{}", ns_info.ns_uuid)),
+                    actual: None,
+                });
+            }
+        }
+        for intf_uuid in &vnet.interfaces {
+            let iface = match self.connector.local.get_interface(*intf_uuid).await {
+                Ok(iface) => iface,
+                Err(_) => {
+                    diffs.push(NetworkKernelDiff {
+                        object: format!("interface {}", intf_uuid),
+                        ns_name: None,
+                        recorded: None,
+                        actual: None,
+                    });
+                    continue;
+                }
+            };
+            let ns_name = match iface.net_ns {
+                Some(ns_uuid) => self
+                    .connector
+                    .local
+                    .get_network_namespace(ns_uuid)
+                    .await
+                    .ok()
+                    .map(|netns| netns.ns_name),
+                None => None,
+            };
+            let exists = match iface.net_ns {
+                Some(ns_uuid) => match self.get_ns_manager(&ns_uuid).await {
+                    Ok(ns_manager) => ns_manager
+                        .check_virtual_interface_exists(iface.if_name.clone())
+                        .await
+                        .ok()
+                        .and_then(|res| res.ok())
+                        .unwrap_or(false),
+                    Err(_) => false,
+                },
+                None => self.kernel().iface_exists(iface.if_name.clone()).await?,
+            };
+            let mut recorded = iface.addresses.clone();
+            recorded.sort();
+            let actual = if exists {
+                // link-local addresses are assigned by the kernel
+                let mut actual: Vec<IPAddress> = self
+                    .read_interface_addresses(&iface)
+                    .await?
+                    .into_iter()
+                    .filter(|addr| match addr {
+                        IPAddress::V6(addr) => (addr.segments()[0] & 0xffc0) != 0xfe80,
+                        IPAddress::V4(_) => true,
+                    })
+                    .collect();
+                actual.sort();
+                Some(actual)
+            } else {
+                None
+            };
+            if actual.as_ref() != Some(&recorded) {
+                diffs.push(NetworkKernelDiff {
+                    object: format!("interface {}", iface.if_name),
+                    ns_name,
+                    recorded: Some(format!("{:?}", recorded)),
+                    actual: actual.map(|addresses| format!("{:?}", addresses)),
+                });
+            }
+        }
+        if let Some(dhcp) = internals.as_ref().and_then(|i| i.dhcp.as_ref()) {
+            if !self.vnet_dhcp_running(vnet.uuid, dhcp).await {
+                diffs.push(NetworkKernelDiff {
+                    object: "dhcp".to_string(),
+                    ns_name: internals
+                        .as_ref()
+                        .and_then(|i| i.associated_netns.as_ref())
+                        .map(|ns_info| ns_info.ns_name.clone()),
+                    recorded: Some(format!("{:?}", dhcp.backend_kind())),
+                    actual: None,
+                });
+            }
+        }
+        for table in internals
+            .as_ref()
+            .map(|i| i.associated_tables.as_slice())
+            .unwrap_or_default()
+        {
+            if !self.nft_table_exists(table) {
+                diffs.push(NetworkKernelDiff {
+                    object: "nft table".to_string(),
+                    ns_name: None,
+                    recorded: Some(table.clone()),
+                    actual: None,
+                });
+            }
+        }
+        Ok(diffs)
+    }
+
+    fn netns_exists(&self, ns_name: &str) -> bool {
+        match simulation::snapshot() {
+            Some(kernel) => kernel.namespaces.contains(ns_name),
+            None => std::path::Path::new("/var/run/netns")
+                .join(ns_name)
+                .exists(),
+        }
+    }
+
+    /// Lists a table with its rule handles, the simulation only tells
+    /// whether it exists
+    fn read_firewall_table(
+        &self,
+        name: &str,
+        family: &str,
+        kind: FirewallTableKind,
+        ns_name: Option<String>,
+    ) -> FirewallTable {
+        let listing = match simulation::snapshot() {
+            Some(kernel) => kernel.nft_tables.get(name).map(|_| String::new()),
+            None => {
+                let mut cmd = match &ns_name {
+                    Some(ns_name) => netns_command(ns_name, "nft"),
+                    None => Command::new("nft"),
+                };
+                cmd.arg("-a").arg("list").arg("table").arg(family).arg(name);
+                exec_command(cmd).ok()
+            }
+        };
+        FirewallTable {
+            name: name.to_string(),
+            family: family.to_string(),
+            kind,
+            ns_name,
+            present: listing.is_some(),
+            chains: listing
+                .map(|listing| parse_nft_chains(&listing))
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Names of the inet tables of the default namespace
+    #[cfg(feature = "nat")]
+    fn list_nft_tables(&self) -> FResult<Vec<String>> {
+        if let Some(kernel) = simulation::snapshot() {
+            return Ok(kernel.nft_tables.keys().cloned().collect());
+        }
+        let mut cmd = Command::new("nft");
+        cmd.arg("list").arg("tables").arg("inet");
+        Ok(exec_command(cmd)?
+            .lines()
+            .filter_map(|line| line.trim().strip_prefix("table inet "))
+            .map(|table| table.trim().to_string())
+            .collect())
+    }
+
+    #[cfg(not(feature = "nat"))]
+    async fn clean_orphaned_nft_tables(&self) -> FResult<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    /// Deletes the tables with random names no virtual network refers to,
+    /// left behind by a crash between their creation and the store of
+    /// the network. Returns the deleted tables.
+    #[cfg(feature = "nat")]
+    async fn clean_orphaned_nft_tables(&self) -> FResult<Vec<String>> {
+        let vnets: Vec<Uuid> = self
+            .state
+            .read()
+            .await
+            .known_vnets
+            .iter()
+            .copied()
+            .collect();
+        let mut referenced = HashSet::new();
+        // the default virtual network is not in the journal on every node
+        if let Ok(vnet) = self.connector.local.get_virtual_network(Uuid::nil()).await {
+            referenced.extend(nft_tables_of(&vnet)?);
+        }
+        for vnet_uuid in vnets {
+            // a network that cannot be read could own any table
+            let vnet = self.connector.local.get_virtual_network(vnet_uuid).await?;
+            referenced.extend(nft_tables_of(&vnet)?);
+        }
+        let orphans: Vec<String> = self
+            .list_nft_tables()?
+            .into_iter()
+            .filter(|table| is_random_nft_table(table) && !referenced.contains(table))
+            .collect();
+        for table in &orphans {
+            log::info!(target: logging::NFT, "Deleting orphaned table {}", table);
+            self.delete_nft_table(table)?;
+        }
+        Ok(orphans)
+    }
+
+    fn nft_table_exists(&self, table: &str) -> bool {
+        match simulation::snapshot() {
+            Some(kernel) => kernel.nft_tables.contains_key(table),
+            None => {
+                let mut cmd = Command::new("nft");
+                cmd.arg("list").arg("table").arg("inet").arg(table);
+                exec_command(cmd).is_ok()
+            }
+        }
+    }
+
+    async fn vnet_dhcp_running(&self, vnet_uuid: Uuid, dhcp: &VNetDHCP) -> bool {
+        if let Some(kernel) = simulation::snapshot() {
+            let vnet_uuid = format!("{}", vnet_uuid);
+            return kernel
+                .processes
+                .iter()
+                .any(|p| p.argv.iter().any(|arg| *arg == vnet_uuid));
+        }
+        match dhcp.backend_kind() {
+            DHCPBackend::EMBEDDED => self
+                .state
+                .read()
+                .await
+                .dhcp_servers
+                .contains_key(&vnet_uuid),
+            DHCPBackend::DNSMASQ | DHCPBackend::RELAY => {
+                match read_pid_file(self.os.as_ref().unwrap(), &dhcp.pid_file).await {
+                    Ok(pid) => kill(Pid::from_raw(pid), None).is_ok(),
+                    Err(_) => false,
+                }
+            }
+        }
+    }
+
+    /// Respawns the DHCP clients that are no longer running, their leases
+    /// would not be renewed otherwise
+    async fn supervise_dhcp_clients(&self) {
+        let clients: Vec<(Uuid, DHCPClient)> = self
+            .state
+            .read()
+            .await
+            .dhcp_clients
+            .iter()
+            .map(|(k, v)| (*k, v.clone()))
+            .collect();
+        for (intf_uuid, client) in clients {
+            let running = match simulation::snapshot() {
+                Some(kernel) => kernel.processes.iter().any(|p| p.pid == client.pid),
+                None => match client.backend {
+                    DHCPClientBackend::DHCLIENT => {
+                        kill(Pid::from_raw(client.pid as i32), None).is_ok()
+                    }
+                    DHCPClientBackend::EMBEDDED => self
+                        .state
+                        .read()
+                        .await
+                        .dhcp_client_handles
+                        .get(&intf_uuid)
+                        .map_or(false, |h| h.is_running()),
+                },
+            };
+            if running {
+                continue;
+            }
+            log::warn!(
+                target: logging::RECONCILE,
+                "DHCP client of {} is not running, restarting it",
+                client.if_name
+            );
+            let mut guard = self.state.write().await;
+            guard.dhcp_clients.remove(&intf_uuid);
+            let handle = guard.dhcp_client_handles.remove(&intf_uuid);
+            drop(guard);
+            if let Some(handle) = handle {
+                // the lease was lost, its address goes with it
+                let lease = handle.lease().await;
+                handle.stop(false).await;
+                self.withdraw_dhcp_lease(intf_uuid, &lease).await;
+            }
+            if let Err(e) = self
+                .start_dhcp_client(intf_uuid, client.if_name.clone(), client.ns_name.clone())
+                .await
+            {
+                log::error!(
+                    target: logging::RECONCILE,
+                    "Unable to restart DHCP client of {}: {}",
+                    client.if_name,
+                    e
+                );
+                // retried at the next cycle
+                self.state
+                    .write()
+                    .await
+                    .dhcp_clients
+                    .entry(intf_uuid)
+                    .or_insert(client);
+            }
+        }
+    }
+
+    fn get_dhcp_backend(&self) -> DHCPBackend {
+        self.config.dhcp_backend.unwrap_or(DHCPBackend::DNSMASQ)
+    }
+
+    fn get_dhcp_client_backend(&self) -> DHCPClientBackend {
+        self.config
+            .dhcp_client
+            .unwrap_or(DHCPClientBackend::EMBEDDED)
+    }
+
+    fn kernel(&self) -> Box<dyn KernelBackend + '_> {
+        if simulation::is_enabled() {
+            return Box::new(SimulatedKernelBackend);
+        }
+        Box::new(NetlinkKernel { plugin: self })
+    }
+
+    fn dhcp_backend(&self, kind: DHCPBackend) -> Box<dyn DhcpBackend + '_> {
+        if simulation::is_enabled() {
+            return Box::new(SimulatedBackend { kind });
+        }
+        match kind {
+            DHCPBackend::DNSMASQ => Box::new(DnsmasqBackend {
+                os: self.os.as_ref().unwrap(),
+                path: &self.config.path,
+                lease_hook: self.config.lease_hook.as_deref(),
+            }),
+            DHCPBackend::EMBEDDED => Box::new(EmbeddedBackend {
+                state: &self.state,
+                lease_hook: self.config.lease_hook.as_deref(),
+            }),
+            DHCPBackend::RELAY => Box::new(RelayBackend {
+                os: self.os.as_ref().unwrap(),
+                path: &self.config.path,
+            }),
+        }
+    }
+
+    /// Restarts the DHCP server of a virtual network from its internals
+    async fn respawn_vnet_dhcp(
+        &self,
+        vnet_uuid: Uuid,
+        ns_name: Option<&str>,
+        br_name: &str,
+        dhcp_internal: &VNetDHCP,
+    ) -> FResult<()> {
+        self.dhcp_backend(dhcp_internal.backend_kind())
+            .respawn(vnet_uuid, ns_name, br_name, dhcp_internal)
+            .await
+    }
 
-        vnet.interfaces.push(external_veth_uuid);
+    /// Stops the DHCP server of a virtual network and removes its files
+    async fn stop_vnet_dhcp(&self, vnet_uuid: Uuid, dhcp_internal: VNetDHCP) -> FResult<()> {
+        self.dhcp_backend(dhcp_internal.backend_kind())
+            .stop(vnet_uuid, dhcp_internal)
+            .await
+    }
 
-        self.set_iface_master(external_veth_name.clone(), br_name.clone())
+    /// Pushes the options and reservations of a virtual network to its
+    /// DHCP server
+    async fn reload_vnet_dhcp(
+        &self,
+        vnet_uuid: Uuid,
+        internals: &mut VirtualNetworkInternals,
+    ) -> FResult<()> {
+        let mut dhcp_internal = internals.dhcp.clone().ok_or(FError::NotFound)?;
+        self.dhcp_backend(dhcp_internal.backend_kind())
+            .reload(vnet_uuid, &mut dhcp_internal, internals)
             .await?;
-        self.set_iface_up(external_veth_name).await?;
-
-        self.set_iface_ns(
-            internal_veth_name.clone(),
-            associated_ns.ns_name.clone().clone(),
-        )
-        .await?;
-
-        // create internal bridge
-        let ns_manager = self.get_ns_manager(&associated_ns.uuid).await?;
+        internals.dhcp = Some(dhcp_internal);
+        Ok(())
+    }
 
-        // This is used to wait that the namespace manager is ready to serve
-        while !ns_manager.verify_server().await? {}
+    #[cfg(feature = "nat")]
+    fn get_firewall_backend(&self) -> FirewallBackendKind {
+        self.config
+            .firewall_backend
+            .unwrap_or(FirewallBackendKind::NFTABLES)
+    }
 
-        ns_manager
-            .set_virtual_interface_up("lo".to_string())
-            .await??;
+    #[cfg(feature = "nat")]
+    fn firewall_backend(&self) -> Box<dyn FirewallBackend> {
+        if simulation::is_enabled() {
+            return Box::new(SimulatedFirewall);
+        }
+        match self.get_firewall_backend() {
+            FirewallBackendKind::NFTABLES => Box::new(NftablesBackend),
+            FirewallBackendKind::IPTABLES => Box::new(IptablesBackend),
+        }
+    }
 
-        ns_manager
-            .add_virtual_interface_bridge(internal_br_name.clone())
-            .await??;
+    #[cfg(feature = "nat")]
+    async fn configure_nat(
+        &self,
+        net: IpNetwork,
+        iface: &str,
+        snat: &SNATOptions,
+    ) -> FResult<String> {
+        let table_name = self.generate_random_nft_table_name();
+        log::trace!(
+            target: logging::NFT,
+            "configure_nat {} {} {} {:?}",
+            net,
+            iface,
+            table_name,
+            snat
+        );
+        self.firewall_backend()
+            .configure_nat(&table_name, net, iface, snat)?;
+        Ok(table_name)
+    }
 
-        ns_manager
-            .set_virtual_interface_up(internal_br_name.clone())
-            .await??;
+    /// Creates the NAT tables of a virtual network on the overlay
+    /// interface, one per subnet with the primary one first
+    #[cfg(feature = "nat")]
+    async fn configure_vnet_nat(
+        &self,
+        vnet: &VirtualNetwork,
+        secondary: Option<&IPConfiguration>,
+        snat: &SNATOptions,
+    ) -> FResult<Vec<String>> {
+        let iface = self.get_overlay_face_from_config().await?.if_name;
+        let mut tables = Vec::new();
+        for (net_addr, prefix) in vnet
+            .ip_configuration
+            .iter()
+            .chain(secondary)
+            .filter_map(|c| c.subnet)
+        {
+            let net = IpNetwork::new(net_addr, prefix)
+                .map_err(|e| FError::NetworkingError(format!("{}", e)))?;
+            tables.push(self.configure_nat(net, &iface, snat).await?);
+        }
+        Ok(tables)
+    }
 
-        vnet.interfaces.push(internal_br_uuid);
+    #[cfg(not(feature = "nat"))]
+    async fn configure_static_nat(
+        &self,
+        _public_ip: IPAddress,
+        _private_ip: IPAddress,
+        _iface: &str,
+        _subnet: Option<IpNetwork>,
+    ) -> FResult<String> {
+        Err(FError::Unimplemented)
+    }
 
-        self.connector
-            .local
-            .add_interface(&v_internal_bridge)
+    /// Adds the public address to `iface` and creates a table with the DNAT
+    /// and SNAT rules of a floating IP or an address mapping, returns the
+    /// name of the table. NAT statements are not covered by
+    /// the nftnl expressions, so the rules are added through nft. Clients
+    /// of `subnet` reaching the public address are masqueraded, so that
+    /// the replies go back through the node.
+    #[cfg(feature = "nat")]
+    async fn configure_static_nat(
+        &self,
+        public_ip: IPAddress,
+        private_ip: IPAddress,
+        iface: &str,
+        subnet: Option<IpNetwork>,
+    ) -> FResult<String> {
+        let table = self.generate_random_nft_table_name();
+        let (family, prefix) = match public_ip {
+            IPAddress::V4(_) => ("ip", 32),
+            IPAddress::V6(_) => ("ip6", 128),
+        };
+        let mut script = format!(
+            "add table inet {table}; \
+             add chain inet {table} prerouting {{ type nat hook prerouting priority -100; }}; \
+             add chain inet {table} postrouting {{ type nat hook postrouting priority 100; }}; \
+             add rule inet {table} prerouting {family} daddr {public} dnat {family} to {private}; \
+             add rule inet {table} postrouting {family} saddr {private} oifname \"{iface}\" snat {family} to {public}",
+            table = table,
+            family = family,
+            public = public_ip,
+            private = private_ip,
+            iface = iface,
+        );
+        if let Some(subnet) = subnet.filter(|net| net.is_ipv4() == public_ip.is_ipv4()) {
+            script.push_str(&format!(
+                "; add rule inet {table} postrouting {family} saddr {subnet} {family} daddr {private} \
+                 ct status dnat masquerade",
+                table = table,
+                family = family,
+                subnet = subnet,
+                private = private_ip,
+            ));
+        }
+        log::trace!(target: logging::NFT, "configure_static_nat: {}", script);
+        self.kernel()
+            .add_iface_address(iface.to_string(), public_ip, prefix)
             .await?;
+        let mut cmd = Command::new("nft");
+        cmd.arg(script);
+        if let Err(e) = exec_command(cmd) {
+            self.kernel()
+                .del_iface_address(iface.to_string(), public_ip)
+                .await?;
+            return Err(e);
+        }
+        Ok(table)
+    }
 
-        ns_manager
-            .set_virtual_interface_master(internal_veth_name.clone(), internal_br_name.clone())
-            .await??;
-
-        ns_manager
-            .set_virtual_interface_up(internal_veth_name.clone())
-            .await??;
-
-        // NAT configuration, skip it for the time being...
-        // let nat_table = self
-        //     .configure_nat(
-        //         IpNetwork::V4(
-        //             ipnetwork::Ipv4Network::new(
-        //                 std::net::Ipv4Addr::new(10, 240, 0, 0),
-        //                 16,
-        //             )
-        //             .map_err(|e| FError::NetworkingError(format!("{}", e)))?,
-        //         ),
-        //         &self.get_overlay_face_from_config().await?.if_name,
-        //     )
-        //     .await?;
+    #[cfg(not(feature = "nat"))]
+    async fn apply_port_forwards(
+        &self,
+        _vnet: &VirtualNetwork,
+        _internals: &VirtualNetworkInternals,
+    ) -> FResult<()> {
+        Err(FError::Unimplemented)
+    }
 
-        // DHCP configuration and spawn
+    /// Replaces the port forwards in the NAT table of a virtual network,
+    /// with the hairpin rules for the clients of the network
+    #[cfg(feature = "nat")]
+    async fn apply_port_forwards(
+        &self,
+        vnet: &VirtualNetwork,
+        internals: &VirtualNetworkInternals,
+    ) -> FResult<()> {
+        let table = internals.associated_tables.first().ok_or_else(|| {
+            FError::NetworkingError("Virtual network has no NAT table".to_string())
+        })?;
+        let overlay = self.get_overlay_face_from_config().await?;
+        let net = vnet
+            .ip_configuration
+            .as_ref()
+            .and_then(|c| c.subnet)
+            .and_then(|(addr, prefix)| IpNetwork::new(addr, prefix).ok());
+        let mut cmd = Command::new("nft");
+        cmd.arg(render_port_forwards(
+            table,
+            &overlay.if_name,
+            &internals.port_forwards,
+            net.map(|net| (net, overlay.addresses.as_slice())),
+        ));
+        exec_command(cmd)?;
+        Ok(())
+    }
 
-        let dhcp_internal = match &vnet.ip_configuration {
-            Some(conf) => None,
-            None => None,
+    /// Creates the sets of a virtual network and replaces the rules
+    /// referencing them, the filter chain lives in its NAT table
+    async fn apply_set_rules(
+        &self,
+        vnet: &VirtualNetwork,
+        internals: &VirtualNetworkInternals,
+    ) -> FResult<()> {
+        let net = match vnet.ip_configuration.as_ref().and_then(|c| c.subnet) {
+            Some((addr @ IPAddress::V4(_), prefix)) => IpNetwork::new(addr, prefix)
+                .map_err(|e| FError::NetworkingError(format!("{}", e)))?,
+            _ => {
+                return Err(FError::NetworkingError(
+                    "Set rules need an IPv4 subnet".to_string(),
+                ))
+            }
         };
+        let active = active_set_rules(&internals.set_rules, now_ms() / 1000);
+        let rules: Vec<SetRule> = internals
+            .set_rules
+            .iter()
+            .zip(&active)
+            .filter(|(_, active)| **active)
+            .map(|(r, _)| r.clone())
+            .collect();
+        self.update_nat_table(internals, &|table| {
+            render_set_rules(table, net, &internals.nft_sets, &rules)
+        })
+        .await?;
+        let mut guard = self.state.write().await;
+        if internals.set_rules.iter().any(|r| r.schedule.is_some()) {
+            guard.scheduled_rules.insert(vnet.uuid, active);
+        } else {
+            guard.scheduled_rules.remove(&vnet.uuid);
+        }
+        Ok(())
+    }
 
-        let ns_info = Some(VNetNetns {
-            ns_name: associated_ns.ns_name.clone(),
-            ns_uuid: associated_ns.uuid,
-        });
+    /// Replaces the egress rules of a virtual network in a table of its
+    /// own, whether the network is NATed or not. The table is dropped
+    /// with the last rule.
+    async fn apply_egress_rules(
+        &self,
+        vnet: &VirtualNetwork,
+        internals: &VirtualNetworkInternals,
+    ) -> FResult<()> {
+        let table = egress_table(vnet.uuid);
+        if internals.egress_rules.is_empty() {
+            return self.delete_egress_rules(vnet.uuid);
+        }
+        let nets = egress_networks(vnet, internals)?;
+        let script = render_egress_rules(&table, &nets, &internals.egress_rules);
+        log::trace!(target: logging::NFT, "apply_egress_rules {}: {}", table, script);
+        if simulation::is_enabled() {
+            return simulation::add_nft_table(&table, script);
+        }
+        let mut cmd = Command::new("nft");
+        cmd.arg(script);
+        exec_command(cmd)?;
+        Ok(())
+    }
 
-        let internals = VirtualNetworkInternals {
-            associated_netns: ns_info,
-            dhcp: dhcp_internal,
-            associated_tables: vec![],
-        };
-        vnet.plugin_internals = Some(serialize_network_internals(&internals)?);
-        Ok(vnet)
+    /// Drops the egress table of a virtual network
+    fn delete_egress_rules(&self, vnet_uuid: Uuid) -> FResult<()> {
+        let table = egress_table(vnet_uuid);
+        if simulation::is_enabled() {
+            return simulation::del_nft_table(&table);
+        }
+        let mut cmd = Command::new("nft");
+        cmd.arg("delete").arg("table").arg("inet").arg(&table);
+        exec_command(cmd)?;
+        Ok(())
     }
 
-    async fn get_overlay_face_from_config(&self) -> FResult<Interface> {
-        let iface = self.config.overlay_iface.as_ref().ok_or(FError::NotFound)?;
-        let addresses = self.get_iface_addresses(iface.clone()).await?;
-        Ok(Interface {
-            if_name: iface.to_string(),
-            kind: InterfaceKind::ETHERNET,
-            addresses,
-            phy_address: None,
-        })
+    /// Rewrites the filter chains of the virtual networks whose scheduled
+    /// set rules were enabled or disabled since they were last written
+    async fn apply_rule_schedules(&self) {
+        let scheduled = self.state.read().await.scheduled_rules.clone();
+        let now = now_ms() / 1000;
+        for (vnet_uuid, applied) in scheduled {
+            if let Err(e) = self.apply_rule_schedule(vnet_uuid, &applied, now).await {
+                log::warn!(
+                    target: logging::RECONCILE,
+                    "Unable to apply rule schedules of {}: {}",
+                    vnet_uuid,
+                    e
+                );
+            }
+        }
     }
 
-    async fn get_dataplane_from_config(&self) -> FResult<Interface> {
-        let iface = self
-            .config
-            .dataplane_iface
-            .as_ref()
-            .ok_or(FError::NotFound)?;
-        let addresses = self.get_iface_addresses(iface.clone()).await?;
-        Ok(Interface {
-            if_name: iface.to_string(),
-            kind: InterfaceKind::ETHERNET,
-            addresses,
-            phy_address: None,
-        })
+    async fn apply_rule_schedule(
+        &self,
+        vnet_uuid: Uuid,
+        applied: &[bool],
+        now: u64,
+    ) -> FResult<()> {
+        let vnet = self.connector.local.get_virtual_network(vnet_uuid).await?;
+        let internals =
+            deserialize_network_internals(vnet.plugin_internals.as_ref().ok_or(FError::NotFound)?)?;
+        if active_set_rules(&internals.set_rules, now) != applied {
+            log::info!("Scheduled set rules of {} changed", vnet_uuid);
+            self.apply_set_rules(&vnet, &internals).await?;
+        }
+        Ok(())
     }
 
-    fn get_domain_socket_locator(&self) -> String {
-        self.config.zfilelocator.clone()
+    #[cfg(not(feature = "nat"))]
+    async fn update_nat_table(
+        &self,
+        _internals: &VirtualNetworkInternals,
+        _script: &(dyn Fn(&str) -> String + Sync),
+    ) -> FResult<()> {
+        Err(FError::Unimplemented)
     }
 
-    fn get_path(&self) -> Box<std::path::Path> {
-        self.config.path.clone()
+    /// Runs the nft commands built by `script` from the name of the NAT
+    /// table of a virtual network
+    #[cfg(feature = "nat")]
+    async fn update_nat_table(
+        &self,
+        internals: &VirtualNetworkInternals,
+        script: &(dyn Fn(&str) -> String + Sync),
+    ) -> FResult<()> {
+        let table = internals.associated_tables.first().ok_or_else(|| {
+            FError::NetworkingError("Virtual network has no NAT table".to_string())
+        })?;
+        let script = script(table);
+        log::trace!(target: logging::NFT, "update_nat_table {}: {}", table, script);
+        let mut cmd = Command::new("nft");
+        cmd.arg(script);
+        exec_command(cmd)?;
+        Ok(())
     }
 
-    fn get_run_path(&self) -> Box<std::path::Path> {
-        self.config.run_path.clone()
+    async fn remove_floating_ip(&self, floating_ip: &FloatingIP) -> FResult<()> {
+        self.remove_static_nat(
+            &floating_ip.table,
+            floating_ip.iface.clone(),
+            floating_ip.public_ip,
+        )
+        .await
     }
 
-    fn generate_random_interface_name(&self) -> String {
-        let iface: String = thread_rng()
-            .sample_iter(&Alphanumeric)
-            .take(8)
-            .map(char::from)
-            .collect();
-        iface
+    #[cfg(not(feature = "nat"))]
+    async fn remove_static_nat(
+        &self,
+        _table: &str,
+        _iface: String,
+        _public_ip: IPAddress,
+    ) -> FResult<()> {
+        Err(FError::Unimplemented)
     }
 
-    fn generate_random_netns_name(&self) -> String {
-        let ns: String = thread_rng()
-            .sample_iter(&Alphanumeric)
-            .take(8)
-            .map(char::from)
-            .collect();
-        format!("ns-{}", ns)
+    /// Removes the table and the public address added by
+    /// `configure_static_nat`
+    #[cfg(feature = "nat")]
+    async fn remove_static_nat(
+        &self,
+        table: &str,
+        iface: String,
+        public_ip: IPAddress,
+    ) -> FResult<()> {
+        self.delete_nft_table(table)?;
+        self.kernel().del_iface_address(iface, public_ip).await
     }
 
-    fn generate_random_nft_table_name(&self) -> String {
-        let tab: String = thread_rng()
-            .sample_iter(&Alphanumeric)
-            .take(10)
-            .map(char::from)
-            .collect();
-        format!("table{}", tab)
+    /// Removes the masquerade of a network, `table_name` is one of its
+    /// `associated_tables`
+    #[cfg(feature = "nat")]
+    async fn clean_nat(&self, table_name: String) -> FResult<()> {
+        log::trace!(target: logging::NFT, "clean_nat {}", table_name);
+        self.firewall_backend().clean_nat(&table_name)
+    }
+
+    /// Deletes an inet table written with `nft`, whatever the backend
+    /// of the masquerade
+    #[cfg(feature = "nat")]
+    fn delete_nft_table(&self, table_name: &str) -> FResult<()> {
+        log::trace!(target: logging::NFT, "delete_nft_table {}", table_name);
+        if simulation::is_enabled() {
+            return simulation::del_nft_table(table_name);
+        }
+        let mut cmd = Command::new("nft");
+        cmd.arg("delete").arg("table").arg("inet").arg(table_name);
+        exec_command(cmd)?;
+        Ok(())
     }
+}
 
+/// Kernel of the node, reached with rtnetlink through the handle of the
+/// plugin. The EBUSY retries are counted in the plugin statistics.
+struct NetlinkKernel<'a> {
+    plugin: &'a LinuxNetwork,
+}
+
+#[async_trait]
+impl KernelBackend for NetlinkKernel<'_> {
+    #[tracing::instrument(target = "netlink", level = "trace", skip(self))]
     async fn add_netns(&self, ns_name: String) -> FResult<()> {
-        log::trace!("add_netns {}", ns_name);
         NetlinkNetworkNamespace::add(ns_name)
             .await
             .map_err(|e| FError::NetworkingError(format!("{}", e)))
     }
 
+    #[tracing::instrument(target = "netlink", level = "trace", skip(self))]
     async fn del_netns(&self, ns_name: String) -> FResult<()> {
-        log::trace!("del_netns {}", ns_name);
         NetlinkNetworkNamespace::del(ns_name)
             .await
             .map_err(|e| FError::NetworkingError(format!("{}", e)))
     }
 
+    #[tracing::instrument(target = "netlink", level = "trace", skip(self))]
     async fn create_bridge(&self, br_name: String) -> FResult<()> {
-        log::trace!("create_bridge {}", br_name);
         let mut backoff = 100;
         loop {
-            let mut state = self.state.write().await;
+            let mut state = self.plugin.state.write().await;
             let res = state
                 .nl_handler
                 .link()
@@ -2609,7 +15016,122 @@ impl LinuxNetwork {
                 Ok(_) => return Ok(()),
                 Err(nlError::NetlinkError(nl)) => {
                     if nl.code == -16 {
-                        task::sleep(Duration::from_millis(backoff)).await;
+                        self.plugin.netlink_backoff("create_bridge", backoff).await;
+                    } else {
+                        return Err(FError::NetworkingError(format!("{}", nl)));
+                    }
+                }
+                Err(e) => return Err(FError::NetworkingError(format!("{}", e))),
+            }
+            backoff *= 2;
+            if backoff > 5000 {
+                return Err(self.plugin.netlink_timeout("create_bridge").await);
+            }
+        }
+    }
+
+    #[tracing::instrument(target = "netlink", level = "trace", skip(self))]
+    async fn create_wireguard(&self, iface: String) -> FResult<()> {
+        let mut backoff = 100;
+        loop {
+            let mut state = self.plugin.state.write().await;
+            let res = state
+                .nl_handler
+                .link()
+                .add()
+                .wireguard(iface.clone())
+                .execute()
+                .await;
+            drop(state);
+
+            match res {
+                Ok(_) => return Ok(()),
+                Err(nlError::NetlinkError(nl)) => {
+                    if nl.code == -16 {
+                        self.plugin
+                            .netlink_backoff("create_wireguard", backoff)
+                            .await;
+                    } else {
+                        return Err(FError::NetworkingError(format!("{}", nl)));
+                    }
+                }
+                Err(e) => return Err(FError::NetworkingError(format!("{}", e))),
+            }
+            backoff *= 2;
+            if backoff > 5000 {
+                return Err(self.plugin.netlink_timeout("create_wireguard").await);
+            }
+        }
+    }
+
+    #[tracing::instrument(target = "netlink", level = "trace", skip(self))]
+    async fn set_iface_bridge_port(&self, iface: &str, config: &BridgePortConfig) -> FResult<()> {
+        // the handle is cloned so the state is not locked during the retries
+        let handle = self.plugin.state.read().await.nl_handler.clone();
+        let index = handle
+            .link()
+            .get()
+            .set_name_filter(iface.to_string())
+            .execute()
+            .try_next()
+            .await
+            .map_err(|e| FError::NetworkingError(format!("{}", e)))?
+            .ok_or(FError::NotFound)?
+            .header
+            .index;
+        retry_busy(|| {
+            let mut handle = handle.clone();
+            async move { set_bridge_port(&mut handle, index, config).await }
+        })
+        .await
+        .map_err(|e| FError::NetworkingError(format!("{}", e)))
+    }
+
+    #[tracing::instrument(target = "netlink", level = "trace", skip(self))]
+    async fn flush_bridge_fdb(&self, br_name: &str) -> FResult<()> {
+        let mut state = self.plugin.state.write().await;
+        let mut links = state
+            .nl_handler
+            .link()
+            .get()
+            .set_name_filter(br_name.to_string())
+            .execute();
+        match links
+            .try_next()
+            .await
+            .map_err(|e| FError::NetworkingError(format!("{}", e)))?
+        {
+            Some(link) => flush_bridge_fdb(&mut state.nl_handler, link.header.index)
+                .await
+                .map_err(|e| FError::NetworkingError(format!("{}", e))),
+            None => Err(FError::NotFound),
+        }
+    }
+
+    #[tracing::instrument(target = "netlink", level = "trace", skip(self))]
+    async fn set_vf_config(&self, pf: &str, index: u32, config: &VFConfig) -> FResult<()> {
+        // the handle is cloned so the state is not locked during the requests
+        let handle = self.plugin.state.read().await.nl_handler.clone();
+        let link = handle
+            .link()
+            .get()
+            .set_name_filter(pf.to_string())
+            .execute()
+            .try_next()
+            .await
+            .map_err(|e| FError::NetworkingError(format!("{}", e)))?
+            .ok_or(FError::NotFound)?;
+        let mut backoff = 100;
+        loop {
+            let mut req = handle.link().set(link.header.index);
+            req.message_mut()
+                .nlas
+                .push(LinkNla::VfInfoList(sriov::vf_info_list(index, config)));
+            match req.execute().await {
+                Ok(_) => return Ok(()),
+                Err(nlError::NetlinkError(nl)) => {
+                    if nl.code == -16 {
+                        self.plugin.netlink_backoff("set_vf_config", backoff).await;
                     } else {
                         return Err(FError::NetworkingError(format!("{}", nl)));
                     }
@@ -2618,17 +15140,43 @@ impl LinuxNetwork {
             }
             backoff *= 2;
             if backoff > 5000 {
-                return Err(FError::NetworkingError("Timeout".to_string()));
+                return Err(self.plugin.netlink_timeout("set_vf_config").await);
             }
         }
     }
 
-    async fn create_veth(&self, iface_i: String, iface_e: String) -> FResult<()> {
-        log::trace!("create_veth {} {}", iface_i, iface_e);
+    #[tracing::instrument(target = "netlink", level = "trace", skip(self))]
+    fn create_ip_tunnel_iface(
+        &self,
+        iface: &str,
+        kind: IPTunnelKind,
+        local_addr: IPAddress,
+        remote_addr: IPAddress,
+        ttl: Option<u8>,
+    ) -> FResult<()> {
+        let mut cmd = Command::new("ip");
+        cmd.arg("link")
+            .arg("add")
+            .arg("name")
+            .arg(iface)
+            .arg("type")
+            .arg(format!("{}", kind))
+            .arg("local")
+            .arg(format!("{}", local_addr))
+            .arg("remote")
+            .arg(format!("{}", remote_addr));
+        if let Some(ttl) = ttl {
+            cmd.arg("ttl").arg(format!("{}", ttl));
+        }
+        exec_command(cmd)?;
+        Ok(())
+    }
 
+    #[tracing::instrument(target = "netlink", level = "trace", skip(self))]
+    async fn create_veth(&self, iface_i: String, iface_e: String) -> FResult<()> {
         let mut backoff = 100;
         loop {
-            let mut state = self.state.write().await;
+            let mut state = self.plugin.state.write().await;
 
             let res = state
                 .nl_handler
@@ -2642,7 +15190,7 @@ impl LinuxNetwork {
                 Ok(_) => return Ok(()),
                 Err(nlError::NetlinkError(nl)) => {
                     if nl.code == -16 {
-                        task::sleep(Duration::from_millis(backoff)).await;
+                        self.plugin.netlink_backoff("create_veth", backoff).await;
                     } else {
                         return Err(FError::NetworkingError(format!("{}", nl)));
                     }
@@ -2651,14 +15199,14 @@ impl LinuxNetwork {
             }
             backoff *= 2;
             if backoff > 5000 {
-                return Err(FError::NetworkingError("Timeout".to_string()));
+                return Err(self.plugin.netlink_timeout("create_veth").await);
             }
         }
     }
 
+    #[tracing::instrument(target = "netlink", level = "trace", skip(self))]
     async fn create_vlan(&self, iface: String, dev: String, tag: u16) -> FResult<()> {
-        let mut state = self.state.write().await;
-        log::trace!("create_vlan {} {} {}", iface, dev, tag);
+        let mut state = self.plugin.state.write().await;
         let mut backoff = 100;
 
         let mut links = state.nl_handler.link().get().set_name_filter(dev).execute();
@@ -2679,7 +15227,7 @@ impl LinuxNetwork {
                     Ok(_) => return Ok(()),
                     Err(nlError::NetlinkError(nl)) => {
                         if nl.code == -16 {
-                            task::sleep(Duration::from_millis(backoff)).await;
+                            self.plugin.netlink_backoff("create_vlan", backoff).await;
                         } else {
                             return Err(FError::NetworkingError(format!("{}", nl)));
                         }
@@ -2688,7 +15236,7 @@ impl LinuxNetwork {
                 }
                 backoff *= 2;
                 if backoff > 5000 {
-                    return Err(FError::NetworkingError("Timeout".to_string()));
+                    return Err(self.plugin.netlink_timeout("create_vlan").await);
                 }
             }
         } else {
@@ -2696,6 +15244,7 @@ impl LinuxNetwork {
         }
     }
 
+    #[tracing::instrument(target = "netlink", level = "trace", skip(self))]
     async fn create_mcast_vxlan(
         &self,
         iface: String,
@@ -2703,17 +15252,10 @@ impl LinuxNetwork {
         vni: u32,
         mcast_addr: IPAddress,
         port: u16,
+        options: &VXLANOptions,
     ) -> FResult<()> {
-        log::trace!(
-            "create_mcast_vxlan {} {} {} {} {}",
-            iface,
-            dev,
-            vni,
-            mcast_addr,
-            port
-        );
         let mut backoff = 100;
-        let mut state = self.state.write().await;
+        let mut state = self.plugin.state.write().await;
 
         let mut links = state.nl_handler.link().get().set_name_filter(dev).execute();
         if let Some(link) = links
@@ -2734,12 +15276,75 @@ impl LinuxNetwork {
                     IPAddress::V6(v6) => vxlan.group6(v6),
                 };
 
-                let res = vxlan.port(port).execute().await;
+                let res = apply_vxlan_options(vxlan, options)
+                    .port(port)
+                    .execute()
+                    .await;
+                match res {
+                    Ok(_) => return Ok(()),
+                    Err(nlError::NetlinkError(nl)) => {
+                        if nl.code == -16 {
+                            self.plugin
+                                .netlink_backoff("create_mcast_vxlan", backoff)
+                                .await;
+                        } else {
+                            return Err(FError::NetworkingError(format!("{}", nl)));
+                        }
+                    }
+                    Err(e) => return Err(FError::NetworkingError(format!("{}", e))),
+                }
+                backoff *= 2;
+                if backoff > 5000 {
+                    return Err(self.plugin.netlink_timeout("create_mcast_vxlan").await);
+                }
+            }
+        } else {
+            Err(FError::NotFound)
+        }
+    }
+
+    #[tracing::instrument(target = "netlink", level = "trace", skip(self))]
+    async fn create_unicast_vxlan(
+        &self,
+        iface: String,
+        dev: String,
+        vni: u32,
+        local_addr: IPAddress,
+        port: u16,
+        options: &VXLANOptions,
+    ) -> FResult<()> {
+        let mut backoff = 100;
+        let mut state = self.plugin.state.write().await;
+        let mut links = state.nl_handler.link().get().set_name_filter(dev).execute();
+        if let Some(link) = links
+            .try_next()
+            .await
+            .map_err(|e| FError::NetworkingError(format!("{}", e)))?
+        {
+            loop {
+                let vxlan = state
+                    .nl_handler
+                    .link()
+                    .add()
+                    .vxlan(iface.clone(), vni)
+                    .link(link.header.index);
+
+                let vxlan = match local_addr {
+                    IPAddress::V4(v4) => vxlan.local(v4),
+                    IPAddress::V6(v6) => vxlan.local6(v6),
+                };
+
+                let res = apply_vxlan_options(vxlan, options)
+                    .port(port)
+                    .execute()
+                    .await;
                 match res {
                     Ok(_) => return Ok(()),
                     Err(nlError::NetlinkError(nl)) => {
                         if nl.code == -16 {
-                            task::sleep(Duration::from_millis(backoff)).await;
+                            self.plugin
+                                .netlink_backoff("create_unicast_vxlan", backoff)
+                                .await;
                         } else {
                             return Err(FError::NetworkingError(format!("{}", nl)));
                         }
@@ -2748,7 +15353,7 @@ impl LinuxNetwork {
                 }
                 backoff *= 2;
                 if backoff > 5000 {
-                    return Err(FError::NetworkingError("Timeout".to_string()));
+                    return Err(self.plugin.netlink_timeout("create_unicast_vxlan").await);
                 }
             }
         } else {
@@ -2756,6 +15361,7 @@ impl LinuxNetwork {
         }
     }
 
+    #[tracing::instrument(target = "netlink", level = "trace", skip(self))]
     async fn create_ptp_vxlan(
         &self,
         iface: String,
@@ -2764,18 +15370,10 @@ impl LinuxNetwork {
         local_addr: IPAddress,
         remote_addr: IPAddress,
         port: u16,
+        options: &VXLANOptions,
     ) -> FResult<()> {
-        log::trace!(
-            "create_ptp_vxlan {} {} {} {} {} {}",
-            iface,
-            dev,
-            vni,
-            local_addr,
-            remote_addr,
-            port
-        );
         let mut backoff = 100;
-        let mut state = self.state.write().await;
+        let mut state = self.plugin.state.write().await;
         let mut links = state.nl_handler.link().get().set_name_filter(dev).execute();
         if let Some(link) = links
             .try_next()
@@ -2799,12 +15397,17 @@ impl LinuxNetwork {
                     IPAddress::V4(v4) => vxlan.remote(v4),
                     IPAddress::V6(v6) => vxlan.remote6(v6),
                 };
-                let res = vxlan.port(port).execute().await;
+                let res = apply_vxlan_options(vxlan, options)
+                    .port(port)
+                    .execute()
+                    .await;
                 match res {
                     Ok(_) => return Ok(()),
                     Err(nlError::NetlinkError(nl)) => {
                         if nl.code == -16 {
-                            task::sleep(Duration::from_millis(backoff)).await;
+                            self.plugin
+                                .netlink_backoff("create_ptp_vxlan", backoff)
+                                .await;
                         } else {
                             return Err(FError::NetworkingError(format!("{}", nl)));
                         }
@@ -2813,7 +15416,7 @@ impl LinuxNetwork {
                 }
                 backoff *= 2;
                 if backoff > 5000 {
-                    return Err(FError::NetworkingError("Timeout".to_string()));
+                    return Err(self.plugin.netlink_timeout("create_ptp_vxlan").await);
                 }
             }
         } else {
@@ -2821,9 +15424,9 @@ impl LinuxNetwork {
         }
     }
 
+    #[tracing::instrument(target = "netlink", level = "trace", skip(self))]
     async fn del_iface(&self, iface: String) -> FResult<()> {
-        log::trace!("del_iface {}", iface);
-        let mut state = self.state.write().await;
+        let mut state = self.plugin.state.write().await;
         let mut links = state
             .nl_handler
             .link()
@@ -2847,7 +15450,7 @@ impl LinuxNetwork {
                     Ok(_) => return Ok(()),
                     Err(nlError::NetlinkError(nl)) => {
                         if nl.code == -16 {
-                            task::sleep(Duration::from_millis(backoff)).await;
+                            self.plugin.netlink_backoff("del_iface", backoff).await;
                         } else {
                             return Err(FError::NetworkingError(format!("{}", nl)));
                         }
@@ -2856,7 +15459,7 @@ impl LinuxNetwork {
                 }
                 backoff *= 2;
                 if backoff > 5000 {
-                    return Err(FError::NetworkingError("Timeout".to_string()));
+                    return Err(self.plugin.netlink_timeout("del_iface").await);
                 }
             }
         } else {
@@ -2864,9 +15467,9 @@ impl LinuxNetwork {
         }
     }
 
+    #[tracing::instrument(target = "netlink", level = "trace", skip(self))]
     async fn set_iface_master(&self, iface: String, master: String) -> FResult<()> {
-        log::trace!("set_iface_master {} {}", iface, master);
-        let mut state = self.state.write().await;
+        let mut state = self.plugin.state.write().await;
         let mut links = state
             .nl_handler
             .link()
@@ -2902,7 +15505,9 @@ impl LinuxNetwork {
                         Ok(_) => return Ok(()),
                         Err(nlError::NetlinkError(nl)) => {
                             if nl.code == -16 {
-                                task::sleep(Duration::from_millis(backoff)).await;
+                                self.plugin
+                                    .netlink_backoff("set_iface_master", backoff)
+                                    .await;
                             } else {
                                 return Err(FError::NetworkingError(format!("{}", nl)));
                             }
@@ -2911,22 +15516,69 @@ impl LinuxNetwork {
                     }
                     backoff *= 2;
                     if backoff > 5000 {
-                        return Err(FError::NetworkingError("Timeout".to_string()));
+                        return Err(self.plugin.netlink_timeout("set_iface_master").await);
                     }
                 }
             } else {
-                log::error!("set_iface_master master not found");
+                log::error!(target: logging::NETLINK, "set_iface_master master not found");
+                Err(FError::NotFound)
+            }
+        } else {
+            log::error!(target: logging::NETLINK, "set_iface_master iface not found");
+            Err(FError::NotFound)
+        }
+    }
+
+    #[tracing::instrument(target = "netlink", level = "trace", skip(self))]
+    async fn get_iface_master(&self, iface: String) -> FResult<Option<String>> {
+        let mut state = self.plugin.state.write().await;
+        let mut links = state
+            .nl_handler
+            .link()
+            .get()
+            .set_name_filter(iface)
+            .execute();
+        if let Some(link) = links
+            .try_next()
+            .await
+            .map_err(|e| FError::NetworkingError(format!("{}", e)))?
+        {
+            let master_index = link.nlas.into_iter().find_map(|nla| match nla {
+                LinkNla::Master(index) => Some(index),
+                _ => None,
+            });
+            let master_index = match master_index {
+                Some(index) => index,
+                None => return Ok(None),
+            };
+            let mut masters = state
+                .nl_handler
+                .link()
+                .get()
+                .match_index(master_index)
+                .execute();
+            if let Some(master) = masters
+                .try_next()
+                .await
+                .map_err(|e| FError::NetworkingError(format!("{}", e)))?
+            {
+                Ok(master.nlas.into_iter().find_map(|nla| match nla {
+                    LinkNla::IfName(name) => Some(name),
+                    _ => None,
+                }))
+            } else {
+                log::error!(target: logging::NETLINK, "get_iface_master master not found");
                 Err(FError::NotFound)
             }
         } else {
-            log::error!("set_iface_master iface not found");
+            log::error!(target: logging::NETLINK, "get_iface_master iface not found");
             Err(FError::NotFound)
         }
     }
 
+    #[tracing::instrument(target = "netlink", level = "trace", skip(self))]
     async fn del_iface_master(&self, iface: String) -> FResult<()> {
-        log::trace!("del_iface_master {}", iface);
-        let mut state = self.state.write().await;
+        let mut state = self.plugin.state.write().await;
         let mut links = state
             .nl_handler
             .link()
@@ -2951,7 +15603,9 @@ impl LinuxNetwork {
                     Ok(_) => return Ok(()),
                     Err(nlError::NetlinkError(nl)) => {
                         if nl.code == -16 {
-                            task::sleep(Duration::from_millis(backoff)).await;
+                            self.plugin
+                                .netlink_backoff("del_iface_master", backoff)
+                                .await;
                         } else {
                             return Err(FError::NetworkingError(format!("{}", nl)));
                         }
@@ -2960,18 +15614,18 @@ impl LinuxNetwork {
                 }
                 backoff *= 2;
                 if backoff > 5000 {
-                    return Err(FError::NetworkingError("Timeout".to_string()));
+                    return Err(self.plugin.netlink_timeout("del_iface_master").await);
                 }
             }
         } else {
-            log::error!("del_iface_master iface not found");
+            log::error!(target: logging::NETLINK, "del_iface_master iface not found");
             Err(FError::NotFound)
         }
     }
 
+    #[tracing::instrument(target = "netlink", level = "trace", skip(self))]
     async fn add_iface_address(&self, iface: String, addr: IPAddress, prefix: u8) -> FResult<()> {
-        log::trace!("add_iface_address {} {} {}", iface, addr, prefix);
-        let mut state = self.state.write().await;
+        let mut state = self.plugin.state.write().await;
         let mut links = state
             .nl_handler
             .link()
@@ -2995,7 +15649,9 @@ impl LinuxNetwork {
                     Ok(_) => return Ok(()),
                     Err(nlError::NetlinkError(nl)) => {
                         if nl.code == -16 {
-                            task::sleep(Duration::from_millis(backoff)).await;
+                            self.plugin
+                                .netlink_backoff("add_iface_address", backoff)
+                                .await;
                         } else {
                             return Err(FError::NetworkingError(format!("{}", nl)));
                         }
@@ -3004,7 +15660,7 @@ impl LinuxNetwork {
                 }
                 backoff *= 2;
                 if backoff > 5000 {
-                    return Err(FError::NetworkingError("Timeout".to_string()));
+                    return Err(self.plugin.netlink_timeout("add_iface_address").await);
                 }
             }
         } else {
@@ -3012,9 +15668,9 @@ impl LinuxNetwork {
         }
     }
 
+    #[tracing::instrument(target = "netlink", level = "trace", skip(self))]
     async fn del_iface_address(&self, iface: String, addr: IPAddress) -> FResult<()> {
-        log::trace!("del_iface_address {} {}", iface, addr);
-        let mut state = self.state.write().await;
+        let mut state = self.plugin.state.write().await;
         use netlink_packet_route::rtnl::address::nlas::Nla;
         use netlink_packet_route::rtnl::address::AddressMessage;
         let octets = match addr {
@@ -3066,7 +15722,9 @@ impl LinuxNetwork {
                             Ok(_) => return Ok(()),
                             Err(nlError::NetlinkError(nl)) => {
                                 if nl.code == -16 {
-                                    task::sleep(Duration::from_millis(backoff)).await;
+                                    self.plugin
+                                        .netlink_backoff("del_iface_address", backoff)
+                                        .await;
                                 } else {
                                     return Err(FError::NetworkingError(format!("{}", nl)));
                                 }
@@ -3075,7 +15733,7 @@ impl LinuxNetwork {
                         }
                         backoff *= 2;
                         if backoff > 5000 {
-                            return Err(FError::NetworkingError("Timeout".to_string()));
+                            return Err(self.plugin.netlink_timeout("del_iface_address").await);
                         }
                     }
                 }
@@ -3086,9 +15744,9 @@ impl LinuxNetwork {
         }
     }
 
+    #[tracing::instrument(target = "netlink", level = "trace", skip(self))]
     async fn get_iface_addresses(&self, iface: String) -> FResult<Vec<IPAddress>> {
-        log::trace!("get_iface_addresses {}", iface);
-        let mut state = self.state.write().await;
+        let mut state = self.plugin.state.write().await;
         use netlink_packet_route::rtnl::address::nlas::Nla;
         use netlink_packet_route::rtnl::address::AddressMessage;
         let mut nl_addresses = Vec::new();
@@ -3143,9 +15801,9 @@ impl LinuxNetwork {
         }
     }
 
+    #[tracing::instrument(target = "netlink", level = "trace", skip(self))]
     async fn set_iface_name(&self, iface: String, new_name: String) -> FResult<()> {
-        log::trace!("set_iface_name {} {}", iface, new_name);
-        let mut state = self.state.write().await;
+        let mut state = self.plugin.state.write().await;
         let mut links = state
             .nl_handler
             .link()
@@ -3170,7 +15828,103 @@ impl LinuxNetwork {
                     Ok(_) => return Ok(()),
                     Err(nlError::NetlinkError(nl)) => {
                         if nl.code == -16 {
-                            task::sleep(Duration::from_millis(backoff)).await;
+                            self.plugin.netlink_backoff("set_iface_name", backoff).await;
+                        } else {
+                            return Err(FError::NetworkingError(format!("{}", nl)));
+                        }
+                    }
+                    Err(e) => return Err(FError::NetworkingError(format!("{}", e))),
+                }
+                backoff *= 2;
+                if backoff > 5000 {
+                    return Err(self.plugin.netlink_timeout("set_iface_name").await);
+                }
+            }
+        } else {
+            Err(FError::NotFound)
+        }
+    }
+
+    #[tracing::instrument(target = "netlink", level = "trace", skip(self))]
+    async fn get_iface_mac(&self, iface: String) -> FResult<Vec<u8>> {
+        let mut state = self.plugin.state.write().await;
+        let mut links = state
+            .nl_handler
+            .link()
+            .get()
+            .set_name_filter(iface)
+            .execute();
+        if let Some(link) = links
+            .try_next()
+            .await
+            .map_err(|e| FError::NetworkingError(format!("{}", e)))?
+        {
+            link.nlas
+                .into_iter()
+                .find_map(|nla| match nla {
+                    LinkNla::Address(address) => Some(address),
+                    _ => None,
+                })
+                .ok_or(FError::NotFound)
+        } else {
+            Err(FError::NotFound)
+        }
+    }
+
+    #[tracing::instrument(target = "netlink", level = "trace", skip(self))]
+    async fn get_iface_mtu(&self, iface: String) -> FResult<u32> {
+        let mut state = self.plugin.state.write().await;
+        let mut links = state
+            .nl_handler
+            .link()
+            .get()
+            .set_name_filter(iface)
+            .execute();
+        if let Some(link) = links
+            .try_next()
+            .await
+            .map_err(|e| FError::NetworkingError(format!("{}", e)))?
+        {
+            link.nlas
+                .into_iter()
+                .find_map(|nla| match nla {
+                    LinkNla::Mtu(mtu) => Some(mtu),
+                    _ => None,
+                })
+                .ok_or(FError::NotFound)
+        } else {
+            Err(FError::NotFound)
+        }
+    }
+
+    #[tracing::instrument(target = "netlink", level = "trace", skip(self))]
+    async fn set_iface_mtu(&self, iface: String, mtu: u32) -> FResult<()> {
+        let mut state = self.plugin.state.write().await;
+        let mut links = state
+            .nl_handler
+            .link()
+            .get()
+            .set_name_filter(iface)
+            .execute();
+        if let Some(link) = links
+            .try_next()
+            .await
+            .map_err(|e| FError::NetworkingError(format!("{}", e)))?
+        {
+            let mut backoff = 100;
+            loop {
+                let res = state
+                    .nl_handler
+                    .link()
+                    .set(link.header.index)
+                    .mtu(mtu)
+                    .execute()
+                    .await;
+                match res {
+                    Ok(_) => return Ok(()),
+                    Err(nlError::NetlinkError(nl)) => {
+                        if nl.code == -16 {
+                            self.plugin.netlink_backoff("set_iface_mtu", backoff).await;
                         } else {
                             return Err(FError::NetworkingError(format!("{}", nl)));
                         }
@@ -3179,7 +15933,7 @@ impl LinuxNetwork {
                 }
                 backoff *= 2;
                 if backoff > 5000 {
-                    return Err(FError::NetworkingError("Timeout".to_string()));
+                    return Err(self.plugin.netlink_timeout("set_iface_mtu").await);
                 }
             }
         } else {
@@ -3187,9 +15941,9 @@ impl LinuxNetwork {
         }
     }
 
+    #[tracing::instrument(target = "netlink", level = "trace", skip(self))]
     async fn set_iface_mac(&self, iface: String, address: Vec<u8>) -> FResult<()> {
-        log::trace!("set_iface_mac {} {:?}", iface, address);
-        let mut state = self.state.write().await;
+        let mut state = self.plugin.state.write().await;
         let mut links = state
             .nl_handler
             .link()
@@ -3214,7 +15968,7 @@ impl LinuxNetwork {
                     Ok(_) => return Ok(()),
                     Err(nlError::NetlinkError(nl)) => {
                         if nl.code == -16 {
-                            task::sleep(Duration::from_millis(backoff)).await;
+                            self.plugin.netlink_backoff("set_iface_mac", backoff).await;
                         } else {
                             return Err(FError::NetworkingError(format!("{}", nl)));
                         }
@@ -3223,7 +15977,7 @@ impl LinuxNetwork {
                 }
                 backoff *= 2;
                 if backoff > 5000 {
-                    return Err(FError::NetworkingError("Timeout".to_string()));
+                    return Err(self.plugin.netlink_timeout("set_iface_mac").await);
                 }
             }
         } else {
@@ -3231,18 +15985,18 @@ impl LinuxNetwork {
         }
     }
 
+    #[tracing::instrument(target = "netlink", level = "trace", skip(self))]
     async fn set_iface_ns(&self, iface: String, netns: String) -> FResult<()> {
-        log::trace!("set_iface_ns {} {}", iface, netns);
         const NETNS_PATH: &str = "/run/netns/";
         let netns = format!("{}{}", NETNS_PATH, netns);
-        let mut state = self.state.write().await;
+        let mut state = self.plugin.state.write().await;
         let nsfile = std::fs::File::open(netns)?;
         let raw_fd = nsfile.into_raw_fd();
         let mut links = state
             .nl_handler
             .link()
             .get()
-            .set_name_filter(iface)
+            .set_name_filter(iface.clone())
             .execute();
         if let Some(link) = links
             .try_next()
@@ -3259,10 +16013,15 @@ impl LinuxNetwork {
                     .execute()
                     .await;
                 match res {
-                    Ok(_) => return Ok(()),
+                    Ok(_) => {
+                        // the events wait for the state lock, the mark is
+                        // there before the deletion is handled
+                        state.moved_links.insert(iface, now_ms());
+                        return Ok(());
+                    }
                     Err(nlError::NetlinkError(nl)) => {
                         if nl.code == -16 {
-                            task::sleep(Duration::from_millis(backoff)).await;
+                            self.plugin.netlink_backoff("set_iface_ns", backoff).await;
                         } else {
                             return Err(FError::NetworkingError(format!("{}", nl)));
                         }
@@ -3271,7 +16030,7 @@ impl LinuxNetwork {
                 }
                 backoff *= 2;
                 if backoff > 5000 {
-                    return Err(FError::NetworkingError("Timeout".to_string()));
+                    return Err(self.plugin.netlink_timeout("set_iface_ns").await);
                 }
             }
         } else {
@@ -3279,9 +16038,9 @@ impl LinuxNetwork {
         }
     }
 
+    #[tracing::instrument(target = "netlink", level = "trace", skip(self))]
     async fn set_iface_default_ns(&self, iface: String) -> FResult<()> {
-        log::trace!("set_iface_default_ns {}", iface);
-        let mut state = self.state.write().await;
+        let mut state = self.plugin.state.write().await;
         let mut links = state
             .nl_handler
             .link()
@@ -3306,7 +16065,9 @@ impl LinuxNetwork {
                     Ok(_) => return Ok(()),
                     Err(nlError::NetlinkError(nl)) => {
                         if nl.code == -16 {
-                            task::sleep(Duration::from_millis(backoff)).await;
+                            self.plugin
+                                .netlink_backoff("set_iface_default_ns", backoff)
+                                .await;
                         } else {
                             return Err(FError::NetworkingError(format!("{}", nl)));
                         }
@@ -3315,7 +16076,7 @@ impl LinuxNetwork {
                 }
                 backoff *= 2;
                 if backoff > 5000 {
-                    return Err(FError::NetworkingError("Timeout".to_string()));
+                    return Err(self.plugin.netlink_timeout("set_iface_default_ns").await);
                 }
             }
         } else {
@@ -3323,9 +16084,9 @@ impl LinuxNetwork {
         }
     }
 
+    #[tracing::instrument(target = "netlink", level = "trace", skip(self))]
     async fn set_iface_up(&self, iface: String) -> FResult<()> {
-        log::trace!("set_iface_up {}", iface);
-        let mut state = self.state.write().await;
+        let mut state = self.plugin.state.write().await;
         let mut links = state
             .nl_handler
             .link()
@@ -3350,7 +16111,7 @@ impl LinuxNetwork {
                     Ok(_) => return Ok(()),
                     Err(nlError::NetlinkError(nl)) => {
                         if nl.code == -16 {
-                            task::sleep(Duration::from_millis(backoff)).await;
+                            self.plugin.netlink_backoff("set_iface_up", backoff).await;
                         } else {
                             return Err(FError::NetworkingError(format!("{}", nl)));
                         }
@@ -3359,7 +16120,7 @@ impl LinuxNetwork {
                 }
                 backoff *= 2;
                 if backoff > 5000 {
-                    return Err(FError::NetworkingError("Timeout".to_string()));
+                    return Err(self.plugin.netlink_timeout("set_iface_up").await);
                 }
             }
         } else {
@@ -3367,9 +16128,9 @@ impl LinuxNetwork {
         }
     }
 
+    #[tracing::instrument(target = "netlink", level = "trace", skip(self))]
     async fn set_iface_down(&self, iface: String) -> FResult<()> {
-        log::trace!("set_iface_down {}", iface);
-        let mut state = self.state.write().await;
+        let mut state = self.plugin.state.write().await;
         let mut links = state
             .nl_handler
             .link()
@@ -3394,7 +16155,7 @@ impl LinuxNetwork {
                     Ok(_) => return Ok(()),
                     Err(nlError::NetlinkError(nl)) => {
                         if nl.code == -16 {
-                            task::sleep(Duration::from_millis(backoff)).await;
+                            self.plugin.netlink_backoff("set_iface_down", backoff).await;
                         } else {
                             return Err(FError::NetworkingError(format!("{}", nl)));
                         }
@@ -3403,7 +16164,7 @@ impl LinuxNetwork {
                 }
                 backoff *= 2;
                 if backoff > 5000 {
-                    return Err(FError::NetworkingError("Timeout".to_string()));
+                    return Err(self.plugin.netlink_timeout("set_iface_down").await);
                 }
             }
         } else {
@@ -3411,9 +16172,9 @@ impl LinuxNetwork {
         }
     }
 
+    #[tracing::instrument(target = "netlink", level = "trace", skip(self))]
     async fn iface_exists(&self, iface: String) -> FResult<bool> {
-        log::trace!("iface_exists {}", iface);
-        let mut state = self.state.write().await;
+        let mut state = self.plugin.state.write().await;
         let mut links = state
             .nl_handler
             .link()
@@ -3430,234 +16191,4 @@ impl LinuxNetwork {
             Ok(false)
         }
     }
-
-    async fn spawn_dnsmasq(&self, config_file: String) -> FResult<Child> {
-        let child = Command::new("dnsmasq")
-            .arg("-C")
-            .arg(config_file)
-            .stdin(Stdio::null())
-            .spawn()
-            .map_err(|e| FError::NetworkingError(format!("{}", e)))?;
-        Ok(child)
-    }
-
-    async fn create_dnsmasq_config(
-        &self,
-        iface: &str,
-        pid_file: &str,
-        lease_file: &str,
-        log_file: &str,
-        dhcp_start: IPAddress,
-        dhcp_end: IPAddress,
-        default_gw: IPAddress,
-        default_dns: IPAddress,
-    ) -> FResult<String> {
-        log::trace!(
-            "create_dnsmasq_config {} {} {} {} {} {} {}",
-            iface,
-            pid_file,
-            lease_file,
-            dhcp_start,
-            dhcp_end,
-            default_gw,
-            default_dns,
-        );
-        let mut context = Context::new();
-        let template_path = self
-            .get_path()
-            .join("*.conf")
-            .to_str()
-            .ok_or(FError::EncodingError)?
-            .to_string();
-        let templates =
-            Tera::new(&template_path).map_err(|e| FError::NetworkingError(format!("{}", e)))?;
-        context.insert("dhcp_interface", iface);
-        context.insert("lease_file", lease_file);
-        context.insert("dhcp_pid", pid_file);
-        context.insert("dhcp_log", log_file);
-        context.insert("dhcp_start", &format!("{}", dhcp_start));
-        context.insert("dhcp_end", &format!("{}", dhcp_end));
-        context.insert("default_gw", &format!("{}", default_gw));
-        context.insert("default_dns", &format!("{}", default_dns));
-
-        match templates.render("dnsmasq.conf", &context) {
-            Ok(t) => Ok(t),
-            Err(e) => {
-                log::error!("Parsing error(s): {} {}", e, e.source().unwrap());
-                Err(FError::NetworkingError(format!(
-                    "{} {}",
-                    e,
-                    e.source().unwrap()
-                )))
-            }
-        }
-    }
-
-    async fn configure_nat(&self, net: IpNetwork, iface: &str) -> FResult<String> {
-        let table_name = self.generate_random_nft_table_name();
-        let chain_name = String::from("postrouting");
-        // Create a batch. This is used to store all the netlink messages we will later send.
-        // Creating a new batch also automatically writes the initial batch begin message needed
-        // to tell netlink this is a single transaction that might arrive over multiple netlink packets.
-        let mut batch = Batch::new();
-        // Create a netfilter table operating on both IPv4 and IPv6 (ProtoFamily::Inet)
-        let table = Table::new(
-            &CString::new(table_name.clone())
-                .map_err(|e| FError::NetworkingError(format!("{}", e)))?,
-            ProtoFamily::Inet,
-        );
-        // Add the table to the batch with the `MsgType::Add` type, thus instructing netfilter to add
-        // this table under its `ProtoFamily::Inet` ruleset.
-        batch.add(&table, nftnl::MsgType::Add);
-
-        // Create a chain under the table we created above.
-        let mut chain = Chain::new(
-            &CString::new(chain_name).map_err(|e| FError::NetworkingError(format!("{}", e)))?,
-            &table,
-        );
-
-        // Hook the chains to the input and output event hooks, with highest priority (priority zero).
-        // See the `Chain::set_hook` documentation for details.
-        chain.set_hook(nftnl::Hook::PostRouting, 0);
-        // Set the chain type.
-        // See the `Chain::set_type` documentation for details.
-        chain.set_type(nftnl::ChainType::Nat);
-
-        // Add the two chains to the batch with the `MsgType` to tell netfilter to create the chains
-        // under the table.
-        batch.add(&chain, nftnl::MsgType::Add);
-
-        // Create a new rule object under the input chain.
-        let mut natting_rule = Rule::new(&chain);
-
-        // Lookup the interface index of the default gw interface.
-        let iface_index = iface_index(iface)?;
-        //Type of payload is source address
-        natting_rule.add_expr(&nft_expr!(payload ipv4 saddr));
-
-        //netmask of the network
-        natting_rule.add_expr(&nft_expr!(bitwise mask net.mask(), xor 0u32));
-
-        //comparing ip portion of the address
-        natting_rule.add_expr(&nft_expr!(cmp == net.ip()));
-
-        // passing the index of output interface oif
-        natting_rule.add_expr(&nft_expr!(meta oif));
-
-        //use interface with this index
-        natting_rule.add_expr(&nft_expr!(cmp == iface_index));
-
-        // Add masquerading
-        natting_rule.add_expr(&nft_expr!(masquerade));
-
-        // Add the rule to the batch.
-        batch.add(&natting_rule, nftnl::MsgType::Add);
-
-        // === FINALIZE THE TRANSACTION AND SEND THE DATA TO NETFILTER ===
-
-        // Finalize the batch. This means the batch end message is written into the batch, telling
-        // netfilter the we reached the end of the transaction message. It's also converted to a type
-        // that implements `IntoIterator<Item = &'a [u8]>`, thus allowing us to get the raw netlink data
-        // out so it can be sent over a netlink socket to netfilter.
-        let finalized_batch = batch.finalize();
-
-        fn send_and_process(batch: &FinalizedBatch) -> FResult<()> {
-            // Create a netlink socket to netfilter.
-            let socket = mnl::Socket::new(mnl::Bus::Netfilter)?;
-            // Send all the bytes in the batch.
-            socket.send_all(batch)?;
-            // Try to parse the messages coming back from netfilter. This part is still very unclear.
-            let portid = socket.portid();
-            let mut buffer = vec![0; nftnl::nft_nlmsg_maxsize() as usize];
-            let very_unclear_what_this_is_for = 2;
-            while let Some(message) = socket_recv(&socket, &mut buffer[..])? {
-                match mnl::cb_run(message, very_unclear_what_this_is_for, portid)? {
-                    mnl::CbResult::Stop => {
-                        break;
-                    }
-                    mnl::CbResult::Ok => (),
-                }
-            }
-            Ok(())
-        }
-
-        fn socket_recv<'a>(socket: &mnl::Socket, buf: &'a mut [u8]) -> FResult<Option<&'a [u8]>> {
-            let ret = socket.recv(buf)?;
-            if ret > 0 {
-                Ok(Some(&buf[..ret]))
-            } else {
-                Ok(None)
-            }
-        }
-
-        // Look up the interface index for a given interface name.
-        fn iface_index(name: &str) -> FResult<libc::c_uint> {
-            let c_name =
-                CString::new(name).map_err(|e| FError::NetworkingError(format!("{}", e)))?;
-            let index = unsafe { libc::if_nametoindex(c_name.as_ptr()) };
-            if index == 0 {
-                Err(FError::from(std::io::Error::last_os_error()))
-            } else {
-                Ok(index)
-            }
-        }
-
-        send_and_process(&finalized_batch)?;
-        Ok(table_name)
-    }
-
-    async fn clean_nat(&self, table_name: String) -> FResult<()> {
-        // Create a batch. This is used to store all the netlink messages we will later send.
-        // Creating a new batch also automatically writes the initial batch begin message needed
-        // to tell netlink this is a single transaction that might arrive over multiple netlink packets.
-        let mut batch = Batch::new();
-        // Create a netfilter table operating on both IPv4 and IPv6 (ProtoFamily::Inet)
-        let table = Table::new(
-            &CString::new(table_name).map_err(|e| FError::NetworkingError(format!("{}", e)))?,
-            ProtoFamily::Inet,
-        );
-        // Add the table to the batch with the `MsgType::Del` type, thus instructing netfilter to remove
-        // this table under its `ProtoFamily::Inet` ruleset.
-        batch.add(&table, nftnl::MsgType::Del);
-
-        // === FINALIZE THE TRANSACTION AND SEND THE DATA TO NETFILTER ===
-
-        // Finalize the batch. This means the batch end message is written into the batch, telling
-        // netfilter the we reached the end of the transaction message. It's also converted to a type
-        // that implements `IntoIterator<Item = &'a [u8]>`, thus allowing us to get the raw netlink data
-        // out so it can be sent over a netlink socket to netfilter.
-        let finalized_batch = batch.finalize();
-
-        fn send_and_process(batch: &FinalizedBatch) -> FResult<()> {
-            // Create a netlink socket to netfilter.
-            let socket = mnl::Socket::new(mnl::Bus::Netfilter)?;
-            // Send all the bytes in the batch.
-            socket.send_all(batch)?;
-            // Try to parse the messages coming back from netfilter. This part is still very unclear.
-            let portid = socket.portid();
-            let mut buffer = vec![0; nftnl::nft_nlmsg_maxsize() as usize];
-            let very_unclear_what_this_is_for = 2;
-            while let Some(message) = socket_recv(&socket, &mut buffer[..])? {
-                match mnl::cb_run(message, very_unclear_what_this_is_for, portid)? {
-                    mnl::CbResult::Stop => {
-                        break;
-                    }
-                    mnl::CbResult::Ok => (),
-                }
-            }
-            Ok(())
-        }
-
-        fn socket_recv<'a>(socket: &mnl::Socket, buf: &'a mut [u8]) -> FResult<Option<&'a [u8]>> {
-            let ret = socket.recv(buf)?;
-            if ret > 0 {
-                Ok(Some(&buf[..ret]))
-            } else {
-                Ok(None)
-            }
-        }
-
-        send_and_process(&finalized_batch)?;
-        Ok(())
-    }
 }
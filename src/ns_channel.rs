@@ -0,0 +1,315 @@
+/*********************************************************************************
+* Copyright (c) 2018,2021 ADLINK Technology Inc.
+*
+* This program and the accompanying materials are made available under the
+* terms of the Eclipse Public License 2.0 which is available at
+* http://www.eclipse.org/legal/epl-2.0, or the Apache Software License 2.0
+* which is available at https://www.apache.org/licenses/LICENSE-2.0.
+*
+* SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+* Contributors:
+*   ADLINK fog05 team, <fog05@adlink-labs.tech>
+*********************************************************************************/
+
+//! Transport used by the plugin to talk with the Namespace Managers.
+//!
+//! By default managers are reached through zenoh RPC, with the
+//! `local-ns-channel` feature they can also be served over a unix socket,
+//! so that a manager does not need its own zenoh session. The namespaces
+//! of a shared manager are reached through its single zenoh server.
+
+use fog05_sdk::fresult::{FError, FResult};
+use fog05_sdk::types::IPAddress;
+
+use ipnetwork::IpNetwork;
+
+use crate::sysctl::{MartianCounters, ReversePathFilter};
+use crate::types::{
+    BridgePortConfig, DefaultRoute, IPTunnelKind, LinkStatsDump, NamespaceManagerClient,
+    SharedNamespaceManagerClient, SpanContext,
+};
+
+#[cfg(feature = "local-ns-channel")]
+use async_std::os::unix::net::UnixStream;
+#[cfg(feature = "local-ns-channel")]
+use async_std::prelude::*;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "local-ns-channel")]
+use std::time::Duration;
+use tracing::Instrument;
+
+use uuid::Uuid;
+
+/// Same timeout used by the zenoh NamespaceManager service
+#[cfg(feature = "local-ns-channel")]
+pub const NS_CHANNEL_TIMEOUT: u64 = 60;
+
+/// Largest frame accepted on the local socket, in bytes
+#[cfg(feature = "local-ns-channel")]
+pub const NS_CHANNEL_MAX_FRAME: usize = 16 * 1024 * 1024;
+
+#[derive(Clone, Debug)]
+pub enum NamespaceManagerChannel {
+    Zenoh(NamespaceManagerClient),
+    /// Namespace served by the shared manager
+    Shared(SharedNamespaceManagerClient, Uuid),
+    #[cfg(feature = "local-ns-channel")]
+    Unix(UnixNamespaceManagerClient),
+    /// Manager of a namespace of the simulated kernel, its requests
+    /// are applied to the model by the plugin
+    Simulated(String),
+}
+
+impl NamespaceManagerChannel {
+    pub async fn verify_server(&self) -> FResult<bool> {
+        match self {
+            NamespaceManagerChannel::Zenoh(client) => Ok(client.verify_server().await?),
+            NamespaceManagerChannel::Shared(client, ns_uuid) => {
+                Ok(client.list_namespaces().await??.contains(ns_uuid))
+            }
+            #[cfg(feature = "local-ns-channel")]
+            NamespaceManagerChannel::Unix(client) => client.verify_server().await,
+            NamespaceManagerChannel::Simulated(ns_name) => Ok(crate::simulation::snapshot()
+                .map_or(false, |k| {
+                    k.processes
+                        .iter()
+                        .any(|p| p.netns.as_deref() == Some(ns_name.as_str()))
+                })),
+        }
+    }
+
+    /// Checks that the endpoint of a terminated manager is not served
+    /// anymore, the socket left behind by an unix manager is removed.
+    pub async fn release_endpoint(&self) -> FResult<()> {
+        if matches!(self.verify_server().await, Ok(true)) {
+            return Err(FError::NetworkingError(
+                "ns-manager endpoint is still served".to_string(),
+            ));
+        }
+        #[cfg(feature = "local-ns-channel")]
+        if let NamespaceManagerChannel::Unix(client) = self {
+            if client.path.exists() {
+                std::fs::remove_file(&client.path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Generates the NamespaceManagerChannel methods, mirroring the
+/// NamespaceManager service, and the unix socket requests.
+macro_rules! ns_channel_methods {
+    ($( $name:ident => $req:ident { $($arg:ident : $ty:ty),* } -> $ret:ty ;)*) => {
+        /// Requests sent over the unix socket or to the simulated
+        /// kernel, one for each method of the NamespaceManager service
+        #[derive(Serialize, Deserialize, Debug, Clone)]
+        pub enum NSManagerRequest {
+            $( $req { $($arg: $ty),* }, )*
+        }
+
+        impl NamespaceManagerChannel {
+            $(
+                pub async fn $name(&self, $($arg: $ty),*) -> FResult<FResult<$ret>> {
+                    let span = tracing::trace_span!(
+                        target: crate::logging::NS_MANAGER,
+                        "ns_manager_request",
+                        method = stringify!($name),
+                        request_id = tracing::field::Empty
+                    );
+                    // the manager serves the request in a span with the same id
+                    let ctx = SpanContext::new(&span);
+                    span.record("request_id", &tracing::field::display(ctx.request_id));
+                    async move {
+                        match self {
+                            NamespaceManagerChannel::Zenoh(client) => {
+                                Ok(client.$name(ctx, $($arg),*).await?)
+                            }
+                            NamespaceManagerChannel::Shared(client, ns_uuid) => {
+                                let req = NSManagerRequest::$req { $($arg),* };
+                                match client.namespace_request(*ns_uuid, ctx, req).await? {
+                                    Ok(data) => decode_response(&data),
+                                    Err(e) => Ok(Err(e)),
+                                }
+                            }
+                            #[cfg(feature = "local-ns-channel")]
+                            NamespaceManagerChannel::Unix(client) => {
+                                client.call(ctx, NSManagerRequest::$req { $($arg),* }).await
+                            }
+                            NamespaceManagerChannel::Simulated(ns_name) => {
+                                crate::simulation::ns_request(ns_name, NSManagerRequest::$req { $($arg),* })
+                            }
+                        }
+                    }
+                    .instrument(span)
+                    .await
+                }
+            )*
+        }
+    };
+}
+
+ns_channel_methods! {
+    set_virtual_interface_up => SetVirtualInterfaceUp { iface: String } -> ();
+    set_virtual_interface_down => SetVirtualInterfaceDown { iface: String } -> ();
+    set_default_route => SetDefaultRoute { iface: String } -> ();
+    add_virtual_interface_default_route => AddVirtualInterfaceDefaultRoute {
+        iface: String,
+        route: DefaultRoute
+    } -> ();
+    del_virtual_interface_default_route => DelVirtualInterfaceDefaultRoute {
+        iface: String,
+        route: DefaultRoute
+    } -> ();
+    probe_gateway => ProbeGateway { iface: String, gateway: IPAddress } -> bool;
+    dump_link_stats => DumpLinkStats {} -> LinkStatsDump;
+    set_reverse_path_filter => SetReversePathFilter {
+        iface: Option<String>,
+        filter: ReversePathFilter
+    } -> ();
+    get_martian_counters => GetMartianCounters {} -> MartianCounters;
+    set_virtual_interface_bridge_port => SetVirtualInterfaceBridgePort {
+        iface: String,
+        config: BridgePortConfig
+    } -> ();
+    flush_virtual_interface_fdb => FlushVirtualInterfaceFdb { iface: String } -> ();
+    add_virtual_interface_route => AddVirtualInterfaceRoute {
+        iface: String,
+        destination: IpNetwork,
+        gateway: IPAddress
+    } -> ();
+    del_virtual_interface_route => DelVirtualInterfaceRoute {
+        iface: String,
+        destination: IpNetwork,
+        gateway: IPAddress
+    } -> ();
+    check_virtual_interface_exists => CheckVirtualInterfaceExists { iface: String } -> bool;
+    move_virtual_interface_into_default_ns => MoveVirtualInterfaceIntoDefaultNs {
+        iface: String
+    } -> ();
+    set_virtual_interface_mac => SetVirtualInterfaceMac { iface: String, address: Vec<u8> } -> ();
+    set_virtual_interface_mtu => SetVirtualInterfaceMtu { iface: String, mtu: u32 } -> ();
+    set_virtual_interface_name => SetVirtualInterfaceName { iface: String, name: String } -> ();
+    del_virtual_interface_address => DelVirtualInterfaceAddress {
+        iface: String,
+        addr: IPAddress
+    } -> ();
+    get_virtual_interface_addresses => GetVirtualInterfaceAddresses {
+        iface: String
+    } -> Vec<IPAddress>;
+    add_virtual_interface_address => AddVirtualInterfaceAddress {
+        iface: String,
+        addr: Option<IpNetwork>
+    } -> Vec<IPAddress>;
+    set_virtual_interface_master => SetVirtualInterfaceMaster {
+        iface: String,
+        master: String
+    } -> ();
+    get_virtual_interface_master => GetVirtualInterfaceMaster { iface: String } -> Option<String>;
+    set_virtual_interface_nomaster => SetVirtualInterfaceNomaster { iface: String } -> ();
+    del_virtual_interface => DelVirtualInterface { iface: String } -> ();
+    add_virtual_interface_ptp_vxlan => AddVirtualInterfacePtpVxlan {
+        iface: String,
+        dev: String,
+        vni: u32,
+        local_addr: IPAddress,
+        remote_addr: IPAddress,
+        port: u16
+    } -> ();
+    add_virtual_interface_mcast_vxlan => AddVirtualInterfaceMcastVxlan {
+        iface: String,
+        dev: String,
+        vni: u32,
+        mcast_addr: IPAddress,
+        port: u16
+    } -> ();
+    add_virtual_interface_vlan => AddVirtualInterfaceVlan {
+        iface: String,
+        dev: String,
+        tag: u16
+    } -> ();
+    add_virtual_interface_ip_tunnel => AddVirtualInterfaceIpTunnel {
+        iface: String,
+        kind: IPTunnelKind,
+        local_addr: IPAddress,
+        remote_addr: IPAddress,
+        ttl: Option<u8>
+    } -> ();
+    add_virtual_interface_veth => AddVirtualInterfaceVeth { iface_i: String, iface_e: String } -> ();
+    add_virtual_interface_bridge => AddVirtualInterfaceBridge { br_name: String } -> ();
+    list_interfaces => ListInterfaces {} -> Vec<String>;
+}
+
+/// Result of a request encoded by the manager
+fn decode_response<T: DeserializeOwned>(data: &[u8]) -> FResult<FResult<T>> {
+    bincode::deserialize::<FResult<T>>(data).map_err(|e| FError::NetworkingError(format!("{}", e)))
+}
+
+/// Client for a Namespace Manager served over a unix socket,
+/// every call opens a new connection to the manager.
+#[cfg(feature = "local-ns-channel")]
+#[derive(Clone, Debug)]
+pub struct UnixNamespaceManagerClient {
+    pub path: std::path::PathBuf,
+}
+
+#[cfg(feature = "local-ns-channel")]
+impl UnixNamespaceManagerClient {
+    pub fn new(path: std::path::PathBuf) -> Self {
+        Self { path }
+    }
+
+    pub async fn verify_server(&self) -> FResult<bool> {
+        Ok(UnixStream::connect(&self.path).await.is_ok())
+    }
+
+    async fn call<T: DeserializeOwned>(
+        &self,
+        ctx: SpanContext,
+        req: NSManagerRequest,
+    ) -> FResult<FResult<T>> {
+        let call = async {
+            let mut stream = UnixStream::connect(&self.path).await?;
+            let data = bincode::serialize(&(ctx, req))
+                .map_err(|e| FError::NetworkingError(format!("{}", e)))?;
+            write_frame(&mut stream, &data).await?;
+            let data = read_frame(&mut stream).await?;
+            decode_response(&data)
+        };
+        async_std::future::timeout(Duration::from_secs(NS_CHANNEL_TIMEOUT), call)
+            .await
+            .map_err(|e| FError::NetworkingError(format!("{}", e)))?
+    }
+}
+
+/// Frames are a big endian u32 length followed by the bincode payload,
+/// requests are sent as a `(SpanContext, NSManagerRequest)` tuple
+#[cfg(feature = "local-ns-channel")]
+pub async fn write_frame(stream: &mut UnixStream, data: &[u8]) -> FResult<()> {
+    if data.len() > NS_CHANNEL_MAX_FRAME {
+        return Err(FError::NetworkingError(format!(
+            "Frame of {} bytes is too large",
+            data.len()
+        )));
+    }
+    stream.write_all(&(data.len() as u32).to_be_bytes()).await?;
+    stream.write_all(data).await?;
+    Ok(())
+}
+
+#[cfg(feature = "local-ns-channel")]
+pub async fn read_frame(stream: &mut UnixStream) -> FResult<Vec<u8>> {
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len).await?;
+    let len = u32::from_be_bytes(len) as usize;
+    // the length comes from the peer, it is checked before allocating
+    if len > NS_CHANNEL_MAX_FRAME {
+        return Err(FError::NetworkingError(format!(
+            "Frame of {} bytes is too large",
+            len
+        )));
+    }
+    let mut data = vec![0u8; len];
+    stream.read_exact(&mut data).await?;
+    Ok(data)
+}
@@ -0,0 +1,102 @@
+/*********************************************************************************
+* Copyright (c) 2018,2021 ADLINK Technology Inc.
+*
+* This program and the accompanying materials are made available under the
+* terms of the Eclipse Public License 2.0 which is available at
+* http://www.eclipse.org/legal/epl-2.0, or the Apache Software License 2.0
+* which is available at https://www.apache.org/licenses/LICENSE-2.0.
+*
+* SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+* Contributors:
+*   ADLINK fog05 team, <fog05@adlink-labs.tech>
+*********************************************************************************/
+
+//! Async runtime used to spawn tasks and wait.
+//!
+//! Tasks run on async-std by default and on tokio with the `tokio-runtime`
+//! feature. Channels and locks do not depend on the runtime. zenoh and
+//! zrpc keep driving their own tasks on async-std in both cases.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+
+pub use async_std::channel::{bounded, Receiver, Sender};
+
+/// Handle of a spawned task, resolves to the output of the task
+pub type JoinHandle<T> = Pin<Box<dyn Future<Output = T> + Send + Sync>>;
+
+#[cfg(not(feature = "tokio-runtime"))]
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    Box::pin(async_std::task::spawn(future))
+}
+
+#[cfg(not(feature = "tokio-runtime"))]
+pub fn spawn_blocking<F, T>(f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    Box::pin(async_std::task::spawn_blocking(f))
+}
+
+#[cfg(not(feature = "tokio-runtime"))]
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    async_std::task::block_on(future)
+}
+
+#[cfg(not(feature = "tokio-runtime"))]
+pub async fn sleep(duration: Duration) {
+    async_std::task::sleep(duration).await
+}
+
+/// Panics of the task are propagated to the awaiting one
+#[cfg(feature = "tokio-runtime")]
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    let handle = tokio::task::spawn(future);
+    Box::pin(async move { unwrap_join(handle.await) })
+}
+
+#[cfg(feature = "tokio-runtime")]
+pub fn spawn_blocking<F, T>(f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let handle = tokio::task::spawn_blocking(f);
+    Box::pin(async move { unwrap_join(handle.await) })
+}
+
+/// Uses the current runtime when called from one of its blocking
+/// threads, otherwise a new runtime is started
+#[cfg(feature = "tokio-runtime")]
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    match tokio::runtime::Handle::try_current() {
+        Ok(handle) => handle.block_on(future),
+        Err(_) => tokio::runtime::Runtime::new()
+            .expect("Unable to start the tokio runtime")
+            .block_on(future),
+    }
+}
+
+#[cfg(feature = "tokio-runtime")]
+pub async fn sleep(duration: Duration) {
+    tokio::time::sleep(duration).await
+}
+
+#[cfg(feature = "tokio-runtime")]
+fn unwrap_join<T>(res: Result<T, tokio::task::JoinError>) -> T {
+    match res {
+        Ok(v) => v,
+        Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+        Err(e) => panic!("Task cancelled: {}", e),
+    }
+}
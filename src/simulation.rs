@@ -0,0 +1,560 @@
+/*********************************************************************************
+* Copyright (c) 2018,2021 ADLINK Technology Inc.
+*
+* This program and the accompanying materials are made available under the
+* terms of the Eclipse Public License 2.0 which is available at
+* http://www.eclipse.org/legal/epl-2.0, or the Apache Software License 2.0
+* which is available at https://www.apache.org/licenses/LICENSE-2.0.
+*
+* SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+* Contributors:
+*   ADLINK fog05 team, <fog05@adlink-labs.tech>
+*********************************************************************************/
+
+//! Simulated kernel, used when the plugin is started with `--simulate`.
+//!
+//! Links, addresses, namespaces, nftables tables and spawned processes are
+//! kept in an in-memory model instead of being created, so that the
+//! networking flows can be run without privileges, eg. on a laptop or in a
+//! container. Commands that are not modelled are only recorded. Namespace
+//! managers are not spawned, their requests are applied to the model by
+//! the plugin itself.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use fog05_sdk::fresult::{FError, FResult};
+use fog05_sdk::types::IPAddress;
+
+use ipnetwork::IpNetwork;
+use rand::{thread_rng, Rng};
+
+use crate::ns_channel::NSManagerRequest;
+use crate::sysctl::MartianCounters;
+use crate::types::LinkStatsDump;
+
+/// Commands kept in the model, the oldest are dropped
+const MAX_RECORDED_COMMANDS: usize = 1000;
+/// Fake pids start here so they are not mistaken for real ones
+const FIRST_SIMULATED_PID: u32 = 4_000_000;
+const DEFAULT_MTU: u32 = 1500;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static KERNEL: Mutex<Option<SimulatedKernel>> = Mutex::new(None);
+
+/// Link of the model, `netns` is `None` for the default namespace
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SimulatedLink {
+    pub name: String,
+    pub netns: Option<String>,
+    pub kind: String,
+    pub peer: Option<String>,
+    pub master: Option<String>,
+    pub up: bool,
+    pub mtu: u32,
+    pub mac: Vec<u8>,
+    pub addresses: Vec<IpNetwork>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SimulatedProcess {
+    pub pid: u32,
+    pub netns: Option<String>,
+    pub argv: Vec<String>,
+}
+
+/// Topology of the simulated node, returned by `get_simulated_topology`
+/// and written in the state dumps
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SimulatedKernel {
+    pub namespaces: BTreeSet<String>,
+    pub links: Vec<SimulatedLink>,
+    /// nftables tables with a description of their content
+    pub nft_tables: BTreeMap<String, String>,
+    pub processes: Vec<SimulatedProcess>,
+    pub commands: Vec<String>,
+    next_pid: u32,
+}
+
+/// Enables the simulation for the whole process, it cannot be disabled
+pub fn enable() {
+    let mut guard = KERNEL.lock().unwrap_or_else(|e| e.into_inner());
+    if guard.is_none() {
+        *guard = Some(SimulatedKernel {
+            next_pid: FIRST_SIMULATED_PID,
+            ..SimulatedKernel::default()
+        });
+    }
+    ENABLED.store(true, Ordering::SeqCst);
+    log::warn!("Simulation enabled, the kernel is not modified");
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::SeqCst)
+}
+
+pub fn snapshot() -> Option<SimulatedKernel> {
+    KERNEL.lock().ok().and_then(|guard| guard.clone())
+}
+
+fn with<T>(f: impl FnOnce(&mut SimulatedKernel) -> FResult<T>) -> FResult<T> {
+    let mut guard = KERNEL
+        .lock()
+        .map_err(|e| FError::NetworkingError(format!("{}", e)))?;
+    match guard.as_mut() {
+        Some(kernel) => f(kernel),
+        None => Err(FError::NetworkingError(
+            "Simulation is not enabled".to_string(),
+        )),
+    }
+}
+
+fn not_found(netns: Option<&str>, name: &str) -> FError {
+    FError::NetworkingError(format!(
+        "Cannot find device \"{}\" in {}",
+        name,
+        netns.unwrap_or("the default namespace")
+    ))
+}
+
+impl SimulatedKernel {
+    fn find(&self, netns: Option<&str>, name: &str) -> Option<usize> {
+        self.links
+            .iter()
+            .position(|l| l.name == name && l.netns.as_deref() == netns)
+    }
+
+    fn link(&mut self, netns: Option<&str>, name: &str) -> FResult<&mut SimulatedLink> {
+        match self.find(netns, name) {
+            Some(i) => Ok(&mut self.links[i]),
+            None => Err(not_found(netns, name)),
+        }
+    }
+
+    fn add(
+        &mut self,
+        netns: Option<&str>,
+        name: &str,
+        kind: &str,
+        peer: Option<&str>,
+    ) -> FResult<()> {
+        if let Some(ns) = netns {
+            if !self.namespaces.contains(ns) {
+                return Err(FError::NotFound);
+            }
+        }
+        if self.find(netns, name).is_some() {
+            return Err(FError::AlreadyPresent);
+        }
+        let mut mac: [u8; 6] = thread_rng().gen();
+        mac[0] = (mac[0] | 0x02) & 0xfe;
+        self.links.push(SimulatedLink {
+            name: name.to_string(),
+            netns: netns.map(String::from),
+            kind: kind.to_string(),
+            peer: peer.map(String::from),
+            master: None,
+            up: false,
+            mtu: DEFAULT_MTU,
+            mac: mac.to_vec(),
+            addresses: Vec::new(),
+        });
+        Ok(())
+    }
+
+    /// Removes a link, the other end of a veth goes with it and the
+    /// ports of a bridge are released
+    fn del(&mut self, netns: Option<&str>, name: &str) -> FResult<()> {
+        let i = self
+            .find(netns, name)
+            .ok_or_else(|| not_found(netns, name))?;
+        let link = self.links.remove(i);
+        if let Some(peer) = link.peer {
+            self.links
+                .retain(|l| l.name != peer || l.peer.as_deref() != Some(name));
+        }
+        for port in self.links.iter_mut() {
+            if port.netns == link.netns && port.master.as_deref() == Some(name) {
+                port.master = None;
+            }
+        }
+        Ok(())
+    }
+
+    fn move_link(&mut self, from: Option<&str>, name: &str, to: Option<&str>) -> FResult<()> {
+        if from == to {
+            return self.link(from, name).map(|_| ());
+        }
+        if let Some(ns) = to {
+            if !self.namespaces.contains(ns) {
+                return Err(FError::NotFound);
+            }
+        }
+        if self.find(to, name).is_some() {
+            return Err(FError::AlreadyPresent);
+        }
+        let link = self.link(from, name)?;
+        link.netns = to.map(String::from);
+        link.master = None;
+        link.up = false;
+        Ok(())
+    }
+
+    fn spawn(&mut self, netns: Option<&str>, argv: Vec<String>) -> u32 {
+        let pid = self.next_pid;
+        self.next_pid += 1;
+        self.processes.push(SimulatedProcess {
+            pid,
+            netns: netns.map(String::from),
+            argv,
+        });
+        pid
+    }
+
+    fn record(&mut self, line: String) {
+        log::debug!("simulated: {}", line);
+        self.commands.push(line);
+        if self.commands.len() > MAX_RECORDED_COMMANDS {
+            self.commands.remove(0);
+        }
+    }
+}
+
+pub fn add_netns(name: &str) -> FResult<()> {
+    with(|k| {
+        k.record(format!("ip netns add {}", name));
+        if !k.namespaces.insert(name.to_string()) {
+            return Err(FError::AlreadyPresent);
+        }
+        Ok(())
+    })
+}
+
+/// Removes a namespace with its links, as the kernel does
+pub fn del_netns(name: &str) -> FResult<()> {
+    with(|k| {
+        k.record(format!("ip netns del {}", name));
+        if !k.namespaces.remove(name) {
+            return Err(FError::NotFound);
+        }
+        let links: Vec<String> = k
+            .links
+            .iter()
+            .filter(|l| l.netns.as_deref() == Some(name))
+            .map(|l| l.name.clone())
+            .collect();
+        for link in links {
+            // the other end of a veth may already be gone with its peer
+            let _ = k.del(Some(name), &link);
+        }
+        Ok(())
+    })
+}
+
+pub fn add_link(netns: Option<&str>, name: &str, kind: &str) -> FResult<()> {
+    with(|k| {
+        k.record(format!("ip link add {} type {}", name, kind));
+        k.add(netns, name, kind, None)
+    })
+}
+
+pub fn add_veth(netns: Option<&str>, name: &str, peer: &str) -> FResult<()> {
+    with(|k| {
+        k.record(format!("ip link add {} type veth peer name {}", name, peer));
+        k.add(netns, name, "veth", Some(peer))?;
+        k.add(netns, peer, "veth", Some(name))
+    })
+}
+
+pub fn del_link(netns: Option<&str>, name: &str) -> FResult<()> {
+    with(|k| {
+        k.record(format!("ip link del {}", name));
+        k.del(netns, name)
+    })
+}
+
+pub fn link_exists(netns: Option<&str>, name: &str) -> FResult<bool> {
+    with(|k| Ok(k.find(netns, name).is_some()))
+}
+
+pub fn list_links(netns: Option<&str>) -> FResult<Vec<String>> {
+    with(|k| {
+        Ok(k.links
+            .iter()
+            .filter(|l| l.netns.as_deref() == netns)
+            .map(|l| l.name.clone())
+            .collect())
+    })
+}
+
+pub fn set_link_up(netns: Option<&str>, name: &str, up: bool) -> FResult<()> {
+    with(|k| {
+        k.record(format!(
+            "ip link set {} {}",
+            name,
+            if up { "up" } else { "down" }
+        ));
+        k.link(netns, name)?.up = up;
+        Ok(())
+    })
+}
+
+pub fn set_link_master(netns: Option<&str>, name: &str, master: Option<&str>) -> FResult<()> {
+    with(|k| {
+        match master {
+            Some(master) => {
+                k.record(format!("ip link set {} master {}", name, master));
+                k.link(netns, master)?;
+            }
+            None => k.record(format!("ip link set {} nomaster", name)),
+        }
+        k.link(netns, name)?.master = master.map(String::from);
+        Ok(())
+    })
+}
+
+pub fn get_link_master(netns: Option<&str>, name: &str) -> FResult<Option<String>> {
+    with(|k| Ok(k.link(netns, name)?.master.clone()))
+}
+
+pub fn set_link_mtu(netns: Option<&str>, name: &str, mtu: u32) -> FResult<()> {
+    with(|k| {
+        k.record(format!("ip link set {} mtu {}", name, mtu));
+        k.link(netns, name)?.mtu = mtu;
+        Ok(())
+    })
+}
+
+pub fn get_link_mtu(netns: Option<&str>, name: &str) -> FResult<u32> {
+    with(|k| Ok(k.link(netns, name)?.mtu))
+}
+
+pub fn set_link_mac(netns: Option<&str>, name: &str, mac: Vec<u8>) -> FResult<()> {
+    with(|k| {
+        k.record(format!("ip link set {} address {:02x?}", name, mac));
+        k.link(netns, name)?.mac = mac;
+        Ok(())
+    })
+}
+
+pub fn get_link_mac(netns: Option<&str>, name: &str) -> FResult<Vec<u8>> {
+    with(|k| Ok(k.link(netns, name)?.mac.clone()))
+}
+
+pub fn set_link_name(netns: Option<&str>, name: &str, new_name: &str) -> FResult<()> {
+    with(|k| {
+        k.record(format!("ip link set {} name {}", name, new_name));
+        if k.find(netns, new_name).is_some() {
+            return Err(FError::AlreadyPresent);
+        }
+        k.link(netns, name)?.name = new_name.to_string();
+        for link in k.links.iter_mut() {
+            if link.peer.as_deref() == Some(name) {
+                link.peer = Some(new_name.to_string());
+            }
+            if link.netns.as_deref() == netns && link.master.as_deref() == Some(name) {
+                link.master = Some(new_name.to_string());
+            }
+        }
+        Ok(())
+    })
+}
+
+pub fn move_link(from: Option<&str>, name: &str, to: Option<&str>) -> FResult<()> {
+    with(|k| {
+        k.record(format!(
+            "ip link set {} netns {}",
+            name,
+            to.unwrap_or("default")
+        ));
+        k.move_link(from, name, to)
+    })
+}
+
+pub fn add_address(netns: Option<&str>, name: &str, addr: IPAddress, prefix: u8) -> FResult<()> {
+    let net =
+        IpNetwork::new(addr, prefix).map_err(|e| FError::NetworkingError(format!("{}", e)))?;
+    with(|k| {
+        k.record(format!("ip address add {} dev {}", net, name));
+        let link = k.link(netns, name)?;
+        if link.addresses.iter().any(|a| a.ip() == addr) {
+            return Err(FError::AlreadyPresent);
+        }
+        link.addresses.push(net);
+        Ok(())
+    })
+}
+
+pub fn del_address(netns: Option<&str>, name: &str, addr: IPAddress) -> FResult<()> {
+    with(|k| {
+        k.record(format!("ip address del {} dev {}", addr, name));
+        k.link(netns, name)?.addresses.retain(|a| a.ip() != addr);
+        Ok(())
+    })
+}
+
+pub fn get_addresses(netns: Option<&str>, name: &str) -> FResult<Vec<IPAddress>> {
+    with(|k| {
+        Ok(k.link(netns, name)?
+            .addresses
+            .iter()
+            .map(|a| a.ip())
+            .collect())
+    })
+}
+
+pub fn add_nft_table(name: &str, description: String) -> FResult<()> {
+    with(|k| {
+        k.record(format!("nft add table inet {}", name));
+        k.nft_tables.insert(name.to_string(), description);
+        Ok(())
+    })
+}
+
+pub fn del_nft_table(name: &str) -> FResult<()> {
+    with(|k| {
+        k.record(format!("nft delete table inet {}", name));
+        k.nft_tables.remove(name);
+        Ok(())
+    })
+}
+
+/// Records a process that would have been spawned, returns its fake pid
+pub fn spawn(netns: Option<&str>, argv: Vec<String>) -> FResult<u32> {
+    with(|k| {
+        k.record(argv.join(" "));
+        Ok(k.spawn(netns, argv))
+    })
+}
+
+pub fn kill(pid: u32) -> FResult<()> {
+    with(|k| {
+        k.record(format!("kill {}", pid));
+        let count = k.processes.len();
+        k.processes.retain(|p| p.pid != pid);
+        if k.processes.len() == count {
+            return Err(FError::NotFound);
+        }
+        Ok(())
+    })
+}
+
+/// Stops the processes spawned with the given arguments
+pub fn kill_matching(argv: &[String]) -> FResult<()> {
+    with(|k| {
+        k.record(format!("pkill -f '{}'", argv.join(" ")));
+        k.processes.retain(|p| p.argv != argv);
+        Ok(())
+    })
+}
+
+/// Records a command run by the plugin, `ip netns exec` is unwrapped to
+/// keep the namespace. Nothing is executed and the output is empty.
+pub fn run_command(cmd: &Command) -> FResult<String> {
+    let mut argv: Vec<String> = std::iter::once(cmd.get_program())
+        .chain(cmd.get_args())
+        .map(|a| a.to_string_lossy().to_string())
+        .collect();
+    let netns = if argv.len() > 4 && argv[..3] == ["ip", "netns", "exec"] {
+        let ns = argv[3].clone();
+        argv.drain(..4);
+        Some(ns)
+    } else {
+        None
+    };
+    with(|k| {
+        match netns {
+            Some(ns) => k.record(format!("[{}] {}", ns, argv.join(" "))),
+            None => k.record(argv.join(" ")),
+        }
+        Ok(String::new())
+    })
+}
+
+/// Applies a request of a namespace manager to the namespace `ns_name`
+pub fn ns_request<T: DeserializeOwned>(
+    ns_name: &str,
+    req: NSManagerRequest,
+) -> FResult<FResult<T>> {
+    let value = match handle_ns_request(ns_name, req) {
+        Ok(value) => value,
+        Err(e) => return Ok(Err(e)),
+    };
+    Ok(serde_json::from_value(value).map_err(|e| FError::NetworkingError(format!("{}", e))))
+}
+
+fn to_value<T: Serialize>(value: T) -> FResult<serde_json::Value> {
+    serde_json::to_value(value).map_err(|e| FError::NetworkingError(format!("{}", e)))
+}
+
+fn handle_ns_request(ns_name: &str, req: NSManagerRequest) -> FResult<serde_json::Value> {
+    let ns = Some(ns_name);
+    match req {
+        NSManagerRequest::SetVirtualInterfaceUp { iface } => set_link_up(ns, &iface, true)?,
+        NSManagerRequest::SetVirtualInterfaceDown { iface } => set_link_up(ns, &iface, false)?,
+        NSManagerRequest::ProbeGateway { .. } => return to_value(true),
+        NSManagerRequest::DumpLinkStats {} => return to_value(LinkStatsDump::default()),
+        NSManagerRequest::GetMartianCounters {} => return to_value(MartianCounters::default()),
+        NSManagerRequest::CheckVirtualInterfaceExists { iface } => {
+            return to_value(link_exists(ns, &iface)?)
+        }
+        NSManagerRequest::MoveVirtualInterfaceIntoDefaultNs { iface } => {
+            move_link(ns, &iface, None)?
+        }
+        NSManagerRequest::SetVirtualInterfaceMac { iface, address } => {
+            set_link_mac(ns, &iface, address)?
+        }
+        NSManagerRequest::SetVirtualInterfaceMtu { iface, mtu } => set_link_mtu(ns, &iface, mtu)?,
+        NSManagerRequest::SetVirtualInterfaceName { iface, name } => {
+            set_link_name(ns, &iface, &name)?
+        }
+        NSManagerRequest::DelVirtualInterfaceAddress { iface, addr } => {
+            del_address(ns, &iface, addr)?
+        }
+        NSManagerRequest::GetVirtualInterfaceAddresses { iface } => {
+            return to_value(get_addresses(ns, &iface)?)
+        }
+        NSManagerRequest::AddVirtualInterfaceAddress { iface, addr } => {
+            // without an address the manager runs a DHCP client
+            if let Some(addr) = addr {
+                add_address(ns, &iface, addr.ip(), addr.prefix())?;
+            }
+            return to_value(get_addresses(ns, &iface)?);
+        }
+        NSManagerRequest::SetVirtualInterfaceMaster { iface, master } => {
+            set_link_master(ns, &iface, Some(&master))?
+        }
+        NSManagerRequest::GetVirtualInterfaceMaster { iface } => {
+            return to_value(get_link_master(ns, &iface)?)
+        }
+        NSManagerRequest::SetVirtualInterfaceNomaster { iface } => {
+            set_link_master(ns, &iface, None)?
+        }
+        NSManagerRequest::DelVirtualInterface { iface } => del_link(ns, &iface)?,
+        NSManagerRequest::AddVirtualInterfacePtpVxlan { iface, .. }
+        | NSManagerRequest::AddVirtualInterfaceMcastVxlan { iface, .. } => {
+            add_link(ns, &iface, "vxlan")?
+        }
+        NSManagerRequest::AddVirtualInterfaceVlan { iface, .. } => add_link(ns, &iface, "vlan")?,
+        NSManagerRequest::AddVirtualInterfaceIpTunnel { iface, .. } => {
+            add_link(ns, &iface, "iptunnel")?
+        }
+        NSManagerRequest::AddVirtualInterfaceVeth { iface_i, iface_e } => {
+            add_veth(ns, &iface_i, &iface_e)?
+        }
+        NSManagerRequest::AddVirtualInterfaceBridge { br_name } => {
+            add_link(ns, &br_name, "bridge")?
+        }
+        NSManagerRequest::ListInterfaces {} => return to_value(list_links(ns)?),
+        other => with(|k| {
+            k.record(format!("[{}] {:?}", ns_name, other));
+            Ok(())
+        })?,
+    }
+    Ok(serde_json::Value::Null)
+}
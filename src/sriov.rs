@@ -0,0 +1,201 @@
+/*********************************************************************************
+* Copyright (c) 2018,2021 ADLINK Technology Inc.
+*
+* This program and the accompanying materials are made available under the
+* terms of the Eclipse Public License 2.0 which is available at
+* http://www.eclipse.org/legal/epl-2.0, or the Apache Software License 2.0
+* which is available at https://www.apache.org/licenses/LICENSE-2.0.
+*
+* SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+* Contributors:
+*   ADLINK fog05 team, <fog05@adlink-labs.tech>
+*********************************************************************************/
+
+//! SR-IOV Virtual Functions management.
+//!
+//! Physical Functions are discovered from sysfs, the VFs have to be
+//! already created (eg. by writing `sriov_numvfs`) and are handed out
+//! from a pool, their MAC, VLAN and spoof checking are configured on the PF
+//! through netlink. The allocations are kept in the journal.
+
+use std::collections::HashMap;
+
+use async_std::fs;
+use async_std::path::Path;
+use async_std::prelude::*;
+
+use serde::{Deserialize, Serialize};
+
+use fog05_sdk::fresult::{FError, FResult};
+use fog05_sdk::types::MACAddress;
+
+use uuid::Uuid;
+
+pub const SYSFS_NET_PATH: &str = "/sys/class/net";
+
+/// SR-IOV capable network card
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SRIOVPhysicalFunction {
+    pub if_name: String,
+    pub total_vfs: u32,
+    pub num_vfs: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct VFConfig {
+    pub mac: Option<MACAddress>,
+    pub vlan: Option<u16>,
+    pub spoofchk: bool,
+}
+
+/// Virtual Function allocated to a connection point,
+/// the PCI address is the one used for the passthrough
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VirtualFunction {
+    pub uuid: Uuid,
+    pub pf: String,
+    pub index: u32,
+    pub pci_address: String,
+    pub config: VFConfig,
+}
+
+/// Pool of the VFs available on this node
+#[derive(Debug, Default)]
+pub struct VFPool {
+    pub free: HashMap<String, Vec<u32>>,
+    pub allocated: HashMap<Uuid, VirtualFunction>,
+}
+
+impl VFPool {
+    /// Adds the VFs of the given PF that are not already known to the pool
+    pub fn add_pf(&mut self, pf: &SRIOVPhysicalFunction) {
+        let allocated: Vec<u32> = self
+            .allocated
+            .values()
+            .filter(|vf| vf.pf == pf.if_name)
+            .map(|vf| vf.index)
+            .collect();
+        let free = self.free.entry(pf.if_name.clone()).or_insert_with(Vec::new);
+        for index in 0..pf.num_vfs {
+            if !allocated.contains(&index) && !free.contains(&index) {
+                free.push(index);
+            }
+        }
+    }
+
+    /// Takes a free VF, from the given PF if any
+    pub fn take(&mut self, pf: Option<&str>) -> FResult<(String, u32)> {
+        let (pf_name, free) = self
+            .free
+            .iter_mut()
+            .find(|(name, free)| !free.is_empty() && pf.map_or(true, |pf| pf == name.as_str()))
+            .ok_or_else(|| FError::NetworkingError("No free virtual functions".to_string()))?;
+        let index = free.remove(0);
+        Ok((pf_name.clone(), index))
+    }
+
+    pub fn give_back(&mut self, pf: String, index: u32) {
+        self.free.entry(pf).or_insert_with(Vec::new).push(index);
+    }
+}
+
+async fn read_sysfs_u32(path: &Path) -> FResult<u32> {
+    let value = fs::read_to_string(path).await?;
+    value
+        .trim()
+        .parse::<u32>()
+        .map_err(|e| FError::NetworkingError(format!("{}", e)))
+}
+
+/// Lists the SR-IOV capable interfaces, optionally restricted to `allowed`
+pub async fn list_physical_functions(
+    allowed: Option<&Vec<String>>,
+) -> FResult<Vec<SRIOVPhysicalFunction>> {
+    let mut pfs = Vec::new();
+    let mut entries = fs::read_dir(SYSFS_NET_PATH).await?;
+    while let Some(entry) = entries.next().await {
+        let entry = entry?;
+        let if_name = entry.file_name().to_string_lossy().to_string();
+        if let Some(allowed) = allowed {
+            if !allowed.contains(&if_name) {
+                continue;
+            }
+        }
+        let device = entry.path().join("device");
+        let total_vfs = match read_sysfs_u32(&device.join("sriov_totalvfs")).await {
+            Ok(total_vfs) if total_vfs > 0 => total_vfs,
+            _ => continue,
+        };
+        let num_vfs = read_sysfs_u32(&device.join("sriov_numvfs")).await?;
+        pfs.push(SRIOVPhysicalFunction {
+            if_name,
+            total_vfs,
+            num_vfs,
+        });
+    }
+    Ok(pfs)
+}
+
+/// Returns the PCI address of a VF from the `virtfnN` link of its PF
+pub async fn get_vf_pci_address(pf: &str, index: u32) -> FResult<String> {
+    let link = Path::new(SYSFS_NET_PATH)
+        .join(pf)
+        .join("device")
+        .join(format!("virtfn{}", index));
+    let target = fs::read_link(&link).await?;
+    target
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .ok_or(FError::NotFound)
+}
+
+const IFLA_VF_INFO: u16 = 1;
+const IFLA_VF_MAC: u16 = 1;
+const IFLA_VF_VLAN: u16 = 2;
+const IFLA_VF_SPOOFCHK: u16 = 4;
+const NLA_F_NESTED: u16 = 1 << 15;
+
+fn push_nla(buf: &mut Vec<u8>, kind: u16, payload: &[u8]) {
+    let len = 4 + payload.len();
+    buf.extend_from_slice(&(len as u16).to_ne_bytes());
+    buf.extend_from_slice(&kind.to_ne_bytes());
+    buf.extend_from_slice(payload);
+    buf.resize(buf.len() + ((4 - len % 4) % 4), 0);
+}
+
+/// Encodes the content of the `IFLA_VFINFO_LIST` attribute applying
+/// MAC, VLAN and spoof checking to a VF, VLAN 0 removes the tag
+pub fn vf_info_list(index: u32, config: &VFConfig) -> Vec<u8> {
+    let vf = index.to_ne_bytes();
+    let mut info = Vec::new();
+    if let Some(ref mac) = config.mac {
+        // struct ifla_vf_mac, the address is padded to 32 bytes
+        let mut payload = vf.to_vec();
+        let mut addr = [0u8; 32];
+        addr[..6].copy_from_slice(&mac.bytes());
+        payload.extend_from_slice(&addr);
+        push_nla(&mut info, IFLA_VF_MAC, &payload);
+    }
+    // struct ifla_vf_vlan, vlan and qos
+    let mut payload = vf.to_vec();
+    payload.extend_from_slice(&u32::from(config.vlan.unwrap_or(0)).to_ne_bytes());
+    payload.extend_from_slice(&0u32.to_ne_bytes());
+    push_nla(&mut info, IFLA_VF_VLAN, &payload);
+    // struct ifla_vf_spoofchk
+    let mut payload = vf.to_vec();
+    payload.extend_from_slice(&u32::from(config.spoofchk).to_ne_bytes());
+    push_nla(&mut info, IFLA_VF_SPOOFCHK, &payload);
+
+    let mut list = Vec::new();
+    push_nla(&mut list, IFLA_VF_INFO | NLA_F_NESTED, &info);
+    list
+}
+
+/// Configuration a released VF is restored to
+pub fn reset_config() -> VFConfig {
+    VFConfig {
+        mac: Some(MACAddress::new(0, 0, 0, 0, 0, 0)),
+        vlan: None,
+        spoofchk: true,
+    }
+}
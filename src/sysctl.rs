@@ -0,0 +1,169 @@
+/*********************************************************************************
+* Copyright (c) 2018,2021 ADLINK Technology Inc.
+*
+* This program and the accompanying materials are made available under the
+* terms of the Eclipse Public License 2.0 which is available at
+* http://www.eclipse.org/legal/epl-2.0, or the Apache Software License 2.0
+* which is available at https://www.apache.org/licenses/LICENSE-2.0.
+*
+* SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+* Contributors:
+*   ADLINK fog05 team, <fog05@adlink-labs.tech>
+*********************************************************************************/
+
+//! Reverse path filtering and martian logging.
+//!
+//! Settings and the reverse path filter counter are per network namespace,
+//! so they are changed and read with `sysctl` and `cat` run in the
+//! namespace: the commands are built by the caller, either directly or with
+//! `ip netns exec`. The martian counters of the kernel are node-wide.
+
+use std::process::Command;
+
+use serde::{Deserialize, Serialize};
+
+use fog05_sdk::fresult::{FError, FResult};
+
+use crate::networking::exec_command;
+
+/// Values of `net.ipv4.conf.*.rp_filter`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum RPFilterMode {
+    OFF,
+    STRICT,
+    LOOSE,
+}
+
+impl RPFilterMode {
+    fn value(&self) -> u8 {
+        match self {
+            RPFilterMode::OFF => 0,
+            RPFilterMode::STRICT => 1,
+            RPFilterMode::LOOSE => 2,
+        }
+    }
+}
+
+/// Settings left to `None` are not changed.
+/// The kernel uses the highest value of the `all` and interface
+/// `rp_filter`, and loose (2) is higher than strict (1): an interface
+/// can be filtered when its namespace is not, and is filtered loosely
+/// whenever either of them is loose.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ReversePathFilter {
+    pub rp_filter: Option<RPFilterMode>,
+    pub log_martians: Option<bool>,
+}
+
+/// Packets dropped by the reverse path filter in a namespace since it was
+/// created, and martians seen by the whole node since it booted
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct MartianCounters {
+    /// `IPReversePathFilter` of `/proc/net/netstat`, per namespace
+    pub rp_filter_drops: u64,
+    /// `in_martian_src` of `/proc/net/stat/rt_cache`, summed over the CPUs,
+    /// the same in all the namespaces
+    pub martian_src: u64,
+    /// `in_martian_dst` of `/proc/net/stat/rt_cache`, summed over the CPUs,
+    /// the same in all the namespaces
+    pub martian_dst: u64,
+}
+
+impl ReversePathFilter {
+    /// The settings of `self`, the ones left to `None` taken from `older`
+    pub fn merge(&self, older: &ReversePathFilter) -> ReversePathFilter {
+        ReversePathFilter {
+            rp_filter: self.rp_filter.or(older.rp_filter),
+            log_martians: self.log_martians.or(older.log_martians),
+        }
+    }
+}
+
+/// Applies the filter to an interface or, when `iface` is `None`, to
+/// `all` and `default` so that it also covers interfaces created later.
+/// `command` builds the command run in the namespace.
+pub fn set_reverse_path_filter<F>(
+    command: F,
+    iface: Option<&str>,
+    filter: &ReversePathFilter,
+) -> FResult<()>
+where
+    F: Fn(&'static str) -> Command,
+{
+    let confs = match iface {
+        Some(iface) => vec![iface],
+        None => vec!["all", "default"],
+    };
+    let mut settings = Vec::new();
+    for conf in confs {
+        // slashes as separators, interface names can contain dots
+        if let Some(mode) = filter.rp_filter {
+            settings.push(format!("net/ipv4/conf/{}/rp_filter={}", conf, mode.value()));
+        }
+        if let Some(log) = filter.log_martians {
+            settings.push(format!(
+                "net/ipv4/conf/{}/log_martians={}",
+                conf,
+                if log { 1 } else { 0 }
+            ));
+        }
+    }
+    if settings.is_empty() {
+        return Ok(());
+    }
+    let mut cmd = command("sysctl");
+    cmd.arg("-w").args(settings);
+    exec_command(cmd)?;
+    Ok(())
+}
+
+/// Reads the counters of the namespace where `command` runs, the
+/// martian ones are not kept per namespace by the kernel
+pub fn read_martian_counters<F>(command: F) -> FResult<MartianCounters>
+where
+    F: Fn(&'static str) -> Command,
+{
+    let mut cmd = command("cat");
+    cmd.arg("/proc/net/netstat");
+    let netstat = exec_command(cmd)?;
+    let mut cmd = command("cat");
+    cmd.arg("/proc/net/stat/rt_cache");
+    let rt_cache = exec_command(cmd)?;
+
+    let mut counters = MartianCounters {
+        rp_filter_drops: netstat_counter(&netstat, "TcpExt:", "IPReversePathFilter").unwrap_or(0),
+        ..Default::default()
+    };
+    let mut lines = rt_cache.lines();
+    let header: Vec<&str> = lines
+        .next()
+        .ok_or_else(|| FError::NetworkingError("Empty rt_cache statistics".to_string()))?
+        .split_whitespace()
+        .collect();
+    let src = header.iter().position(|h| *h == "in_martian_src");
+    let dst = header.iter().position(|h| *h == "in_martian_dst");
+    // one line of hexadecimal values per CPU
+    for line in lines {
+        let values: Vec<&str> = line.split_whitespace().collect();
+        let hex = |pos: Option<usize>| {
+            pos.and_then(|p| values.get(p))
+                .and_then(|v| u64::from_str_radix(v, 16).ok())
+                .unwrap_or(0)
+        };
+        counters.martian_src += hex(src);
+        counters.martian_dst += hex(dst);
+    }
+    Ok(counters)
+}
+
+/// Counters in `/proc/net/netstat` come as a line with the names
+/// followed by one with the values, both starting with the prefix
+fn netstat_counter(netstat: &str, prefix: &str, name: &str) -> Option<u64> {
+    let mut lines = netstat.lines().filter(|l| l.starts_with(prefix));
+    while let (Some(names), Some(values)) = (lines.next(), lines.next()) {
+        if let Some(pos) = names.split_whitespace().position(|n| n == name) {
+            return values.split_whitespace().nth(pos)?.parse().ok();
+        }
+    }
+    None
+}
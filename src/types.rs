@@ -16,17 +16,32 @@
 extern crate serde;
 extern crate serde_json;
 
-use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer, Serialize};
 
-use async_std::sync::{Arc, RwLock};
+use async_std::sync::{Arc, Mutex, RwLock};
 
 use futures::prelude::*;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::str;
 
 use fog05_sdk::agent::{os::OSClient, plugin::AgentPluginInterfaceClient};
 use fog05_sdk::fresult::{FError, FResult};
-use fog05_sdk::types::IPAddress;
+use fog05_sdk::types::{
+    ConnectionPoint, IPAddress, IPConfiguration, IPVersion, MACAddress, NetworkNamespace,
+    VirtualInterface, VirtualNetwork,
+};
+
+use crate::dhcp_client::{DHCPClientHandle, DHCPLease};
+use crate::dhcp_server::{DHCPServerConfig, DHCPServerHandle};
+use crate::dns64::DNS64Handle;
+use crate::ipam::{IPAMPool, VNetIPAM};
+use crate::journal::JournalEntry;
+use crate::logging::{LogLevel, LogSettings};
+use crate::ns_channel::{NSManagerRequest, NamespaceManagerChannel};
+use crate::simulation::SimulatedKernel;
+use crate::sriov::{SRIOVPhysicalFunction, VFConfig, VFPool, VirtualFunction};
+use crate::sysctl::{MartianCounters, ReversePathFilter};
 
 use zenoh::*;
 use znrpc_macros::znservice;
@@ -36,6 +51,8 @@ use uuid::Uuid;
 
 use ipnetwork::IpNetwork;
 
+use rtnetlink::packet::rtnl::link::nlas::{Nla as LinkNla, State as LinkState};
+
 pub type LinuxNetworkStateGuard<'a> = async_std::sync::RwLockReadGuard<'a, LinuxNetworkState>;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -48,12 +65,911 @@ pub struct LinuxNetworkConfig {
     pub monitoring_interveal: u64,
     pub overlay_iface: Option<String>,
     pub dataplane_iface: Option<String>,
+    pub dpdk_bridge: Option<String>,
+    pub ns_manager_max_concurrent_spawns: Option<usize>,
+    pub ns_manager_spawn_delay_ms: Option<u64>,
+    pub ns_manager_kill_timeout_ms: Option<u64>,
+    pub shared_ns_manager: Option<bool>,
+    pub ns_manager_transport: Option<NSManagerTransport>,
+    pub sriov_pfs: Option<Vec<String>>,
+    pub vxlan_options: Option<VXLANOptions>,
+    pub auto_dhcp_range: Option<bool>,
+    pub dhcp_reserved_window: Option<u32>,
+    pub dns_cache: Option<bool>,
+    pub dns_cache_size: Option<u32>,
+    pub dns_upstream: Option<Vec<IPAddress>>,
+    pub state_dump_operations: Option<usize>,
+    /// Finished operations of the `_async` RPCs kept for queries
+    pub async_operations_kept: Option<usize>,
+    pub elan_multicast: Option<bool>,
+    /// Restricts the interfaces bound to connection points to their own
+    /// MAC and addresses, enabled when not set
+    pub anti_spoofing: Option<bool>,
+    pub mgmt_iface: Option<String>,
+    pub dhcp_backend: Option<DHCPBackend>,
+    pub dhcp_client: Option<DHCPClientBackend>,
+    pub firewall_backend: Option<FirewallBackendKind>,
+    /// Collector of the flow export of the virtual networks, `address:port`
+    pub flow_collector: Option<String>,
+    /// One packet out of n is accounted by the flow export, 1 when not set
+    pub flow_sampling_rate: Option<u32>,
+    /// Executable run on DHCP lease events as dnsmasq runs a
+    /// `dhcp-script`, see `dhcp::DhcpBackend`
+    pub lease_hook: Option<String>,
+    pub default_route_metric: Option<u32>,
+    pub replication_peer: Option<Uuid>,
+    /// File with the base64 of the key shared with the replication peer,
+    /// required to send and accept replicas
+    pub replication_key_file: Option<String>,
+    /// File with the base64 of the key shared by all the nodes, required
+    /// to write the global records through the coordinator
+    pub global_key_file: Option<String>,
+    /// Pools the subnets of the virtual networks can be allocated from
+    pub ipam_pools: Option<Vec<IPAMPool>>,
+    pub churn_limits: Option<ChurnLimits>,
+    /// Overrides of the `RUST_LOG` levels, see `logging`
+    pub log: Option<LogSettings>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChurnOperation {
+    CREATE,
+    DELETE,
+}
+
+/// Creates and deletes of virtual networks a tenant can do in
+/// `window_secs`, the operations left to `None` are not limited
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ChurnLimit {
+    pub window_secs: u64,
+    pub max_creates: Option<u32>,
+    pub max_deletes: Option<u32>,
+}
+
+/// `default` applies to every tenant not listed in `tenants`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ChurnLimits {
+    pub default: Option<ChurnLimit>,
+    #[serde(default)]
+    pub tenants: HashMap<String, ChurnLimit>,
+}
+
+const THROTTLED_ERROR_PREFIX: &str = "Throttled: ";
+
+/// Returned when a tenant goes over its churn limit. The SDK errors are
+/// fixed, so it travels as a `NetworkingError` and callers get it back
+/// with `ThrottledError::from_error`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ThrottledError {
+    pub tenant: String,
+    pub operation: ChurnOperation,
+    pub limit: u32,
+    pub window_secs: u64,
+    /// Time until the oldest operation leaves the window
+    pub retry_after_ms: u64,
+}
+
+impl ThrottledError {
+    pub fn from_error(err: &FError) -> Option<Self> {
+        match err {
+            FError::NetworkingError(msg) => msg
+                .strip_prefix(THROTTLED_ERROR_PREFIX)
+                .and_then(|raw| serde_json::from_str(raw).ok()),
+            _ => None,
+        }
+    }
+}
+
+impl From<ThrottledError> for FError {
+    fn from(err: ThrottledError) -> Self {
+        match serde_json::to_string(&err) {
+            Ok(raw) => FError::NetworkingError(format!("{}{}", THROTTLED_ERROR_PREFIX, raw)),
+            Err(e) => FError::NetworkingError(format!("{}", e)),
+        }
+    }
+}
+
+/// Bandwidth limit of a connection point, enforced with a token bucket
+/// filter on its external veth
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ConnectionPointQoS {
+    pub rate_kbit: u32,
+    pub burst_kb: u32,
+    pub latency_ms: Option<u32>,
+}
+
+/// Rate limits of the traffic sent by a connection point, enforced with
+/// nftables on the ingress of its external veth while it is bound to a
+/// virtual network. Packets over any of the limits are dropped, the
+/// limits left to `None` are not enforced.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ConnectionPointRateLimit {
+    pub packets_per_sec: Option<u32>,
+    pub packets_burst: Option<u32>,
+    pub kbytes_per_sec: Option<u32>,
+    pub kbytes_burst: Option<u32>,
+}
+
+/// Public address of the node mapped to the address of a connection point,
+/// `table` is the nftables table holding the DNAT and SNAT rules
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FloatingIP {
+    pub cp_uuid: Uuid,
+    pub public_ip: IPAddress,
+    pub private_ip: IPAddress,
+    pub iface: String,
+    pub table: String,
+}
+
+/// Address of the node mapped 1:1 to an address of a virtual network
+/// with `map_address`, `table` is the nftables table holding the DNAT
+/// and SNAT rules. Unlike floating IPs they do not follow a connection
+/// point and are restored when the network is replayed.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AddressMapping {
+    pub internal_ip: IPAddress,
+    pub external_ip: IPAddress,
+    pub iface: String,
+    pub table: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum PortForwardProtocol {
+    TCP,
+    UDP,
+}
+
+impl std::fmt::Display for PortForwardProtocol {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PortForwardProtocol::TCP => write!(f, "tcp"),
+            PortForwardProtocol::UDP => write!(f, "udp"),
+        }
+    }
+}
+
+/// Port of the overlay interface forwarded to an address behind
+/// the NAT of a virtual network
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PortForward {
+    pub proto: PortForwardProtocol,
+    pub ext_port: u16,
+    pub internal_ip: IPAddress,
+    pub internal_port: u16,
+}
+
+/// Virtual network offering services (eg. DNS, update caches) to tenant
+/// networks: the tenants can open connections to it, it can only answer.
+/// Set as a whole, the tenants not listed lose their access.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SharedServices {
+    pub services_vnet: Uuid,
+    pub tenants: Vec<Uuid>,
+}
+
+/// Elements of a named nft set
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum NFTSetKind {
+    /// IPv4 addresses and CIDRs
+    IPV4,
+    /// Ports and port ranges, eg. `8000-8080`
+    PORT,
+}
+
+/// Named nft set in the NAT table of a virtual network, elements can be
+/// added and removed without rewriting the rules referencing it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct NFTSet {
+    pub name: String,
+    pub kind: NFTSetKind,
+    #[serde(default)]
+    pub elements: Vec<String>,
+}
+
+/// `INGRESS` is the traffic forwarded to the subnet of the network,
+/// `EGRESS` the one forwarded from it
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum SetRuleDirection {
+    INGRESS,
+    EGRESS,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum SetRuleAction {
+    ACCEPT,
+    DROP,
+}
+
+/// Filter rule matching the remote address or the destination port of
+/// the traffic against a set, `proto` is required with port sets.
+/// Rules are evaluated in order, the traffic matching none is accepted.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SetRule {
+    pub set: String,
+    pub direction: SetRuleDirection,
+    #[serde(default)]
+    pub proto: Option<PortForwardProtocol>,
+    pub action: SetRuleAction,
+    /// Hours the rule is active, always active when `None`
+    #[serde(default)]
+    pub schedule: Option<RuleSchedule>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum EgressAction {
+    ALLOW,
+    DENY,
+}
+
+/// Filter on the traffic forwarded from the subnets of a virtual network,
+/// matching its destination prefix and optionally its protocol and
+/// destination port, `port` needs `proto`. Rules are evaluated in order,
+/// the traffic matching none is allowed.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct EgressRule {
+    pub destination: (IPAddress, u8),
+    #[serde(default)]
+    pub proto: Option<PortForwardProtocol>,
+    #[serde(default)]
+    pub port: Option<u16>,
+    pub action: EgressAction,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Weekday {
+    MON,
+    TUE,
+    WED,
+    THU,
+    FRI,
+    SAT,
+    SUN,
+}
+
+/// Window in which a set rule is active, checked by the monitoring loop.
+/// `start` and `end` are minutes since midnight: a window ending before
+/// it starts spans midnight and belongs to the day it starts, one
+/// ending when it starts lasts the whole day.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RuleSchedule {
+    /// Days the window starts on, every day when empty
+    #[serde(default)]
+    pub days: Vec<Weekday>,
+    pub start: u16,
+    pub end: u16,
+    /// Minutes added to UTC to get the local time of the window
+    #[serde(default)]
+    pub utc_offset: i16,
+}
+
+impl RuleSchedule {
+    pub fn is_active(&self, unix_secs: u64) -> bool {
+        const WEEK: [Weekday; 7] = [
+            Weekday::MON,
+            Weekday::TUE,
+            Weekday::WED,
+            Weekday::THU,
+            Weekday::FRI,
+            Weekday::SAT,
+            Weekday::SUN,
+        ];
+        let local = unix_secs as i64 + i64::from(self.utc_offset) * 60;
+        let day = local.div_euclid(86400);
+        let minute = (local.rem_euclid(86400) / 60) as u16;
+        // 1970-01-01 was a Thursday
+        let on_day = |day: i64| {
+            self.days.is_empty() || self.days.contains(&WEEK[(day + 3).rem_euclid(7) as usize])
+        };
+        if self.start < self.end {
+            on_day(day) && minute >= self.start && minute < self.end
+        } else if self.start > self.end {
+            (on_day(day) && minute >= self.start) || (on_day(day - 1) && minute < self.end)
+        } else {
+            on_day(day)
+        }
+    }
+}
+
+/// Connection point receiving a copy of the traffic of a virtual network,
+/// `iface` is its external veth, target of the mirred actions
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct VNetMirror {
+    pub cp_uuid: Uuid,
+    pub iface: String,
+}
+
+/// Node where a virtual network spanning nodes is instantiated, kept in
+/// the global record of the network, `endpoint` is its VXLAN endpoint
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct VNetMember {
+    pub node_uuid: Uuid,
+    pub endpoint: IPAddress,
+}
+
+/// Veth pair connecting a virtual network to a namespace it does not own,
+/// the internal end lives in the namespace
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct VNetNamespaceAttachment {
+    pub ns_uuid: Uuid,
+    pub internal_veth: Uuid,
+    pub external_veth: Uuid,
+}
+
+/// Routed veth pair between the namespace of a services network and the
+/// one of a tenant. The ends take the two host addresses of `link`, the
+/// services end first, and route the subnet of the other network.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct VNetServicesLink {
+    pub tenant: Uuid,
+    pub services_veth: Uuid,
+    pub tenant_veth: Uuid,
+    pub link: IpNetwork,
+}
+
+/// Optional subsystems the plugin was built with
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct PluginCapabilities {
+    pub nat: bool,
+    pub dhcp: bool,
+    pub qos: bool,
+    pub flow_export: bool,
+    pub local_ns_channel: bool,
+}
+
+/// Tunable VXLAN parameters, options left to `None` keep the kernel defaults
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct VXLANOptions {
+    pub learning: Option<bool>,
+    pub ttl: Option<u8>,
+    pub tos: Option<u8>,
+    pub udp_csum: Option<bool>,
+}
+
+/// Forwarding database limits of the bridge of a virtual network,
+/// options left to `None` keep the kernel defaults.
+/// `max_learned` needs a kernel with `fdb_max_learned` (6.8 or later)
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct FDBOptions {
+    pub max_learned: Option<u32>,
+    pub ageing_time_s: Option<u32>,
+}
+
+/// Channel used to reach the Namespace Managers,
+/// `UNIX` requires the `local-ns-channel` feature and
+/// is not used by the shared Namespace Manager
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum NSManagerTransport {
+    ZENOH,
+    UNIX,
+}
+
+/// DHCP backend of the virtual networks, see `dhcp::DhcpBackend`.
+/// `EMBEDDED` runs in the plugin and serves IPv4 only, caching resolvers
+/// still use dnsmasq
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum DHCPBackend {
+    DNSMASQ,
+    EMBEDDED,
+    /// dnsmasq relaying to a central server, selected by the networks
+    /// setting `dhcp_relay` and not as `dhcp_backend`
+    RELAY,
+}
+
+/// Backend of the source NAT of the virtual networks, see
+/// `firewall::FirewallBackend`. `IPTABLES` uses iptables-legacy for the
+/// kernels without the `nft_*` modules
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum FirewallBackendKind {
+    NFTABLES,
+    IPTABLES,
+}
+
+/// DHCP client of the interfaces assigned without an address,
+/// `EMBEDDED` runs in the plugin and does not need dhclient
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum DHCPClientBackend {
+    DHCLIENT,
+    EMBEDDED,
 }
 
 pub struct LinuxNetworkState {
     pub uuid: Option<Uuid>,
     pub nl_handler: rtnetlink::Handle,
-    pub ns_managers: HashMap<Uuid, (u32, NamespaceManagerClient)>,
+    pub ns_managers: HashMap<Uuid, (u32, NamespaceManagerChannel)>,
+    pub dataplane_ports: HashMap<Uuid, DataplanePort>,
+    pub resolved_endpoints: HashMap<Uuid, String>,
+    pub unicast_vnets: HashMap<Uuid, String>,
+    pub ns_manager_spawn_stats: NSManagerSpawnStats,
+    pub shared_ns_manager: Option<(u32, SharedNamespaceManagerClient)>,
+    pub ip_tunnels: HashMap<Uuid, IPTunnel>,
+    pub vf_pool: VFPool,
+    pub pinned_bridge_macs: HashMap<Uuid, PinnedBridgeMAC>,
+    pub interface_states: HashMap<Uuid, InterfaceAdminState>,
+    pub interface_names: HashMap<Uuid, (String, Option<Uuid>)>,
+    pub l2tp_sessions: HashMap<Uuid, VNetL2TP>,
+    /// Running resolvers with the namespace they are in
+    pub resolvers: HashMap<Uuid, (String, VNetResolver)>,
+    /// Records of the other nodes, served by the local resolvers
+    pub remote_dns_records: HashMap<Uuid, HashMap<Uuid, Vec<DNSRecord>>>,
+    pub cp_qos: HashMap<Uuid, ConnectionPointQoS>,
+    /// Reverse path filters set on the namespaces and interfaces
+    pub rp_filters: HashMap<Uuid, ReversePathFilter>,
+    pub cp_rate_limits: HashMap<Uuid, ConnectionPointRateLimit>,
+    /// Bridges of the virtual networks isolated from each other
+    pub isolated_bridges: HashMap<Uuid, String>,
+    /// Records written by this plugin, used to list them
+    pub known_vnets: HashSet<Uuid>,
+    pub known_namespaces: HashSet<Uuid>,
+    pub known_cps: HashSet<Uuid>,
+    /// Embedded DHCP servers of the virtual networks
+    pub dhcp_servers: HashMap<Uuid, DHCPServerHandle>,
+    /// Default routes with failover of the namespaces
+    pub route_failovers: HashMap<Uuid, (DefaultRouteFailover, DefaultRouteFailoverStatus)>,
+    /// Link counters of the last monitoring cycle, by namespace,
+    /// `None` is the default namespace
+    pub link_stats: HashMap<Option<Uuid>, LinkStatsDump>,
+    /// Counters of the interfaces last read with the stats RPCs
+    pub interface_stats: HashMap<Uuid, InterfaceStatistics>,
+    /// Status published by the last monitoring cycle
+    pub node_status: Option<NodeNetworkingStatus>,
+    /// Operational state of the interfaces of the default namespace,
+    /// updated by the link events
+    pub link_oper_states: HashMap<Uuid, LinkOperState>,
+    /// Managed links with a raised alert, by name
+    pub managed_links_down: HashSet<String>,
+    /// Links moved out of the default namespace by the plugin, by name,
+    /// with the time of the move, their deletion event is not a removal
+    pub moved_links: HashMap<String, u64>,
+    /// Etag of the records last sent to the replication peer
+    pub replicated_etag: Option<String>,
+    /// Virtual networks with scheduled set rules, with the rules that
+    /// were active when the filter chain was last written
+    pub scheduled_rules: HashMap<Uuid, Vec<bool>>,
+    /// STP settings of the bridge ports, as in the journal
+    pub bridge_ports: HashMap<Uuid, BridgePortConfig>,
+    /// Audit data of the records, as in the journal
+    pub record_metadata: HashMap<Uuid, RecordMetadata>,
+    /// Times of the recent creates and deletes of each tenant
+    pub churn_history: HashMap<(String, ChurnOperation), VecDeque<u64>>,
+    /// DHCP clients of the interfaces addressed with DHCP
+    pub dhcp_clients: HashMap<Uuid, DHCPClient>,
+    /// Embedded DHCP clients, by interface
+    pub dhcp_client_handles: HashMap<Uuid, DHCPClientHandle>,
+    /// Operations started by the `_async` RPCs, the finished ones are
+    /// kept until `async_operations_kept` newer ones finish
+    pub async_operations: HashMap<Uuid, AsyncOperation>,
+    /// DNS64 proxies of the NAT64 virtual networks
+    pub dns64_proxies: HashMap<Uuid, DNS64Handle>,
+}
+
+/// End of a veth pair with its names resolved
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct InterfaceEnd {
+    pub uuid: Uuid,
+    pub if_name: String,
+    pub net_ns: Option<Uuid>,
+    pub ns_name: Option<String>,
+}
+
+/// Counters of an interface, the first fields of `rtnl_link_stats64`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct InterfaceStats {
+    pub rx_packets: u64,
+    pub tx_packets: u64,
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+    pub rx_errors: u64,
+    pub tx_errors: u64,
+    pub rx_dropped: u64,
+    pub tx_dropped: u64,
+}
+
+impl InterfaceStats {
+    /// Parses the `IFLA_STATS64` attribute, in host byte order
+    pub fn from_stats64(buf: &[u8]) -> Option<Self> {
+        let counter = |i: usize| -> Option<u64> {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(buf.get(i * 8..(i + 1) * 8)?);
+            Some(u64::from_ne_bytes(bytes))
+        };
+        Some(InterfaceStats {
+            rx_packets: counter(0)?,
+            tx_packets: counter(1)?,
+            rx_bytes: counter(2)?,
+            tx_bytes: counter(3)?,
+            rx_errors: counter(4)?,
+            tx_errors: counter(5)?,
+            rx_dropped: counter(6)?,
+            tx_dropped: counter(7)?,
+        })
+    }
+}
+
+/// Counters of an interface when they were last read
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct InterfaceStatistics {
+    pub intf_uuid: Uuid,
+    pub collected_ms: u64,
+    pub stats: InterfaceStats,
+}
+
+/// Counters of all the links of a namespace, collected with a single
+/// `RTM_GETLINK` dump
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct LinkStatsDump {
+    pub collected_ms: u64,
+    /// Counters keyed by ifindex
+    pub stats: HashMap<u32, InterfaceStats>,
+    /// Ifindex of the links by name
+    pub indexes: HashMap<String, u32>,
+    /// Ifindex of the links whose operational state is down
+    #[serde(default)]
+    pub down: HashSet<u32>,
+}
+
+impl LinkStatsDump {
+    /// Adds one message of the dump
+    pub fn add_link(&mut self, index: u32, nlas: Vec<LinkNla>) {
+        for nla in nlas {
+            match nla {
+                LinkNla::IfName(name) => {
+                    self.indexes.insert(name, index);
+                }
+                LinkNla::Stats64(buf) => {
+                    if let Some(stats) = InterfaceStats::from_stats64(&buf) {
+                        self.stats.insert(index, stats);
+                    }
+                }
+                LinkNla::OperState(LinkState::Down)
+                | LinkNla::OperState(LinkState::LowerLayerDown) => {
+                    self.down.insert(index);
+                }
+                _ => (),
+            }
+        }
+    }
+
+    pub fn get(&self, if_name: &str) -> Option<InterfaceStats> {
+        self.indexes
+            .get(if_name)
+            .and_then(|index| self.stats.get(index))
+            .copied()
+    }
+}
+
+/// Health of a virtual network on this node, the servers are `None`
+/// when the network does not have them
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct VNetStatus {
+    pub vnet_uuid: Uuid,
+    /// The bridge of the network is in the kernel
+    pub bridge_present: bool,
+    pub dhcp_running: Option<bool>,
+    pub resolver_running: Option<bool>,
+    pub connection_points: usize,
+    /// Managed links down the network depends on
+    #[serde(default)]
+    pub degraded_by: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct NSManagerStatus {
+    pub ns_uuid: Uuid,
+    pub pid: u32,
+    pub alive: bool,
+}
+
+/// Networking status of the node collected by the monitoring loop and
+/// published under `/fos/local/<plugin uuid>/status` for the agent.
+/// Interfaces are checked against the link dumps of the same cycle.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct NodeNetworkingStatus {
+    pub collected_ms: u64,
+    pub interfaces: usize,
+    /// Interfaces recorded whose link is not in the kernel
+    pub interfaces_missing: Vec<Uuid>,
+    /// Interfaces whose operational state is down
+    pub interfaces_down: Vec<Uuid>,
+    pub virtual_networks: Vec<VNetStatus>,
+    pub ns_managers: Vec<NSManagerStatus>,
+}
+
+/// Cumulative traffic of a virtual network, `in` is the traffic routed
+/// to its bridge and `out` the traffic routed from it
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct VNetTraffic {
+    pub vnet_uuid: Uuid,
+    pub collected_ms: u64,
+    pub packets_in: u64,
+    pub bytes_in: u64,
+    pub packets_out: u64,
+    pub bytes_out: u64,
+}
+
+/// Result of the check of one component, `component` is `netlink`,
+/// `ns_manager/<uuid>`, `dhcp/<vnet uuid>` or `interface/<uuid>`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ComponentHealth {
+    pub component: String,
+    pub healthy: bool,
+    pub detail: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct HealthReport {
+    pub checked_ms: u64,
+    /// All the components are healthy
+    pub healthy: bool,
+    pub components: Vec<ComponentHealth>,
+}
+
+/// Field of a virtual network that differs between the global descriptor
+/// and the record of this node. Values are JSON encoded, `None` when the
+/// field is missing, null or empty.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct NetworkFieldDiff {
+    pub field: String,
+    pub desired: Option<String>,
+    pub recorded: Option<String>,
+}
+
+/// Kernel object of a virtual network not matching the records of this
+/// node, `recorded` is `None` when the object has no record and `actual`
+/// when it is missing from the kernel
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct NetworkKernelDiff {
+    pub object: String,
+    pub ns_name: Option<String>,
+    pub recorded: Option<String>,
+    pub actual: Option<String>,
+}
+
+/// Three-way comparison of a virtual network: the global descriptor is
+/// the desired state, compared with the record of this node, which is
+/// compared with the kernel. Only the differences are listed.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NetworkDiff {
+    pub vnet_uuid: Uuid,
+    pub has_descriptor: bool,
+    pub has_record: bool,
+    pub fields: Vec<NetworkFieldDiff>,
+    pub kernel: Vec<NetworkKernelDiff>,
+}
+
+/// What a table created for a virtual network is used for
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum FirewallTableKind {
+    /// Masquerade of the subnet, port forwards and set rules
+    NAT,
+    FLOATINGIP,
+    ADDRESSMAPPING,
+    /// Masquerade of the NAT64 pool, in the namespace of the network
+    NAT64,
+}
+
+/// `rule` is as listed by `nft -a`, without the handle comment
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FirewallRule {
+    pub handle: Option<u64>,
+    pub rule: String,
+}
+
+/// Base chains have a type, a hook, a priority and a policy
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FirewallChain {
+    pub name: String,
+    pub chain_type: Option<String>,
+    pub hook: Option<String>,
+    pub priority: Option<String>,
+    pub policy: Option<String>,
+    pub rules: Vec<FirewallRule>,
+}
+
+/// `chains` is empty when the table is missing from the kernel,
+/// `ns_name` is `None` for the default namespace
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FirewallTable {
+    pub name: String,
+    pub family: String,
+    pub kind: FirewallTableKind,
+    pub ns_name: Option<String>,
+    pub present: bool,
+    pub chains: Vec<FirewallChain>,
+}
+
+/// nftables tables the plugin created for a virtual network, as they are
+/// in the kernel
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FirewallState {
+    pub vnet_uuid: Uuid,
+    pub tables: Vec<FirewallTable>,
+}
+
+/// Address of a virtual network with the flows it takes part in,
+/// `bytes` is only counted when conntrack accounting is enabled
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ConntrackTalker {
+    pub address: IPAddress,
+    pub flows: u64,
+    pub bytes: Option<u64>,
+}
+
+/// Flows of a virtual network in the conntrack table of the node, the
+/// ones opened from its subnets and the ones forwarded to them
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ConntrackStats {
+    pub vnet_uuid: Uuid,
+    pub flows: u64,
+    /// Flows by protocol, eg. `tcp`
+    pub protocols: BTreeMap<String, u64>,
+    /// TCP flows by state, eg. `ESTABLISHED`
+    pub tcp_states: BTreeMap<String, u64>,
+    /// Addresses with the most flows first
+    pub top_talkers: Vec<ConntrackTalker>,
+}
+
+/// Interface record with the information needed to understand its connectivity
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct InterfaceInspection {
+    pub iface: VirtualInterface,
+    pub ns_name: Option<String>,
+    pub peer: Option<InterfaceEnd>,
+    #[serde(default)]
+    pub metadata: Option<RecordMetadata>,
+    /// Counters of the interface when they were last read
+    #[serde(default)]
+    pub last_stats: Option<InterfaceStatistics>,
+    /// Last operational state seen in the link events
+    #[serde(default)]
+    pub oper_state: Option<LinkOperState>,
+}
+
+/// Operational state of a link, from the netlink link events
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum LinkOperState {
+    UP,
+    DOWN,
+    /// The link was deleted underneath the plugin
+    REMOVED,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum ManagedLinkKind {
+    OVERLAY,
+    DATAPLANE,
+    BRIDGE,
+}
+
+/// Raised when a managed link goes down and cleared when it is back,
+/// published under `/fos/local/<plugin uuid>/alerts/links/<if name>`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LinkAlert {
+    pub if_name: String,
+    pub kind: ManagedLinkKind,
+    pub down: bool,
+    /// Virtual networks marked degraded or no longer degraded
+    pub affected_vnets: Vec<Uuid>,
+    pub timestamp_ms: u64,
+}
+
+/// Change of a stored interface seen in the netlink events, published
+/// under `/fos/local/<plugin uuid>/links/<interface uuid>`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LinkChange {
+    pub intf_uuid: Uuid,
+    pub if_name: String,
+    pub oper_state: LinkOperState,
+    pub addresses: Vec<IPAddress>,
+    pub timestamp_ms: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum InterfaceAdminState {
+    UP,
+    DOWN,
+}
+
+/// STP settings of a bridge port, the ones left to `None` keep their
+/// current value. Kept in the journal and applied again when the port
+/// is re-enslaved.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct BridgePortConfig {
+    /// 0 to 63, the lowest wins between ports of the same cost
+    pub priority: Option<u8>,
+    /// Path cost, at least 1
+    pub cost: Option<u32>,
+}
+
+impl BridgePortConfig {
+    pub fn validate(&self) -> FResult<()> {
+        match self.priority {
+            Some(priority) if priority > 63 => Err(FError::NetworkingError(format!(
+                "Invalid bridge port priority {}",
+                priority
+            ))),
+            _ => match self.cost {
+                Some(0) => Err(FError::NetworkingError(
+                    "Bridge port cost has to be at least 1".to_string(),
+                )),
+                _ => Ok(()),
+            },
+        }
+    }
+
+    /// `IFLA_BRPORT_*` attributes of the settings, the slave data of the
+    /// link info of the port
+    pub fn slave_data(&self) -> Vec<u8> {
+        // IFLA_BRPORT_PRIORITY and IFLA_BRPORT_COST
+        let mut data = Vec::new();
+        let mut nla = |kind: u16, value: &[u8]| {
+            data.extend_from_slice(&(4 + value.len() as u16).to_ne_bytes());
+            data.extend_from_slice(&kind.to_ne_bytes());
+            data.extend_from_slice(value);
+            data.resize((data.len() + 3) & !3, 0);
+        };
+        if let Some(priority) = self.priority {
+            nla(2, &u16::from(priority).to_ne_bytes());
+        }
+        if let Some(cost) = self.cost {
+            nla(3, &cost.to_ne_bytes());
+        }
+        data
+    }
+}
+
+/// MAC assigned to a bridge at creation and re-asserted by the monitoring loop
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PinnedBridgeMAC {
+    pub if_name: String,
+    pub net_ns: Option<Uuid>,
+    pub address: [u8; 6],
+}
+
+/// Counting semaphore limiting how many namespace managers
+/// can be starting at the same time
+#[derive(Clone)]
+pub struct SpawnPermits {
+    sender: crate::runtime::Sender<()>,
+    receiver: crate::runtime::Receiver<()>,
+}
+
+impl SpawnPermits {
+    /// There is always at least one permit, a channel cannot have no room
+    pub fn new(permits: usize) -> Self {
+        let permits = permits.max(1);
+        let (sender, receiver) = crate::runtime::bounded(permits);
+        for _ in 0..permits {
+            // the channel is empty and has room for all the permits
+            let _ = sender.try_send(());
+        }
+        Self { sender, receiver }
+    }
+
+    pub async fn acquire(&self) {
+        let _ = self.receiver.recv().await;
+    }
+
+    pub async fn release(&self) {
+        let _ = self.sender.send(()).await;
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct NSManagerSpawnStats {
+    pub spawned: u64,
+    pub failures: u64,
+    pub last_latency_ms: u64,
+    pub max_latency_ms: u64,
+    pub total_latency_ms: u64,
+}
+
+/// EBUSY retries of a netlink operation, `backoff_ms` is the
+/// total time waited and `timeouts` the times it was given up
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct NetlinkRetryStats {
+    pub retries: u64,
+    pub backoff_ms: u64,
+    pub timeouts: u64,
 }
 
 #[derive(Clone)]
@@ -65,6 +981,172 @@ pub struct LinuxNetwork {
     pub os: Option<OSClient>,
     pub config: LinuxNetworkConfig,
     pub state: Arc<RwLock<LinuxNetworkState>>,
+    pub ns_manager_permits: SpawnPermits,
+    /// Versions of the records written by this plugin, kept apart from
+    /// the state as they are locked while the connector is accessed
+    pub record_versions: Arc<Mutex<HashMap<Uuid, RecordVersion>>>,
+    /// Per-bridge locks serializing attach and detach of ports
+    pub bridge_locks: Arc<Mutex<HashMap<Uuid, Arc<Mutex<()>>>>>,
+    /// Serializes the writes to the global records when this plugin is
+    /// the coordinator
+    pub global_lock: Arc<Mutex<()>>,
+    /// Last operations done on the local records, written in the state dumps
+    pub recent_operations: Arc<Mutex<VecDeque<OperationRecord>>>,
+    /// Serializes the writes to the journal, holds the appends
+    /// since it was last compacted
+    pub journal_lock: Arc<Mutex<usize>>,
+    /// Netlink retries by operation, kept apart from the state
+    /// as the retries can happen while it is locked
+    pub netlink_retries: Arc<Mutex<HashMap<String, NetlinkRetryStats>>>,
+}
+
+/// Records restored from the journal, and the ones that failed
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct JournalReplay {
+    pub namespaces: Vec<Uuid>,
+    pub interfaces: Vec<Uuid>,
+    pub virtual_networks: Vec<Uuid>,
+    pub connection_points: Vec<Uuid>,
+    pub failures: Vec<String>,
+}
+
+/// WireGuard private key of a virtual network, sent to the replication
+/// peer along with the records using it. The peer writes it under its own
+/// `run_path`, named after the virtual network.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ReplicatedSecret {
+    pub vnet_uuid: Uuid,
+    pub content: Vec<u8>,
+}
+
+/// Local records of a node, as folded from its journal, kept by its
+/// warm-standby peer until they are taken over
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NodeReplica {
+    pub node_uuid: Uuid,
+    pub timestamp: u64,
+    pub entries: Vec<JournalEntry>,
+    pub secrets: Vec<ReplicatedSecret>,
+}
+
+/// `NodeReplica` encrypted with the key shared by the two peers, the
+/// UUID of the sending node is authenticated along with it
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SealedReplica {
+    pub node_uuid: Uuid,
+    pub nonce: Vec<u8>,
+    pub ciphertext: Vec<u8>,
+}
+
+/// Write of the internals of a global record asked to the coordinator,
+/// applied if the record is still at version `expected`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GlobalInternalsSwap {
+    pub vnet_uuid: Uuid,
+    pub expected: u64,
+    pub internals: VirtualNetworkInternals,
+}
+
+/// `GlobalInternalsSwap` encrypted with the key shared by all the nodes,
+/// the UUID of the sending node is authenticated along with it
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SealedGlobalSwap {
+    pub node_uuid: Uuid,
+    pub nonce: Vec<u8>,
+    pub ciphertext: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReplicaInfo {
+    pub node_uuid: Uuid,
+    pub timestamp: u64,
+    pub records: usize,
+}
+
+/// Change of a local record done by the plugin,
+/// `timestamp` is in milliseconds since the epoch
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OperationRecord {
+    pub timestamp: u64,
+    pub operation: String,
+    pub target: Uuid,
+    pub error: Option<String>,
+}
+
+/// Serializable part of the plugin state
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LinuxNetworkStateSnapshot {
+    pub uuid: Option<Uuid>,
+    /// PIDs of the Namespace Managers
+    pub ns_managers: HashMap<Uuid, u32>,
+    pub shared_ns_manager: Option<u32>,
+    pub ns_manager_spawn_stats: NSManagerSpawnStats,
+    pub dataplane_ports: HashMap<Uuid, DataplanePort>,
+    pub resolved_endpoints: HashMap<Uuid, String>,
+    pub unicast_vnets: HashMap<Uuid, String>,
+    pub ip_tunnels: HashMap<Uuid, IPTunnel>,
+    pub pinned_bridge_macs: HashMap<Uuid, PinnedBridgeMAC>,
+    pub interface_states: HashMap<Uuid, InterfaceAdminState>,
+    pub interface_names: HashMap<Uuid, (String, Option<Uuid>)>,
+    pub l2tp_sessions: HashMap<Uuid, VNetL2TP>,
+    pub resolvers: HashMap<Uuid, (String, VNetResolver)>,
+    pub remote_dns_records: HashMap<Uuid, HashMap<Uuid, Vec<DNSRecord>>>,
+    pub cp_qos: HashMap<Uuid, ConnectionPointQoS>,
+    #[serde(default)]
+    pub rp_filters: HashMap<Uuid, ReversePathFilter>,
+    pub isolated_bridges: HashMap<Uuid, String>,
+    pub known_vnets: HashSet<Uuid>,
+    pub known_namespaces: HashSet<Uuid>,
+    pub known_cps: HashSet<Uuid>,
+    pub dhcp_servers: HashSet<Uuid>,
+    pub route_failovers: HashMap<Uuid, (DefaultRouteFailover, DefaultRouteFailoverStatus)>,
+    #[serde(default)]
+    pub dhcp_clients: HashMap<Uuid, DHCPClient>,
+    #[serde(default)]
+    pub async_operations: HashMap<Uuid, AsyncOperation>,
+    #[serde(default)]
+    pub dns64_proxies: HashSet<Uuid>,
+    #[serde(default)]
+    pub cp_rate_limits: HashMap<Uuid, ConnectionPointRateLimit>,
+}
+
+/// Written under `run_path` on panics and fatal errors,
+/// `state` is `None` if the state was locked at the time
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StateDump {
+    pub timestamp: u64,
+    pub reason: String,
+    pub state: Option<LinuxNetworkStateSnapshot>,
+    pub operations: Vec<OperationRecord>,
+    #[serde(default)]
+    pub netlink_retries: HashMap<String, NetlinkRetryStats>,
+    /// Model of the kernel when running with `--simulate`
+    #[serde(default)]
+    pub simulation: Option<SimulatedKernel>,
+}
+
+/// Version of an interface or virtual network record as last written by
+/// this plugin, `generation` is incremented on every write and `etag` is
+/// a FNV-1a hash of the record content to detect writes from others.
+/// Virtual networks keep it in their internals, interfaces in the
+/// metadata of the record.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct RecordVersion {
+    pub generation: u64,
+    pub etag: String,
+}
+
+/// When and where a record was created and when it was last written by
+/// this plugin, times are in milliseconds since the epoch and
+/// `created_by` is the node, `None` when the agent could not tell it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RecordMetadata {
+    pub created_at: u64,
+    pub updated_at: u64,
+    pub created_by: Option<Uuid>,
+    /// Version of interface records, which have no room for it
+    #[serde(default)]
+    pub version: Option<RecordVersion>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -73,6 +1155,254 @@ pub struct VNetDHCP {
     pub pid_file: String,
     pub conf: String,
     pub log_file: String,
+    /// File with the DHCP options, re-read by dnsmasq on SIGHUP
+    #[serde(default)]
+    pub opts_file: Option<String>,
+    #[serde(default)]
+    pub router: Option<IPAddress>,
+    /// Addresses of the server on the bridge, one per address family
+    #[serde(default)]
+    pub routers: Vec<IpNetwork>,
+    /// Configuration of the embedded server, `None` with dnsmasq
+    #[serde(default, deserialize_with = "none_if_unknown")]
+    pub embedded: Option<DHCPServerConfig>,
+    /// File with the reservations, re-read by dnsmasq on SIGHUP
+    #[serde(default)]
+    pub hosts_file: Option<String>,
+    /// Backend serving the network, see `VNetDHCP::backend_kind`
+    #[serde(default, deserialize_with = "none_if_unknown")]
+    pub backend: Option<DHCPBackend>,
+}
+
+/// Caching resolver serving the clients of a virtual network, it keeps
+/// answering from its cache and local records when upstream is unreachable
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VNetResolver {
+    pub pid_file: String,
+    pub conf: String,
+    pub log_file: String,
+    pub hosts_file: String,
+    /// Upstream servers, missing for resolvers started by older versions
+    #[serde(default)]
+    pub servers_file: Option<String>,
+}
+
+/// Flow export of a virtual network: softflowd on its bridge sends
+/// NetFlow v9 records to the collector of the configuration
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct VNetFlowExport {
+    pub bridge: String,
+    pub collector: String,
+    /// One packet out of `sampling_rate` is accounted
+    pub sampling_rate: u32,
+    pub pid_file: String,
+    pub ctl_socket: String,
+}
+
+/// Source NAT of a virtual network on the overlay interface, a
+/// masquerade when no field is set. `address` only applies to the
+/// subnets of its family, `ports` to TCP and UDP. With `disabled` the
+/// subnets are forwarded without translation.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct SNATOptions {
+    #[serde(default)]
+    pub address: Option<IPAddress>,
+    #[serde(default)]
+    pub ports: Option<(u16, u16)>,
+    #[serde(default)]
+    pub disabled: bool,
+}
+
+/// NAT64 requested for an IPv6-only virtual network, addresses under
+/// `prefix` are translated to the IPv4 ones in their last 32 bits.
+/// `prefix` defaults to the well-known 64:ff9b::/96 and `pool` to
+/// 192.168.255.0/24, the IPv4 addresses given to the IPv6 clients
+/// before they are masqueraded.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct NAT64Options {
+    pub prefix: Option<(IPAddress, u8)>,
+    pub pool: Option<(IPAddress, u8)>,
+}
+
+/// NAT64 gateway running in the namespace of a virtual network: tayga
+/// translating between `tun` and the namespace, a masquerade of `pool`
+/// in `nft_table` and the DNS64 proxy answering on the bridge
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VNetNAT64 {
+    pub tun: String,
+    pub prefix: (IPAddress, u8),
+    pub pool: (IPAddress, u8),
+    /// Address of tayga, the last one of the subnet of the network
+    pub ipv6_addr: IPAddress,
+    pub conf: String,
+    pub pid_file: String,
+    pub data_dir: String,
+    pub nft_table: String,
+}
+
+/// DHCP client holding the lease of an interface, it renews the lease
+/// on its own until it is released. `ns_name` is `None` for the
+/// default namespace. The embedded client runs in the plugin, `pid` is
+/// the one of the plugin and `pid_file` is empty.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DHCPClient {
+    pub if_name: String,
+    pub ns_name: Option<String>,
+    pub backend: DHCPClientBackend,
+    pub pid: u32,
+    pub pid_file: String,
+    pub lease_file: String,
+    /// Lease of the embedded client
+    pub lease: Option<DHCPLease>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum AsyncOperationStatus {
+    RUNNING,
+    COMPLETED,
+    FAILED,
+}
+
+/// Long running operation started by an `_async` RPC, `step` goes from
+/// 1 to `steps` and `description` tells what the step is doing.
+/// Times are in milliseconds since the epoch.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AsyncOperation {
+    pub uuid: Uuid,
+    pub operation: String,
+    pub target: Uuid,
+    pub status: AsyncOperationStatus,
+    pub step: u32,
+    pub steps: u32,
+    pub description: String,
+    pub error: Option<String>,
+    pub started_at: u64,
+    pub finished_at: Option<u64>,
+}
+
+/// Address always given by the DHCP server of a virtual network to the
+/// client with the given MAC, it can be outside of the DHCP range
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DHCPReservation {
+    pub mac: MACAddress,
+    pub address: IPAddress,
+    pub hostname: Option<String>,
+}
+
+/// Fixed address given with `assign_static_address` to an interface
+/// attached to a virtual network
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct StaticAddress {
+    pub intf_uuid: Uuid,
+    pub address: IPAddress,
+}
+
+/// Local name served by the resolver of a virtual network
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DNSRecord {
+    pub name: String,
+    pub address: IPAddress,
+}
+
+/// Network boot parameters, sent as DHCP options 66 and 67
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DHCPBootOptions {
+    pub tftp_server: String,
+    pub filename: String,
+}
+
+/// Value of a DHCP option, integers are sent in network byte order
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum DHCPOptionValue {
+    Text(String),
+    Addresses(Vec<IPAddress>),
+    U8(u8),
+    U16(u16),
+    U32(u32),
+}
+
+/// DHCPv4 option sent as it is to the clients, the ones set by the
+/// plugin (eg. router, DNS, lease time) cannot be overridden
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DHCPOption {
+    pub code: u8,
+    pub value: DHCPOptionValue,
+}
+
+/// New DHCP settings of an existing virtual network, fields left
+/// to `None` keep their current value, `boot: Some(None)` removes
+/// the boot parameters.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct VirtualNetworkUpdate {
+    pub ntp_servers: Option<Vec<IPAddress>>,
+    pub boot: Option<Option<DHCPBootOptions>>,
+    pub static_routes: Option<Vec<DHCPStaticRoute>>,
+    #[serde(default)]
+    pub mtu: Option<Option<u16>>,
+    #[serde(default)]
+    pub domain_name: Option<Option<String>>,
+    #[serde(default)]
+    pub dhcp_options: Option<Vec<DHCPOption>>,
+    #[serde(default)]
+    pub dns_upstream: Option<Vec<IPAddress>>,
+}
+
+/// Classless static route (DHCP option 121) learnt by the DHCP clients
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DHCPStaticRoute {
+    pub destination: IPAddress,
+    pub prefix: u8,
+    pub gateway: IPAddress,
+}
+
+/// Default route of a network namespace, `gateway` has to be of the
+/// given family, without it the route is on-link. Routes of the same
+/// family with different metrics coexist, the lowest one is preferred.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DefaultRoute {
+    pub version: IPVersion,
+    pub gateway: Option<IPAddress>,
+    pub metric: Option<u32>,
+}
+
+/// Gateway of a default route with failover, reached through
+/// an interface of the namespace
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FailoverGateway {
+    pub intf_uuid: Uuid,
+    pub gateway: IPAddress,
+}
+
+/// Two default routes of a namespace, eg. through two redundant provider
+/// networks. The primary one has the lowest metric until its gateway
+/// misses `max_failures` probes, then the metrics are swapped until it
+/// answers again. Probes are sent by the monitoring loop.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DefaultRouteFailover {
+    pub primary: FailoverGateway,
+    pub backup: FailoverGateway,
+    pub max_failures: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct DefaultRouteFailoverStatus {
+    pub primary_active: bool,
+    /// Consecutive probes the primary gateway did not answer
+    pub failures: u32,
+    pub switches: u64,
+}
+
+/// Address allotted outside of fog05 (eg. by cloud-init or an external
+/// IPAM), with the gateway, resolvers and routes that come with it
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ExternalAddress {
+    pub address: IpNetwork,
+    #[serde(default)]
+    pub gateway: Option<IPAddress>,
+    #[serde(default)]
+    pub dns_servers: Vec<IPAddress>,
+    #[serde(default)]
+    pub routes: Vec<DHCPStaticRoute>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -82,23 +1412,359 @@ pub struct VNetNetns {
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VNetWireGuard {
+    pub if_name: String,
+    pub public_key: String,
+    pub private_key_file: String,
+    pub listen_port: u16,
+}
+
+/// VRF device realizing a routed virtual network,
+/// member interfaces are enslaved to the VRF
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VNetVRF {
+    pub if_name: String,
+    pub table: u32,
+    pub members: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct VirtualNetworkInternals {
+    #[serde(default)]
     pub dhcp: Option<VNetDHCP>,
+    #[serde(default)]
     pub associated_netns: Option<VNetNetns>,
+    #[serde(default)]
     pub associated_tables: Vec<String>,
+    #[serde(default, deserialize_with = "none_if_unknown")]
+    pub wireguard: Option<VNetWireGuard>,
+    #[serde(default, deserialize_with = "none_if_unknown")]
+    pub remote_endpoint: Option<RemoteEndpoint>,
+    #[serde(default)]
+    pub unicast_peers: Option<Vec<IPAddress>>,
+    #[serde(default, deserialize_with = "none_if_unknown")]
+    pub vrf: Option<VNetVRF>,
+    #[serde(default, deserialize_with = "none_if_unknown")]
+    pub vxlan_options: Option<VXLANOptions>,
+    /// Derive the DHCP range from the subnet when the virtual network
+    /// does not provide one, can be set in the descriptor internals
+    /// and overrides `auto_dhcp_range` from the plugin configuration
+    #[serde(default)]
+    pub auto_dhcp_range: Option<bool>,
+    #[serde(default, deserialize_with = "none_if_unknown")]
+    pub l2tp: Option<VNetL2TP>,
+    #[serde(default, deserialize_with = "skip_unknown")]
+    pub static_routes: Vec<DHCPStaticRoute>,
+    #[serde(default, deserialize_with = "skip_unknown")]
+    pub ntp_servers: Vec<IPAddress>,
+    #[serde(default, deserialize_with = "none_if_unknown")]
+    pub boot: Option<DHCPBootOptions>,
+    /// Interface MTU announced to the clients, DHCP option 26
+    #[serde(default)]
+    pub dhcp_mtu: Option<u16>,
+    /// DHCP option 15
+    #[serde(default)]
+    pub domain_name: Option<String>,
+    #[serde(default, deserialize_with = "skip_unknown")]
+    pub dhcp_options: Vec<DHCPOption>,
+    /// Run a caching resolver in the network namespace, can be set in
+    /// the descriptor internals and overrides `dns_cache` from the
+    /// plugin configuration
+    #[serde(default)]
+    pub dns_cache: Option<bool>,
+    /// Servers the caching resolver forwards to, when empty the ones of
+    /// `dns_upstream` in the plugin configuration or the DNS servers of
+    /// the network are used
+    #[serde(default)]
+    pub dns_upstream: Vec<IPAddress>,
+    #[serde(default, deserialize_with = "none_if_unknown")]
+    pub resolver: Option<VNetResolver>,
+    /// NAT64 and DNS64 for an IPv6-only network, can be set in the
+    /// descriptor internals
+    #[serde(default, deserialize_with = "none_if_unknown")]
+    pub nat64: Option<NAT64Options>,
+    /// Source NAT of the network, can be set in the descriptor internals:
+    /// networks requesting it get a NAT table when they are created
+    #[serde(default, deserialize_with = "none_if_unknown")]
+    pub snat: Option<SNATOptions>,
+    #[serde(default, deserialize_with = "none_if_unknown")]
+    pub nat64_gateway: Option<VNetNAT64>,
+    #[serde(default, deserialize_with = "skip_unknown")]
+    pub dns_records: Vec<DNSRecord>,
+    #[serde(default, deserialize_with = "skip_unknown")]
+    pub dhcp_reservations: Vec<DHCPReservation>,
+    #[serde(default, deserialize_with = "skip_unknown")]
+    pub floating_ips: Vec<FloatingIP>,
+    #[serde(default, deserialize_with = "skip_unknown")]
+    pub address_mappings: Vec<AddressMapping>,
+    #[serde(default, deserialize_with = "skip_unknown")]
+    pub port_forwards: Vec<PortForward>,
+    #[serde(default, deserialize_with = "skip_unknown")]
+    pub nft_sets: Vec<NFTSet>,
+    #[serde(default, deserialize_with = "skip_unknown")]
+    pub set_rules: Vec<SetRule>,
+    #[serde(default, deserialize_with = "skip_unknown")]
+    pub egress_rules: Vec<EgressRule>,
+    /// Virtual networks allowed to exchange traffic with this one
+    #[serde(default)]
+    pub peerings: Vec<Uuid>,
+    /// Virtual networks allowed to open connections to this one, which
+    /// can only answer them, see `SharedServices`
+    #[serde(default)]
+    pub services_tenants: Vec<Uuid>,
+    /// Links to the tenants of a services network
+    #[serde(default, deserialize_with = "skip_unknown")]
+    pub services_links: Vec<VNetServicesLink>,
+    #[serde(default, deserialize_with = "skip_unknown")]
+    pub mirrors: Vec<VNetMirror>,
+    #[serde(default, deserialize_with = "none_if_unknown")]
+    pub fdb: Option<FDBOptions>,
+    /// Configuration of the other address family of a dual-stack
+    /// network, can be set in the descriptor internals
+    #[serde(default, deserialize_with = "none_if_unknown")]
+    pub secondary_ip_configuration: Option<IPConfiguration>,
+    /// Namespaces of other connection points this network is attached to
+    #[serde(default, deserialize_with = "skip_unknown")]
+    pub namespace_attachments: Vec<VNetNamespaceAttachment>,
+    /// VLAN tag of the dataplane interface a provider network is mapped
+    /// to, no VXLAN is used, can be set in the descriptor internals
+    #[serde(default)]
+    pub provider_vlan: Option<u16>,
+    /// Central DHCP server the requests of the network are relayed to
+    /// instead of being served on the node, with the address of the
+    /// bridge as relay agent address. It is reached through the routes
+    /// of the namespace, can be set in the descriptor internals
+    #[serde(default)]
+    pub dhcp_relay: Option<IPAddress>,
+    /// Subnet pool and allocations of the network, the ones in the
+    /// global record are authoritative
+    #[serde(default, deserialize_with = "none_if_unknown")]
+    pub ipam: Option<VNetIPAM>,
+    /// Tenant the network is accounted to by the churn limits, can be
+    /// set in the descriptor internals
+    #[serde(default)]
+    pub tenant: Option<String>,
+    #[serde(default, deserialize_with = "skip_unknown")]
+    pub static_addresses: Vec<StaticAddress>,
+    #[serde(default, deserialize_with = "none_if_unknown")]
+    pub flow_export: Option<VNetFlowExport>,
+    /// Managed links the network depends on that are down, the network
+    /// is degraded while it is not empty
+    #[serde(default)]
+    pub degraded_by: Vec<String>,
+    /// Bridge of the network in the isolation table, set when the
+    /// isolation is enforced
+    #[serde(default)]
+    pub isolated_bridge: Option<String>,
+    /// Nodes of an ELAN network using unicast VXLAN, only in the
+    /// global record
+    #[serde(default, deserialize_with = "skip_unknown")]
+    pub elan_members: Vec<VNetMember>,
+    /// Nodes owning the two ends of an ELINE network, only in the
+    /// global record
+    #[serde(default, deserialize_with = "skip_unknown")]
+    pub eline_ends: Vec<VNetMember>,
+    /// Version of the record as last written by this plugin, the etag
+    /// is computed with this field unset
+    #[serde(default, deserialize_with = "none_if_unknown")]
+    pub record_version: Option<RecordVersion>,
+    /// Version of the global record, incremented by the coordinator on
+    /// every write, see `compare_and_swap_global_internals`
+    #[serde(default)]
+    pub global_version: u64,
+    /// Elements of the lists not understood, by list, kept so that they
+    /// are not lost when the internals are written back
+    #[serde(skip)]
+    pub unknown_elements: BTreeMap<String, Vec<serde_json::Value>>,
+    /// Optional values not understood, by JSON pointer, kept so that
+    /// they are written back while the field is not set again
+    #[serde(skip)]
+    pub unknown_values: BTreeMap<String, serde_json::Value>,
+    /// Fields written by a newer version of the plugin, kept so that
+    /// they are not lost when the internals are written back
+    #[serde(flatten)]
+    pub unknown_fields: serde_json::Map<String, serde_json::Value>,
+}
+
+/// Lists of the internals deserialized with `skip_unknown`
+const ELEMENT_LISTS: &[&str] = &[
+    "static_routes",
+    "ntp_servers",
+    "dhcp_options",
+    "dns_records",
+    "dhcp_reservations",
+    "floating_ips",
+    "address_mappings",
+    "port_forwards",
+    "nft_sets",
+    "set_rules",
+    "egress_rules",
+    "mirrors",
+    "namespace_attachments",
+    "static_addresses",
+    "elan_members",
+    "eline_ends",
+    "services_links",
+];
+
+/// Optional values of the internals deserialized with `none_if_unknown`
+const OPTIONAL_VALUES: &[&str] = &[
+    "/wireguard",
+    "/remote_endpoint",
+    "/vrf",
+    "/vxlan_options",
+    "/l2tp",
+    "/boot",
+    "/resolver",
+    "/nat64",
+    "/snat",
+    "/nat64_gateway",
+    "/fdb",
+    "/secondary_ip_configuration",
+    "/ipam",
+    "/flow_export",
+    "/record_version",
+    "/dhcp/embedded",
+    "/dhcp/backend",
+];
+
+/// Remote endpoint of an ELINE virtual network,
+/// hostnames are resolved when the network is created and then
+/// periodically by the monitoring loop, the tunnel is re-pointed
+/// if the resolved address changes.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum RemoteEndpoint {
+    Address(IPAddress),
+    Hostname(String),
+}
+
+/// Peer and tunnel information used to build an ELINE virtual network
+/// over a WireGuard interface instead of the plain overlay interface.
+/// The VXLAN tunnel is then created between `local_tunnel_addr`
+/// and `peer_tunnel_addr`, so it is encrypted by WireGuard.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WireGuardInfo {
+    pub listen_port: u16,
+    pub local_tunnel_addr: IpNetwork,
+    pub peer_tunnel_addr: IPAddress,
+    pub peer_public_key: String,
+    pub peer_endpoint: Option<(IPAddress, u16)>,
+    pub allowed_ips: Vec<IpNetwork>,
+    pub persistent_keepalive: Option<u16>,
+}
+
+/// Deserializes an optional value written by a newer version of the
+/// plugin as `None` when it is not understood (eg. an unknown variant),
+/// `deserialize_network_internals` keeps it in `unknown_values`
+fn none_if_unknown<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: DeserializeOwned,
+{
+    let value = Option::<serde_json::Value>::deserialize(deserializer)?;
+    Ok(value.and_then(|v| match serde_json::from_value(v) {
+        Ok(v) => Some(v),
+        Err(e) => {
+            log::warn!("Ignoring unknown value in network internals: {}", e);
+            None
+        }
+    }))
+}
+
+/// Deserializes a list skipping the elements that are not understood,
+/// `deserialize_network_internals` keeps them in `unknown_elements`
+fn skip_unknown<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: DeserializeOwned,
+{
+    let values = Vec::<serde_json::Value>::deserialize(deserializer)?;
+    Ok(values
+        .into_iter()
+        .filter_map(|v| match serde_json::from_value(v) {
+            Ok(v) => Some(v),
+            Err(e) => {
+                log::warn!("Ignoring unknown element in network internals: {}", e);
+                None
+            }
+        })
+        .collect())
 }
 
 pub fn serialize_network_internals(data: &VirtualNetworkInternals) -> FResult<Vec<u8>> {
-    Ok(serde_json::to_string(data)
+    let mut value =
+        serde_json::to_value(data).map_err(|e| FError::NetworkingError(format!("{}", e)))?;
+    if let serde_json::Value::Object(fields) = &mut value {
+        for (list, elements) in &data.unknown_elements {
+            if let Some(serde_json::Value::Array(known)) = fields.get_mut(list) {
+                known.extend(elements.iter().cloned());
+            }
+        }
+    }
+    for (pointer, raw) in &data.unknown_values {
+        if !value.pointer(pointer).map_or(true, |v| v.is_null()) {
+            continue;
+        }
+        let (parent, field) = pointer.split_at(pointer.rfind('/').unwrap_or(0));
+        if let Some(serde_json::Value::Object(fields)) = value.pointer_mut(parent) {
+            fields.insert(field.trim_start_matches('/').to_string(), raw.clone());
+        }
+    }
+    Ok(serde_json::to_string(&value)
         .map_err(|e| FError::NetworkingError(format!("{}", e)))?
         .into_bytes())
 }
 
 pub fn deserialize_network_internals(raw_data: &[u8]) -> FResult<VirtualNetworkInternals> {
-    serde_json::from_str::<VirtualNetworkInternals>(
+    let value = serde_json::from_str::<serde_json::Value>(
         std::str::from_utf8(raw_data).map_err(|e| FError::NetworkingError(format!("{}", e)))?,
     )
-    .map_err(|e| FError::NetworkingError(format!("{}", e)))
+    .map_err(|e| FError::NetworkingError(format!("{}", e)))?;
+    let mut internals = serde_json::from_value::<VirtualNetworkInternals>(value.clone())
+        .map_err(|e| FError::NetworkingError(format!("{}", e)))?;
+    for list in ELEMENT_LISTS {
+        if let Some(serde_json::Value::Array(elements)) = value.get(list) {
+            let unknown: Vec<serde_json::Value> = elements
+                .iter()
+                .filter(|e| !is_known_element(list, e))
+                .cloned()
+                .collect();
+            if !unknown.is_empty() {
+                internals.unknown_elements.insert(list.to_string(), unknown);
+            }
+        }
+    }
+    let known =
+        serde_json::to_value(&internals).map_err(|e| FError::NetworkingError(format!("{}", e)))?;
+    for pointer in OPTIONAL_VALUES {
+        if let Some(raw) = value.pointer(pointer).filter(|v| !v.is_null()) {
+            if known.pointer(pointer).map_or(true, |v| v.is_null()) {
+                internals
+                    .unknown_values
+                    .insert(pointer.to_string(), raw.clone());
+            }
+        }
+    }
+    Ok(internals)
+}
+
+/// Whether `skip_unknown` keeps `element` when it is in `list`
+fn is_known_element(list: &str, element: &serde_json::Value) -> bool {
+    let mut fields = serde_json::Map::new();
+    fields.insert(
+        list.to_string(),
+        serde_json::Value::Array(vec![element.clone()]),
+    );
+    serde_json::from_value::<VirtualNetworkInternals>(serde_json::Value::Object(fields))
+        .ok()
+        .and_then(|internals| serde_json::to_value(internals).ok())
+        .and_then(|value| {
+            value
+                .get(list)
+                .and_then(|v| v.as_array())
+                .map(|v| !v.is_empty())
+        })
+        .unwrap_or(false)
 }
 
 pub fn serialize_plugin_config(data: &LinuxNetworkConfig) -> FResult<Vec<u8>> {
@@ -108,33 +1774,164 @@ pub fn serialize_plugin_config(data: &LinuxNetworkConfig) -> FResult<Vec<u8>> {
 }
 
 pub fn deserialize_plugin_config(raw_data: &[u8]) -> FResult<LinuxNetworkConfig> {
-    serde_yaml::from_str::<LinuxNetworkConfig>(
+    let config = serde_yaml::from_str::<LinuxNetworkConfig>(
         std::str::from_utf8(raw_data).map_err(|e| FError::NetworkingError(format!("{}", e)))?,
     )
-    .map_err(|e| FError::NetworkingError(format!("{}", e)))
+    .map_err(|e| FError::NetworkingError(format!("{}", e)))?;
+    if config.ns_manager_max_concurrent_spawns == Some(0) {
+        return Err(FError::NetworkingError(
+            "Invalid ns_manager_max_concurrent_spawns 0, at least one is needed".to_string(),
+        ));
+    }
+    Ok(config)
+}
+
+/// Tracing context sent with every NamespaceManager request, the manager
+/// serves the request in a span carrying the same `request_id` so that its
+/// netlink spans can be matched with the plugin operation that sent it.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct SpanContext {
+    pub request_id: Uuid,
+    /// Id of the plugin span the request was sent from, `None` when
+    /// the span is disabled
+    pub parent_span: Option<u64>,
+}
+
+impl SpanContext {
+    /// Context of a request sent from `span`
+    pub fn new(span: &tracing::Span) -> Self {
+        Self {
+            request_id: Uuid::new_v4(),
+            parent_span: span.id().map(|id| id.into_u64()),
+        }
+    }
+
+    /// Span in which a manager serves the request
+    pub fn request_span(&self, method: &str, ns_uuid: Uuid) -> tracing::Span {
+        tracing::trace_span!(
+            target: crate::logging::NS_MANAGER,
+            "ns_manager_serve",
+            method,
+            %ns_uuid,
+            request_id = %self.request_id,
+            parent_span = ?self.parent_span
+        )
+    }
 }
 
 #[znservice(timeout_s = 60, prefix = "/fos/local")]
 pub trait NamespaceManager {
-    async fn set_virtual_interface_up(&self, iface: String) -> FResult<()>;
-    async fn set_virtual_interface_down(&self, iface: String) -> FResult<()>;
-    async fn set_default_route(&self, iface: String) -> FResult<()>;
-    async fn check_virtual_interface_exists(&self, iface: String) -> FResult<bool>;
-    async fn move_virtual_interface_into_default_ns(&self, iface: String) -> FResult<()>;
-    async fn set_virtual_interface_mac(&self, iface: String, address: Vec<u8>) -> FResult<()>;
-    async fn set_virtual_interface_name(&self, iface: String, name: String) -> FResult<()>;
-    async fn del_virtual_interface_address(&self, iface: String, addr: IPAddress) -> FResult<()>;
-    async fn get_virtual_interface_addresses(&self, iface: String) -> FResult<Vec<IPAddress>>;
+    async fn set_virtual_interface_up(&self, ctx: SpanContext, iface: String) -> FResult<()>;
+    async fn set_virtual_interface_down(&self, ctx: SpanContext, iface: String) -> FResult<()>;
+    async fn set_default_route(&self, ctx: SpanContext, iface: String) -> FResult<()>;
+    async fn add_virtual_interface_default_route(
+        &self,
+        ctx: SpanContext,
+        iface: String,
+        route: DefaultRoute,
+    ) -> FResult<()>;
+    async fn del_virtual_interface_default_route(
+        &self,
+        ctx: SpanContext,
+        iface: String,
+        route: DefaultRoute,
+    ) -> FResult<()>;
+    async fn probe_gateway(
+        &self,
+        ctx: SpanContext,
+        iface: String,
+        gateway: IPAddress,
+    ) -> FResult<bool>;
+    async fn dump_link_stats(&self, ctx: SpanContext) -> FResult<LinkStatsDump>;
+    async fn set_reverse_path_filter(
+        &self,
+        ctx: SpanContext,
+        iface: Option<String>,
+        filter: ReversePathFilter,
+    ) -> FResult<()>;
+    async fn get_martian_counters(&self, ctx: SpanContext) -> FResult<MartianCounters>;
+    async fn set_virtual_interface_bridge_port(
+        &self,
+        ctx: SpanContext,
+        iface: String,
+        config: BridgePortConfig,
+    ) -> FResult<()>;
+    async fn flush_virtual_interface_fdb(&self, ctx: SpanContext, iface: String) -> FResult<()>;
+    async fn add_virtual_interface_route(
+        &self,
+        ctx: SpanContext,
+        iface: String,
+        destination: IpNetwork,
+        gateway: IPAddress,
+    ) -> FResult<()>;
+    async fn del_virtual_interface_route(
+        &self,
+        ctx: SpanContext,
+        iface: String,
+        destination: IpNetwork,
+        gateway: IPAddress,
+    ) -> FResult<()>;
+    async fn check_virtual_interface_exists(
+        &self,
+        ctx: SpanContext,
+        iface: String,
+    ) -> FResult<bool>;
+    async fn move_virtual_interface_into_default_ns(
+        &self,
+        ctx: SpanContext,
+        iface: String,
+    ) -> FResult<()>;
+    async fn set_virtual_interface_mac(
+        &self,
+        ctx: SpanContext,
+        iface: String,
+        address: Vec<u8>,
+    ) -> FResult<()>;
+    async fn set_virtual_interface_mtu(
+        &self,
+        ctx: SpanContext,
+        iface: String,
+        mtu: u32,
+    ) -> FResult<()>;
+    async fn set_virtual_interface_name(
+        &self,
+        ctx: SpanContext,
+        iface: String,
+        name: String,
+    ) -> FResult<()>;
+    async fn del_virtual_interface_address(
+        &self,
+        ctx: SpanContext,
+        iface: String,
+        addr: IPAddress,
+    ) -> FResult<()>;
+    async fn get_virtual_interface_addresses(
+        &self,
+        ctx: SpanContext,
+        iface: String,
+    ) -> FResult<Vec<IPAddress>>;
     async fn add_virtual_interface_address(
         &self,
+        ctx: SpanContext,
         iface: String,
         addr: Option<IpNetwork>,
     ) -> FResult<Vec<IPAddress>>;
-    async fn set_virtual_interface_master(&self, iface: String, master: String) -> FResult<()>;
-    async fn set_virtual_interface_nomaster(&self, iface: String) -> FResult<()>;
-    async fn del_virtual_interface(&self, iface: String) -> FResult<()>;
+    async fn set_virtual_interface_master(
+        &self,
+        ctx: SpanContext,
+        iface: String,
+        master: String,
+    ) -> FResult<()>;
+    async fn get_virtual_interface_master(
+        &self,
+        ctx: SpanContext,
+        iface: String,
+    ) -> FResult<Option<String>>;
+    async fn set_virtual_interface_nomaster(&self, ctx: SpanContext, iface: String) -> FResult<()>;
+    async fn del_virtual_interface(&self, ctx: SpanContext, iface: String) -> FResult<()>;
     async fn add_virtual_interface_ptp_vxlan(
         &self,
+        ctx: SpanContext,
         iface: String,
         dev: String,
         vni: u32,
@@ -144,15 +1941,530 @@ pub trait NamespaceManager {
     ) -> FResult<()>;
     async fn add_virtual_interface_mcast_vxlan(
         &self,
+        ctx: SpanContext,
         iface: String,
         dev: String,
         vni: u32,
         mcast_addr: IPAddress,
         port: u16,
     ) -> FResult<()>;
-    async fn add_virtual_interface_vlan(&self, iface: String, dev: String, tag: u16)
-        -> FResult<()>;
-    async fn add_virtual_interface_veth(&self, iface_i: String, iface_e: String) -> FResult<()>;
-    async fn add_virtual_interface_bridge(&self, br_name: String) -> FResult<()>;
-    async fn list_interfaces(&self) -> FResult<Vec<String>>;
+    async fn add_virtual_interface_vlan(
+        &self,
+        ctx: SpanContext,
+        iface: String,
+        dev: String,
+        tag: u16,
+    ) -> FResult<()>;
+    async fn add_virtual_interface_ip_tunnel(
+        &self,
+        ctx: SpanContext,
+        iface: String,
+        kind: IPTunnelKind,
+        local_addr: IPAddress,
+        remote_addr: IPAddress,
+        ttl: Option<u8>,
+    ) -> FResult<()>;
+    async fn add_virtual_interface_veth(
+        &self,
+        ctx: SpanContext,
+        iface_i: String,
+        iface_e: String,
+    ) -> FResult<()>;
+    async fn add_virtual_interface_bridge(&self, ctx: SpanContext, br_name: String) -> FResult<()>;
+    async fn list_interfaces(&self, ctx: SpanContext) -> FResult<Vec<String>>;
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum DataplanePortKind {
+    /// vhost-user port on the DPDK bridge, the switch connects as client
+    /// to the socket created by the hypervisor
+    VHOSTUSER,
+    /// vDPA device created from the given management device (eg. pci/0000:03:00.2)
+    VDPA(String),
+}
+
+/// New transport parameters for the VXLAN interface of an existing
+/// virtual network, fields left to `None` keep their current value.
+/// `mcast_addr` applies to L2 networks, `remote_addr` to ELINE networks.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct VXLANTransportUpdate {
+    pub vni: Option<u32>,
+    pub port: Option<u16>,
+    pub mcast_addr: Option<IPAddress>,
+    pub remote_addr: Option<IPAddress>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum IPTunnelKind {
+    /// IPv4 over IPv4
+    IPIP,
+    /// IPv6 over IPv4
+    SIT,
+}
+
+impl std::fmt::Display for IPTunnelKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            IPTunnelKind::IPIP => write!(f, "ipip"),
+            IPTunnelKind::SIT => write!(f, "sit"),
+        }
+    }
+}
+
+/// Encapsulation used by an L2TPv3 tunnel
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum L2TPEncap {
+    UDP,
+    IP,
+}
+
+impl std::fmt::Display for L2TPEncap {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            L2TPEncap::UDP => write!(f, "udp"),
+            L2TPEncap::IP => write!(f, "ip"),
+        }
+    }
+}
+
+/// Carrier provided L2TPv3 pseudowire, the peer ids are given by the carrier
+/// while the local tunnel and session ids are allocated by the plugin
+/// when not set.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct L2TPv3Info {
+    pub local_addr: IPAddress,
+    pub remote_addr: IPAddress,
+    pub encap: L2TPEncap,
+    pub udp_sport: Option<u16>,
+    pub udp_dport: Option<u16>,
+    pub tunnel_id: Option<u32>,
+    pub peer_tunnel_id: u32,
+    pub session_id: Option<u32>,
+    pub peer_session_id: u32,
+}
+
+/// L2TPv3 session terminating a virtual network,
+/// the session interface is enslaved to `bridge`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VNetL2TP {
+    pub if_name: String,
+    pub bridge: String,
+    pub tunnel_id: u32,
+    pub peer_tunnel_id: u32,
+    pub session_id: u32,
+    pub peer_session_id: u32,
+}
+
+/// L3 tunnel interface, `net_ns` is `None` for the default namespace
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct IPTunnel {
+    pub uuid: Uuid,
+    pub if_name: String,
+    pub kind: IPTunnelKind,
+    pub local_addr: IPAddress,
+    pub remote_addr: IPAddress,
+    pub ttl: Option<u8>,
+    pub net_ns: Option<Uuid>,
+}
+
+/// Port provisioned on a DPDK-backed switch and used as FDU connection
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DataplanePort {
+    pub uuid: Uuid,
+    pub if_name: String,
+    pub kind: DataplanePortKind,
+    pub socket_path: Option<String>,
+}
+
+/// Interface of a shared namespace manager process. The requests of the
+/// added namespaces carry the namespace UUID, the manager enters the
+/// namespace to serve each of them and answers with the bincode
+/// encoded result of the request.
+#[znservice(timeout_s = 60, prefix = "/fos/local")]
+pub trait SharedNamespaceManager {
+    async fn add_namespace(&self, ns_name: String, ns_uuid: Uuid) -> FResult<()>;
+    async fn remove_namespace(&self, ns_uuid: Uuid) -> FResult<()>;
+    async fn list_namespaces(&self) -> FResult<Vec<Uuid>>;
+    async fn namespace_request(
+        &self,
+        ns_uuid: Uuid,
+        ctx: SpanContext,
+        req: NSManagerRequest,
+    ) -> FResult<Vec<u8>>;
+}
+
+/// Linux specific operations exposed by the plugin that are not part
+/// of the generic fog05 `NetworkingPlugin` interface.
+/// The server is registered with the same instance UUID of the plugin.
+#[znservice(timeout_s = 60, prefix = "/fos/local")]
+pub trait LinuxNetworkExtension {
+    async fn create_wireguard_virtual_network(
+        &self,
+        vnet_uuid: Uuid,
+        wg_info: WireGuardInfo,
+    ) -> FResult<VirtualNetwork>;
+    async fn create_eline_virtual_network(
+        &self,
+        vnet_uuid: Uuid,
+        remote: RemoteEndpoint,
+    ) -> FResult<VirtualNetwork>;
+    async fn update_eline_remote_endpoint(
+        &self,
+        vnet_uuid: Uuid,
+        remote: RemoteEndpoint,
+    ) -> FResult<VirtualNetwork>;
+    async fn create_unicast_vxlan_virtual_network(
+        &self,
+        vnet_uuid: Uuid,
+    ) -> FResult<VirtualNetwork>;
+    async fn create_elan_virtual_network(&self, vnet_uuid: Uuid) -> FResult<VirtualNetwork>;
+    async fn get_vxlan_endpoint(&self, vnet_uuid: Uuid) -> FResult<IPAddress>;
+    async fn announce_eline_endpoint(
+        &self,
+        vnet_uuid: Uuid,
+        node_uuid: Uuid,
+        remote_addr: IPAddress,
+    ) -> FResult<IPAddress>;
+    async fn create_vrf_virtual_network(
+        &self,
+        vnet_uuid: Uuid,
+        table: u32,
+    ) -> FResult<VirtualNetwork>;
+    async fn add_vrf_member(&self, vnet_uuid: Uuid, iface: String) -> FResult<VirtualNetwork>;
+    async fn remove_vrf_member(&self, vnet_uuid: Uuid, iface: String) -> FResult<VirtualNetwork>;
+    async fn create_l2tpv3_virtual_network(
+        &self,
+        vnet_uuid: Uuid,
+        info: L2TPv3Info,
+    ) -> FResult<VirtualNetwork>;
+    async fn get_l2tpv3_session(&self, vnet_uuid: Uuid) -> FResult<VNetL2TP>;
+    async fn set_dhcp_static_routes(
+        &self,
+        vnet_uuid: Uuid,
+        routes: Vec<DHCPStaticRoute>,
+    ) -> FResult<VirtualNetwork>;
+    async fn get_dhcp_static_routes(&self, vnet_uuid: Uuid) -> FResult<Vec<DHCPStaticRoute>>;
+    async fn update_virtual_network(
+        &self,
+        vnet_uuid: Uuid,
+        update: VirtualNetworkUpdate,
+    ) -> FResult<VirtualNetwork>;
+    async fn add_dns_record(&self, vnet_uuid: Uuid, record: DNSRecord) -> FResult<VirtualNetwork>;
+    async fn remove_dns_record(&self, vnet_uuid: Uuid, name: String) -> FResult<VirtualNetwork>;
+    async fn list_dns_records(&self, vnet_uuid: Uuid) -> FResult<Vec<DNSRecord>>;
+    async fn register_dns_name(
+        &self,
+        vnet_uuid: Uuid,
+        name: String,
+        ip: IPAddress,
+    ) -> FResult<VirtualNetwork>;
+    async fn add_dhcp_reservation(
+        &self,
+        vnet_uuid: Uuid,
+        reservation: DHCPReservation,
+    ) -> FResult<VirtualNetwork>;
+    async fn remove_dhcp_reservation(
+        &self,
+        vnet_uuid: Uuid,
+        mac: MACAddress,
+    ) -> FResult<VirtualNetwork>;
+    async fn list_dhcp_reservations(&self, vnet_uuid: Uuid) -> FResult<Vec<DHCPReservation>>;
+    async fn assign_static_address(
+        &self,
+        vnet_uuid: Uuid,
+        intf_uuid: Uuid,
+    ) -> FResult<VirtualInterface>;
+    async fn release_static_address(
+        &self,
+        vnet_uuid: Uuid,
+        intf_uuid: Uuid,
+    ) -> FResult<VirtualInterface>;
+    async fn set_vxlan_options(
+        &self,
+        vnet_uuid: Uuid,
+        options: VXLANOptions,
+    ) -> FResult<VirtualNetwork>;
+    async fn update_network_transport(
+        &self,
+        vnet_uuid: Uuid,
+        update: VXLANTransportUpdate,
+    ) -> FResult<VirtualNetwork>;
+    async fn get_ns_manager_spawn_stats(&self) -> FResult<NSManagerSpawnStats>;
+    async fn get_netlink_retry_stats(&self) -> FResult<HashMap<String, NetlinkRetryStats>>;
+    async fn get_capabilities(&self) -> FResult<PluginCapabilities>;
+    async fn replay_journal(&self) -> FResult<JournalReplay>;
+    async fn get_node_uuid(&self) -> FResult<Uuid>;
+    async fn store_replica(&self, replica: SealedReplica) -> FResult<()>;
+    async fn list_replicas(&self) -> FResult<Vec<ReplicaInfo>>;
+    async fn takeover(&self, peer_node_uuid: Uuid) -> FResult<JournalReplay>;
+    async fn set_connection_point_qos(
+        &self,
+        cp_uuid: Uuid,
+        qos: Option<ConnectionPointQoS>,
+    ) -> FResult<ConnectionPoint>;
+    async fn get_connection_point_qos(&self, cp_uuid: Uuid) -> FResult<Option<ConnectionPointQoS>>;
+    async fn assign_floating_ip(&self, cp_uuid: Uuid, public_ip: IPAddress) -> FResult<FloatingIP>;
+    async fn release_floating_ip(&self, cp_uuid: Uuid) -> FResult<FloatingIP>;
+    async fn get_floating_ip(&self, cp_uuid: Uuid) -> FResult<FloatingIP>;
+    async fn add_port_forward(
+        &self,
+        vnet_uuid: Uuid,
+        proto: PortForwardProtocol,
+        ext_port: u16,
+        internal_ip: IPAddress,
+        internal_port: u16,
+    ) -> FResult<VirtualNetwork>;
+    async fn remove_port_forward(
+        &self,
+        vnet_uuid: Uuid,
+        proto: PortForwardProtocol,
+        ext_port: u16,
+    ) -> FResult<VirtualNetwork>;
+    async fn list_port_forwards(&self, vnet_uuid: Uuid) -> FResult<Vec<PortForward>>;
+    async fn add_nft_set(&self, vnet_uuid: Uuid, set: NFTSet) -> FResult<VirtualNetwork>;
+    async fn remove_nft_set(&self, vnet_uuid: Uuid, name: String) -> FResult<VirtualNetwork>;
+    async fn list_nft_sets(&self, vnet_uuid: Uuid) -> FResult<Vec<NFTSet>>;
+    async fn add_set_element(
+        &self,
+        vnet_uuid: Uuid,
+        name: String,
+        element: String,
+    ) -> FResult<VirtualNetwork>;
+    async fn del_set_element(
+        &self,
+        vnet_uuid: Uuid,
+        name: String,
+        element: String,
+    ) -> FResult<VirtualNetwork>;
+    async fn add_set_rule(&self, vnet_uuid: Uuid, rule: SetRule) -> FResult<VirtualNetwork>;
+    async fn remove_set_rule(&self, vnet_uuid: Uuid, rule: SetRule) -> FResult<VirtualNetwork>;
+    async fn list_set_rules(&self, vnet_uuid: Uuid) -> FResult<Vec<SetRule>>;
+    async fn create_mirror_connection_point(&self, vnet_uuid: Uuid) -> FResult<ConnectionPoint>;
+    async fn delete_mirror_connection_point(&self, vnet_uuid: Uuid, cp_uuid: Uuid)
+        -> FResult<Uuid>;
+    async fn list_mirror_connection_points(&self, vnet_uuid: Uuid) -> FResult<Vec<Uuid>>;
+    async fn set_fdb_options(
+        &self,
+        vnet_uuid: Uuid,
+        options: FDBOptions,
+    ) -> FResult<VirtualNetwork>;
+    async fn flush_fdb(&self, vnet_uuid: Uuid) -> FResult<()>;
+    async fn allow_peering(&self, vnet_a: Uuid, vnet_b: Uuid) -> FResult<()>;
+    async fn deny_peering(&self, vnet_a: Uuid, vnet_b: Uuid) -> FResult<()>;
+    async fn set_shared_services(&self, services: SharedServices) -> FResult<SharedServices>;
+    async fn remove_shared_services(&self, services_vnet: Uuid) -> FResult<SharedServices>;
+    async fn get_shared_services(&self, services_vnet: Uuid) -> FResult<SharedServices>;
+    async fn list_virtual_networks(&self) -> FResult<Vec<VirtualNetwork>>;
+    async fn list_virtual_interfaces(&self) -> FResult<Vec<VirtualInterface>>;
+    async fn list_network_namespaces(&self) -> FResult<Vec<NetworkNamespace>>;
+    async fn list_connection_points(&self) -> FResult<Vec<ConnectionPoint>>;
+    async fn get_interface_peer(&self, intf_uuid: Uuid) -> FResult<InterfaceEnd>;
+    async fn inspect_virtual_interface(&self, intf_uuid: Uuid) -> FResult<InterfaceInspection>;
+    async fn get_virtual_interface_stats(&self, intf_uuid: Uuid) -> FResult<InterfaceStats>;
+    async fn set_namespace_reverse_path_filter(
+        &self,
+        ns_uuid: Uuid,
+        filter: ReversePathFilter,
+    ) -> FResult<()>;
+    async fn set_virtual_interface_reverse_path_filter(
+        &self,
+        intf_uuid: Uuid,
+        filter: ReversePathFilter,
+    ) -> FResult<()>;
+    async fn get_namespace_martian_counters(&self, ns_uuid: Uuid) -> FResult<MartianCounters>;
+    async fn set_virtual_interface_state(
+        &self,
+        intf_uuid: Uuid,
+        state: InterfaceAdminState,
+    ) -> FResult<VirtualInterface>;
+    async fn get_virtual_interface_state(&self, intf_uuid: Uuid) -> FResult<InterfaceAdminState>;
+    async fn set_bridge_port_config(
+        &self,
+        intf_uuid: Uuid,
+        config: BridgePortConfig,
+    ) -> FResult<VirtualInterface>;
+    async fn get_bridge_port_config(&self, intf_uuid: Uuid) -> FResult<BridgePortConfig>;
+    async fn set_interface_mtu(&self, intf_uuid: Uuid, mtu: u32) -> FResult<VirtualInterface>;
+    async fn find_interface_by_name(
+        &self,
+        if_name: String,
+        ns_uuid: Option<Uuid>,
+    ) -> FResult<VirtualInterface>;
+    async fn find_interface_by_mac(&self, mac: MACAddress) -> FResult<VirtualInterface>;
+    async fn assign_external_address(
+        &self,
+        intf_uuid: Uuid,
+        vnet_uuid: Uuid,
+        external: ExternalAddress,
+    ) -> FResult<VirtualInterface>;
+    async fn set_default_routes_in_network_namespace(
+        &self,
+        ns_uuid: Uuid,
+        intf_uuid: Uuid,
+        routes: Vec<DefaultRoute>,
+    ) -> FResult<()>;
+    async fn del_default_route_in_network_namespace(
+        &self,
+        ns_uuid: Uuid,
+        intf_uuid: Uuid,
+        route: DefaultRoute,
+    ) -> FResult<()>;
+    async fn set_default_route_failover(
+        &self,
+        ns_uuid: Uuid,
+        failover: DefaultRouteFailover,
+    ) -> FResult<DefaultRouteFailoverStatus>;
+    async fn remove_default_route_failover(&self, ns_uuid: Uuid) -> FResult<()>;
+    async fn get_default_route_failover_status(
+        &self,
+        ns_uuid: Uuid,
+    ) -> FResult<DefaultRouteFailoverStatus>;
+    async fn attach_network_to_namespace(
+        &self,
+        vnet_uuid: Uuid,
+        ns_uuid: Uuid,
+    ) -> FResult<VirtualInterface>;
+    async fn detach_network_from_namespace(&self, vnet_uuid: Uuid, ns_uuid: Uuid) -> FResult<()>;
+    async fn list_sriov_pfs(&self) -> FResult<Vec<SRIOVPhysicalFunction>>;
+    async fn allocate_vf(&self, pf: Option<String>, config: VFConfig) -> FResult<VirtualFunction>;
+    async fn get_vf(&self, vf_uuid: Uuid) -> FResult<VirtualFunction>;
+    async fn release_vf(&self, vf_uuid: Uuid) -> FResult<VirtualFunction>;
+    async fn get_record_version(&self, record_uuid: Uuid) -> FResult<RecordVersion>;
+    async fn get_record_metadata(&self, record_uuid: Uuid) -> FResult<RecordMetadata>;
+    async fn list_record_metadata(&self) -> FResult<HashMap<Uuid, RecordMetadata>>;
+    async fn create_ip_tunnel(
+        &self,
+        if_name: String,
+        kind: IPTunnelKind,
+        local_addr: IPAddress,
+        remote_addr: IPAddress,
+        ttl: Option<u8>,
+        ns_uuid: Option<Uuid>,
+    ) -> FResult<IPTunnel>;
+    async fn get_ip_tunnel(&self, tunnel_uuid: Uuid) -> FResult<IPTunnel>;
+    async fn delete_ip_tunnel(&self, tunnel_uuid: Uuid) -> FResult<IPTunnel>;
+    async fn create_dataplane_port(
+        &self,
+        if_name: String,
+        kind: DataplanePortKind,
+    ) -> FResult<DataplanePort>;
+    async fn get_dataplane_port(&self, port_uuid: Uuid) -> FResult<DataplanePort>;
+    async fn delete_dataplane_port(&self, port_uuid: Uuid) -> FResult<DataplanePort>;
+    async fn allocate_vnet_subnet(&self, vnet_uuid: Uuid, pool: String)
+        -> FResult<IPConfiguration>;
+    async fn list_ipam_subnets(&self) -> FResult<Vec<(IPAddress, u8)>>;
+    async fn get_ipam_allocations(&self, vnet_uuid: Uuid) -> FResult<VNetIPAM>;
+    async fn reserve_pool_subnet(
+        &self,
+        vnet_uuid: Uuid,
+        pool: IPAMPool,
+    ) -> FResult<IPConfiguration>;
+    async fn swap_global_internals(&self, sealed: SealedGlobalSwap) -> FResult<bool>;
+    async fn allocate_address(&self, vnet_uuid: Uuid, owner: Option<String>) -> FResult<IPAddress>;
+    async fn release_address(&self, vnet_uuid: Uuid, address: IPAddress) -> FResult<()>;
+    async fn get_simulated_topology(&self) -> FResult<SimulatedKernel>;
+    async fn get_dhcp_client(&self, intf_uuid: Uuid) -> FResult<DHCPClient>;
+    async fn renew_dhcp_lease(&self, intf_uuid: Uuid) -> FResult<VirtualInterface>;
+    async fn get_log_settings(&self) -> FResult<LogSettings>;
+    async fn set_log_level(&self, target: String, level: Option<LogLevel>) -> FResult<()>;
+    async fn set_log_trace_sampling(&self, target: String, rate: Option<u32>) -> FResult<()>;
+    async fn diff_network(&self, vnet_uuid: Uuid) -> FResult<NetworkDiff>;
+    async fn create_virtual_network_async(&self, vnet_uuid: Uuid) -> FResult<Uuid>;
+    async fn get_async_operation(&self, op_uuid: Uuid) -> FResult<AsyncOperation>;
+    async fn list_async_operations(&self) -> FResult<Vec<AsyncOperation>>;
+    async fn get_firewall_state(&self, vnet_uuid: Uuid) -> FResult<FirewallState>;
+    async fn set_connection_point_rate_limit(
+        &self,
+        cp_uuid: Uuid,
+        limit: Option<ConnectionPointRateLimit>,
+    ) -> FResult<ConnectionPoint>;
+    async fn get_connection_point_rate_limit(
+        &self,
+        cp_uuid: Uuid,
+    ) -> FResult<Option<ConnectionPointRateLimit>>;
+    async fn add_egress_rule(&self, vnet_uuid: Uuid, rule: EgressRule) -> FResult<VirtualNetwork>;
+    async fn remove_egress_rule(
+        &self,
+        vnet_uuid: Uuid,
+        rule: EgressRule,
+    ) -> FResult<VirtualNetwork>;
+    async fn list_egress_rules(&self, vnet_uuid: Uuid) -> FResult<Vec<EgressRule>>;
+    async fn map_address(
+        &self,
+        vnet_uuid: Uuid,
+        internal_ip: IPAddress,
+        external_ip: IPAddress,
+    ) -> FResult<AddressMapping>;
+    async fn unmap_address(
+        &self,
+        vnet_uuid: Uuid,
+        external_ip: IPAddress,
+    ) -> FResult<AddressMapping>;
+    async fn list_address_mappings(&self, vnet_uuid: Uuid) -> FResult<Vec<AddressMapping>>;
+    async fn get_conntrack_stats(&self, vnet_uuid: Uuid) -> FResult<ConntrackStats>;
+    async fn get_interface_statistics(&self, intf_uuid: Uuid) -> FResult<InterfaceStatistics>;
+    async fn get_node_networking_status(&self) -> FResult<NodeNetworkingStatus>;
+    async fn healthcheck(&self) -> FResult<HealthReport>;
+    async fn get_vnet_traffic(&self, vnet_uuid: Uuid) -> FResult<VNetTraffic>;
+    async fn enable_flow_export(
+        &self,
+        vnet_uuid: Uuid,
+        sampling_rate: Option<u32>,
+    ) -> FResult<VNetFlowExport>;
+    async fn disable_flow_export(&self, vnet_uuid: Uuid) -> FResult<VirtualNetwork>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(raw: serde_json::Value) -> (VirtualNetworkInternals, serde_json::Value) {
+        let internals = deserialize_network_internals(raw.to_string().as_bytes()).unwrap();
+        let written = serialize_network_internals(&internals).unwrap();
+        (internals, serde_json::from_slice(&written).unwrap())
+    }
+
+    #[test]
+    fn unknown_variant_survives_round_trip() {
+        let (internals, written) = round_trip(serde_json::json!({
+            "remote_endpoint": {"Fqdn": "peer.example.com"},
+            "vrf": {"if_name": "vrf0", "table": 10, "members": []},
+        }));
+        assert!(internals.remote_endpoint.is_none());
+        assert_eq!(internals.vrf.as_ref().map(|v| v.table), Some(10));
+        assert_eq!(
+            written["remote_endpoint"],
+            serde_json::json!({"Fqdn": "peer.example.com"})
+        );
+    }
+
+    #[test]
+    fn unknown_dhcp_backend_survives_round_trip() {
+        let (internals, written) = round_trip(serde_json::json!({
+            "dhcp": {
+                "leases_file": "leases",
+                "pid_file": "pid",
+                "conf": "conf",
+                "log_file": "log",
+                "backend": "KEA",
+            },
+        }));
+        let dhcp = internals.dhcp.unwrap();
+        assert!(dhcp.backend.is_none());
+        assert_eq!(dhcp.leases_file, "leases");
+        assert_eq!(written["dhcp"]["backend"], serde_json::json!("KEA"));
+    }
+
+    #[test]
+    fn set_value_replaces_unknown_one() {
+        let raw = serde_json::json!({"remote_endpoint": {"Fqdn": "peer.example.com"}});
+        let mut internals = deserialize_network_internals(raw.to_string().as_bytes()).unwrap();
+        internals.remote_endpoint = Some(RemoteEndpoint::Hostname("peer".to_string()));
+        let written: serde_json::Value =
+            serde_json::from_slice(&serialize_network_internals(&internals).unwrap()).unwrap();
+        assert_eq!(
+            written["remote_endpoint"],
+            serde_json::json!({"Hostname": "peer"})
+        );
+    }
 }
@@ -0,0 +1,390 @@
+/*********************************************************************************
+* Copyright (c) 2018,2021 ADLINK Technology Inc.
+*
+* This program and the accompanying materials are made available under the
+* terms of the Eclipse Public License 2.0 which is available at
+* http://www.eclipse.org/legal/epl-2.0, or the Apache Software License 2.0
+* which is available at https://www.apache.org/licenses/LICENSE-2.0.
+*
+* SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+* Contributors:
+*   ADLINK fog05 team, <fog05@adlink-labs.tech>
+*********************************************************************************/
+
+//! Smoke tests of the netlink operations used by the plugin.
+//!
+//! They change the links of the network namespace they run in, so they
+//! are meant to be run inside a throwaway one, see `resources/smoke`.
+//! Tests needing a kernel module that is not available return early.
+//! The attributes the plugin encodes itself are in native endianness,
+//! they are checked here as well so that the cross-arch runs cover them.
+
+#![cfg(feature = "smoke-tests")]
+
+use futures::stream::TryStreamExt;
+use rtnetlink::packet::rtnl::link::nlas::{Info, Nla};
+use rtnetlink::{new_connection, Error, Handle};
+
+use fog05_sdk::types::MACAddress;
+
+use fog05_networking_linux::networking::{flush_bridge_fdb, retry_busy, set_bridge_port};
+use fog05_networking_linux::sriov::{vf_info_list, VFConfig};
+use fog05_networking_linux::types::BridgePortConfig;
+
+/// Kernel errors meaning the link kind is not available
+const EOPNOTSUPP: i32 = -95;
+const EPROTONOSUPPORT: i32 = -93;
+const ENODEV: i32 = -19;
+
+/// `IFLA_BRPORT_*` and `IFLA_VF_*` attributes
+const IFLA_BRPORT_PRIORITY: u16 = 2;
+const IFLA_BRPORT_COST: u16 = 3;
+const IFLA_VF_INFO: u16 = 1;
+const IFLA_VF_MAC: u16 = 1;
+const IFLA_VF_VLAN: u16 = 2;
+const IFLA_VF_SPOOFCHK: u16 = 4;
+const NLA_F_NESTED: u16 = 1 << 15;
+
+fn handle() -> Handle {
+    let (connection, handle, _) = new_connection().unwrap();
+    async_std::task::spawn(connection);
+    handle
+}
+
+fn error_code(e: &Error) -> Option<i32> {
+    match e {
+        Error::NetlinkError(nl) => Some(nl.code),
+        _ => None,
+    }
+}
+
+fn is_unsupported(e: &Error) -> bool {
+    matches!(error_code(e), Some(EOPNOTSUPP) | Some(EPROTONOSUPPORT))
+}
+
+async fn get_index(handle: &Handle, name: &str) -> Option<u32> {
+    let mut links = handle
+        .link()
+        .get()
+        .set_name_filter(name.to_string())
+        .execute();
+    match links.try_next().await {
+        Ok(Some(link)) => Some(link.header.index),
+        _ => None,
+    }
+}
+
+async fn get_master(handle: &Handle, name: &str) -> Option<u32> {
+    let mut links = handle
+        .link()
+        .get()
+        .set_name_filter(name.to_string())
+        .execute();
+    let link = links.try_next().await.ok()??;
+    link.nlas.into_iter().find_map(|nla| match nla {
+        Nla::Master(index) => Some(index),
+        _ => None,
+    })
+}
+
+/// Slave data of the link info of a bridge port
+async fn get_slave_data(handle: &Handle, name: &str) -> Option<Vec<u8>> {
+    let mut links = handle
+        .link()
+        .get()
+        .set_name_filter(name.to_string())
+        .execute();
+    let link = links.try_next().await.ok()??;
+    link.nlas.into_iter().find_map(|nla| match nla {
+        Nla::Info(infos) => infos.into_iter().find_map(|info| match info {
+            Info::SlaveData(data) => Some(data),
+            _ => None,
+        }),
+        _ => None,
+    })
+}
+
+/// Kind and payload of the attributes in `data`, each one is aligned to
+/// 4 bytes and its header is in native endianness
+fn parse_nlas(data: &[u8]) -> Vec<(u16, Vec<u8>)> {
+    let mut nlas = Vec::new();
+    let mut i = 0;
+    while i + 4 <= data.len() {
+        let len = u16::from_ne_bytes([data[i], data[i + 1]]) as usize;
+        let kind = u16::from_ne_bytes([data[i + 2], data[i + 3]]);
+        assert!(len >= 4 && i + len <= data.len(), "malformed attribute");
+        nlas.push((kind, data[i + 4..i + len].to_vec()));
+        i += (len + 3) & !3;
+    }
+    assert_eq!(i, data.len(), "trailing bytes after the attributes");
+    nlas
+}
+
+fn find_nla(nlas: &[(u16, Vec<u8>)], kind: u16) -> Option<&[u8]> {
+    nlas.iter()
+        .find(|(k, _)| *k == kind)
+        .map(|(_, payload)| payload.as_slice())
+}
+
+fn ne_u32(data: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(&data[offset..offset + 4]);
+    u32::from_ne_bytes(bytes)
+}
+
+async fn delete(handle: &Handle, name: &str) {
+    if let Some(index) = get_index(handle, name).await {
+        let _ = handle.link().del(index).execute().await;
+    }
+}
+
+#[async_std::test]
+async fn bridge_lifecycle() {
+    let handle = handle();
+    let name = "smkbr0".to_string();
+    retry_busy(|| handle.link().add().bridge(name.clone()).execute())
+        .await
+        .unwrap();
+    let index = get_index(&handle, &name).await.expect("bridge not found");
+    handle.link().set(index).mtu(1400).execute().await.unwrap();
+    handle.link().set(index).up().execute().await.unwrap();
+    handle.link().del(index).execute().await.unwrap();
+    assert!(get_index(&handle, &name).await.is_none());
+}
+
+#[async_std::test]
+async fn veth_enslaved_to_bridge() {
+    let handle = handle();
+    let (br, veth_i, veth_e) = ("smkbr1", "smkvi1", "smkve1");
+    retry_busy(|| handle.link().add().bridge(br.to_string()).execute())
+        .await
+        .unwrap();
+    match handle
+        .link()
+        .add()
+        .veth(veth_i.to_string(), veth_e.to_string())
+        .execute()
+        .await
+    {
+        // veth not available
+        Err(e) if is_unsupported(&e) => {
+            delete(&handle, br).await;
+            return;
+        }
+        res => res.unwrap(),
+    }
+    let br_index = get_index(&handle, br).await.unwrap();
+    let veth_index = get_index(&handle, veth_e).await.unwrap();
+    handle
+        .link()
+        .set(veth_index)
+        .master(br_index)
+        .execute()
+        .await
+        .unwrap();
+    assert_eq!(get_master(&handle, veth_e).await, Some(br_index));
+    handle
+        .link()
+        .set(veth_index)
+        .nomaster()
+        .execute()
+        .await
+        .unwrap();
+    assert_eq!(get_master(&handle, veth_e).await, None);
+    // removing one end removes the pair
+    delete(&handle, veth_i).await;
+    assert!(get_index(&handle, veth_e).await.is_none());
+    delete(&handle, br).await;
+}
+
+#[async_std::test]
+async fn vxlan_on_bridge() {
+    let handle = handle();
+    let (br, vxl) = ("smkbr2", "smkvx2");
+    retry_busy(|| handle.link().add().bridge(br.to_string()).execute())
+        .await
+        .unwrap();
+    let dev = get_index(&handle, br).await.unwrap();
+    match handle
+        .link()
+        .add()
+        .vxlan(vxl.to_string(), 3845)
+        .link(dev)
+        .port(4789)
+        .learning(0)
+        .execute()
+        .await
+    {
+        // vxlan not available
+        Err(e) if is_unsupported(&e) => {
+            delete(&handle, br).await;
+            return;
+        }
+        res => res.unwrap(),
+    }
+    assert!(get_index(&handle, vxl).await.is_some());
+    delete(&handle, vxl).await;
+    delete(&handle, br).await;
+}
+
+#[async_std::test]
+async fn missing_link_errors() {
+    let handle = handle();
+    assert!(get_index(&handle, "smknone").await.is_none());
+    // an index no link has
+    let res = handle.link().del(u32::MAX - 1).execute().await;
+    assert_eq!(res.as_ref().err().and_then(error_code), Some(ENODEV));
+}
+
+#[test]
+fn bridge_port_slave_data_layout() {
+    let config = BridgePortConfig {
+        priority: Some(7),
+        cost: Some(100_000),
+    };
+    let data = config.slave_data();
+    // u16 priority padded to 4 bytes, then the u32 cost
+    assert_eq!(data.len(), 16);
+    let nlas = parse_nlas(&data);
+    assert_eq!(
+        find_nla(&nlas, IFLA_BRPORT_PRIORITY),
+        Some(&7u16.to_ne_bytes()[..])
+    );
+    assert_eq!(
+        find_nla(&nlas, IFLA_BRPORT_COST),
+        Some(&100_000u32.to_ne_bytes()[..])
+    );
+    assert!(BridgePortConfig::default().slave_data().is_empty());
+}
+
+#[test]
+fn vf_info_list_layout() {
+    let config = VFConfig {
+        mac: Some(MACAddress::new(0x02, 0, 0, 0, 0, 0x2a)),
+        vlan: Some(100),
+        spoofchk: false,
+    };
+    let list = parse_nlas(&vf_info_list(3, &config));
+    assert_eq!(list.len(), 1);
+    assert_eq!(list[0].0, IFLA_VF_INFO | NLA_F_NESTED);
+    let info = parse_nlas(&list[0].1);
+
+    // struct ifla_vf_mac { __u32 vf; __u8 mac[32]; }
+    let mac = find_nla(&info, IFLA_VF_MAC).expect("no VF MAC");
+    assert_eq!(mac.len(), 36);
+    assert_eq!(ne_u32(mac, 0), 3);
+    assert_eq!(&mac[4..10], &[0x02, 0, 0, 0, 0, 0x2a]);
+    assert!(mac[10..].iter().all(|b| *b == 0));
+
+    // struct ifla_vf_vlan { __u32 vf; __u32 vlan; __u32 qos; }
+    let vlan = find_nla(&info, IFLA_VF_VLAN).expect("no VF VLAN");
+    assert_eq!(vlan.len(), 12);
+    assert_eq!(ne_u32(vlan, 0), 3);
+    assert_eq!(ne_u32(vlan, 4), 100);
+    assert_eq!(ne_u32(vlan, 8), 0);
+
+    // struct ifla_vf_spoofchk { __u32 vf; __u32 setting; }
+    let spoofchk = find_nla(&info, IFLA_VF_SPOOFCHK).expect("no VF spoof checking");
+    assert_eq!(spoofchk.len(), 8);
+    assert_eq!(ne_u32(spoofchk, 0), 3);
+    assert_eq!(ne_u32(spoofchk, 4), 0);
+}
+
+#[async_std::test]
+async fn bridge_port_config_applied() {
+    let handle = handle();
+    let (br, veth_i, veth_e) = ("smkbr3", "smkvi3", "smkve3");
+    retry_busy(|| handle.link().add().bridge(br.to_string()).execute())
+        .await
+        .unwrap();
+    match handle
+        .link()
+        .add()
+        .veth(veth_i.to_string(), veth_e.to_string())
+        .execute()
+        .await
+    {
+        // veth not available
+        Err(e) if is_unsupported(&e) => {
+            delete(&handle, br).await;
+            return;
+        }
+        res => res.unwrap(),
+    }
+    let br_index = get_index(&handle, br).await.unwrap();
+    let veth_index = get_index(&handle, veth_e).await.unwrap();
+    handle
+        .link()
+        .set(veth_index)
+        .master(br_index)
+        .execute()
+        .await
+        .unwrap();
+
+    let config = BridgePortConfig {
+        priority: Some(12),
+        cost: Some(4242),
+    };
+    retry_busy(|| {
+        let mut handle = handle.clone();
+        let config = config.clone();
+        async move { set_bridge_port(&mut handle, veth_index, &config).await }
+    })
+    .await
+    .unwrap();
+    // the kernel reports the port settings in the slave data
+    let data = get_slave_data(&handle, veth_e)
+        .await
+        .expect("no bridge port data");
+    let nlas = parse_nlas(&data);
+    assert_eq!(
+        find_nla(&nlas, IFLA_BRPORT_PRIORITY),
+        Some(&12u16.to_ne_bytes()[..])
+    );
+    assert_eq!(
+        find_nla(&nlas, IFLA_BRPORT_COST),
+        Some(&4242u32.to_ne_bytes()[..])
+    );
+
+    flush_bridge_fdb(&mut handle.clone(), br_index)
+        .await
+        .unwrap();
+
+    delete(&handle, veth_i).await;
+    delete(&handle, br).await;
+}
+
+#[async_std::test]
+async fn bridge_port_helpers_missing_link() {
+    let handle = handle();
+    let config = BridgePortConfig {
+        priority: Some(1),
+        cost: None,
+    };
+    let res = set_bridge_port(&mut handle.clone(), u32::MAX - 1, &config).await;
+    assert_eq!(res.as_ref().err().and_then(error_code), Some(ENODEV));
+}
+
+#[async_std::test]
+async fn vf_info_list_accepted_by_kernel() {
+    let handle = handle();
+    let br = "smkbr4";
+    retry_busy(|| handle.link().add().bridge(br.to_string()).execute())
+        .await
+        .unwrap();
+    let index = get_index(&handle, br).await.unwrap();
+    let config = VFConfig {
+        mac: Some(MACAddress::new(0x02, 0, 0, 0, 0, 0x2b)),
+        vlan: Some(10),
+        spoofchk: true,
+    };
+    let mut req = handle.link().set(index);
+    req.message_mut()
+        .nlas
+        .push(Nla::VfInfoList(vf_info_list(0, &config)));
+    // a bridge has no VFs: the attributes are parsed by the kernel and
+    // only applying them fails, a malformed list is rejected as invalid
+    let res = req.execute().await;
+    assert_eq!(res.as_ref().err().and_then(error_code), Some(EOPNOTSUPP));
+    delete(&handle, br).await;
+}